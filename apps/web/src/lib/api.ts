@@ -330,22 +330,24 @@ export const tracksApi = {
   },
   master: (trackId: string, options: MasterOptions) => {
     // Map frontend options to backend format
-    // Backend expects: profile (balanced/warm/punchy/custom), loudnessTarget (low/medium/high)
-    const loudnessTarget = options.targetLufs && options.targetLufs >= -10 ? "high"
-      : options.targetLufs && options.targetLufs <= -18 ? "low"
+    // Backend expects: profile (balanced/warm/punchy/custom or a genre preset),
+    // loudnessTarget (a named target or LUFS from -40 to -4)
+    const loudnessTarget = options.targetLufs
+      ? Math.min(-4, Math.max(-40, options.targetLufs))
       : "medium";
     // Map genre to profile
     const genreToProfile: Record<string, string> = {
       pop: "balanced",
       rock: "punchy",
-      electronic: "punchy",
-      "hip-hop": "punchy",
-      jazz: "warm",
-      classical: "balanced",
+      electronic: "edm",
+      "hip-hop": "hip_hop",
+      jazz: "jazz",
+      classical: "classical",
       rnb: "warm",
-      country: "balanced",
+      country: "acoustic",
       metal: "punchy",
-      acoustic: "warm",
+      acoustic: "acoustic",
+      podcast: "podcast",
     };
     const profile = genreToProfile[options.genre || "pop"] || "balanced";
     return api.post<{ jobId: string; trackId: string; status: string }>(
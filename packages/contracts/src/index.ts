@@ -5,6 +5,14 @@
 // Job Types
 // ============================================================================
 
+/**
+ * Job payload schema version. Stamped on every enqueued job as
+ * `schemaVersion`; workers reject newer versions than they understand with
+ * an `unsupported_schema_version` failure instead of misreading them. Bump it
+ * only when an existing field changes meaning, not when one is added.
+ */
+export const JOB_SCHEMA_VERSION = 1;
+
 export interface AnalyzeJob {
   type: "analyze";
   /** Unique job identifier */
@@ -13,18 +21,302 @@ export interface AnalyzeJob {
   trackId: string;
   /** S3 or HTTP URL where the original track can be downloaded */
   sourceUrl: string;
+  /** Hex SHA-256 the downloaded source must match */
+  sourceSha256?: string;
+}
+
+export interface BatchAnalyzeJob {
+  type: "batch-analyze";
+  jobId: string;
+  /** Names the batch's report in storage, e.g. a catalog or label ID */
+  batchId: string;
+  trackIds: string[];
+  /** Source URLs in the same order as trackIds */
+  sourceUrls: string[];
+  /** Expected SHA-256 of each source, in the same order */
+  sourceSha256s?: string[];
+}
+
+/** A platform's loudness spec */
+export interface PlatformSpec {
+  name: string;
+  targetLufs: number;
+  /** Highest true peak accepted (dBTP) */
+  maxTruePeak: number;
+  /** How far from the target a delivery may be (LU), e.g. 0.5 for EBU R128; without one the platform normalizes instead */
+  toleranceLu?: number;
+}
+
+export interface ComplianceCheckJob {
+  type: "compliance-check";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Specs to check against (default Spotify, Apple Music, YouTube, Tidal, Amazon Music and Deezer) */
+  platforms?: PlatformSpec[];
+}
+
+/** Check an audiobook chapter against ACX's submission requirements */
+export interface AcxCheckJob {
+  type: "acx-check";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+}
+
+export interface SegmentDetectJob {
+  type: "segment-detect";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Level below which audio counts as silence (dB RMS, default -50) */
+  silenceThresholdDb?: number;
+  /** Shortest silence that marks a boundary (seconds, default 2) */
+  minSilenceSeconds?: number;
+  /** Shortest segment between boundaries (seconds, default 30) */
+  minSegmentSeconds?: number;
+  /** Also mark changes in spectral shape with no silence between (default true) */
+  detectChanges?: boolean;
+  /** Markers are labelled "<prefix> 1", "<prefix> 2", ... (default "Chapter") */
+  labelPrefix?: string;
+}
+
+export interface TranscribeJob {
+  type: "transcribe";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** ISO 639-1 code of the spoken language, or "auto"; the backend detects it when unset */
+  language?: string;
+  /** Text to steer spelling and style, such as names and jargon */
+  prompt?: string;
+  /** Fold the audio down to mono before sending it (default true) */
+  downmix?: boolean;
+  /** Bring the audio to a steady speech level first (default true) */
+  normalize?: boolean;
+}
+
+export interface DuplicateCheckJob {
+  type: "duplicate-check";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Fingerprints to compare against, as earlier duplicate checks reported them */
+  candidates?: {
+    trackId: string;
+    fingerprint: string;
+  }[];
+  /** Fingerprint index in Redis to compare against as well */
+  index?: string;
+  /** Store this track's fingerprint in `index` once compared */
+  addToIndex?: boolean;
+  /** Similarity from which a track is reported (0-1, default 0.6) */
+  minSimilarity?: number;
+}
+
+export interface RestoreJob {
+  type: "restore";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** How hard to declick, decrackle, dehum and denoise, from a light touch to heavy repair (0-1, default 0.5) */
+  strength?: number;
+}
+
+export interface KaraokeJob {
+  type: "karaoke";
+  jobId: string;
+  trackId: string;
+  /** A stereo master */
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Share of the center taken out of the instrumental (0-1, default 1); less leaves some vocal as a guide */
+  suppression?: number;
+}
+
+export interface TimePitchJob {
+  type: "time-pitch";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Speed relative to the source, keeping pitch (0.5-2, e.g. 0.95 for 95%; default 1) */
+  tempo?: number;
+  /** Pitch shift, keeping tempo (-12 to 12, fractions for cents; default 0) */
+  semitones?: number;
+}
+
+export interface VocalBalanceJob {
+  type: "vocal-balance";
+  jobId: string;
+  trackId: string;
+  /** A stereo mix */
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Vocal-to-backing balance below which the vocal is flagged as buried (dB, default -8) */
+  buriedDb?: number;
+  /** Balance above which it is flagged as overpowering (dB, default 4) */
+  overpoweringDb?: number;
+}
+
+export interface BounceStem {
+  /** Shown in the report; defaults to the stem's position */
+  name?: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Default 0 dB, at most +24 */
+  gainDb?: number;
+  /** -1 (left) to 1 (right), default 0; mono stems pan at constant power, stereo stems are balanced */
+  pan?: number;
+  /** Left out of the mix, and not downloaded */
+  mute?: boolean;
+}
+
+export interface BounceJob {
+  type: "bounce";
+  jobId: string;
+  /** Track the mix (and its master) is stored under */
+  trackId: string;
+  /** Mono or stereo stems, summed into a stereo mix as long as the longest */
+  stems: BounceStem[];
+  /** 44100, 48000, 88200 or 96000 (default: the first unmuted stem's rate) */
+  sampleRate?: number;
+  /** Master the mix as a master job would */
+  master?: {
+    profile: MasterProfile;
+    loudnessTarget: LoudnessTarget;
+    /** Dither for the 16-bit deliverable (default "tpdf") */
+    dither?: "none" | "tpdf" | "noise-shaped";
+  };
+}
+
+export interface RevisionReportJob {
+  type: "revision-report";
+  jobId: string;
+  trackId: string;
+  /** 2 to 20 revisions of the track's master, oldest first */
+  revisions: {
+    /** e.g. "v3" */
+    label: string;
+    sourceUrl: string;
+    sourceSha256?: string;
+  }[];
+}
+
+export interface ReplayGainJob {
+  type: "replay-gain";
+  jobId: string;
+  /** Up to 100 tracks of one album, in order; album gain covers them all */
+  tracks: {
+    trackId: string;
+    sourceUrl: string;
+    sourceSha256?: string;
+  }[];
+}
+
+export interface PipelineJob {
+  type: "pipeline";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /**
+   * Up to 32 stages run in order on the decoded source. Each encode stage
+   * renders the audio as it stands, and at least one is required. Invalid
+   * settings fail the job before the source is downloaded.
+   */
+  stages: PipelineStage[];
 }
 
+export type PipelineStage =
+  /** A fix module with its settings, e.g. {"stage": "fix", "module": "de_ess"} */
+  | ({ stage: "fix" } & Exclude<FixModule, FixModuleName>)
+  | {
+      stage: "eq";
+      /** 1 to 16 bands, applied in order */
+      bands: {
+        type: "low-shelf" | "high-shelf" | "peak" | "high-pass" | "low-pass";
+        /** 10-24000, below the Nyquist frequency */
+        frequencyHz: number;
+        /** -24 to 24; ignored by the pass filters */
+        gainDb?: number;
+        /** Peak bands only (0.1-10; default 1) */
+        q?: number;
+      }[];
+      mode?: "iir" | "linear-phase";
+      precision?: "f32" | "f64";
+    }
+  /** Wideband compressor (threshold -60 to 0 dBFS, ratio 1-20; attack 10 ms and release 100 ms by default) */
+  | {
+      stage: "compressor";
+      thresholdDb: number;
+      ratio: number;
+      attackMs?: number;
+      releaseMs?: number;
+      makeupDb?: number;
+    }
+  /** Soft clipping, drive 0-2 */
+  | { stage: "saturation"; drive: number }
+  /** -48 to 24 dB */
+  | { stage: "gain"; gainDb: number }
+  /** True-peak limiter with makeup gain to targetLufs (-40 to -5); ceiling -6 to 0 dBTP, default the worker's true peak max */
+  | {
+      stage: "limiter";
+      targetLufs: number;
+      ceilingDb?: number;
+      mode?: "brick-wall" | "multiband";
+      releaseMs?: number;
+      precision?: "f32" | "f64";
+    }
+  | { stage: "resample"; sampleRate: 44100 | 48000 | 88200 | 96000 }
+  /** name labels the file (letters, digits, "-" and "_"; default by format) */
+  | { stage: "encode"; format: ExportFormat; name?: string }
+  /**
+   * A WebAssembly (WASI) module run sandboxed on the audio, with params
+   * passed to it as JSON. The module is checked against moduleSha256.
+   */
+  | {
+      stage: "plugin";
+      moduleUrl: string;
+      moduleSha256: string;
+      /** Reported in place of the module's hash */
+      name?: string;
+      params?: unknown;
+    }
+  /** A tool configured on the worker under [processors.<processor>], given params as JSON */
+  | { stage: "external"; processor: string; params?: unknown }
+  /**
+   * A CLAP plugin configured on the worker under [clap.<plugin>], restored
+   * from one of its configured presets; needs a worker built with `clap`
+   */
+  | { stage: "clap"; plugin: string; preset?: string };
+
 export interface FixJob {
   type: "fix";
   jobId: string;
   trackId: string;
   sourceUrl: string;
-  /** List of fix modules to apply, e.g. ["clip_repair","de_ess","normalize"] */
+  sourceSha256?: string;
+  /**
+   * Fix modules to apply in order, by name for their defaults or with
+   * settings, e.g. ["clip_repair", {"module": "normalize", "targetDb": -0.3}].
+   * Unknown modules and out-of-range settings reject the job.
+   */
   modules: FixModule[];
+  /** Run after the fix modules, each reported as a change */
+  stages?: CustomStage[];
 }
 
-export type FixModule =
+/** The pipeline stages a fix or master job can also run */
+export type CustomStage = Extract<PipelineStage, { stage: "plugin" | "external" | "clap" }>;
+
+export type FixModuleName =
   | "normalize"
   | "clip_repair"
   | "de_ess"
@@ -32,30 +324,188 @@ export type FixModule =
   | "dc_offset"
   | "silence_trim";
 
+export type FixModule =
+  | FixModuleName
+  /** Peak to normalize to (dBFS, at most 0; default -1) */
+  | { module: "normalize"; targetDb?: number }
+  /** Linear level counted as clipped (0-1; default the worker's clip threshold) */
+  | { module: "clip_repair"; threshold?: number }
+  /** Sibilance from frequencyHz (1000-16000; default 4000), threshold and ratio 0-1 (defaults 0.3 and 0.5) */
+  | { module: "de_ess"; frequencyHz?: number; threshold?: number; ratio?: number }
+  /** Noise floor gated below twice over (dBFS, -120 to 0; default -60) */
+  | { module: "noise_reduction"; floorDb?: number }
+  | { module: "dc_offset" }
+  /** Linear level treated as silent (0-1), and silence kept at either end (ms; default 100) */
+  | { module: "silence_trim"; threshold?: number; keepMs?: number };
+
 export interface MasterJob {
   type: "master";
   jobId: string;
   trackId: string;
   sourceUrl: string;
+  sourceSha256?: string;
   /** Name of the mastering profile to use */
   profile: MasterProfile;
   /** Target loudness level */
   loudnessTarget: LoudnessTarget;
+  /** Reference track whose tonal balance and loudness to match */
+  referenceUrl?: string;
+  referenceSha256?: string;
+  /** EQ filter implementation (default "iir") */
+  eqMode?: EqMode;
+  /** Arithmetic precision of the EQ and crossover filters (default "f32") */
+  precision?: Precision;
+  /** Overrides the profile's final limiter */
+  limiterMode?: LimiterMode;
+  /** Minimum loudness range to preserve (LU); compression backs off to keep it */
+  minLoudnessRange?: number;
+  /** Dither for the 16-bit deliverable (default "tpdf") */
+  dither?: Dither;
+  /** Dry/wet blend of the whole chain (0-1, default 1), at the target loudness */
+  mix?: number;
+  /** Gain after the chain (dB, at most 0, default 0) */
+  outputTrimDb?: number;
+  /** Run on the source before the mastering chain, and listed in the QC report */
+  stages?: CustomStage[];
+  /**
+   * A CLAP plugin run after the chain's limiter, e.g. a required commercial
+   * limiter; loudness, true peak and QC are measured on its output
+   */
+  finalStage?: Extract<PipelineStage, { stage: "clap" }>;
 }
 
-export type MasterProfile = "balanced" | "warm" | "punchy" | "custom";
+export type EqMode = "iir" | "linear-phase";
+
+export type Precision = "f32" | "f64";
 
-export type LoudnessTarget = "low" | "medium" | "high";
+export type LimiterMode = "brick-wall" | "multiband";
+
+export type Dither = "none" | "tpdf" | "noise-shaped";
+
+/** Unknown profiles reject the job */
+export type MasterProfile =
+  | "balanced"
+  | "warm"
+  | "punchy"
+  | "custom"
+  | "hip_hop"
+  | "edm"
+  | "acoustic"
+  | "classical"
+  | "podcast"
+  | "jazz";
+
+/**
+ * A named loudness target, or the integrated loudness in LUFS (-40 to -4).
+ * "streaming" is -14 LUFS, "club" -6, "broadcast_ebu" -23 (EBU R 128) and
+ * "broadcast_atsc" -24 (ATSC A/85); results echo the target as given.
+ */
+export type LoudnessTarget =
+  | "low"
+  | "medium"
+  | "high"
+  | "streaming"
+  | "club"
+  | "broadcast_ebu"
+  | "broadcast_atsc"
+  | number;
 
 export interface CodecPreviewJob {
   type: "codec-preview";
   jobId: string;
   trackId: string;
   masterUrl: string;
+  /** Hex SHA-256 the downloaded master must match */
+  masterSha256?: string;
   /** Codecs to simulate, e.g. ["aac-128","mp3-128","opus-96"] */
   codecs: CodecFormat[];
 }
 
+/** Window of the master to encode; without a start, the loudest durationSeconds (default 30) */
+export interface PreviewSegment {
+  startSeconds?: number;
+  durationSeconds?: number;
+}
+
+export interface CodecSweepJob {
+  type: "codec-sweep";
+  jobId: string;
+  trackId: string;
+  masterUrl: string;
+  masterSha256?: string;
+  /** Codec without its bitrate, e.g. "opus" or "opus-vbr" */
+  codec: string;
+  /** Bitrates to try (kbps; default 32 to 320) */
+  bitrates?: number[];
+  /** Highest acceptable artifact score for the recommendation (default 60) */
+  maxArtifactScore?: number;
+  segment?: PreviewSegment;
+}
+
+export type StreamFormat = "hls" | "dash";
+
+export interface StreamingLadderJob {
+  type: "streaming-ladder";
+  jobId: string;
+  trackId: string;
+  masterUrl: string;
+  masterSha256?: string;
+  /** Manifest flavour (default "hls") */
+  format?: StreamFormat;
+  /** AAC/Opus codec strings, one per rendition; defaults to an AAC ladder */
+  renditions?: string[];
+  /** Media segment length (seconds) */
+  segmentSeconds?: number;
+  segment?: PreviewSegment;
+}
+
+export type Stem = "vocals" | "drums" | "bass" | "other" | "guitar" | "piano";
+
+export interface StemSeparationJob {
+  type: "stem-separation";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Demucs model, e.g. "htdemucs_ft" or "htdemucs_6s" (default from the worker config) */
+  model?: string;
+  /** Split out just this stem and everything else, e.g. "vocals" for karaoke */
+  twoStems?: Stem;
+}
+
+export interface CompareJob {
+  type: "compare";
+  jobId: string;
+  trackId: string;
+  /** The earlier render, e.g. master v1 */
+  referenceUrl: string;
+  referenceSha256?: string;
+  /** The render under review, e.g. master v2 */
+  candidateUrl: string;
+  candidateSha256?: string;
+  /** Longest lead-in difference to align across (seconds, default 1, at most 10) */
+  maxOffsetSeconds?: number;
+  /** Also upload the aligned difference (candidate minus reference) as a 32-bit WAV */
+  renderDifference?: boolean;
+}
+
+export interface PreviewClipJob {
+  type: "preview-clip";
+  jobId: string;
+  trackId: string;
+  masterUrl: string;
+  masterSha256?: string;
+  /** Codec strings to encode the clip with, e.g. ["mp3-192","opus-96"] (default ["mp3-192"]) */
+  codecs?: string[];
+  /** Clip length (seconds, default 30) */
+  durationSeconds?: number;
+  /** Start of the clip; by default the loudest section, moved to onsets at both ends */
+  startSeconds?: number;
+  /** Fade lengths (seconds, default 0.05 in and 1.5 out) */
+  fadeInSeconds?: number;
+  fadeOutSeconds?: number;
+}
+
 export type CodecFormat =
   | "aac-128"
   | "aac-256"
@@ -71,10 +521,40 @@ export interface AlbumMasterJob {
   projectId: string;
   /** Track IDs in desired album order */
   trackIds: string[];
+  /** Source URLs in the same order as trackIds */
+  sourceUrls: string[];
+  /** Hex SHA-256 each source must match, in the same order; may be omitted */
+  sourceSha256s?: string[];
   profile: MasterProfile;
   loudnessTarget: LoudnessTarget;
   /** Whether to normalize loudness across all tracks (±1 LU) */
   normalizeLoudness: boolean;
+  /** Silence after each track in the continuous render (seconds, default 0) */
+  gapSeconds?: number[];
+  /** Crossfade into the next track (seconds); replaces the gap when set */
+  crossfadeSeconds?: number[];
+  /** Also render the whole album as one continuous file */
+  renderContinuous?: boolean;
+}
+
+export interface PodcastProcessJob {
+  type: "podcast-process";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Loudness target (default -16 LUFS, or -19 LUFS for mono) */
+  targetLufs?: number;
+  /** Corner of the rumble filter (Hz, default 80) */
+  highPassHz?: number;
+  /** Turn room tone down between phrases (default true) */
+  gate?: boolean;
+  /** Duck harsh sibilance (default true) */
+  deEss?: boolean;
+  /** Fold the recording down to mono before processing */
+  mono?: boolean;
+  /** MP3 bitrate (kbps: 48, 64, 96, 128, 160, 192, 256 or 320; default 128) */
+  mp3Bitrate?: number;
 }
 
 export interface ExportJob {
@@ -85,28 +565,140 @@ export interface ExportJob {
   formats: ExportFormat[];
   /** Whether to include QC reports */
   includeQc: boolean;
+  /** Final masters to export, in album order */
+  tracks: ExportTrack[];
+  /** FLAC compression level (0-8, default 5) */
+  flacCompressionLevel?: number;
+}
+
+/** A mastered track included in an export */
+export interface ExportTrack {
+  trackId: string;
+  /** URL of the final 24-bit master */
+  masterUrl: string;
+  /** Hex SHA-256 the downloaded master must match */
+  masterSha256?: string;
+  qcReportUrl?: string;
+  /** Rendered PDF QC report, packaged alongside the JSON one */
+  qcPdfUrl?: string;
 }
 
 export type ExportFormat = "wav-24" | "wav-16" | "mp3-320" | "flac";
 
+export interface WaveformPeaksJob {
+  type: "waveform-peaks";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Source frames per point of each zoom level (default 256, 1024, 4096, 16384; at least 32) */
+  samplesPerPixel?: number[];
+  /** Resolution of the points (default 8) */
+  bits?: 8 | 16;
+}
+
+export type SpectrogramScale = "mel" | "linear";
+
+export type ColorMap = "heat" | "viridis" | "magma" | "grayscale";
+
+export interface SpectrogramResolution {
+  /** 16-8192 pixels */
+  width: number;
+  /** 16-1024 pixels */
+  height: number;
+}
+
+export interface SpectrogramJob {
+  type: "spectrogram";
+  jobId: string;
+  trackId: string;
+  sourceUrl: string;
+  sourceSha256?: string;
+  /** Image sizes to render (default 1024x256 and 4096x512, at most 8) */
+  resolutions?: SpectrogramResolution[];
+  /** Frequency axis (default mel) */
+  scale?: SpectrogramScale;
+  /** Default heat, the QC report's colours */
+  colorMap?: ColorMap;
+}
+
 // Union type of all possible jobs
 export type Job =
   | AnalyzeJob
+  | BatchAnalyzeJob
+  | ComplianceCheckJob
+  | AcxCheckJob
+  | SegmentDetectJob
+  | TranscribeJob
+  | DuplicateCheckJob
+  | RestoreJob
+  | KaraokeJob
+  | TimePitchJob
+  | VocalBalanceJob
+  | BounceJob
+  | RevisionReportJob
+  | ReplayGainJob
+  | PipelineJob
   | FixJob
+  | PodcastProcessJob
   | MasterJob
   | CodecPreviewJob
+  | CodecSweepJob
+  | StreamingLadderJob
   | AlbumMasterJob
-  | ExportJob;
+  | ExportJob
+  | WaveformPeaksJob
+  | SpectrogramJob
+  | PreviewClipJob
+  | StemSeparationJob
+  | CompareJob;
 
 // ============================================================================
 // Job Results
 // ============================================================================
 
+/**
+ * Versions of the completed results with a published JSON Schema, by job
+ * type. Each result is posted with its version as `schemaVersion`, and
+ * validates against `schemas/<endpoint>.v<version>.schema.json` in this
+ * package, generated from the worker's Rust types. A version is bumped
+ * whenever its `data` changes shape; earlier schemas stay published.
+ */
+export const RESULT_SCHEMA_VERSIONS = {
+  analyze: 1,
+  fix: 1,
+  master: 2,
+  "codec-preview": 1,
+} as const;
+
 export interface JobResult {
   jobId: string;
   type: Job["type"];
   status: "completed" | "failed";
   error?: string;
+  /** Set when a worker rejected the payload without running it */
+  errorCode?: "invalid_payload" | "unsupported_schema_version";
+  /**
+   * On completed results listed in `RESULT_SCHEMA_VERSIONS`, the version of
+   * the result's schema. On rejections, the payload version the job carried,
+   * with the newest the worker supports.
+   */
+  schemaVersion?: number;
+  supportedSchemaVersion?: number;
+}
+
+/**
+ * What a fix, master or album-master job submitted with `dryRun` would have
+ * done, posted to `/webhooks/jobs/:jobId/plan` in place of its result.
+ * Nothing was rendered or uploaded.
+ */
+export interface PlanResult {
+  jobId: string;
+  type: "fix" | "master" | "album-master";
+  status: "planned";
+  dryRun: true;
+  /** The job type's plan: the changes, chain settings or predicted levels */
+  data: Record<string, unknown>;
 }
 
 export interface AnalysisResult extends JobResult {
@@ -136,8 +728,446 @@ export interface AnalysisResult extends JobResult {
     bitDepth: number;
     channels: number;
     durationSecs: number;
-    // Report URL
+    // Report URL (presigned), bucket key and SHA-256
     reportUrl?: string;
+    reportKey?: string;
+    reportSha256?: string;
+    /** SHA-256 of the source as downloaded */
+    sourceSha256?: string;
+  };
+}
+
+/**
+ * Progress of an analyze job, posted to its progress webhook (and Redis
+ * channel) after every minute of audio, with the metrics so far
+ */
+export interface AnalysisProgress {
+  progress: number;
+  message: string;
+  analysis: {
+    durationSecs: number;
+    /** Null until the first gated 400 ms block */
+    integratedLufs: number | null;
+    loudnessRange: number | null;
+    shortTermLufs: number | null;
+    momentaryLufs: number | null;
+    samplePeak: number;
+    /** Short of the last few milliseconds decoded */
+    truePeak: number;
+    clippedSamples: number;
+  };
+}
+
+export interface LoudnessDistribution {
+  min: number;
+  max: number;
+  mean: number;
+  median: number;
+  p10: number;
+  p90: number;
+  stdDev: number;
+}
+
+export interface BatchAnalysisResult extends JobResult {
+  type: "batch-analyze";
+  data?: {
+    batchId: string;
+    catalog: {
+      trackCount: number;
+      analyzed: number;
+      failed: number;
+      totalDurationSecs: number;
+      integratedLufs: LoudnessDistribution | null;
+      loudnessRange: LoudnessDistribution | null;
+      truePeak: LoudnessDistribution | null;
+      /** Tracks per 1 LU bin of integrated loudness, from the quietest to the loudest */
+      loudnessHistogram: { lufs: number; count: number }[];
+      /**
+       * Tracks outside the Tukey fences of the batch's loudness, above the QC
+       * true peak gate, or clipped; limit is the fence, gate or 0 crossed
+       */
+      outliers: {
+        trackId: string;
+        reason: "too-loud" | "too-quiet" | "true-peak" | "clipping";
+        value: number;
+        limit: number;
+      }[];
+    };
+    /** In the order of trackIds; tracks that failed carry an error instead of an analysis */
+    tracks: {
+      trackId: string;
+      sourceSha256: string | null;
+      analysis: {
+        integratedLufs: number;
+        loudnessRange: number;
+        shortTermMax: number;
+        truePeak: number;
+        samplePeak: number;
+        hasClipping: boolean;
+        clippedSamples: number;
+        hasDcOffset: boolean;
+        sampleRate: number;
+        channels: number;
+        durationSecs: number;
+      } | null;
+      error: string | null;
+    }[];
+    /** The same catalog and tracks as a JSON report */
+    reportUrl: string;
+    reportKey: string;
+    reportSha256: string;
+  };
+}
+
+export interface ComplianceCheckResult extends JobResult {
+  type: "compliance-check";
+  data?: {
+    integratedLufs: number;
+    loudnessRange: number;
+    truePeak: number;
+    samplePeak: number;
+    sampleRate: number;
+    channels: number;
+    durationSecs: number;
+    /** Whether every platform passes */
+    passes: boolean;
+    platforms: (PlatformSpec & {
+      passes: boolean;
+      loudnessPasses: boolean;
+      truePeakPasses: boolean;
+      /** Gain the platform applies on playback (dB, 0 for specs with a tolerance) */
+      playbackGainDb: number;
+      /** Gain needed to land within the tolerance (dB, 0 when it already does) */
+      requiredGainDb: number;
+      /** True peak once both gains are applied (dBTP) */
+      truePeakAfterGain: number;
+      /** How much further peaks must come down to pass (dB) */
+      truePeakReductionDb: number;
+    })[];
+    sourceSha256: string;
+  };
+}
+
+export interface RenderLoudness {
+  integratedLufs: number;
+  loudnessRange: number;
+  truePeak: number;
+  samplePeak: number;
+}
+
+export interface PodcastProcessResult extends JobResult {
+  type: "podcast-process";
+  data?: {
+    targetLufs: number;
+    finalLufs: number;
+    finalTruePeak: number;
+    loudnessIterations: number;
+    /** Share of the recording the gate turned down (0-1), null with the gate off */
+    gatedFraction: number | null;
+    /** Largest cut the de-esser made (dB), null with it off */
+    deEssMaxReductionDb: number | null;
+    /** Range of gain the leveler applied (dB) */
+    levelerMinGainDb: number;
+    levelerMaxGainDb: number;
+    source: RenderLoudness;
+    output: RenderLoudness;
+    mp3Url: string;
+    mp3Key: string;
+    mp3Sha256: string;
+    /** JSON loudness report */
+    reportUrl: string;
+    reportKey: string;
+    reportSha256: string;
+    sourceSha256: string;
+  };
+}
+
+export interface AcxCheckResult extends JobResult {
+  type: "acx-check";
+  data?: {
+    /** Whether every criterion passes */
+    passes: boolean;
+    /** min/max are the allowed range; null where ACX sets no bound */
+    criteria: {
+      name: "rms" | "peak" | "noise-floor" | "head-room-tone" | "tail-room-tone";
+      passes: boolean;
+      /** Measured level (dB) or room tone length (seconds) */
+      value: number;
+      min: number | null;
+      max: number | null;
+    }[];
+    rmsDb: number;
+    peakDb: number;
+    /** RMS of the quietest half second */
+    noiseFloorDb: number;
+    headRoomToneSeconds: number;
+    tailRoomToneSeconds: number;
+    sampleRate: number;
+    channels: number;
+    durationSecs: number;
+    sourceSha256: string;
+  };
+}
+
+export interface SegmentDetectResult extends JobResult {
+  type: "segment-detect";
+  data?: {
+    durationSecs: number;
+    /** Segment starts in order, the first at 0 */
+    markers: {
+      timeSeconds: number;
+      label: string;
+      kind: "start" | "silence" | "change";
+      /** Length of the silence (seconds), or how much the spectral shape changes (0-1) */
+      strength: number;
+    }[];
+    silences: {
+      startSeconds: number;
+      endSeconds: number;
+    }[];
+    sourceSha256: string;
+  };
+}
+
+export interface TranscribeResult extends JobResult {
+  type: "transcribe";
+  data?: {
+    text: string;
+    /** Language the backend detected or was told, as it names it */
+    language: string | null;
+    /** Timed segments; empty if the backend gave only the text */
+    segments: {
+      startSeconds: number;
+      endSeconds: number;
+      text: string;
+    }[];
+    durationSecs: number;
+    /** Gain applied before sending (dB, 0 without normalize) */
+    gainDb: number;
+    sourceSha256: string;
+  };
+}
+
+export interface DuplicateCheckResult extends JobResult {
+  type: "duplicate-check";
+  data?: {
+    /** This track's fingerprint, to pass as a candidate or keep for later checks */
+    fingerprint: string;
+    fingerprintSecs: number;
+    /** Fingerprints compared, supplied and from the index */
+    compared: number;
+    /** Probable duplicates, most similar first */
+    matches: {
+      trackId: string;
+      /** 1 for identical audio, around 0 for unrelated audio */
+      similarity: number;
+      /** Share of the shorter track the two overlap in (0-1) */
+      coverage: number;
+      /** Where the other track starts in this one (seconds, negative when it starts before) */
+      offsetSeconds: number;
+      source: "supplied" | "index";
+    }[];
+    sourceSha256: string;
+  };
+}
+
+export interface RestoreResult extends JobResult {
+  type: "restore";
+  data?: {
+    restoredUrl: string;
+    restoredKey: string;
+    restoredSha256: string;
+    strength: number;
+    /** Lag of the right channel behind the left that was removed (samples, negative when the right led) */
+    azimuthLagSamples: number;
+    /** Correlation between the channels at that lag (0 for mono) */
+    channelCorrelation: number;
+    polarityInverted: boolean;
+    clicksRepaired: number;
+    cracklesRepaired: number;
+    /** Measured hum fundamental (Hz), null when no hum was found */
+    humHz: number | null;
+    humHarmonicsNotched: number;
+    /** RMS of the quietest stretches before and after (dBFS) */
+    noiseFloorDb: number;
+    noiseFloorAfterDb: number;
+    maxNoiseReductionDb: number;
+    sourceSha256: string;
+  };
+}
+
+export interface KaraokeResult extends JobResult {
+  type: "karaoke";
+  data?: {
+    suppression: number;
+    /** Share of the vocal range's mid energy found in the center; low values mean the vocal likely remains */
+    centerShare: number;
+    /** 24-bit WAVs: "instrumental" (stereo, center suppressed), then "acapella" (the center) */
+    files: {
+      name: "instrumental" | "acapella";
+      url: string;
+      key: string;
+      sha256: string;
+      integratedLufs: number;
+      loudnessRange: number;
+      truePeak: number;
+      samplePeak: number;
+    }[];
+    sourceSha256: string;
+  };
+}
+
+export interface TimePitchResult extends JobResult {
+  type: "time-pitch";
+  data?: {
+    tempo: number;
+    semitones: number;
+    sourceDurationSecs: number;
+    durationSecs: number;
+    /** 24-bit WAV */
+    renderedUrl: string;
+    renderedKey: string;
+    renderedSha256: string;
+    sourceSha256: string;
+  };
+}
+
+export interface VocalBalanceResult extends JobResult {
+  type: "vocal-balance";
+  data?: {
+    /** Median vocal-to-backing balance where a vocal is present (dB); null if it never is */
+    medianBalanceDb: number | null;
+    /** Share of the track with a vocal present (0-1) */
+    vocalShare: number;
+    buriedDb: number;
+    overpoweringDb: number;
+    /** Runs of 4 s or more outside the allowed balance */
+    sections: {
+      startSeconds: number;
+      endSeconds: number;
+      issue: "buried" | "overpowering";
+      balanceDb: number;
+    }[];
+    /** Every second, short-term (3 s) loudness of the vocal estimate and the backing */
+    points: {
+      timeSeconds: number;
+      vocalLufs: number;
+      backingLufs: number;
+      /** null where no vocal is present */
+      balanceDb: number | null;
+    }[];
+    durationSecs: number;
+    sourceSha256: string;
+  };
+}
+
+export interface BounceResult extends JobResult {
+  type: "bounce";
+  data?: {
+    sampleRate: number;
+    durationSecs: number;
+    /** Highest sample of the sum (dBFS) */
+    peakDb: number;
+    /** The sum goes over full scale, so the 24-bit mix WAV clips (the master is made from the unclipped sum) */
+    clipped: boolean;
+    stems: {
+      name: string;
+      muted: boolean;
+      /** Source rate and length; null for muted stems */
+      sampleRate: number | null;
+      durationSecs: number | null;
+    }[];
+    mixUrl: string;
+    mixKey: string;
+    mixSha256: string;
+    /** Outputs of the master, when one was asked for; sourceSha256 is the mix's */
+    master: MasterResult["data"] | null;
+  };
+}
+
+export interface RevisionReportResult extends JobResult {
+  type: "revision-report";
+  data?: {
+    revisions: {
+      label: string;
+      integratedLufs: number;
+      loudnessRange: number;
+      shortTermMax: number;
+      truePeak: number;
+      samplePeak: number;
+      stereoWidth: number | null;
+      clippedSamples: number;
+      /** Octave band levels relative to the whole revision; empty if it was too short to measure */
+      tonalBalance: {
+        lowHz: number;
+        highHz: number;
+        levelDb: number;
+      }[];
+      sampleRate: number;
+      durationSecs: number;
+      sourceSha256: string;
+    }[];
+    /** Each revision against the one before it */
+    changes: {
+      from: string;
+      to: string;
+      integratedLufsDelta: number;
+      loudnessRangeDelta: number;
+      truePeakDelta: number;
+      stereoWidthDelta: number | null;
+      durationDeltaSecs: number;
+      tonalBalanceDelta: {
+        lowHz: number;
+        highHz: number;
+        deltaDb: number;
+      }[];
+      /** The changes big enough to hear, in words, e.g. "1.5 LU louder (-9.2 LUFS)" */
+      notes: string[];
+    }[];
+    reportUrl: string;
+    reportKey: string;
+    reportSha256: string;
+  };
+}
+
+export interface ReplayGainResult extends JobResult {
+  type: "replay-gain";
+  data?: {
+    /** Loudness the gains bring tracks to (-18 LUFS) */
+    referenceLufs: number;
+    albumGainDb: number;
+    /** Linear; 1.0 is full scale */
+    albumPeak: number;
+    tracks: {
+      trackId: string;
+      trackGainDb: number;
+      trackPeak: number;
+    }[];
+  };
+}
+
+export interface PipelineResult extends JobResult {
+  type: "pipeline";
+  data?: {
+    stages: {
+      index: number;
+      stage: PipelineStage["stage"];
+      /** What the stage did, e.g. "Limited to -14.0 LUFS, -1.1 dBTP" */
+      description: string;
+    }[];
+    /** One per encode stage, in order */
+    files: {
+      trackId: string;
+      format: ExportFormat;
+      filename: string;
+      sampleRate: number;
+      url: string;
+      key: string;
+      sha256: string;
+    }[];
+    /** Loudness and true peak after the last stage */
+    finalLufs: number;
+    finalTruePeak: number;
   };
 }
 
@@ -145,9 +1175,12 @@ export interface FixResult extends JobResult {
   type: "fix";
   data?: {
     fixedUrl: string;
-    appliedModules: FixModule[];
+    fixedKey?: string;
+    fixedSha256?: string;
+    sourceSha256?: string;
+    appliedModules: FixModuleName[];
     changes: {
-      module: FixModule;
+      module: FixModuleName;
       description: string;
     }[];
   };
@@ -157,21 +1190,37 @@ export interface MasterResult extends JobResult {
   type: "master";
   data?: {
     wavHdUrl: string;
+    wavHdKey?: string;
+    wavHdSha256?: string;
     wav16Url: string;
+    wav16Key?: string;
+    wav16Sha256?: string;
     mp3PreviewUrl: string;
+    mp3PreviewKey?: string;
+    mp3PreviewSha256?: string;
     finalLufs: number;
     finalTruePeak: number;
     passesQc: boolean;
+    /** Whether finalLufs is within the QC loudness tolerance of the target */
+    passesLoudness: boolean;
+    /** True-peak ceiling the master was checked against (dBTP) */
+    truePeakMax: number;
     qcReportUrl?: string;
+    qcReportKey?: string;
+    qcReportSha256?: string;
+    sourceSha256?: string;
   };
 }
 
 export interface CodecPreviewResult extends JobResult {
   type: "codec-preview";
   data?: {
+    masterSha256?: string;
     previews: {
       codec: CodecFormat;
       previewUrl: string;
+      previewKey?: string;
+      previewSha256?: string;
       truePeakAfter: number;
       artifactScore: number;
       clippingRisk: boolean;
@@ -179,23 +1228,163 @@ export interface CodecPreviewResult extends JobResult {
   };
 }
 
+/** Part of the master a codec job encoded, or null for the whole track */
+export type EncodedSegment = { startSeconds: number; durationSeconds: number } | null;
+
+export interface CodecSweepResult extends JobResult {
+  type: "codec-sweep";
+  data?: {
+    masterSha256: string;
+    codec: string;
+    maxArtifactScore: number;
+    /** Lowest bitrate within maxArtifactScore, if any */
+    recommendedBitrate: number | null;
+    /** One preview per bitrate tried (kbps) */
+    points: (NonNullable<CodecPreviewResult["data"]>["previews"][number] & {
+      bitrate: number;
+    })[];
+    segment: EncodedSegment;
+  };
+}
+
+export interface StreamingLadderResult extends JobResult {
+  type: "streaming-ladder";
+  data?: {
+    masterSha256: string;
+    format: StreamFormat;
+    /** HLS master playlist or DASH MPD */
+    manifestUrl: string;
+    manifestKey: string;
+    manifestSha256: string;
+    segmentSeconds: number;
+    renditions: {
+      codec: string;
+      /** RFC 6381 codec string, as advertised in the manifest */
+      codecs: string;
+      /** Peak and average bitrate (bits/s) */
+      bandwidth: number;
+      averageBandwidth: number;
+      /** Media playlist (HLS only) */
+      playlistUrl: string | null;
+      playlistKey: string | null;
+      playlistSha256: string | null;
+    }[];
+    segment: EncodedSegment;
+  };
+}
+
+export interface StemSeparationResult extends JobResult {
+  type: "stem-separation";
+  data?: {
+    model: string;
+    sourceSha256: string;
+    /** 24-bit WAVs in Demucs's order, e.g. vocals, drums, bass, other (or vocals, no_vocals) */
+    stems: {
+      name: string;
+      url: string;
+      key: string;
+      sha256: string;
+      integratedLufs: number;
+      loudnessRange: number;
+      truePeak: number;
+      samplePeak: number;
+    }[];
+  };
+}
+
+export interface PreviewClipResult extends JobResult {
+  type: "preview-clip";
+  data?: {
+    masterSha256: string;
+    /** Section of the master the clip was cut from */
+    segment: { startSeconds: number; durationSeconds: number };
+    /** Fades applied, shortened to fit short tracks */
+    fadeInSeconds: number;
+    fadeOutSeconds: number;
+    previews: {
+      codec: string;
+      url: string;
+      key: string;
+      sha256: string;
+      /** Gapless priming and padding (MP3 and AAC only) */
+      encoderDelay?: number;
+      encoderPadding?: number;
+    }[];
+  };
+}
+
+export interface CompareResult extends JobResult {
+  type: "compare";
+  data?: {
+    referenceSha256: string;
+    candidateSha256: string;
+    sampleRate: number;
+    channels: number;
+    /** Frames the candidate lags the reference by (negative: it leads) */
+    offsetFrames: number;
+    offsetSeconds: number;
+    overlapSeconds: number;
+    /** Set when the candidate was resampled from this rate to the reference's */
+    candidateSampleRate: number | null;
+    referenceRmsDb: number;
+    differenceRmsDb: number;
+    differencePeakDb: number;
+    /** How far the difference is below the reference (dB; higher is closer) */
+    nullDepthDb: number;
+    blockSeconds: number;
+    /** Difference RMS of each block (dBFS, -144 for digital silence) */
+    differenceOverTime: number[];
+    /** Octave bands */
+    bands: {
+      lowHz: number;
+      highHz: number;
+      referenceDb: number;
+      candidateDb: number;
+      deltaDb: number;
+    }[];
+    reference: RenderLoudness;
+    candidate: RenderLoudness;
+    /** Candidate minus reference */
+    loudnessDelta: RenderLoudness;
+    differenceUrl: string | null;
+    differenceKey: string | null;
+    differenceSha256: string | null;
+  };
+}
+
+/** Deliverables and measurements of one track of an album master */
+export type AlbumTrackResult = NonNullable<MasterResult["data"]> & {
+  trackId: string;
+};
+
 export interface AlbumMasterResult extends JobResult {
   type: "album-master";
   data?: {
-    tracks: {
-      trackId: string;
-      masterResult: MasterResult["data"];
-    }[];
-    albumQcReport: {
-      loudnessConsistency: boolean;
-      tonalBalance: boolean;
-      recommendedOrder: string[];
-      outliers: {
-        trackId: string;
-        issue: string;
-      }[];
-      reportUrl?: string;
+    projectId: string;
+    /** In album order */
+    tracks: AlbumTrackResult[];
+    album: {
+      trackCount: number;
+      targetLufs: number;
+      averageLufs: number;
+      /** Difference between the loudest and quietest master (LU) */
+      loudnessSpread: number;
+      maxTruePeak: number;
+      allPassQc: boolean;
     };
+    /** Continuous render of the whole album, when renderContinuous was set */
+    albumRender: {
+      url: string;
+      key: string;
+      sha256: string;
+      /** Start time of each track in the render (seconds) */
+      trackOffsets: number[];
+      durationSecs: number;
+    } | null;
+    /** Album-level QC report (JSON) */
+    albumQcUrl: string | null;
+    albumQcKey: string | null;
+    albumQcSha256: string | null;
   };
 }
 
@@ -203,14 +1392,63 @@ export interface ExportResult extends JobResult {
   type: "export";
   data?: {
     packUrl: string;
+    packKey?: string;
+    packSha256?: string;
     files: {
       format: ExportFormat;
       filename: string;
+      sha256?: string;
     }[];
     qcReportIncluded: boolean;
   };
 }
 
+export interface WaveformPeaksResult extends JobResult {
+  type: "waveform-peaks";
+  data?: {
+    sampleRate: number;
+    channels: number;
+    durationSecs: number;
+    bits: 8 | 16;
+    sourceSha256: string;
+    /** One audiowaveform version 2 `.dat` file per zoom level */
+    levels: {
+      samplesPerPixel: number;
+      /** Points per channel */
+      length: number;
+      url: string;
+      key: string;
+      sha256: string;
+    }[];
+    /** Every level as JSON, with per-point RMS alongside the min/max pairs */
+    jsonUrl: string;
+    jsonKey: string;
+    jsonSha256: string;
+  };
+}
+
+export interface SpectrogramResult extends JobResult {
+  type: "spectrogram";
+  data?: {
+    sampleRate: number;
+    channels: number;
+    durationSecs: number;
+    scale: SpectrogramScale;
+    colorMap: ColorMap;
+    /** Level drawn in the first colour of the map (dBFS); full scale is the last */
+    floorDb: number;
+    sourceSha256: string;
+    /** One PNG per requested resolution, highest frequency at the top */
+    images: {
+      width: number;
+      height: number;
+      url: string;
+      key: string;
+      sha256: string;
+    }[];
+  };
+}
+
 // ============================================================================
 // API Request/Response Types
 // ============================================================================
@@ -230,13 +1468,11 @@ export interface AnalyzeTrackRequest {
 }
 
 export interface FixTrackRequest {
-  modules: FixModule[];
+  modules: FixModuleName[];
+  stages?: CustomStage[];
 }
 
-export interface MasterTrackRequest {
-  profile: MasterProfile;
-  loudnessTarget: LoudnessTarget;
-}
+export type MasterTrackRequest = Omit<MasterJob, "type" | "jobId" | "trackId" | "sourceUrl" | "sourceSha256">;
 
 export interface CodecPreviewRequest {
   codecs: CodecFormat[];
@@ -247,11 +1483,18 @@ export interface AlbumMasterRequest {
   profile: MasterProfile;
   loudnessTarget: LoudnessTarget;
   normalizeLoudness?: boolean;
+  /** Silence after each track in the continuous render (seconds) */
+  gapSeconds?: number[];
+  /** Crossfade into the next track (seconds); replaces the gap when set */
+  crossfadeSeconds?: number[];
+  renderContinuous?: boolean;
 }
 
 export interface ExportProjectRequest {
   formats: ExportFormat[];
   includeQc?: boolean;
+  /** FLAC compression level (0-8, default 5) */
+  flacCompressionLevel?: number;
 }
 
 // ============================================================================
@@ -268,5 +1511,11 @@ export const QC_CONSTANTS = {
     low: -14.0,
     medium: -11.0,
     high: -8.0,
+    streaming: -14.0,
+    club: -6.0,
+    broadcast_ebu: -23.0,
+    broadcast_atsc: -24.0,
   },
+  /** Levels a numeric loudness target may ask for (LUFS) */
+  LOUDNESS_TARGET_RANGE: { min: -40.0, max: -4.0 },
 } as const;
@@ -0,0 +1,9 @@
+-- Migration: Add the genre mastering profiles to MasterProfile
+-- ADD VALUE can't run inside a transaction block on PostgreSQL < 12
+
+ALTER TYPE "MasterProfile" ADD VALUE IF NOT EXISTS 'HIP_HOP';
+ALTER TYPE "MasterProfile" ADD VALUE IF NOT EXISTS 'EDM';
+ALTER TYPE "MasterProfile" ADD VALUE IF NOT EXISTS 'ACOUSTIC';
+ALTER TYPE "MasterProfile" ADD VALUE IF NOT EXISTS 'CLASSICAL';
+ALTER TYPE "MasterProfile" ADD VALUE IF NOT EXISTS 'PODCAST';
+ALTER TYPE "MasterProfile" ADD VALUE IF NOT EXISTS 'JAZZ';
@@ -0,0 +1,10 @@
+-- Migration: Add the named loudness targets and numeric (CUSTOM) targets
+-- ADD VALUE can't run inside a transaction block on PostgreSQL < 12
+
+ALTER TYPE "LoudnessTarget" ADD VALUE IF NOT EXISTS 'STREAMING';
+ALTER TYPE "LoudnessTarget" ADD VALUE IF NOT EXISTS 'CLUB';
+ALTER TYPE "LoudnessTarget" ADD VALUE IF NOT EXISTS 'BROADCAST_EBU';
+ALTER TYPE "LoudnessTarget" ADD VALUE IF NOT EXISTS 'BROADCAST_ATSC';
+ALTER TYPE "LoudnessTarget" ADD VALUE IF NOT EXISTS 'CUSTOM';
+
+ALTER TABLE "Master" ADD COLUMN IF NOT EXISTS "targetLufs" DOUBLE PRECISION;
@@ -0,0 +1,3 @@
+-- Migration: Store the plans dry-run jobs report
+
+ALTER TABLE "Job" ADD COLUMN IF NOT EXISTS "plan" JSONB;
@@ -0,0 +1,3 @@
+-- Migration: Store the true-peak ceiling each master's QC was checked against
+
+ALTER TABLE "QcReport" ADD COLUMN IF NOT EXISTS "truePeakMax" DOUBLE PRECISION;
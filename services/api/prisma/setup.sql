@@ -13,8 +13,8 @@ CREATE TYPE "DLQStatus" AS ENUM ('PENDING', 'RETRYING', 'EXHAUSTED', 'RESOLVED')
 CREATE TYPE "ProjectType" AS ENUM ('SINGLE', 'ALBUM');
 CREATE TYPE "ProjectStatus" AS ENUM ('CREATED', 'ANALYZING', 'ANALYZED', 'MASTERING', 'MASTERED', 'EXPORTING', 'EXPORTED', 'FAILED');
 CREATE TYPE "TrackStatus" AS ENUM ('UPLOADED', 'ANALYZING', 'ANALYZED', 'FIXING', 'FIXED', 'MASTERING', 'MASTERED', 'FAILED');
-CREATE TYPE "MasterProfile" AS ENUM ('BALANCED', 'WARM', 'PUNCHY', 'CUSTOM');
-CREATE TYPE "LoudnessTarget" AS ENUM ('LOW', 'MEDIUM', 'HIGH');
+CREATE TYPE "MasterProfile" AS ENUM ('BALANCED', 'WARM', 'PUNCHY', 'CUSTOM', 'HIP_HOP', 'EDM', 'ACOUSTIC', 'CLASSICAL', 'PODCAST', 'JAZZ');
+CREATE TYPE "LoudnessTarget" AS ENUM ('LOW', 'MEDIUM', 'HIGH', 'STREAMING', 'CLUB', 'BROADCAST_EBU', 'BROADCAST_ATSC', 'CUSTOM');
 CREATE TYPE "JobType" AS ENUM ('ANALYZE', 'FIX', 'MASTER', 'CODEC_PREVIEW', 'ALBUM_MASTER', 'EXPORT');
 CREATE TYPE "JobStatus" AS ENUM ('PENDING', 'QUEUED', 'PROCESSING', 'COMPLETED', 'FAILED');
 CREATE TYPE "ExportStatus" AS ENUM ('PENDING', 'QUEUED', 'PROCESSING', 'SUCCEEDED', 'COMPLETED', 'FAILED');
@@ -189,6 +189,7 @@ CREATE TABLE "Master" (
     "trackId" TEXT NOT NULL,
     "profile" "MasterProfile" NOT NULL,
     "loudnessTarget" "LoudnessTarget" NOT NULL,
+    "targetLufs" DOUBLE PRECISION,
     "wavHdUrl" TEXT,
     "wav16Url" TEXT,
     "mp3PreviewUrl" TEXT,
@@ -206,6 +207,7 @@ CREATE TABLE "QcReport" (
     "masterId" TEXT NOT NULL,
     "truePeakPasses" BOOLEAN NOT NULL,
     "truePeakValue" DOUBLE PRECISION NOT NULL,
+    "truePeakMax" DOUBLE PRECISION,
     "loudnessPasses" BOOLEAN NOT NULL,
     "loudnessValue" DOUBLE PRECISION NOT NULL,
     "lowFreqBalance" DOUBLE PRECISION,
@@ -245,6 +247,7 @@ CREATE TABLE "Job" (
     "progress" INTEGER NOT NULL DEFAULT 0,
     "message" TEXT,
     "resultUrl" TEXT,
+    "plan" JSONB,
     "error" TEXT,
     "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
     "startedAt" TIMESTAMP(3),
@@ -1,8 +1,12 @@
 // Redis client for job queue (lazy-loaded for serverless)
 import Redis from "ioredis";
+import { JOB_SCHEMA_VERSION } from "@budi/contracts";
 
 const redisUrl = process.env.REDIS_URL || "redis://localhost:6379";
 
+// How the workers read queues (their QUEUE_MODE): "list" or "stream"
+const queueMode = process.env.QUEUE_MODE || "list";
+
 // Lazy singleton for serverless environments
 let _redis: Redis | null = null;
 let _connectionFailed = false;
@@ -65,13 +69,20 @@ export const QUEUES = {
 } as const;
 
 /**
- * Enqueue a job to the specified queue
+ * Enqueue a job to the specified queue, stamped with the job schema version
+ * unless it already carries one (e.g. when replayed from the DLQ). In stream
+ * mode the queue is a Redis stream and the job an entry's `payload` field.
  */
 export async function enqueueJob<T extends object>(
   queue: string,
   job: T
 ): Promise<void> {
-  await redis.lpush(queue, JSON.stringify(job));
+  const payload = JSON.stringify({ schemaVersion: JOB_SCHEMA_VERSION, ...job });
+  if (queueMode === "stream") {
+    await redis.xadd(queue, "*", "payload", payload);
+  } else {
+    await redis.lpush(queue, payload);
+  }
 }
 
 /**
@@ -53,21 +53,79 @@ export const fixModuleSchema = z.enum([
   "silence_trim",
 ]);
 
+/** A CLAP plugin configured on the worker, restored from one of its presets */
+export const clapStageSchema = z.object({
+  stage: z.literal("clap"),
+  plugin: z.string().min(1),
+  preset: z.string().min(1).optional(),
+});
+
+/** Customer processing a fix or master job runs: a WebAssembly module, or a tool or CLAP plugin configured on the worker */
+export const customStageSchema = z.discriminatedUnion("stage", [
+  z.object({
+    stage: z.literal("plugin"),
+    moduleUrl: z.string().url(),
+    moduleSha256: z.string().regex(/^[0-9a-f]{64}$/i),
+    name: z.string().regex(/^[A-Za-z0-9_-]{1,64}$/).optional(),
+    params: z.unknown().optional(),
+  }),
+  z.object({
+    stage: z.literal("external"),
+    processor: z.string().min(1),
+    params: z.unknown().optional(),
+  }),
+  clapStageSchema,
+]);
+
 export const fixTrackSchema = z.object({
   modules: z.array(fixModuleSchema).min(1),
+  /** Run after the fix modules */
+  stages: z.array(customStageSchema).max(32).optional(),
 });
 
 // ============================================================================
 // Mastering schemas
 // ============================================================================
 
-export const masterProfileSchema = z.enum(["balanced", "warm", "punchy", "custom"]);
+export const masterProfileSchema = z.enum([
+  "balanced",
+  "warm",
+  "punchy",
+  "custom",
+  "hip_hop",
+  "edm",
+  "acoustic",
+  "classical",
+  "podcast",
+  "jazz",
+]);
 
-export const loudnessTargetSchema = z.enum(["low", "medium", "high"]);
+/** A named target, or the integrated loudness in LUFS */
+export const loudnessTargetSchema = z.union([
+  z.enum(["low", "medium", "high", "streaming", "club", "broadcast_ebu", "broadcast_atsc"]),
+  z.number().min(-40).max(-4),
+]);
 
 export const masterTrackSchema = z.object({
   profile: masterProfileSchema,
   loudnessTarget: loudnessTargetSchema,
+  /** Reference track whose tonal balance and loudness to match */
+  referenceUrl: z.string().url().optional(),
+  referenceSha256: z.string().regex(/^[0-9a-f]{64}$/i).optional(),
+  eqMode: z.enum(["iir", "linear-phase"]).optional(),
+  precision: z.enum(["f32", "f64"]).optional(),
+  limiterMode: z.enum(["brick-wall", "multiband"]).optional(),
+  /** Minimum loudness range to preserve (LU) */
+  minLoudnessRange: z.number().min(0).max(30).optional(),
+  dither: z.enum(["none", "tpdf", "noise-shaped"]).optional(),
+  /** Dry/wet blend of the whole chain */
+  mix: z.number().min(0).max(1).optional(),
+  /** Gain after the chain (dB); never positive, which would break the ceiling */
+  outputTrimDb: z.number().min(-24).max(0).optional(),
+  /** Run on the source before the mastering chain */
+  stages: z.array(customStageSchema).max(32).optional(),
+  /** Run after the chain's limiter; the master is measured on its output */
+  finalStage: clapStageSchema.optional(),
 });
 
 // ============================================================================
@@ -97,6 +155,11 @@ export const albumMasterSchema = z.object({
   profile: masterProfileSchema,
   loudnessTarget: loudnessTargetSchema,
   normalizeLoudness: z.boolean().default(true),
+  /** Silence after each track in the continuous render (seconds) */
+  gapSeconds: z.array(z.number().min(0).max(60)).optional(),
+  /** Crossfade into the next track (seconds); replaces the gap when set */
+  crossfadeSeconds: z.array(z.number().min(0).max(30)).optional(),
+  renderContinuous: z.boolean().default(false),
 });
 
 // ============================================================================
@@ -108,6 +171,8 @@ export const exportFormatSchema = z.enum(["wav-24", "wav-16", "mp3-320", "flac"]
 export const exportProjectSchema = z.object({
   formats: z.array(exportFormatSchema).min(1),
   includeQc: z.boolean().default(true),
+  /** FLAC compression level (0-8, default 5) */
+  flacCompressionLevel: z.number().int().min(0).max(8).optional(),
 });
 
 // ============================================================================
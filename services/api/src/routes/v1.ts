@@ -24,6 +24,9 @@ import type {
   CodecPreviewJob,
   AlbumMasterJob,
   ExportJob,
+  ExportTrack,
+  FixTrackRequest,
+  MasterTrackRequest,
 } from "@budi/contracts";
 
 import { rateLimitHandler } from "../middleware/rateLimiter.js";
@@ -1057,7 +1060,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
   // ============================================================================
 
   /** Enqueue a fix job for a track */
-  app.post<{ Params: { trackId: string }; Body: { modules: string[] } }>(
+  app.post<{ Params: { trackId: string }; Body: FixTrackRequest }>(
     "/v1/tracks/:trackId/fix",
     { preHandler: [app.authenticate] },
     async (request, reply) => {
@@ -1067,7 +1070,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         return reply.code(400).send({ error: "Validation failed", details: parsed.error.issues });
       }
 
-      const { modules } = parsed.data;
+      const { modules, stages } = parsed.data;
 
       const track = await prisma.track.findFirst({
         where: {
@@ -1088,7 +1091,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
           trackId,
           type: "FIX",
           status: "QUEUED",
-          payload: { trackId, sourceUrl: track.originalUrl, modules },
+          payload: { trackId, sourceUrl: track.originalUrl, modules, stages },
         },
       });
 
@@ -1105,6 +1108,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         trackId,
         sourceUrl: track.originalUrl,
         modules,
+        stages,
       };
       await enqueueJob(QUEUES.DSP_JOBS, job);
 
@@ -1122,7 +1126,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
   // ============================================================================
 
   /** Enqueue a master job for a track */
-  app.post<{ Params: { trackId: string }; Body: { profile: string; loudnessTarget: string } }>(
+  app.post<{ Params: { trackId: string }; Body: MasterTrackRequest }>(
     "/v1/tracks/:trackId/master",
     { preHandler: [app.authenticate] },
     async (request, reply) => {
@@ -1132,7 +1136,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         return reply.code(400).send({ error: "Validation failed", details: parsed.error.issues });
       }
 
-      const { profile, loudnessTarget } = parsed.data;
+      const { profile, loudnessTarget, ...options } = parsed.data;
 
       const track = await prisma.track.findFirst({
         where: {
@@ -1155,7 +1159,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
           trackId,
           type: "MASTER",
           status: "QUEUED",
-          payload: { trackId, sourceUrl, profile, loudnessTarget },
+          payload: { trackId, sourceUrl, profile, loudnessTarget, ...options },
         },
       });
 
@@ -1173,6 +1177,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         sourceUrl,
         profile,
         loudnessTarget,
+        ...options,
       };
       await enqueueJob(QUEUES.DSP_JOBS, job);
 
@@ -1260,7 +1265,15 @@ const v1Routes: FastifyPluginAsync = async (app) => {
   /** Enqueue an album master job */
   app.post<{
     Params: { projectId: string };
-    Body: { trackIds?: string[]; profile: string; loudnessTarget: string; normalizeLoudness?: boolean };
+    Body: {
+      trackIds?: string[];
+      profile: string;
+      loudnessTarget: string | number;
+      normalizeLoudness?: boolean;
+      gapSeconds?: number[];
+      crossfadeSeconds?: number[];
+      renderContinuous?: boolean;
+    };
   }>(
     "/v1/projects/:projectId/album-master",
     { preHandler: [app.authenticate] },
@@ -1271,7 +1284,14 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         return reply.code(400).send({ error: "Validation failed", details: parsed.error.issues });
       }
 
-      const { profile, loudnessTarget, normalizeLoudness = true } = parsed.data;
+      const {
+        profile,
+        loudnessTarget,
+        normalizeLoudness = true,
+        gapSeconds,
+        crossfadeSeconds,
+        renderContinuous = false,
+      } = parsed.data;
 
       const project = await prisma.project.findFirst({
         where: { id: projectId, userId: request.userId },
@@ -1289,6 +1309,19 @@ const v1Routes: FastifyPluginAsync = async (app) => {
 
       // Use provided order or default order
       const trackIds = parsed.data.trackIds || project.tracks.map((t: (typeof project.tracks)[number]) => t.id);
+      const tracksById = new Map(
+        project.tracks.map((t: (typeof project.tracks)[number]) => [t.id, t] as const)
+      );
+      const unknownTracks = trackIds.filter((id: string) => !tracksById.has(id));
+      if (unknownTracks.length > 0) {
+        return reply.code(400).send({ error: "Tracks not in project", unknownTracks });
+      }
+
+      // Use fixed URLs if available, otherwise originals
+      const sourceUrls = trackIds.map((id: string) => {
+        const track = tracksById.get(id)!;
+        return track.fixedUrl || track.originalUrl;
+      });
       const jobId = generateId("job_");
 
       // Create job record
@@ -1298,7 +1331,17 @@ const v1Routes: FastifyPluginAsync = async (app) => {
           projectId,
           type: "ALBUM_MASTER",
           status: "QUEUED",
-          payload: { projectId, trackIds, profile, loudnessTarget, normalizeLoudness },
+          payload: {
+            projectId,
+            trackIds,
+            sourceUrls,
+            profile,
+            loudnessTarget,
+            normalizeLoudness,
+            gapSeconds,
+            crossfadeSeconds,
+            renderContinuous,
+          },
         },
       });
 
@@ -1314,9 +1357,13 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         jobId,
         projectId,
         trackIds,
+        sourceUrls,
         profile,
         loudnessTarget,
         normalizeLoudness,
+        gapSeconds,
+        crossfadeSeconds,
+        renderContinuous,
       };
       await enqueueJob(QUEUES.DSP_JOBS, job);
 
@@ -1336,7 +1383,10 @@ const v1Routes: FastifyPluginAsync = async (app) => {
   // ============================================================================
 
   /** Export a project */
-  app.post<{ Params: { projectId: string }; Body: { formats: string[]; includeQc?: boolean } }>(
+  app.post<{
+    Params: { projectId: string };
+    Body: { formats: string[]; includeQc?: boolean; flacCompressionLevel?: number };
+  }>(
     "/v1/projects/:projectId/export",
     { preHandler: [app.authenticate] },
     async (request, reply) => {
@@ -1346,13 +1396,16 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         return reply.code(400).send({ error: "Validation failed", details: parsed.error.issues });
       }
 
-      const { formats, includeQc = true } = parsed.data;
+      const { formats, includeQc = true, flacCompressionLevel } = parsed.data;
 
       const project = await prisma.project.findFirst({
         where: { id: projectId, userId: request.userId },
         include: {
           tracks: {
-            include: { masters: { take: 1, orderBy: { createdAt: "desc" } } },
+            orderBy: { orderIndex: "asc" },
+            include: {
+              masters: { take: 1, orderBy: { createdAt: "desc" }, include: { qcReport: true } },
+            },
           },
         },
       });
@@ -1361,7 +1414,9 @@ const v1Routes: FastifyPluginAsync = async (app) => {
       }
 
       // Verify all tracks are mastered
-      const unmasteredTracks = project.tracks.filter((t: (typeof project.tracks)[number]) => t.masters.length === 0);
+      const unmasteredTracks = project.tracks.filter(
+        (t: (typeof project.tracks)[number]) => !t.masters[0]?.wavHdUrl
+      );
       if (unmasteredTracks.length > 0) {
         return reply.code(400).send({
           error: "All tracks must be mastered before export",
@@ -1369,6 +1424,16 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         });
       }
 
+      // The latest master of each track, in album order
+      const tracks: ExportTrack[] = project.tracks.map((t: (typeof project.tracks)[number]) => {
+        const master = t.masters[0];
+        return {
+          trackId: t.id,
+          masterUrl: master.wavHdUrl as string,
+          qcReportUrl: master.qcReport?.reportUrl ?? undefined,
+        };
+      });
+
       const jobId = generateId("job_");
       const exportId = generateId("exp_");
 
@@ -1390,7 +1455,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
           projectId,
           type: "EXPORT",
           status: "QUEUED",
-          payload: { projectId, formats, includeQc },
+          payload: { projectId, formats, includeQc, tracks, flacCompressionLevel },
         },
       });
 
@@ -1407,6 +1472,8 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         projectId,
         formats,
         includeQc,
+        tracks,
+        flacCompressionLevel,
       };
       await enqueueJob(QUEUES.DSP_JOBS, job);
 
@@ -1486,6 +1553,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
         trackId: job.trackId,
         trackName: job.track?.name,
         resultUrl: job.resultUrl,
+        plan: job.plan,
         error: job.error,
         createdAt: job.createdAt,
         startedAt: job.startedAt,
@@ -1592,6 +1660,7 @@ const v1Routes: FastifyPluginAsync = async (app) => {
             data: {
               truePeakPasses: master.qcReport.truePeakPasses,
               truePeakValue: master.qcReport.truePeakValue,
+              truePeakMax: master.qcReport.truePeakMax,
               loudnessPasses: master.qcReport.loudnessPasses,
               loudnessValue: master.qcReport.loudnessValue,
               lowFreqBalance: master.qcReport.lowFreqBalance,
@@ -7,9 +7,67 @@ import type {
   FixResult,
   MasterResult,
   CodecPreviewResult,
+  CodecSweepResult,
+  StreamingLadderResult,
   AlbumMasterResult,
   ExportResult,
+  PlanResult,
 } from "@budi/contracts";
+import type { LoudnessTarget, MasterProfile } from "../../generated/prisma/index.js";
+
+/** Record a mastered track, with its QC report if one was uploaded */
+async function createMaster(
+  trackId: string,
+  jobPayload: unknown,
+  data: NonNullable<MasterResult["data"]>
+): Promise<void> {
+  // Get job payload for profile info
+  const payload = jobPayload as { profile?: string; loudnessTarget?: string | number };
+  const target = payload.loudnessTarget;
+
+  // Create master record
+  const master = await prisma.master.create({
+    data: {
+      trackId,
+      profile: (payload.profile?.toUpperCase() as MasterProfile) || "BALANCED",
+      loudnessTarget:
+        typeof target === "number" ? "CUSTOM" : (target?.toUpperCase() as LoudnessTarget) || "MEDIUM",
+      targetLufs: typeof target === "number" ? target : null,
+      wavHdUrl: data.wavHdUrl,
+      wav16Url: data.wav16Url,
+      mp3PreviewUrl: data.mp3PreviewUrl,
+      finalLufs: data.finalLufs,
+      finalTruePeak: data.finalTruePeak,
+      passesQc: data.passesQc,
+    },
+  });
+
+  // Create QC report if available, with the worker's own verdicts
+  if (data.qcReportUrl) {
+    const truePeakPasses = data.finalTruePeak <= data.truePeakMax;
+    const failureReasons = [
+      ...(truePeakPasses
+        ? []
+        : [`True peak ${data.finalTruePeak.toFixed(2)} dBTP is over the ${data.truePeakMax} dBTP ceiling`]),
+      ...(data.passesLoudness
+        ? []
+        : [`Loudness ${data.finalLufs.toFixed(1)} LUFS missed the target`]),
+    ];
+    await prisma.qcReport.create({
+      data: {
+        masterId: master.id,
+        truePeakPasses,
+        truePeakValue: data.finalTruePeak,
+        truePeakMax: data.truePeakMax,
+        loudnessPasses: data.passesLoudness,
+        loudnessValue: data.finalLufs,
+        overallPass: data.passesQc,
+        failureReasons,
+        reportUrl: data.qcReportUrl,
+      },
+    });
+  }
+}
 
 const webhookRoutes: FastifyPluginAsync = async (app) => {
   // SECURITY: WEBHOOK_SECRET must be set - fail fast if missing in production
@@ -50,6 +108,33 @@ const webhookRoutes: FastifyPluginAsync = async (app) => {
     reply.send({ ok: true });
   });
 
+  /** Report what a dry-run job would have done; its track is left as it was */
+  app.post<{ Params: { jobId: string }; Body: PlanResult }>(
+    "/webhooks/jobs/:jobId/plan",
+    async (request, reply) => {
+      const { jobId } = request.params;
+      const result = request.body;
+
+      const job = await prisma.job.findUnique({ where: { id: jobId } });
+      if (!job) {
+        return reply.code(404).send({ error: "Job not found" });
+      }
+
+      await prisma.job.update({
+        where: { id: jobId },
+        data: {
+          status: "COMPLETED",
+          progress: 100,
+          message: "Dry run: nothing was rendered",
+          plan: result.data as object,
+          completedAt: new Date(),
+        },
+      });
+
+      reply.send({ ok: true });
+    }
+  );
+
   /** Report analysis job completion */
   app.post<{ Params: { jobId: string }; Body: AnalysisResult }>(
     "/webhooks/jobs/:jobId/analysis",
@@ -213,39 +298,7 @@ const webhookRoutes: FastifyPluginAsync = async (app) => {
       }
 
       if (result.status === "completed" && result.data) {
-        // Get job payload for profile info
-        const payload = job.payload as { profile?: string; loudnessTarget?: string };
-
-        // Create master record
-        const master = await prisma.master.create({
-          data: {
-            trackId: job.trackId,
-            profile: (payload.profile?.toUpperCase() as "BALANCED" | "WARM" | "PUNCHY" | "CUSTOM") || "BALANCED",
-            loudnessTarget: (payload.loudnessTarget?.toUpperCase() as "LOW" | "MEDIUM" | "HIGH") || "MEDIUM",
-            wavHdUrl: result.data.wavHdUrl,
-            wav16Url: result.data.wav16Url,
-            mp3PreviewUrl: result.data.mp3PreviewUrl,
-            finalLufs: result.data.finalLufs,
-            finalTruePeak: result.data.finalTruePeak,
-            passesQc: result.data.passesQc,
-          },
-        });
-
-        // Create QC report if available
-        if (result.data.qcReportUrl) {
-          await prisma.qcReport.create({
-            data: {
-              masterId: master.id,
-              truePeakPasses: result.data.finalTruePeak <= -2.0,
-              truePeakValue: result.data.finalTruePeak,
-              loudnessPasses: true, // Will be calculated properly by worker
-              loudnessValue: result.data.finalLufs,
-              overallPass: result.data.passesQc,
-              failureReasons: result.data.passesQc ? [] : ["QC check failed"],
-              reportUrl: result.data.qcReportUrl,
-            },
-          });
-        }
+        await createMaster(job.trackId, job.payload, result.data);
 
         // Update track status
         await prisma.track.update({
@@ -334,6 +387,83 @@ const webhookRoutes: FastifyPluginAsync = async (app) => {
     }
   );
 
+  /** Report codec sweep job completion */
+  app.post<{ Params: { jobId: string }; Body: CodecSweepResult }>(
+    "/webhooks/jobs/:jobId/codec-sweep",
+    async (request, reply) => {
+      const { jobId } = request.params;
+      const result = request.body;
+
+      const job = await prisma.job.findUnique({ where: { id: jobId } });
+      if (!job || !job.trackId) {
+        return reply.code(404).send({ error: "Job not found" });
+      }
+
+      if (result.status === "completed" && result.data) {
+        // The sweep itself stays in the job's result
+        await prisma.job.update({
+          where: { id: jobId },
+          data: {
+            status: "COMPLETED",
+            progress: 100,
+            message: result.data.recommendedBitrate
+              ? `Recommended ${result.data.codec} at ${result.data.recommendedBitrate} kbps`
+              : `No ${result.data.codec} bitrate within the artifact threshold`,
+            completedAt: new Date(),
+          },
+        });
+      } else {
+        await prisma.job.update({
+          where: { id: jobId },
+          data: {
+            status: "FAILED",
+            error: result.error,
+            completedAt: new Date(),
+          },
+        });
+      }
+
+      reply.send({ ok: true });
+    }
+  );
+
+  /** Report streaming ladder job completion */
+  app.post<{ Params: { jobId: string }; Body: StreamingLadderResult }>(
+    "/webhooks/jobs/:jobId/streaming-ladder",
+    async (request, reply) => {
+      const { jobId } = request.params;
+      const result = request.body;
+
+      const job = await prisma.job.findUnique({ where: { id: jobId } });
+      if (!job || !job.trackId) {
+        return reply.code(404).send({ error: "Job not found" });
+      }
+
+      if (result.status === "completed" && result.data) {
+        await prisma.job.update({
+          where: { id: jobId },
+          data: {
+            status: "COMPLETED",
+            progress: 100,
+            resultUrl: result.data.manifestUrl,
+            completedAt: new Date(),
+          },
+        });
+      } else {
+        await prisma.job.update({
+          where: { id: jobId },
+          data: {
+            status: "FAILED",
+            error: result.error,
+            completedAt: new Date(),
+          },
+        });
+      }
+
+      reply.send({ ok: true });
+    }
+  );
+
   /** Report album master job completion */
   app.post<{ Params: { jobId: string }; Body: AlbumMasterResult }>(
     "/webhooks/jobs/:jobId/album-master",
@@ -347,6 +477,15 @@ const webhookRoutes: FastifyPluginAsync = async (app) => {
       }
 
       if (result.status === "completed" && result.data) {
+        // Record each track's master
+        for (const track of result.data.tracks) {
+          await createMaster(track.trackId, job.payload, track);
+          await prisma.track.update({
+            where: { id: track.trackId },
+            data: { status: "MASTERED" },
+          });
+        }
+
         // Update project status
         await prisma.project.update({
           where: { id: job.projectId },
@@ -359,7 +498,7 @@ const webhookRoutes: FastifyPluginAsync = async (app) => {
           data: {
             status: "COMPLETED",
             progress: 100,
-            resultUrl: result.data.albumQcReport.reportUrl,
+            resultUrl: result.data.albumQcUrl,
             completedAt: new Date(),
           },
         });
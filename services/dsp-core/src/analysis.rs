@@ -0,0 +1,491 @@
+//! Audio analysis: loudness, peaks, spectral metrics
+//!
+//! Every metric is accumulated a block at a time by `Analyzer`, so a source
+//! can be measured as it is decoded, holding no more than an FFT window or
+//! resampler chunk of it: a three-hour 96 kHz live recording is analyzed in
+//! the same memory as a single. Audio that isn't a file (a live stream, an
+//! upload arriving in HTTP chunks) is pushed in with `Analyzer::feed` as it
+//! comes, and `Analyzer::snapshot` gives the metrics so far at any point.
+//!
+//! It is also a single pass: input is cut into `BLOCK_FRAMES` blocks, and
+//! each block feeds loudness, true peak, spectrum and stereo while it is
+//! still in cache, with sample peak, clipping, DC offset and the mono
+//! downmix gathered in one sweep of its samples.
+
+use anyhow::Result;
+use ebur128::{EbuR128, Mode};
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::settings;
+use crate::simd;
+use crate::true_peak::TruePeakMeter;
+use crate::types::{AnalysisResult, PartialAnalysis};
+use crate::AudioBuffer;
+
+/// FFT size for the long-term average spectrum
+const FFT_SIZE: usize = 4096;
+
+/// Frames fed to every accumulator at a time, small enough to stay in cache
+/// (128 KB of 8-channel audio)
+const BLOCK_FRAMES: usize = 4096;
+
+/// Analyze an audio buffer and return comprehensive metrics
+#[tracing::instrument(name = "dsp.analyze", skip_all)]
+pub fn analyze_audio(buffer: &AudioBuffer, bit_depth: u32) -> Result<AnalysisResult> {
+    let mut analyzer = Analyzer::new(buffer.channels, buffer.sample_rate)?;
+    analyzer.add(buffer)?;
+    analyzer.finalize(bit_depth)
+}
+
+/// Running state of every analysis metric, fed a block of audio at a time
+pub struct Analyzer {
+    channels: usize,
+    sample_rate: u32,
+    frames: usize,
+    /// Loudness using ITU-R BS.1770 (via ebur128)
+    ebu: EbuR128,
+    /// Largest absolute sample
+    sample_peak: f32,
+    true_peak: TruePeakMeter,
+    clip_threshold: f32,
+    clipped_samples: usize,
+    /// Sum of every sample of every channel, for the DC offset
+    sample_sum: f64,
+    spectrum: AverageSpectrum,
+    stereo: StereoSums,
+    /// Scratch space for a block interleaved for ebur128 and downmixed
+    interleaved: Vec<f32>,
+    mono: Vec<f32>,
+    /// Scratch space for frames given interleaved to `feed`
+    planes: Vec<Vec<f32>>,
+}
+
+impl Analyzer {
+    pub fn new(channels: usize, sample_rate: u32) -> Result<Self> {
+        let mode = Mode::I | Mode::LRA | Mode::S | Mode::M;
+        Ok(Self {
+            channels,
+            sample_rate,
+            frames: 0,
+            ebu: EbuR128::new(channels as u32, sample_rate, mode)?,
+            sample_peak: 0.0,
+            true_peak: TruePeakMeter::new(channels, sample_rate)?,
+            clip_threshold: settings::get().clip_threshold,
+            clipped_samples: 0,
+            sample_sum: 0.0,
+            spectrum: AverageSpectrum::new(FFT_SIZE),
+            stereo: StereoSums::default(),
+            interleaved: Vec::with_capacity(BLOCK_FRAMES * channels),
+            mono: Vec::with_capacity(BLOCK_FRAMES),
+            planes: Vec::new(),
+        })
+    }
+
+    /// Add the next block of the track
+    pub fn add(&mut self, block: &AudioBuffer) -> Result<()> {
+        let frames = block.frame_count();
+        for start in (0..frames).step_by(BLOCK_FRAMES) {
+            let end = (start + BLOCK_FRAMES).min(frames);
+            self.add_frames(&block.samples, start..end)?;
+        }
+        Ok(())
+    }
+
+    /// Add the next interleaved frames of the track, in any amount
+    pub fn feed(&mut self, frames: &[f32]) -> Result<()> {
+        anyhow::ensure!(
+            frames.len().is_multiple_of(self.channels),
+            "{} samples aren't whole frames of {} channels",
+            frames.len(),
+            self.channels
+        );
+        let mut planes = std::mem::take(&mut self.planes);
+        planes.resize_with(self.channels, Vec::new);
+        for block in frames.chunks(BLOCK_FRAMES * self.channels) {
+            for (c, plane) in planes.iter_mut().enumerate() {
+                plane.clear();
+                plane.extend(block.iter().skip(c).step_by(self.channels));
+            }
+            self.add_frames(&planes, 0..block.len() / self.channels)?;
+        }
+        self.planes = planes;
+        Ok(())
+    }
+
+    /// Metrics of everything added so far
+    ///
+    /// The true peak leaves out the last few milliseconds, still in the
+    /// oversampler until more audio or `finalize` pushes them through.
+    pub fn snapshot(&self) -> PartialAnalysis {
+        let measured = |lufs: Result<f64, _>| lufs.ok().filter(|l: &f64| l.is_finite());
+        PartialAnalysis {
+            duration_secs: self.frames as f64 / self.sample_rate as f64,
+            integrated_lufs: measured(self.ebu.loudness_global()),
+            loudness_range: self.ebu.loudness_range().ok(),
+            short_term_lufs: measured(self.ebu.loudness_shortterm()),
+            momentary_lufs: measured(self.ebu.loudness_momentary()),
+            sample_peak: peak_db(self.sample_peak),
+            true_peak: self.true_peak.peak().db(),
+            clipped_samples: self.clipped_samples,
+        }
+    }
+
+    /// Feed frames `range` of `planes` to every accumulator
+    fn add_frames(&mut self, planes: &[Vec<f32>], range: Range<usize>) -> Result<()> {
+        self.frames += range.len();
+        let planes: Vec<&[f32]> = planes.iter().map(|p| &p[range.clone()]).collect();
+
+        self.interleaved.clear();
+        simd::interleave(&planes, 0..range.len(), &mut self.interleaved);
+        self.ebu.add_frames_f32(&self.interleaved)?;
+
+        // Peaks, clipping, DC and the downmix in one sweep of the samples
+        self.mono.clear();
+        self.mono.resize(range.len(), 0.0);
+        for plane in &planes {
+            for (mono, &sample) in self.mono.iter_mut().zip(*plane) {
+                let abs_sample = sample.abs();
+                self.sample_peak = self.sample_peak.max(abs_sample);
+                if abs_sample >= self.clip_threshold {
+                    self.clipped_samples += 1;
+                }
+                self.sample_sum += sample as f64;
+                *mono += sample;
+            }
+        }
+        let channels = self.channels as f32;
+        for mono in &mut self.mono {
+            *mono /= channels;
+        }
+
+        self.true_peak.add(&planes)?;
+        self.spectrum.add(&self.mono)?;
+        if self.channels >= 2 {
+            self.stereo.add(planes[0], planes[1]);
+        }
+        Ok(())
+    }
+
+    /// Metrics of everything added, once the track has ended
+    pub fn finalize(self, bit_depth: u32) -> Result<AnalysisResult> {
+        let integrated_lufs = self.ebu.loudness_global().unwrap_or(-70.0);
+        let loudness_range = self.ebu.loudness_range().unwrap_or(0.0);
+
+        // Get max short-term and momentary
+        let short_term_max = self.ebu.loudness_shortterm().unwrap_or(-70.0);
+        let momentary_max = self.ebu.loudness_momentary().unwrap_or(-70.0);
+
+        let clipped_samples = self.clipped_samples;
+
+        let (has_dc_offset, dc_offset_value) = if self.frames == 0 {
+            (false, None)
+        } else {
+            let dc_offset = self.sample_sum / (self.frames * self.channels) as f64;
+            let threshold = settings::get().dc_offset_threshold;
+            (dc_offset.abs() > threshold, Some(dc_offset))
+        };
+
+        let (spectral_centroid, spectral_rolloff) = match self.spectrum.finish() {
+            Some(magnitudes) => spectral_shape(&magnitudes, self.sample_rate),
+            None => (None, None),
+        };
+
+        // Stereo analysis (only for stereo tracks)
+        let (stereo_correlation, stereo_width) = if self.channels >= 2 {
+            self.stereo.finish()
+        } else {
+            (None, None)
+        };
+
+        Ok(AnalysisResult {
+            integrated_lufs,
+            loudness_range,
+            short_term_max,
+            momentary_max,
+            sample_peak: peak_db(self.sample_peak),
+            true_peak: self.true_peak.finish()?.db(),
+            spectral_centroid,
+            spectral_rolloff,
+            stereo_correlation,
+            stereo_width,
+            has_clipping: clipped_samples > 0,
+            has_dc_offset,
+            dc_offset_value,
+            clipped_samples,
+            sample_rate: self.sample_rate,
+            bit_depth,
+            channels: self.channels,
+            duration_secs: self.frames as f64 / self.sample_rate as f64,
+        })
+    }
+}
+
+/// Peak level in dBFS, with silence at -96
+fn peak_db(peak: f32) -> f64 {
+    if peak > 0.0 {
+        20.0 * (peak as f64).log10()
+    } else {
+        -96.0 // Below noise floor
+    }
+}
+
+/// Long-term average magnitude spectrum of the mono downmix, over
+/// Hann-windowed FFTs with 50% overlap
+struct AverageSpectrum {
+    fft_size: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    /// Downmix not yet covered by a full window
+    mono: Vec<f32>,
+    /// Scratch space for one windowed FFT
+    input: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    magnitudes: Vec<f64>,
+    windows: usize,
+}
+
+impl AverageSpectrum {
+    fn new(fft_size: usize) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(fft_size);
+        let window = (0..fft_size)
+            .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / fft_size as f32).cos()))
+            .collect();
+        Self {
+            fft_size,
+            input: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            fft,
+            window,
+            mono: Vec::with_capacity(fft_size + BLOCK_FRAMES),
+            magnitudes: vec![0.0; fft_size / 2 + 1],
+            windows: 0,
+        }
+    }
+
+    /// Add the next stretch of the mono downmix
+    fn add(&mut self, mono: &[f32]) -> Result<()> {
+        self.mono.extend_from_slice(mono);
+
+        let hop_size = self.fft_size / 2;
+        let mut start = 0;
+        while start + self.fft_size <= self.mono.len() {
+            // Apply Hann window
+            for ((input, &sample), &window) in self
+                .input
+                .iter_mut()
+                .zip(&self.mono[start..start + self.fft_size])
+                .zip(&self.window)
+            {
+                *input = sample * window;
+            }
+
+            self.fft.process(&mut self.input, &mut self.spectrum)?;
+
+            // Accumulate magnitudes
+            for (magnitude, c) in self.magnitudes.iter_mut().zip(&self.spectrum) {
+                *magnitude += (c.re * c.re + c.im * c.im).sqrt() as f64;
+            }
+            self.windows += 1;
+            start += hop_size;
+        }
+        self.mono.drain(..start);
+        Ok(())
+    }
+
+    /// The average, or `None` when less than one window was added
+    fn finish(self) -> Option<Vec<f64>> {
+        if self.windows == 0 {
+            return None;
+        }
+        let windows = self.windows as f64;
+        Some(self.magnitudes.into_iter().map(|m| m / windows).collect())
+    }
+}
+
+/// Running sums of the first two channels for correlation and width
+#[derive(Default)]
+struct StereoSums {
+    n: usize,
+    sum_l: f64,
+    sum_r: f64,
+    sum_ll: f64,
+    sum_rr: f64,
+    sum_lr: f64,
+    mid_energy: f64,
+    side_energy: f64,
+}
+
+impl StereoSums {
+    fn add(&mut self, left: &[f32], right: &[f32]) {
+        for (&l, &r) in left.iter().zip(right) {
+            let l = l as f64;
+            let r = r as f64;
+            self.sum_l += l;
+            self.sum_r += r;
+            self.sum_ll += l * l;
+            self.sum_rr += r * r;
+            self.sum_lr += l * r;
+            let mid = (l + r) / 2.0;
+            let side = (l - r) / 2.0;
+            self.mid_energy += mid * mid;
+            self.side_energy += side * side;
+        }
+        self.n += left.len().min(right.len());
+    }
+
+    /// Correlation coefficient and stereo width (side share of mid/side energy)
+    fn finish(&self) -> (Option<f64>, Option<f64>) {
+        if self.n == 0 {
+            return (None, None);
+        }
+
+        let n = self.n as f64;
+        let mean_l = self.sum_l / n;
+        let mean_r = self.sum_r / n;
+
+        let var_l = self.sum_ll / n - mean_l * mean_l;
+        let var_r = self.sum_rr / n - mean_r * mean_r;
+        let cov_lr = self.sum_lr / n - mean_l * mean_r;
+
+        let correlation = if var_l > 0.0 && var_r > 0.0 {
+            cov_lr / (var_l.sqrt() * var_r.sqrt())
+        } else {
+            0.0
+        };
+
+        let total = self.mid_energy + self.side_energy;
+        let stereo_width = if total > 0.0 {
+            self.side_energy / total
+        } else {
+            0.0
+        };
+
+        (Some(correlation), Some(stereo_width))
+    }
+}
+
+/// Spectral centroid and rolloff of an average magnitude spectrum
+fn spectral_shape(avg_magnitudes: &[f64], sample_rate: u32) -> (Option<f64>, Option<f64>) {
+    // Calculate spectral centroid
+    let freq_resolution = sample_rate as f64 / FFT_SIZE as f64;
+    let mut weighted_sum = 0.0;
+    let mut mag_sum = 0.0;
+
+    for (i, &mag) in avg_magnitudes.iter().enumerate() {
+        let freq = i as f64 * freq_resolution;
+        weighted_sum += freq * mag;
+        mag_sum += mag;
+    }
+
+    let spectral_centroid = if mag_sum > 0.0 {
+        Some(weighted_sum / mag_sum)
+    } else {
+        None
+    };
+
+    // Calculate spectral rolloff (frequency below which 85% of energy exists)
+    let total_energy: f64 = avg_magnitudes.iter().map(|m| m * m).sum();
+    let rolloff_threshold = total_energy * 0.85;
+    let mut cumulative_energy = 0.0;
+    let mut rolloff_bin = 0;
+
+    for (i, &mag) in avg_magnitudes.iter().enumerate() {
+        cumulative_energy += mag * mag;
+        if cumulative_energy >= rolloff_threshold {
+            rolloff_bin = i;
+            break;
+        }
+    }
+
+    let spectral_rolloff = Some(rolloff_bin as f64 * freq_resolution);
+
+    (spectral_centroid, spectral_rolloff)
+}
+
+/// Compute the long-term average magnitude spectrum of the mono downmix
+///
+/// Returns `None` when the buffer is shorter than a single FFT window.
+fn average_spectrum(buffer: &AudioBuffer, fft_size: usize) -> Result<Option<Vec<f64>>> {
+    let mut spectrum = AverageSpectrum::new(fft_size);
+    let mut mono = Vec::with_capacity(BLOCK_FRAMES);
+    for start in (0..buffer.frame_count()).step_by(BLOCK_FRAMES) {
+        let end = (start + BLOCK_FRAMES).min(buffer.frame_count());
+        // Mix channels to mono for spectral analysis
+        mono.clear();
+        mono.extend((start..end).map(|i| {
+            let sum: f32 = buffer.samples.iter().map(|ch| ch[i]).sum();
+            sum / buffer.channels as f32
+        }));
+        spectrum.add(&mono)?;
+    }
+    Ok(spectrum.finish())
+}
+
+/// Measure the energy in each frequency band relative to the total energy, in dB
+///
+/// Because levels are relative, the result describes tonal balance
+/// independently of overall loudness. Returns `None` for buffers too short to
+/// analyze.
+pub fn band_levels(buffer: &AudioBuffer, bands: &[(f64, f64)]) -> Result<Option<Vec<f64>>> {
+    let avg_magnitudes = match average_spectrum(buffer, FFT_SIZE)? {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+
+    let total_energy: f64 = avg_magnitudes.iter().map(|m| m * m).sum();
+    if total_energy <= 0.0 {
+        return Ok(None);
+    }
+
+    let levels = band_energies(&avg_magnitudes, buffer.sample_rate, bands)
+        .into_iter()
+        .map(|energy| 10.0 * (energy.max(1e-12) / total_energy).log10())
+        .collect();
+
+    Ok(Some(levels))
+}
+
+/// Measure the average energy in each frequency band, in dB
+///
+/// Unlike `band_levels`, the levels follow the overall level, so two buffers
+/// at the same sample rate can be compared band by band. Returns `None` for
+/// buffers too short to analyze.
+pub fn absolute_band_levels(
+    buffer: &AudioBuffer,
+    bands: &[(f64, f64)],
+) -> Result<Option<Vec<f64>>> {
+    let avg_magnitudes = match average_spectrum(buffer, FFT_SIZE)? {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+
+    let levels = band_energies(&avg_magnitudes, buffer.sample_rate, bands)
+        .into_iter()
+        .map(|energy| 10.0 * energy.max(1e-12).log10())
+        .collect();
+
+    Ok(Some(levels))
+}
+
+/// Sum of the squared magnitudes falling in each band
+fn band_energies(avg_magnitudes: &[f64], sample_rate: u32, bands: &[(f64, f64)]) -> Vec<f64> {
+    let freq_resolution = sample_rate as f64 / FFT_SIZE as f64;
+    bands
+        .iter()
+        .map(|&(low, high)| {
+            avg_magnitudes
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| {
+                    let freq = *i as f64 * freq_resolution;
+                    freq >= low && freq < high
+                })
+                .map(|(_, m)| m * m)
+                .sum()
+        })
+        .collect()
+}
@@ -0,0 +1,34 @@
+//! Decoded audio, one plane of samples per channel
+
+/// Audio buffer for processing
+#[derive(Debug, Clone)]
+pub struct AudioBuffer {
+    pub samples: Vec<Vec<f32>>, // One plane of samples per channel
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+impl AudioBuffer {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            samples: vec![Vec::new(); channels],
+            sample_rate,
+            channels,
+        }
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        if self.samples.is_empty() || self.samples[0].is_empty() {
+            return 0.0;
+        }
+        self.samples[0].len() as f64 / self.sample_rate as f64
+    }
+
+    pub fn frame_count(&self) -> usize {
+        if self.samples.is_empty() {
+            0
+        } else {
+            self.samples[0].len()
+        }
+    }
+}
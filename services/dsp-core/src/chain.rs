@@ -0,0 +1,321 @@
+//! Mastering chains assembled stage by stage
+//!
+//! `MasteringChainBuilder` puts together the same EQ, compressors,
+//! saturation and true-peak limiter `apply_mastering` uses, in any order and
+//! with any settings, and `build` checks them once into a `MasteringChain`
+//! that can run over any number of buffers:
+//!
+//! ```no_run
+//! # use budi_dsp_core::chain::MasteringChain;
+//! # use budi_dsp_core::mastering::BandCompression;
+//! # use budi_dsp_core::types::LimiterMode;
+//! # let mut buffer = budi_dsp_core::AudioBuffer::new(2, 48000);
+//! let chain = MasteringChain::builder()
+//!     .add_compressor(BandCompression {
+//!         band: "wide",
+//!         threshold_db: -18.0,
+//!         ratio: 2.0,
+//!         attack_ms: 10.0,
+//!         release_ms: 100.0,
+//!     })
+//!     .add_limiter(LimiterMode::BrickWall, 100.0)
+//!     .set_target(-14.0)
+//!     .set_ceiling(-1.0)
+//!     .build()?;
+//! let outcome = chain.process(&mut buffer)?;
+//! # anyhow::Ok(())
+//! ```
+//!
+//! The profiles of `apply_mastering` are presets over this: each is a chain
+//! of the profile's EQ, multiband compression, optional saturation and
+//! limiter.
+
+use anyhow::Result;
+
+use crate::mastering::{self, BandCompression, EqSettings, LimiterOutcome};
+use crate::settings;
+use crate::types::{EqMode, LimiterMode, Precision};
+use crate::AudioBuffer;
+
+/// One stage of a mastering chain
+#[derive(Debug, Clone)]
+pub enum ChainStage {
+    /// Low shelf, mid peak and high shelf
+    Eq(EqSettings),
+    /// Wideband feed-forward compressor
+    Compressor(BandCompression),
+    /// Low, mid and high band compressors around the mastering crossovers
+    MultibandCompressor([BandCompression; 3]),
+    /// Tanh soft clipping
+    Saturation { drive: f32 },
+    /// Loudness-targeting limiter with the chain's true-peak ceiling
+    Limiter { mode: LimiterMode, release_ms: f32 },
+}
+
+/// Assembles a `MasteringChain`
+#[derive(Debug, Clone, Default)]
+pub struct MasteringChainBuilder {
+    stages: Vec<ChainStage>,
+    target_lufs: Option<f64>,
+    ceiling_db: Option<f64>,
+    eq_mode: EqMode,
+    precision: Precision,
+}
+
+impl MasteringChainBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_eq(mut self, eq: EqSettings) -> Self {
+        self.stages.push(ChainStage::Eq(eq));
+        self
+    }
+
+    pub fn add_compressor(mut self, settings: BandCompression) -> Self {
+        self.stages.push(ChainStage::Compressor(settings));
+        self
+    }
+
+    pub fn add_multiband_compressor(mut self, bands: [BandCompression; 3]) -> Self {
+        self.stages.push(ChainStage::MultibandCompressor(bands));
+        self
+    }
+
+    pub fn add_saturation(mut self, drive: f32) -> Self {
+        self.stages.push(ChainStage::Saturation { drive });
+        self
+    }
+
+    /// The final stage, bringing the chain to its target loudness
+    pub fn add_limiter(mut self, mode: LimiterMode, release_ms: f32) -> Self {
+        self.stages.push(ChainStage::Limiter { mode, release_ms });
+        self
+    }
+
+    /// Integrated loudness the limiter targets (LUFS)
+    pub fn set_target(mut self, target_lufs: f64) -> Self {
+        self.target_lufs = Some(target_lufs);
+        self
+    }
+
+    /// True-peak ceiling (dBTP), `qc.true_peak_max` unless set
+    pub fn set_ceiling(mut self, ceiling_db: f64) -> Self {
+        self.ceiling_db = Some(ceiling_db);
+        self
+    }
+
+    /// Filter implementation of the EQ stages
+    pub fn set_eq_mode(mut self, eq_mode: EqMode) -> Self {
+        self.eq_mode = eq_mode;
+        self
+    }
+
+    /// Arithmetic precision of the EQ and crossover filters
+    pub fn set_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Check the stages and settings
+    pub fn build(self) -> Result<MasteringChain> {
+        let ceiling_db = self
+            .ceiling_db
+            .unwrap_or_else(|| settings::get().true_peak_max);
+        anyhow::ensure!(
+            ceiling_db.is_finite() && ceiling_db <= 0.0,
+            "Ceiling must be at most 0 dBTP"
+        );
+        let limiters = self
+            .stages
+            .iter()
+            .filter(|s| matches!(s, ChainStage::Limiter { .. }))
+            .count();
+        anyhow::ensure!(limiters <= 1, "A chain takes one limiter");
+        if limiters == 1 {
+            anyhow::ensure!(
+                matches!(self.stages.last(), Some(ChainStage::Limiter { .. })),
+                "The limiter must be the last stage"
+            );
+            anyhow::ensure!(
+                self.target_lufs.is_some_and(f64::is_finite),
+                "A chain with a limiter needs a target"
+            );
+        }
+
+        for stage in &self.stages {
+            match stage {
+                ChainStage::Eq(eq) => anyhow::ensure!(
+                    [eq.low_shelf_hz, eq.mid_hz, eq.high_shelf_hz]
+                        .iter()
+                        .all(|hz| hz.is_finite() && *hz > 0.0)
+                        && eq.mid_q > 0.0,
+                    "EQ frequencies and Q must be above 0"
+                ),
+                ChainStage::Compressor(band) => check_compression(band)?,
+                ChainStage::MultibandCompressor(bands) => {
+                    bands.iter().try_for_each(check_compression)?
+                }
+                ChainStage::Saturation { drive } => anyhow::ensure!(
+                    drive.is_finite() && *drive >= 0.0,
+                    "Saturation drive must not be negative"
+                ),
+                ChainStage::Limiter { release_ms, .. } => {
+                    anyhow::ensure!(*release_ms > 0.0, "Limiter release must be above 0 ms")
+                }
+            }
+        }
+
+        Ok(MasteringChain {
+            stages: self.stages,
+            target_lufs: self.target_lufs,
+            ceiling_db,
+            eq_mode: self.eq_mode,
+            precision: self.precision,
+        })
+    }
+}
+
+fn check_compression(band: &BandCompression) -> Result<()> {
+    anyhow::ensure!(
+        band.ratio >= 1.0 && band.attack_ms > 0.0 && band.release_ms > 0.0,
+        "Compressor ({}): ratio must be at least 1, attack and release above 0 ms",
+        band.band
+    );
+    Ok(())
+}
+
+/// A checked mastering chain
+#[derive(Debug, Clone)]
+pub struct MasteringChain {
+    stages: Vec<ChainStage>,
+    target_lufs: Option<f64>,
+    ceiling_db: f64,
+    eq_mode: EqMode,
+    precision: Precision,
+}
+
+/// Loudness and peaks after a chain, and what its limiter did
+pub struct ChainOutcome {
+    pub final_lufs: f64,
+    pub final_true_peak: f64,
+    /// Whether the true peak is within the chain's ceiling
+    pub passes_ceiling: bool,
+    pub limiter: Option<LimiterOutcome>,
+}
+
+impl MasteringChain {
+    pub fn builder() -> MasteringChainBuilder {
+        MasteringChainBuilder::new()
+    }
+
+    pub fn stages(&self) -> &[ChainStage] {
+        &self.stages
+    }
+
+    pub fn target_lufs(&self) -> Option<f64> {
+        self.target_lufs
+    }
+
+    pub fn ceiling_db(&self) -> f64 {
+        self.ceiling_db
+    }
+
+    /// Run every stage over `buffer` in order
+    #[tracing::instrument(name = "dsp.chain", skip_all)]
+    pub fn process(&self, buffer: &mut AudioBuffer) -> Result<ChainOutcome> {
+        let sample_rate = buffer.sample_rate as f32;
+        let mut limiter = None;
+        for stage in &self.stages {
+            match stage {
+                ChainStage::Eq(eq) => {
+                    let sections = mastering::eq_sections(sample_rate, eq);
+                    mastering::apply_eq_sections(buffer, &sections, self.eq_mode, self.precision)?
+                }
+                ChainStage::Compressor(settings) => {
+                    for channel in &mut buffer.samples {
+                        mastering::Compressor::new(settings, sample_rate).process(channel);
+                    }
+                }
+                ChainStage::MultibandCompressor(bands) => {
+                    mastering::apply_multiband_compression(buffer, bands, self.precision)?
+                }
+                ChainStage::Saturation { drive } => mastering::apply_saturation(buffer, *drive)?,
+                ChainStage::Limiter { mode, release_ms } => {
+                    let target_lufs = self.target_lufs.expect("checked by build");
+                    limiter = Some(mastering::apply_limiter(
+                        buffer,
+                        target_lufs,
+                        *mode,
+                        *release_ms,
+                        self.precision,
+                        self.ceiling_db,
+                    )?);
+                }
+            }
+        }
+
+        let (final_lufs, final_true_peak) = match &limiter {
+            Some(limiter) => (limiter.final_lufs, limiter.final_true_peak),
+            None => (
+                mastering::calculate_loudness(buffer)?,
+                mastering::calculate_true_peak(buffer)?,
+            ),
+        };
+        Ok(ChainOutcome {
+            final_lufs,
+            final_true_peak,
+            passes_ceiling: final_true_peak <= self.ceiling_db,
+            limiter,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_checks_and_runs_chain() {
+        let limiter_first = MasteringChain::builder()
+            .add_limiter(LimiterMode::BrickWall, 100.0)
+            .add_saturation(0.2)
+            .set_target(-14.0)
+            .build();
+        assert!(limiter_first.is_err());
+        let no_target = MasteringChain::builder()
+            .add_limiter(LimiterMode::BrickWall, 100.0)
+            .build();
+        assert!(no_target.is_err());
+
+        let chain = MasteringChain::builder()
+            .add_compressor(BandCompression {
+                band: "wide",
+                threshold_db: -18.0,
+                ratio: 2.0,
+                attack_ms: 10.0,
+                release_ms: 100.0,
+            })
+            .add_saturation(0.2)
+            .add_limiter(LimiterMode::BrickWall, 100.0)
+            .set_target(-12.0)
+            .set_ceiling(-1.5)
+            .build()
+            .unwrap();
+        let mut buffer = AudioBuffer::new(2, 48000);
+        let tone: Vec<f32> = (0..48000 * 3)
+            .map(|i| 0.1 * (i as f32 * 440.0 * std::f32::consts::TAU / 48000.0).sin())
+            .collect();
+        buffer.samples = vec![tone.clone(), tone];
+
+        let outcome = chain.process(&mut buffer).unwrap();
+        assert!(outcome.passes_ceiling);
+        assert!(outcome.final_true_peak <= -1.5);
+        assert!(
+            (outcome.final_lufs + 12.0).abs() <= settings::get().loudness_tolerance,
+            "{} LUFS",
+            outcome.final_lufs
+        );
+        assert_eq!(outcome.limiter.unwrap().final_lufs, outcome.final_lufs);
+    }
+}
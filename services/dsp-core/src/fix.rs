@@ -1,24 +1,54 @@
 //! Audio repair and fix operations
 
-use crate::types::{AudioBuffer, FixChange};
+use crate::settings;
+use crate::simd;
+use crate::types::{FixChange, FixModule};
+use crate::AudioBuffer;
 use anyhow::Result;
 
+/// Defaults of the module settings a job leaves out
+const DEFAULT_NORMALIZE_DB: f64 = -1.0;
+const DEFAULT_DE_ESS_HZ: f32 = 4000.0;
+const DEFAULT_DE_ESS_THRESHOLD: f32 = 0.3;
+const DEFAULT_DE_ESS_RATIO: f32 = 0.5;
+const DEFAULT_NOISE_FLOOR_DB: f64 = -60.0;
+const DEFAULT_SILENCE_KEEP_MS: f64 = 100.0;
+
 /// Apply a list of fix modules to an audio buffer
-pub fn apply_fixes(buffer: &mut AudioBuffer, modules: &[String]) -> Result<Vec<FixChange>> {
+#[tracing::instrument(name = "dsp.fix", skip_all)]
+pub fn apply_fixes(buffer: &mut AudioBuffer, modules: &[FixModule]) -> Result<Vec<FixChange>> {
+    for module in modules {
+        module.validate()?;
+    }
     let mut changes = Vec::new();
 
     for module in modules {
-        let change = match module.as_str() {
-            "normalize" => apply_normalize(buffer)?,
-            "clip_repair" => apply_clip_repair(buffer)?,
-            "de_ess" => apply_de_ess(buffer)?,
-            "noise_reduction" => apply_noise_reduction(buffer)?,
-            "dc_offset" => apply_dc_offset_removal(buffer)?,
-            "silence_trim" => apply_silence_trim(buffer)?,
-            _ => {
-                tracing::warn!("Unknown fix module: {}", module);
-                continue;
+        let change = match *module {
+            FixModule::Normalize { target_db } => {
+                apply_normalize(buffer, target_db.unwrap_or(DEFAULT_NORMALIZE_DB))?
+            }
+            FixModule::ClipRepair { threshold } => {
+                apply_clip_repair(buffer, threshold.unwrap_or(settings::get().clip_threshold))?
             }
+            FixModule::DeEss {
+                frequency_hz,
+                threshold,
+                ratio,
+            } => apply_de_ess(
+                buffer,
+                frequency_hz.unwrap_or(DEFAULT_DE_ESS_HZ),
+                threshold.unwrap_or(DEFAULT_DE_ESS_THRESHOLD),
+                ratio.unwrap_or(DEFAULT_DE_ESS_RATIO),
+            )?,
+            FixModule::NoiseReduction { floor_db } => {
+                apply_noise_reduction(buffer, floor_db.unwrap_or(DEFAULT_NOISE_FLOOR_DB))?
+            }
+            FixModule::DcOffset => apply_dc_offset_removal(buffer)?,
+            FixModule::SilenceTrim { threshold, keep_ms } => apply_silence_trim(
+                buffer,
+                threshold.unwrap_or(settings::get().silence_threshold),
+                keep_ms.unwrap_or(DEFAULT_SILENCE_KEEP_MS),
+            )?,
         };
 
         if let Some(change) = change {
@@ -29,21 +59,15 @@ pub fn apply_fixes(buffer: &mut AudioBuffer, modules: &[String]) -> Result<Vec<F
     Ok(changes)
 }
 
-/// Normalize audio to -1dB peak
-fn apply_normalize(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
-    let target_db = -1.0;
-    let target_linear = 10.0_f32.powf(target_db / 20.0);
+/// Normalize audio to a `target_db` peak
+fn apply_normalize(buffer: &mut AudioBuffer, target_db: f64) -> Result<Option<FixChange>> {
+    let target_linear = 10.0_f32.powf(target_db as f32 / 20.0);
 
     // Find current peak
-    let mut max_sample: f32 = 0.0;
-    for channel in &buffer.samples {
-        for &sample in channel {
-            let abs = sample.abs();
-            if abs > max_sample {
-                max_sample = abs;
-            }
-        }
-    }
+    let max_sample = buffer
+        .samples
+        .iter()
+        .fold(0.0_f32, |peak, channel| peak.max(simd::peak_abs(channel)));
 
     if max_sample < 0.0001 {
         return Ok(None); // Too quiet to normalize
@@ -57,21 +81,21 @@ fn apply_normalize(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
 
     // Apply gain
     for channel in &mut buffer.samples {
-        for sample in channel {
-            *sample *= gain;
-        }
+        simd::scale(channel, gain);
     }
 
     let gain_db = 20.0 * gain.log10();
     Ok(Some(FixChange {
         module: "normalize".to_string(),
-        description: format!("Applied {:.1}dB gain to normalize to -1dB peak", gain_db),
+        description: format!(
+            "Applied {:.1}dB gain to normalize to {}dB peak",
+            gain_db, target_db
+        ),
     }))
 }
 
-/// Repair clipped samples using interpolation
-fn apply_clip_repair(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
-    let clip_threshold = 0.99;
+/// Repair samples at or above `clip_threshold` using interpolation
+fn apply_clip_repair(buffer: &mut AudioBuffer, clip_threshold: f32) -> Result<Option<FixChange>> {
     let mut repaired_count = 0;
 
     for channel in &mut buffer.samples {
@@ -125,15 +149,15 @@ fn apply_clip_repair(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
 }
 
 /// Basic de-essing using dynamic EQ on sibilant frequencies
-fn apply_de_ess(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
-    // De-essing targets frequencies between 4kHz and 10kHz
+fn apply_de_ess(
+    buffer: &mut AudioBuffer,
+    sibilant_low: f32,
+    threshold: f32,
+    ratio: f32,
+) -> Result<Option<FixChange>> {
+    // De-essing targets frequencies from `sibilant_low` (4kHz by default)
     // This is a simplified implementation using a dynamic attenuator
 
-    let sibilant_low = 4000.0;
-    let _sibilant_high = 10000.0; // Reserved for future multi-band de-esser
-    let threshold = 0.3; // Threshold for detection
-    let ratio = 0.5; // Reduction ratio
-
     let sample_rate = buffer.sample_rate as f32;
 
     // Simple high-pass filter coefficients for sibilance detection
@@ -200,10 +224,12 @@ fn apply_de_ess(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
 }
 
 /// Basic noise reduction using spectral gating
-fn apply_noise_reduction(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
+fn apply_noise_reduction(
+    buffer: &mut AudioBuffer,
+    noise_floor_db: f64,
+) -> Result<Option<FixChange>> {
     // Simple noise gate implementation
-    let noise_floor_db = -60.0;
-    let noise_floor = 10.0_f32.powf(noise_floor_db / 20.0);
+    let noise_floor = 10.0_f32.powf(noise_floor_db as f32 / 20.0);
     let gate_threshold = noise_floor * 2.0;
 
     let sample_rate = buffer.sample_rate as f32;
@@ -295,11 +321,13 @@ fn apply_dc_offset_removal(buffer: &mut AudioBuffer) -> Result<Option<FixChange>
     }
 }
 
-/// Trim silence from start and end
-fn apply_silence_trim(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
-    let silence_threshold = 0.001; // -60dB
-    let min_silence_ms = 100; // Minimum silence to keep
-    let min_silence_samples = (min_silence_ms as f32 * buffer.sample_rate as f32 / 1000.0) as usize;
+/// Trim silence from start and end, keeping `min_silence_ms` of it
+fn apply_silence_trim(
+    buffer: &mut AudioBuffer,
+    silence_threshold: f32,
+    min_silence_ms: f64,
+) -> Result<Option<FixChange>> {
+    let min_silence_samples = (min_silence_ms * buffer.sample_rate as f64 / 1000.0) as usize;
 
     let frame_count = buffer.frame_count();
     if frame_count == 0 {
@@ -359,3 +387,35 @@ fn apply_silence_trim(buffer: &mut AudioBuffer) -> Result<Option<FixChange>> {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modules_by_name_or_with_settings() {
+        let modules: Vec<FixModule> =
+            serde_json::from_str(r#"["dc_offset", {"module": "normalize", "targetDb": -6}]"#)
+                .unwrap();
+        assert_eq!(modules[0], FixModule::DcOffset);
+        assert_eq!(
+            modules[1],
+            FixModule::Normalize {
+                target_db: Some(-6.0)
+            }
+        );
+
+        let mut buffer = AudioBuffer::new(1, 48000);
+        buffer.samples[0] = (0..4800).map(|i| 0.1 * (i as f32 * 0.05).sin()).collect();
+        let changes = apply_fixes(&mut buffer, &modules[1..]).unwrap();
+        assert_eq!(changes[0].module, "normalize");
+        let peak = simd::peak_abs(&buffer.samples[0]);
+        assert!((20.0 * peak.log10() + 6.0).abs() < 0.01);
+
+        let unknown = serde_json::from_str::<FixModule>(r#""reverse""#).unwrap_err();
+        assert!(unknown.to_string().contains("unknown variant `reverse`"));
+        let out_of_range =
+            serde_json::from_str::<FixModule>(r#"{"module": "de_ess", "ratio": 2}"#).unwrap_err();
+        assert!(out_of_range.to_string().contains("ratio"));
+    }
+}
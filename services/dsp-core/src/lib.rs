@@ -0,0 +1,33 @@
+//! Budi DSP Core - Audio analysis, fixing and mastering
+//!
+//! Everything here works on an in-memory `AudioBuffer` and knows nothing of
+//! queues, storage or webhooks, so services and tests can measure and
+//! process audio without a worker around it:
+//!
+//! - Analysis: loudness (BS.1770), sample and true peak, spectrum and
+//!   stereo metrics, whole or a block at a time (`analysis::Analyzer`)
+//! - Fixes: normalize, clip repair, de-ess, noise reduction, DC offset
+//!   removal and silence trim
+//! - Mastering: EQ (IIR or linear-phase FIR), multiband compression,
+//!   saturation and true-peak limiting to a loudness target, with dry-run
+//!   planning and reference matching; the same stages can be put together
+//!   into chains of one's own (`chain::MasteringChainBuilder`)
+//! - True-peak metering, and the biquad, crossover and SIMD kernels the
+//!   above are built on
+//!
+//! Thresholds and QC gates default to the DSP worker's defaults; see
+//! `settings`.
+
+pub mod analysis;
+mod buffer;
+pub mod chain;
+pub mod fir;
+pub mod fix;
+pub mod mastering;
+pub mod settings;
+pub mod simd;
+pub mod true_peak;
+pub mod types;
+
+pub use buffer::AudioBuffer;
+pub use settings::Settings;
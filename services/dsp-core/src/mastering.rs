@@ -0,0 +1,1732 @@
+//! Audio mastering chain: EQ, compression, limiting
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::analysis;
+use crate::chain::MasteringChain;
+use crate::fir::{self, BiquadCoefs};
+use crate::settings;
+use crate::simd;
+use crate::true_peak;
+use crate::types::{Dither, EqMode, LimiterMode, LoudnessTarget, MasterProfile, Precision};
+use crate::AudioBuffer;
+
+/// Frequency bands (Hz) compared when matching a reference track, aligned
+/// with the low shelf, mid peak and high shelf of the mastering EQ
+const REFERENCE_BANDS: [(f64, f64); 3] = [(20.0, 150.0), (1000.0, 4000.0), (8000.0, 20000.0)];
+
+/// Maximum EQ correction applied per band when matching a reference (dB)
+const REFERENCE_MAX_CORRECTION_DB: f64 = 6.0;
+
+/// Optional per-job settings for the mastering chain
+#[derive(Debug, Clone)]
+pub struct MasteringOptions {
+    /// Settings derived from a reference track, overriding the loudness target
+    pub reference: Option<ReferenceMatch>,
+    /// Filter implementation used for the EQ stage
+    pub eq_mode: EqMode,
+    /// Arithmetic precision of the EQ and crossover filters
+    pub precision: Precision,
+    /// Final limiter override; defaults to the profile's choice
+    pub limiter_mode: Option<LimiterMode>,
+    /// Minimum loudness range (LU) to preserve, trading away loudness if needed
+    pub min_loudness_range: Option<f64>,
+    /// Dither applied when rendering the 16-bit deliverable
+    pub dither: Dither,
+    /// Blend of processed (1.0) and unprocessed (0.0) signal
+    pub mix: f32,
+    /// Gain applied after the whole chain (dB, at most 0)
+    pub output_trim_db: f64,
+}
+
+impl MasteringOptions {
+    /// Check the blend and trim
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.mix),
+            "mix must be between 0 and 1"
+        );
+        // A positive trim would push the master over the ceiling
+        anyhow::ensure!(
+            self.output_trim_db.is_finite() && self.output_trim_db <= 0.0,
+            "outputTrimDb must be at most 0 dB"
+        );
+        Ok(())
+    }
+}
+
+impl Default for MasteringOptions {
+    fn default() -> Self {
+        Self {
+            reference: None,
+            eq_mode: EqMode::default(),
+            precision: Precision::default(),
+            limiter_mode: None,
+            min_loudness_range: None,
+            dither: Dither::default(),
+            mix: 1.0,
+            output_trim_db: 0.0,
+        }
+    }
+}
+
+/// EQ and loudness settings derived from a reference track
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceMatch {
+    pub reference_lufs: f64,
+    pub target_lufs: f64,
+    pub low_gain_db: f64,
+    pub mid_gain_db: f64,
+    pub high_gain_db: f64,
+}
+
+/// Derive matching EQ corrections and a loudness target from a reference track
+///
+/// The tonal balance of both tracks is compared in the low, mid and high
+/// bands; each band is corrected by the difference, clamped to a safe range.
+/// The reference's integrated loudness becomes the target, limited to what
+/// the true-peak ceiling can reasonably deliver.
+#[tracing::instrument(name = "dsp.match_reference", skip_all)]
+pub fn match_reference(source: &AudioBuffer, reference: &AudioBuffer) -> Result<ReferenceMatch> {
+    let reference_lufs = calculate_loudness(reference)?;
+    let target_lufs = reference_lufs.clamp(-20.0, -7.0);
+
+    let (low_gain_db, mid_gain_db, high_gain_db) =
+        match (tonal_balance(source)?, tonal_balance(reference)?) {
+            (Some(src), Some(reference)) => {
+                let correction = |band: usize| {
+                    (reference[band] - src[band])
+                        .clamp(-REFERENCE_MAX_CORRECTION_DB, REFERENCE_MAX_CORRECTION_DB)
+                };
+                (correction(0), correction(1), correction(2))
+            }
+            // Too short to compare spectra - match loudness only
+            _ => (0.0, 0.0, 0.0),
+        };
+
+    Ok(ReferenceMatch {
+        reference_lufs,
+        target_lufs,
+        low_gain_db,
+        mid_gain_db,
+        high_gain_db,
+    })
+}
+
+/// Compression intensity and loudness target offset (LU) tried in turn when
+/// preserving a minimum loudness range, from full processing to none
+const DYNAMICS_STEPS: [(f32, f64); 4] = [(1.0, 0.0), (0.6, -2.0), (0.3, -4.0), (0.0, -6.0)];
+
+/// Measure low/mid/high band levels relative to total energy (dB)
+///
+/// Uses the same bands as reference matching, so results from different
+/// tracks can be compared to derive EQ corrections.
+pub fn tonal_balance(buffer: &AudioBuffer) -> Result<Option<Vec<f64>>> {
+    analysis::band_levels(buffer, &REFERENCE_BANDS)
+}
+
+/// Apply the complete mastering chain to an audio buffer
+#[tracing::instrument(name = "dsp.master", skip_all)]
+pub fn apply_mastering(
+    buffer: &mut AudioBuffer,
+    profile: MasterProfile,
+    target: LoudnessTarget,
+    options: &MasteringOptions,
+) -> Result<MasteringResult> {
+    options.validate()?;
+    let mix = options.mix;
+    let dry = if mix < 1.0 {
+        Some(Dry {
+            lufs: calculate_loudness(buffer)?,
+            samples: buffer.samples.clone(),
+        })
+    } else {
+        None
+    };
+
+    let mut result = render_with_dynamics(buffer, profile, target, options)?;
+
+    if dry.is_some() || options.output_trim_db != 0.0 {
+        apply_output_stage(
+            buffer,
+            dry.as_ref(),
+            mix,
+            &result,
+            options.precision,
+            options.output_trim_db,
+        )?;
+        result.remeasure(buffer, options.output_trim_db)?;
+    }
+
+    Ok(result)
+}
+
+/// The unprocessed input, kept for a partial `mix`
+struct Dry {
+    samples: Vec<Vec<f32>>,
+    lufs: f64,
+}
+
+/// Blend the processed signal with the dry input and apply the output trim
+///
+/// The dry signal is brought to the processed loudness first, so the mix
+/// dials back the processing rather than the level. It isn't peak-controlled,
+/// so the blend passes through the brick-wall limiter again, landing it back
+/// on the chain's target, before the trim, which only ever lowers it.
+fn apply_output_stage(
+    buffer: &mut AudioBuffer,
+    dry: Option<&Dry>,
+    mix: f32,
+    chain: &MasteringResult,
+    precision: Precision,
+    output_trim_db: f64,
+) -> Result<()> {
+    if let Some(dry) = dry {
+        // Silence has no loudness to match
+        let match_gain = if dry.lufs.is_finite() {
+            10.0_f64.powf((chain.final_lufs - dry.lufs) / 20.0) as f32
+        } else {
+            1.0
+        };
+        let dry_gain = match_gain * (1.0 - mix);
+        for (wet_channel, dry_channel) in buffer.samples.iter_mut().zip(&dry.samples) {
+            for (wet, &dry) in wet_channel.iter_mut().zip(dry_channel) {
+                *wet = dry * dry_gain + *wet * mix;
+            }
+        }
+        apply_limiter(
+            buffer,
+            chain.target_lufs,
+            LimiterMode::BrickWall,
+            100.0,
+            precision,
+            settings::get().true_peak_max,
+        )?;
+    }
+
+    if output_trim_db != 0.0 {
+        let trim = 10.0_f64.powf(output_trim_db / 20.0) as f32;
+        for channel in &mut buffer.samples {
+            for sample in channel.iter_mut() {
+                *sample *= trim;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the chain, backing off processing when a minimum loudness range is set
+fn render_with_dynamics(
+    buffer: &mut AudioBuffer,
+    profile: MasterProfile,
+    target: LoudnessTarget,
+    options: &MasteringOptions,
+) -> Result<MasteringResult> {
+    let requested_lufs = options
+        .reference
+        .map(|r| r.target_lufs)
+        .unwrap_or_else(|| target.lufs_value());
+
+    let min_lra = match options.min_loudness_range {
+        Some(min_lra) => min_lra,
+        None => return render_chain(buffer, profile, requested_lufs, 1.0, options),
+    };
+
+    // Dynamics preservation: back off compression and loudness step by step
+    // until the master keeps the requested loudness range
+    let source = buffer.samples.clone();
+    let source_lra = calculate_loudness_range(buffer)?;
+
+    for (attempt, &(intensity, offset)) in DYNAMICS_STEPS.iter().enumerate() {
+        buffer.samples.clone_from(&source);
+        let mut result =
+            render_chain(buffer, profile, requested_lufs + offset, intensity, options)?;
+        let final_lra = calculate_loudness_range(buffer)?;
+
+        let satisfied = final_lra >= min_lra;
+        if satisfied || attempt + 1 == DYNAMICS_STEPS.len() {
+            result.dynamics = Some(DynamicsTradeoff {
+                min_loudness_range: min_lra,
+                source_loudness_range: source_lra,
+                final_loudness_range: final_lra,
+                requested_lufs,
+                loudness_sacrificed: requested_lufs - result.final_lufs,
+                compression_intensity: intensity,
+                attempts: attempt + 1,
+                satisfied,
+            });
+            return Ok(result);
+        }
+    }
+
+    unreachable!("DYNAMICS_STEPS is not empty")
+}
+
+/// The chain of a profile: its EQ (plus reference corrections), multiband
+/// compression at `compression_intensity`, optional saturation and limiter,
+/// at the QC true-peak ceiling
+pub fn profile_chain(
+    profile: MasterProfile,
+    target_lufs: f64,
+    compression_intensity: f32,
+    options: &MasteringOptions,
+) -> Result<MasteringChain> {
+    let mut builder = MasteringChain::builder()
+        .set_eq_mode(options.eq_mode)
+        .set_precision(options.precision)
+        .add_eq(eq_settings(profile, options.reference.as_ref()))
+        .add_multiband_compressor(compression_settings(profile, compression_intensity));
+    if let Some(drive) = saturation_drive(profile) {
+        builder = builder.add_saturation(drive);
+    }
+    let limiter_mode = options
+        .limiter_mode
+        .unwrap_or_else(|| profile.limiter_mode());
+    builder
+        .add_limiter(limiter_mode, profile.limiter_release_ms())
+        .set_target(target_lufs)
+        .build()
+}
+
+/// Render the mastering chain once at the given target and compression intensity
+fn render_chain(
+    buffer: &mut AudioBuffer,
+    profile: MasterProfile,
+    target_lufs: f64,
+    compression_intensity: f32,
+    options: &MasteringOptions,
+) -> Result<MasteringResult> {
+    let chain = profile_chain(profile, target_lufs, compression_intensity, options)?;
+    let outcome = chain.process(buffer)?;
+    let limiter = outcome.limiter.expect("profile chains end in a limiter");
+    let limiter_mode = options
+        .limiter_mode
+        .unwrap_or_else(|| profile.limiter_mode());
+
+    let passes_loudness = within_loudness_tolerance(outcome.final_lufs, target_lufs);
+    Ok(MasteringResult {
+        final_lufs: outcome.final_lufs,
+        final_true_peak: outcome.final_true_peak,
+        passes_qc: outcome.passes_ceiling && passes_loudness,
+        passes_loudness,
+        target_lufs,
+        loudness_iterations: limiter.iterations,
+        limiter_mode,
+        band_reduction: limiter.band_reduction,
+        reference: options.reference,
+        dynamics: None,
+    })
+}
+
+/// What `apply_mastering` would do to a track, worked out without rendering
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MasteringPlan {
+    pub source_lufs: f64,
+    pub source_true_peak: f64,
+    pub target_lufs: f64,
+    /// Loudness change from the source to the master (dB)
+    pub gain_db: f64,
+    /// Integrated loudness the master should end up at: the target, which
+    /// the limiter lands within `qc.loudness_tolerance` of, plus the output
+    /// trim. A partial `mix` is brought back to the target before the trim.
+    pub predicted_lufs: f64,
+    pub true_peak_ceiling: f64,
+    pub eq_mode: EqMode,
+    pub precision: Precision,
+    pub eq: EqSettings,
+    pub compression: [BandCompression; 3],
+    /// Saturation drive, for the profiles that saturate
+    pub saturation_drive: Option<f32>,
+    pub limiter_mode: LimiterMode,
+    pub limiter_release_ms: f32,
+    pub reference: Option<ReferenceMatch>,
+    /// With a minimum loudness range, compression and then loudness (by up
+    /// to 6 LU) are backed off until the master keeps it
+    pub min_loudness_range: Option<f64>,
+    pub source_loudness_range: Option<f64>,
+    pub mix: f32,
+    pub output_trim_db: f64,
+    pub dither: Dither,
+}
+
+/// Work out the settings `apply_mastering` would use on `source`
+#[tracing::instrument(name = "dsp.plan_master", skip_all)]
+pub fn plan_mastering(
+    source: &AudioBuffer,
+    profile: MasterProfile,
+    target: LoudnessTarget,
+    options: &MasteringOptions,
+) -> Result<MasteringPlan> {
+    options.validate()?;
+    let source_lufs = calculate_loudness(source)?;
+    let target_lufs = options
+        .reference
+        .map(|r| r.target_lufs)
+        .unwrap_or_else(|| target.lufs_value());
+    let source_loudness_range = match options.min_loudness_range {
+        Some(_) => Some(calculate_loudness_range(source)?),
+        None => None,
+    };
+
+    Ok(MasteringPlan {
+        source_lufs,
+        source_true_peak: calculate_true_peak(source)?,
+        target_lufs,
+        gain_db: target_lufs - source_lufs,
+        predicted_lufs: target_lufs + options.output_trim_db,
+        true_peak_ceiling: settings::get().true_peak_max,
+        eq_mode: options.eq_mode,
+        precision: options.precision,
+        eq: eq_settings(profile, options.reference.as_ref()),
+        compression: compression_settings(profile, 1.0),
+        saturation_drive: saturation_drive(profile),
+        limiter_mode: options
+            .limiter_mode
+            .unwrap_or_else(|| profile.limiter_mode()),
+        limiter_release_ms: profile.limiter_release_ms(),
+        reference: options.reference,
+        min_loudness_range: options.min_loudness_range,
+        source_loudness_range,
+        mix: options.mix,
+        output_trim_db: options.output_trim_db,
+        dither: options.dither,
+    })
+}
+
+/// How loudness was traded for dynamics in preservation mode
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DynamicsTradeoff {
+    pub min_loudness_range: f64,
+    pub source_loudness_range: f64,
+    pub final_loudness_range: f64,
+    pub requested_lufs: f64,
+    /// Integrated loudness given up relative to the requested target (LU)
+    pub loudness_sacrificed: f64,
+    /// Fraction of the profile's compression that was applied (0-1)
+    pub compression_intensity: f32,
+    pub attempts: usize,
+    /// Whether the final loudness range meets the requested minimum
+    pub satisfied: bool,
+}
+
+pub struct MasteringResult {
+    pub final_lufs: f64,
+    pub final_true_peak: f64,
+    /// Whether the true peak is within the ceiling and the loudness passes
+    pub passes_qc: bool,
+    /// Whether the final loudness is within `qc.loudness_tolerance` of the
+    /// target plus any output trim
+    pub passes_loudness: bool,
+    pub target_lufs: f64,
+    /// Number of measure/adjust passes the loudness targeting needed
+    pub loudness_iterations: usize,
+    pub limiter_mode: LimiterMode,
+    /// Per-band gain reduction when the multiband limiter was used
+    pub band_reduction: Option<Vec<BandReduction>>,
+    pub reference: Option<ReferenceMatch>,
+    /// Loudness/dynamics trade-off when a minimum loudness range was requested
+    pub dynamics: Option<DynamicsTradeoff>,
+}
+
+impl MasteringResult {
+    /// Measure the master again after processing past the limiter, with
+    /// `output_trim_db` of intended gain since
+    pub fn remeasure(&mut self, buffer: &AudioBuffer, output_trim_db: f64) -> Result<()> {
+        self.final_lufs = calculate_loudness(buffer)?;
+        self.final_true_peak = calculate_true_peak(buffer)?;
+        self.passes_loudness =
+            within_loudness_tolerance(self.final_lufs, self.target_lufs + output_trim_db);
+        self.passes_qc =
+            self.final_true_peak <= settings::get().true_peak_max && self.passes_loudness;
+        Ok(())
+    }
+}
+
+/// Whether `lufs` lands within `qc.loudness_tolerance` of `target_lufs`
+fn within_loudness_tolerance(lufs: f64, target_lufs: f64) -> bool {
+    (lufs - target_lufs).abs() <= settings::get().loudness_tolerance
+}
+
+/// Run biquad `sections` over every channel as IIR filters, or as the
+/// linear-phase FIR with the same magnitude response
+#[tracing::instrument(name = "dsp.eq", skip_all)]
+pub fn apply_eq_sections(
+    buffer: &mut AudioBuffer,
+    sections: &[BiquadCoefs],
+    mode: EqMode,
+    precision: Precision,
+) -> Result<()> {
+    if sections.is_empty() {
+        return Ok(());
+    }
+
+    match mode {
+        EqMode::Iir if precision == Precision::F64 => {
+            for channel in &mut buffer.samples {
+                for &coefs in sections {
+                    Biquad::new(coefs, precision).process(channel);
+                }
+            }
+        }
+        EqMode::Iir => {
+            // Apply biquad filters for each band
+            simd::biquad_cascade(&mut buffer.samples, sections);
+        }
+        EqMode::LinearPhase => {
+            // FIR with the same magnitude response, long enough to resolve
+            // the low shelf at high sample rates
+            let design_size = if buffer.sample_rate > 48000 {
+                16384
+            } else {
+                8192
+            };
+            let taps = fir::design_linear_phase(sections, design_size)?;
+            for channel in &mut buffer.samples {
+                fir::convolve_linear_phase(channel, &taps)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Bands of the mastering EQ: a low shelf, a mid peak and a high shelf
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EqSettings {
+    pub low_shelf_hz: f32,
+    pub low_gain_db: f32,
+    pub mid_hz: f32,
+    pub mid_gain_db: f32,
+    pub mid_q: f32,
+    pub high_shelf_hz: f32,
+    pub high_gain_db: f32,
+}
+
+/// EQ bands for a profile, plus any reference corrections
+fn eq_settings(profile: MasterProfile, reference: Option<&ReferenceMatch>) -> EqSettings {
+    // Define EQ parameters based on profile
+    let (mut low_gain, mut mid_gain, mut high_gain, low_freq, high_freq) = match profile {
+        MasterProfile::Balanced => (0.0, 0.0, 0.5, 80.0, 12000.0),
+        MasterProfile::Warm => (1.5, -0.5, -1.0, 100.0, 8000.0),
+        MasterProfile::Punchy => (2.0, 1.0, 1.5, 60.0, 10000.0),
+        MasterProfile::Custom => (0.0, 0.0, 0.0, 80.0, 12000.0),
+        MasterProfile::HipHop => (2.5, -0.5, 1.0, 60.0, 10000.0),
+        MasterProfile::Edm => (2.0, 0.5, 2.0, 50.0, 11000.0),
+        MasterProfile::Acoustic => (0.5, 0.5, 1.0, 100.0, 10000.0),
+        MasterProfile::Classical => (0.0, 0.0, 0.5, 60.0, 14000.0),
+        MasterProfile::Podcast => (-3.0, 1.5, 1.0, 100.0, 8000.0),
+        MasterProfile::Jazz => (1.0, -0.5, 0.5, 80.0, 12000.0),
+    };
+
+    if let Some(reference) = reference {
+        low_gain += reference.low_gain_db as f32;
+        mid_gain += reference.mid_gain_db as f32;
+        high_gain += reference.high_gain_db as f32;
+    }
+
+    EqSettings {
+        low_shelf_hz: low_freq,
+        low_gain_db: low_gain,
+        // Peaking filter around 1kHz-3kHz
+        mid_hz: 2000.0,
+        mid_gain_db: mid_gain,
+        mid_q: 1.0,
+        high_shelf_hz: high_freq,
+        high_gain_db: high_gain,
+    }
+}
+
+/// Build the biquad sections of the mastering EQ, leaving out flat bands
+pub(crate) fn eq_sections(sample_rate: f32, eq: &EqSettings) -> Vec<BiquadCoefs> {
+    let mut sections = Vec::new();
+
+    // Low shelf filter
+    if eq.low_gain_db.abs() > 0.01 {
+        sections.push(low_shelf_coefs(
+            sample_rate,
+            eq.low_shelf_hz,
+            eq.low_gain_db,
+        ));
+    }
+
+    // Mid band
+    if eq.mid_gain_db.abs() > 0.01 {
+        sections.push(peaking_eq_coefs(
+            sample_rate,
+            eq.mid_hz,
+            eq.mid_gain_db,
+            eq.mid_q,
+        ));
+    }
+
+    // High shelf filter
+    if eq.high_gain_db.abs() > 0.01 {
+        sections.push(high_shelf_coefs(
+            sample_rate,
+            eq.high_shelf_hz,
+            eq.high_gain_db,
+        ));
+    }
+
+    sections
+}
+
+/// Low shelf filter implementation
+pub fn low_shelf_coefs(sample_rate: f32, freq: f32, gain_db: f32) -> BiquadCoefs {
+    let a = 10.0_f32.powf(gain_db / 40.0);
+    let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+    let cos_w0 = w0.cos();
+    let sin_w0 = w0.sin();
+    let alpha = sin_w0 / 2.0 * ((a + 1.0 / a) * (1.0 / 0.707 - 1.0) + 2.0).sqrt();
+
+    let b0 = a * ((a + 1.0) - (a - 1.0) * cos_w0 + 2.0 * a.sqrt() * alpha);
+    let b1 = 2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0);
+    let b2 = a * ((a + 1.0) - (a - 1.0) * cos_w0 - 2.0 * a.sqrt() * alpha);
+    let a0 = (a + 1.0) + (a - 1.0) * cos_w0 + 2.0 * a.sqrt() * alpha;
+    let a1 = -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0);
+    let a2 = (a + 1.0) + (a - 1.0) * cos_w0 - 2.0 * a.sqrt() * alpha;
+
+    [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+}
+
+/// High shelf filter implementation
+pub fn high_shelf_coefs(sample_rate: f32, freq: f32, gain_db: f32) -> BiquadCoefs {
+    let a = 10.0_f32.powf(gain_db / 40.0);
+    let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+    let cos_w0 = w0.cos();
+    let sin_w0 = w0.sin();
+    let alpha = sin_w0 / 2.0 * ((a + 1.0 / a) * (1.0 / 0.707 - 1.0) + 2.0).sqrt();
+
+    let b0 = a * ((a + 1.0) + (a - 1.0) * cos_w0 + 2.0 * a.sqrt() * alpha);
+    let b1 = -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0);
+    let b2 = a * ((a + 1.0) + (a - 1.0) * cos_w0 - 2.0 * a.sqrt() * alpha);
+    let a0 = (a + 1.0) - (a - 1.0) * cos_w0 + 2.0 * a.sqrt() * alpha;
+    let a1 = 2.0 * ((a - 1.0) - (a + 1.0) * cos_w0);
+    let a2 = (a + 1.0) - (a - 1.0) * cos_w0 - 2.0 * a.sqrt() * alpha;
+
+    [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+}
+
+/// Peaking EQ filter implementation
+pub fn peaking_eq_coefs(sample_rate: f32, freq: f32, gain_db: f32, q: f32) -> BiquadCoefs {
+    let a = 10.0_f32.powf(gain_db / 40.0);
+    let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+    let cos_w0 = w0.cos();
+    let sin_w0 = w0.sin();
+    let alpha = sin_w0 / (2.0 * q);
+
+    let b0 = 1.0 + alpha * a;
+    let b1 = -2.0 * cos_w0;
+    let b2 = 1.0 - alpha * a;
+    let a0 = 1.0 + alpha / a;
+    let a1 = -2.0 * cos_w0;
+    let a2 = 1.0 - alpha / a;
+
+    [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+}
+
+/// Biquad filter whose state carries over from one block to the next
+#[derive(Clone, Copy)]
+pub struct Biquad {
+    coefs: BiquadCoefs,
+    state: BiquadState,
+}
+
+/// Previous inputs and outputs `[x1, x2, y1, y2]` at the filter's precision
+#[derive(Clone, Copy)]
+enum BiquadState {
+    F32([f32; 4]),
+    F64([f64; 4]),
+}
+
+impl Biquad {
+    pub fn new(coefs: BiquadCoefs, precision: Precision) -> Self {
+        let state = match precision {
+            Precision::F32 => BiquadState::F32([0.0; 4]),
+            Precision::F64 => BiquadState::F64([0.0; 4]),
+        };
+        Self { coefs, state }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let [b0, b1, b2, a1, a2] = self.coefs;
+        match &mut self.state {
+            BiquadState::F32([x1, x2, y1, y2]) => {
+                for sample in samples.iter_mut() {
+                    let x0 = *sample;
+                    let y0 = b0 * x0 + b1 * *x1 + b2 * *x2 - a1 * *y1 - a2 * *y2;
+
+                    *x2 = *x1;
+                    *x1 = x0;
+                    *y2 = *y1;
+                    *y1 = y0;
+
+                    *sample = y0;
+                }
+            }
+            BiquadState::F64([x1, x2, y1, y2]) => {
+                let [b0, b1, b2, a1, a2] = [b0, b1, b2, a1, a2].map(f64::from);
+                for sample in samples.iter_mut() {
+                    let x0 = *sample as f64;
+                    let y0 = b0 * x0 + b1 * *x1 + b2 * *x2 - a1 * *y1 - a2 * *y2;
+
+                    *x2 = *x1;
+                    *x1 = x0;
+                    *y2 = *y1;
+                    *y1 = y0;
+
+                    *sample = y0 as f32;
+                }
+            }
+        }
+    }
+}
+
+/// Crossover frequencies of the multiband compressor (Hz)
+const COMPRESSION_CROSSOVERS: (f32, f32) = (200.0, 2000.0);
+
+/// Compressor settings for one band of the multiband compressor
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandCompression {
+    pub band: &'static str,
+    pub threshold_db: f32,
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+/// Low, mid and high band compressor settings for a profile
+///
+/// `intensity` scales the profile's ratios towards 1:1 (0 disables compression).
+fn compression_settings(profile: MasterProfile, intensity: f32) -> [BandCompression; 3] {
+    // Compression parameters based on profile
+    let (low_ratio, mid_ratio, high_ratio, low_threshold, mid_threshold, high_threshold) =
+        match profile {
+            MasterProfile::Balanced => (2.0, 2.0, 2.0, -18.0, -16.0, -14.0),
+            MasterProfile::Warm => (3.0, 2.0, 1.5, -16.0, -18.0, -20.0),
+            MasterProfile::Punchy => (4.0, 3.0, 2.5, -14.0, -14.0, -12.0),
+            MasterProfile::Custom => (2.0, 2.0, 2.0, -18.0, -16.0, -14.0),
+            MasterProfile::HipHop => (4.0, 2.5, 2.0, -14.0, -16.0, -16.0),
+            MasterProfile::Edm => (4.0, 3.5, 3.0, -12.0, -12.0, -12.0),
+            MasterProfile::Acoustic => (1.5, 1.5, 1.5, -22.0, -20.0, -20.0),
+            MasterProfile::Classical => (1.2, 1.2, 1.2, -26.0, -26.0, -26.0),
+            MasterProfile::Podcast => (2.0, 3.0, 2.5, -20.0, -18.0, -18.0),
+            MasterProfile::Jazz => (1.5, 1.8, 1.5, -20.0, -18.0, -18.0),
+        };
+    let band = |band, threshold_db, ratio: f32, attack_ms, release_ms| BandCompression {
+        band,
+        threshold_db,
+        ratio: 1.0 + (ratio - 1.0) * intensity,
+        attack_ms,
+        release_ms,
+    };
+    [
+        band("low", low_threshold, low_ratio, 20.0, 200.0),
+        band("mid", mid_threshold, mid_ratio, 10.0, 100.0),
+        band("high", high_threshold, high_ratio, 5.0, 50.0),
+    ]
+}
+
+/// Apply multiband compression (3 bands) with low, mid and high band `settings`
+#[tracing::instrument(name = "dsp.multiband_compression", skip_all)]
+pub(crate) fn apply_multiband_compression(
+    buffer: &mut AudioBuffer,
+    settings: &[BandCompression; 3],
+    precision: Precision,
+) -> Result<()> {
+    let sample_rate = buffer.sample_rate as f32;
+    let (low_mid_freq, mid_high_freq) = COMPRESSION_CROSSOVERS;
+
+    // Bands are split, compressed and summed a block at a time, so the only
+    // copies of the signal are three blocks of scratch space
+    let mut bands = [(); 3].map(|_| vec![0.0_f32; MULTIBAND_BLOCK_FRAMES]);
+    for channel in &mut buffer.samples {
+        let mut splitter = BandSplitter::new(sample_rate, low_mid_freq, mid_high_freq, precision);
+        let mut compressors = settings.each_ref().map(|s| Compressor::new(s, sample_rate));
+
+        for block in channel.chunks_mut(MULTIBAND_BLOCK_FRAMES) {
+            let n = block.len();
+            let [low, mid, high] = &mut bands;
+            splitter.split(block, &mut low[..n], &mut mid[..n], &mut high[..n]);
+
+            for (band, compressor) in bands.iter_mut().zip(&mut compressors) {
+                compressor.process(&mut band[..n]);
+            }
+
+            // Sum the bands
+            for (i, sample) in block.iter_mut().enumerate() {
+                *sample = bands[0][i] + bands[1][i] + bands[2][i];
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Frames of each band held at a time by the multiband compressor
+const MULTIBAND_BLOCK_FRAMES: usize = 4096;
+
+/// Split a signal into low, mid and high bands that sum back to an allpass
+fn split_bands(
+    samples: &[f32],
+    sample_rate: f32,
+    low_freq: f32,
+    high_freq: f32,
+    precision: Precision,
+) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let mut low_band = vec![0.0; samples.len()];
+    let mut mid_band = vec![0.0; samples.len()];
+    let mut high_band = vec![0.0; samples.len()];
+    BandSplitter::new(sample_rate, low_freq, high_freq, precision).split(
+        samples,
+        &mut low_band,
+        &mut mid_band,
+        &mut high_band,
+    );
+    (low_band, mid_band, high_band)
+}
+
+/// Three-band Linkwitz-Riley crossover, run a block at a time
+///
+/// The signal is first split at `low_freq`; the upper half is split again at
+/// `high_freq`. The low band then passes through an allpass matching the phase
+/// of the second crossover, so the three bands recombine with a flat magnitude
+/// response instead of comb filtering around the crossover points.
+struct BandSplitter {
+    /// LR4 lowpass at `low_freq`, then the allpass at `high_freq`
+    low: [Biquad; 3],
+    /// LR4 highpass at `low_freq`, shared by the mid and high bands
+    upper: [Biquad; 2],
+    /// LR4 lowpass and highpass at `high_freq`
+    mid: [Biquad; 2],
+    high: [Biquad; 2],
+}
+
+impl BandSplitter {
+    fn new(sample_rate: f32, low_freq: f32, high_freq: f32, precision: Precision) -> Self {
+        let lowpass = |freq| Biquad::new(lowpass_butterworth_coefs(sample_rate, freq), precision);
+        let highpass = |freq| Biquad::new(highpass_butterworth_coefs(sample_rate, freq), precision);
+        Self {
+            low: [
+                lowpass(low_freq),
+                lowpass(low_freq),
+                Biquad::new(allpass_coefs(sample_rate, high_freq), precision),
+            ],
+            upper: [highpass(low_freq), highpass(low_freq)],
+            mid: [lowpass(high_freq), lowpass(high_freq)],
+            high: [highpass(high_freq), highpass(high_freq)],
+        }
+    }
+
+    /// Split the next block of `input` into bands of the same length
+    fn split(&mut self, input: &[f32], low: &mut [f32], mid: &mut [f32], high: &mut [f32]) {
+        low.copy_from_slice(input);
+        for filter in &mut self.low {
+            filter.process(low);
+        }
+
+        mid.copy_from_slice(input);
+        for filter in &mut self.upper {
+            filter.process(mid);
+        }
+
+        high.copy_from_slice(mid);
+        for filter in &mut self.mid {
+            filter.process(mid);
+        }
+        for filter in &mut self.high {
+            filter.process(high);
+        }
+    }
+}
+
+/// Second-order allpass with the phase response of an LR4 crossover
+///
+/// The sum of an LR4 lowpass and highpass equals a 2nd order allpass with
+/// Butterworth Q, which is what this applies.
+fn allpass_coefs(sample_rate: f32, freq: f32) -> BiquadCoefs {
+    let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+    let cos_w0 = w0.cos();
+    let sin_w0 = w0.sin();
+    let alpha = sin_w0 / (2.0 * BUTTERWORTH_Q);
+
+    let b0 = 1.0 - alpha;
+    let b1 = -2.0 * cos_w0;
+    let b2 = 1.0 + alpha;
+    let a0 = 1.0 + alpha;
+    let a1 = -2.0 * cos_w0;
+    let a2 = 1.0 - alpha;
+
+    [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+}
+
+/// Q of a 2nd order Butterworth section (1/sqrt(2))
+const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+pub fn lowpass_butterworth_coefs(sample_rate: f32, freq: f32) -> BiquadCoefs {
+    let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+    let cos_w0 = w0.cos();
+    let sin_w0 = w0.sin();
+    let alpha = sin_w0 / (2.0 * BUTTERWORTH_Q);
+
+    let b0 = (1.0 - cos_w0) / 2.0;
+    let b1 = 1.0 - cos_w0;
+    let b2 = (1.0 - cos_w0) / 2.0;
+    let a0 = 1.0 + alpha;
+    let a1 = -2.0 * cos_w0;
+    let a2 = 1.0 - alpha;
+
+    [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+}
+
+pub fn highpass_butterworth_coefs(sample_rate: f32, freq: f32) -> BiquadCoefs {
+    let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+    let cos_w0 = w0.cos();
+    let sin_w0 = w0.sin();
+    let alpha = sin_w0 / (2.0 * BUTTERWORTH_Q);
+
+    let b0 = (1.0 + cos_w0) / 2.0;
+    let b1 = -(1.0 + cos_w0);
+    let b2 = (1.0 + cos_w0) / 2.0;
+    let a0 = 1.0 + alpha;
+    let a1 = -2.0 * cos_w0;
+    let a2 = 1.0 - alpha;
+
+    [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+}
+
+/// Feed-forward compressor whose envelope carries over between blocks
+pub struct Compressor {
+    threshold: f32,
+    ratio: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    envelope: f32,
+}
+
+impl Compressor {
+    pub fn new(settings: &BandCompression, sample_rate: f32) -> Self {
+        Self {
+            threshold: 10.0_f32.powf(settings.threshold_db / 20.0),
+            ratio: settings.ratio,
+            attack_coef: (-1.0 / (settings.attack_ms * sample_rate / 1000.0)).exp(),
+            release_coef: (-1.0 / (settings.release_ms * sample_rate / 1000.0)).exp(),
+            envelope: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let (threshold, ratio) = (self.threshold, self.ratio);
+        let (attack_coef, release_coef) = (self.attack_coef, self.release_coef);
+        let mut envelope = self.envelope;
+
+        for sample in samples.iter_mut() {
+            let input_abs = sample.abs();
+
+            // Envelope follower
+            if input_abs > envelope {
+                envelope = attack_coef * envelope + (1.0 - attack_coef) * input_abs;
+            } else {
+                envelope = release_coef * envelope + (1.0 - release_coef) * input_abs;
+            }
+
+            // Calculate gain reduction
+            let gain = if envelope > threshold {
+                let over_db = 20.0 * (envelope / threshold).log10();
+                let reduction_db = over_db * (1.0 - 1.0 / ratio);
+                10.0_f32.powf(-reduction_db / 20.0)
+            } else {
+                1.0
+            };
+
+            *sample *= gain;
+        }
+        self.envelope = envelope;
+    }
+}
+
+/// Saturation drive of the profiles that use it
+fn saturation_drive(profile: MasterProfile) -> Option<f32> {
+    match profile {
+        MasterProfile::Warm => Some(0.3),
+        MasterProfile::Punchy => Some(0.5),
+        MasterProfile::HipHop => Some(0.4),
+        MasterProfile::Edm => Some(0.5),
+        _ => None,
+    }
+}
+
+/// Apply tape saturation / harmonic exciter
+#[tracing::instrument(name = "dsp.saturation", skip_all)]
+pub fn apply_saturation(buffer: &mut AudioBuffer, drive: f32) -> Result<()> {
+    for channel in &mut buffer.samples {
+        for sample in channel.iter_mut() {
+            // Soft clipping using tanh
+            let x = *sample * (1.0 + drive);
+            *sample = x.tanh();
+        }
+    }
+
+    Ok(())
+}
+
+/// Outcome of the loudness-targeting limiter stage
+pub struct LimiterOutcome {
+    pub final_lufs: f64,
+    pub final_true_peak: f64,
+    pub iterations: usize,
+    pub band_reduction: Option<Vec<BandReduction>>,
+}
+
+/// Gain reduction statistics for one band of the multiband limiter
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandReduction {
+    pub band: &'static str,
+    pub max_reduction_db: f64,
+    pub average_reduction_db: f64,
+}
+
+/// Headroom kept below the true-peak ceiling by the final safety trim (dB)
+const TRUE_PEAK_SAFETY_MARGIN_DB: f64 = 0.05;
+
+/// Maximum number of measure/adjust passes when targeting loudness
+const MAX_LOUDNESS_ITERATIONS: usize = 5;
+
+/// Apply brick-wall limiter with a true peak ceiling of `ceiling_db` (dBTP)
+///
+/// Limiting pulls the integrated loudness below what the makeup gain alone
+/// predicts, so the gain is refined iteratively: each pass renders the limiter
+/// and the true-peak safety trim from the unlimited signal, measures the
+/// result and corrects the makeup gain by the remaining error until it lands
+/// within `qc.loudness_tolerance`.
+#[tracing::instrument(name = "dsp.limiter", skip_all)]
+pub fn apply_limiter(
+    buffer: &mut AudioBuffer,
+    target_lufs: f64,
+    mode: LimiterMode,
+    release_ms: f32,
+    precision: Precision,
+    ceiling_db: f64,
+) -> Result<LimiterOutcome> {
+    let qc = settings::get();
+
+    // First pass: Calculate current loudness
+    let current_lufs = calculate_loudness(buffer)?;
+    let unlimited = buffer.samples.clone();
+
+    // Calculate makeup gain needed
+    let mut makeup_db = target_lufs - current_lufs;
+    let mut iterations = 0;
+    let mut band_reduction;
+    let mut final_true_peak;
+    let safe_ceiling = ceiling_db - TRUE_PEAK_SAFETY_MARGIN_DB;
+
+    let final_lufs = loop {
+        iterations += 1;
+        buffer.samples.clone_from(&unlimited);
+
+        let makeup_gain = 10.0_f64.powf(makeup_db / 20.0) as f32;
+        band_reduction =
+            apply_gain_and_limit(buffer, makeup_gain, mode, release_ms, precision, ceiling_db);
+
+        // True-peak safety stage: the limiter's gain modulation can still
+        // leave a small inter-sample overshoot, which a static trim removes.
+        // The loudness it costs is measured below, so the next pass makes
+        // it up.
+        final_true_peak = calculate_true_peak(buffer)?;
+        if final_true_peak > safe_ceiling {
+            let trim = 10.0_f64.powf((safe_ceiling - final_true_peak) / 20.0) as f32;
+            for channel in &mut buffer.samples {
+                for sample in channel.iter_mut() {
+                    *sample *= trim;
+                }
+            }
+            final_true_peak = calculate_true_peak(buffer)?;
+        }
+
+        let measured_lufs = calculate_loudness(buffer)?;
+        let error = target_lufs - measured_lufs;
+        if error.abs() <= qc.loudness_tolerance || iterations >= MAX_LOUDNESS_ITERATIONS {
+            break measured_lufs;
+        }
+        makeup_db += error;
+    };
+
+    Ok(LimiterOutcome {
+        final_lufs,
+        final_true_peak,
+        iterations,
+        band_reduction,
+    })
+}
+
+/// Apply makeup gain followed by a lookahead brick-wall limiter
+///
+/// Peak detection runs on a 4x oversampled estimate of the signal so the
+/// ceiling is enforced on inter-sample peaks, not just sample values. In
+/// multiband mode each band is limited first and the brick wall only catches
+/// the remaining wideband peaks; per-band gain reduction (averaged across
+/// channels) is returned in that case.
+fn apply_gain_and_limit(
+    buffer: &mut AudioBuffer,
+    makeup_gain: f32,
+    mode: LimiterMode,
+    release_ms: f32,
+    precision: Precision,
+    ceiling_db: f64,
+) -> Option<Vec<BandReduction>> {
+    let ceiling_linear = 10.0_f32.powf(ceiling_db as f32 / 20.0);
+
+    let sample_rate = buffer.sample_rate as f32;
+    let lookahead_samples = ((0.005 * sample_rate) as usize).max(1); // 5ms lookahead
+    let release_coef = (-1.0 / (release_ms * sample_rate / 1000.0)).exp();
+
+    let mut band_reduction: Option<Vec<BandReduction>> = None;
+    let channel_count = buffer.channels.max(1) as f64;
+
+    for channel in &mut buffer.samples {
+        // Apply makeup gain
+        simd::scale(channel, makeup_gain);
+
+        if mode == LimiterMode::Multiband {
+            let stats = limit_multiband(
+                channel,
+                sample_rate,
+                ceiling_linear,
+                lookahead_samples,
+                precision,
+            );
+            let reduction = band_reduction.get_or_insert_with(|| {
+                MULTIBAND_LIMITER_BAND_NAMES
+                    .iter()
+                    .map(|&band| BandReduction {
+                        band,
+                        max_reduction_db: 0.0,
+                        average_reduction_db: 0.0,
+                    })
+                    .collect()
+            });
+            for (entry, stat) in reduction.iter_mut().zip(&stats) {
+                entry.max_reduction_db = entry.max_reduction_db.max(stat.max_db);
+                entry.average_reduction_db += stat.average_db / channel_count;
+            }
+        }
+
+        let detector = true_peak_envelope(channel);
+        limit_channel(
+            channel,
+            &detector,
+            ceiling_linear,
+            lookahead_samples,
+            release_coef,
+        );
+    }
+
+    band_reduction
+}
+
+/// Lookahead limiter driven by an external peak detector signal
+///
+/// Each output sample is delayed by `lookahead` samples, and the gain applied
+/// to it accounts for every detector value from that sample up to the newest
+/// input, so no detected peak passes above the ceiling.
+fn limit_channel(
+    channel: &mut [f32],
+    detector: &[f32],
+    ceiling: f32,
+    lookahead: usize,
+    release_coef: f32,
+) -> ReductionStats {
+    let len = channel.len();
+    let mut window: Vec<f32> = vec![0.0; lookahead + 1];
+    let mut gain_reduction = 1.0_f32;
+    let mut min_gain = 1.0_f32;
+    let mut reduction_sum_db = 0.0_f64;
+
+    for i in 0..len + lookahead {
+        // Lookahead peak detection (flushing zeros past the end)
+        window[i % (lookahead + 1)] = detector.get(i).copied().unwrap_or(0.0);
+        let peak = window.iter().cloned().fold(0.0_f32, f32::max);
+
+        // Calculate required gain reduction
+        let target_gr = if peak > ceiling { ceiling / peak } else { 1.0 };
+
+        // Smooth gain reduction
+        if target_gr < gain_reduction {
+            gain_reduction = target_gr; // Instant attack
+        } else {
+            gain_reduction = release_coef * gain_reduction + (1.0 - release_coef) * target_gr;
+        }
+
+        // Apply gain reduction with lookahead delay
+        if i >= lookahead {
+            channel[i - lookahead] *= gain_reduction;
+            if gain_reduction < 1.0 {
+                min_gain = min_gain.min(gain_reduction);
+                reduction_sum_db -= 20.0 * (gain_reduction as f64).log10();
+            }
+        }
+    }
+
+    ReductionStats {
+        max_db: -20.0 * (min_gain as f64).log10(),
+        average_db: if len > 0 {
+            reduction_sum_db / len as f64
+        } else {
+            0.0
+        },
+    }
+}
+
+/// Gain reduction applied by a limiter pass
+struct ReductionStats {
+    max_db: f64,
+    average_db: f64,
+}
+
+/// Crossovers (Hz) and release times (ms) of the multiband limiter bands
+const MULTIBAND_LIMITER_CROSSOVERS: (f32, f32) = (200.0, 2000.0);
+const MULTIBAND_LIMITER_RELEASE_MS: [f32; 3] = [150.0, 80.0, 40.0];
+const MULTIBAND_LIMITER_BAND_NAMES: [&str; 3] = ["low", "mid", "high"];
+
+/// Band limiters sit slightly below the final ceiling so the wideband
+/// true-peak stage only catches what the band sum adds back (dB)
+const MULTIBAND_LIMITER_HEADROOM_DB: f32 = 1.0;
+
+/// Limit each band of a channel separately, then recombine
+///
+/// Returns the gain reduction applied per band, ordered low to high.
+fn limit_multiband(
+    channel: &mut [f32],
+    sample_rate: f32,
+    ceiling: f32,
+    lookahead: usize,
+    precision: Precision,
+) -> [ReductionStats; 3] {
+    let (low_freq, high_freq) = MULTIBAND_LIMITER_CROSSOVERS;
+    let (low, mid, high) = split_bands(channel, sample_rate, low_freq, high_freq, precision);
+    let band_ceiling = ceiling * 10.0_f32.powf(-MULTIBAND_LIMITER_HEADROOM_DB / 20.0);
+
+    let mut bands = [low, mid, high];
+    let stats = std::array::from_fn(|i| {
+        let release_coef = (-1.0 / (MULTIBAND_LIMITER_RELEASE_MS[i] * sample_rate / 1000.0)).exp();
+        let detector: Vec<f32> = bands[i].iter().map(|s| s.abs()).collect();
+        limit_channel(
+            &mut bands[i],
+            &detector,
+            band_ceiling,
+            lookahead,
+            release_coef,
+        )
+    });
+
+    for (i, sample) in channel.iter_mut().enumerate() {
+        *sample = bands[0][i] + bands[1][i] + bands[2][i];
+    }
+
+    stats
+}
+
+/// Oversampling factor used for true-peak detection inside the limiter
+const TRUE_PEAK_OVERSAMPLE: usize = 4;
+
+/// Interpolation taps on each side of the interpolated point
+const TRUE_PEAK_TAPS: isize = 6;
+
+/// Lanczos kernel used for the oversampling interpolator
+fn lanczos(t: f32, a: f32) -> f32 {
+    if t.abs() < 1e-6 {
+        return 1.0;
+    }
+    if t.abs() >= a {
+        return 0.0;
+    }
+    let pi_t = std::f32::consts::PI * t;
+    a * pi_t.sin() * (pi_t / a).sin() / (pi_t * pi_t)
+}
+
+/// Per-sample true-peak detector signal
+///
+/// For every sample, returns the largest magnitude among the sample itself and
+/// the interpolated points on the intervals adjacent to it.
+fn true_peak_envelope(samples: &[f32]) -> Vec<f32> {
+    let taps = (2 * TRUE_PEAK_TAPS) as usize;
+    let phases: Vec<Vec<f32>> = (1..TRUE_PEAK_OVERSAMPLE)
+        .map(|p| {
+            let frac = p as f32 / TRUE_PEAK_OVERSAMPLE as f32;
+            (0..taps)
+                .map(|i| {
+                    let offset = i as isize - (TRUE_PEAK_TAPS - 1);
+                    lanczos(frac - offset as f32, TRUE_PEAK_TAPS as f32)
+                })
+                .collect()
+        })
+        .collect();
+
+    let len = samples.len();
+    let mut envelope: Vec<f32> = samples.iter().map(|s| s.abs()).collect();
+
+    for n in 0..len.saturating_sub(1) {
+        let mut peak = 0.0_f32;
+        for coefs in &phases {
+            let mut acc = 0.0_f32;
+            for (i, &c) in coefs.iter().enumerate() {
+                let idx = n as isize + i as isize - (TRUE_PEAK_TAPS - 1);
+                if idx >= 0 && (idx as usize) < len {
+                    acc += c * samples[idx as usize];
+                }
+            }
+            peak = peak.max(acc.abs());
+        }
+        envelope[n] = envelope[n].max(peak);
+        envelope[n + 1] = envelope[n + 1].max(peak);
+    }
+
+    envelope
+}
+
+/// Calculate integrated loudness using ebur128
+pub fn calculate_loudness(buffer: &AudioBuffer) -> Result<f64> {
+    use ebur128::{EbuR128, Mode};
+
+    let mode = Mode::I;
+    let mut ebu = EbuR128::new(buffer.channels as u32, buffer.sample_rate, mode)?;
+
+    let frame_count = buffer.frame_count();
+    let chunk_size = 4096;
+
+    for start in (0..frame_count).step_by(chunk_size) {
+        let end = (start + chunk_size).min(frame_count);
+        let chunk_len = end - start;
+
+        let mut interleaved = Vec::with_capacity(chunk_len * buffer.channels);
+        for i in start..end {
+            for ch in 0..buffer.channels {
+                interleaved.push(buffer.samples[ch][i]);
+            }
+        }
+
+        ebu.add_frames_f32(&interleaved)?;
+    }
+
+    Ok(ebu.loudness_global().unwrap_or(-70.0))
+}
+
+/// Calculate loudness range (LRA) using ebur128
+fn calculate_loudness_range(buffer: &AudioBuffer) -> Result<f64> {
+    use ebur128::{EbuR128, Mode};
+
+    let mut ebu = EbuR128::new(buffer.channels as u32, buffer.sample_rate, Mode::LRA)?;
+
+    let frame_count = buffer.frame_count();
+    let chunk_size = 4096;
+
+    for start in (0..frame_count).step_by(chunk_size) {
+        let end = (start + chunk_size).min(frame_count);
+
+        let mut interleaved = Vec::with_capacity((end - start) * buffer.channels);
+        for i in start..end {
+            for ch in 0..buffer.channels {
+                interleaved.push(buffer.samples[ch][i]);
+            }
+        }
+
+        ebu.add_frames_f32(&interleaved)?;
+    }
+
+    Ok(ebu.loudness_range().unwrap_or(0.0))
+}
+
+/// Calculate true peak using 4x oversampling
+pub fn calculate_true_peak(buffer: &AudioBuffer) -> Result<f64> {
+    Ok(true_peak::measure(buffer)?.db())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_split_recombines_flat() {
+        let sample_rate = 44100.0;
+        let cases = [100.0, 200.0, 630.0, 2000.0, 5000.0]
+            .into_iter()
+            .flat_map(|freq| [(freq, Precision::F32), (freq, Precision::F64)]);
+        for (freq, precision) in cases {
+            let input: Vec<f32> = (0..44100)
+                .map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / sample_rate).sin())
+                .collect();
+            let (low, mid, high) = split_bands(&input, sample_rate, 200.0, 2000.0, precision);
+
+            // Skip the filters' settling time
+            let rms = |x: &[f32]| (x.iter().map(|s| s * s).sum::<f32>() / x.len() as f32).sqrt();
+            let summed: Vec<f32> = (0..input.len())
+                .map(|i| low[i] + mid[i] + high[i])
+                .collect();
+            let ratio_db = 20.0 * (rms(&summed[4410..]) / rms(&input[4410..])).log10();
+            assert!(
+                ratio_db.abs() < 0.1,
+                "{} Hz deviates by {} dB at {:?}",
+                freq,
+                ratio_db,
+                precision
+            );
+        }
+    }
+
+    #[test]
+    fn test_multiband_compression_is_seamless_across_blocks() {
+        let sample_rate = 44100.0;
+        let input: Vec<f32> = (0..2 * MULTIBAND_BLOCK_FRAMES + 1000)
+            .map(|i| {
+                let t = i as f32 / sample_rate;
+                0.8 * (2.0 * std::f32::consts::PI * 110.0 * t).sin()
+                    + 0.3 * (2.0 * std::f32::consts::PI * 3000.0 * t).sin()
+            })
+            .collect();
+
+        // The whole channel split and compressed at once
+        let settings = compression_settings(MasterProfile::Punchy, 1.0);
+        let (mut low, mut mid, mut high) =
+            split_bands(&input, sample_rate, 200.0, 2000.0, Precision::F64);
+        for (band, s) in [&mut low, &mut mid, &mut high].into_iter().zip(&settings) {
+            Compressor::new(s, sample_rate).process(band);
+        }
+
+        let mut buffer = AudioBuffer {
+            samples: vec![input],
+            sample_rate: 44100,
+            channels: 1,
+        };
+        apply_multiband_compression(&mut buffer, &settings, Precision::F64).unwrap();
+        for (i, sample) in buffer.samples[0].iter().enumerate() {
+            assert_eq!(*sample, low[i] + mid[i] + high[i], "frame {}", i);
+        }
+    }
+
+    #[test]
+    fn test_limiter_honors_true_peak_ceiling() {
+        // A quarter-sample-rate sine phased so every sample lands off the
+        // crest hides its peaks between samples
+        let channel: Vec<f32> = (0..88200)
+            .map(|i| 0.5 * (std::f32::consts::PI * (i as f32 / 2.0 + 0.25)).sin())
+            .collect();
+        for mode in [LimiterMode::BrickWall, LimiterMode::Multiband] {
+            let mut buffer = AudioBuffer {
+                samples: vec![channel.clone(), channel.clone()],
+                sample_rate: 44100,
+                channels: 2,
+            };
+
+            let ceiling = settings::get().true_peak_max;
+            let outcome =
+                apply_limiter(&mut buffer, -6.0, mode, 100.0, Precision::F32, ceiling).unwrap();
+            assert!(outcome.final_true_peak <= ceiling);
+            assert_eq!(
+                outcome.band_reduction.is_some(),
+                mode == LimiterMode::Multiband
+            );
+        }
+    }
+
+    #[test]
+    fn test_heavy_limiting_converges_on_target() {
+        // A quiet tone under loud clicks: reaching -12 LUFS means limiting the
+        // clicks hard, which costs loudness the first pass doesn't predict
+        let channel: Vec<f32> = (0..132300)
+            .map(|i| {
+                let tone = 0.02 * (2.0 * std::f32::consts::PI * 220.0 * i as f32 / 44100.0).sin();
+                let click = if i % 4410 < 20 { 0.9 } else { 0.0 };
+                tone + click
+            })
+            .collect();
+        let mut buffer = AudioBuffer {
+            samples: vec![channel.clone(), channel],
+            sample_rate: 44100,
+            channels: 2,
+        };
+        let qc = settings::get();
+        let outcome = apply_limiter(
+            &mut buffer,
+            -12.0,
+            LimiterMode::BrickWall,
+            100.0,
+            Precision::F32,
+            qc.true_peak_max,
+        )
+        .unwrap();
+        assert!(outcome.iterations > 1, "{} passes", outcome.iterations);
+        assert!(outcome.iterations <= MAX_LOUDNESS_ITERATIONS);
+        assert!(
+            (outcome.final_lufs + 12.0).abs() <= qc.loudness_tolerance,
+            "{} LUFS",
+            outcome.final_lufs
+        );
+        assert!(outcome.final_true_peak <= qc.true_peak_max);
+    }
+
+    #[test]
+    fn test_limiter_lands_on_target_after_safety_trim() {
+        // Inter-sample peaks the limiter misses, so every pass is trimmed
+        let channel: Vec<f32> = (0..88200)
+            .map(|i| 0.5 * (std::f32::consts::PI * (i as f32 / 2.0 + 0.25)).sin())
+            .collect();
+        let mut buffer = AudioBuffer {
+            samples: vec![channel.clone(), channel],
+            sample_rate: 44100,
+            channels: 2,
+        };
+        let qc = settings::get();
+        let outcome = apply_limiter(
+            &mut buffer,
+            -10.0,
+            LimiterMode::BrickWall,
+            100.0,
+            Precision::F32,
+            qc.true_peak_max,
+        )
+        .unwrap();
+        assert!(outcome.final_true_peak <= qc.true_peak_max);
+        assert!(
+            (outcome.final_lufs + 10.0).abs() <= qc.loudness_tolerance,
+            "{} LUFS",
+            outcome.final_lufs
+        );
+    }
+
+    /// Three seconds of a quiet 440 Hz tone
+    fn quiet_tone() -> AudioBuffer {
+        let channel: Vec<f32> = (0..132300)
+            .map(|i| 0.05 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin())
+            .collect();
+        AudioBuffer {
+            samples: vec![channel.clone(), channel],
+            sample_rate: 44100,
+            channels: 2,
+        }
+    }
+
+    #[test]
+    fn test_partial_mix_lands_on_target() {
+        let qc = settings::get();
+        for (mix, output_trim_db) in [(0.0, 0.0), (0.5, 0.0), (0.5, -2.0)] {
+            let mut buffer = quiet_tone();
+            let options = MasteringOptions {
+                mix,
+                output_trim_db,
+                ..Default::default()
+            };
+            let result = apply_mastering(
+                &mut buffer,
+                MasterProfile::Balanced,
+                LoudnessTarget::Medium,
+                &options,
+            )
+            .unwrap();
+            let expected = LoudnessTarget::Medium.lufs_value() + output_trim_db;
+            assert!(
+                (result.final_lufs - expected).abs() <= qc.loudness_tolerance,
+                "mix {}: {} LUFS",
+                mix,
+                result.final_lufs
+            );
+            assert!(result.final_true_peak <= qc.true_peak_max);
+            assert!(result.passes_qc, "mix {}", mix);
+        }
+    }
+
+    #[test]
+    fn test_loudness_miss_fails_qc() {
+        let mut buffer = quiet_tone();
+        let mut result = apply_mastering(
+            &mut buffer,
+            MasterProfile::Balanced,
+            LoudnessTarget::Medium,
+            &MasteringOptions::default(),
+        )
+        .unwrap();
+        assert!(result.passes_qc);
+
+        // Turned down 6 dB past the limiter without asking for a trim
+        for channel in &mut buffer.samples {
+            for sample in channel.iter_mut() {
+                *sample *= 0.5;
+            }
+        }
+        result.remeasure(&buffer, 0.0).unwrap();
+        assert!(result.final_true_peak <= settings::get().true_peak_max);
+        assert!(!result.passes_loudness);
+        assert!(!result.passes_qc);
+
+        // The same level is a pass when it is the trim asked for
+        result.remeasure(&buffer, -6.0).unwrap();
+        assert!(result.passes_loudness);
+        assert!(result.passes_qc);
+    }
+
+    /// Three seconds of tones in the low, mid and high matching bands
+    fn three_band_mix(low: f32, mid: f32, high: f32) -> AudioBuffer {
+        let channel: Vec<f32> = (0..132300)
+            .map(|i| {
+                let t = i as f32 / 44100.0;
+                let tone = |freq: f32| (2.0 * std::f32::consts::PI * freq * t).sin();
+                low * tone(80.0) + mid * tone(2000.0) + high * tone(10000.0)
+            })
+            .collect();
+        AudioBuffer {
+            samples: vec![channel.clone(), channel],
+            sample_rate: 44100,
+            channels: 2,
+        }
+    }
+
+    #[test]
+    fn test_match_reference() {
+        let source = three_band_mix(0.2, 0.2, 0.2);
+
+        // Darker and much quieter: cut the highs, never by more than the
+        // correction limit, and don't chase the level below -20 LUFS
+        let reference = three_band_mix(0.01, 0.01, 0.0005);
+        let matched = match_reference(&source, &reference).unwrap();
+        assert!(matched.reference_lufs < -20.0);
+        assert_eq!(matched.target_lufs, -20.0);
+        assert_eq!(matched.high_gain_db, -REFERENCE_MAX_CORRECTION_DB);
+        assert!(matched.low_gain_db > 0.0);
+        assert!(matched.mid_gain_db > 0.0);
+
+        // Brighter and louder than the ceiling allows
+        let reference = three_band_mix(1.0, 1.0, 2.0);
+        let matched = match_reference(&source, &reference).unwrap();
+        assert!(matched.reference_lufs > -7.0);
+        assert_eq!(matched.target_lufs, -7.0);
+        assert!(matched.high_gain_db > 0.0);
+        assert!(matched.low_gain_db < 0.0);
+
+        // The same balance needs no EQ
+        let matched = match_reference(&source, &three_band_mix(0.1, 0.1, 0.1)).unwrap();
+        for gain in [
+            matched.low_gain_db,
+            matched.mid_gain_db,
+            matched.high_gain_db,
+        ] {
+            assert!(gain.abs() < 0.1, "{} dB", gain);
+        }
+    }
+
+    #[test]
+    fn test_options_reject_bad_mix_and_trim() {
+        for (mix, output_trim_db) in [(1.5, 0.0), (-0.1, 0.0), (f32::NAN, 0.0), (1.0, 0.5)] {
+            let options = MasteringOptions {
+                mix,
+                output_trim_db,
+                ..Default::default()
+            };
+            assert!(options.validate().is_err(), "{} {}", mix, output_trim_db);
+            let mut buffer = AudioBuffer::new(2, 44100);
+            buffer.samples = vec![vec![0.1; 44100]; 2];
+            assert!(apply_mastering(
+                &mut buffer,
+                MasterProfile::Balanced,
+                LoudnessTarget::Medium,
+                &options
+            )
+            .is_err());
+        }
+        let options = MasteringOptions {
+            mix: 0.5,
+            output_trim_db: -3.0,
+            ..Default::default()
+        };
+        options.validate().unwrap();
+    }
+
+    #[test]
+    fn test_plan_predicts_rendered_master() {
+        // Three seconds of a decaying tone mix, quiet enough to need gain
+        let channel: Vec<f32> = (0..132300)
+            .map(|i| {
+                let t = i as f32 / 44100.0;
+                let tone = (2.0 * std::f32::consts::PI * 110.0 * t).sin()
+                    + 0.5 * (2.0 * std::f32::consts::PI * 1760.0 * t).sin();
+                0.1 * tone * (1.0 - (t * 4.0).fract() * 0.5)
+            })
+            .collect();
+        let mut buffer = AudioBuffer {
+            samples: vec![channel.clone(), channel],
+            sample_rate: 44100,
+            channels: 2,
+        };
+        let options = MasteringOptions::default();
+
+        let plan = plan_mastering(
+            &buffer,
+            MasterProfile::Punchy,
+            LoudnessTarget::Medium,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(plan.limiter_mode, LimiterMode::BrickWall);
+        assert!(plan.saturation_drive.is_some());
+        assert!(plan.gain_db > 0.0);
+
+        let result = apply_mastering(
+            &mut buffer,
+            MasterProfile::Punchy,
+            LoudnessTarget::Medium,
+            &options,
+        )
+        .unwrap();
+        assert!(
+            (result.final_lufs - plan.predicted_lufs).abs()
+                <= settings::get().loudness_tolerance + 0.1,
+            "planned {} LUFS, rendered {}",
+            plan.predicted_lufs,
+            result.final_lufs
+        );
+    }
+
+    #[test]
+    fn test_loudness_targets_by_name_or_level() {
+        let targets: Vec<LoudnessTarget> =
+            serde_json::from_str(r#"["broadcast_ebu", "medium", -12.5, -9]"#).unwrap();
+        assert_eq!(targets[0].lufs_value(), -23.0);
+        assert_eq!(targets[1], LoudnessTarget::Medium);
+        assert_eq!(targets[2], LoudnessTarget::Lufs(-12.5));
+        assert_eq!(targets[3].lufs_value(), -9.0);
+        assert_eq!(
+            serde_json::to_string(&targets).unwrap(),
+            r#"["broadcast_ebu","medium",-12.5,-9.0]"#
+        );
+
+        assert!(serde_json::from_str::<LoudnessTarget>(r#""louder""#).is_err());
+        let quiet = serde_json::from_str::<LoudnessTarget>("-60").unwrap_err();
+        assert!(quiet.to_string().contains("outside"), "{}", quiet);
+    }
+
+    #[test]
+    fn test_profiles_by_name() {
+        let profiles: Vec<MasterProfile> =
+            serde_json::from_str(r#"["hip_hop", "hip-hop", "classical", "voice"]"#).unwrap();
+        assert_eq!(
+            profiles,
+            [
+                MasterProfile::HipHop,
+                MasterProfile::HipHop,
+                MasterProfile::Classical,
+                MasterProfile::Podcast
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&profiles[0]).unwrap(),
+            format!(r#""{}""#, profiles[0])
+        );
+        assert!(serde_json::from_str::<MasterProfile>(r#""metal""#).is_err());
+    }
+}
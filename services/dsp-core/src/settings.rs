@@ -0,0 +1,47 @@
+//! Detection thresholds and QC gates
+//!
+//! The DSP worker fills these in from its `[dsp]` and `[qc]` config
+//! sections at startup; anything else using the library gets the defaults
+//! unless it calls `init` first.
+
+use std::sync::OnceLock;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Sample level counted as clipped, and repaired by the clip repair fix
+    /// (linear, slightly below full scale to catch near-clipping)
+    pub clip_threshold: f32,
+    /// Mean sample value reported as DC offset
+    pub dc_offset_threshold: f64,
+    /// Level the silence trim treats as silent (linear, 0.001 is -60 dBFS)
+    pub silence_threshold: f32,
+    /// Highest true peak a master passes with (dBTP), also the limiter
+    /// ceiling
+    pub true_peak_max: f64,
+    /// How close to its target the limiter brings a master's loudness (LU)
+    pub loudness_tolerance: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            clip_threshold: 0.99,
+            dc_offset_threshold: 0.001,
+            silence_threshold: 0.001,
+            true_peak_max: -2.0,
+            loudness_tolerance: 1.0,
+        }
+    }
+}
+
+/// Use `settings` for the rest of the process
+pub fn init(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+/// This process's settings, or the defaults if `init` wasn't called
+pub fn get() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
@@ -0,0 +1,203 @@
+//! True-peak metering: the largest magnitude of the signal oversampled 4x
+//!
+//! One FFT resampler runs over the whole track, chunk after chunk, so its
+//! overlap carries across chunk boundaries and a peak straddling one is
+//! measured like any other. At the end the partial last chunk is padded and
+//! the resampler's own delay flushed through with silence; without that, the
+//! inter-sample peaks of the last few milliseconds never come out of it.
+//!
+//! Input arrives in blocks of any size. Whole chunks are read straight from
+//! them, and only a block's remainder is copied aside for the next one.
+
+use anyhow::Result;
+use rubato::{FftFixedIn, Resampler};
+
+use crate::AudioBuffer;
+
+/// Oversampling factor
+const OVERSAMPLE: usize = 4;
+
+/// Input frames per resampler chunk
+const CHUNK_FRAMES: usize = 1024;
+
+/// Streaming 4x true-peak meter
+pub struct TruePeakMeter {
+    resampler: FftFixedIn<f32>,
+    /// Input short of a whole chunk, one plane per channel
+    pending: Vec<Vec<f32>>,
+    /// Reused output of every chunk
+    output: Vec<Vec<f32>>,
+    input_frames: usize,
+    output_frames: usize,
+    peak: TruePeak,
+}
+
+/// What a `TruePeakMeter` measured
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TruePeak {
+    /// Largest oversampled magnitude (linear)
+    pub peak: f32,
+    /// Oversampled samples above full scale
+    pub overs: usize,
+}
+
+impl TruePeak {
+    /// The peak in dBTP, -96 for silence
+    pub fn db(&self) -> f64 {
+        if self.peak > 0.0 {
+            20.0 * (self.peak as f64).log10()
+        } else {
+            -96.0
+        }
+    }
+}
+
+impl TruePeakMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Result<Self> {
+        let resampler = FftFixedIn::<f32>::new(
+            sample_rate as usize,
+            sample_rate as usize * OVERSAMPLE,
+            CHUNK_FRAMES,
+            2,
+            channels,
+        )?;
+        let output = resampler.output_buffer_allocate(true);
+        Ok(Self {
+            resampler,
+            pending: vec![Vec::with_capacity(CHUNK_FRAMES); channels],
+            output,
+            input_frames: 0,
+            output_frames: 0,
+            peak: TruePeak::default(),
+        })
+    }
+
+    /// Add the next block of the track, one plane per channel
+    pub fn add<P: AsRef<[f32]>>(&mut self, planes: &[P]) -> Result<()> {
+        let frames = planes.iter().map(|p| p.as_ref().len()).min().unwrap_or(0);
+        self.input_frames += frames;
+        let mut start = 0;
+
+        // Complete the chunk the last block left unfinished
+        let buffered = self.pending.first().map_or(0, Vec::len);
+        if buffered > 0 {
+            start = (CHUNK_FRAMES - buffered).min(frames);
+            for (pending, plane) in self.pending.iter_mut().zip(planes) {
+                pending.extend_from_slice(&plane.as_ref()[..start]);
+            }
+            if buffered + start < CHUNK_FRAMES {
+                return Ok(());
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.process(Some(&pending))?;
+            self.pending = pending;
+            self.pending.iter_mut().for_each(Vec::clear);
+        }
+
+        while frames - start >= CHUNK_FRAMES {
+            let chunk: Vec<&[f32]> = planes
+                .iter()
+                .map(|plane| &plane.as_ref()[start..start + CHUNK_FRAMES])
+                .collect();
+            self.process(Some(&chunk))?;
+            start += CHUNK_FRAMES;
+        }
+
+        for (pending, plane) in self.pending.iter_mut().zip(planes) {
+            pending.extend_from_slice(&plane.as_ref()[start..frames]);
+        }
+        Ok(())
+    }
+
+    /// What has been measured so far, short of the input still held in the
+    /// resampler
+    pub fn peak(&self) -> TruePeak {
+        self.peak
+    }
+
+    /// Flush the track's end through the resampler and return the result
+    pub fn finish(mut self) -> Result<TruePeak> {
+        if self.input_frames == 0 {
+            return Ok(self.peak);
+        }
+        if self.pending.first().is_some_and(|p| !p.is_empty()) {
+            let pending = std::mem::take(&mut self.pending);
+            self.process(Some(&pending))?;
+        }
+        let wanted = self.input_frames * OVERSAMPLE + self.resampler.output_delay();
+        while self.output_frames < wanted {
+            self.process::<Vec<f32>>(None)?;
+        }
+        Ok(self.peak)
+    }
+
+    /// Resample one chunk, or a partial one padded with silence, and scan
+    /// the output
+    fn process<V: AsRef<[f32]>>(&mut self, chunk: Option<&[V]>) -> Result<()> {
+        let (_, written) =
+            self.resampler
+                .process_partial_into_buffer(chunk, &mut self.output, None)?;
+        self.output_frames += written;
+        for channel in &self.output {
+            for &sample in &channel[..written] {
+                let abs = sample.abs();
+                self.peak.peak = self.peak.peak.max(abs);
+                if abs > 1.0 {
+                    self.peak.overs += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// True peak of a whole buffer
+pub fn measure(buffer: &AudioBuffer) -> Result<TruePeak> {
+    let mut meter = TruePeakMeter::new(buffer.channels, buffer.sample_rate)?;
+    meter.add(&buffer.samples)?;
+    meter.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peaks_at_the_end_and_across_blocks() {
+        // A quarter-rate sine at 45 degrees samples to +-0.707 but peaks at
+        // 1.0 between samples; put a burst of it at the very end of a track
+        // that fills its last chunk, so only the flush brings it out
+        let len = 3 * CHUNK_FRAMES;
+        let burst: Vec<f32> = (0..len)
+            .map(|i| {
+                if i + 32 < len {
+                    0.0
+                } else {
+                    (std::f32::consts::FRAC_PI_2 * i as f32 + std::f32::consts::FRAC_PI_4).sin()
+                }
+            })
+            .collect();
+        let mut buffer = AudioBuffer::new(2, 48000);
+        buffer.samples = vec![burst.clone(), vec![0.0; len]];
+
+        let whole = measure(&buffer).unwrap();
+        assert!(whole.db() > -1.5, "true peak {:.2} dBTP", whole.db());
+
+        // Feeding it in odd-sized blocks measures the same
+        let mut meter = TruePeakMeter::new(2, 48000).unwrap();
+        for start in (0..len).step_by(700) {
+            let end = (start + 700).min(len);
+            let block: Vec<Vec<f32>> = buffer
+                .samples
+                .iter()
+                .map(|plane| plane[start..end].to_vec())
+                .collect();
+            meter.add(&block).unwrap();
+        }
+        let blocks = meter.finish().unwrap();
+        assert_eq!(blocks.peak, whole.peak);
+        assert_eq!(blocks.overs, whole.overs);
+
+        assert_eq!(measure(&AudioBuffer::new(2, 48000)).unwrap().db(), -96.0);
+    }
+}
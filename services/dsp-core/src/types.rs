@@ -0,0 +1,457 @@
+//! Results of analysis and fixes, and the settings of the mastering chain
+
+use serde::de::value::{MapAccessDeserializer, MapDeserializer};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Analysis results
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalysisResult {
+    pub integrated_lufs: f64,
+    pub loudness_range: f64,
+    pub short_term_max: f64,
+    pub momentary_max: f64,
+    pub sample_peak: f64,
+    pub true_peak: f64,
+    pub spectral_centroid: Option<f64>,
+    pub spectral_rolloff: Option<f64>,
+    pub stereo_correlation: Option<f64>,
+    pub stereo_width: Option<f64>,
+    pub has_clipping: bool,
+    pub has_dc_offset: bool,
+    pub dc_offset_value: Option<f64>,
+    pub clipped_samples: usize,
+    pub sample_rate: u32,
+    pub bit_depth: u32,
+    pub channels: usize,
+    pub duration_secs: f64,
+}
+
+/// Metrics of the audio an `Analyzer` has been given so far
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialAnalysis {
+    pub duration_secs: f64,
+    /// None until the first gated 400 ms block
+    pub integrated_lufs: Option<f64>,
+    pub loudness_range: Option<f64>,
+    /// Over the last 3 s
+    pub short_term_lufs: Option<f64>,
+    /// Over the last 400 ms
+    pub momentary_lufs: Option<f64>,
+    pub sample_peak: f64,
+    pub true_peak: f64,
+    pub clipped_samples: usize,
+}
+
+/// Loudness and peaks of a render, or the change in them
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    pub loudness_range: f64,
+    pub true_peak: f64,
+    pub sample_peak: f64,
+}
+
+impl From<&AnalysisResult> for Loudness {
+    fn from(analysis: &AnalysisResult) -> Self {
+        Self {
+            integrated_lufs: analysis.integrated_lufs,
+            loudness_range: analysis.loudness_range,
+            true_peak: analysis.true_peak,
+            sample_peak: analysis.sample_peak,
+        }
+    }
+}
+
+/// A fix module and its settings
+///
+/// Jobs name a module on its own (`"normalize"`) to use its defaults, or as
+/// an object with the settings to change (`{"module": "normalize",
+/// "targetDb": -0.3}`). Unknown modules and out-of-range settings are
+/// rejected with the payload instead of being skipped.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(remote = "Self", tag = "module", rename_all = "snake_case")]
+pub enum FixModule {
+    /// Gain bringing the sample peak to `target_db` (dBFS, default -1)
+    #[serde(rename_all = "camelCase")]
+    Normalize {
+        #[serde(default)]
+        target_db: Option<f64>,
+    },
+    /// Interpolation across runs of samples at or above `threshold` (linear,
+    /// default `dsp.clip_threshold`)
+    #[serde(rename_all = "camelCase")]
+    ClipRepair {
+        #[serde(default)]
+        threshold: Option<f32>,
+    },
+    /// Reduction of sibilance above `frequency_hz` (default 4000) once its
+    /// envelope passes `threshold` (linear, default 0.3), by `ratio` (0-1,
+    /// default 0.5)
+    #[serde(rename_all = "camelCase")]
+    DeEss {
+        #[serde(default)]
+        frequency_hz: Option<f32>,
+        #[serde(default)]
+        threshold: Option<f32>,
+        #[serde(default)]
+        ratio: Option<f32>,
+    },
+    /// Gating of passages below twice `floor_db` (dBFS, default -60)
+    #[serde(rename_all = "camelCase")]
+    NoiseReduction {
+        #[serde(default)]
+        floor_db: Option<f64>,
+    },
+    DcOffset,
+    /// Trimming of the silence (below `threshold`, linear, default
+    /// `dsp.silence_threshold`) at either end, keeping `keep_ms` of it
+    /// (default 100)
+    #[serde(rename_all = "camelCase")]
+    SilenceTrim {
+        #[serde(default)]
+        threshold: Option<f32>,
+        #[serde(default)]
+        keep_ms: Option<f64>,
+    },
+}
+
+impl FixModule {
+    /// Name of the module, as reported in `FixChange::module`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Normalize { .. } => "normalize",
+            Self::ClipRepair { .. } => "clip_repair",
+            Self::DeEss { .. } => "de_ess",
+            Self::NoiseReduction { .. } => "noise_reduction",
+            Self::DcOffset => "dc_offset",
+            Self::SilenceTrim { .. } => "silence_trim",
+        }
+    }
+
+    /// Check the module's settings
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let level = |name: &str, x: Option<f32>| {
+            anyhow::ensure!(
+                x.is_none_or(|x| x > 0.0 && x <= 1.0),
+                "{}: {} must be above 0 and at most 1",
+                self.name(),
+                name
+            );
+            Ok(())
+        };
+        match *self {
+            Self::Normalize { target_db } => anyhow::ensure!(
+                target_db.is_none_or(|db| db.is_finite() && db <= 0.0),
+                "normalize: targetDb must be at most 0 dBFS"
+            ),
+            Self::ClipRepair { threshold } => level("threshold", threshold)?,
+            Self::DeEss {
+                frequency_hz,
+                threshold,
+                ratio,
+            } => {
+                anyhow::ensure!(
+                    frequency_hz.is_none_or(|hz| (1000.0..=16000.0).contains(&hz)),
+                    "de_ess: frequencyHz must be between 1000 and 16000"
+                );
+                level("threshold", threshold)?;
+                level("ratio", ratio)?;
+            }
+            Self::NoiseReduction { floor_db } => anyhow::ensure!(
+                floor_db.is_none_or(|db| (-120.0..0.0).contains(&db)),
+                "noise_reduction: floorDb must be between -120 and 0 dBFS"
+            ),
+            Self::DcOffset => {}
+            Self::SilenceTrim { threshold, keep_ms } => {
+                level("threshold", threshold)?;
+                anyhow::ensure!(
+                    keep_ms.is_none_or(|ms| ms.is_finite() && ms >= 0.0),
+                    "silence_trim: keepMs must not be negative"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for FixModule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ModuleVisitor;
+
+        impl<'de> Visitor<'de> for ModuleVisitor {
+            type Value = FixModule;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a fix module name, or an object with its `module` and settings")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<FixModule, E> {
+                FixModule::deserialize(MapDeserializer::new(std::iter::once(("module", name))))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<FixModule, A::Error> {
+                FixModule::deserialize(MapAccessDeserializer::new(map))
+            }
+        }
+
+        let module = deserializer.deserialize_any(ModuleVisitor)?;
+        module.validate().map_err(de::Error::custom)?;
+        Ok(module)
+    }
+}
+
+impl Serialize for FixModule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FixModule::serialize(self, serializer)
+    }
+}
+
+/// Fix operation result
+#[derive(Debug, Clone, Serialize)]
+pub struct FixChange {
+    pub module: String,
+    pub description: String,
+}
+
+/// Mastering profile
+///
+/// Jobs name it in snake_case (`"hip_hop"`). Unknown names are rejected with
+/// the payload instead of falling back to `Balanced`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MasterProfile {
+    Balanced,
+    Warm,
+    Punchy,
+    Custom,
+    #[serde(alias = "hip-hop", alias = "hiphop")]
+    HipHop,
+    #[serde(alias = "electronic")]
+    Edm,
+    Acoustic,
+    Classical,
+    #[serde(alias = "voice")]
+    Podcast,
+    Jazz,
+}
+
+impl MasterProfile {
+    /// The name jobs use
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Balanced => "balanced",
+            Self::Warm => "warm",
+            Self::Punchy => "punchy",
+            Self::Custom => "custom",
+            Self::HipHop => "hip_hop",
+            Self::Edm => "edm",
+            Self::Acoustic => "acoustic",
+            Self::Classical => "classical",
+            Self::Podcast => "podcast",
+            Self::Jazz => "jazz",
+        }
+    }
+
+    /// Final limiter used by the profile unless the job overrides it
+    pub fn limiter_mode(&self) -> LimiterMode {
+        match self {
+            Self::HipHop | Self::Edm => LimiterMode::Multiband,
+            _ => LimiterMode::BrickWall,
+        }
+    }
+
+    /// Limiter release time; fast for dense genres, slow for dynamic material
+    pub fn limiter_release_ms(&self) -> f32 {
+        match self {
+            Self::Edm => 50.0,
+            Self::HipHop => 80.0,
+            Self::Acoustic | Self::Jazz => 150.0,
+            Self::Classical => 250.0,
+            _ => 100.0,
+        }
+    }
+}
+
+impl fmt::Display for MasterProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Filter implementation for the mastering EQ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EqMode {
+    /// Minimum-phase biquad shelves and peaks
+    #[default]
+    Iir,
+    /// FIR with the same magnitude response and no phase shift
+    LinearPhase,
+}
+
+/// Arithmetic precision of the mastering chain's filters
+///
+/// Long biquad cascades (the EQ and the band crossovers) accumulate f32
+/// round-off in their recursive state, which can be heard as a noise floor
+/// on quiet material such as classical recordings. Samples stay f32 between
+/// stages either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Precision {
+    /// Single-precision filters, vectorized with the `simd` feature
+    #[default]
+    F32,
+    /// Double-precision filter arithmetic and state
+    F64,
+}
+
+/// Final limiter implementation
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimiterMode {
+    /// Single-band true-peak brick wall
+    BrickWall,
+    /// Per-band limiting ahead of the true-peak brick wall
+    Multiband,
+}
+
+/// Dither applied when reducing word length for 16-bit deliverables
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    /// Plain rounding/truncation
+    None,
+    /// Triangular PDF dither at +-1 LSB
+    #[default]
+    Tpdf,
+    /// TPDF dither with first-order noise shaping
+    NoiseShaped,
+}
+
+/// Loudness target of a master
+///
+/// Jobs name a target (`"streaming"`, `"broadcast_ebu"`...) or give the
+/// integrated loudness in LUFS (`-12.5`), and either is echoed back in
+/// results as it was given. Unknown names and levels outside `LUFS_RANGE`
+/// are rejected with the payload instead of falling back to `Medium`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoudnessTarget {
+    Low,    // -14 LUFS
+    Medium, // -11 LUFS
+    High,   // -8 LUFS
+    /// -14 LUFS, where the major streaming services normalize to
+    Streaming,
+    /// -6 LUFS, for DJ and club play
+    Club,
+    /// -23 LUFS (EBU R 128)
+    BroadcastEbu,
+    /// -24 LKFS (ATSC A/85)
+    BroadcastAtsc,
+    /// Integrated loudness in LUFS
+    Lufs(f64),
+}
+
+impl LoudnessTarget {
+    /// Levels a numeric target may ask for (LUFS)
+    pub const LUFS_RANGE: std::ops::RangeInclusive<f64> = -40.0..=-4.0;
+
+    const NAMED: [(&'static str, LoudnessTarget); 7] = [
+        ("low", Self::Low),
+        ("medium", Self::Medium),
+        ("high", Self::High),
+        ("streaming", Self::Streaming),
+        ("club", Self::Club),
+        ("broadcast_ebu", Self::BroadcastEbu),
+        ("broadcast_atsc", Self::BroadcastAtsc),
+    ];
+
+    pub fn lufs_value(&self) -> f64 {
+        match self {
+            Self::Low | Self::Streaming => -14.0,
+            Self::Medium => -11.0,
+            Self::High => -8.0,
+            Self::Club => -6.0,
+            Self::BroadcastEbu => -23.0,
+            Self::BroadcastAtsc => -24.0,
+            Self::Lufs(lufs) => *lufs,
+        }
+    }
+
+    /// The name jobs use, unless the target is numeric
+    pub fn name(&self) -> Option<&'static str> {
+        Self::NAMED
+            .iter()
+            .find(|(_, target)| target == self)
+            .map(|(name, _)| *name)
+    }
+}
+
+impl fmt::Display for LoudnessTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{} LUFS", self.lufs_value()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LoudnessTarget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TargetVisitor;
+
+        impl Visitor<'_> for TargetVisitor {
+            type Value = LoudnessTarget;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(
+                    "low, medium, high, streaming, club, broadcast_ebu, broadcast_atsc, \
+                     or a level in LUFS",
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<LoudnessTarget, E> {
+                LoudnessTarget::NAMED
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, target)| *target)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(name), &self))
+            }
+
+            fn visit_f64<E: de::Error>(self, lufs: f64) -> Result<LoudnessTarget, E> {
+                let range = LoudnessTarget::LUFS_RANGE;
+                if !range.contains(&lufs) {
+                    return Err(E::custom(format!(
+                        "loudness target {} LUFS is outside {} to {} LUFS",
+                        lufs,
+                        range.start(),
+                        range.end()
+                    )));
+                }
+                Ok(LoudnessTarget::Lufs(lufs))
+            }
+
+            fn visit_i64<E: de::Error>(self, lufs: i64) -> Result<LoudnessTarget, E> {
+                self.visit_f64(lufs as f64)
+            }
+
+            fn visit_u64<E: de::Error>(self, lufs: u64) -> Result<LoudnessTarget, E> {
+                self.visit_f64(lufs as f64)
+            }
+        }
+
+        deserializer.deserialize_any(TargetVisitor)
+    }
+}
+
+impl Serialize for LoudnessTarget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.name() {
+            Some(name) => serializer.serialize_str(name),
+            None => serializer.serialize_f64(self.lufs_value()),
+        }
+    }
+}
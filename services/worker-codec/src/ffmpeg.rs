@@ -0,0 +1,162 @@
+//! FFmpeg discovery and capability probing
+//!
+//! The binary comes from `[ffmpeg] path` in the worker config, or
+//! `FFMPEG_PATH` (default: `ffmpeg` on the `PATH`). It is probed once at
+//! startup for its version and audio encoders, so jobs asking for an encoder
+//! the build lacks fail before any work is done.
+
+use anyhow::{Context, Result};
+use budi_worker_core::Config;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Oldest FFmpeg major version with the muxer and encoder options we use
+const MIN_MAJOR_VERSION: u32 = 4;
+
+/// Stderr lines kept when FFmpeg fails
+const ERROR_LINES: usize = 3;
+
+static FFMPEG: OnceLock<Option<Ffmpeg>> = OnceLock::new();
+
+/// Config file section the codec worker adds
+pub const SECTION: &str = "ffmpeg";
+
+/// The `[ffmpeg]` config section
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// `FFMPEG_PATH`
+    pub path: Option<String>,
+}
+
+impl Settings {
+    pub fn load(config: &Config) -> Result<Self> {
+        let mut settings: Self = config.section(SECTION)?;
+        let mut path = settings.path.take().unwrap_or_default();
+        budi_worker_core::config::env_override(&mut path, "FFMPEG_PATH")?;
+        settings.path = Some(path).filter(|p| !p.is_empty());
+        Ok(settings)
+    }
+}
+
+/// A probed FFmpeg binary
+#[derive(Debug)]
+pub struct Ffmpeg {
+    pub path: String,
+    pub version: String,
+    /// Audio encoders this build was compiled with
+    pub encoders: BTreeSet<String>,
+}
+
+impl Ffmpeg {
+    pub fn has_encoder(&self, name: &str) -> bool {
+        self.encoders.contains(name)
+    }
+
+    /// A command running this binary
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.path);
+        command.arg("-hide_banner");
+        command
+    }
+}
+
+/// Probe FFmpeg and remember the result
+///
+/// A missing default `ffmpeg` isn't an error (the built-in encoders take
+/// over), but an unusable configured path or a too-old version is.
+pub fn init(settings: &Settings) -> Result<Option<&'static Ffmpeg>> {
+    let probed = probe(settings.path.as_deref())?;
+    Ok(FFMPEG.get_or_init(|| probed).as_ref())
+}
+
+/// The probed FFmpeg, if there is one
+pub fn get() -> Option<&'static Ffmpeg> {
+    FFMPEG.get_or_init(|| probe(None).ok().flatten()).as_ref()
+}
+
+fn probe(configured: Option<&str>) -> Result<Option<Ffmpeg>> {
+    let path = configured.unwrap_or("ffmpeg").to_string();
+
+    let output = match Command::new(&path)
+        .args(["-hide_banner", "-version"])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(_) | Err(_) if configured.is_none() => return Ok(None),
+        Ok(output) => anyhow::bail!(
+            "FFmpeg at {} failed to run: {}",
+            path,
+            error_summary(&output.stderr)
+        ),
+        Err(e) => anyhow::bail!("FFmpeg at {} failed to run: {}", path, e),
+    };
+
+    // "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) ..."
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("ffmpeg version "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .with_context(|| format!("{} doesn't look like FFmpeg", path))?
+        .to_string();
+    // Git builds ("N-113350-g...") have no release number to check
+    let major = version
+        .trim_start_matches('n')
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|major| major.parse::<u32>().ok());
+    if let Some(major) = major.filter(|m| *m < MIN_MAJOR_VERSION) {
+        anyhow::bail!(
+            "FFmpeg {} at {} is too old (need {} or newer, found major version {})",
+            version,
+            path,
+            MIN_MAJOR_VERSION,
+            major
+        );
+    }
+
+    let output = Command::new(&path)
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .context("Failed to list FFmpeg encoders")?;
+    let encoders = parse_encoders(&String::from_utf8_lossy(&output.stdout));
+
+    Ok(Some(Ffmpeg {
+        path,
+        version,
+        encoders,
+    }))
+}
+
+/// Audio encoder names from `ffmpeg -encoders`
+///
+/// Entries follow a legend ending in a `------` line and look like
+/// ` A....D aac                  AAC (Advanced Audio Coding)`.
+fn parse_encoders(listing: &str) -> BTreeSet<String> {
+    listing
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let flags = fields.next()?;
+            let name = fields.next()?;
+            flags.starts_with('A').then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// The last few lines of FFmpeg's stderr, where it puts the actual error
+pub fn error_summary(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(ERROR_LINES)..].join(" / ")
+}
@@ -0,0 +1,572 @@
+//! Gapless playback metadata for AAC and MP3 previews
+//!
+//! Lossy encoders prime the stream with silence and pad out the last frame.
+//! Players trim both using metadata in the file: the LAME/Info tag for MP3,
+//! and the `iTunSMPB` atom (alongside FFmpeg's edit list) for AAC in MP4.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::CodecFormat;
+
+/// MP3 decoders' own delay, which LAME tags leave out (samples)
+const MP3_DECODER_DELAY: u32 = 528 + 1;
+
+/// Samples a decoder should drop to recover the original signal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GaplessInfo {
+    /// Priming at the start of the decoded stream
+    pub delay: u32,
+    /// Padding at the end of the decoded stream
+    pub padding: u32,
+}
+
+/// Read the delay and padding of an encoded preview, writing `iTunSMPB`
+/// into AAC previews so Apple players trim them too
+///
+/// Returns `None` for codecs without gapless metadata, or when the encoder
+/// didn't leave any.
+pub fn apply(path: &Path, format: CodecFormat) -> Result<Option<GaplessInfo>> {
+    match format {
+        CodecFormat::Mp3 => Ok(read_lame_tag(&std::fs::read(path)?)),
+        CodecFormat::Aac | CodecFormat::HeAac | CodecFormat::HeAacV2 => tag_mp4(path),
+        _ => Ok(None),
+    }
+}
+
+/// An MPEG audio Layer III frame header
+struct FrameHeader {
+    mpeg1: bool,
+    mono: bool,
+    bitrate: u32,
+    len: usize,
+}
+
+impl FrameHeader {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        const MPEG1_KBPS: [u32; 15] = [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ];
+        const MPEG2_KBPS: [u32; 15] =
+            [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+        let [b0, b1, b2, b3] = *bytes.get(..4)? else {
+            return None;
+        };
+        // Sync word and Layer III
+        if b0 != 0xFF || b1 & 0xE0 != 0xE0 || (b1 >> 1) & 3 != 1 {
+            return None;
+        }
+        let version = (b1 >> 3) & 3;
+        let mpeg1 = match version {
+            3 => true,
+            0 | 2 => false,
+            _ => return None,
+        };
+        let bitrate_index = (b2 >> 4) as usize;
+        let rate_index = ((b2 >> 2) & 3) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+            return None;
+        }
+        let bitrate = if mpeg1 { MPEG1_KBPS } else { MPEG2_KBPS }[bitrate_index];
+        // MPEG-2 halves the MPEG-1 rates and MPEG-2.5 quarters them
+        let sample_rate = match version {
+            3 => [44100, 48000, 32000][rate_index],
+            2 => [22050, 24000, 16000][rate_index],
+            _ => [11025, 12000, 8000][rate_index],
+        };
+        let coefficient = if mpeg1 { 144_000 } else { 72_000 };
+        let len = (coefficient * bitrate / sample_rate + ((b2 >> 1) & 1) as u32) as usize;
+
+        Some(Self {
+            mpeg1,
+            mono: b3 >> 6 == 3,
+            bitrate,
+            len,
+        })
+    }
+
+    fn samples(&self) -> u32 {
+        if self.mpeg1 {
+            1152
+        } else {
+            576
+        }
+    }
+
+    /// Offset of the Xing/Info tag from the start of the frame
+    fn tag_offset(&self) -> usize {
+        4 + match (self.mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        }
+    }
+}
+
+/// Length of a leading ID3v2 tag, if any
+fn id3v2_len(data: &[u8]) -> usize {
+    match data.get(..10) {
+        Some(header) if &header[..3] == b"ID3" => {
+            let size = header[6..10]
+                .iter()
+                .fold(0usize, |size, b| (size << 7) | (*b & 0x7F) as usize);
+            10 + size + if header[5] & 0x10 != 0 { 10 } else { 0 }
+        }
+        _ => 0,
+    }
+}
+
+/// Delay and padding from the LAME extension of an MP3's Xing/Info frame
+fn read_lame_tag(data: &[u8]) -> Option<GaplessInfo> {
+    let start = id3v2_len(data);
+    let frame = data.get(start..)?;
+    let header = FrameHeader::parse(frame)?;
+    let frame = frame.get(..header.len)?;
+
+    let tag = header.tag_offset();
+    if !matches!(frame.get(tag..tag + 4)?, b"Xing" | b"Info") {
+        return None;
+    }
+    let flags = u32::from_be_bytes(frame.get(tag + 4..tag + 8)?.try_into().ok()?);
+    let fields = [(1, 4), (2, 4), (4, 100), (8, 4)]
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, len)| len)
+        .sum::<usize>();
+    let vendor = tag + 8 + fields;
+
+    // Encoders without the extension leave the encoder string out
+    let lame = frame.get(vendor..vendor + 24)?;
+    if !lame[..4].iter().all(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+    let delay = ((lame[21] as u32) << 4) | (lame[22] as u32 >> 4);
+    let padding = ((lame[22] as u32 & 0x0F) << 8) | lame[23] as u32;
+
+    Some(GaplessInfo {
+        delay: delay + MP3_DECODER_DELAY,
+        padding: padding.saturating_sub(MP3_DECODER_DELAY),
+    })
+}
+
+/// Fill the empty first frame LAME reserves in a raw encode with an
+/// Info/Xing frame carrying the encoder delay and padding
+///
+/// `delay` is LAME's encoder delay and `original_frames` the number of
+/// samples per channel that went in.
+#[cfg_attr(not(feature = "native-mp3"), allow(dead_code))]
+pub fn write_lame_tag(data: &mut [u8], delay: u32, original_frames: u64, vbr: bool) -> Result<()> {
+    let header = FrameHeader::parse(data).context("MP3 has no leading frame")?;
+    let tag = header.tag_offset();
+    let vendor = tag + 120;
+    if header.len < vendor + 36 || data[4..header.len].iter().any(|b| *b != 0) {
+        anyhow::bail!("MP3 has no reserved tag frame");
+    }
+
+    // Offsets of the audio frames that follow
+    let mut offsets = Vec::new();
+    let mut pos = header.len;
+    while let Some(frame) = data.get(pos..).and_then(FrameHeader::parse) {
+        offsets.push(pos);
+        pos += frame.len;
+    }
+    if offsets.is_empty() {
+        anyhow::bail!("MP3 has no audio frames");
+    }
+    let total_bytes = data.len() as u64;
+    let total_samples = offsets.len() as u64 * header.samples() as u64;
+    let padding = total_samples
+        .saturating_sub(delay as u64 + original_frames)
+        .min(0xFFF) as u32;
+
+    let frame = &mut data[..header.len];
+    frame[tag..tag + 4].copy_from_slice(if vbr { b"Xing" } else { b"Info" });
+    // Frame count, byte count, seek table and quality
+    frame[tag + 4..tag + 8].copy_from_slice(&0x0Fu32.to_be_bytes());
+    frame[tag + 8..tag + 12].copy_from_slice(&(offsets.len() as u32).to_be_bytes());
+    frame[tag + 12..tag + 16].copy_from_slice(&(total_bytes as u32).to_be_bytes());
+    for i in 0..100 {
+        let offset = offsets[i * offsets.len() / 100] as u64;
+        frame[tag + 16 + i] = (offset * 256 / total_bytes).min(255) as u8;
+    }
+
+    let lame = &mut frame[vendor..vendor + 36];
+    lame[..9].copy_from_slice(b"LAME3.100");
+    // Tag revision 0; CBR or VBR (mtrh)
+    lame[9] = if vbr { 4 } else { 1 };
+    lame[20] = header.bitrate.min(255) as u8;
+    lame[21] = (delay >> 4) as u8;
+    lame[22] = (((delay & 0x0F) << 4) | (padding >> 8)) as u8;
+    lame[23] = padding as u8;
+    lame[28..32].copy_from_slice(&(total_bytes as u32).to_be_bytes());
+
+    let music_crc = crc16(&data[header.len..]);
+    data[vendor + 32..vendor + 34].copy_from_slice(&music_crc.to_be_bytes());
+    let tag_crc = crc16(&data[..vendor + 34]);
+    data[vendor + 34..vendor + 36].copy_from_slice(&tag_crc.to_be_bytes());
+    Ok(())
+}
+
+/// CRC-16 as used by LAME tags (polynomial 0x8005, reflected)
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// A box inside an MP4 file or another box
+struct Mp4Box {
+    kind: [u8; 4],
+    start: usize,
+    body: usize,
+    end: usize,
+}
+
+/// Boxes directly inside `data`
+fn mp4_boxes(data: &[u8]) -> Result<Vec<Mp4Box>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into()?;
+        let (body, end) = match size {
+            0 => (pos + 8, data.len()),
+            1 => {
+                let large = data.get(pos + 8..pos + 16).context("Truncated MP4 box")?;
+                (
+                    pos + 16,
+                    pos + u64::from_be_bytes(large.try_into()?) as usize,
+                )
+            }
+            _ => (pos + 8, pos + size),
+        };
+        if end < body || end > data.len() {
+            anyhow::bail!("Truncated MP4 {} box", String::from_utf8_lossy(&kind));
+        }
+        boxes.push(Mp4Box {
+            kind,
+            start: pos,
+            body,
+            end,
+        });
+        pos = end;
+    }
+    Ok(boxes)
+}
+
+/// Body of the first `path` box below `data`
+fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Result<Option<&'a [u8]>> {
+    let Some((first, rest)) = path.split_first() else {
+        return Ok(Some(data));
+    };
+    match mp4_boxes(data)?.into_iter().find(|b| &b.kind == *first) {
+        Some(b) => find_box(&data[b.body..b.end], rest),
+        None => Ok(None),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(
+        data.get(offset..offset + 4)
+            .context("Truncated MP4 box")?
+            .try_into()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    Ok(u64::from_be_bytes(
+        data.get(offset..offset + 8)
+            .context("Truncated MP4 box")?
+            .try_into()?,
+    ))
+}
+
+/// Timescale from an `mvhd` or `mdhd` body
+fn timescale(header: &[u8]) -> Result<u64> {
+    let offset = if header.first() == Some(&1) { 20 } else { 12 };
+    Ok(read_u32(header, offset)? as u64)
+}
+
+/// Priming, padding and valid length of the audio track (media timescale)
+fn mp4_gapless(moov: &[u8]) -> Result<Option<(u64, u64, u64)>> {
+    let movie_scale = timescale(find_box(moov, &[b"mvhd"])?.context("MP4 has no mvhd")?)?;
+    let Some(trak) = find_box(moov, &[b"trak"])? else {
+        return Ok(None);
+    };
+    let (Some(elst), Some(mdhd), Some(stts)) = (
+        find_box(trak, &[b"edts", b"elst"])?,
+        find_box(trak, &[b"mdia", b"mdhd"])?,
+        find_box(trak, &[b"mdia", b"minf", b"stbl", b"stts"])?,
+    ) else {
+        return Ok(None);
+    };
+    let media_scale = timescale(mdhd)?;
+    if movie_scale == 0 || media_scale == 0 {
+        return Ok(None);
+    }
+
+    // The first non-empty edit starts after the priming
+    let wide = elst.first() == Some(&1);
+    let entry_len = if wide { 20 } else { 12 };
+    let mut delay = None;
+    let mut valid = 0;
+    for i in 0..read_u32(elst, 4)? as usize {
+        let entry = 8 + i * entry_len;
+        let (duration, media_time) = if wide {
+            (read_u64(elst, entry)?, read_u64(elst, entry + 8)? as i64)
+        } else {
+            (
+                read_u32(elst, entry)? as u64,
+                read_u32(elst, entry + 4)? as i32 as i64,
+            )
+        };
+        if media_time < 0 {
+            continue;
+        }
+        delay.get_or_insert(media_time as u64);
+        valid += duration * media_scale / movie_scale;
+    }
+    let Some(delay) = delay else {
+        return Ok(None);
+    };
+
+    let mut total = 0u64;
+    for i in 0..read_u32(stts, 4)? as usize {
+        let entry = 8 + i * 8;
+        total += read_u32(stts, entry)? as u64 * read_u32(stts, entry + 4)? as u64;
+    }
+    let padding = total.saturating_sub(delay + valid);
+    Ok(Some((delay, padding, valid)))
+}
+
+/// Read FFmpeg's edit list and add a matching `iTunSMPB` atom
+fn tag_mp4(path: &Path) -> Result<Option<GaplessInfo>> {
+    let mut data = std::fs::read(path)?;
+    let top = mp4_boxes(&data)?;
+    let Some(moov) = top.iter().find(|b| &b.kind == b"moov") else {
+        anyhow::bail!("MP4 preview has no moov box");
+    };
+    let Some((delay, padding, valid)) = mp4_gapless(&data[moov.body..moov.end])? else {
+        return Ok(None);
+    };
+    let info = GaplessInfo {
+        delay: delay as u32,
+        padding: padding as u32,
+    };
+
+    // Growing moov would shift sample offsets unless nothing follows it
+    if moov.end != data.len() || moov.body != moov.start + 8 {
+        return Ok(Some(info));
+    }
+
+    let smpb = format!(
+        " 00000000 {:08X} {:08X} {:016X} 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000",
+        delay, padding, valid
+    );
+    let mut data_atom = vec![0, 0, 0, 1, 0, 0, 0, 0];
+    data_atom.extend_from_slice(smpb.as_bytes());
+    let freeform = mp4_box(
+        b"----",
+        &[
+            mp4_box(b"mean", &[&[0u8; 4][..], b"com.apple.iTunes"].concat()),
+            mp4_box(b"name", &[&[0u8; 4][..], b"iTunSMPB"].concat()),
+            mp4_box(b"data", &data_atom),
+        ]
+        .concat(),
+    );
+
+    let moov_body = append_child(&data[moov.body..], &[b"udta", b"meta", b"ilst"], &freeform)?;
+    let moov_start = moov.start;
+    data.truncate(moov_start);
+    data.extend_from_slice(&mp4_box(b"moov", &moov_body));
+    std::fs::write(path, data)?;
+
+    Ok(Some(info))
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// `contents` with `child` appended to the box at `path`, creating any
+/// missing boxes along the way
+fn append_child(contents: &[u8], path: &[&[u8; 4]], child: &[u8]) -> Result<Vec<u8>> {
+    let Some((first, rest)) = path.split_first() else {
+        return Ok([contents, child].concat());
+    };
+    // meta is a full box: version and flags come before its children
+    let prefix = if *first == b"meta" { 4 } else { 0 };
+
+    let mut out = Vec::with_capacity(contents.len() + child.len() + 64);
+    let mut found = false;
+    for b in mp4_boxes(contents)? {
+        if &b.kind == *first && !found && b.body == b.start + 8 {
+            found = true;
+            let body = &contents[b.body..b.end];
+            let head = body.get(..prefix).context("Truncated MP4 meta box")?;
+            let inner = append_child(&body[prefix..], rest, child)?;
+            out.extend_from_slice(&mp4_box(first, &[head, &inner].concat()));
+        } else {
+            out.extend_from_slice(&contents[b.start..b.end]);
+        }
+    }
+    if !found {
+        let mut body = vec![0u8; prefix];
+        if *first == b"meta" {
+            // iTunes metadata handler
+            let mut hdlr = vec![0u8; 8];
+            hdlr.extend_from_slice(b"mdirappl");
+            hdlr.extend_from_slice(&[0u8; 9]);
+            body.extend_from_slice(&mp4_box(b"hdlr", &hdlr));
+        }
+        body.extend_from_slice(&append_child(&[], rest, child)?);
+        out.extend_from_slice(&mp4_box(first, &body));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MPEG-1 Layer III, 128 kbps, 44.1 kHz, stereo: 417-byte frames
+    const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+    const FRAME_LEN: usize = 417;
+
+    /// A raw encode: an empty reserved frame, then `frames` audio frames
+    fn raw_mp3(frames: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..=frames {
+            let mut frame = vec![if i == 0 { 0 } else { 0x55 }; FRAME_LEN];
+            frame[..4].copy_from_slice(&HEADER);
+            data.extend_from_slice(&frame);
+        }
+        data
+    }
+
+    #[test]
+    fn test_lame_tag_round_trip() {
+        let mut data = raw_mp3(10);
+        // 11520 samples encoded, 576 of priming and 10000 of audio
+        write_lame_tag(&mut data, 576, 10_000, false).unwrap();
+        assert_eq!(&data[36..40], b"Info");
+        assert_eq!(&data[156..165], b"LAME3.100");
+
+        let info = read_lame_tag(&data).unwrap();
+        assert_eq!(info.delay, 576 + MP3_DECODER_DELAY);
+        assert_eq!(info.padding, 11_520 - 576 - 10_000 - MP3_DECODER_DELAY);
+
+        // Behind an empty ID3v2 tag
+        let tagged = [&b"ID3\x04\x00\x00\x00\x00\x00\x00"[..], &data].concat();
+        assert_eq!(read_lame_tag(&tagged), Some(info));
+    }
+
+    #[test]
+    fn test_write_lame_tag_vbr() {
+        let mut data = raw_mp3(4);
+        write_lame_tag(&mut data, 576, 4_000, true).unwrap();
+        assert_eq!(&data[36..40], b"Xing");
+        assert_eq!(u32::from_be_bytes(data[44..48].try_into().unwrap()), 4);
+    }
+
+    #[test]
+    fn test_lame_tag_missing() {
+        // No tag frame, or no MP3 at all
+        assert_eq!(read_lame_tag(&raw_mp3(3)), None);
+        assert_eq!(read_lame_tag(b"not an mp3"), None);
+
+        let mut data = raw_mp3(3);
+        data[100] = 1;
+        let err = write_lame_tag(&mut data, 576, 1_000, false).unwrap_err();
+        assert!(err.to_string().contains("no reserved tag frame"));
+        let err = write_lame_tag(&mut raw_mp3(0), 576, 1_000, false).unwrap_err();
+        assert!(err.to_string().contains("no audio frames"));
+    }
+
+    fn kinds(data: &[u8]) -> Vec<[u8; 4]> {
+        mp4_boxes(data).unwrap().iter().map(|b| b.kind).collect()
+    }
+
+    #[test]
+    fn test_mp4_boxes() {
+        let data = [
+            mp4_box(b"ftyp", b"M4A "),
+            mp4_box(b"moov", &mp4_box(b"mvhd", &[0; 4])),
+        ]
+        .concat();
+        assert_eq!(kinds(&data), vec![*b"ftyp", *b"moov"]);
+        assert_eq!(
+            find_box(&data, &[b"moov", b"mvhd"]).unwrap(),
+            Some(&[0u8; 4][..])
+        );
+        assert_eq!(find_box(&data, &[b"moov", b"trak"]).unwrap(), None);
+
+        // A 64-bit size, and a size of 0 running to the end
+        let mut large = vec![0, 0, 0, 1];
+        large.extend_from_slice(b"mdat");
+        large.extend_from_slice(&20u64.to_be_bytes());
+        large.extend_from_slice(&[7; 4]);
+        let open = [&[0u8, 0, 0, 0][..], b"free", &[1, 2, 3]].concat();
+        let boxes = mp4_boxes(&[large, open].concat()).unwrap();
+        assert_eq!((boxes[0].body, boxes[0].end), (16, 20));
+        assert_eq!((boxes[1].body, boxes[1].end), (28, 31));
+    }
+
+    #[test]
+    fn test_mp4_boxes_truncated() {
+        let mut data = mp4_box(b"moov", &[0; 16]);
+        data.truncate(12);
+        let err = mp4_boxes(&data).err().unwrap();
+        assert_eq!(err.to_string(), "Truncated MP4 moov box");
+
+        let err = mp4_boxes(&[0, 0, 0, 1, b'm', b'd', b'a', b't', 0])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Truncated MP4 box");
+    }
+
+    #[test]
+    fn test_append_child_to_existing_boxes() {
+        let ilst = mp4_box(b"ilst", &mp4_box(b"\xA9nam", b"x"));
+        let meta = mp4_box(b"meta", &[&[0u8; 4][..], &ilst].concat());
+        let moov = [mp4_box(b"mvhd", &[0; 4]), mp4_box(b"udta", &meta)].concat();
+        let child = mp4_box(b"----", b"tag");
+
+        let out = append_child(&moov, &[b"udta", b"meta", b"ilst"], &child).unwrap();
+        assert_eq!(kinds(&out), vec![*b"mvhd", *b"udta"]);
+        let meta = find_box(&out, &[b"udta", b"meta"]).unwrap().unwrap();
+        // Past meta's version and flags
+        let ilst = find_box(&meta[4..], &[b"ilst"]).unwrap().unwrap();
+        assert_eq!(kinds(ilst), vec![*b"\xA9nam", *b"----"]);
+    }
+
+    #[test]
+    fn test_append_child_creates_boxes() {
+        let child = mp4_box(b"----", b"tag");
+        let out = append_child(&[], &[b"udta", b"meta", b"ilst"], &child).unwrap();
+        let meta = find_box(&out, &[b"udta", b"meta"]).unwrap().unwrap();
+        assert_eq!(kinds(&meta[4..]), vec![*b"hdlr", *b"ilst"]);
+        let hdlr = find_box(&meta[4..], &[b"hdlr"]).unwrap().unwrap();
+        assert_eq!(&hdlr[8..16], b"mdirappl");
+        assert_eq!(find_box(&meta[4..], &[b"ilst"]).unwrap(), Some(&child[..]));
+
+        // A meta box too short for its version and flags
+        let udta = mp4_box(b"udta", &mp4_box(b"meta", &[0; 2]));
+        let err = append_child(&udta, &[b"udta", b"meta", b"ilst"], &child).unwrap_err();
+        assert_eq!(err.to_string(), "Truncated MP4 meta box");
+    }
+}
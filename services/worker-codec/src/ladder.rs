@@ -0,0 +1,414 @@
+//! Streaming preview ladders
+//!
+//! Encodes the master into several AAC/Opus renditions cut into fMP4
+//! segments, with an HLS master playlist or a DASH manifest on top, so the
+//! web player can switch between renditions instead of downloading each
+//! preview in full.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{parse_codec, CodecFormat, CodecSpec, RateControl};
+
+/// Renditions encoded when a ladder job doesn't list any
+pub const DEFAULT_LADDER: [&str; 3] = ["aac-64", "aac-128", "aac-256"];
+
+/// Media segment length when a ladder job doesn't give one (seconds)
+pub const DEFAULT_MEDIA_SEGMENT_SECONDS: f64 = 6.0;
+
+/// Manifest flavour of a streaming ladder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamFormat {
+    #[default]
+    Hls,
+    Dash,
+}
+
+impl StreamFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Hls => "hls",
+            Self::Dash => "dash",
+        }
+    }
+
+    /// Top-level manifest, relative to the ladder directory
+    pub fn manifest_name(&self) -> &'static str {
+        match self {
+            Self::Hls => "master.m3u8",
+            Self::Dash => "manifest.mpd",
+        }
+    }
+}
+
+/// One encoded rendition of a ladder
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rendition {
+    pub codec: String,
+    /// RFC 6381 codec string, as advertised in the manifest
+    pub codecs: &'static str,
+    /// Peak segment bitrate (bits/s)
+    pub bandwidth: u64,
+    /// Average bitrate over the whole rendition (bits/s)
+    pub average_bandwidth: u64,
+    /// Media playlist, relative to the ladder directory (HLS only)
+    #[serde(skip)]
+    pub playlist: Option<String>,
+    /// Uploaded media playlist, its key and SHA-256 (HLS only)
+    pub playlist_url: Option<String>,
+    pub playlist_key: Option<String>,
+    pub playlist_sha256: Option<String>,
+}
+
+/// Encode `input` into a ladder under `out_dir`
+///
+/// `duration_secs` is the length of `input`, used to measure bandwidth.
+#[tracing::instrument(name = "codec.ladder", skip_all)]
+pub fn build(
+    input: &Path,
+    out_dir: &Path,
+    format: StreamFormat,
+    codecs: &[String],
+    segment_seconds: f64,
+    duration_secs: f64,
+) -> Result<Vec<Rendition>> {
+    let specs = validate(codecs, segment_seconds)?;
+
+    match format {
+        StreamFormat::Hls => build_hls(
+            input,
+            out_dir,
+            codecs,
+            &specs,
+            segment_seconds,
+            duration_secs,
+        ),
+        StreamFormat::Dash => build_dash(
+            input,
+            out_dir,
+            codecs,
+            &specs,
+            segment_seconds,
+            duration_secs,
+        ),
+    }
+}
+
+/// Check a ladder's renditions and segment length, returning each
+/// rendition's spec and codec string
+pub fn validate(codecs: &[String], segment_seconds: f64) -> Result<Vec<(CodecSpec, &'static str)>> {
+    if codecs.is_empty() {
+        anyhow::bail!("Streaming ladder has no renditions");
+    }
+    if !(1.0..=30.0).contains(&segment_seconds) {
+        anyhow::bail!("Segment length {} s out of range (1-30 s)", segment_seconds);
+    }
+    codecs
+        .iter()
+        .map(|codec| {
+            let spec = parse_codec(codec)?;
+            codec_string(&spec).map(|tag| (spec, tag))
+        })
+        .collect()
+}
+
+/// RFC 6381 codec string for a spec that can go in an fMP4 segment
+fn codec_string(spec: &CodecSpec) -> Result<&'static str> {
+    Ok(match spec.format {
+        CodecFormat::Aac => "mp4a.40.2",
+        CodecFormat::HeAac => "mp4a.40.5",
+        CodecFormat::HeAacV2 => "mp4a.40.29",
+        CodecFormat::Opus if !matches!(spec.rate, RateControl::Quality(_)) => "opus",
+        _ => anyhow::bail!(
+            "{:?} can't be used in a streaming ladder (AAC, HE-AAC or Opus only)",
+            spec.format
+        ),
+    })
+}
+
+/// One media playlist per rendition, tied together by a master playlist
+fn build_hls(
+    input: &Path,
+    out_dir: &Path,
+    codecs: &[String],
+    specs: &[(CodecSpec, &'static str)],
+    segment_seconds: f64,
+    duration_secs: f64,
+) -> Result<Vec<Rendition>> {
+    let mut renditions = Vec::with_capacity(specs.len());
+
+    for (i, (codec, (spec, tag))) in codecs.iter().zip(specs).enumerate() {
+        let dir_name = format!("{}_{}", i, codec);
+        let dir = out_dir.join(&dir_name);
+        std::fs::create_dir_all(&dir)?;
+
+        let mut args = vec!["-i".to_string(), path_arg(input)?, "-vn".into()];
+        args.extend(spec.ffmpeg_args());
+        args.extend(
+            [
+                "-strict",
+                "experimental",
+                "-f",
+                "hls",
+                "-hls_playlist_type",
+                "vod",
+                "-hls_segment_type",
+                "fmp4",
+                "-hls_fmp4_init_filename",
+                "init.mp4",
+                "-hls_time",
+            ]
+            .map(String::from),
+        );
+        args.push(segment_seconds.to_string());
+        args.push("-hls_segment_filename".into());
+        args.push(path_arg(&dir.join("seg_%05d.m4s"))?);
+        args.push("-y".into());
+        args.push(path_arg(&dir.join("playlist.m3u8"))?);
+        run_ffmpeg(&args)?;
+
+        let (bandwidth, average_bandwidth) = measure(
+            &dir,
+            "seg_",
+            Some("init.mp4"),
+            segment_seconds,
+            duration_secs,
+        )?;
+        renditions.push(Rendition {
+            codec: codec.clone(),
+            codecs: tag,
+            bandwidth,
+            average_bandwidth,
+            playlist: Some(format!("{}/playlist.m3u8", dir_name)),
+            playlist_url: None,
+            playlist_key: None,
+            playlist_sha256: None,
+        });
+    }
+
+    let mut master = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    for rendition in &renditions {
+        master.push_str(&format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},AVERAGE-BANDWIDTH={},CODECS=\"{}\"\n{}\n",
+            rendition.bandwidth,
+            rendition.average_bandwidth,
+            rendition.codecs,
+            rendition.playlist.as_deref().unwrap_or_default()
+        ));
+    }
+    std::fs::write(out_dir.join(StreamFormat::Hls.manifest_name()), master)?;
+
+    Ok(renditions)
+}
+
+/// All renditions in one FFmpeg run, one adaptation set per codec
+fn build_dash(
+    input: &Path,
+    out_dir: &Path,
+    codecs: &[String],
+    specs: &[(CodecSpec, &'static str)],
+    segment_seconds: f64,
+    duration_secs: f64,
+) -> Result<Vec<Rendition>> {
+    let mut args = vec!["-i".to_string(), path_arg(input)?, "-vn".into()];
+    for _ in specs {
+        args.extend(["-map", "0:a:0"].map(String::from));
+    }
+    for (i, (spec, _)) in specs.iter().enumerate() {
+        args.extend(stream_args(spec, i));
+    }
+
+    // Players can only switch seamlessly within an adaptation set
+    let mut sets: Vec<(&str, Vec<String>)> = Vec::new();
+    for (i, (_, tag)) in specs.iter().enumerate() {
+        match sets.iter_mut().find(|(t, _)| t == tag) {
+            Some((_, streams)) => streams.push(i.to_string()),
+            None => sets.push((tag, vec![i.to_string()])),
+        }
+    }
+    let adaptation_sets = sets
+        .iter()
+        .enumerate()
+        .map(|(id, (_, streams))| format!("id={},streams={}", id, streams.join(",")))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    args.extend(
+        [
+            "-strict",
+            "experimental",
+            "-f",
+            "dash",
+            "-use_template",
+            "1",
+            "-use_timeline",
+            "0",
+            "-init_seg_name",
+            "init-$RepresentationID$.m4s",
+            "-media_seg_name",
+            "chunk-$RepresentationID$-$Number%05d$.m4s",
+            "-adaptation_sets",
+        ]
+        .map(String::from),
+    );
+    args.push(adaptation_sets);
+    args.push("-seg_duration".into());
+    args.push(segment_seconds.to_string());
+    args.push("-y".into());
+    args.push(path_arg(&out_dir.join(StreamFormat::Dash.manifest_name()))?);
+    run_ffmpeg(&args)?;
+
+    codecs
+        .iter()
+        .zip(specs)
+        .enumerate()
+        .map(|(i, (codec, (_, tag)))| {
+            let init = format!("init-{}.m4s", i);
+            let (bandwidth, average_bandwidth) = measure(
+                out_dir,
+                &format!("chunk-{}-", i),
+                Some(&init),
+                segment_seconds,
+                duration_secs,
+            )?;
+            Ok(Rendition {
+                codec: codec.clone(),
+                codecs: tag,
+                bandwidth,
+                average_bandwidth,
+                playlist: None,
+                playlist_url: None,
+                playlist_key: None,
+                playlist_sha256: None,
+            })
+        })
+        .collect()
+}
+
+/// `spec`'s codec arguments scoped to output stream `index`
+///
+/// "-c:a aac -b:a 128k" becomes "-c:a:1 aac -b:a:1 128k"; private encoder
+/// options like "-vbr" get a full audio stream specifier.
+fn stream_args(spec: &CodecSpec, index: usize) -> Vec<String> {
+    spec.ffmpeg_args()
+        .into_iter()
+        .map(|arg| match arg.strip_prefix('-') {
+            Some(option) if option.contains(':') => format!("-{}:{}", option, index),
+            Some(option) => format!("-{}:a:{}", option, index),
+            None => arg,
+        })
+        .collect()
+}
+
+/// Peak and average bitrate of a rendition from its segment files
+fn measure(
+    dir: &Path,
+    segment_prefix: &str,
+    init: Option<&str>,
+    segment_seconds: f64,
+    duration_secs: f64,
+) -> Result<(u64, u64)> {
+    let mut total = init
+        .map(|name| std::fs::metadata(dir.join(name)).map(|m| m.len()))
+        .transpose()?
+        .unwrap_or(0);
+    let mut largest = 0;
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(segment_prefix)
+        {
+            continue;
+        }
+        let size = entry.metadata()?.len();
+        total += size;
+        largest = largest.max(size);
+        count += 1;
+    }
+    if count == 0 {
+        anyhow::bail!("FFmpeg wrote no {}* segments", segment_prefix);
+    }
+
+    let average = (total as f64 * 8.0 / duration_secs.max(f64::EPSILON)) as u64;
+    let peak = ((largest as f64 * 8.0 / segment_seconds) as u64).max(average);
+    Ok((peak, average))
+}
+
+/// Every file under `dir`, for upload
+pub fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// MIME type of a ladder file, by extension
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("mpd") => "application/dash+xml",
+        _ => "audio/mp4",
+    }
+}
+
+fn path_arg(path: &Path) -> Result<String> {
+    path.to_str()
+        .map(String::from)
+        .with_context(|| format!("Non-UTF-8 path: {}", path.display()))
+}
+
+fn run_ffmpeg(args: &[String]) -> Result<()> {
+    let output = crate::ffmpeg::get()
+        .context("FFmpeg is not installed")?
+        .command()
+        .args(args)
+        .output()
+        .context("Failed to run FFmpeg")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "FFmpeg segmenting failed: {}",
+            crate::ffmpeg::error_summary(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_args() {
+        let cases: &[(&str, usize, &str)] = &[
+            ("aac-128", 0, "-c:a:0 aac -b:a:0 128k"),
+            (
+                "aac-he-48",
+                1,
+                "-c:a:1 libfdk_aac -profile:a:1 aac_he -b:a:1 48k",
+            ),
+            ("aac-q4", 2, "-c:a:2 libfdk_aac -vbr:a:2 4"),
+            ("opus-vbr-64", 1, "-c:a:1 libopus -vbr:a:1 on -b:a:1 64k"),
+            ("mp3-v2", 3, "-c:a:3 libmp3lame -q:a:3 2"),
+        ];
+        for (codec, index, expected) in cases {
+            let spec = parse_codec(codec).unwrap();
+            assert_eq!(stream_args(&spec, *index).join(" "), *expected, "{}", codec);
+        }
+    }
+}
@@ -1,35 +1,43 @@
 //! Budi Codec Preview Worker - Audio codec quality testing
 //!
 //! This worker processes codec preview jobs:
-//! - Transcodes audio to various lossy codecs (AAC, MP3, Opus)
+//! - Transcodes audio to various lossy codecs (AAC, HE-AAC, MP3, Opus, Vorbis,
+//!   AC-3, E-AC-3), with built-in MP3/Opus encoders when FFmpeg is missing
 //! - Measures true peak after encode/decode cycle
 //! - Calculates artifact score to estimate quality loss
 //! - Detects potential clipping risk
+//! - Measures how much the stereo image narrows or widens
+//! - Optionally encodes only a segment (given, or the loudest 30 seconds)
+//! - Sweeps a codec across bitrates and recommends the lowest acceptable one
+//! - Reports and writes gapless metadata (encoder delay/padding) for AAC/MP3
+//! - Builds segmented HLS/DASH preview ladders for adaptive streaming
+//! - Cuts store-style preview clips from the loudest section, on onsets, with
+//!   fades
+//! - Reports codec previews with a `schemaVersion`, against the JSON Schema
+//!   published in `packages/contracts/schemas`
 
 use anyhow::{Context, Result};
-use aws_sdk_s3::{
-    config::{Credentials, Region},
-    primitives::ByteStream,
-    Client,
+use budi_worker_core::align::align;
+use budi_worker_core::audio::{read_audio_cached, read_audio_file, write_wav_f32};
+use budi_worker_core::result_schema;
+use budi_worker_core::schema::{JsonSchema, ResultSchema};
+use budi_worker_core::{
+    refused, temp, true_peak, AudioBuffer, Config, QueueJob, Refused, Storage, Uploaded,
+    WebhookClient,
 };
-use bytes::Bytes;
-use redis::AsyncCommands;
-use reqwest::Client as HttpClient;
-use rubato::{FftFixedIn, Resampler};
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::path::Path;
-use std::process::Command;
-use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use tempfile::TempDir;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-use tracing::{error, info, warn};
+use tracing::{info, warn};
+
+mod ffmpeg;
+mod gapless;
+mod ladder;
+mod native;
+mod preview;
+
+use ladder::StreamFormat;
 
 /// Job definition for codec preview
 #[derive(Debug, Clone, Deserialize)]
@@ -43,131 +51,461 @@ enum Job {
         track_id: String,
         #[serde(rename = "masterUrl")]
         master_url: String,
+        /// Expected SHA-256 of the master, checked after download
+        #[serde(rename = "masterSha256", default)]
+        master_sha256: Option<String>,
+        codecs: Vec<String>,
+        /// Encode only this window of the master
+        #[serde(default)]
+        segment: Option<PreviewSegment>,
+        /// Also upload each codec's boosted difference signal
+        #[serde(rename = "nullTest", default)]
+        null_test: bool,
+    },
+    /// Encode one codec at several bitrates and recommend the lowest acceptable one
+    #[serde(rename = "codec-sweep")]
+    CodecSweep {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "masterUrl")]
+        master_url: String,
+        #[serde(rename = "masterSha256", default)]
+        master_sha256: Option<String>,
+        /// Codec without its bitrate, e.g. "opus" or "opus-vbr"
+        codec: String,
+        /// Bitrates to try (kbps); defaults to a standard ladder
+        #[serde(default)]
+        bitrates: Vec<u32>,
+        /// Highest acceptable artifact score for the recommendation
+        #[serde(rename = "maxArtifactScore", default)]
+        max_artifact_score: Option<f64>,
+        #[serde(default)]
+        segment: Option<PreviewSegment>,
+    },
+    /// Encode a segmented rendition ladder the web player can stream
+    #[serde(rename = "streaming-ladder")]
+    StreamingLadder {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "masterUrl")]
+        master_url: String,
+        #[serde(rename = "masterSha256", default)]
+        master_sha256: Option<String>,
+        #[serde(default)]
+        format: StreamFormat,
+        /// AAC/Opus codec strings, one per rendition; defaults to an AAC ladder
+        #[serde(default)]
+        renditions: Vec<String>,
+        /// Media segment length (seconds)
+        #[serde(rename = "segmentSeconds", default)]
+        segment_seconds: Option<f64>,
+        #[serde(default)]
+        segment: Option<PreviewSegment>,
+    },
+    /// Cut, fade and encode a short clip for store-style previews
+    #[serde(rename = "preview-clip")]
+    PreviewClip {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "masterUrl")]
+        master_url: String,
+        #[serde(rename = "masterSha256", default)]
+        master_sha256: Option<String>,
+        /// Codec strings to encode the clip with; defaults to MP3 at 192 kbps
+        #[serde(default)]
         codecs: Vec<String>,
+        /// Clip length (seconds, default 30)
+        #[serde(rename = "durationSeconds", default)]
+        duration_seconds: Option<f64>,
+        /// Start of the clip (seconds); picked from the track when absent
+        #[serde(rename = "startSeconds", default)]
+        start_seconds: Option<f64>,
+        /// Fade lengths (seconds, default 0.05 in and 1.5 out)
+        #[serde(rename = "fadeInSeconds", default)]
+        fade_in_seconds: Option<f64>,
+        #[serde(rename = "fadeOutSeconds", default)]
+        fade_out_seconds: Option<f64>,
     },
 }
 
-/// Codec preview result
-#[derive(Debug, Clone, Serialize)]
-struct CodecPreviewResult {
-    codec: String,
-    preview_url: String,
-    true_peak_after: f64,
-    artifact_score: f64,
-    clipping_risk: bool,
+/// Window of the master a preview is encoded from
+///
+/// Without a start, the loudest `durationSeconds` of the track is used.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PreviewSegment {
+    #[serde(default)]
+    start_seconds: Option<f64>,
+    #[serde(default)]
+    duration_seconds: Option<f64>,
 }
 
-/// Audio buffer for processing
-struct AudioBuffer {
-    samples: Vec<Vec<f32>>,
-    sample_rate: u32,
-    channels: usize,
+/// Preview length when a segment doesn't give one (seconds)
+const DEFAULT_SEGMENT_SECONDS: f64 = 30.0;
+
+/// Block size for finding the loudest segment (seconds)
+const SEGMENT_BLOCK_SECONDS: f64 = 0.5;
+
+/// Bitrates tried when a sweep doesn't specify any (kbps)
+const DEFAULT_SWEEP_BITRATES: [u32; 9] = [32, 48, 64, 96, 128, 160, 192, 256, 320];
+
+/// Default quality threshold for sweep recommendations (SNR of about 24 dB)
+const DEFAULT_MAX_ARTIFACT_SCORE: f64 = 60.0;
+
+/// Longest null-test clip, taken from where the difference is loudest (seconds)
+const NULL_TEST_SECONDS: f64 = 10.0;
+
+/// Boost applied to the difference signal so it's audible (dB)
+const NULL_TEST_GAIN_DB: f64 = 20.0;
+
+/// Highest peak the boosted difference may reach (dBFS)
+const NULL_TEST_CEILING_DB: f64 = -1.0;
+
+result_schema! {
+    /// Codec preview result
+    #[derive(Debug, Clone, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct CodecPreviewResult {
+        codec: String,
+        preview_url: String,
+        preview_key: String,
+        preview_sha256: String,
+        true_peak_after: f64,
+        artifact_score: f64,
+        clipping_risk: bool,
+        /// Oversampled samples above 0 dBFS after decoding
+        inter_sample_overs: usize,
+        /// Gain to apply before encoding so the decoded signal never exceeds
+        /// 0 dBFS (dB, 0 when there are no overs)
+        suggested_pre_gain_db: f64,
+        /// Stereo image after the round-trip and its change from the original
+        /// (stereo sources only)
+        stereo_correlation_after: Option<f64>,
+        stereo_correlation_delta: Option<f64>,
+        stereo_width_after: Option<f64>,
+        stereo_width_delta: Option<f64>,
+        /// Priming and end padding, in decoded samples, from the preview's
+        /// gapless metadata (AAC/MP3 only)
+        encoder_delay: Option<u32>,
+        encoder_padding: Option<u32>,
+        /// Original minus decoded, time-aligned and boosted by
+        /// `null_test_gain_db` (only when a null test was requested)
+        null_test_url: Option<String>,
+        null_test_key: Option<String>,
+        null_test_sha256: Option<String>,
+        null_test_gain_db: Option<f64>,
+    }
 }
 
-impl AudioBuffer {
-    fn frame_count(&self) -> usize {
-        if self.samples.is_empty() {
-            0
-        } else {
-            self.samples[0].len()
+result_schema! {
+    /// `data` of a codec preview result
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct CodecPreviewData<'a> {
+        master_sha256: &'a str,
+        previews: &'a [CodecPreviewResult],
+        segment: Option<EncodedSegment>,
+    }
+}
+
+result_schema! {
+    /// Window of the master the previews were encoded from
+    #[derive(Debug, Clone, Copy, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct EncodedSegment {
+        start_seconds: f64,
+        duration_seconds: f64,
+    }
+}
+
+/// Codec preview results, posted to `codec-preview`
+const CODEC_PREVIEW_RESULT: ResultSchema = ResultSchema {
+    job_type: "codec-preview",
+    endpoint: "codec-preview",
+    version: 1,
+    data: <CodecPreviewData<'static>>::json_schema,
+};
+
+/// Stereo image measurements
+#[derive(Debug, Clone, Copy)]
+struct StereoImage {
+    /// Pearson correlation of left and right (-1 to 1)
+    correlation: f64,
+    /// Side energy as a fraction of total mid + side energy (0-1)
+    width: f64,
+}
+
+impl QueueJob for Job {
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn job_id(&self) -> &str {
+        match self {
+            Job::CodecPreview { job_id, .. } => job_id,
+            Job::CodecSweep { job_id, .. } => job_id,
+            Job::StreamingLadder { job_id, .. } => job_id,
+            Job::PreviewClip { job_id, .. } => job_id,
+        }
+    }
+
+    fn job_type(&self) -> &'static str {
+        match self {
+            Job::CodecPreview { .. } => "codec-preview",
+            Job::CodecSweep { .. } => "codec-sweep",
+            Job::StreamingLadder { .. } => "streaming-ladder",
+            Job::PreviewClip { .. } => "preview-clip",
+        }
+    }
+
+    fn endpoint_for(payload_type: &str) -> Option<&'static str> {
+        match payload_type {
+            "codec-preview" => Some("codec-preview"),
+            "codec-sweep" => Some("codec-sweep"),
+            "streaming-ladder" => Some("streaming-ladder"),
+            "preview-clip" => Some("preview-clip"),
+            _ => None,
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("worker_codec=info".parse()?)
-                .add_directive("warn".parse()?),
-        )
-        .init();
+    let config = Config::load("CODEC_QUEUE", "codec-jobs", &[ffmpeg::SECTION])?;
+    let ffmpeg_settings = ffmpeg::Settings::load(&config)?;
+
+    // `--status` prints what this install can encode and exits, for health checks
+    if std::env::args().any(|arg| arg == "--status") {
+        let status = capabilities_json(ffmpeg::init(&ffmpeg_settings)?);
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        let usable = status["codecs"]
+            .as_array()
+            .is_some_and(|codecs| codecs.iter().any(|c| c["available"] == true));
+        std::process::exit(if usable { 0 } else { 1 });
+    }
+
+    budi_worker_core::init_tracing("worker_codec")?;
 
     info!("Budi Codec Preview Worker starting...");
 
-    // Connect to Redis
-    let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string());
-    let client = redis::Client::open(redis_url)?;
-    let mut conn = client.get_multiplexed_async_connection().await?;
-
-    // Queue name for codec jobs
-    let queue = env::var("CODEC_QUEUE").unwrap_or_else(|_| "codec-jobs".to_string());
-
-    info!("Listening for jobs on queue: {}", queue);
-
-    // Main worker loop
-    loop {
-        let result: Option<(String, String)> = conn.brpop(&queue, 0.0).await?;
-
-        if let Some((_key, payload)) = result {
-            match serde_json::from_str::<Job>(&payload) {
-                Ok(Job::CodecPreview {
-                    job_id,
-                    track_id,
-                    master_url,
-                    codecs,
-                }) => {
-                    info!(
-                        "Processing codec preview job {} for track {}",
-                        job_id, track_id
-                    );
-
-                    if let Err(e) =
-                        process_codec_preview(&job_id, &track_id, &master_url, &codecs).await
-                    {
-                        error!("Job {} failed: {:?}", job_id, e);
-                        report_failure(&job_id, &e.to_string()).await.ok();
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to parse job: {:?}", e);
-                    warn!("Payload was: {}", payload);
-                }
+    match ffmpeg::init(&ffmpeg_settings)? {
+        Some(ffmpeg) => {
+            info!("Encoding with FFmpeg {} ({})", ffmpeg.version, ffmpeg.path);
+            let missing: Vec<String> = CodecFormat::ALL
+                .iter()
+                .filter(|f| !ffmpeg.has_encoder(f.ffmpeg_encoder()))
+                .map(|f| format!("{} ({})", f.name(), f.ffmpeg_encoder()))
+                .collect();
+            if !missing.is_empty() {
+                warn!("FFmpeg lacks encoders for: {}", missing.join(", "));
             }
         }
+        None if native::available().is_empty() => {
+            anyhow::bail!("FFmpeg not found and this build has no built-in encoders");
+        }
+        None => warn!(
+            "FFmpeg not found; falling back to built-in encoders: {:?}",
+            native::available()
+        ),
+    }
+
+    // Clients live for the whole process and are shared by every job task
+    let storage: &'static Storage = Box::leak(Box::new(Storage::new(&config).await?));
+    let webhook: &'static WebhookClient =
+        Box::leak(Box::new(WebhookClient::new(&config, storage)?));
+
+    let result = budi_worker_core::run_jobs(&config, webhook, move |job: Job| async move {
+        process_job(job, storage, webhook).await
+    })
+    .await;
+    budi_worker_core::shutdown_tracing();
+    result
+}
+
+/// Process a single job
+async fn process_job(job: Job, storage: &Storage, webhook: &WebhookClient) -> Result<()> {
+    match job {
+        Job::CodecPreview {
+            job_id,
+            track_id,
+            master_url,
+            master_sha256,
+            codecs,
+            segment,
+            null_test,
+        } => {
+            process_codec_preview(
+                &job_id,
+                &track_id,
+                &master_url,
+                master_sha256.as_deref(),
+                &codecs,
+                segment.as_ref(),
+                null_test,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::CodecSweep {
+            job_id,
+            track_id,
+            master_url,
+            master_sha256,
+            codec,
+            bitrates,
+            max_artifact_score,
+            segment,
+        } => {
+            let threshold = max_artifact_score.unwrap_or(DEFAULT_MAX_ARTIFACT_SCORE);
+            process_codec_sweep(
+                &job_id,
+                &track_id,
+                &master_url,
+                master_sha256.as_deref(),
+                &codec,
+                &bitrates,
+                threshold,
+                segment.as_ref(),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::StreamingLadder {
+            job_id,
+            track_id,
+            master_url,
+            master_sha256,
+            format,
+            renditions,
+            segment_seconds,
+            segment,
+        } => {
+            let renditions = if renditions.is_empty() {
+                ladder::DEFAULT_LADDER.map(String::from).to_vec()
+            } else {
+                renditions
+            };
+            process_streaming_ladder(
+                &job_id,
+                &track_id,
+                &master_url,
+                master_sha256.as_deref(),
+                format,
+                &renditions,
+                segment_seconds.unwrap_or(ladder::DEFAULT_MEDIA_SEGMENT_SECONDS),
+                segment.as_ref(),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::PreviewClip {
+            job_id,
+            track_id,
+            master_url,
+            master_sha256,
+            codecs,
+            duration_seconds,
+            start_seconds,
+            fade_in_seconds,
+            fade_out_seconds,
+        } => {
+            let codecs = if codecs.is_empty() {
+                preview::DEFAULT_CODECS.map(String::from).to_vec()
+            } else {
+                codecs
+            };
+            let fades = (
+                fade_in_seconds.unwrap_or(preview::DEFAULT_FADE_IN_SECONDS),
+                fade_out_seconds.unwrap_or(preview::DEFAULT_FADE_OUT_SECONDS),
+            );
+            process_preview_clip(
+                &job_id,
+                &track_id,
+                &master_url,
+                master_sha256.as_deref(),
+                &codecs,
+                duration_seconds.unwrap_or(preview::DEFAULT_CLIP_SECONDS),
+                start_seconds,
+                fades,
+                storage,
+                webhook,
+            )
+            .await
+        }
     }
 }
 
 /// Process a codec preview job
+#[allow(clippy::too_many_arguments)]
 async fn process_codec_preview(
     job_id: &str,
     track_id: &str,
     master_url: &str,
+    master_sha256: Option<&str>,
     codecs: &[String],
+    segment: Option<&PreviewSegment>,
+    null_test: bool,
+    storage: &Storage,
+    webhook: &WebhookClient,
 ) -> Result<()> {
-    report_progress(job_id, 5, "Downloading master file...").await?;
+    check_encoders(codecs)?;
 
-    let temp_dir = TempDir::new()?;
-    let input_path = temp_dir.path().join("master.wav");
-
-    // Download the master file
-    download_file(master_url, &input_path).await?;
-    report_progress(job_id, 15, "Reading audio...").await?;
+    webhook
+        .report_progress(job_id, 5, "Downloading master file...")
+        .await?;
 
-    // Read the original audio for comparison
-    let original = read_audio_file(&input_path)?;
+    let temp_dir = temp::job_dir()?;
+    let (input_path, original, window, master_sha256) = prepare_source(
+        job_id,
+        &temp_dir,
+        master_url,
+        master_sha256,
+        segment,
+        storage,
+        webhook,
+    )
+    .await?;
 
     let mut results = Vec::new();
     let codec_count = codecs.len();
 
     for (i, codec) in codecs.iter().enumerate() {
         let progress = 20 + (i * 60 / codec_count.max(1));
-        report_progress(job_id, progress as u8, &format!("Processing {}...", codec)).await?;
-
-        let result =
-            process_single_codec(&temp_dir, &input_path, &original, codec, track_id).await?;
+        webhook
+            .report_progress(job_id, progress as u8, &format!("Processing {}...", codec))
+            .await?;
+
+        let result = process_single_codec(
+            &temp_dir,
+            &input_path,
+            &original,
+            codec,
+            track_id,
+            null_test,
+            storage,
+        )
+        .await?;
 
         results.push(result);
     }
 
-    report_progress(job_id, 95, "Reporting results...").await?;
+    webhook
+        .report_progress(job_id, 95, "Reporting results...")
+        .await?;
 
     // Report results
-    report_codec_results(job_id, &results).await?;
+    report_codec_results(webhook, job_id, &master_sha256, &results, window).await?;
 
-    report_progress(job_id, 100, "Codec preview complete").await?;
+    webhook
+        .report_progress(job_id, 100, "Codec preview complete")
+        .await?;
 
     info!(
         "Codec preview complete for {}: {} codecs tested",
@@ -178,6 +516,498 @@ async fn process_codec_preview(
     Ok(())
 }
 
+/// Process a codec sweep job
+#[allow(clippy::too_many_arguments)]
+async fn process_codec_sweep(
+    job_id: &str,
+    track_id: &str,
+    master_url: &str,
+    master_sha256: Option<&str>,
+    codec: &str,
+    bitrates: &[u32],
+    max_artifact_score: f64,
+    segment: Option<&PreviewSegment>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    let codecs = sweep_codecs(codec, bitrates).map_err(refused)?;
+    let names: Vec<String> = codecs.iter().map(|(_, c)| c.clone()).collect();
+    check_encoders(&names)?;
+
+    webhook
+        .report_progress(job_id, 5, "Downloading master file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let (input_path, original, window, master_sha256) = prepare_source(
+        job_id,
+        &temp_dir,
+        master_url,
+        master_sha256,
+        segment,
+        storage,
+        webhook,
+    )
+    .await?;
+
+    let mut results = Vec::new();
+    for (i, (bitrate, codec)) in codecs.iter().enumerate() {
+        let progress = 20 + (i * 70 / codecs.len());
+        webhook
+            .report_progress(
+                job_id,
+                progress as u8,
+                &format!("Encoding {} kbps...", bitrate),
+            )
+            .await?;
+
+        let result = process_single_codec(
+            &temp_dir,
+            &input_path,
+            &original,
+            codec,
+            track_id,
+            false,
+            storage,
+        )
+        .await?;
+        results.push((*bitrate, result));
+    }
+
+    // Results are in ascending bitrate order
+    let recommended = results
+        .iter()
+        .find(|(_, r)| r.artifact_score <= max_artifact_score && !r.clipping_risk)
+        .map(|(bitrate, _)| *bitrate);
+
+    webhook
+        .report_progress(job_id, 95, "Reporting results...")
+        .await?;
+    report_sweep_results(
+        webhook,
+        job_id,
+        &master_sha256,
+        codec,
+        &results,
+        max_artifact_score,
+        recommended,
+        window,
+    )
+    .await?;
+    webhook
+        .report_progress(job_id, 100, "Codec sweep complete")
+        .await?;
+
+    match recommended {
+        Some(bitrate) => info!(
+            "Codec sweep complete for {}: {} recommended at {} kbps",
+            track_id, codec, bitrate
+        ),
+        None => info!(
+            "Codec sweep complete for {}: no {} bitrate under artifact score {:.0}",
+            track_id, codec, max_artifact_score
+        ),
+    }
+
+    Ok(())
+}
+
+/// Process a streaming ladder job
+#[allow(clippy::too_many_arguments)]
+async fn process_streaming_ladder(
+    job_id: &str,
+    track_id: &str,
+    master_url: &str,
+    master_sha256: Option<&str>,
+    format: StreamFormat,
+    renditions: &[String],
+    segment_seconds: f64,
+    segment: Option<&PreviewSegment>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    if ffmpeg::get().is_none() {
+        return Err(
+            Refused("Streaming ladders need FFmpeg, which is not installed".to_string()).into(),
+        );
+    }
+    ladder::validate(renditions, segment_seconds).map_err(refused)?;
+    check_encoders(renditions)?;
+
+    webhook
+        .report_progress(job_id, 5, "Downloading master file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let (input_path, original, window, master_sha256) = prepare_source(
+        job_id,
+        &temp_dir,
+        master_url,
+        master_sha256,
+        segment,
+        storage,
+        webhook,
+    )
+    .await?;
+
+    webhook
+        .report_progress(
+            job_id,
+            20,
+            &format!("Encoding {} renditions...", renditions.len()),
+        )
+        .await?;
+
+    let out_dir = temp_dir.path().join("ladder");
+    std::fs::create_dir_all(&out_dir)?;
+    let duration = original.frame_count() as f64 / original.sample_rate as f64;
+    let mut results = ladder::build(
+        &input_path,
+        &out_dir,
+        format,
+        renditions,
+        segment_seconds,
+        duration,
+    )?;
+
+    webhook
+        .report_progress(job_id, 70, "Uploading segments...")
+        .await?;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis();
+    let prefix = format!("streams/{}/{}-{}", track_id, timestamp, format.name());
+    let mut uploads = HashMap::new();
+    for path in ladder::files(&out_dir)? {
+        let relative = path
+            .strip_prefix(&out_dir)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let uploaded = storage
+            .upload_file(
+                &path,
+                &format!("{}/{}", prefix, relative),
+                ladder::content_type(&path),
+            )
+            .await?;
+        uploads.insert(relative, uploaded);
+    }
+    let manifest = uploads
+        .get(format.manifest_name())
+        .cloned()
+        .context("FFmpeg wrote no manifest")?;
+    let manifest_key = format!("{}/{}", prefix, format.manifest_name());
+    for rendition in &mut results {
+        let playlist = rendition.playlist.as_ref().and_then(|p| uploads.get(p));
+        rendition.playlist_url = playlist.map(|u| u.url.clone());
+        rendition.playlist_sha256 = playlist.map(|u| u.sha256.clone());
+        rendition.playlist_key = rendition
+            .playlist
+            .as_ref()
+            .filter(|p| uploads.contains_key(*p))
+            .map(|p| format!("{}/{}", prefix, p));
+    }
+
+    webhook
+        .report_progress(job_id, 95, "Reporting results...")
+        .await?;
+    report_ladder_results(
+        webhook,
+        job_id,
+        &master_sha256,
+        format,
+        &manifest,
+        &manifest_key,
+        segment_seconds,
+        &results,
+        window,
+    )
+    .await?;
+    webhook
+        .report_progress(job_id, 100, "Streaming ladder complete")
+        .await?;
+
+    info!(
+        "Streaming ladder complete for {}: {} renditions at {}",
+        track_id,
+        results.len(),
+        manifest.url
+    );
+
+    Ok(())
+}
+
+/// Process a preview clip job
+#[allow(clippy::too_many_arguments)]
+async fn process_preview_clip(
+    job_id: &str,
+    track_id: &str,
+    master_url: &str,
+    master_sha256: Option<&str>,
+    codecs: &[String],
+    duration_seconds: f64,
+    start_seconds: Option<f64>,
+    (fade_in, fade_out): (f64, f64),
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    check_encoders(codecs)?;
+    if duration_seconds <= 0.0 || fade_in < 0.0 || fade_out < 0.0 {
+        return Err(Refused(
+            "Preview clip length must be positive and fades non-negative".to_string(),
+        )
+        .into());
+    }
+    if fade_in + fade_out > duration_seconds {
+        return Err(Refused(format!(
+            "Preview clip fades ({:.1}s) are longer than the clip ({:.1}s)",
+            fade_in + fade_out,
+            duration_seconds
+        ))
+        .into());
+    }
+
+    webhook
+        .report_progress(job_id, 5, "Downloading master file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let (_, original, _, master_sha256) = prepare_source(
+        job_id,
+        &temp_dir,
+        master_url,
+        master_sha256,
+        None,
+        storage,
+        webhook,
+    )
+    .await?;
+
+    webhook
+        .report_progress(job_id, 25, "Selecting preview section...")
+        .await?;
+    let (start, frames) = preview::select(&original, duration_seconds, start_seconds);
+    if frames == 0 {
+        anyhow::bail!("Preview clip is empty");
+    }
+    let mut clip = AudioBuffer {
+        samples: original
+            .samples
+            .iter()
+            .map(|ch| ch[start..start + frames].to_vec())
+            .collect(),
+        sample_rate: original.sample_rate,
+        channels: original.channels,
+    };
+    // Short tracks get fades scaled down with them
+    let scale = (frames as f64 / original.sample_rate as f64 / (fade_in + fade_out)).min(1.0);
+    let (fade_in, fade_out) = (fade_in * scale, fade_out * scale);
+    preview::apply_fades(&mut clip, fade_in, fade_out);
+    let clip_path = temp_dir.path().join("clip.wav");
+    write_wav_f32(&clip, &clip_path)?;
+
+    let mut previews = Vec::with_capacity(codecs.len());
+    for (i, codec) in codecs.iter().enumerate() {
+        let progress = 30 + (i * 60 / codecs.len());
+        webhook
+            .report_progress(job_id, progress as u8, &format!("Encoding {}...", codec))
+            .await?;
+
+        let spec = parse_codec(codec)?;
+        let extension = spec.format.extension();
+        let output_path = temp_dir
+            .path()
+            .join(format!("clip_{}.{}", codec, extension));
+        if ffmpeg::get().is_some() {
+            encode_with_ffmpeg(&clip_path, &output_path, &spec)?;
+        } else {
+            native::round_trip(&clip, &spec, &output_path)?;
+        }
+        let gapless = gapless::apply(&output_path, spec.format)?;
+
+        let key = Storage::generate_key(
+            "previews",
+            track_id,
+            &format!("clip-{}.{}", codec, extension),
+        );
+        let uploaded = storage
+            .upload_file(&output_path, &key, spec.format.content_type())
+            .await?;
+        previews.push(serde_json::json!({
+            "codec": codec,
+            "url": uploaded.url,
+            "key": key,
+            "sha256": uploaded.sha256,
+            "encoderDelay": gapless.map(|g| g.delay),
+            "encoderPadding": gapless.map(|g| g.padding)
+        }));
+    }
+
+    webhook
+        .report_progress(job_id, 95, "Reporting results...")
+        .await?;
+    let rate = original.sample_rate as f64;
+    let window = (start as f64 / rate, frames as f64 / rate);
+    let data = serde_json::json!({
+        "masterSha256": master_sha256,
+        "segment": segment_json(Some(window)),
+        "fadeInSeconds": fade_in,
+        "fadeOutSeconds": fade_out,
+        "previews": previews
+    });
+    webhook
+        .report_completed(job_id, "preview-clip", &data)
+        .await?;
+    webhook
+        .report_progress(job_id, 100, "Preview clip complete")
+        .await?;
+
+    info!(
+        "Preview clip for {}: {:.1}s from {:.1}s in {} codecs",
+        track_id,
+        window.1,
+        window.0,
+        previews.len()
+    );
+
+    Ok(())
+}
+
+/// Download and decode the master, cutting it down to the requested segment
+///
+/// Returns the file to encode, its decoded audio, the segment used (start
+/// and duration in seconds) if the master was cut, and the master's SHA-256.
+async fn prepare_source(
+    job_id: &str,
+    temp_dir: &TempDir,
+    master_url: &str,
+    master_sha256: Option<&str>,
+    segment: Option<&PreviewSegment>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<(PathBuf, AudioBuffer, Option<(f64, f64)>, String)> {
+    let input_path = temp_dir.path().join("master.wav");
+
+    // Download the master file
+    let master_sha256 = storage
+        .download_file(master_url, &input_path, master_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 15, "Reading audio...")
+        .await?;
+
+    // Read the original audio for comparison
+    let original = read_audio_cached(&input_path, &master_sha256)?;
+    let Some(segment) = segment else {
+        return Ok((input_path, original, None, master_sha256));
+    };
+
+    let (start, frames) = segment_frames(&original, segment);
+    if frames == 0 {
+        anyhow::bail!("Preview segment is empty");
+    }
+    let clip = AudioBuffer {
+        samples: original
+            .samples
+            .iter()
+            .map(|ch| ch[start..start + frames].to_vec())
+            .collect(),
+        sample_rate: original.sample_rate,
+        channels: original.channels,
+    };
+    let clip_path = temp_dir.path().join("segment.wav");
+    write_wav_f32(&clip, &clip_path)?;
+
+    let rate = original.sample_rate as f64;
+    let window = (start as f64 / rate, frames as f64 / rate);
+    info!(
+        "Previewing {:.1}s from {:.1}s of {:.1}s master",
+        window.1,
+        window.0,
+        original.frame_count() as f64 / rate
+    );
+    Ok((clip_path, clip, Some(window), master_sha256))
+}
+
+/// Start frame and length of a preview segment, clamped to the track
+fn segment_frames(buffer: &AudioBuffer, segment: &PreviewSegment) -> (usize, usize) {
+    let rate = buffer.sample_rate as f64;
+    let total = buffer.frame_count();
+    let duration = segment
+        .duration_seconds
+        .unwrap_or(DEFAULT_SEGMENT_SECONDS)
+        .max(0.0);
+    let frames = ((duration * rate) as usize).min(total);
+
+    let start = match segment.start_seconds {
+        Some(start) => ((start.max(0.0) * rate) as usize).min(total - frames),
+        None => loudest_segment_start(buffer, frames),
+    };
+    (start, frames)
+}
+
+/// Start of the `frames`-long window with the most energy, on block boundaries
+fn loudest_segment_start(buffer: &AudioBuffer, frames: usize) -> usize {
+    let block = ((SEGMENT_BLOCK_SECONDS * buffer.sample_rate as f64) as usize).max(1);
+    let total = buffer.frame_count();
+    let block_count = total / block;
+    let window_blocks = (frames / block).max(1);
+    if block_count <= window_blocks {
+        return 0;
+    }
+
+    let energies: Vec<f64> = (0..block_count)
+        .map(|b| {
+            buffer
+                .samples
+                .iter()
+                .flat_map(|ch| &ch[b * block..(b + 1) * block])
+                .map(|&s| (s as f64).powi(2))
+                .sum()
+        })
+        .collect();
+
+    let mut sum: f64 = energies[..window_blocks].iter().sum();
+    let (mut best_sum, mut best_block) = (sum, 0);
+    for b in window_blocks..block_count {
+        sum += energies[b] - energies[b - window_blocks];
+        if sum > best_sum {
+            best_sum = sum;
+            best_block = b + 1 - window_blocks;
+        }
+    }
+    (best_block * block).min(total - frames)
+}
+
+/// Codec strings for each bitrate of a sweep, lowest first
+///
+/// Bitrates outside the codec's supported range are dropped from the
+/// default ladder but rejected when requested explicitly.
+fn sweep_codecs(codec: &str, bitrates: &[u32]) -> Result<Vec<(u32, String)>> {
+    let mut ladder: Vec<u32> = if bitrates.is_empty() {
+        let format = CodecFormat::from_name(codec.strip_suffix("-vbr").unwrap_or(codec))?;
+        let (min, max) = format.bitrate_range();
+        DEFAULT_SWEEP_BITRATES
+            .into_iter()
+            .filter(|b| (min..=max).contains(b))
+            .collect()
+    } else {
+        bitrates.to_vec()
+    };
+    ladder.sort_unstable();
+    ladder.dedup();
+
+    ladder
+        .into_iter()
+        .map(|bitrate| {
+            let spec = format!("{}-{}", codec, bitrate);
+            parse_codec(&spec)?;
+            Ok((bitrate, spec))
+        })
+        .collect()
+}
+
 /// Process a single codec
 async fn process_single_codec(
     temp_dir: &TempDir,
@@ -185,24 +1015,44 @@ async fn process_single_codec(
     original: &AudioBuffer,
     codec: &str,
     track_id: &str,
+    null_test: bool,
+    storage: &Storage,
 ) -> Result<CodecPreviewResult> {
-    let output_path = temp_dir.path().join(format!("preview_{}.audio", codec));
+    // Parse codec format
+    let spec = parse_codec(codec)?;
+
+    // Keep the container's extension so FFmpeg picks the right muxer and the
+    // uploaded preview's key matches its content type
+    let extension = spec.format.extension();
+    let output_path = temp_dir
+        .path()
+        .join(format!("preview_{}.{}", codec, extension));
     let decoded_path = temp_dir.path().join(format!("decoded_{}.wav", codec));
 
-    // Parse codec format
-    let (format, bitrate) = parse_codec(codec)?;
+    let decoded = if ffmpeg::get().is_some() {
+        // Encode using FFmpeg
+        encode_with_ffmpeg(input_path, &output_path, &spec)?;
 
-    // Encode using FFmpeg
-    encode_with_ffmpeg(input_path, &output_path, &format, bitrate)?;
+        // Decode back to WAV for analysis
+        decode_with_ffmpeg(&output_path, &decoded_path, original.sample_rate)?;
 
-    // Decode back to WAV for analysis
-    decode_with_ffmpeg(&output_path, &decoded_path)?;
+        // Read decoded audio
+        read_audio_file(&decoded_path)?
+    } else if native::supports(&spec) {
+        native::round_trip(original, &spec, &output_path)?
+    } else {
+        anyhow::bail!(
+            "FFmpeg is not installed and {} has no built-in encoder",
+            codec
+        );
+    };
 
-    // Read decoded audio
-    let decoded = read_audio_file(&decoded_path)?;
+    // Album previews play back to back, so players need the priming and padding
+    let gapless = gapless::apply(&output_path, spec.format)?;
 
     // Calculate true peak of decoded audio
-    let true_peak = calculate_true_peak(&decoded)?;
+    let peak_stats = true_peak::measure(&decoded)?;
+    let true_peak = peak_stats.db();
 
     // Calculate artifact score (difference from original)
     let artifact_score = calculate_artifact_score(original, &decoded)?;
@@ -210,76 +1060,411 @@ async fn process_single_codec(
     // Check clipping risk
     let clipping_risk = true_peak > -0.5;
 
+    // Codecs are close to linear in level, so attenuating the master by the
+    // decoded overshoot keeps the decoded peak at full scale
+    let suggested_pre_gain_db = (-true_peak).min(0.0);
+
+    // Joint stereo and parametric stereo narrow the image in ways SNR misses
+    let stereo_before = analyze_stereo(original);
+    let stereo_after = analyze_stereo(&decoded);
+    let delta = |f: fn(&StereoImage) -> f64| match (&stereo_before, &stereo_after) {
+        (Some(before), Some(after)) => Some(f(after) - f(before)),
+        _ => None,
+    };
+
     // Upload preview file
-    let preview_url = upload_file(&output_path, track_id, codec).await?;
+    let key = Storage::generate_key("previews", track_id, &format!("{}.{}", codec, extension));
+    let preview = storage
+        .upload_file(&output_path, &key, spec.format.content_type())
+        .await?;
+
+    let (null_test_upload, null_test_key, null_test_gain_db) = if null_test {
+        let (difference, gain_db) = null_test_signal(original, &decoded)?;
+        let null_path = temp_dir.path().join(format!("null_{}.wav", codec));
+        write_wav_f32(&difference, &null_path)?;
+        let key = Storage::generate_key("previews", track_id, &format!("{}-null.wav", codec));
+        let uploaded = storage.upload_file(&null_path, &key, "audio/wav").await?;
+        (Some(uploaded), Some(key), Some(gain_db))
+    } else {
+        (None, None, None)
+    };
 
     Ok(CodecPreviewResult {
         codec: codec.to_string(),
-        preview_url,
+        preview_url: preview.url,
+        preview_key: key,
+        preview_sha256: preview.sha256,
         true_peak_after: true_peak,
         artifact_score,
         clipping_risk,
+        inter_sample_overs: peak_stats.overs,
+        suggested_pre_gain_db,
+        stereo_correlation_after: stereo_after.map(|s| s.correlation),
+        stereo_correlation_delta: delta(|s| s.correlation),
+        stereo_width_after: stereo_after.map(|s| s.width),
+        stereo_width_delta: delta(|s| s.width),
+        encoder_delay: gapless.map(|g| g.delay),
+        encoder_padding: gapless.map(|g| g.padding),
+        null_test_url: null_test_upload.as_ref().map(|u| u.url.clone()),
+        null_test_key,
+        null_test_sha256: null_test_upload.map(|u| u.sha256),
+        null_test_gain_db,
     })
 }
 
-/// Parse codec string (e.g., "aac-128" -> ("aac", 128))
-fn parse_codec(codec: &str) -> Result<(String, u32)> {
-    let parts: Vec<&str> = codec.split('-').collect();
-    if parts.len() != 2 {
-        anyhow::bail!("Invalid codec format: {}", codec);
+/// Lossy codec a preview can be encoded with
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CodecFormat {
+    Aac,
+    /// HE-AAC (AAC-LC + SBR)
+    HeAac,
+    /// HE-AAC v2 (AAC-LC + SBR + parametric stereo)
+    HeAacV2,
+    Mp3,
+    Opus,
+    Vorbis,
+    Ac3,
+    Eac3,
+}
+
+impl CodecFormat {
+    const ALL: [CodecFormat; 8] = [
+        Self::Aac,
+        Self::HeAac,
+        Self::HeAacV2,
+        Self::Mp3,
+        Self::Opus,
+        Self::Vorbis,
+        Self::Ac3,
+        Self::Eac3,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Aac => "aac",
+            Self::HeAac => "he-aac",
+            Self::HeAacV2 => "he-aac-v2",
+            Self::Mp3 => "mp3",
+            Self::Opus => "opus",
+            Self::Vorbis => "vorbis",
+            Self::Ac3 => "ac3",
+            Self::Eac3 => "e-ac3",
+        }
+    }
+
+    /// FFmpeg encoder this codec is encoded with
+    fn ffmpeg_encoder(&self) -> &'static str {
+        self.encoder_args()[1]
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            // Profiles can also be spelled out after the codec, e.g. "aac-he-64"
+            "aac" | "aac-lc" => Self::Aac,
+            "heaac" | "he-aac" | "aac-he" => Self::HeAac,
+            "heaacv2" | "he-aac-v2" | "aac-hev2" | "aac-he-v2" => Self::HeAacV2,
+            "mp3" => Self::Mp3,
+            "opus" => Self::Opus,
+            "vorbis" => Self::Vorbis,
+            "ac3" => Self::Ac3,
+            "eac3" | "e-ac3" => Self::Eac3,
+            _ => anyhow::bail!("Unsupported codec: {}", name),
+        })
+    }
+
+    /// FFmpeg encoder and any extra arguments it needs
+    fn encoder_args(&self) -> &'static [&'static str] {
+        match self {
+            Self::Aac => &["-c:a", "aac"],
+            // FFmpeg's native AAC encoder has no SBR, so HE-AAC needs libfdk_aac
+            Self::HeAac => &["-c:a", "libfdk_aac", "-profile:a", "aac_he"],
+            Self::HeAacV2 => &["-c:a", "libfdk_aac", "-profile:a", "aac_he_v2"],
+            Self::Mp3 => &["-c:a", "libmp3lame"],
+            Self::Opus => &["-c:a", "libopus"],
+            Self::Vorbis => &["-c:a", "libvorbis"],
+            Self::Ac3 => &["-c:a", "ac3"],
+            Self::Eac3 => &["-c:a", "eac3"],
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Aac | Self::HeAac | Self::HeAacV2 => "m4a",
+            Self::Mp3 => "mp3",
+            // RFC 7845's extension for Ogg Opus
+            Self::Opus => "opus",
+            Self::Vorbis => "ogg",
+            Self::Ac3 => "ac3",
+            Self::Eac3 => "eac3",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Aac | Self::HeAac | Self::HeAacV2 => "audio/mp4",
+            Self::Mp3 => "audio/mpeg",
+            // The codecs parameter lets browsers check playability up front
+            Self::Opus => "audio/ogg; codecs=opus",
+            Self::Vorbis => "audio/ogg; codecs=vorbis",
+            Self::Ac3 => "audio/ac3",
+            Self::Eac3 => "audio/eac3",
+        }
+    }
+
+    /// Supported bitrate range (kbps)
+    fn bitrate_range(&self) -> (u32, u32) {
+        match self {
+            Self::Aac | Self::Mp3 => (32, 320),
+            Self::HeAac => (24, 128),
+            Self::HeAacV2 => (16, 64),
+            Self::Opus => (6, 510),
+            Self::Vorbis => (45, 500),
+            Self::Ac3 => (96, 640),
+            Self::Eac3 => (96, 1536),
+        }
     }
-    let format = parts[0].to_string();
-    let bitrate = parts[1].parse::<u32>().context("Invalid bitrate")?;
-    Ok((format, bitrate))
 }
 
-/// Encode audio using FFmpeg
-fn encode_with_ffmpeg(input: &Path, output: &Path, format: &str, bitrate: u32) -> Result<()> {
-    let bitrate_str = format!("{}k", bitrate);
-    let codec_args: Vec<&str> = match format {
-        "aac" => vec!["-c:a", "aac", "-b:a", &bitrate_str],
-        "mp3" => vec!["-c:a", "libmp3lame", "-b:a", &bitrate_str],
-        "opus" => vec!["-c:a", "libopus", "-b:a", &bitrate_str],
-        _ => anyhow::bail!("Unsupported codec: {}", format),
-    };
+/// How the encoder is asked to spend bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RateControl {
+    /// Target bitrate in the encoder's default mode (kbps)
+    Bitrate(u32),
+    /// Variable bitrate averaging the given target (kbps)
+    Vbr(u32),
+    /// Quality-based VBR on the encoder's own scale (LAME V0-V9, Vorbis q0-q10,
+    /// FDK AAC VBR 1-5)
+    Quality(u8),
+}
+
+/// A parsed codec string: which codec and how it is rate-controlled
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CodecSpec {
+    format: CodecFormat,
+    rate: RateControl,
+}
 
-    let extension = match format {
-        "aac" => "m4a",
-        "mp3" => "mp3",
-        "opus" => "ogg",
-        _ => "audio",
+impl CodecSpec {
+    /// FFmpeg encoder this spec needs
+    fn ffmpeg_encoder(&self) -> &'static str {
+        match (self.format, self.rate) {
+            // FFmpeg's native AAC encoder has no usable VBR mode
+            (CodecFormat::Aac, RateControl::Quality(_)) => "libfdk_aac",
+            (format, _) => format.ffmpeg_encoder(),
+        }
+    }
+
+    /// Full FFmpeg codec arguments for this spec
+    fn ffmpeg_args(&self) -> Vec<String> {
+        let encoder_args = match (self.format, self.rate) {
+            (CodecFormat::Aac, RateControl::Quality(_)) => &["-c:a", "libfdk_aac"],
+            (format, _) => format.encoder_args(),
+        };
+        let mut args: Vec<String> = encoder_args.iter().map(|a| a.to_string()).collect();
+        let extra: Vec<String> = match (self.format, self.rate) {
+            (_, RateControl::Bitrate(kbps)) => vec!["-b:a".into(), format!("{}k", kbps)],
+            (CodecFormat::Opus, RateControl::Vbr(kbps)) => {
+                vec![
+                    "-vbr".into(),
+                    "on".into(),
+                    "-b:a".into(),
+                    format!("{}k", kbps),
+                ]
+            }
+            (CodecFormat::Mp3, RateControl::Vbr(kbps)) => {
+                vec![
+                    "-abr".into(),
+                    "1".into(),
+                    "-b:a".into(),
+                    format!("{}k", kbps),
+                ]
+            }
+            // libvorbis treats a bitrate as the nominal rate of a VBR stream
+            (_, RateControl::Vbr(kbps)) => vec!["-b:a".into(), format!("{}k", kbps)],
+            (
+                CodecFormat::Aac | CodecFormat::HeAac | CodecFormat::HeAacV2,
+                RateControl::Quality(q),
+            ) => vec!["-vbr".into(), q.to_string()],
+            (_, RateControl::Quality(q)) => vec!["-q:a".into(), q.to_string()],
+        };
+        args.extend(extra);
+        args
+    }
+}
+
+/// Parse codec string
+///
+/// - "aac-128", "he-aac-64": codec at a target bitrate
+/// - "aac-lc-128", "aac-he-64", "aac-hev2-32": AAC with an explicit profile
+/// - "opus-vbr-96": VBR averaging 96 kbps (Opus, MP3 ABR, Vorbis)
+/// - "mp3-v0", "vorbis-q6", "aac-q5", "aac-he-q2": quality-based VBR
+fn parse_codec(codec: &str) -> Result<CodecSpec> {
+    let (name, setting) = codec
+        .rsplit_once('-')
+        .with_context(|| format!("Invalid codec format: {}", codec))?;
+
+    if let Some(name) = name.strip_suffix("-vbr") {
+        let format = CodecFormat::from_name(name)?;
+        if !matches!(
+            format,
+            CodecFormat::Opus | CodecFormat::Mp3 | CodecFormat::Vorbis
+        ) {
+            anyhow::bail!("Bitrate-targeted VBR is not supported for {}", name);
+        }
+        let bitrate = parse_bitrate(format, name, setting)?;
+        return Ok(CodecSpec {
+            format,
+            rate: RateControl::Vbr(bitrate),
+        });
+    }
+
+    let format = CodecFormat::from_name(name)?;
+    let quality = match format {
+        CodecFormat::Mp3 => setting.strip_prefix('v').map(|q| (q, 9)),
+        CodecFormat::Vorbis => setting.strip_prefix('q').map(|q| (q, 10)),
+        // libfdk_aac's VBR modes, which it also supports with SBR and PS
+        CodecFormat::Aac | CodecFormat::HeAac | CodecFormat::HeAacV2 => {
+            setting.strip_prefix('q').map(|q| (q, 5))
+        }
+        _ => None,
+    };
+    let rate = match quality {
+        Some((q, max)) => {
+            let q = q.parse::<u8>().context("Invalid quality level")?;
+            let min = match format {
+                CodecFormat::Aac | CodecFormat::HeAac | CodecFormat::HeAacV2 => 1,
+                _ => 0,
+            };
+            if !(min..=max).contains(&q) {
+                anyhow::bail!("Quality {} out of range for {} ({}-{})", q, name, min, max);
+            }
+            RateControl::Quality(q)
+        }
+        None => RateControl::Bitrate(parse_bitrate(format, name, setting)?),
     };
+    Ok(CodecSpec { format, rate })
+}
 
-    let output_with_ext = output.with_extension(extension);
+/// Parse and range-check a bitrate in kbps
+fn parse_bitrate(format: CodecFormat, name: &str, bitrate: &str) -> Result<u32> {
+    let bitrate = bitrate.parse::<u32>().context("Invalid bitrate")?;
+    let (min, max) = format.bitrate_range();
+    if !(min..=max).contains(&bitrate) {
+        anyhow::bail!(
+            "Bitrate {} kbps out of range for {} ({}-{} kbps)",
+            bitrate,
+            name,
+            min,
+            max
+        );
+    }
+    Ok(bitrate)
+}
 
-    let status = Command::new("ffmpeg")
+/// Fail unless every codec can be encoded by this install
+///
+/// Run before any work so a job asking for a missing encoder fails with a
+/// list of what's missing rather than partway through. Either way the job is
+/// refused, since retrying it here won't help.
+fn check_encoders(codecs: &[String]) -> Result<()> {
+    let mut missing = Vec::new();
+    for codec in codecs {
+        let spec = parse_codec(codec).map_err(refused)?;
+        match ffmpeg::get() {
+            Some(ffmpeg) if !ffmpeg.has_encoder(spec.ffmpeg_encoder()) => {
+                missing.push(format!("{} (needs {})", codec, spec.ffmpeg_encoder()))
+            }
+            None if !native::supports(&spec) => {
+                missing.push(format!("{} (no FFmpeg or built-in encoder)", codec))
+            }
+            _ => {}
+        }
+    }
+    if !missing.is_empty() {
+        return Err(Refused(format!(
+            "Codecs unavailable on this worker: {}",
+            missing.join(", ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// What this install can encode, for `--status`
+fn capabilities_json(ffmpeg: Option<&ffmpeg::Ffmpeg>) -> serde_json::Value {
+    let native = native::available();
+    let codecs: Vec<serde_json::Value> = CodecFormat::ALL
+        .iter()
+        .map(|format| {
+            let encoder = format.ffmpeg_encoder();
+            let via_ffmpeg = ffmpeg.is_some_and(|f| f.has_encoder(encoder));
+            let via_native = native.contains(&format.name());
+            serde_json::json!({
+                "codec": format.name(),
+                "encoder": encoder,
+                "ffmpeg": via_ffmpeg,
+                "native": via_native,
+                "available": via_ffmpeg || (ffmpeg.is_none() && via_native)
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "ffmpeg": ffmpeg.map(|f| serde_json::json!({
+            "path": f.path,
+            "version": f.version,
+            "encoders": f.encoders
+        })),
+        "nativeEncoders": native,
+        "codecs": codecs
+    })
+}
+
+/// Encode audio using FFmpeg
+#[tracing::instrument(name = "codec.encode", skip_all)]
+fn encode_with_ffmpeg(input: &Path, output: &Path, spec: &CodecSpec) -> Result<()> {
+    let codec_args = spec.ffmpeg_args();
+
+    let status = ffmpeg::get()
+        .context("FFmpeg is not installed")?
+        .command()
         .args(["-i", input.to_str().unwrap()])
         .args(&codec_args)
-        .args(["-y", output_with_ext.to_str().unwrap()])
+        .args(["-y", output.to_str().unwrap()])
         .output()
         .context("Failed to run FFmpeg")?;
 
     if !status.status.success() {
+        let stderr = String::from_utf8_lossy(&status.stderr);
+        if stderr.contains("Unknown encoder") {
+            anyhow::bail!("FFmpeg was built without the {} encoder", codec_args[1]);
+        }
         anyhow::bail!(
             "FFmpeg encoding failed: {}",
-            String::from_utf8_lossy(&status.stderr)
+            ffmpeg::error_summary(&status.stderr)
         );
     }
 
-    // Rename to expected output path
-    std::fs::rename(&output_with_ext, output)?;
-
     Ok(())
 }
 
 /// Decode audio back to WAV using FFmpeg
-fn decode_with_ffmpeg(input: &Path, output: &Path) -> Result<()> {
-    let status = Command::new("ffmpeg")
+///
+/// Output is float at the original sample rate (Opus always decodes at
+/// 48 kHz), so it can be compared sample-for-sample and overs aren't clipped.
+#[tracing::instrument(name = "codec.decode", skip_all)]
+fn decode_with_ffmpeg(input: &Path, output: &Path, sample_rate: u32) -> Result<()> {
+    let sample_rate = sample_rate.to_string();
+    let status = ffmpeg::get()
+        .context("FFmpeg is not installed")?
+        .command()
         .args([
             "-i",
             input.to_str().unwrap(),
             "-c:a",
-            "pcm_s24le",
+            "pcm_f32le",
+            "-ar",
+            &sample_rate,
             "-y",
             output.to_str().unwrap(),
         ])
@@ -289,156 +1474,75 @@ fn decode_with_ffmpeg(input: &Path, output: &Path) -> Result<()> {
     if !status.status.success() {
         anyhow::bail!(
             "FFmpeg decoding failed: {}",
-            String::from_utf8_lossy(&status.stderr)
+            ffmpeg::error_summary(&status.stderr)
         );
     }
 
     Ok(())
 }
 
-/// Read an audio file using Symphonia
-fn read_audio_file(path: &Path) -> Result<AudioBuffer> {
-    let file = std::fs::File::open(path)?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-
-    let mut hint = Hint::new();
-    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-        hint.with_extension(ext);
+/// Measure stereo correlation and width, matching the DSP worker's analysis
+fn analyze_stereo(buffer: &AudioBuffer) -> Option<StereoImage> {
+    if buffer.channels < 2 {
+        return None;
     }
 
-    let format_opts = FormatOptions::default();
-    let metadata_opts = MetadataOptions::default();
-    let probed =
-        symphonia::default::get_probe().format(&hint, mss, &format_opts, &metadata_opts)?;
-
-    let mut format = probed.format;
-
-    let track = format
-        .tracks()
-        .iter()
-        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-        .context("No audio track found")?;
-
-    let track_id = track.id;
-    let codec_params = track.codec_params.clone();
-    let sample_rate = codec_params.sample_rate.unwrap_or(44100);
-    let channels = codec_params.channels.map(|c| c.count()).unwrap_or(2);
-
-    let decoder_opts = DecoderOptions::default();
-    let mut decoder = symphonia::default::get_codecs().make(&codec_params, &decoder_opts)?;
-
-    let mut buffer = AudioBuffer {
-        samples: vec![Vec::new(); channels],
-        sample_rate,
-        channels,
-    };
-
-    loop {
-        let packet = match format.next_packet() {
-            Ok(p) => p,
-            Err(symphonia::core::errors::Error::IoError(e))
-                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
-            {
-                break;
-            }
-            Err(e) => return Err(e.into()),
-        };
-
-        if packet.track_id() != track_id {
-            continue;
-        }
-
-        let decoded = decoder.decode(&packet)?;
-        append_samples(&mut buffer, decoded)?;
+    let left = &buffer.samples[0];
+    let right = &buffer.samples[1];
+    let len = left.len().min(right.len());
+    if len == 0 {
+        return None;
     }
 
-    Ok(buffer)
-}
-
-/// Append decoded samples to buffer
-fn append_samples(buffer: &mut AudioBuffer, decoded: AudioBufferRef) -> Result<()> {
-    match decoded {
-        AudioBufferRef::F32(buf) => {
-            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
-                buffer.samples[ch].extend_from_slice(buf.chan(ch));
-            }
-        }
-        AudioBufferRef::S16(buf) => {
-            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
-                buffer.samples[ch].extend(buf.chan(ch).iter().map(|&s| s as f32 / 32768.0));
-            }
-        }
-        AudioBufferRef::S32(buf) => {
-            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
-                buffer.samples[ch].extend(buf.chan(ch).iter().map(|&s| s as f32 / 2147483648.0));
-            }
-        }
-        _ => {}
+    let (mut sum_l, mut sum_r, mut sum_ll, mut sum_rr, mut sum_lr) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    let (mut mid_energy, mut side_energy) = (0.0, 0.0);
+    for (&l, &r) in left[..len].iter().zip(&right[..len]) {
+        let (l, r) = (l as f64, r as f64);
+        sum_l += l;
+        sum_r += r;
+        sum_ll += l * l;
+        sum_rr += r * r;
+        sum_lr += l * r;
+
+        let mid = (l + r) / 2.0;
+        let side = (l - r) / 2.0;
+        mid_energy += mid * mid;
+        side_energy += side * side;
     }
-    Ok(())
-}
-
-/// Calculate true peak using 4x oversampling
-fn calculate_true_peak(buffer: &AudioBuffer) -> Result<f64> {
-    let target_rate = buffer.sample_rate * 4;
-
-    let mut resampler = FftFixedIn::<f32>::new(
-        buffer.sample_rate as usize,
-        target_rate as usize,
-        1024,
-        2,
-        buffer.channels,
-    )?;
-
-    let mut max_peak: f32 = 0.0;
-    let chunk_size = resampler.input_frames_next();
-    let frame_count = buffer.frame_count();
-
-    for start in (0..frame_count).step_by(chunk_size) {
-        let end = (start + chunk_size).min(frame_count);
 
-        let chunk: Vec<Vec<f32>> = if end - start < chunk_size {
-            buffer
-                .samples
-                .iter()
-                .map(|ch| {
-                    let mut c = ch[start..end].to_vec();
-                    c.resize(chunk_size, 0.0);
-                    c
-                })
-                .collect()
-        } else {
-            buffer
-                .samples
-                .iter()
-                .map(|ch| ch[start..end].to_vec())
-                .collect()
-        };
-
-        if let Ok(output) = resampler.process(&chunk, None) {
-            for ch in &output {
-                for &sample in ch {
-                    let abs = sample.abs();
-                    if abs > max_peak {
-                        max_peak = abs;
-                    }
-                }
-            }
-        }
-    }
+    let n = len as f64;
+    let (mean_l, mean_r) = (sum_l / n, sum_r / n);
+    let var_l = sum_ll / n - mean_l * mean_l;
+    let var_r = sum_rr / n - mean_r * mean_r;
+    let cov_lr = sum_lr / n - mean_l * mean_r;
 
-    Ok(if max_peak > 0.0 {
-        20.0 * (max_peak as f64).log10()
+    let correlation = if var_l > 0.0 && var_r > 0.0 {
+        cov_lr / (var_l.sqrt() * var_r.sqrt())
     } else {
-        -96.0
-    })
+        0.0
+    };
+    let width = if mid_energy + side_energy > 0.0 {
+        side_energy / (mid_energy + side_energy)
+    } else {
+        0.0
+    };
+
+    Some(StereoImage { correlation, width })
 }
 
+/// Largest encoder + decoder delay searched for when aligning (frames)
+///
+/// Covers AAC/HE-AAC priming (up to 2112 samples before SBR upsampling),
+/// LAME's 1105 samples plus decoder delay, and AC-3's block overlap.
+const MAX_CODEC_DELAY: usize = 8192;
+
 /// Calculate artifact score (0-100, lower is better)
+///
+/// The decoded signal is first aligned with the original, trimming priming
+/// samples the container didn't already account for, so the score reflects
+/// coding error rather than latency.
 fn calculate_artifact_score(original: &AudioBuffer, decoded: &AudioBuffer) -> Result<f64> {
-    let orig_frames = original.frame_count();
-    let dec_frames = decoded.frame_count();
-    let min_frames = orig_frames.min(dec_frames);
+    let (orig_offset, dec_offset, min_frames) = align(original, decoded, MAX_CODEC_DELAY)?;
 
     if min_frames == 0 {
         return Ok(0.0);
@@ -448,9 +1552,10 @@ fn calculate_artifact_score(original: &AudioBuffer, decoded: &AudioBuffer) -> Re
     let mut total_energy: f64 = 0.0;
 
     for ch in 0..original.channels.min(decoded.channels) {
-        for i in 0..min_frames {
-            let orig = original.samples[ch][i] as f64;
-            let dec = decoded.samples[ch][i] as f64;
+        let orig_ch = &original.samples[ch][orig_offset..orig_offset + min_frames];
+        let dec_ch = &decoded.samples[ch][dec_offset..dec_offset + min_frames];
+        for (&orig, &dec) in orig_ch.iter().zip(dec_ch) {
+            let (orig, dec) = (orig as f64, dec as f64);
             let error = (orig - dec).powi(2);
             total_error += error;
             total_energy += orig.powi(2);
@@ -470,152 +1575,220 @@ fn calculate_artifact_score(original: &AudioBuffer, decoded: &AudioBuffer) -> Re
     Ok(artifact_score)
 }
 
-/// Download file from S3/MinIO
-async fn download_file(url: &str, path: &Path) -> Result<()> {
-    let endpoint =
-        env::var("MINIO_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string());
-    let access_key = env::var("MINIO_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string());
-    let secret_key = env::var("MINIO_SECRET_KEY").unwrap_or_else(|_| "minioadmin".to_string());
-
-    let credentials = Credentials::new(access_key, secret_key, None, None, "env");
-    let config = aws_sdk_s3::Config::builder()
-        .endpoint_url(&endpoint)
-        .region(Region::new("us-east-1"))
-        .credentials_provider(credentials)
-        .force_path_style(true)
-        .build();
-
-    let client = Client::from_conf(config);
-
-    // Parse URL to get bucket and key
-    let url_parsed = url::Url::parse(url)?;
-    let path_str = url_parsed.path().trim_start_matches('/');
-    let parts: Vec<&str> = path_str.splitn(2, '/').collect();
-    if parts.len() != 2 {
-        anyhow::bail!("Invalid S3 URL: {}", url);
+/// Original minus decoded, time-aligned and boosted, for listening
+///
+/// Boosted by `NULL_TEST_GAIN_DB` unless that would push the peak past
+/// `NULL_TEST_CEILING_DB`, and cut to the loudest `NULL_TEST_SECONDS`.
+/// Returns the clip and the gain applied (dB).
+fn null_test_signal(original: &AudioBuffer, decoded: &AudioBuffer) -> Result<(AudioBuffer, f64)> {
+    let (orig_offset, dec_offset, frames) = align(original, decoded, MAX_CODEC_DELAY)?;
+    let channels = original.channels.min(decoded.channels);
+
+    let mut difference = AudioBuffer::new(channels, original.sample_rate);
+    for ch in 0..channels {
+        let orig_ch = &original.samples[ch][orig_offset..orig_offset + frames];
+        let dec_ch = &decoded.samples[ch][dec_offset..dec_offset + frames];
+        difference.samples[ch] = orig_ch.iter().zip(dec_ch).map(|(o, d)| o - d).collect();
     }
-    let (bucket, key) = (parts[0], parts[1]);
 
-    let response = client.get_object().bucket(bucket).key(key).send().await?;
+    let clip_frames = ((NULL_TEST_SECONDS * original.sample_rate as f64) as usize).min(frames);
+    let start = loudest_segment_start(&difference, clip_frames);
+    for ch in difference.samples.iter_mut() {
+        ch.truncate(start + clip_frames);
+        ch.drain(..start);
+    }
 
-    let body = response.body.collect().await?;
-    tokio::fs::write(path, body.into_bytes()).await?;
+    let peak = difference
+        .samples
+        .iter()
+        .flatten()
+        .fold(0.0f32, |max, &s| max.max(s.abs()));
+    let gain_db = if peak > 0.0 {
+        NULL_TEST_GAIN_DB.min(NULL_TEST_CEILING_DB - 20.0 * (peak as f64).log10())
+    } else {
+        NULL_TEST_GAIN_DB
+    };
+    let gain = 10f64.powf(gain_db / 20.0) as f32;
+    for s in difference.samples.iter_mut().flatten() {
+        *s *= gain;
+    }
 
-    Ok(())
+    Ok((difference, gain_db))
 }
 
-/// Upload file to S3/MinIO
-async fn upload_file(path: &Path, track_id: &str, codec: &str) -> Result<String> {
-    let endpoint =
-        env::var("MINIO_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string());
-    let access_key = env::var("MINIO_ACCESS_KEY").unwrap_or_else(|_| "minioadmin".to_string());
-    let secret_key = env::var("MINIO_SECRET_KEY").unwrap_or_else(|_| "minioadmin".to_string());
-    let bucket = env::var("MINIO_BUCKET_AUDIO").unwrap_or_else(|_| "audio".to_string());
-
-    let credentials = Credentials::new(access_key, secret_key, None, None, "env");
-    let config = aws_sdk_s3::Config::builder()
-        .endpoint_url(&endpoint)
-        .region(Region::new("us-east-1"))
-        .credentials_provider(credentials)
-        .force_path_style(true)
-        .build();
-
-    let client = Client::from_conf(config);
-
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_millis();
-    let key = format!("previews/{}/{}-{}", track_id, timestamp, codec);
-
-    let mut file = File::open(path).await?;
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents).await?;
-
-    let body = ByteStream::from(Bytes::from(contents));
-
-    client
-        .put_object()
-        .bucket(&bucket)
-        .key(&key)
-        .body(body)
-        .content_type("audio/mpeg")
-        .send()
-        .await?;
-
-    Ok(format!("{}/{}/{}", endpoint, bucket, key))
+/// Report codec preview results
+async fn report_codec_results(
+    webhook: &WebhookClient,
+    job_id: &str,
+    master_sha256: &str,
+    results: &[CodecPreviewResult],
+    segment: Option<(f64, f64)>,
+) -> Result<()> {
+    let data = CodecPreviewData {
+        master_sha256,
+        previews: results,
+        segment: encoded_segment(segment),
+    };
+    webhook
+        .report_result(job_id, &CODEC_PREVIEW_RESULT, &data)
+        .await
 }
 
-/// Report job progress
-async fn report_progress(job_id: &str, progress: u8, message: &str) -> Result<()> {
-    let api_url = env::var("API_URL").unwrap_or_else(|_| "http://localhost:4000".to_string());
-    let secret = env::var("WEBHOOK_SECRET").unwrap_or_else(|_| "budi-webhook-secret".to_string());
+/// Report codec sweep results
+#[allow(clippy::too_many_arguments)]
+async fn report_sweep_results(
+    webhook: &WebhookClient,
+    job_id: &str,
+    master_sha256: &str,
+    codec: &str,
+    results: &[(u32, CodecPreviewResult)],
+    max_artifact_score: f64,
+    recommended_bitrate: Option<u32>,
+    segment: Option<(f64, f64)>,
+) -> Result<()> {
+    let mut points = Vec::with_capacity(results.len());
+    for (bitrate, result) in results {
+        let mut point = serde_json::to_value(result)?;
+        point["bitrate"] = (*bitrate).into();
+        points.push(point);
+    }
 
-    let client = HttpClient::new();
-    client
-        .post(format!("{}/webhooks/jobs/{}/progress", api_url, job_id))
-        .header("X-Webhook-Secret", &secret)
-        .json(&serde_json::json!({
-            "progress": progress,
-            "message": message
-        }))
-        .send()
-        .await?;
+    let data = serde_json::json!({
+        "masterSha256": master_sha256,
+        "codec": codec,
+        "maxArtifactScore": max_artifact_score,
+        "recommendedBitrate": recommended_bitrate,
+        "points": points,
+        "segment": segment_json(segment)
+    });
+    webhook.report_completed(job_id, "codec-sweep", &data).await
+}
 
-    Ok(())
+/// Report streaming ladder results
+#[allow(clippy::too_many_arguments)]
+async fn report_ladder_results(
+    webhook: &WebhookClient,
+    job_id: &str,
+    master_sha256: &str,
+    format: StreamFormat,
+    manifest: &Uploaded,
+    manifest_key: &str,
+    segment_seconds: f64,
+    renditions: &[ladder::Rendition],
+    segment: Option<(f64, f64)>,
+) -> Result<()> {
+    let data = serde_json::json!({
+        "masterSha256": master_sha256,
+        "format": format,
+        "manifestUrl": manifest.url,
+        "manifestKey": manifest_key,
+        "manifestSha256": manifest.sha256,
+        "segmentSeconds": segment_seconds,
+        "renditions": renditions,
+        "segment": segment_json(segment)
+    });
+    webhook
+        .report_completed(job_id, "streaming-ladder", &data)
+        .await
 }
 
-/// Report codec preview results
-async fn report_codec_results(job_id: &str, results: &[CodecPreviewResult]) -> Result<()> {
-    let api_url = env::var("API_URL").unwrap_or_else(|_| "http://localhost:4000".to_string());
-    let secret = env::var("WEBHOOK_SECRET").unwrap_or_else(|_| "budi-webhook-secret".to_string());
-
-    let client = HttpClient::new();
-    client
-        .post(format!(
-            "{}/webhooks/jobs/{}/codec-preview",
-            api_url, job_id
-        ))
-        .header("X-Webhook-Secret", &secret)
-        .json(&serde_json::json!({
-            "jobId": job_id,
-            "type": "codec-preview",
-            "status": "completed",
-            "data": {
-                "previews": results.iter().map(|r| serde_json::json!({
-                    "codec": r.codec,
-                    "previewUrl": r.preview_url,
-                    "truePeakAfter": r.true_peak_after,
-                    "artifactScore": r.artifact_score,
-                    "clippingRisk": r.clipping_risk
-                })).collect::<Vec<_>>()
-            }
-        }))
-        .send()
-        .await?;
+/// Segment (start, duration) as reported in webhooks
+fn segment_json(segment: Option<(f64, f64)>) -> serde_json::Value {
+    serde_json::json!(encoded_segment(segment))
+}
 
-    Ok(())
+fn encoded_segment(segment: Option<(f64, f64)>) -> Option<EncodedSegment> {
+    segment.map(|(start_seconds, duration_seconds)| EncodedSegment {
+        start_seconds,
+        duration_seconds,
+    })
 }
 
-/// Report job failure
-async fn report_failure(job_id: &str, error: &str) -> Result<()> {
-    let api_url = env::var("API_URL").unwrap_or_else(|_| "http://localhost:4000".to_string());
-    let secret = env::var("WEBHOOK_SECRET").unwrap_or_else(|_| "budi-webhook-secret".to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let client = HttpClient::new();
-    client
-        .post(format!(
-            "{}/webhooks/jobs/{}/codec-preview",
-            api_url, job_id
-        ))
-        .header("X-Webhook-Secret", &secret)
-        .json(&serde_json::json!({
-            "jobId": job_id,
-            "type": "codec-preview",
-            "status": "failed",
-            "error": error
-        }))
-        .send()
-        .await?;
+    #[test]
+    fn test_published_result_schemas_are_current() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../packages/contracts/schemas");
+        budi_worker_core::schema::check_published(&[CODEC_PREVIEW_RESULT], &dir).unwrap();
+    }
 
-    Ok(())
+    #[test]
+    fn test_codec_spec_ffmpeg_args() {
+        let cases: &[(&str, &str)] = &[
+            ("aac-128", "-c:a aac -b:a 128k"),
+            ("aac-lc-256", "-c:a aac -b:a 256k"),
+            ("he-aac-64", "-c:a libfdk_aac -profile:a aac_he -b:a 64k"),
+            ("aac-he-48", "-c:a libfdk_aac -profile:a aac_he -b:a 48k"),
+            (
+                "aac-hev2-32",
+                "-c:a libfdk_aac -profile:a aac_he_v2 -b:a 32k",
+            ),
+            (
+                "aac-he-v2-24",
+                "-c:a libfdk_aac -profile:a aac_he_v2 -b:a 24k",
+            ),
+            ("aac-q5", "-c:a libfdk_aac -vbr 5"),
+            ("aac-he-q2", "-c:a libfdk_aac -profile:a aac_he -vbr 2"),
+            ("mp3-320", "-c:a libmp3lame -b:a 320k"),
+            ("mp3-v0", "-c:a libmp3lame -q:a 0"),
+            ("mp3-vbr-192", "-c:a libmp3lame -abr 1 -b:a 192k"),
+            ("opus-96", "-c:a libopus -b:a 96k"),
+            ("opus-vbr-96", "-c:a libopus -vbr on -b:a 96k"),
+            ("vorbis-q6", "-c:a libvorbis -q:a 6"),
+            ("vorbis-vbr-160", "-c:a libvorbis -b:a 160k"),
+            ("ac3-640", "-c:a ac3 -b:a 640k"),
+            ("e-ac3-1536", "-c:a eac3 -b:a 1536k"),
+        ];
+        for (codec, expected) in cases {
+            let spec = parse_codec(codec).unwrap();
+            assert_eq!(spec.ffmpeg_args().join(" "), *expected, "{}", codec);
+        }
+        assert_eq!(
+            parse_codec("aac-q3").unwrap().ffmpeg_encoder(),
+            "libfdk_aac"
+        );
+        assert_eq!(parse_codec("aac-128").unwrap().ffmpeg_encoder(), "aac");
+    }
+
+    #[test]
+    fn test_parse_codec_errors() {
+        let cases: &[(&str, &str)] = &[
+            ("aac", "Invalid codec format: aac"),
+            ("flac-900", "Unsupported codec: flac"),
+            (
+                "aac-vbr-128",
+                "Bitrate-targeted VBR is not supported for aac",
+            ),
+            ("aac-abc", "Invalid bitrate"),
+            (
+                "aac-16",
+                "Bitrate 16 kbps out of range for aac (32-320 kbps)",
+            ),
+            (
+                "aac-he-160",
+                "Bitrate 160 kbps out of range for aac-he (24-128 kbps)",
+            ),
+            (
+                "aac-hev2-96",
+                "Bitrate 96 kbps out of range for aac-hev2 (16-64 kbps)",
+            ),
+            (
+                "opus-vbr-600",
+                "Bitrate 600 kbps out of range for opus (6-510 kbps)",
+            ),
+            ("mp3-v10", "Quality 10 out of range for mp3 (0-9)"),
+            ("aac-q0", "Quality 0 out of range for aac (1-5)"),
+            ("vorbis-qx", "Invalid quality level"),
+        ];
+        for (codec, expected) in cases {
+            let err = parse_codec(codec).unwrap_err();
+            assert_eq!(err.to_string(), *expected, "{}", codec);
+
+            // Retrying won't make the codec valid
+            let err = check_encoders(&[codec.to_string()]).unwrap_err();
+            assert!(budi_worker_core::limits::is_refused(&err), "{}", codec);
+        }
+    }
 }
@@ -0,0 +1,345 @@
+//! Built-in encoders used when the `ffmpeg` binary isn't available
+//!
+//! - MP3 through LAME (`native-mp3`, on by default)
+//! - Opus in Ogg through libopus (`native-opus`, opt-in: needs libopus or
+//!   CMake to build)
+//!
+//! Each preview is decoded straight back so it can be scored like an FFmpeg
+//! round-trip.
+
+// Everything but the fallbacks' signatures compiles away without either feature
+#![cfg_attr(
+    not(any(feature = "native-mp3", feature = "native-opus")),
+    allow(unused)
+)]
+
+use anyhow::Result;
+use std::path::Path;
+
+use crate::{AudioBuffer, CodecFormat, CodecSpec, RateControl};
+
+/// Whether `spec` can be encoded without FFmpeg
+pub fn supports(spec: &CodecSpec) -> bool {
+    match (spec.format, spec.rate) {
+        #[cfg(feature = "native-mp3")]
+        (CodecFormat::Mp3, RateControl::Bitrate(kbps)) => mp3::bitrate(kbps).is_some(),
+        #[cfg(feature = "native-mp3")]
+        (CodecFormat::Mp3, RateControl::Quality(_)) => true,
+        #[cfg(feature = "native-opus")]
+        (CodecFormat::Opus, RateControl::Bitrate(_) | RateControl::Vbr(_)) => true,
+        _ => false,
+    }
+}
+
+/// Codecs with a built-in encoder in this build
+pub fn available() -> Vec<&'static str> {
+    let mut codecs = Vec::new();
+    if cfg!(feature = "native-mp3") {
+        codecs.push("mp3");
+    }
+    if cfg!(feature = "native-opus") {
+        codecs.push("opus");
+    }
+    codecs
+}
+
+/// Encode `buffer` into `output` and return the decoded result
+#[tracing::instrument(name = "codec.native_round_trip", skip_all)]
+pub fn round_trip(buffer: &AudioBuffer, spec: &CodecSpec, output: &Path) -> Result<AudioBuffer> {
+    if !(1..=2).contains(&buffer.channels) {
+        anyhow::bail!(
+            "Built-in encoders support mono and stereo only ({} channels)",
+            buffer.channels
+        );
+    }
+
+    match spec.format {
+        #[cfg(feature = "native-mp3")]
+        CodecFormat::Mp3 => mp3::round_trip(buffer, spec.rate, output),
+        #[cfg(feature = "native-opus")]
+        CodecFormat::Opus => opus_ogg::round_trip(buffer, spec.rate, output),
+        _ => anyhow::bail!("No built-in encoder for {:?}", spec.format),
+    }
+}
+
+#[cfg(feature = "native-mp3")]
+mod mp3 {
+    use super::*;
+    use mp3lame_encoder::{
+        max_required_buffer_size, Bitrate, Builder, FlushGap, InterleavedPcm, MonoPcm, Quality,
+        VbrMode,
+    };
+    use std::mem::MaybeUninit;
+
+    /// Samples of priming LAME adds before the signal
+    const ENCODER_DELAY: u32 = 576;
+
+    /// LAME's fixed bitrate for `kbps`, if it has one
+    pub fn bitrate(kbps: u32) -> Option<Bitrate> {
+        Some(match kbps {
+            32 => Bitrate::Kbps32,
+            40 => Bitrate::Kbps40,
+            48 => Bitrate::Kbps48,
+            64 => Bitrate::Kbps64,
+            80 => Bitrate::Kbps80,
+            96 => Bitrate::Kbps96,
+            112 => Bitrate::Kbps112,
+            128 => Bitrate::Kbps128,
+            160 => Bitrate::Kbps160,
+            192 => Bitrate::Kbps192,
+            224 => Bitrate::Kbps224,
+            256 => Bitrate::Kbps256,
+            320 => Bitrate::Kbps320,
+            _ => return None,
+        })
+    }
+
+    fn lame_err<E: std::fmt::Debug>(what: &'static str) -> impl FnOnce(E) -> anyhow::Error {
+        move |e| anyhow::anyhow!("Failed to {}: {:?}", what, e)
+    }
+
+    /// LAME's VBR quality scale, V0 (best) to V9
+    fn vbr_quality(level: u8) -> Quality {
+        match level {
+            0 => Quality::Best,
+            1 => Quality::SecondBest,
+            2 => Quality::NearBest,
+            3 => Quality::VeryNice,
+            4 => Quality::Nice,
+            5 => Quality::Good,
+            6 => Quality::Decent,
+            7 => Quality::Ok,
+            8 => Quality::SecondWorst,
+            _ => Quality::Worst,
+        }
+    }
+
+    pub fn round_trip(
+        buffer: &AudioBuffer,
+        rate: RateControl,
+        output: &Path,
+    ) -> Result<AudioBuffer> {
+        let mut builder =
+            Builder::new().ok_or_else(|| anyhow::anyhow!("Failed to create MP3 encoder"))?;
+        builder
+            .set_num_channels(buffer.channels as u8)
+            .map_err(lame_err("set channels"))?;
+        builder
+            .set_sample_rate(buffer.sample_rate)
+            .map_err(lame_err("set sample rate"))?;
+        match rate {
+            RateControl::Bitrate(kbps) => {
+                let brate = bitrate(kbps)
+                    .ok_or_else(|| anyhow::anyhow!("LAME has no {} kbps mode", kbps))?;
+                builder.set_brate(brate).map_err(lame_err("set bitrate"))?;
+            }
+            RateControl::Quality(level) => {
+                builder
+                    .set_vbr_mode(VbrMode::Mtrh)
+                    .map_err(lame_err("set VBR mode"))?;
+                builder
+                    .set_vbr_quality(vbr_quality(level))
+                    .map_err(lame_err("set VBR quality"))?;
+            }
+            RateControl::Vbr(_) => anyhow::bail!("Built-in MP3 encoder has no ABR mode"),
+        }
+        builder
+            .set_quality(Quality::Best)
+            .map_err(lame_err("set quality"))?;
+        let mut encoder = builder.build().map_err(lame_err("build MP3 encoder"))?;
+
+        let frames = buffer.frame_count();
+        let mut mp3: Vec<MaybeUninit<u8>> =
+            vec![MaybeUninit::uninit(); max_required_buffer_size(frames * buffer.channels)];
+        let encoded = if buffer.channels == 1 {
+            encoder.encode(MonoPcm(&buffer.samples[0]), &mut mp3)
+        } else {
+            let interleaved: Vec<f32> = (0..frames)
+                .flat_map(|i| [buffer.samples[0][i], buffer.samples[1][i]])
+                .collect();
+            encoder.encode(InterleavedPcm(&interleaved), &mut mp3)
+        }
+        .map_err(lame_err("encode MP3"))?;
+        let flushed = encoder
+            .flush::<FlushGap>(&mut mp3[encoded..])
+            .map_err(lame_err("flush MP3 encoder"))?;
+
+        // SAFETY: the encoder initialised the first `encoded + flushed` bytes
+        let mut data: Vec<u8> = mp3[..encoded + flushed]
+            .iter()
+            .map(|b| unsafe { b.assume_init() })
+            .collect();
+
+        // LAME leaves its first frame empty for the caller to fill with the tag
+        let vbr = matches!(rate, RateControl::Quality(_));
+        crate::gapless::write_lame_tag(&mut data, ENCODER_DELAY, frames as u64, vbr)?;
+
+        // Decode through Symphonia, which takes the `.mp3` extension as a hint
+        std::fs::write(output, data)?;
+        budi_worker_core::audio::read_audio_file(output)
+    }
+}
+
+#[cfg(feature = "native-opus")]
+mod opus_ogg {
+    use super::*;
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+    use rubato::{FftFixedIn, Resampler};
+    use std::io::BufWriter;
+
+    /// Opus always runs at 48 kHz internally
+    const OPUS_RATE: u32 = 48000;
+
+    /// 20 ms frames
+    const FRAME_SIZE: usize = 960;
+
+    /// Largest packet libopus produces
+    const MAX_PACKET: usize = 4000;
+
+    pub fn round_trip(
+        buffer: &AudioBuffer,
+        rate: RateControl,
+        output: &Path,
+    ) -> Result<AudioBuffer> {
+        let channels = buffer.channels;
+        let source = resample(buffer, OPUS_RATE)?;
+        let opus_channels = if channels == 1 {
+            opus::Channels::Mono
+        } else {
+            opus::Channels::Stereo
+        };
+
+        let mut encoder = opus::Encoder::new(OPUS_RATE, opus_channels, opus::Application::Audio)?;
+        let kbps = match rate {
+            // libopus is VBR by default, as with FFmpeg
+            RateControl::Bitrate(kbps) => kbps,
+            RateControl::Vbr(kbps) => {
+                encoder.set_vbr(true)?;
+                kbps
+            }
+            RateControl::Quality(_) => anyhow::bail!("Opus has no quality-based mode"),
+        };
+        encoder.set_bitrate(opus::Bitrate::Bits(kbps as i32 * 1000))?;
+        let pre_skip = encoder.get_lookahead()? as usize;
+        let mut decoder = opus::Decoder::new(OPUS_RATE, opus_channels)?;
+
+        // Ogg Opus identification and comment headers (RFC 7845)
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels as u8);
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        head.extend_from_slice(&buffer.sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        let vendor = b"budi";
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes());
+
+        let serial = 1;
+        let mut writer = PacketWriter::new(BufWriter::new(std::fs::File::create(output)?));
+        writer.write_packet(head, serial, PacketWriteEndInfo::EndPage, 0)?;
+        writer.write_packet(tags, serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        // Pad so the encoder's lookahead is flushed and the last frame is whole
+        let frames = source.frame_count();
+        let padded = (frames + pre_skip).div_ceil(FRAME_SIZE) * FRAME_SIZE;
+        let mut interleaved = vec![0.0f32; padded * channels];
+        for (ch, samples) in source.samples.iter().enumerate() {
+            for (i, &s) in samples.iter().enumerate() {
+                interleaved[i * channels + ch] = s;
+            }
+        }
+
+        let mut decoded = vec![Vec::with_capacity(padded); channels];
+        let mut packet = vec![0u8; MAX_PACKET];
+        let mut pcm = vec![0.0f32; FRAME_SIZE * channels];
+        let packet_count = padded / FRAME_SIZE;
+        for (i, frame) in interleaved.chunks(FRAME_SIZE * channels).enumerate() {
+            let len = encoder.encode_float(frame, &mut packet)?;
+            let last = i + 1 == packet_count;
+            // The final granule position marks where playback ends
+            let granule = if last {
+                (pre_skip + frames) as u64
+            } else {
+                ((i + 1) * FRAME_SIZE) as u64
+            };
+            let end = if last {
+                PacketWriteEndInfo::EndStream
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            writer.write_packet(packet[..len].to_vec(), serial, end, granule)?;
+
+            let samples = decoder.decode_float(&packet[..len], &mut pcm, false)?;
+            for f in 0..samples {
+                for (ch, out) in decoded.iter_mut().enumerate() {
+                    out.push(pcm[f * channels + ch]);
+                }
+            }
+        }
+
+        // Drop the pre-skip and padding, as a conforming player would
+        for ch in &mut decoded {
+            ch.drain(..pre_skip.min(ch.len()));
+            ch.truncate(frames);
+        }
+        let decoded = AudioBuffer {
+            samples: decoded,
+            sample_rate: OPUS_RATE,
+            channels,
+        };
+        resample(&decoded, buffer.sample_rate)
+    }
+
+    /// Convert to `target_rate`, keeping the expected length
+    fn resample(buffer: &AudioBuffer, target_rate: u32) -> Result<AudioBuffer> {
+        if buffer.sample_rate == target_rate {
+            return Ok(AudioBuffer {
+                samples: buffer.samples.clone(),
+                sample_rate: buffer.sample_rate,
+                channels: buffer.channels,
+            });
+        }
+
+        let mut resampler = FftFixedIn::<f32>::new(
+            buffer.sample_rate as usize,
+            target_rate as usize,
+            1024,
+            2,
+            buffer.channels,
+        )?;
+        let delay = resampler.output_delay();
+        let frames = buffer.frame_count();
+        let expected = (frames as u64 * target_rate as u64 / buffer.sample_rate as u64) as usize;
+        let chunk_size = resampler.input_frames_next();
+
+        let mut output = vec![Vec::with_capacity(expected + delay); buffer.channels];
+        let mut start = 0;
+        while output[0].len() < expected + delay {
+            let chunk: Vec<Vec<f32>> = buffer
+                .samples
+                .iter()
+                .map(|ch| {
+                    let mut c = ch[start.min(frames)..(start + chunk_size).min(frames)].to_vec();
+                    c.resize(chunk_size, 0.0);
+                    c
+                })
+                .collect();
+            for (out, ch) in output.iter_mut().zip(resampler.process(&chunk, None)?) {
+                out.extend(ch);
+            }
+            start += chunk_size;
+        }
+
+        Ok(AudioBuffer {
+            samples: output
+                .into_iter()
+                .map(|ch| ch[delay..delay + expected].to_vec())
+                .collect(),
+            sample_rate: target_rate,
+            channels: buffer.channels,
+        })
+    }
+}
@@ -0,0 +1,131 @@
+//! Store-style preview clips
+//!
+//! Picks a representative section of the master (the loudest window, as
+//! codec previews do without a segment), then moves each end of it to the
+//! strongest onset nearby, so the clip starts on a downbeat or phrase rather
+//! than mid-note and ends as the next phrase arrives. A short fade in takes
+//! the click off the first sample and a longer fade out closes the clip.
+
+use crate::{loudest_segment_start, AudioBuffer};
+
+/// Clip length when a job doesn't give one (seconds)
+pub const DEFAULT_CLIP_SECONDS: f64 = 30.0;
+
+/// Fades when a job doesn't give them (seconds)
+pub const DEFAULT_FADE_IN_SECONDS: f64 = 0.05;
+pub const DEFAULT_FADE_OUT_SECONDS: f64 = 1.5;
+
+/// Codecs a preview clip is encoded with when a job doesn't list any
+pub const DEFAULT_CODECS: [&str; 1] = ["mp3-192"];
+
+/// How far each end of the clip may move to reach an onset (seconds)
+const ONSET_SEARCH_SECONDS: f64 = 2.0;
+
+/// Hop of the energy envelope onsets are found in (seconds)
+const ONSET_HOP_SECONDS: f64 = 0.01;
+
+/// Envelope hops an onset is measured against
+const ONSET_HISTORY: usize = 8;
+
+/// Smallest rise over the recent envelope that counts as an onset (dB)
+const MIN_ONSET_DB: f64 = 3.0;
+
+/// Start frame and length of the clip to preview
+///
+/// With `start_seconds` the window is taken as given; otherwise it is the
+/// loudest `duration_seconds` of the track with its ends moved to onsets.
+pub fn select(
+    buffer: &AudioBuffer,
+    duration_seconds: f64,
+    start_seconds: Option<f64>,
+) -> (usize, usize) {
+    let rate = buffer.sample_rate as f64;
+    let total = buffer.frame_count();
+    let frames = ((duration_seconds.max(0.0) * rate) as usize).min(total);
+    if let Some(start) = start_seconds {
+        return (
+            ((start.max(0.0) * rate) as usize).min(total - frames),
+            frames,
+        );
+    }
+    if frames == total {
+        return (0, total);
+    }
+
+    let start = loudest_segment_start(buffer, frames);
+    let hop = ((ONSET_HOP_SECONDS * rate) as usize).max(1);
+    let search = (ONSET_SEARCH_SECONDS * rate) as usize;
+    let strength = onset_strength(buffer, hop);
+    let snap = |frame: usize, lo: usize, hi: usize| {
+        strongest_onset(&strength, hop, lo, hi).unwrap_or(frame)
+    };
+
+    let start = snap(
+        start,
+        start.saturating_sub(search),
+        (start + search).min(total),
+    );
+    // The end may only move as far as keeps the clip within the search
+    // distance of the length asked for
+    let end = (start + frames).min(total);
+    let end = snap(
+        end,
+        end.saturating_sub(search).max(start + 1),
+        (end + search).min(total),
+    );
+    (start, end - start)
+}
+
+/// Rise of each envelope hop over the hops before it (dB, 0 when falling)
+fn onset_strength(buffer: &AudioBuffer, hop: usize) -> Vec<f64> {
+    let energies: Vec<f64> = (0..buffer.frame_count() / hop)
+        .map(|h| {
+            let sum: f64 = buffer
+                .samples
+                .iter()
+                .flat_map(|ch| &ch[h * hop..(h + 1) * hop])
+                .map(|&s| (s as f64).powi(2))
+                .sum();
+            sum / (hop * buffer.channels.max(1)) as f64
+        })
+        .collect();
+
+    (0..energies.len())
+        .map(|h| {
+            let history = &energies[h.saturating_sub(ONSET_HISTORY)..h];
+            if history.is_empty() {
+                return 0.0;
+            }
+            let recent = history.iter().sum::<f64>() / history.len() as f64;
+            (10.0 * ((energies[h] + 1e-12) / (recent + 1e-12)).log10()).max(0.0)
+        })
+        .collect()
+}
+
+/// First frame of the strongest onset between frames `lo` and `hi`
+fn strongest_onset(strength: &[f64], hop: usize, lo: usize, hi: usize) -> Option<usize> {
+    let hops = lo.div_ceil(hop)..(hi / hop).min(strength.len());
+    hops.filter(|&h| strength[h] >= MIN_ONSET_DB)
+        .max_by(|&a, &b| strength[a].total_cmp(&strength[b]))
+        .map(|h| h * hop)
+}
+
+/// Raised-cosine fades over the first `fade_in` and last `fade_out` seconds
+pub fn apply_fades(buffer: &mut AudioBuffer, fade_in: f64, fade_out: f64) {
+    let rate = buffer.sample_rate as f64;
+    let total = buffer.frame_count();
+    let fade_in = ((fade_in.max(0.0) * rate) as usize).min(total);
+    let fade_out = ((fade_out.max(0.0) * rate) as usize).min(total);
+    let gain = |i: usize, len: usize| {
+        0.5 - 0.5 * (std::f64::consts::PI * (i as f64 + 0.5) / len as f64).cos()
+    };
+
+    for ch in &mut buffer.samples {
+        for (i, s) in ch[..fade_in].iter_mut().enumerate() {
+            *s *= gain(i, fade_in) as f32;
+        }
+        for (i, s) in ch[total - fade_out..].iter_mut().rev().enumerate() {
+            *s *= gain(i, fade_out) as f32;
+        }
+    }
+}
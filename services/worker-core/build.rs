@@ -0,0 +1,17 @@
+//! Generates the gRPC job API from `proto/jobs.proto` with the `grpc` feature
+
+fn main() {
+    println!("cargo:rerun-if-changed=proto/jobs.proto");
+    #[cfg(feature = "grpc")]
+    {
+        // Use the bundled protoc unless one is given
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path().expect("No bundled protoc");
+            std::env::set_var("PROTOC", protoc);
+        }
+        tonic_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/jobs.proto"], &["proto"])
+            .expect("Failed to compile proto/jobs.proto");
+    }
+}
@@ -0,0 +1,134 @@
+//! Time alignment of two renders of the same audio
+//!
+//! Before two renders can be subtracted they have to line up to the sample:
+//! a codec's decoded output lags the master by its priming, and two
+//! revisions of a master can differ in their lead-in. The delay between them
+//! is found by FFT cross-correlation of a mono excerpt.
+
+use anyhow::Result;
+use realfft::num_complex::Complex;
+use realfft::RealFftPlanner;
+
+use crate::audio::AudioBuffer;
+
+/// Shortest excerpt used to estimate the delay (frames); longer searches use
+/// an excerpt four times the largest delay
+const MIN_WINDOW: usize = 1 << 16;
+
+/// Offsets into `reference` and `other` that line them up, and the length
+/// they overlap for (frames), searching delays up to `max_delay` frames
+pub fn align(
+    reference: &AudioBuffer,
+    other: &AudioBuffer,
+    max_delay: usize,
+) -> Result<(usize, usize, usize)> {
+    let delay = estimate_delay(reference, other, max_delay)?;
+    // Positive delay: `other` lags the reference; negative: it starts early
+    let (ref_offset, other_offset) = if delay >= 0 {
+        (0, delay as usize)
+    } else {
+        (delay.unsigned_abs(), 0)
+    };
+
+    let ref_frames = reference.frame_count().saturating_sub(ref_offset);
+    let other_frames = other.frame_count().saturating_sub(other_offset);
+    Ok((ref_offset, other_offset, ref_frames.min(other_frames)))
+}
+
+/// Delay of `other` relative to `reference` in frames, by cross-correlation
+///
+/// Correlates a mono excerpt from the middle of the track (where there is
+/// almost always programme material) via FFT, searching ±`max_delay`.
+pub fn estimate_delay(
+    reference: &AudioBuffer,
+    other: &AudioBuffer,
+    max_delay: usize,
+) -> Result<isize> {
+    let frames = reference.frame_count().min(other.frame_count());
+    let window = MIN_WINDOW
+        .max((4 * max_delay).next_power_of_two())
+        .min(frames);
+    if window <= max_delay {
+        return Ok(0);
+    }
+    let start = (frames - window) / 2;
+
+    let mono = |buffer: &AudioBuffer| -> Vec<f32> {
+        (start..start + window)
+            .map(|i| buffer.samples.iter().map(|ch| ch[i]).sum::<f32>())
+            .collect()
+    };
+
+    let fft_size = (2 * window).next_power_of_two();
+    let mut planner = RealFftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(fft_size);
+    let inverse = planner.plan_fft_inverse(fft_size);
+
+    let spectrum = |signal: Vec<f32>| -> Result<Vec<Complex<f32>>> {
+        let mut input = forward.make_input_vec();
+        input[..signal.len()].copy_from_slice(&signal);
+        let mut output = forward.make_output_vec();
+        forward
+            .process(&mut input, &mut output)
+            .map_err(|e| anyhow::anyhow!("FFT failed: {}", e))?;
+        Ok(output)
+    };
+    let ref_spectrum = spectrum(mono(reference))?;
+    let other_spectrum = spectrum(mono(other))?;
+
+    // corr[k] = sum reference[n] * other[n + k]
+    let mut cross: Vec<Complex<f32>> = ref_spectrum
+        .iter()
+        .zip(&other_spectrum)
+        .map(|(r, o)| r.conj() * o)
+        .collect();
+    let mut corr = inverse.make_output_vec();
+    inverse
+        .process(&mut cross, &mut corr)
+        .map_err(|e| anyhow::anyhow!("Inverse FFT failed: {}", e))?;
+
+    let lag_at = |k: usize| -> isize {
+        if k <= max_delay {
+            k as isize
+        } else {
+            k as isize - fft_size as isize
+        }
+    };
+    let best = (0..=max_delay)
+        .chain(fft_size - max_delay..fft_size)
+        .max_by(|&a, &b| corr[a].total_cmp(&corr[b]))
+        .map(lag_at)
+        .unwrap_or(0);
+
+    Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_found_both_ways() {
+        // Noise, and the same noise 1234 frames later
+        let mut x: u32 = 1;
+        let noise: Vec<f32> = (0..200_000)
+            .map(|_| {
+                x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+                (x >> 8) as f32 / 16777216.0 - 0.5
+            })
+            .collect();
+        let mut early = AudioBuffer::new(1, 48000);
+        early.samples[0] = noise[1234..].to_vec();
+        let mut late = AudioBuffer::new(1, 48000);
+        late.samples[0] = noise.clone();
+
+        assert_eq!(estimate_delay(&early, &late, 48000).unwrap(), 1234);
+        assert_eq!(estimate_delay(&late, &early, 48000).unwrap(), -1234);
+        let (a, b, frames) = align(&late, &early, 48000).unwrap();
+        assert_eq!((a, b), (1234, 0));
+        assert_eq!(
+            late.samples[0][a..a + frames],
+            early.samples[0][b..b + frames]
+        );
+    }
+}
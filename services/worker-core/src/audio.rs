@@ -0,0 +1,404 @@
+//! Audio decoding using Symphonia, and WAV I/O using Hound
+//!
+//! `read_audio_file` decodes a whole track into memory; `AudioDecoder` hands
+//! it over a packet at a time for work that doesn't need it all at once.
+//! Plain PCM and float WAVs skip Symphonia and are read from a memory map
+//! instead; see `mapped_wav`.
+//! `read_audio_cached` goes through the local cache, for sources whose digest
+//! is known.
+
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::{AudioBufferRef, Signal};
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::cache;
+use crate::limits;
+use crate::mapped_wav::MappedWav;
+
+/// Frames converted at a time from a memory-mapped WAV
+const MAPPED_WINDOW_FRAMES: usize = 4096;
+
+pub use budi_dsp_core::AudioBuffer;
+
+/// Read an audio file and return the decoded samples
+#[tracing::instrument(name = "audio.decode", skip_all)]
+pub fn read_audio_file(path: &Path) -> Result<AudioBuffer> {
+    let mut decoder = AudioDecoder::open(path)?;
+
+    // Streams that don't declare their length are decoded unchecked
+    if let Some(frames) = decoder.n_frames {
+        limits::check_decode(frames, decoder.channels)?;
+    }
+
+    let mut audio_buffer = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    if let Some(frames) = decoder.n_frames {
+        // Growing the planes by doubling would briefly need up to twice this
+        for plane in &mut audio_buffer.samples {
+            plane.reserve_exact(frames as usize);
+        }
+    }
+    while decoder.decode_next(&mut audio_buffer)? {}
+    Ok(audio_buffer)
+}
+
+/// Read a source with hex SHA-256 `sha256`, reusing its decoded audio from
+/// the local cache when an earlier job left it there
+pub fn read_audio_cached(path: &Path, sha256: &str) -> Result<AudioBuffer> {
+    if let Some(buffer) = cache::load_decoded(sha256) {
+        return Ok(buffer);
+    }
+    let buffer = read_audio_file(path)?;
+    cache::store_decoded(sha256, &buffer);
+    Ok(buffer)
+}
+
+/// Packet-by-packet decoder for the first audio track of a file
+///
+/// For work that can consume audio as it is decoded, such as analysis, so
+/// memory is bounded by a packet rather than the length of the file.
+pub struct AudioDecoder {
+    source: Source,
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Length in frames, if the container declares it
+    pub n_frames: Option<u64>,
+}
+
+enum Source {
+    Symphonia {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32,
+    },
+    /// A WAV read a window at a time, from `next_frame` on
+    Mapped { wav: MappedWav, next_frame: u64 },
+}
+
+impl AudioDecoder {
+    /// Probe `path` and set up a decoder for its first audio track
+    pub fn open(path: &Path) -> Result<Self> {
+        match MappedWav::open(path) {
+            Some(wav) => Ok(Self {
+                sample_rate: wav.sample_rate,
+                channels: wav.channels,
+                n_frames: Some(wav.frames),
+                source: Source::Mapped { wav, next_frame: 0 },
+            }),
+            None => Self::open_symphonia(path),
+        }
+    }
+
+    fn open_symphonia(path: &Path) -> Result<Self> {
+        let file = File::open(path).context("Failed to open audio file")?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+        // Create a hint for the file type
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+
+        // Probe the file
+        let format_opts = FormatOptions::default();
+        let metadata_opts = MetadataOptions::default();
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &format_opts, &metadata_opts)
+            .map_err(|e| undecodable(e, "Failed to probe audio format"))?;
+
+        let format = probed.format;
+
+        // Find the first audio track
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .context("No audio track found")
+            .map_err(limits::refused)?;
+
+        let track_id = track.id;
+        let codec_params = track.codec_params.clone();
+
+        // Create decoder
+        let decoder_opts = DecoderOptions::default();
+        let decoder = symphonia::default::get_codecs()
+            .make(&codec_params, &decoder_opts)
+            .map_err(|e| undecodable(e, "Failed to create decoder"))?;
+
+        Ok(Self {
+            source: Source::Symphonia {
+                format,
+                decoder,
+                track_id,
+            },
+            sample_rate: codec_params.sample_rate.unwrap_or(44100),
+            channels: codec_params.channels.map(|c| c.count()).unwrap_or(2),
+            n_frames: codec_params.n_frames,
+        })
+    }
+
+    /// Decode the next packet of the track and append it to `buffer`;
+    /// returns false at the end of the stream
+    pub fn decode_next(&mut self, buffer: &mut AudioBuffer) -> Result<bool> {
+        let (format, decoder, track_id) = match &mut self.source {
+            Source::Symphonia {
+                format,
+                decoder,
+                track_id,
+            } => (format, decoder, *track_id),
+            Source::Mapped { wav, next_frame } => {
+                let read = wav.read(*next_frame, MAPPED_WINDOW_FRAMES, buffer);
+                *next_frame += read as u64;
+                return Ok(read > 0);
+            }
+        };
+        loop {
+            let packet = match format.next_packet() {
+                Ok(p) => p,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(false);
+                }
+                Err(e) => return Err(undecodable(e, "Failed to read audio packet")),
+            };
+
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            let decoded = decoder
+                .decode(&packet)
+                .map_err(|e| undecodable(e, "Failed to decode audio"))?;
+            append_samples(buffer, decoded)?;
+            return Ok(true);
+        }
+    }
+}
+
+/// A Symphonia error as a job failure, refused unless reading the file
+/// failed, since the same bytes fail to decode the same way every time
+fn undecodable(error: SymphoniaError, what: &'static str) -> anyhow::Error {
+    let io = matches!(error, SymphoniaError::IoError(_));
+    let error = anyhow::Error::new(error).context(what);
+    if io {
+        error
+    } else {
+        limits::refused(error)
+    }
+}
+
+/// Append decoded samples to the audio buffer
+fn append_samples(buffer: &mut AudioBuffer, decoded: AudioBufferRef) -> Result<()> {
+    match decoded {
+        AudioBufferRef::F32(buf) => {
+            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
+                let plane = buf.chan(ch);
+                buffer.samples[ch].extend_from_slice(plane);
+            }
+        }
+        AudioBufferRef::S16(buf) => {
+            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
+                let plane = buf.chan(ch);
+                buffer.samples[ch].extend(plane.iter().map(|&s| s as f32 / 32768.0));
+            }
+        }
+        AudioBufferRef::S24(buf) => {
+            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
+                let plane = buf.chan(ch);
+                buffer.samples[ch].extend(plane.iter().map(|&s| s.inner() as f32 / 8388608.0));
+            }
+        }
+        AudioBufferRef::S32(buf) => {
+            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
+                let plane = buf.chan(ch);
+                buffer.samples[ch].extend(plane.iter().map(|&s| s as f32 / 2147483648.0));
+            }
+        }
+        AudioBufferRef::F64(buf) => {
+            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
+                let plane = buf.chan(ch);
+                buffer.samples[ch].extend(plane.iter().map(|&s| s as f32));
+            }
+        }
+        AudioBufferRef::U8(buf) => {
+            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
+                let plane = buf.chan(ch);
+                buffer.samples[ch].extend(plane.iter().map(|&s| (s as f32 - 128.0) / 128.0));
+            }
+        }
+        _ => {
+            return Err(limits::refused(anyhow::anyhow!("Unsupported audio format")));
+        }
+    }
+    Ok(())
+}
+
+/// Read WAV file using hound (for simpler cases)
+pub fn read_wav_file(path: &Path) -> Result<AudioBuffer> {
+    let reader = hound::WavReader::open(path).context("Failed to open WAV file")?;
+    let spec = reader.spec();
+
+    let channels = spec.channels as usize;
+    let sample_rate = spec.sample_rate;
+
+    let mut buffer = AudioBuffer::new(channels, sample_rate);
+
+    match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Int, 16) => {
+            let samples: Vec<i16> = reader
+                .into_samples::<i16>()
+                .filter_map(|s| s.ok())
+                .collect();
+            for (i, sample) in samples.iter().enumerate() {
+                let ch = i % channels;
+                buffer.samples[ch].push(*sample as f32 / 32768.0);
+            }
+        }
+        (SampleFormat::Int, 24) | (SampleFormat::Int, 32) => {
+            let samples: Vec<i32> = reader
+                .into_samples::<i32>()
+                .filter_map(|s| s.ok())
+                .collect();
+            let max_val = if spec.bits_per_sample == 24 {
+                8388608.0
+            } else {
+                2147483648.0
+            };
+            for (i, sample) in samples.iter().enumerate() {
+                let ch = i % channels;
+                buffer.samples[ch].push(*sample as f32 / max_val);
+            }
+        }
+        (SampleFormat::Float, _) => {
+            let samples: Vec<f32> = reader
+                .into_samples::<f32>()
+                .filter_map(|s| s.ok())
+                .collect();
+            for (i, sample) in samples.iter().enumerate() {
+                let ch = i % channels;
+                buffer.samples[ch].push(*sample);
+            }
+        }
+        _ => anyhow::bail!(
+            "Unsupported WAV format: {:?} {}bit",
+            spec.sample_format,
+            spec.bits_per_sample
+        ),
+    }
+
+    Ok(buffer)
+}
+
+/// Write audio buffer to a 32-bit float WAV file, keeping overs intact
+pub fn write_wav_f32(buffer: &AudioBuffer, path: &Path) -> Result<()> {
+    let spec = WavSpec {
+        channels: buffer.channels as u16,
+        sample_rate: buffer.sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec).context("Failed to create WAV file")?;
+    for i in 0..buffer.frame_count() {
+        for ch in &buffer.samples {
+            writer.write_sample(ch[i])?;
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_float_wav_round_trip() {
+        let mut buffer = AudioBuffer::new(2, 48000);
+        for ch in 0..2 {
+            buffer.samples[ch] = (0..4800)
+                .map(|i| (i as f32 * 0.02 * (ch + 1) as f32).sin() * 1.2)
+                .collect();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.wav");
+        write_wav_f32(&buffer, &path).unwrap();
+
+        for decoded in [
+            read_audio_file(&path).unwrap(),
+            read_wav_file(&path).unwrap(),
+        ] {
+            assert_eq!(decoded.channels, 2);
+            assert_eq!(decoded.sample_rate, 48000);
+            assert_eq!(decoded.samples, buffer.samples);
+        }
+    }
+
+    #[test]
+    fn test_undecodable_file_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.mp3");
+        std::fs::write(&path, b"not audio at all, just some text").unwrap();
+        let error = read_audio_file(&path).err().unwrap();
+        assert!(limits::is_refused(&error), "{:#}", error);
+
+        // A file that can't be read may be there next time
+        let error = read_audio_file(&dir.path().join("missing.mp3"))
+            .err()
+            .unwrap();
+        assert!(!limits::is_refused(&error));
+    }
+
+    #[test]
+    fn test_mapped_wav_matches_symphonia() {
+        let dir = tempfile::tempdir().unwrap();
+        let frames = 3 * MAPPED_WINDOW_FRAMES + 100;
+        let formats = [
+            (8, SampleFormat::Int),
+            (16, SampleFormat::Int),
+            (24, SampleFormat::Int),
+            (32, SampleFormat::Int),
+            (32, SampleFormat::Float),
+        ];
+        for (bits, sample_format) in formats {
+            let path = dir.path().join(format!("{}-{:?}.wav", bits, sample_format));
+            let spec = WavSpec {
+                channels: 3,
+                sample_rate: 44100,
+                bits_per_sample: bits,
+                sample_format,
+            };
+            let mut writer = WavWriter::create(&path, spec).unwrap();
+            for i in 0..frames * 3 {
+                let x = (i as f64 * 0.001).sin();
+                match (sample_format, bits) {
+                    (SampleFormat::Float, _) => writer.write_sample(x as f32).unwrap(),
+                    (_, 8) => writer.write_sample((x * 127.0) as i8).unwrap(),
+                    (_, 16) => writer.write_sample((x * 32767.0) as i16).unwrap(),
+                    (_, bits) => {
+                        let full_scale = ((1i64 << (bits - 1)) - 1) as f64;
+                        writer.write_sample((x * full_scale) as i32).unwrap()
+                    }
+                }
+            }
+            writer.finalize().unwrap();
+
+            let mapped = read_audio_file(&path).unwrap();
+            let mut decoder = AudioDecoder::open_symphonia(&path).unwrap();
+            let mut decoded = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+            while decoder.decode_next(&mut decoded).unwrap() {}
+
+            assert!(MappedWav::open(&path).is_some(), "{} bit", bits);
+            assert_eq!(mapped.frame_count(), frames);
+            assert_eq!(mapped.samples, decoded.samples, "{} bit", bits);
+        }
+    }
+}
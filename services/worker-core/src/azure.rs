@@ -0,0 +1,426 @@
+//! Azure Blob Storage, for deployments without an S3 gateway
+//!
+//! Objects are block blobs in `azure.container`, named by their keys, and
+//! their URLs are the plain blob URLs (presigning is S3-only), which the
+//! worker can read back as sources. Requests carry the configured SAS token,
+//! or else a bearer token for the worker's managed identity, fetched from
+//! the platform's identity endpoint and reused until shortly before it
+//! expires.
+//!
+//! Files up to `azure.block_size_mb` go up in one Put Blob; bigger ones are
+//! sent a block at a time, each tried up to `azure.block_attempts` times,
+//! and committed with Put Block List.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use reqwest::{Method, RequestBuilder, Response};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::config::AzureConfig;
+use crate::http;
+use crate::limits;
+use crate::reconnect::Backoff;
+
+/// Blob service REST API version requests are made against
+const API_VERSION: &str = "2021-08-06";
+
+/// Resource managed identity tokens are requested for
+const STORAGE_RESOURCE: &str = "https://storage.azure.com/";
+
+/// Instance metadata service, for managed identity on VMs and AKS
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+
+/// Most blocks one blob can have
+const MAX_BLOCKS: u64 = 50_000;
+
+/// How long before expiry a managed identity token is replaced
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Azure Blob Storage client for one container
+pub(crate) struct AzureBlobClient {
+    client: reqwest::Client,
+    account_url: Url,
+    container: String,
+    auth: Auth,
+    block_size: u64,
+    block_attempts: u32,
+}
+
+enum Auth {
+    /// Query string appended to every request, without its leading `?`
+    Sas(String),
+    ManagedIdentity {
+        client_id: Option<String>,
+        token: Mutex<Option<Token>>,
+    },
+}
+
+struct Token {
+    value: String,
+    refresh_at: Instant,
+}
+
+impl AzureBlobClient {
+    pub(crate) fn new(config: &AzureConfig) -> Result<Self> {
+        let account_url =
+            Url::parse(&config.account_url).context("Invalid Azure storage account URL")?;
+        let auth = match config.sas_token.as_deref() {
+            Some(sas) => Auth::Sas(sas.trim_start_matches('?').to_string()),
+            None => Auth::ManagedIdentity {
+                client_id: config.client_id.clone(),
+                token: Mutex::new(None),
+            },
+        };
+        Ok(Self {
+            client: reqwest::Client::new(),
+            account_url,
+            container: config.container.clone(),
+            auth,
+            block_size: config.block_size_mb * 1024 * 1024,
+            block_attempts: config.block_attempts,
+        })
+    }
+
+    /// Whether `url` is a blob in this storage account
+    pub(crate) fn is_blob_url(&self, url: &str) -> bool {
+        Url::parse(url).is_ok_and(|url| url.origin() == self.account_url.origin())
+    }
+
+    /// Download a blob URL to a local path
+    ///
+    /// A URL that already has a query string is taken to carry its own SAS
+    /// and is fetched as it is.
+    #[tracing::instrument(name = "azure.download", skip(self, local_path))]
+    pub(crate) async fn download_file(&self, url: &str, local_path: &Path) -> Result<()> {
+        tracing::info!("Downloading {} to {:?}", url, local_path);
+        let url = Url::parse(url).context("Invalid blob URL")?;
+        let response = if url.query().is_some() {
+            check(self.client.get(url).send().await?).await
+        } else {
+            self.send(Method::GET, url, |request| request).await
+        }
+        .context("Failed to get blob from Azure")?;
+
+        if let Some(size) = response.content_length() {
+            limits::check_download(local_path, size)?;
+        }
+        http::save_response(response, local_path, false).await
+    }
+
+    /// Upload a file from a local path to `key`, returning its `object_url`
+    #[tracing::instrument(name = "azure.upload", skip(self, local_path, content_type))]
+    pub(crate) async fn upload_file(
+        &self,
+        local_path: &Path,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String> {
+        tracing::info!("Uploading {:?} to {}/{}", local_path, self.container, key);
+        let size = tokio::fs::metadata(local_path)
+            .await
+            .context("Failed to open file for upload")?
+            .len();
+
+        if size <= self.block_size {
+            let data = tokio::fs::read(local_path)
+                .await
+                .context("Failed to read file for upload")?;
+            self.put_blob(data, key, content_type).await?;
+        } else {
+            self.upload_blocks(local_path, size, key, content_type)
+                .await?;
+        }
+        self.object_url(key)
+    }
+
+    /// Upload bytes to `key`, returning their `object_url`
+    #[tracing::instrument(name = "azure.upload", skip(self, data, content_type))]
+    pub(crate) async fn upload_bytes(
+        &self,
+        data: &[u8],
+        key: &str,
+        content_type: &str,
+    ) -> Result<String> {
+        tracing::info!(
+            "Uploading {} bytes to {}/{}",
+            data.len(),
+            self.container,
+            key
+        );
+        self.put_blob(data.to_vec(), key, content_type).await?;
+        self.object_url(key)
+    }
+
+    /// The blob URL of `key`
+    pub(crate) fn object_url(&self, key: &str) -> Result<String> {
+        Ok(self.blob_url(key)?.to_string())
+    }
+
+    async fn put_blob(&self, data: Vec<u8>, key: &str, content_type: &str) -> Result<()> {
+        self.send(Method::PUT, self.blob_url(key)?, |request| {
+            request
+                .header("x-ms-blob-type", "BlockBlob")
+                .header("x-ms-blob-content-type", content_type)
+                .body(data)
+        })
+        .await
+        .context("Failed to upload to Azure")?;
+        Ok(())
+    }
+
+    /// Upload a file in `block_size` blocks and commit them as the blob
+    ///
+    /// Blocks that are never committed are discarded by Azure, so a failed
+    /// upload leaves nothing to clean up.
+    async fn upload_blocks(
+        &self,
+        local_path: &Path,
+        size: u64,
+        key: &str,
+        content_type: &str,
+    ) -> Result<()> {
+        let count = size.div_ceil(self.block_size);
+        anyhow::ensure!(
+            count <= MAX_BLOCKS,
+            "{} bytes is over {} blocks of {} MB (AZURE_BLOCK_SIZE_MB)",
+            size,
+            MAX_BLOCKS,
+            self.block_size / (1024 * 1024)
+        );
+
+        let mut file = tokio::fs::File::open(local_path)
+            .await
+            .context("Failed to open file for upload")?;
+        let mut block_ids = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let offset = index * self.block_size;
+            let length = self.block_size.min(size - offset);
+            let mut data = vec![0; length as usize];
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            file.read_exact(&mut data)
+                .await
+                .context("Failed to read file for upload")?;
+
+            let id = block_id(index);
+            let mut backoff = Backoff::new();
+            let mut attempt = 1;
+            loop {
+                match self.put_block(data.clone(), key, &id).await {
+                    Ok(()) => break,
+                    Err(e) if attempt < self.block_attempts => {
+                        let delay = backoff.next_delay();
+                        tracing::warn!(
+                            "Block {} of {} failed (attempt {} of {}), retrying in {:?}: {:#}",
+                            index + 1,
+                            count,
+                            attempt,
+                            self.block_attempts,
+                            delay,
+                            e
+                        );
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        return Err(e.context(format!(
+                            "Failed to upload block {} of {} to Azure",
+                            index + 1,
+                            count
+                        )))
+                    }
+                }
+            }
+            block_ids.push(id);
+        }
+
+        let mut url = self.blob_url(key)?;
+        url.query_pairs_mut().append_pair("comp", "blocklist");
+        let body = block_list(&block_ids);
+        self.send(Method::PUT, url, |request| {
+            request
+                .header("x-ms-blob-content-type", content_type)
+                .body(body)
+        })
+        .await
+        .context("Failed to commit block list to Azure")?;
+        Ok(())
+    }
+
+    async fn put_block(&self, data: Vec<u8>, key: &str, id: &str) -> Result<()> {
+        let mut url = self.blob_url(key)?;
+        url.query_pairs_mut()
+            .append_pair("comp", "block")
+            .append_pair("blockid", id);
+        self.send(Method::PUT, url, |request| request.body(data))
+            .await?;
+        Ok(())
+    }
+
+    fn blob_url(&self, key: &str) -> Result<Url> {
+        let mut url = self.account_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Azure storage account URL can't have a path"))?
+            .pop_if_empty()
+            .push(&self.container)
+            .extend(key.split('/'));
+        Ok(url)
+    }
+
+    /// Send an authorized request, failing on an error status
+    async fn send(
+        &self,
+        method: Method,
+        mut url: Url,
+        build: impl FnOnce(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Response> {
+        let bearer = match &self.auth {
+            Auth::Sas(sas) => {
+                let query = match url.query() {
+                    Some(query) => format!("{}&{}", query, sas),
+                    None => sas.clone(),
+                };
+                url.set_query(Some(&query));
+                None
+            }
+            Auth::ManagedIdentity { client_id, token } => {
+                Some(self.identity_token(client_id.as_deref(), token).await?)
+            }
+        };
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-ms-version", API_VERSION);
+        if let Some(bearer) = bearer {
+            request = request.bearer_auth(bearer);
+        }
+        check(build(request).send().await?).await
+    }
+
+    /// The managed identity's storage token, fetched when missing or about
+    /// to expire
+    async fn identity_token(
+        &self,
+        client_id: Option<&str>,
+        token: &Mutex<Option<Token>>,
+    ) -> Result<String> {
+        let mut token = token.lock().await;
+        if let Some(cached) = token.as_ref().filter(|t| Instant::now() < t.refresh_at) {
+            return Ok(cached.value.clone());
+        }
+
+        // App Service and Container Apps expose their own endpoint; VMs and
+        // AKS use the instance metadata service
+        let request = match (
+            std::env::var("IDENTITY_ENDPOINT"),
+            std::env::var("IDENTITY_HEADER"),
+        ) {
+            (Ok(endpoint), Ok(header)) => self
+                .client
+                .get(endpoint)
+                .query(&[("api-version", "2019-08-01")])
+                .header("X-IDENTITY-HEADER", header),
+            _ => self
+                .client
+                .get(IMDS_TOKEN_URL)
+                .query(&[("api-version", "2018-02-01")])
+                .header("Metadata", "true"),
+        };
+        let mut request = request.query(&[("resource", STORAGE_RESOURCE)]);
+        if let Some(client_id) = client_id {
+            request = request.query(&[("client_id", client_id)]);
+        }
+        let response: serde_json::Value = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .context("Failed to get a managed identity token")?
+            .json()
+            .await
+            .context("Invalid managed identity token response")?;
+
+        let value = response["access_token"]
+            .as_str()
+            .context("Managed identity token response has no access_token")?
+            .to_string();
+        let lifetime = seconds(&response["expires_in"]).unwrap_or(0);
+        *token = Some(Token {
+            value: value.clone(),
+            refresh_at: Instant::now()
+                + Duration::from_secs(lifetime).saturating_sub(TOKEN_REFRESH_MARGIN),
+        });
+        Ok(value)
+    }
+}
+
+/// Turn an error status into an error naming Azure's error code
+async fn check(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let code = response
+        .headers()
+        .get("x-ms-error-code")
+        .and_then(|code| code.to_str().ok())
+        .unwrap_or("no error code")
+        .to_string();
+    anyhow::bail!("Azure returned {} ({})", status, code)
+}
+
+/// Block IDs must be base64 and the same length for every block of a blob
+fn block_id(index: u64) -> String {
+    base64::engine::general_purpose::STANDARD.encode(format!("{:06}", index))
+}
+
+fn block_list(ids: &[String]) -> String {
+    let latest: String = ids
+        .iter()
+        .map(|id| format!("<Latest>{}</Latest>", id))
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>{}</BlockList>",
+        latest
+    )
+}
+
+/// A token lifetime, which identity endpoints send as a number or a string
+fn seconds(value: &serde_json::Value) -> Option<u64> {
+    value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blob_urls_and_blocks() {
+        let client = AzureBlobClient::new(&AzureConfig {
+            account_url: "https://budi.blob.core.windows.net".to_string(),
+            sas_token: Some("?sv=2021-08-06&sig=abc".to_string()),
+            ..AzureConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            client.object_url("masters/t1/1-master 24bit.wav").unwrap(),
+            "https://budi.blob.core.windows.net/audio/masters/t1/1-master%2024bit.wav"
+        );
+        assert!(client.is_blob_url("https://budi.blob.core.windows.net/audio/a.wav"));
+        assert!(!client.is_blob_url("https://other.blob.core.windows.net/audio/a.wav"));
+        assert!(matches!(&client.auth, Auth::Sas(sas) if sas == "sv=2021-08-06&sig=abc"));
+
+        assert_eq!(block_id(7), "MDAwMDA3");
+        assert_eq!(block_id(7).len(), block_id(49_999).len());
+        assert_eq!(
+            block_list(&[block_id(0)]),
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList><Latest>MDAwMDAw</Latest></BlockList>"
+        );
+        assert_eq!(seconds(&serde_json::json!("3599")), Some(3599));
+        assert_eq!(seconds(&serde_json::json!(86399)), Some(86399));
+    }
+}
@@ -0,0 +1,816 @@
+//! BullMQ-compatible queue mode
+//!
+//! With `queue.mode = "bullmq"` (`QUEUE_MODE=bullmq`) the worker consumes
+//! BullMQ queues directly, so jobs added with BullMQ's `Queue.add` need no
+//! bridge process. Each of `queue.names` is a BullMQ queue under
+//! `queue.bullmq_prefix` (default `bull`), read through BullMQ 5's keys:
+//!
+//! - `{prefix}:{queue}:wait`, `:prioritized` and `:delayed` hold waiting
+//!   jobs; delayed jobs are promoted once due as the worker polls, and a
+//!   paused queue (`paused` in `:meta`) isn't taken from
+//! - `{prefix}:{queue}:{id}` is the job hash. Its `data` is the payload list
+//!   mode takes, with `type` and `jobId` defaulting to the BullMQ job name
+//!   and id.
+//! - A job being processed sits in `:active` with a `{id}:lock` holding this
+//!   worker's token, renewed from its own thread every third of
+//!   `queue.visibility_timeout_seconds` (BullMQ's `lockDuration`)
+//! - Finished jobs move to `:completed`, with the result webhooks they posted
+//!   as `returnvalue`, or to `:failed` with a `failedReason`, honoring
+//!   `removeOnComplete` and `removeOnFail`, and the usual events are added to
+//!   `:events`
+//!
+//! Retries follow each job's own `attempts` and `backoff` options rather than
+//! `queue.max_attempts`, and BullMQ's failed set stands in for the dead
+//! letter queue, so BullMQ tooling sees jobs just as it would with its own
+//! workers. Jobs whose lock lapses because their worker died are handed back
+//! to `:wait` by the stalled check every worker runs, and failed once they
+//! stall more than once. List mode's result cache isn't used, as BullMQ
+//! deduplicates by job id itself.
+
+use anyhow::Result;
+use redis::Script;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::inflight::{self, Keepalive};
+use crate::limits;
+use crate::webhook::WebhookClient;
+use crate::worker::{job_span, parse_job, run_handler, LoopConfig, QueueJob, XorShift};
+
+type Connection = redis::aio::MultiplexedConnection;
+
+/// How long the marker wait blocks before checking for shutdown (seconds)
+const POLL_TIMEOUT_SECS: f64 = 1.0;
+
+/// Times a job may stall before it is failed, as BullMQ's `maxStalledCount`
+const MAX_STALLED_COUNT: u32 = 1;
+
+/// Events kept on a queue's stream, as BullMQ's default `maxLenEvents`
+const MAX_EVENTS: u32 = 10_000;
+
+/// Promote due delayed jobs, then move the next waiting job to `active`
+/// under a lock, returning its id and hash
+///
+/// KEYS: wait, active, prioritized, delayed, meta, events, priority counter
+/// ARGV: key prefix, lock token, lock duration (ms), now (ms), max events
+const MOVE_TO_ACTIVE: &str = r#"
+local base = ARGV[1]
+local now = tonumber(ARGV[4])
+
+-- Delayed scores are the due time in ms times 0x1000 plus a counter
+local due = redis.call("ZRANGEBYSCORE", KEYS[4], 0, (now + 1) * 0x1000 - 1, "LIMIT", 0, 1000)
+for _, id in ipairs(due) do
+  redis.call("ZREM", KEYS[4], id)
+  local priority = tonumber(redis.call("HGET", base .. id, "priority")) or 0
+  if priority > 0 then
+    local counter = redis.call("INCR", KEYS[7])
+    redis.call("ZADD", KEYS[3], priority * 0x100000000 + counter % 0x100000000, id)
+  else
+    redis.call("LPUSH", KEYS[1], id)
+  end
+  redis.call("XADD", KEYS[6], "MAXLEN", "~", ARGV[5], "*", "event", "waiting", "jobId", id, "prev", "delayed")
+end
+
+if redis.call("HEXISTS", KEYS[5], "paused") == 1 then
+  return nil
+end
+
+local id = redis.call("RPOPLPUSH", KEYS[1], KEYS[2])
+if not id then
+  local popped = redis.call("ZPOPMIN", KEYS[3])
+  if #popped == 0 then
+    return nil
+  end
+  id = popped[1]
+  redis.call("LPUSH", KEYS[2], id)
+end
+
+local job = base .. id
+redis.call("SET", job .. ":lock", ARGV[2], "PX", ARGV[3])
+if redis.call("EXISTS", job) == 1 then
+  redis.call("HSET", job, "processedOn", ARGV[4])
+  redis.call("HINCRBY", job, "ats", 1)
+end
+redis.call("XADD", KEYS[6], "MAXLEN", "~", ARGV[5], "*", "event", "active", "jobId", id, "prev", "waiting")
+return {id, redis.call("HGETALL", job)}
+"#;
+
+/// Renew a job's lock if this worker still holds it
+///
+/// KEYS: lock, stalled
+/// ARGV: lock token, lock duration (ms), job id
+const EXTEND_LOCK: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+  redis.call("PEXPIRE", KEYS[1], ARGV[2])
+  redis.call("SREM", KEYS[2], ARGV[3])
+  return 1
+end
+return 0
+"#;
+
+/// Move a job this worker holds to `completed` or `failed`, or return 0 if
+/// its lock has passed to another worker
+///
+/// KEYS: active, completed or failed, job, lock, events, stalled
+/// ARGV: key prefix, job id, lock token, now (ms), `returnvalue` or
+/// `failedReason`, its value, `completed` or `failed`, `removeOnComplete` or
+/// `removeOnFail`, max events
+const MOVE_TO_FINISHED: &str = r#"
+local lock = redis.call("GET", KEYS[4])
+if lock and lock ~= ARGV[3] then
+  return 0
+end
+redis.call("DEL", KEYS[4])
+redis.call("SREM", KEYS[6], ARGV[2])
+redis.call("LREM", KEYS[1], -1, ARGV[2])
+redis.call("XADD", KEYS[5], "MAXLEN", "~", ARGV[9], "*", "event", ARGV[7], "jobId", ARGV[2], ARGV[5], ARGV[6], "prev", "active")
+if redis.call("EXISTS", KEYS[3]) == 0 then
+  return 1
+end
+redis.call("HINCRBY", KEYS[3], "atm", 1)
+redis.call("HSET", KEYS[3], ARGV[5], ARGV[6], "finishedOn", ARGV[4])
+
+local ok, opts = pcall(cjson.decode, redis.call("HGET", KEYS[3], "opts") or "{}")
+local remove = ok and type(opts) == "table" and opts[ARGV[8]]
+local keep
+if remove == true then
+  keep = 0
+elseif type(remove) == "number" then
+  keep = remove
+elseif type(remove) == "table" and type(remove.count) == "number" then
+  keep = remove.count
+end
+
+if keep == 0 then
+  redis.call("DEL", KEYS[3], KEYS[3] .. ":logs")
+  return 1
+end
+redis.call("ZADD", KEYS[2], ARGV[4], ARGV[2])
+if keep and keep > 0 then
+  local stale = redis.call("ZRANGE", KEYS[2], 0, -(keep + 1))
+  for _, old in ipairs(stale) do
+    redis.call("DEL", ARGV[1] .. old, ARGV[1] .. old .. ":logs")
+  end
+  if #stale > 0 then
+    redis.call("ZREMRANGEBYRANK", KEYS[2], 0, -(keep + 1))
+  end
+end
+return 1
+"#;
+
+/// Put a job this worker holds back to wait, for another attempt after
+/// `delay` or (without counting an attempt) because the worker is stopping
+///
+/// KEYS: active, wait, delayed, job, lock, events, stalled, marker
+/// ARGV: job id, lock token, now (ms), failure reason ("" when stopping),
+/// delay (ms), max events
+const MOVE_TO_WAIT: &str = r#"
+local lock = redis.call("GET", KEYS[5])
+if lock and lock ~= ARGV[2] then
+  return 0
+end
+redis.call("DEL", KEYS[5])
+redis.call("SREM", KEYS[7], ARGV[1])
+redis.call("LREM", KEYS[1], -1, ARGV[1])
+
+local delay = tonumber(ARGV[5])
+if ARGV[4] ~= "" then
+  redis.call("HINCRBY", KEYS[4], "atm", 1)
+  redis.call("HSET", KEYS[4], "failedReason", ARGV[4])
+end
+if delay > 0 then
+  local due = tonumber(ARGV[3]) + delay
+  redis.call("ZADD", KEYS[3], due * 0x1000, ARGV[1])
+  redis.call("HSET", KEYS[4], "delay", delay)
+  redis.call("XADD", KEYS[6], "MAXLEN", "~", ARGV[6], "*", "event", "delayed", "jobId", ARGV[1], "delay", due)
+elseif ARGV[4] ~= "" then
+  redis.call("LPUSH", KEYS[2], ARGV[1])
+  redis.call("ZADD", KEYS[8], 0, "0")
+  redis.call("XADD", KEYS[6], "MAXLEN", "~", ARGV[6], "*", "event", "waiting", "jobId", ARGV[1], "prev", "failed")
+else
+  -- Stopping: first in line again, as it was
+  redis.call("RPUSH", KEYS[2], ARGV[1])
+  redis.call("ZADD", KEYS[8], 0, "0")
+  redis.call("XADD", KEYS[6], "MAXLEN", "~", ARGV[6], "*", "event", "waiting", "jobId", ARGV[1], "prev", "active")
+end
+return 1
+"#;
+
+/// BullMQ's stalled check: active jobs marked on the previous check whose
+/// lock has lapsed go back to wait, or to failed if they stalled too often;
+/// then every active job is marked for the next check. Returns the number
+/// recovered and failed, or nothing if another worker checked recently.
+///
+/// KEYS: stalled, active, wait, failed, events, stalled-check, marker
+/// ARGV: key prefix, now (ms), max stalled count, check interval (ms), max
+/// events
+const CHECK_STALLED: &str = r#"
+if not redis.call("SET", KEYS[6], ARGV[2], "PX", ARGV[4], "NX") then
+  return {}
+end
+
+local base = ARGV[1]
+local recovered, failed = 0, 0
+for _, id in ipairs(redis.call("SMEMBERS", KEYS[1])) do
+  local job = base .. id
+  if redis.call("EXISTS", job .. ":lock") == 0 and redis.call("LREM", KEYS[2], 1, id) > 0 then
+    if redis.call("HINCRBY", job, "stc", 1) > tonumber(ARGV[3]) then
+      local reason = "job stalled more than allowable limit"
+      redis.call("ZADD", KEYS[4], ARGV[2], id)
+      redis.call("HSET", job, "failedReason", reason, "finishedOn", ARGV[2])
+      redis.call("XADD", KEYS[5], "MAXLEN", "~", ARGV[5], "*", "event", "failed", "jobId", id, "failedReason", reason, "prev", "active")
+      failed = failed + 1
+    else
+      redis.call("RPUSH", KEYS[3], id)
+      redis.call("ZADD", KEYS[7], 0, "0")
+      redis.call("XADD", KEYS[5], "MAXLEN", "~", ARGV[5], "*", "event", "stalled", "jobId", id)
+      recovered = recovered + 1
+    end
+  end
+end
+
+redis.call("DEL", KEYS[1])
+local active = redis.call("LRANGE", KEYS[2], 0, -1)
+for i = 1, #active, 1000 do
+  redis.call("SADD", KEYS[1], unpack(active, i, math.min(i + 999, #active)))
+end
+return {recovered, failed}
+"#;
+
+/// Key names of one BullMQ queue
+struct QueueKeys {
+    name: String,
+    /// `{prefix}:{queue}:`, which job ids are appended to
+    base: String,
+}
+
+impl QueueKeys {
+    fn new(prefix: &str, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            base: format!("{}:{}:", prefix, name),
+        }
+    }
+
+    fn key(&self, suffix: &str) -> String {
+        format!("{}{}", self.base, suffix)
+    }
+
+    fn lock(&self, id: &str) -> String {
+        format!("{}{}:lock", self.base, id)
+    }
+}
+
+struct Scripts {
+    move_to_active: Script,
+    move_to_finished: Script,
+    move_to_wait: Script,
+    check_stalled: Script,
+}
+
+impl Scripts {
+    fn new() -> Self {
+        Self {
+            move_to_active: Script::new(MOVE_TO_ACTIVE),
+            move_to_finished: Script::new(MOVE_TO_FINISHED),
+            move_to_wait: Script::new(MOVE_TO_WAIT),
+            check_stalled: Script::new(CHECK_STALLED),
+        }
+    }
+}
+
+/// The options BullMQ's `Queue.add` stores that affect how a job is run
+#[derive(Debug, Default, Deserialize)]
+struct JobOptions {
+    /// Total attempts, 1 when unset
+    attempts: Option<u32>,
+    backoff: Option<BackoffOption>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BackoffOption {
+    /// A fixed delay (ms)
+    Fixed(u64),
+    Strategy {
+        #[serde(rename = "type")]
+        kind: String,
+        #[serde(default)]
+        delay: u64,
+    },
+}
+
+impl JobOptions {
+    fn max_attempts(&self) -> u32 {
+        self.attempts.unwrap_or(1).max(1)
+    }
+
+    /// Wait before the next attempt of a job that has failed `attempts_made`
+    /// times, as BullMQ's built-in strategies compute it; custom strategies
+    /// live in the producer's code, so they retry immediately
+    fn retry_delay(&self, attempts_made: u32) -> Duration {
+        let ms = match &self.backoff {
+            None => 0,
+            Some(BackoffOption::Fixed(delay)) => *delay,
+            Some(BackoffOption::Strategy { kind, delay }) => match kind.as_str() {
+                "fixed" => *delay,
+                "exponential" => delay.saturating_mul(1 << attempts_made.saturating_sub(1).min(32)),
+                _ => 0,
+            },
+        };
+        Duration::from_millis(ms)
+    }
+}
+
+/// The payload a BullMQ job carries: its `data`, with `type` and `jobId`
+/// taken from the job's name and id when missing
+fn job_payload(id: &str, fields: &HashMap<String, String>) -> String {
+    let Some(data) = fields.get("data") else {
+        return String::new();
+    };
+    let Ok(serde_json::Value::Object(mut payload)) = serde_json::from_str(data) else {
+        return data.clone();
+    };
+    if let Some(name) = fields.get("name") {
+        payload
+            .entry("type")
+            .or_insert_with(|| serde_json::Value::String(name.clone()));
+    }
+    payload
+        .entry("jobId")
+        .or_insert_with(|| serde_json::Value::String(id.to_string()));
+    serde_json::Value::Object(payload).to_string()
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Renew a job's lock every third of its duration while it runs
+fn keep_locked(
+    client: &redis::Client,
+    keys: &QueueKeys,
+    id: &str,
+    token: &str,
+    duration: Duration,
+) -> Keepalive {
+    let script = Script::new(EXTEND_LOCK);
+    let (lock, stalled) = (keys.lock(id), keys.key("stalled"));
+    let (id, token) = (id.to_string(), token.to_string());
+    let what = format!("the lock on BullMQ job {}", id);
+    Keepalive::start(client, what, duration / 3, move |conn| {
+        let held: i32 = script
+            .key(&lock)
+            .key(&stalled)
+            .arg(&token)
+            .arg(duration.as_millis() as u64)
+            .arg(&id)
+            .invoke(conn)?;
+        Ok(held == 1)
+    })
+}
+
+/// Process BullMQ jobs over one Redis connection until shutdown or a Redis
+/// error
+pub(crate) async fn process_jobs<J, F, Fut>(
+    task: u64,
+    client: redis::Client,
+    mut conn: Connection,
+    config: Arc<LoopConfig>,
+    webhook: &'static WebhookClient,
+    handler: Arc<F>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let scripts = Scripts::new();
+    let queues: HashMap<&str, QueueKeys> = config
+        .queues
+        .iter()
+        .map(|q| {
+            (
+                q.name.as_str(),
+                QueueKeys::new(&config.bullmq_prefix, &q.name),
+            )
+        })
+        .collect();
+    let lock_duration = config.visibility;
+    let consumer = inflight::consumer(&config.worker, task);
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    let mut rng = XorShift::new(seed ^ task.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let mut tokens = 0u64;
+    let mut last_check: Option<Instant> = None;
+
+    loop {
+        if *shutdown.borrow() {
+            break;
+        }
+
+        // BullMQ's own workers run the same check, guarded so only one
+        // worker does it per interval
+        if task == 0 && last_check.is_none_or(|t| t.elapsed() >= lock_duration) {
+            for keys in queues.values() {
+                check_stalled(&mut conn, &scripts, keys, lock_duration).await?;
+            }
+            last_check = Some(Instant::now());
+        }
+
+        tokens += 1;
+        let token = format!("{}:{}", consumer, tokens);
+        let order = config.poll_order(&mut rng);
+        let mut popped = None;
+        for name in &order {
+            let keys = &queues[name];
+            let active: Option<(String, HashMap<String, String>)> = scripts
+                .move_to_active
+                .key(keys.key("wait"))
+                .key(keys.key("active"))
+                .key(keys.key("prioritized"))
+                .key(keys.key("delayed"))
+                .key(keys.key("meta"))
+                .key(keys.key("events"))
+                .key(keys.key("pc"))
+                .arg(&keys.base)
+                .arg(&token)
+                .arg(lock_duration.as_millis() as u64)
+                .arg(now_ms())
+                .arg(MAX_EVENTS)
+                .invoke_async(&mut conn)
+                .await?;
+            if let Some((id, fields)) = active {
+                popped = Some((keys, id, fields));
+                break;
+            }
+        }
+
+        let Some((keys, id, fields)) = popped else {
+            // BullMQ adds to the marker whenever a job becomes ready; older
+            // producers don't, which the timeout covers
+            let marker = queues[order[0]].key("marker");
+            redis::cmd("BZPOPMIN")
+                .arg(marker)
+                .arg(POLL_TIMEOUT_SECS)
+                .query_async::<_, redis::Value>(&mut conn)
+                .await?;
+            continue;
+        };
+
+        let keeper = keep_locked(&client, keys, &id, &token, lock_duration);
+        let outcome = run_job(
+            &mut conn, &scripts, keys, &id, &token, &fields, &config, webhook, &*handler, &shutdown,
+        )
+        .await;
+        drop(keeper);
+        if !outcome? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Run one job taken from a BullMQ queue and record how it ended; returns
+/// whether to keep taking jobs
+#[allow(clippy::too_many_arguments)]
+async fn run_job<J, F, Fut>(
+    conn: &mut Connection,
+    scripts: &Scripts,
+    keys: &QueueKeys,
+    id: &str,
+    token: &str,
+    fields: &HashMap<String, String>,
+    config: &LoopConfig,
+    webhook: &'static WebhookClient,
+    handler: &F,
+    shutdown: &watch::Receiver<bool>,
+) -> Result<bool>
+where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    if fields.is_empty() {
+        warn!(
+            "BullMQ job {} on {} was removed while waiting",
+            id, keys.name
+        );
+        finish(
+            conn,
+            scripts,
+            keys,
+            id,
+            token,
+            Finished::Failed("job removed"),
+        )
+        .await?;
+        return Ok(true);
+    }
+
+    let payload = job_payload(id, fields);
+    let job = match parse_job::<J>(&payload) {
+        Ok(job) => job,
+        Err(rejection) => {
+            error!("Rejected BullMQ job {}: {}", id, rejection.error);
+            warn!("Payload was: {}", payload);
+            finish(
+                conn,
+                scripts,
+                keys,
+                id,
+                token,
+                Finished::Failed(&rejection.error),
+            )
+            .await?;
+            if let (Some(job_id), Some(endpoint)) = (&rejection.job_id, rejection.endpoint) {
+                if let Err(we) = webhook
+                    .report_rejected(job_id, endpoint, &rejection, J::SCHEMA_VERSION)
+                    .await
+                {
+                    error!("Failed to report rejected job: {:?}", we);
+                }
+            }
+            return Ok(true);
+        }
+    };
+
+    let options: JobOptions = fields
+        .get("opts")
+        .and_then(|opts| serde_json::from_str(opts).ok())
+        .unwrap_or_default();
+    let attempts_made: u32 = fields
+        .get("atm")
+        .or_else(|| fields.get("attemptsMade"))
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    let max_attempts = options.max_attempts();
+    let traceparent = serde_json::from_str::<serde_json::Value>(&payload)
+        .ok()
+        .and_then(|p| p.get("traceparent")?.as_str().map(String::from));
+
+    let job_id = job.job_id().to_string();
+    let job_type = job.job_type();
+    info!(
+        "Processing {} job {} (BullMQ job {} on {}, attempt {} of {})",
+        job_type,
+        job_id,
+        id,
+        keys.name,
+        attempts_made + 1,
+        max_attempts
+    );
+
+    let span = job_span(
+        job_type,
+        &job_id,
+        &keys.name,
+        attempts_made + 1,
+        traceparent.as_deref(),
+    );
+    let Some(result) = run_handler(handler, job, span, shutdown, config.grace).await else {
+        warn!(
+            "Job {} didn't finish within {:?} of shutdown",
+            job_id, config.grace
+        );
+        move_to_wait(conn, scripts, keys, id, token, "", Duration::ZERO).await?;
+        info!("Returned unfinished BullMQ job {} to {}", id, keys.name);
+        return Ok(false);
+    };
+
+    match result {
+        Ok(posts) => {
+            let returnvalue = serde_json::to_string(&posts)?;
+            finish(
+                conn,
+                scripts,
+                keys,
+                id,
+                token,
+                Finished::Completed(&returnvalue),
+            )
+            .await?;
+        }
+        Err(e) if attempts_made + 1 < max_attempts && !limits::is_refused(&e) => {
+            let delay = options.retry_delay(attempts_made + 1);
+            warn!(
+                "Job {} failed (attempt {} of {}), retrying in {:?}: {:?}",
+                job_id,
+                attempts_made + 1,
+                max_attempts,
+                delay,
+                e
+            );
+            let reason = format!("{:#}", e);
+            move_to_wait(conn, scripts, keys, id, token, &reason, delay).await?;
+        }
+        Err(e) => {
+            if limits::is_refused(&e) {
+                error!("Job {} refused: {:?}", job_id, e);
+            } else {
+                error!(
+                    "Job {} failed after {} attempts: {:?}",
+                    job_id,
+                    attempts_made + 1,
+                    e
+                );
+            }
+            let reason = format!("{:#}", e);
+            finish(conn, scripts, keys, id, token, Finished::Failed(&reason)).await?;
+            if let Err(we) = webhook
+                .report_failure(&job_id, job_type, &e.to_string())
+                .await
+            {
+                error!("Failed to report job failure: {:?}", we);
+            }
+        }
+    }
+    Ok(true)
+}
+
+enum Finished<'a> {
+    /// With the job's return value (JSON)
+    Completed(&'a str),
+    /// With the failure reason
+    Failed(&'a str),
+}
+
+async fn finish(
+    conn: &mut Connection,
+    scripts: &Scripts,
+    keys: &QueueKeys,
+    id: &str,
+    token: &str,
+    finished: Finished<'_>,
+) -> Result<()> {
+    let (set, field, value, event, remove) = match finished {
+        Finished::Completed(value) => (
+            "completed",
+            "returnvalue",
+            value,
+            "completed",
+            "removeOnComplete",
+        ),
+        Finished::Failed(reason) => ("failed", "failedReason", reason, "failed", "removeOnFail"),
+    };
+    let held: i32 = scripts
+        .move_to_finished
+        .key(keys.key("active"))
+        .key(keys.key(set))
+        .key(keys.key(id))
+        .key(keys.lock(id))
+        .key(keys.key("events"))
+        .key(keys.key("stalled"))
+        .arg(&keys.base)
+        .arg(id)
+        .arg(token)
+        .arg(now_ms())
+        .arg(field)
+        .arg(value)
+        .arg(event)
+        .arg(remove)
+        .arg(MAX_EVENTS)
+        .invoke_async(conn)
+        .await?;
+    if held == 0 {
+        warn!(
+            "BullMQ job {} on {} was taken over by another worker before it finished",
+            id, keys.name
+        );
+    }
+    Ok(())
+}
+
+/// Put a job back to wait: after `delay` for a retry, or at the front of the
+/// line when `reason` is empty because the worker is stopping
+async fn move_to_wait(
+    conn: &mut Connection,
+    scripts: &Scripts,
+    keys: &QueueKeys,
+    id: &str,
+    token: &str,
+    reason: &str,
+    delay: Duration,
+) -> Result<()> {
+    let held: i32 = scripts
+        .move_to_wait
+        .key(keys.key("active"))
+        .key(keys.key("wait"))
+        .key(keys.key("delayed"))
+        .key(keys.key(id))
+        .key(keys.lock(id))
+        .key(keys.key("events"))
+        .key(keys.key("stalled"))
+        .key(keys.key("marker"))
+        .arg(id)
+        .arg(token)
+        .arg(now_ms())
+        .arg(reason)
+        .arg(delay.as_millis() as u64)
+        .arg(MAX_EVENTS)
+        .invoke_async(conn)
+        .await?;
+    if held == 0 {
+        warn!(
+            "BullMQ job {} on {} was taken over by another worker before it was returned",
+            id, keys.name
+        );
+    }
+    Ok(())
+}
+
+async fn check_stalled(
+    conn: &mut Connection,
+    scripts: &Scripts,
+    keys: &QueueKeys,
+    interval: Duration,
+) -> Result<()> {
+    let counts: Vec<u32> = scripts
+        .check_stalled
+        .key(keys.key("stalled"))
+        .key(keys.key("active"))
+        .key(keys.key("wait"))
+        .key(keys.key("failed"))
+        .key(keys.key("events"))
+        .key(keys.key("stalled-check"))
+        .key(keys.key("marker"))
+        .arg(&keys.base)
+        .arg(now_ms())
+        .arg(MAX_STALLED_COUNT)
+        .arg(interval.as_millis() as u64)
+        .arg(MAX_EVENTS)
+        .invoke_async(conn)
+        .await?;
+    if let [recovered, failed] = counts[..] {
+        if recovered > 0 {
+            warn!(
+                "Returned {} stalled BullMQ jobs to {}",
+                recovered, keys.name
+            );
+        }
+        if failed > 0 {
+            warn!(
+                "Failed {} BullMQ jobs on {} that stalled too often",
+                failed, keys.name
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_payload_and_options() {
+        let fields = |data: &str, opts: &str| {
+            HashMap::from([
+                ("name".to_string(), "analyze".to_string()),
+                ("data".to_string(), data.to_string()),
+                ("opts".to_string(), opts.to_string()),
+            ])
+        };
+
+        // Type and job id default to the BullMQ name and id
+        let payload: serde_json::Value =
+            serde_json::from_str(&job_payload("42", &fields(r#"{"trackId":"t1"}"#, "{}"))).unwrap();
+        assert_eq!(payload["type"], "analyze");
+        assert_eq!(payload["jobId"], "42");
+        let payload: serde_json::Value = serde_json::from_str(&job_payload(
+            "42",
+            &fields(r#"{"type":"fix","jobId":"j1"}"#, "{}"),
+        ))
+        .unwrap();
+        assert_eq!(
+            (&payload["type"], &payload["jobId"]),
+            (&"fix".into(), &"j1".into())
+        );
+        assert_eq!(job_payload("42", &fields("not json", "{}")), "not json");
+
+        let options = |opts: &str| serde_json::from_str::<JobOptions>(opts).unwrap();
+        assert_eq!(options("{}").max_attempts(), 1);
+        let exponential =
+            options(r#"{"attempts":4,"backoff":{"type":"exponential","delay":1000}}"#);
+        assert_eq!(exponential.max_attempts(), 4);
+        let delays: Vec<u64> = (1..=3)
+            .map(|a| exponential.retry_delay(a).as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [1000, 2000, 4000]);
+        assert_eq!(
+            options(r#"{"backoff":500}"#).retry_delay(3),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            options(r#"{"backoff":{"type":"custom"}}"#).retry_delay(1),
+            Duration::ZERO
+        );
+    }
+}
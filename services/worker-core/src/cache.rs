@@ -0,0 +1,267 @@
+//! Local cache of sources and their decoded audio, keyed by SHA-256
+//!
+//! Analyze, fix and master jobs often run back to back on the same source.
+//! With `cache.dir` set, every downloaded source is kept there under its
+//! digest, and so is the audio decoded from it: a later job that gives the
+//! source's `sourceSha256` skips the download, and one that decodes it skips
+//! the decode. The directory is held to `cache.max_mb` by evicting the least
+//! recently used entries.
+//!
+//! Entries are written under a temporary name and renamed into place, so
+//! several workers on a host can share a directory. The cache is only an
+//! optimization: a failure to read or write it is logged and the job goes on
+//! without it.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use tracing::{debug, warn};
+
+use crate::audio::AudioBuffer;
+use crate::config::CacheConfig;
+use crate::limits;
+
+const MB: u64 = 1024 * 1024;
+
+/// Extensions of cached sources and decoded audio
+const SOURCE_EXT: &str = "src";
+const DECODED_EXT: &str = "pcm";
+
+/// First bytes of a decoded entry: then channels and sample rate (u32) and
+/// frames (u64), followed by each channel's f32 samples in turn, all
+/// little-endian
+const DECODED_MAGIC: &[u8; 8] = b"BUDIPCM1";
+
+/// Prefix of entries still being written; never read or evicted
+const PARTIAL_PREFIX: &str = ".partial-";
+
+static CACHE: OnceLock<CacheConfig> = OnceLock::new();
+
+/// Use the cache from `config` for the rest of the process
+pub fn init(config: &CacheConfig) {
+    let _ = CACHE.set(config.clone());
+}
+
+/// The cache directory and its size limit in bytes, if caching is on
+fn settings() -> Option<(&'static Path, u64)> {
+    let config = CACHE.get()?;
+    let dir = config.dir.as_deref()?;
+    Some((Path::new(dir), config.max_mb * MB))
+}
+
+/// Path of the entry for `sha256`, or `None` if caching is off or the digest
+/// isn't one (it may come straight from a job)
+fn entry_path(sha256: &str, ext: &str) -> Option<PathBuf> {
+    let (dir, _) = settings()?;
+    let sha256 = sha256.trim().to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(dir.join(format!("{}.{}", sha256, ext)))
+}
+
+/// Copy the cached source with digest `sha256` to `path`; false on a miss
+pub(crate) fn fetch_source(sha256: &str, path: &Path) -> bool {
+    let Some(entry) = entry_path(sha256, SOURCE_EXT) else {
+        return false;
+    };
+    if !touch(&entry) {
+        return false;
+    }
+    match std::fs::copy(&entry, path) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Failed to copy cached {}: {:?}", entry.display(), e);
+            false
+        }
+    }
+}
+
+/// Keep a copy of the downloaded source at `path`
+pub(crate) fn store_source(sha256: &str, path: &Path) {
+    if let Some(entry) = entry_path(sha256, SOURCE_EXT) {
+        store(&entry, |partial| {
+            std::fs::copy(path, partial)?;
+            Ok(())
+        });
+    }
+}
+
+/// Drop the source with digest `sha256`, e.g. when its copy no longer matches
+pub(crate) fn remove_source(sha256: &str) {
+    if let Some(entry) = entry_path(sha256, SOURCE_EXT) {
+        let _ = std::fs::remove_file(entry);
+    }
+}
+
+/// Audio decoded from the source with digest `sha256`, if it is cached
+pub(crate) fn load_decoded(sha256: &str) -> Option<AudioBuffer> {
+    let entry = entry_path(sha256, DECODED_EXT)?;
+    if !touch(&entry) {
+        return None;
+    }
+    match read_decoded(&entry) {
+        Ok(buffer) => Some(buffer),
+        Err(e) if limits::is_refused(&e) => None,
+        Err(e) => {
+            warn!("Discarding unreadable {}: {:?}", entry.display(), e);
+            let _ = std::fs::remove_file(&entry);
+            None
+        }
+    }
+}
+
+/// Keep `buffer` as the audio decoded from the source with digest `sha256`
+pub(crate) fn store_decoded(sha256: &str, buffer: &AudioBuffer) {
+    if let Some(entry) = entry_path(sha256, DECODED_EXT) {
+        store(&entry, |partial| write_decoded(partial, buffer));
+    }
+}
+
+/// Mark `entry` as just used; false if it isn't there
+fn touch(entry: &Path) -> bool {
+    match File::options().append(true).open(entry) {
+        Ok(file) => {
+            let _ = file.set_modified(SystemTime::now());
+            debug!("Cache hit for {}", entry.display());
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Write an entry with `write`, then evict down to the size limit
+fn store(entry: &Path, write: impl FnOnce(&Path) -> Result<()>) {
+    let Some((dir, max_bytes)) = settings() else {
+        return;
+    };
+    let stored = std::fs::create_dir_all(dir)
+        .context("Failed to create cache directory")
+        .and_then(|_| {
+            let partial = tempfile::Builder::new()
+                .prefix(PARTIAL_PREFIX)
+                .tempfile_in(dir)?
+                .into_temp_path();
+            write(&partial)?;
+            partial.persist(entry)?;
+            Ok(())
+        });
+    if let Err(e) = stored {
+        warn!("Failed to cache {}: {:?}", entry.display(), e);
+        return;
+    }
+    if let Err(e) = evict(dir, max_bytes) {
+        warn!("Failed to evict from cache {}: {:?}", dir.display(), e);
+    }
+}
+
+/// Remove the least recently used entries until the rest fit in `max_bytes`
+fn evict(dir: &Path, max_bytes: u64) -> Result<()> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(PARTIAL_PREFIX)
+        {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            entries.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+    }
+
+    let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    entries.sort();
+    for (_, len, path) in entries {
+        if total <= max_bytes {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => debug!("Evicted {} from cache", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(format!("Failed to remove {}", path.display())),
+        }
+        total = total.saturating_sub(len);
+    }
+    Ok(())
+}
+
+fn write_decoded(path: &Path, buffer: &AudioBuffer) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(DECODED_MAGIC)?;
+    writer.write_all(&(buffer.channels as u32).to_le_bytes())?;
+    writer.write_all(&buffer.sample_rate.to_le_bytes())?;
+    writer.write_all(&(buffer.frame_count() as u64).to_le_bytes())?;
+    for plane in &buffer.samples {
+        for sample in plane {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_decoded(path: &Path) -> Result<AudioBuffer> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 24];
+    reader.read_exact(&mut header)?;
+    anyhow::ensure!(&header[..8] == DECODED_MAGIC, "Not a decoded audio entry");
+    let channels = u32::from_le_bytes(header[8..12].try_into()?) as usize;
+    let sample_rate = u32::from_le_bytes(header[12..16].try_into()?);
+    let frames = u64::from_le_bytes(header[16..24].try_into()?);
+    limits::check_decode(frames, channels)?;
+
+    let mut buffer = AudioBuffer::new(channels, sample_rate);
+    let mut bytes = vec![0u8; frames as usize * 4];
+    for plane in &mut buffer.samples {
+        reader.read_exact(&mut bytes)?;
+        *plane = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoded_round_trip_and_eviction() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut buffer = AudioBuffer::new(2, 48000);
+        buffer.samples = vec![vec![0.25, -1.0, 0.5], vec![0.0, 1.0, -0.125]];
+        let path = dir.path().join("a.pcm");
+        write_decoded(&path, &buffer).unwrap();
+        let read = read_decoded(&path).unwrap();
+        assert_eq!(read.samples, buffer.samples);
+        assert_eq!((read.channels, read.sample_rate), (2, 48000));
+
+        // The oldest entries go first, and only as many as needed
+        for (name, age_secs) in [("old", 300), ("mid", 200), ("new", 100)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, vec![0u8; 1000]).unwrap();
+            let modified = SystemTime::now() - std::time::Duration::from_secs(age_secs);
+            File::options()
+                .append(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        std::fs::write(dir.path().join(".partial-x"), vec![0u8; 5000]).unwrap();
+        evict(dir.path(), 2100).unwrap();
+        assert!(!dir.path().join("old").exists());
+        assert!(dir.path().join("mid").exists());
+        assert!(dir.path().join("new").exists());
+        assert!(dir.path().join(".partial-x").exists());
+    }
+}
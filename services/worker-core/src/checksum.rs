@@ -0,0 +1,77 @@
+//! SHA-256 digests of sources and artifacts
+//!
+//! Every downloaded source and uploaded artifact is hashed, and the hex
+//! digests go out in the result webhooks so downstream systems can verify
+//! what they fetch. A job can also give a source's expected digest; a
+//! download that doesn't match fails the attempt, which is retried like any
+//! other failed download, since the copy may have been damaged on the way.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+use crate::s3::DOWNLOAD_BUFFER_BYTES;
+
+/// Hex SHA-256 of a file, read a buffer at a time
+pub async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .context("Failed to open file to hash")?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; DOWNLOAD_BUFFER_BYTES];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .context("Failed to read file to hash")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hex SHA-256 of bytes
+pub fn sha256_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Check a source's digest against the one its job gave, if any
+pub(crate) fn verify(url: &str, actual: &str, expected: Option<&str>) -> Result<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    anyhow::ensure!(
+        actual.eq_ignore_ascii_case(expected.trim()),
+        "Checksum mismatch for {}: expected SHA-256 {}, got {}",
+        url,
+        expected,
+        actual
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_digests_and_verification() {
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(sha256_bytes(b""), empty);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("source.wav");
+        let data: Vec<u8> = (0..3 * DOWNLOAD_BUFFER_BYTES).map(|i| i as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        let digest = sha256_file(&path).await.unwrap();
+        assert_eq!(digest, sha256_bytes(&data));
+
+        assert!(verify("s3://audio/a.wav", &digest, None).is_ok());
+        assert!(verify("s3://audio/a.wav", &digest, Some(&digest.to_uppercase())).is_ok());
+        let error = verify("s3://audio/a.wav", &digest, Some(empty)).unwrap_err();
+        assert!(error.to_string().contains("Checksum mismatch"));
+    }
+}
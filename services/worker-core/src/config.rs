@@ -0,0 +1,1253 @@
+//! Worker configuration
+//!
+//! Settings come from an optional TOML or YAML file named by `BUDI_CONFIG`
+//! (the format follows its extension), with the environment variables the
+//! workers have always read overriding it, so an existing `.env` keeps
+//! working. Everything is checked once at startup, so a typo fails the
+//! deploy rather than the first job.
+//!
+//! ```toml
+//! [redis]
+//! url = "redis://localhost:6379"
+//!
+//! [queue]
+//! names = "dsp-jobs-high:4,dsp-jobs:1"
+//! concurrency = 2
+//!
+//! [s3]
+//! endpoint = "http://minio:9000"
+//! bucket = "audio"
+//!
+//! [webhook]
+//! api_url = "http://api:4000"
+//! ```
+//!
+//! Workers add sections of their own, read with `Config::section`.
+
+use anyhow::{Context, Result};
+use redis::IntoConnectionInfo;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::worker;
+
+/// Environment variable naming the config file
+pub const CONFIG_PATH_VAR: &str = "BUDI_CONFIG";
+
+/// Sections every worker understands
+const CORE_SECTIONS: [&str; 11] = [
+    "redis", "queue", "storage", "s3", "azure", "webhook", "limits", "temp", "cache", "kafka",
+    "grpc",
+];
+
+/// Default time an in-flight job gets to finish after SIGTERM, kept under
+/// Kubernetes' 30 second termination grace period
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 25;
+
+/// Default time a worker can go without a heartbeat before its jobs are reaped
+const DEFAULT_VISIBILITY_TIMEOUT_SECS: u64 = 60;
+
+/// Default number of times a job is run before it is reported failed
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// Default wait before the first retry; doubles with each further attempt
+const DEFAULT_RETRY_BASE_SECS: u64 = 5;
+
+/// Default time a finished job's results are kept for duplicates (seconds)
+const DEFAULT_RESULT_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Default size of a multipart upload's parts (MB)
+const DEFAULT_S3_PART_SIZE_MB: u64 = 16;
+
+/// S3's bounds on part size (MB)
+const MIN_S3_PART_SIZE_MB: u64 = 5;
+
+const MAX_S3_PART_SIZE_MB: u64 = 5 * 1024;
+
+/// Default times each part of an upload is tried
+const DEFAULT_S3_PART_ATTEMPTS: u32 = 3;
+
+/// Longest a presigned URL can last under SigV4, and the default
+const MAX_S3_PRESIGN_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Default times each S3 request is tried when S3 throttles or fails
+const DEFAULT_S3_MAX_ATTEMPTS: u32 = 5;
+
+/// Default wait before the first S3 request retry (milliseconds)
+const DEFAULT_S3_RETRY_BASE_MS: u64 = 1000;
+
+/// Default time to open a connection to S3
+const DEFAULT_S3_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// Default time S3 may go without sending data on a request
+const DEFAULT_S3_READ_TIMEOUT_SECS: u64 = 60;
+
+/// Default number of S3 requests a worker has in flight at once
+const DEFAULT_S3_MAX_CONNECTIONS: usize = 32;
+
+/// Default size of the blocks bigger Azure uploads are sent in (MB)
+const DEFAULT_AZURE_BLOCK_SIZE_MB: u64 = 16;
+
+/// Largest block size allowed (MB); blocks are held in memory while sent
+const MAX_AZURE_BLOCK_SIZE_MB: u64 = 256;
+
+/// Default times each block of an Azure upload is tried
+const DEFAULT_AZURE_BLOCK_ATTEMPTS: u32 = 3;
+
+/// Default largest decoded audio a job may hold (MB), about three hours of
+/// 96 kHz stereo
+const DEFAULT_MAX_DECODED_MB: u64 = 4096;
+
+/// Default disk space a download must leave free (MB)
+const DEFAULT_MIN_FREE_DISK_MB: u64 = 1024;
+
+/// Default largest file fetched from a plain HTTP(S) source URL (MB)
+const DEFAULT_MAX_HTTP_DOWNLOAD_MB: u64 = 2048;
+
+/// Default age after which a job's temp directory counts as orphaned
+const DEFAULT_TEMP_MAX_AGE_SECS: u64 = 6 * 60 * 60;
+
+/// Default time between sweeps for orphaned temp directories
+const DEFAULT_TEMP_SWEEP_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Default size the source cache is held to (MB)
+const DEFAULT_CACHE_MAX_MB: u64 = 10 * 1024;
+
+/// Default longest time a Kafka consumer may spend on one job
+const DEFAULT_KAFKA_MAX_POLL_INTERVAL_SECS: u64 = 60 * 60;
+
+/// librdkafka's bounds on `max.poll.interval.ms`, in seconds; it can't be
+/// under the default session timeout
+const MIN_KAFKA_MAX_POLL_INTERVAL_SECS: u64 = 60;
+
+const MAX_KAFKA_MAX_POLL_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+/// Default number of jobs the gRPC job API holds waiting at once
+const DEFAULT_GRPC_MAX_QUEUED: usize = 1000;
+
+/// Validated worker settings
+#[derive(Clone)]
+pub struct Config {
+    pub redis: RedisConfig,
+    pub queue: QueueConfig,
+    pub storage: StorageConfig,
+    pub s3: S3Config,
+    pub azure: AzureConfig,
+    pub webhook: WebhookConfig,
+    pub limits: LimitsConfig,
+    pub temp: TempConfig,
+    pub cache: CacheConfig,
+    pub kafka: KafkaConfig,
+    pub grpc: GrpcConfig,
+    /// Where the file settings came from, for error messages
+    source: String,
+    /// The whole file, for worker-specific sections
+    sections: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// `REDIS_URL`
+    pub url: String,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://localhost:6379".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    /// `QUEUE_MODE`: where jobs come from
+    pub mode: QueueMode,
+    /// Queues to poll, in the worker's queue variable (`DSP_QUEUE`,
+    /// `CODEC_QUEUE`); see `worker::parse_queues` for the format
+    pub names: String,
+    /// `WORKER_CONCURRENCY`: jobs processed at once (default one per CPU)
+    pub concurrency: Option<usize>,
+    /// `SHUTDOWN_GRACE_SECONDS`: time in-flight jobs get after SIGTERM
+    pub shutdown_grace_seconds: u64,
+    /// `JOB_VISIBILITY_TIMEOUT_SECONDS`: time a worker can miss heartbeats
+    /// before other workers re-queue its jobs
+    pub visibility_timeout_seconds: u64,
+    /// `JOB_MAX_ATTEMPTS`: runs per job before it is reported failed
+    pub max_attempts: u32,
+    /// `JOB_RETRY_BASE_SECONDS`: wait before the first retry
+    pub retry_base_seconds: u64,
+    /// `DEAD_LETTER_QUEUE` (default `{first queue}:dead`, or the
+    /// `{first topic}.dead` topic in `kafka` mode)
+    pub dead_letter_queue: Option<String>,
+    /// `JOB_RESULT_TTL_SECONDS`: time finished jobs' results are kept
+    pub result_ttl_seconds: u64,
+    /// `BULLMQ_PREFIX`: key prefix of BullMQ queues, in `bullmq` mode
+    pub bullmq_prefix: String,
+    /// `STREAM_GROUP`: consumer group workers share, in `stream` mode
+    pub stream_group: String,
+}
+
+/// Where jobs come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueMode {
+    /// Lists of JSON payloads, as the API enqueues them
+    #[default]
+    List,
+    /// BullMQ queues, consumed natively; see `bullmq`
+    Bullmq,
+    /// Kafka topics, read by a consumer group instead of Redis; needs the
+    /// `kafka` feature, see `kafka`
+    Kafka,
+    /// Redis streams read through a consumer group; see `streams`
+    Stream,
+    /// Submitted over the worker's own gRPC API and held in memory, without
+    /// Redis; needs the `grpc` feature, see `grpc`
+    Grpc,
+}
+
+impl QueueMode {
+    /// Whether the worker talks to Redis in this mode
+    pub fn uses_redis(self) -> bool {
+        !matches!(self, Self::Kafka | Self::Grpc)
+    }
+}
+
+impl FromStr for QueueMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(Self::List),
+            "bullmq" => Ok(Self::Bullmq),
+            "kafka" => Ok(Self::Kafka),
+            "stream" => Ok(Self::Stream),
+            "grpc" => Ok(Self::Grpc),
+            _ => Err("expected list, bullmq, kafka, stream or grpc".to_string()),
+        }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            mode: QueueMode::List,
+            names: String::new(),
+            concurrency: None,
+            shutdown_grace_seconds: DEFAULT_SHUTDOWN_GRACE_SECS,
+            visibility_timeout_seconds: DEFAULT_VISIBILITY_TIMEOUT_SECS,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_base_seconds: DEFAULT_RETRY_BASE_SECS,
+            dead_letter_queue: None,
+            result_ttl_seconds: DEFAULT_RESULT_TTL_SECS,
+            bullmq_prefix: "bull".to_string(),
+            stream_group: "budi-workers".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// `STORAGE_BACKEND`: where outputs are written
+    pub backend: StorageBackend,
+    /// `STORAGE_ROOT`: directory objects are written under, in `local` mode
+    pub root: Option<String>,
+}
+
+/// Where jobs write their outputs; see `storage`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// The `s3` section's S3/MinIO bucket
+    #[default]
+    S3,
+    /// Files under `storage.root`, for deployments without MinIO
+    Local,
+    /// The `azure` section's Azure Blob Storage container
+    Azure,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "s3" => Ok(Self::S3),
+            "local" => Ok(Self::Local),
+            "azure" => Ok(Self::Azure),
+            _ => Err("expected s3, local or azure".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// `MINIO_ENDPOINT`
+    pub endpoint: String,
+    /// `MINIO_ACCESS_KEY`
+    pub access_key: String,
+    /// `MINIO_SECRET_KEY`
+    pub secret_key: String,
+    /// `MINIO_BUCKET_AUDIO`
+    pub bucket: String,
+    /// `S3_PART_SIZE_MB`: uploads bigger than this are sent in parts of
+    /// this size, streamed from disk (S3 needs at least 5)
+    pub part_size_mb: u64,
+    /// `S3_PART_ATTEMPTS`: times each part is tried before the upload fails
+    pub part_attempts: u32,
+    /// `S3_PRESIGN_EXPIRY_SECONDS`: how long the presigned GET URLs returned
+    /// for uploads stay valid (at most a week; 0 returns plain
+    /// `endpoint/bucket/key` URLs, which need a public bucket)
+    pub presign_expiry_seconds: u64,
+    /// `S3_RETRY_MODE`: how requests that S3 throttles or fails with a 5xx
+    /// are retried
+    pub retry_mode: S3RetryMode,
+    /// `S3_MAX_ATTEMPTS`: times each request is tried before the error
+    /// reaches the job (1 turns retries off)
+    pub max_attempts: u32,
+    /// `S3_RETRY_BASE_MS`: wait before the first retry, growing
+    /// exponentially with jitter
+    pub retry_base_ms: u64,
+    /// `S3_CONNECT_TIMEOUT_SECONDS`: time to open a connection (0: no limit)
+    pub connect_timeout_seconds: u64,
+    /// `S3_READ_TIMEOUT_SECONDS`: time S3 may go without sending data
+    /// (0: no limit)
+    pub read_timeout_seconds: u64,
+    /// `S3_MAX_CONNECTIONS`: S3 requests in flight at once across the
+    /// worker's jobs (0: no limit)
+    pub max_connections: usize,
+    /// `S3_SSE`: server-side encryption for uploads, `AES256` (SSE-S3) or
+    /// `aws:kms` (SSE-KMS)
+    pub encryption: Option<String>,
+    /// `S3_SSE_KMS_KEY_ID`: KMS key for `aws:kms` (default: the account's
+    /// `aws/s3` key)
+    pub kms_key_id: Option<String>,
+    /// `S3_STORAGE_CLASS`: e.g. `STANDARD_IA` for masters kept for archive
+    pub storage_class: Option<String>,
+    /// `S3_TAGS`: object tags as a query string, e.g. `app=budi&env=prod`
+    pub tags: Option<String>,
+    /// Overrides of the four settings above for one kind of artifact, named
+    /// by the first part of its key (`masters`, `exports`, `previews`,
+    /// `reports`, `results`, ...); file only, e.g. `[s3.artifacts.masters]`
+    pub artifacts: HashMap<String, S3ObjectSettings>,
+}
+
+impl S3Config {
+    /// The settings for uploads of `artifact`
+    pub fn object_settings(&self, artifact: &str) -> S3ObjectSettings {
+        let overrides = self.artifacts.get(artifact).cloned().unwrap_or_default();
+        S3ObjectSettings {
+            encryption: overrides.encryption.or(self.encryption.clone()),
+            kms_key_id: overrides.kms_key_id.or(self.kms_key_id.clone()),
+            storage_class: overrides.storage_class.or(self.storage_class.clone()),
+            tags: overrides.tags.or(self.tags.clone()),
+        }
+    }
+}
+
+/// Settings S3 applies to an object as it is written; unset ones are left to
+/// the bucket's defaults
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3ObjectSettings {
+    pub encryption: Option<String>,
+    pub kms_key_id: Option<String>,
+    pub storage_class: Option<String>,
+    pub tags: Option<String>,
+}
+
+/// How S3 requests are retried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum S3RetryMode {
+    /// Retry throttling, 5xx and connection errors with exponential backoff
+    #[default]
+    Standard,
+    /// As `standard`, also slowing the worker's own request rate while S3
+    /// is throttling it
+    Adaptive,
+}
+
+impl FromStr for S3RetryMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(Self::Standard),
+            "adaptive" => Ok(Self::Adaptive),
+            _ => Err("expected standard or adaptive".to_string()),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:9000".to_string(),
+            access_key: "minioadmin".to_string(),
+            secret_key: "minioadmin".to_string(),
+            bucket: "audio".to_string(),
+            part_size_mb: DEFAULT_S3_PART_SIZE_MB,
+            part_attempts: DEFAULT_S3_PART_ATTEMPTS,
+            presign_expiry_seconds: MAX_S3_PRESIGN_EXPIRY_SECS,
+            retry_mode: S3RetryMode::Standard,
+            max_attempts: DEFAULT_S3_MAX_ATTEMPTS,
+            retry_base_ms: DEFAULT_S3_RETRY_BASE_MS,
+            connect_timeout_seconds: DEFAULT_S3_CONNECT_TIMEOUT_SECS,
+            read_timeout_seconds: DEFAULT_S3_READ_TIMEOUT_SECS,
+            max_connections: DEFAULT_S3_MAX_CONNECTIONS,
+            encryption: None,
+            kms_key_id: None,
+            storage_class: None,
+            tags: None,
+            artifacts: HashMap::new(),
+        }
+    }
+}
+
+/// Azure Blob Storage, for the `azure` storage backend
+///
+/// Requests are authorized with the SAS token when one is set, and otherwise
+/// with a token for the worker's managed identity.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AzureConfig {
+    /// `AZURE_STORAGE_ACCOUNT_URL`: e.g. `https://budi.blob.core.windows.net`
+    pub account_url: String,
+    /// `AZURE_STORAGE_CONTAINER`
+    pub container: String,
+    /// `AZURE_STORAGE_SAS_TOKEN`: account or container SAS with read, create
+    /// and write permissions
+    pub sas_token: Option<String>,
+    /// `AZURE_CLIENT_ID`: user-assigned managed identity to use, when the
+    /// worker has more than one
+    pub client_id: Option<String>,
+    /// `AZURE_BLOCK_SIZE_MB`: uploads bigger than this are sent as blocks of
+    /// this size
+    pub block_size_mb: u64,
+    /// `AZURE_BLOCK_ATTEMPTS`: times each block is tried before the upload
+    /// fails
+    pub block_attempts: u32,
+}
+
+impl Default for AzureConfig {
+    fn default() -> Self {
+        Self {
+            account_url: String::new(),
+            container: "audio".to_string(),
+            sas_token: None,
+            client_id: None,
+            block_size_mb: DEFAULT_AZURE_BLOCK_SIZE_MB,
+            block_attempts: DEFAULT_AZURE_BLOCK_ATTEMPTS,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// `API_URL`
+    pub api_url: String,
+    /// `WEBHOOK_SECRET`
+    pub secret: String,
+    /// `PUBLISH_PROGRESS`: also publish progress updates to the Redis
+    /// channel `progress:{jobId}` for live listeners (not in kafka or grpc mode)
+    pub publish_progress: bool,
+    /// `STORE_RESULTS`: also write each result webhook's payload to
+    /// `results/{jobId}.json` in storage, for consumers and audits
+    pub store_results: bool,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "http://localhost:4000".to_string(),
+            secret: "budi-webhook-secret".to_string(),
+            publish_progress: true,
+            store_results: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// `MAX_DECODED_MB`: largest decoded audio a job may hold; bigger files
+    /// are refused without retries (0 turns the check off)
+    pub max_decoded_mb: u64,
+    /// `MIN_FREE_DISK_MB`: free space a download must leave on the temp
+    /// disk; jobs that would dip below are retried later
+    pub min_free_disk_mb: u64,
+    /// `MAX_HTTP_DOWNLOAD_MB`: largest file fetched from a source URL that
+    /// isn't on the S3 endpoint; bigger files are refused without retries
+    /// (0 turns the check off)
+    pub max_http_download_mb: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_decoded_mb: DEFAULT_MAX_DECODED_MB,
+            min_free_disk_mb: DEFAULT_MIN_FREE_DISK_MB,
+            max_http_download_mb: DEFAULT_MAX_HTTP_DOWNLOAD_MB,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TempConfig {
+    /// `WORKER_TEMP_DIR`: where jobs get their scratch directories (default
+    /// `budi-worker` in the system temp directory)
+    pub root: Option<String>,
+    /// `TEMP_MAX_AGE_SECONDS`: time since a job directory last changed before
+    /// it is swept; keep this above the longest job
+    pub max_age_seconds: u64,
+    /// `TEMP_SWEEP_INTERVAL_SECONDS`: time between sweeps after the one at
+    /// startup (0 sweeps at startup only)
+    pub sweep_interval_seconds: u64,
+}
+
+impl Default for TempConfig {
+    fn default() -> Self {
+        Self {
+            root: None,
+            max_age_seconds: DEFAULT_TEMP_MAX_AGE_SECS,
+            sweep_interval_seconds: DEFAULT_TEMP_SWEEP_INTERVAL_SECS,
+        }
+    }
+}
+
+/// Local cache of downloaded sources and their decoded audio; see `cache`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// `CACHE_DIR`: where to keep them (default: no cache)
+    pub dir: Option<String>,
+    /// `CACHE_MAX_MB`: size the directory is held to, least recently used
+    /// entries going first
+    pub max_mb: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_mb: DEFAULT_CACHE_MAX_MB,
+        }
+    }
+}
+
+/// Where `kafka` mode reads jobs from; `queue.names` are the topics
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    /// `KAFKA_BROKERS`: comma-separated bootstrap servers
+    pub brokers: String,
+    /// `KAFKA_GROUP_ID`: consumer group the worker's tasks join (default
+    /// `{first topic}-workers`)
+    pub group_id: Option<String>,
+    /// `KAFKA_MAX_POLL_INTERVAL_SECONDS`: longest a job may run before the
+    /// group gives its partition to another worker; keep this above the
+    /// longest job
+    pub max_poll_interval_seconds: u64,
+    /// `KAFKA_SECURITY_PROTOCOL`: e.g. `SASL_SSL` (default `plaintext`)
+    pub security_protocol: Option<String>,
+    /// `KAFKA_SASL_MECHANISM`: e.g. `SCRAM-SHA-512`
+    pub sasl_mechanism: Option<String>,
+    /// `KAFKA_SASL_USERNAME`
+    pub sasl_username: Option<String>,
+    /// `KAFKA_SASL_PASSWORD`
+    pub sasl_password: Option<String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            group_id: None,
+            max_poll_interval_seconds: DEFAULT_KAFKA_MAX_POLL_INTERVAL_SECS,
+            security_protocol: None,
+            sasl_mechanism: None,
+            sasl_username: None,
+            sasl_password: None,
+        }
+    }
+}
+
+/// Where `grpc` mode serves its job API
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// `GRPC_LISTEN`: address the job API listens on
+    pub listen: String,
+    /// `GRPC_MAX_QUEUED`: jobs that may wait at once; further submissions
+    /// are refused until some start
+    pub max_queued: usize,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:50051".to_string(),
+            max_queued: DEFAULT_GRPC_MAX_QUEUED,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file (if `BUDI_CONFIG` is set) and environment overrides
+    ///
+    /// `queue_var` and `default_queue` are the worker's queue variable and
+    /// default queue; `sections` are the worker's own top-level sections,
+    /// so any other section is reported as a typo.
+    pub fn load(queue_var: &str, default_queue: &str, sections: &[&str]) -> Result<Self> {
+        let (source, document) = match lookup_env(CONFIG_PATH_VAR) {
+            Some(path) => {
+                let document = read_file(Path::new(&path))?;
+                (path, document)
+            }
+            None => ("defaults".to_string(), Map::new()),
+        };
+        Self::from_document(
+            source,
+            document,
+            queue_var,
+            default_queue,
+            sections,
+            lookup_env,
+        )
+    }
+
+    fn from_document(
+        source: String,
+        sections: Map<String, Value>,
+        queue_var: &str,
+        default_queue: &str,
+        worker_sections: &[&str],
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let known: Vec<&str> = CORE_SECTIONS
+            .iter()
+            .chain(worker_sections)
+            .copied()
+            .collect();
+        if let Some(unknown) = sections.keys().find(|k| !known.contains(&k.as_str())) {
+            anyhow::bail!(
+                "Unknown section [{}] in {} (expected {})",
+                unknown,
+                source,
+                known.join(", ")
+            );
+        }
+
+        let mut config = Self {
+            redis: parse_section(&sections, "redis", &source)?,
+            queue: parse_section(&sections, "queue", &source)?,
+            storage: parse_section(&sections, "storage", &source)?,
+            s3: parse_section(&sections, "s3", &source)?,
+            azure: parse_section(&sections, "azure", &source)?,
+            webhook: parse_section(&sections, "webhook", &source)?,
+            limits: parse_section(&sections, "limits", &source)?,
+            temp: parse_section(&sections, "temp", &source)?,
+            cache: parse_section(&sections, "cache", &source)?,
+            kafka: parse_section(&sections, "kafka", &source)?,
+            grpc: parse_section(&sections, "grpc", &source)?,
+            source,
+            sections,
+        };
+
+        override_with(&mut config.redis.url, "REDIS_URL", &env)?;
+        let queue = &mut config.queue;
+        override_with(&mut queue.mode, "QUEUE_MODE", &env)?;
+        override_with(&mut queue.names, queue_var, &env)?;
+        if queue.names.trim().is_empty() {
+            queue.names = default_queue.to_string();
+        }
+        if env("WORKER_CONCURRENCY").is_some() {
+            let mut concurrency = 0;
+            override_with(&mut concurrency, "WORKER_CONCURRENCY", &env)?;
+            queue.concurrency = Some(concurrency);
+        }
+        override_with(
+            &mut queue.shutdown_grace_seconds,
+            "SHUTDOWN_GRACE_SECONDS",
+            &env,
+        )?;
+        override_with(
+            &mut queue.visibility_timeout_seconds,
+            "JOB_VISIBILITY_TIMEOUT_SECONDS",
+            &env,
+        )?;
+        override_with(&mut queue.max_attempts, "JOB_MAX_ATTEMPTS", &env)?;
+        override_with(
+            &mut queue.retry_base_seconds,
+            "JOB_RETRY_BASE_SECONDS",
+            &env,
+        )?;
+        if let Some(name) = env("DEAD_LETTER_QUEUE") {
+            queue.dead_letter_queue = Some(name);
+        }
+        override_with(
+            &mut queue.result_ttl_seconds,
+            "JOB_RESULT_TTL_SECONDS",
+            &env,
+        )?;
+        override_with(&mut queue.bullmq_prefix, "BULLMQ_PREFIX", &env)?;
+        override_with(&mut queue.stream_group, "STREAM_GROUP", &env)?;
+        override_with(&mut config.storage.backend, "STORAGE_BACKEND", &env)?;
+        if let Some(root) = env("STORAGE_ROOT") {
+            config.storage.root = Some(root);
+        }
+        let s3 = &mut config.s3;
+        override_with(&mut s3.endpoint, "MINIO_ENDPOINT", &env)?;
+        override_with(&mut s3.access_key, "MINIO_ACCESS_KEY", &env)?;
+        override_with(&mut s3.secret_key, "MINIO_SECRET_KEY", &env)?;
+        override_with(&mut s3.bucket, "MINIO_BUCKET_AUDIO", &env)?;
+        override_with(&mut s3.part_size_mb, "S3_PART_SIZE_MB", &env)?;
+        override_with(&mut s3.part_attempts, "S3_PART_ATTEMPTS", &env)?;
+        override_with(
+            &mut s3.presign_expiry_seconds,
+            "S3_PRESIGN_EXPIRY_SECONDS",
+            &env,
+        )?;
+        override_with(&mut s3.retry_mode, "S3_RETRY_MODE", &env)?;
+        override_with(&mut s3.max_attempts, "S3_MAX_ATTEMPTS", &env)?;
+        override_with(&mut s3.retry_base_ms, "S3_RETRY_BASE_MS", &env)?;
+        override_with(
+            &mut s3.connect_timeout_seconds,
+            "S3_CONNECT_TIMEOUT_SECONDS",
+            &env,
+        )?;
+        override_with(
+            &mut s3.read_timeout_seconds,
+            "S3_READ_TIMEOUT_SECONDS",
+            &env,
+        )?;
+        override_with(&mut s3.max_connections, "S3_MAX_CONNECTIONS", &env)?;
+        for (setting, var) in [
+            (&mut s3.encryption, "S3_SSE"),
+            (&mut s3.kms_key_id, "S3_SSE_KMS_KEY_ID"),
+            (&mut s3.storage_class, "S3_STORAGE_CLASS"),
+            (&mut s3.tags, "S3_TAGS"),
+        ] {
+            if let Some(value) = env(var) {
+                *setting = Some(value);
+            }
+        }
+        let azure = &mut config.azure;
+        override_with(&mut azure.account_url, "AZURE_STORAGE_ACCOUNT_URL", &env)?;
+        override_with(&mut azure.container, "AZURE_STORAGE_CONTAINER", &env)?;
+        if let Some(token) = env("AZURE_STORAGE_SAS_TOKEN") {
+            azure.sas_token = Some(token);
+        }
+        if let Some(client_id) = env("AZURE_CLIENT_ID") {
+            azure.client_id = Some(client_id);
+        }
+        override_with(&mut azure.block_size_mb, "AZURE_BLOCK_SIZE_MB", &env)?;
+        override_with(&mut azure.block_attempts, "AZURE_BLOCK_ATTEMPTS", &env)?;
+        override_with(&mut config.webhook.api_url, "API_URL", &env)?;
+        override_with(&mut config.webhook.secret, "WEBHOOK_SECRET", &env)?;
+        override_with(
+            &mut config.webhook.publish_progress,
+            "PUBLISH_PROGRESS",
+            &env,
+        )?;
+        override_with(&mut config.webhook.store_results, "STORE_RESULTS", &env)?;
+        let limits = &mut config.limits;
+        override_with(&mut limits.max_decoded_mb, "MAX_DECODED_MB", &env)?;
+        override_with(&mut limits.min_free_disk_mb, "MIN_FREE_DISK_MB", &env)?;
+        override_with(
+            &mut limits.max_http_download_mb,
+            "MAX_HTTP_DOWNLOAD_MB",
+            &env,
+        )?;
+        let temp = &mut config.temp;
+        if let Some(root) = env("WORKER_TEMP_DIR") {
+            temp.root = Some(root);
+        }
+        override_with(&mut temp.max_age_seconds, "TEMP_MAX_AGE_SECONDS", &env)?;
+        override_with(
+            &mut temp.sweep_interval_seconds,
+            "TEMP_SWEEP_INTERVAL_SECONDS",
+            &env,
+        )?;
+        if let Some(dir) = env("CACHE_DIR") {
+            config.cache.dir = Some(dir);
+        }
+        override_with(&mut config.cache.max_mb, "CACHE_MAX_MB", &env)?;
+        let kafka = &mut config.kafka;
+        override_with(&mut kafka.brokers, "KAFKA_BROKERS", &env)?;
+        if let Some(group_id) = env("KAFKA_GROUP_ID") {
+            kafka.group_id = Some(group_id);
+        }
+        override_with(
+            &mut kafka.max_poll_interval_seconds,
+            "KAFKA_MAX_POLL_INTERVAL_SECONDS",
+            &env,
+        )?;
+        if let Some(protocol) = env("KAFKA_SECURITY_PROTOCOL") {
+            kafka.security_protocol = Some(protocol);
+        }
+        if let Some(mechanism) = env("KAFKA_SASL_MECHANISM") {
+            kafka.sasl_mechanism = Some(mechanism);
+        }
+        if let Some(username) = env("KAFKA_SASL_USERNAME") {
+            kafka.sasl_username = Some(username);
+        }
+        if let Some(password) = env("KAFKA_SASL_PASSWORD") {
+            kafka.sasl_password = Some(password);
+        }
+        override_with(&mut config.grpc.listen, "GRPC_LISTEN", &env)?;
+        override_with(&mut config.grpc.max_queued, "GRPC_MAX_QUEUED", &env)?;
+
+        config.validate(queue_var)?;
+        Ok(config)
+    }
+
+    fn validate(&self, queue_var: &str) -> Result<()> {
+        // The URL may hold a password, so it isn't echoed back
+        self.redis
+            .url
+            .as_str()
+            .into_connection_info()
+            .context("redis.url (REDIS_URL) is not a valid Redis URL")?;
+
+        let queue = &self.queue;
+        worker::parse_queues(&queue.names)
+            .with_context(|| format!("Invalid queue.names ({})", queue_var))?;
+        anyhow::ensure!(
+            queue.concurrency.is_none_or(|n| n >= 1),
+            "queue.concurrency (WORKER_CONCURRENCY) must be at least 1"
+        );
+        anyhow::ensure!(
+            queue.visibility_timeout_seconds >= 1,
+            "queue.visibility_timeout_seconds (JOB_VISIBILITY_TIMEOUT_SECONDS) must be at least 1"
+        );
+        anyhow::ensure!(
+            queue.max_attempts >= 1,
+            "queue.max_attempts (JOB_MAX_ATTEMPTS) must be at least 1"
+        );
+        anyhow::ensure!(
+            queue
+                .dead_letter_queue
+                .as_deref()
+                .is_none_or(|name| !name.trim().is_empty()),
+            "queue.dead_letter_queue (DEAD_LETTER_QUEUE) can't be empty"
+        );
+        anyhow::ensure!(
+            !queue.bullmq_prefix.is_empty(),
+            "queue.bullmq_prefix (BULLMQ_PREFIX) can't be empty"
+        );
+        anyhow::ensure!(
+            !queue.stream_group.trim().is_empty(),
+            "queue.stream_group (STREAM_GROUP) can't be empty"
+        );
+
+        match queue.mode {
+            QueueMode::Kafka => self.validate_kafka(queue_var)?,
+            QueueMode::Grpc => self.validate_grpc()?,
+            QueueMode::List | QueueMode::Bullmq | QueueMode::Stream => {}
+        }
+
+        anyhow::ensure!(
+            self.temp.max_age_seconds >= 60,
+            "temp.max_age_seconds (TEMP_MAX_AGE_SECONDS) must be at least 60"
+        );
+        if let Some(dir) = &self.cache.dir {
+            anyhow::ensure!(
+                !dir.trim().is_empty(),
+                "cache.dir (CACHE_DIR) can't be empty"
+            );
+            anyhow::ensure!(
+                self.cache.max_mb >= 1,
+                "cache.max_mb (CACHE_MAX_MB) must be at least 1"
+            );
+        }
+
+        match self.storage.backend {
+            StorageBackend::S3 => self.validate_s3()?,
+            StorageBackend::Local => anyhow::ensure!(
+                self.storage
+                    .root
+                    .as_deref()
+                    .is_some_and(|root| !root.trim().is_empty()),
+                "storage.root (STORAGE_ROOT) must be set for the local storage backend"
+            ),
+            StorageBackend::Azure => self.validate_azure()?,
+        }
+
+        url::Url::parse(&self.webhook.api_url).with_context(|| {
+            format!(
+                "webhook.api_url (API_URL) is not a URL: {}",
+                self.webhook.api_url
+            )
+        })?;
+        Ok(())
+    }
+
+    fn validate_s3(&self) -> Result<()> {
+        url::Url::parse(&self.s3.endpoint).with_context(|| {
+            format!(
+                "s3.endpoint (MINIO_ENDPOINT) is not a URL: {}",
+                self.s3.endpoint
+            )
+        })?;
+        anyhow::ensure!(
+            !self.s3.bucket.is_empty(),
+            "s3.bucket (MINIO_BUCKET_AUDIO) can't be empty"
+        );
+        anyhow::ensure!(
+            (MIN_S3_PART_SIZE_MB..=MAX_S3_PART_SIZE_MB).contains(&self.s3.part_size_mb),
+            "s3.part_size_mb (S3_PART_SIZE_MB) must be between {} and {}",
+            MIN_S3_PART_SIZE_MB,
+            MAX_S3_PART_SIZE_MB
+        );
+        anyhow::ensure!(
+            self.s3.part_attempts >= 1,
+            "s3.part_attempts (S3_PART_ATTEMPTS) must be at least 1"
+        );
+        anyhow::ensure!(
+            self.s3.presign_expiry_seconds <= MAX_S3_PRESIGN_EXPIRY_SECS,
+            "s3.presign_expiry_seconds (S3_PRESIGN_EXPIRY_SECONDS) can't be over {}",
+            MAX_S3_PRESIGN_EXPIRY_SECS
+        );
+        anyhow::ensure!(
+            self.s3.max_attempts >= 1,
+            "s3.max_attempts (S3_MAX_ATTEMPTS) must be at least 1"
+        );
+        validate_s3_objects(&self.s3.object_settings(""), "s3")?;
+        for artifact in self.s3.artifacts.keys() {
+            let settings = self.s3.object_settings(artifact);
+            validate_s3_objects(&settings, &format!("s3.artifacts.{}", artifact))?;
+        }
+        Ok(())
+    }
+
+    fn validate_azure(&self) -> Result<()> {
+        let azure = &self.azure;
+        url::Url::parse(&azure.account_url).with_context(|| {
+            format!(
+                "azure.account_url (AZURE_STORAGE_ACCOUNT_URL) is not a URL: {:?}",
+                azure.account_url
+            )
+        })?;
+        anyhow::ensure!(
+            !azure.container.is_empty(),
+            "azure.container (AZURE_STORAGE_CONTAINER) can't be empty"
+        );
+        anyhow::ensure!(
+            (1..=MAX_AZURE_BLOCK_SIZE_MB).contains(&azure.block_size_mb),
+            "azure.block_size_mb (AZURE_BLOCK_SIZE_MB) must be between 1 and {}",
+            MAX_AZURE_BLOCK_SIZE_MB
+        );
+        anyhow::ensure!(
+            azure.block_attempts >= 1,
+            "azure.block_attempts (AZURE_BLOCK_ATTEMPTS) must be at least 1"
+        );
+        Ok(())
+    }
+
+    fn validate_kafka(&self, queue_var: &str) -> Result<()> {
+        anyhow::ensure!(
+            cfg!(feature = "kafka"),
+            "queue.mode (QUEUE_MODE) kafka needs a worker built with the kafka feature"
+        );
+        let queue = &self.queue;
+        let topics = worker::parse_queues(&queue.names)?;
+        anyhow::ensure!(
+            topics.iter().all(|topic| topic.weight.is_none()),
+            "queue.names ({}) can't have weights in kafka mode",
+            queue_var
+        );
+        let names = topics.iter().map(|topic| topic.name.as_str());
+        for name in names.chain(queue.dead_letter_queue.as_deref()) {
+            anyhow::ensure!(
+                name.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')),
+                "{} isn't a valid Kafka topic name (letters, digits, '.', '_' and '-')",
+                name
+            );
+        }
+
+        let kafka = &self.kafka;
+        anyhow::ensure!(
+            !kafka.brokers.trim().is_empty(),
+            "kafka.brokers (KAFKA_BROKERS) can't be empty"
+        );
+        anyhow::ensure!(
+            kafka
+                .group_id
+                .as_deref()
+                .is_none_or(|group| !group.trim().is_empty()),
+            "kafka.group_id (KAFKA_GROUP_ID) can't be empty"
+        );
+        anyhow::ensure!(
+            (MIN_KAFKA_MAX_POLL_INTERVAL_SECS..=MAX_KAFKA_MAX_POLL_INTERVAL_SECS)
+                .contains(&kafka.max_poll_interval_seconds),
+            "kafka.max_poll_interval_seconds (KAFKA_MAX_POLL_INTERVAL_SECONDS) must be between {} and {}",
+            MIN_KAFKA_MAX_POLL_INTERVAL_SECS,
+            MAX_KAFKA_MAX_POLL_INTERVAL_SECS
+        );
+        Ok(())
+    }
+
+    fn validate_grpc(&self) -> Result<()> {
+        anyhow::ensure!(
+            cfg!(feature = "grpc"),
+            "queue.mode (QUEUE_MODE) grpc needs a worker built with the grpc feature"
+        );
+        let grpc = &self.grpc;
+        grpc.listen.parse::<SocketAddr>().with_context(|| {
+            format!(
+                "grpc.listen (GRPC_LISTEN) is not a socket address: {}",
+                grpc.listen
+            )
+        })?;
+        anyhow::ensure!(
+            grpc.max_queued >= 1,
+            "grpc.max_queued (GRPC_MAX_QUEUED) must be at least 1"
+        );
+        Ok(())
+    }
+
+    /// A worker-specific section, or its default when the file has none
+    pub fn section<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T> {
+        parse_section(&self.sections, name, &self.source)
+    }
+}
+
+fn validate_s3_objects(settings: &S3ObjectSettings, section: &str) -> Result<()> {
+    use aws_sdk_s3::types::{ServerSideEncryption, StorageClass};
+
+    if let Some(encryption) = &settings.encryption {
+        anyhow::ensure!(
+            matches!(encryption.as_str(), "AES256" | "aws:kms"),
+            "{}.encryption (S3_SSE) must be AES256 or aws:kms, not {}",
+            section,
+            encryption
+        );
+    }
+    anyhow::ensure!(
+        settings.kms_key_id.is_none()
+            || settings
+                .encryption
+                .as_deref()
+                .map(ServerSideEncryption::from)
+                == Some(ServerSideEncryption::AwsKms),
+        "{}.kms_key_id (S3_SSE_KMS_KEY_ID) needs encryption (S3_SSE) aws:kms",
+        section
+    );
+    if let Some(class) = &settings.storage_class {
+        anyhow::ensure!(
+            StorageClass::values().contains(&class.as_str()),
+            "{}.storage_class (S3_STORAGE_CLASS) must be one of {}",
+            section,
+            StorageClass::values().join(", ")
+        );
+    }
+    if let Some(tags) = &settings.tags {
+        anyhow::ensure!(
+            url::form_urlencoded::parse(tags.as_bytes()).all(|(key, _)| !key.is_empty()),
+            "{}.tags (S3_TAGS) must be key=value pairs joined by &",
+            section
+        );
+    }
+    Ok(())
+}
+
+/// Replace `value` with environment variable `var` when it is set
+pub fn env_override<T>(value: &mut T, var: &str) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    override_with(value, var, &lookup_env)
+}
+
+fn override_with<T>(value: &mut T, var: &str, env: &impl Fn(&str) -> Option<String>) -> Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(raw) = env(var) {
+        *value = raw
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {}={}: {}", var, raw, e))?;
+    }
+    Ok(())
+}
+
+/// An environment variable, treating an empty one as unset
+fn lookup_env(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|v| !v.is_empty())
+}
+
+fn read_file(path: &Path) -> Result<Map<String, Value>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let document: Value = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&text)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?,
+        _ => anyhow::bail!(
+            "Config file {} must end in .toml, .yaml or .yml",
+            path.display()
+        ),
+    };
+    match document {
+        Value::Object(sections) => Ok(sections),
+        // An empty YAML file
+        Value::Null => Ok(Map::new()),
+        _ => anyhow::bail!("Config file {} must be a table of sections", path.display()),
+    }
+}
+
+fn parse_section<T: DeserializeOwned + Default>(
+    sections: &Map<String, Value>,
+    name: &str,
+    source: &str,
+) -> Result<T> {
+    match sections.get(name) {
+        Some(value) => T::deserialize(value)
+            .with_context(|| format!("Invalid [{}] section in {}", name, source)),
+        None => Ok(T::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_file_env_overrides_and_validation() {
+        let document = r#"
+            [queue]
+            names = "dsp-jobs-high:4,dsp-jobs:1"
+            concurrency = 2
+            max_attempts = 5
+
+            [s3]
+            bucket = "masters"
+
+            [dsp]
+            clip_threshold = 0.98
+        "#;
+        let load = |toml: &str, env: &[(&str, &str)]| {
+            let sections = match toml::from_str(toml).unwrap() {
+                Value::Object(sections) => sections,
+                _ => unreachable!(),
+            };
+            let env: HashMap<String, String> = env
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            Config::from_document(
+                "test.toml".to_string(),
+                sections,
+                "DSP_QUEUE",
+                "dsp-jobs",
+                &["dsp"],
+                |var| env.get(var).cloned(),
+            )
+        };
+
+        let config = load(document, &[("JOB_MAX_ATTEMPTS", "7")]).unwrap();
+        assert_eq!(config.queue.names, "dsp-jobs-high:4,dsp-jobs:1");
+        assert_eq!(config.queue.concurrency, Some(2));
+        let overridden = load(document, &[("WORKER_CONCURRENCY", "64")]).unwrap();
+        assert_eq!(overridden.queue.concurrency, Some(64));
+        assert!(load(document, &[("WORKER_CONCURRENCY", "0")]).is_err());
+        assert_eq!(load("", &[]).unwrap().queue.concurrency, None);
+        assert_eq!(config.queue.max_attempts, 7);
+        assert_eq!(config.queue.retry_base_seconds, DEFAULT_RETRY_BASE_SECS);
+        assert_eq!(config.s3.bucket, "masters");
+        assert_eq!(config.s3.endpoint, "http://localhost:9000");
+
+        #[derive(Default, Deserialize)]
+        #[serde(default)]
+        struct Dsp {
+            clip_threshold: f32,
+        }
+        let dsp: Dsp = config.section("dsp").unwrap();
+        assert_eq!(dsp.clip_threshold, 0.98);
+
+        let config = load("", &[]).unwrap();
+        assert_eq!(config.queue.names, "dsp-jobs");
+
+        // Typos and bad values are caught up front
+        let error = |toml: &str, env: &[(&str, &str)]| match load(toml, env) {
+            Ok(_) => panic!("expected {:?} to be rejected", toml),
+            Err(e) => format!("{:#}", e),
+        };
+        assert!(error("[queue]\nconcurency = 2", &[]).contains("unknown field `concurency`"));
+        assert!(error("[dps]\n", &[]).contains("Unknown section [dps]"));
+        assert!(error("", &[("WORKER_CONCURRENCY", "many")]).contains("WORKER_CONCURRENCY=many"));
+        assert!(error("[queue]\nmax_attempts = 0", &[]).contains("queue.max_attempts"));
+        assert!(error("", &[("DSP_QUEUE", "a:0")]).contains("weight must be positive"));
+        assert!(error("[webhook]\napi_url = \"api\"", &[]).contains("webhook.api_url"));
+        assert!(error("", &[("S3_PART_SIZE_MB", "1")]).contains("s3.part_size_mb"));
+        let cache = ("CACHE_DIR", "/var/cache/budi");
+        assert!(error("", &[cache, ("CACHE_MAX_MB", "0")]).contains("cache.max_mb"));
+        assert_eq!(
+            load("", &[cache]).unwrap().cache.max_mb,
+            DEFAULT_CACHE_MAX_MB
+        );
+        assert!(error("", &[("S3_MAX_ATTEMPTS", "0")]).contains("s3.max_attempts"));
+        assert!(error("", &[("S3_RETRY_MODE", "legacy")]).contains("expected standard or adaptive"));
+        assert!(error("", &[("S3_SSE", "aes")]).contains("s3.encryption"));
+        assert!(error("", &[("S3_SSE_KMS_KEY_ID", "key")]).contains("s3.kms_key_id"));
+        let archive = "[s3]\nstorage_class = \"STANDARD\"\n[s3.artifacts.masters]\nstorage_class = \"STANDARD_IA\"";
+        let config = load(archive, &[("S3_SSE", "AES256")]).unwrap();
+        let masters = config.s3.object_settings("masters");
+        assert_eq!(masters.storage_class.as_deref(), Some("STANDARD_IA"));
+        assert_eq!(masters.encryption.as_deref(), Some("AES256"));
+        assert!(
+            error("[s3.artifacts.masters]\nstorage_class = \"COLD\"", &[])
+                .contains("s3.artifacts.masters.storage_class")
+        );
+        assert!(error("", &[("QUEUE_MODE", "sqs")])
+            .contains("expected list, bullmq, kafka, stream or grpc"));
+        let local = [("STORAGE_BACKEND", "local")];
+        assert!(error("", &local).contains("storage.root"));
+        let config = load("", &[local[0], ("STORAGE_ROOT", "/srv/budi")]).unwrap();
+        assert_eq!(config.storage.backend, StorageBackend::Local);
+        let azure = [("STORAGE_BACKEND", "azure")];
+        assert!(error("", &azure).contains("azure.account_url"));
+        let account = (
+            "AZURE_STORAGE_ACCOUNT_URL",
+            "https://budi.blob.core.windows.net",
+        );
+        assert!(load("", &[azure[0], account]).is_ok());
+        if cfg!(feature = "kafka") {
+            let kafka = [("QUEUE_MODE", "kafka")];
+            assert!(load("", &kafka).is_ok());
+            assert!(error("", &[kafka[0], ("DSP_QUEUE", "a:2,b")]).contains("weights"));
+            assert!(error("", &[kafka[0], ("DEAD_LETTER_QUEUE", "jobs:dead")])
+                .contains("valid Kafka topic"));
+        } else {
+            assert!(error("", &[("QUEUE_MODE", "kafka")]).contains("kafka feature"));
+        }
+        if cfg!(feature = "grpc") {
+            let grpc = [("QUEUE_MODE", "grpc")];
+            assert!(load("", &grpc).is_ok());
+            assert!(error("", &[grpc[0], ("GRPC_LISTEN", "localhost")]).contains("grpc.listen"));
+        } else {
+            assert!(error("", &[("QUEUE_MODE", "grpc")]).contains("grpc feature"));
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! gRPC queue mode (`grpc` feature)
+//!
+//! With `queue.mode = "grpc"` (`QUEUE_MODE=grpc`) the worker takes jobs over
+//! a gRPC API of its own instead of a queue, so a small deployment can run a
+//! single worker and no Redis. The `Jobs` service in `proto/jobs.proto` is
+//! served on `grpc.listen`:
+//!
+//! - `SubmitJob` takes the JSON payload the other modes read from their
+//!   queues, refuses one that wouldn't run with `INVALID_ARGUMENT`, and
+//!   queues it in memory; past `grpc.max_queued` waiting jobs it answers
+//!   `RESOURCE_EXHAUSTED`, and for a job ID already waiting or running
+//!   `ALREADY_EXISTS`
+//! - `GetStatus` reports a job's state, attempts, error and the result
+//!   webhooks it posted
+//! - `CancelJob` drops a waiting job, or stops a running one (removing its
+//!   temp files) and reports it failed; a job that finishes before it can be
+//!   stopped keeps its outcome
+//!
+//! Jobs run in the order they were submitted on `queue.concurrency` tasks,
+//! retried in place with list mode's backoff, and report to the webhooks as
+//! in every other mode. Finished jobs can be looked up for
+//! `queue.result_ttl_seconds`. Nothing is persisted: waiting jobs, and running
+//! ones that outlast the shutdown grace period, are lost when the worker
+//! stops, and jobs that fail for good aren't dead-lettered.
+
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinSet;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, warn};
+
+use crate::limits;
+use crate::webhook::{RecordedPost, WebhookClient};
+use crate::worker::{job_span, parse_job, run_handler, LoopConfig, QueueJob};
+
+mod proto {
+    tonic::include_proto!("budi.worker.v1");
+}
+
+use proto::jobs_server::{Jobs, JobsServer};
+use proto::{
+    CancelJobRequest, CancelJobResponse, GetStatusRequest, JobResult, JobState, JobStatus,
+    SubmitJobRequest, SubmitJobResponse,
+};
+
+/// Request header the trace context may travel in instead of the payload
+const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Error a cancelled job is reported failed with
+const CANCELLED_ERROR: &str = "Job cancelled";
+
+/// A submitted job, from when it is queued until its status expires
+struct Tracked {
+    payload: String,
+    traceparent: Option<String>,
+    state: JobState,
+    attempts: u32,
+    error: String,
+    results: Vec<RecordedPost>,
+    /// Stops the job while it runs
+    cancel: Arc<Notify>,
+    finished: Option<Instant>,
+}
+
+/// A job taken to be run
+struct Next {
+    job_id: String,
+    payload: String,
+    traceparent: Option<String>,
+    cancel: Arc<Notify>,
+}
+
+/// Waiting and tracked jobs, behind the registry's lock
+#[derive(Default)]
+struct Table {
+    /// IDs of waiting jobs, oldest first
+    waiting: VecDeque<String>,
+    all: HashMap<String, Tracked>,
+}
+
+/// Why a submission was turned away
+#[derive(Debug, PartialEq)]
+enum Refusal {
+    /// A job with the same ID is waiting or running
+    Duplicate,
+    /// `grpc.max_queued` jobs are waiting
+    Full,
+}
+
+impl From<Refusal> for Status {
+    fn from(refusal: Refusal) -> Self {
+        match refusal {
+            Refusal::Duplicate => Status::already_exists("The job is already queued or running"),
+            Refusal::Full => Status::resource_exhausted("Too many jobs are waiting"),
+        }
+    }
+}
+
+/// Every job submitted to this worker whose status hasn't expired
+struct Registry {
+    jobs: Mutex<Table>,
+    /// Woken for each submission
+    submitted: Notify,
+    max_queued: usize,
+    status_ttl: Duration,
+}
+
+impl Registry {
+    fn new(max_queued: usize, status_ttl: Duration) -> Self {
+        Self {
+            jobs: Mutex::new(Table::default()),
+            submitted: Notify::new(),
+            max_queued,
+            status_ttl,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Table> {
+        // The lock is never held across anything that can panic
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue a job; returns the number of jobs waiting ahead of it
+    fn submit(
+        &self,
+        job_id: &str,
+        payload: String,
+        traceparent: Option<String>,
+    ) -> Result<u32, Refusal> {
+        let mut jobs = self.lock();
+        let ttl = self.status_ttl;
+        jobs.all
+            .retain(|_, job| job.finished.is_none_or(|at| at.elapsed() < ttl));
+        if jobs
+            .all
+            .get(job_id)
+            .is_some_and(|job| job.finished.is_none())
+        {
+            return Err(Refusal::Duplicate);
+        }
+        if jobs.waiting.len() >= self.max_queued {
+            return Err(Refusal::Full);
+        }
+        let position = jobs.waiting.len() as u32;
+        jobs.waiting.push_back(job_id.to_string());
+        jobs.all.insert(
+            job_id.to_string(),
+            Tracked {
+                payload,
+                traceparent,
+                state: JobState::Queued,
+                attempts: 0,
+                error: String::new(),
+                results: Vec::new(),
+                cancel: Arc::new(Notify::new()),
+                finished: None,
+            },
+        );
+        drop(jobs);
+        self.submitted.notify_one();
+        Ok(position)
+    }
+
+    /// Take the oldest waiting job and mark it running
+    fn take(&self) -> Option<Next> {
+        let mut jobs = self.lock();
+        let job_id = jobs.waiting.pop_front()?;
+        let job = jobs.all.get_mut(&job_id)?;
+        job.state = JobState::Running;
+        Some(Next {
+            payload: job.payload.clone(),
+            traceparent: job.traceparent.clone(),
+            cancel: job.cancel.clone(),
+            job_id,
+        })
+    }
+
+    fn started_attempt(&self, job_id: &str, attempt: u32) {
+        if let Some(job) = self.lock().all.get_mut(job_id) {
+            job.attempts = attempt;
+        }
+    }
+
+    fn finish(&self, job_id: &str, state: JobState, error: String, results: Vec<RecordedPost>) {
+        if let Some(job) = self.lock().all.get_mut(job_id) {
+            job.state = state;
+            job.error = error;
+            job.results = results;
+            job.finished = Some(Instant::now());
+        }
+    }
+
+    fn status(&self, job_id: &str) -> Option<JobStatus> {
+        let jobs = self.lock();
+        let job = jobs.all.get(job_id)?;
+        Some(JobStatus {
+            job_id: job_id.to_string(),
+            state: job.state.into(),
+            attempts: job.attempts,
+            error: job.error.clone(),
+            results: job
+                .results
+                .iter()
+                .map(|post| JobResult {
+                    endpoint: post.endpoint.clone(),
+                    payload: post.payload.to_string(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Drop a waiting job or tell a running one to stop; returns the job's
+    /// state afterwards
+    fn cancel(&self, job_id: &str) -> Option<JobState> {
+        let mut jobs = self.lock();
+        let jobs = &mut *jobs;
+        let job = jobs.all.get_mut(job_id)?;
+        match job.state {
+            JobState::Queued => {
+                jobs.waiting.retain(|id| id != job_id);
+                job.state = JobState::Cancelled;
+                job.error = CANCELLED_ERROR.to_string();
+                job.finished = Some(Instant::now());
+            }
+            JobState::Running => {
+                // Remembered if the job is between attempts
+                job.cancel.notify_one();
+                job.state = JobState::Cancelled;
+            }
+            _ => {}
+        }
+        Some(job.state)
+    }
+}
+
+/// The `Jobs` service, parsing payloads as `J` to check them
+struct Api<J> {
+    registry: Arc<Registry>,
+    job: PhantomData<fn() -> J>,
+}
+
+#[tonic::async_trait]
+impl<J: QueueJob + 'static> Jobs for Api<J> {
+    async fn submit_job(
+        &self,
+        request: Request<SubmitJobRequest>,
+    ) -> Result<Response<SubmitJobResponse>, Status> {
+        let header = request
+            .metadata()
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let payload = request.into_inner().payload;
+        let job = parse_job::<J>(&payload).map_err(|rejection| {
+            Status::invalid_argument(format!("{}: {}", rejection.code, rejection.error))
+        })?;
+        let job_id = job.job_id().to_string();
+        if job_id.is_empty() {
+            return Err(Status::invalid_argument("jobId can't be empty"));
+        }
+        let traceparent = serde_json::from_str::<serde_json::Value>(&payload)
+            .ok()
+            .and_then(|p| p.get("traceparent")?.as_str().map(String::from))
+            .or(header);
+        let position = self.registry.submit(&job_id, payload, traceparent)?;
+        info!(
+            "Queued {} job {} ({} ahead of it)",
+            job.job_type(),
+            job_id,
+            position
+        );
+        Ok(Response::new(SubmitJobResponse { job_id, position }))
+    }
+
+    async fn get_status(
+        &self,
+        request: Request<GetStatusRequest>,
+    ) -> Result<Response<JobStatus>, Status> {
+        let job_id = request.into_inner().job_id;
+        let status = self
+            .registry
+            .status(&job_id)
+            .ok_or_else(|| Status::not_found(format!("No job {}", job_id)))?;
+        Ok(Response::new(status))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobRequest>,
+    ) -> Result<Response<CancelJobResponse>, Status> {
+        let job_id = request.into_inner().job_id;
+        let state = self
+            .registry
+            .cancel(&job_id)
+            .ok_or_else(|| Status::not_found(format!("No job {}", job_id)))?;
+        info!("Cancel requested for job {}", job_id);
+        Ok(Response::new(CancelJobResponse {
+            state: state.into(),
+        }))
+    }
+}
+
+/// Serve the job API and run submitted jobs on `concurrency` tasks until
+/// shutdown
+pub(crate) async fn run<J, F, Fut>(
+    config: Arc<LoopConfig>,
+    concurrency: usize,
+    webhook: &'static WebhookClient,
+    handler: Arc<F>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    J: QueueJob + Send + 'static,
+    F: Fn(J) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let grpc = &config.grpc;
+    let addr: SocketAddr = grpc
+        .listen
+        .parse()
+        .with_context(|| format!("Invalid gRPC listen address {}", grpc.listen))?;
+    let registry = Arc::new(Registry::new(
+        grpc.max_queued,
+        Duration::from_secs(config.result_ttl),
+    ));
+    let api = Api::<J> {
+        registry: registry.clone(),
+        job: PhantomData,
+    };
+
+    // New submissions stop with shutdown; running jobs get the grace period
+    let mut stop = shutdown.clone();
+    let server = tonic::transport::Server::builder()
+        .add_service(JobsServer::new(api))
+        .serve_with_shutdown(addr, async move {
+            let _ = stop.wait_for(|&stop| stop).await;
+        });
+    let mut tasks = JoinSet::new();
+    tasks.spawn(async move { server.await.context("The gRPC job API failed") });
+    info!(
+        "Serving the gRPC job API on {} ({} jobs at a time)",
+        addr, concurrency
+    );
+
+    for _ in 0..concurrency {
+        tasks.spawn(process_jobs(
+            registry.clone(),
+            config.clone(),
+            webhook,
+            handler.clone(),
+            shutdown.clone(),
+        ));
+    }
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+    info!("Worker stopped");
+    Ok(())
+}
+
+/// One job-processing task: run submitted jobs until shutdown
+async fn process_jobs<J, F, Fut>(
+    registry: Arc<Registry>,
+    config: Arc<LoopConfig>,
+    webhook: &'static WebhookClient,
+    handler: Arc<F>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut stop = shutdown.clone();
+    loop {
+        let submitted = registry.submitted.notified();
+        let Some(next) = registry.take() else {
+            tokio::select! {
+                _ = submitted => continue,
+                _ = stop.wait_for(|&stop| stop) => break,
+            }
+        };
+        run_job(next, &registry, &config, webhook, &*handler, &shutdown).await;
+        if *shutdown.borrow() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Run a job, retrying it in place, and record how it ended
+async fn run_job<J, F, Fut>(
+    next: Next,
+    registry: &Registry,
+    config: &LoopConfig,
+    webhook: &'static WebhookClient,
+    handler: &F,
+    shutdown: &watch::Receiver<bool>,
+) where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let Next {
+        job_id,
+        payload,
+        traceparent,
+        cancel,
+    } = next;
+    // Jobs are labelled with the first queue name, as in the other modes
+    let queue = &config.queues[0].name;
+    let retry = &config.retry;
+    let mut attempts = 0;
+
+    loop {
+        // Checked on submission, but a job is consumed by running it, so
+        // each attempt parses the payload again
+        let job = match parse_job::<J>(&payload) {
+            Ok(job) => job,
+            Err(rejection) => {
+                error!("Rejected job {}: {}", job_id, rejection.error);
+                registry.finish(&job_id, JobState::Failed, rejection.error, Vec::new());
+                return;
+            }
+        };
+        let job_type = job.job_type();
+        attempts += 1;
+        registry.started_attempt(&job_id, attempts);
+        if attempts == 1 {
+            info!("Processing {} job {}", job_type, job_id);
+        } else {
+            info!(
+                "Processing {} job {} (attempt {} of {})",
+                job_type, job_id, attempts, retry.max_attempts
+            );
+        }
+
+        let span = job_span(job_type, &job_id, queue, attempts, traceparent.as_deref());
+        let result = tokio::select! {
+            result = run_handler(handler, job, span, shutdown, config.grace) => result,
+            _ = cancel.notified() => {
+                cancelled(&job_id, job_type, registry, webhook).await;
+                return;
+            }
+        };
+        let Some(result) = result else {
+            warn!(
+                "Job {} didn't finish within {:?} of shutdown",
+                job_id, config.grace
+            );
+            return;
+        };
+
+        match result {
+            Ok(posts) => {
+                registry.finish(&job_id, JobState::Completed, String::new(), posts);
+                return;
+            }
+            Err(e) if attempts < retry.max_attempts && !limits::is_refused(&e) => {
+                let delay = retry.delay(attempts);
+                warn!(
+                    "Job {} failed (attempt {} of {}), retrying in {:?}: {:?}",
+                    job_id, attempts, retry.max_attempts, delay, e
+                );
+                let mut stop = shutdown.clone();
+                let stopped = tokio::select! {
+                    _ = tokio::time::sleep(delay) => false,
+                    _ = cancel.notified() => true,
+                    _ = stop.wait_for(|&stop| stop) => return,
+                };
+                if stopped {
+                    cancelled(&job_id, job_type, registry, webhook).await;
+                    return;
+                }
+            }
+            Err(e) => {
+                if limits::is_refused(&e) {
+                    error!("Job {} refused: {:?}", job_id, e);
+                } else {
+                    error!("Job {} failed after {} attempts: {:?}", job_id, attempts, e);
+                }
+                if let Err(we) = webhook
+                    .report_failure(&job_id, job_type, &e.to_string())
+                    .await
+                {
+                    error!("Failed to report job failure: {:?}", we);
+                }
+                registry.finish(&job_id, JobState::Failed, format!("{:#}", e), Vec::new());
+                return;
+            }
+        }
+    }
+}
+
+/// Record a job stopped by `CancelJob` and report it failed
+async fn cancelled(
+    job_id: &str,
+    job_type: &str,
+    registry: &Registry,
+    webhook: &'static WebhookClient,
+) {
+    warn!("Job {} cancelled", job_id);
+    if let Err(e) = webhook
+        .report_failure(job_id, job_type, CANCELLED_ERROR)
+        .await
+    {
+        error!("Failed to report job cancellation: {:?}", e);
+    }
+    registry.finish(
+        job_id,
+        JobState::Cancelled,
+        CANCELLED_ERROR.to_string(),
+        Vec::new(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_queues_and_cancels() {
+        let registry = Registry::new(2, Duration::from_secs(60));
+        assert_eq!(registry.submit("a", "{}".to_string(), None).unwrap(), 0);
+        assert_eq!(registry.submit("b", "{}".to_string(), None).unwrap(), 1);
+        let full = registry.submit("c", "{}".to_string(), None);
+        assert_eq!(full, Err(Refusal::Full));
+
+        let next = registry.take().unwrap();
+        assert_eq!(next.job_id, "a");
+        let running = registry.submit("a", "{}".to_string(), None);
+        assert_eq!(running, Err(Refusal::Duplicate));
+
+        // A waiting job is dropped; a running one is told to stop
+        assert_eq!(registry.cancel("b"), Some(JobState::Cancelled));
+        assert!(registry.take().is_none());
+        assert_eq!(registry.cancel("a"), Some(JobState::Cancelled));
+        assert!(registry.cancel("c").is_none());
+
+        registry.finish("a", JobState::Completed, String::new(), Vec::new());
+        let status = registry.status("a").unwrap();
+        assert_eq!(status.state(), JobState::Completed);
+        assert!(registry.submit("a", "{}".to_string(), None).is_ok());
+    }
+}
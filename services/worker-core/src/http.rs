@@ -0,0 +1,129 @@
+//! Downloads from plain HTTP(S) source URLs
+//!
+//! A job's source doesn't have to be in the worker's bucket: customer-hosted
+//! files and CDN links are fetched over HTTP instead. Nothing bounds what a
+//! third-party server sends, so the response must have an accepted content
+//! type and stay under `max_http_download_mb`, checked against
+//! `Content-Length` up front and again as the body streams to disk.
+
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use reqwest::Response;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+use crate::limits::{self, Refused};
+use crate::s3::DOWNLOAD_BUFFER_BYTES;
+
+/// Media types a source may be served as, matched by prefix: audio (and
+/// video containers holding it), artwork, QC reports, and the generic binary
+/// types CDNs fall back to. Responses without a type are let through.
+const ACCEPTED_CONTENT_TYPES: &[&str] = &[
+    "audio/",
+    "video/",
+    "image/",
+    "application/octet-stream",
+    "binary/octet-stream",
+    "application/ogg",
+    "application/json",
+    "application/pdf",
+];
+
+/// Download `url` to `local_path`
+pub(crate) async fn download(client: &reqwest::Client, url: &str, local_path: &Path) -> Result<()> {
+    tracing::info!("Downloading {} to {:?}", url, local_path);
+
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .context("Failed to fetch source URL")?
+        .error_for_status()
+        .context("Source URL returned an error")?;
+
+    check_content_type(response.headers().get(CONTENT_TYPE))?;
+    if let Some(size) = response.content_length() {
+        limits::check_http_download(size)?;
+        limits::check_download(local_path, size)?;
+    }
+
+    save_response(response, local_path, true).await
+}
+
+/// Stream a response body to `path`, held to `max_http_download_mb` when
+/// `capped`; a failed download doesn't leave a partial file behind
+pub(crate) async fn save_response(response: Response, path: &Path, capped: bool) -> Result<()> {
+    let written = write_response(response, path, capped).await;
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    written
+}
+
+/// Refuse a response whose type isn't one of `ACCEPTED_CONTENT_TYPES`, such
+/// as the HTML error or login page a bad link often serves with a 200
+fn check_content_type(header: Option<&HeaderValue>) -> Result<()> {
+    let Some(header) = header else {
+        return Ok(());
+    };
+    let value = header.to_str().unwrap_or_default();
+    let media_type = value
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if ACCEPTED_CONTENT_TYPES
+        .iter()
+        .any(|accepted| media_type.starts_with(accepted))
+    {
+        return Ok(());
+    }
+    Err(Refused(format!(
+        "Source URL served unsupported content type {:?}",
+        value
+    ))
+    .into())
+}
+
+/// The size limit is enforced as the body arrives, since `Content-Length`
+/// may be missing
+async fn write_response(mut response: Response, path: &Path, capped: bool) -> Result<()> {
+    let file = File::create(path).await.context("Failed to create file")?;
+    let mut file = BufWriter::with_capacity(DOWNLOAD_BUFFER_BYTES, file);
+    let mut written = 0u64;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context("Failed to read source URL")?
+    {
+        written += chunk.len() as u64;
+        if capped {
+            limits::check_http_download(written)?;
+        }
+        file.write_all(&chunk)
+            .await
+            .context("Failed to write file")?;
+    }
+    file.flush().await.context("Failed to write file")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_types() {
+        let check = |value: &str| check_content_type(Some(&HeaderValue::from_str(value).unwrap()));
+        assert!(check("audio/wav").is_ok());
+        assert!(check("Audio/FLAC").is_ok());
+        assert!(check("video/mp4").is_ok());
+        assert!(check("application/octet-stream; charset=binary").is_ok());
+        assert!(check_content_type(None).is_ok());
+
+        let error = check("text/html; charset=utf-8").unwrap_err();
+        assert!(limits::is_refused(&error));
+    }
+}
@@ -0,0 +1,550 @@
+//! In-flight job tracking
+//!
+//! Jobs are popped with RPOPLPUSH into a per-task processing list,
+//! `{queue}:processing:{worker}/{task}`, so a popped job is never only in a
+//! worker's memory. Each task registers itself in `{queue}:consumers`, and a
+//! thread keeps the worker's `worker-heartbeat:{worker}` key alive with a
+//! TTL of `JOB_VISIBILITY_TIMEOUT_SECONDS`.
+//!
+//! Every worker periodically reaps the consumers whose heartbeat has
+//! expired, returning their jobs to the front of the queue, so a job held by
+//! a worker that crashed or was killed is picked up again.
+
+use anyhow::Result;
+use redis::AsyncCommands;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
+
+type Connection = redis::aio::MultiplexedConnection;
+
+/// Unique name for this worker process
+pub fn worker_id() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    format!("{}-{}-{:08x}", host, std::process::id(), nanos)
+}
+
+/// Name a job-processing task registers under
+pub fn consumer(worker: &str, task: u64) -> String {
+    format!("{}/{}", worker, task)
+}
+
+/// The worker a consumer belongs to
+fn consumer_worker(consumer: &str) -> &str {
+    consumer
+        .rsplit_once('/')
+        .map_or(consumer, |(worker, _)| worker)
+}
+
+fn consumers_set(queue: &str) -> String {
+    format!("{}:consumers", queue)
+}
+
+fn processing_list(queue: &str, consumer: &str) -> String {
+    format!("{}:processing:{}", queue, consumer)
+}
+
+fn heartbeat_key(worker: &str) -> String {
+    format!("worker-heartbeat:{}", worker)
+}
+
+/// Keeps a worker's heartbeat key alive from its own thread
+///
+/// A plain thread rather than a task, so jobs doing long stretches of
+/// blocking DSP work on every runtime thread can't starve it.
+pub struct Heartbeat {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    client: redis::Client,
+    worker: String,
+}
+
+impl Heartbeat {
+    pub fn start(client: &redis::Client, worker: &str, timeout: Duration) -> Result<Self> {
+        let mut conn = client.get_connection()?;
+        let key = heartbeat_key(worker);
+        let ttl = timeout.as_secs().max(1);
+        redis::cmd("SET")
+            .arg(&key)
+            .arg(1)
+            .arg("EX")
+            .arg(ttl)
+            .query::<()>(&mut conn)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            let client = client.clone();
+            let interval = timeout / 3;
+            std::thread::spawn(move || {
+                // Dropped after a failure and reopened on the next beat, so
+                // a Redis restart doesn't silence the heartbeat for good
+                let mut conn = Some(conn);
+                let mut last = std::time::Instant::now();
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(200));
+                    if last.elapsed() < interval {
+                        continue;
+                    }
+                    last = std::time::Instant::now();
+                    let result = match conn.as_mut() {
+                        Some(conn) => Ok(conn),
+                        None => client.get_connection().map(|c| conn.insert(c)),
+                    }
+                    .and_then(|conn| {
+                        redis::cmd("SET")
+                            .arg(&key)
+                            .arg(1)
+                            .arg("EX")
+                            .arg(ttl)
+                            .query::<()>(conn)
+                    });
+                    if let Err(e) = result {
+                        error!("Failed to refresh worker heartbeat: {:?}", e);
+                        conn = None;
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+            client: client.clone(),
+            worker: worker.to_string(),
+        })
+    }
+
+    /// Stop heartbeating and remove the key, for a clean exit
+    pub fn stop(mut self) -> Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let mut conn = self.client.get_connection()?;
+        redis::cmd("DEL")
+            .arg(heartbeat_key(&self.worker))
+            .query::<()>(&mut conn)?;
+        Ok(())
+    }
+}
+
+/// Runs `renew` from its own thread every `interval` until dropped, or until
+/// `renew` finds that `what` it renews is no longer held
+///
+/// A plain thread for the same reason as `Heartbeat`, reconnecting the same
+/// way after a failure.
+pub(crate) struct Keepalive {
+    stop: Arc<AtomicBool>,
+}
+
+impl Keepalive {
+    pub(crate) fn start<F>(
+        client: &redis::Client,
+        what: String,
+        interval: Duration,
+        mut renew: F,
+    ) -> Self
+    where
+        F: FnMut(&mut redis::Connection) -> redis::RedisResult<bool> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let client = client.clone();
+        let stopped = stop.clone();
+        std::thread::spawn(move || {
+            let mut conn = None;
+            let mut last = std::time::Instant::now();
+            while !stopped.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(200));
+                if last.elapsed() < interval {
+                    continue;
+                }
+                last = std::time::Instant::now();
+                let result = match conn.as_mut() {
+                    Some(conn) => Ok(conn),
+                    None => client.get_connection().map(|c| conn.insert(c)),
+                }
+                .and_then(&mut renew);
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!("Lost {} to another worker", what);
+                        return;
+                    }
+                    Err(e) => {
+                        error!("Failed to renew {}: {:?}", what, e);
+                        conn = None;
+                    }
+                }
+            }
+        });
+        Self { stop }
+    }
+}
+
+impl Drop for Keepalive {
+    fn drop(&mut self) {
+        // The thread notices within one sleep; renewing something released
+        // by then does nothing
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Register a task as a consumer of each queue, so reapers can find it
+pub async fn register(conn: &mut Connection, queues: &[&str], consumer: &str) -> Result<()> {
+    for queue in queues {
+        conn.sadd::<_, _, ()>(consumers_set(queue), consumer)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Deregister a task whose processing lists are empty
+pub async fn deregister(conn: &mut Connection, queues: &[&str], consumer: &str) -> Result<()> {
+    for queue in queues {
+        conn.srem::<_, _, ()>(consumers_set(queue), consumer)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Pop the next job from the first non-empty queue in `keys` into the
+/// task's processing list, returning its queue and payload
+///
+/// Redis can only block on one list while moving, so the other queues are
+/// checked without blocking first and only the first queue is waited on.
+pub async fn pop(
+    conn: &mut Connection,
+    keys: &[&str],
+    consumer: &str,
+    timeout_secs: f64,
+) -> Result<Option<(String, String)>> {
+    for queue in keys {
+        let payload: Option<String> = conn
+            .rpoplpush(queue, processing_list(queue, consumer))
+            .await?;
+        if let Some(payload) = payload {
+            return Ok(Some((queue.to_string(), payload)));
+        }
+    }
+    let Some(first) = keys.first() else {
+        return Ok(None);
+    };
+    let payload: Option<String> = conn
+        .brpoplpush(first, processing_list(first, consumer), timeout_secs)
+        .await?;
+    Ok(payload.map(|payload| (first.to_string(), payload)))
+}
+
+/// Remove a job that has been dealt with from the task's processing list
+pub async fn ack(conn: &mut Connection, queue: &str, consumer: &str, payload: &str) -> Result<()> {
+    conn.lrem::<_, _, ()>(processing_list(queue, consumer), 1, payload)
+        .await?;
+    Ok(())
+}
+
+/// Move a job from the task's processing list back to the front of its queue
+pub async fn release(
+    conn: &mut Connection,
+    queue: &str,
+    consumer: &str,
+    payload: &str,
+) -> Result<()> {
+    redis::pipe()
+        .atomic()
+        .lrem(processing_list(queue, consumer), 1, payload)
+        .ignore()
+        .rpush(queue, payload)
+        .ignore()
+        .query_async::<_, ()>(conn)
+        .await?;
+    Ok(())
+}
+
+/// Return the jobs of consumers whose worker stopped heartbeating to the
+/// front of their queue, returning how many were recovered
+pub async fn reap(conn: &mut Connection, queues: &[&str]) -> Result<usize> {
+    let mut recovered = 0;
+    for queue in queues {
+        let consumers: Vec<String> = conn.smembers(consumers_set(queue)).await?;
+        for consumer in consumers {
+            let alive: bool = conn
+                .exists(heartbeat_key(consumer_worker(&consumer)))
+                .await?;
+            if alive {
+                continue;
+            }
+
+            recovered += requeue(conn, queue, &consumer).await?;
+            let remaining: usize = conn.llen(processing_list(queue, &consumer)).await?;
+            if remaining == 0 {
+                conn.srem::<_, _, ()>(consumers_set(queue), &consumer)
+                    .await?;
+            } else {
+                warn!(
+                    "Processing list {} refilled while reaping",
+                    processing_list(queue, &consumer)
+                );
+            }
+        }
+    }
+    Ok(recovered)
+}
+
+/// Return the jobs a task left in its own processing lists, after it lost
+/// its Redis connection partway through one, returning how many there were
+pub async fn recover(conn: &mut Connection, queues: &[&str], consumer: &str) -> Result<usize> {
+    let mut recovered = 0;
+    for queue in queues {
+        recovered += requeue(conn, queue, consumer).await?;
+    }
+    Ok(recovered)
+}
+
+/// Move a consumer's jobs from its processing list to the front of `queue`
+async fn requeue(conn: &mut Connection, queue: &str, consumer: &str) -> Result<usize> {
+    let list = processing_list(queue, consumer);
+    let payloads: Vec<String> = conn.lrange(&list, 0, -1).await?;
+    let mut requeued = 0;
+    // The oldest job is last in the list; pushing it last puts it where
+    // BRPOP takes the next job from
+    for payload in &payloads {
+        // Another reaper may be racing us; only whoever removes it re-queues it
+        let removed: u32 = conn.lrem(&list, 1, payload).await?;
+        if removed > 0 {
+            conn.rpush::<_, _, ()>(queue, payload).await?;
+            requeued += 1;
+        }
+    }
+    Ok(requeued)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, HashMap, VecDeque};
+    use std::sync::Mutex;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    #[derive(Default)]
+    struct Keys {
+        lists: HashMap<String, VecDeque<String>>,
+        sets: HashMap<String, BTreeSet<String>>,
+        strings: HashMap<String, String>,
+    }
+
+    enum Reply {
+        Ok,
+        Int(usize),
+        Bulk(Option<String>),
+        Array(Vec<String>),
+    }
+
+    impl Keys {
+        /// The handful of commands the queue code sends
+        fn apply(&mut self, args: &[String]) -> Reply {
+            let key = args.get(1).cloned().unwrap_or_default();
+            match args[0].to_ascii_uppercase().as_str() {
+                // CLIENT SETINFO, sent on connecting
+                "CLIENT" => Reply::Ok,
+                "SET" => {
+                    self.strings.insert(key, args[2].clone());
+                    Reply::Ok
+                }
+                "EXISTS" => Reply::Int(self.strings.contains_key(&key) as usize),
+                "SADD" => {
+                    Reply::Int(self.sets.entry(key).or_default().insert(args[2].clone()) as usize)
+                }
+                "SREM" => Reply::Int(self.sets.entry(key).or_default().remove(&args[2]) as usize),
+                "SMEMBERS" => {
+                    Reply::Array(self.sets.get(&key).into_iter().flatten().cloned().collect())
+                }
+                "LPUSH" => {
+                    let list = self.lists.entry(key).or_default();
+                    args[2..].iter().for_each(|v| list.push_front(v.clone()));
+                    Reply::Int(list.len())
+                }
+                "RPUSH" => {
+                    let list = self.lists.entry(key).or_default();
+                    args[2..].iter().for_each(|v| list.push_back(v.clone()));
+                    Reply::Int(list.len())
+                }
+                "LLEN" => Reply::Int(self.lists.get(&key).map_or(0, |l| l.len())),
+                // Only the whole-list range the queue code asks for
+                "LRANGE" => Reply::Array(
+                    self.lists
+                        .get(&key)
+                        .into_iter()
+                        .flatten()
+                        .cloned()
+                        .collect(),
+                ),
+                // Only a count of 1, as `ack` and `requeue` send
+                "LREM" => {
+                    let list = self.lists.entry(key).or_default();
+                    let found = list.iter().position(|v| *v == args[3]);
+                    Reply::Int(found.and_then(|i| list.remove(i)).is_some() as usize)
+                }
+                "RPOPLPUSH" => {
+                    let value = self.lists.entry(key).or_default().pop_back();
+                    if let Some(value) = &value {
+                        self.lists
+                            .entry(args[2].clone())
+                            .or_default()
+                            .push_front(value.clone());
+                    }
+                    Reply::Bulk(value)
+                }
+                other => panic!("unexpected command {}", other),
+            }
+        }
+    }
+
+    /// A Redis stand-in on a local port, for the commands in `Keys::apply`
+    async fn fake_redis() -> (Connection, Arc<Mutex<Keys>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let keys = Arc::new(Mutex::new(Keys::default()));
+        let served = keys.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let keys = served.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = socket.into_split();
+                    let mut read = BufReader::new(read);
+                    let mut line = String::new();
+                    loop {
+                        line.clear();
+                        if read.read_line(&mut line).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let count: usize = line.trim()[1..].parse().unwrap();
+                        let mut args = Vec::with_capacity(count);
+                        for _ in 0..count {
+                            line.clear();
+                            read.read_line(&mut line).await.unwrap();
+                            let len: usize = line.trim()[1..].parse().unwrap();
+                            let mut arg = vec![0; len + 2];
+                            read.read_exact(&mut arg).await.unwrap();
+                            arg.truncate(len);
+                            args.push(String::from_utf8(arg).unwrap());
+                        }
+                        let bulk = |v: &str| format!("${}\r\n{}\r\n", v.len(), v);
+                        let reply = match keys.lock().unwrap().apply(&args) {
+                            Reply::Ok => "+OK\r\n".to_string(),
+                            Reply::Int(n) => format!(":{}\r\n", n),
+                            Reply::Bulk(None) => "$-1\r\n".to_string(),
+                            Reply::Bulk(Some(v)) => bulk(&v),
+                            Reply::Array(values) => {
+                                let items: String = values.iter().map(|v| bulk(v)).collect();
+                                format!("*{}\r\n{}", values.len(), items)
+                            }
+                        };
+                        write.write_all(reply.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        let client = redis::Client::open(format!("redis://{}", addr)).unwrap();
+        let conn = client.get_multiplexed_async_connection().await.unwrap();
+        (conn, keys)
+    }
+
+    fn list(keys: &Mutex<Keys>, key: &str) -> Vec<String> {
+        keys.lock()
+            .unwrap()
+            .lists
+            .get(key)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_reap_requeues_only_dead_workers() {
+        let (mut conn, keys) = fake_redis().await;
+        let queue = "jobs:dsp";
+        let dead = consumer("dead", 0);
+        let live = consumer("live", 0);
+        for (consumer, jobs) in [(&dead, ["older", "newer"]), (&live, ["running", "next"])] {
+            register(&mut conn, &[queue], consumer).await.unwrap();
+            for job in jobs {
+                conn.lpush::<_, _, ()>(queue, job).await.unwrap();
+                pop(&mut conn, &[queue], consumer, 0.0)
+                    .await
+                    .unwrap()
+                    .unwrap();
+            }
+        }
+        conn.lpush::<_, _, ()>(queue, "queued").await.unwrap();
+        // Only the live worker keeps its heartbeat
+        conn.set::<_, _, ()>(heartbeat_key("live"), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(reap(&mut conn, &[queue]).await.unwrap(), 2);
+        // Back in the queue, with the dead worker's oldest job next in line
+        let queued: Vec<String> = conn.lrange(queue, 0, -1).await.unwrap();
+        assert_eq!(queued, ["queued", "newer", "older"]);
+        let next = pop(&mut conn, &[queue], &live, 0.0).await.unwrap();
+        assert_eq!(next, Some((queue.to_string(), "older".to_string())));
+        assert!(list(&keys, &processing_list(queue, &dead)).is_empty());
+        let members: Vec<String> = conn.smembers(consumers_set(queue)).await.unwrap();
+        assert_eq!(members, [live.as_str()]);
+
+        // The live worker's own jobs stay put, behind the one it just took
+        assert_eq!(
+            list(&keys, &processing_list(queue, &live)),
+            ["older", "next", "running"]
+        );
+        assert_eq!(reap(&mut conn, &[queue]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_recover_requeues_own_jobs() {
+        let (mut conn, keys) = fake_redis().await;
+        let queues = ["jobs:dsp", "jobs:codec"];
+        let task = consumer("live", 1);
+        conn.set::<_, _, ()>(heartbeat_key("live"), 1)
+            .await
+            .unwrap();
+        for queue in queues {
+            conn.lpush::<_, _, ()>(queue, format!("{}-job", queue))
+                .await
+                .unwrap();
+        }
+        pop(&mut conn, &queues, &task, 0.0).await.unwrap().unwrap();
+        pop(&mut conn, &queues, &task, 0.0).await.unwrap().unwrap();
+        assert!(list(&keys, "jobs:dsp").is_empty() && list(&keys, "jobs:codec").is_empty());
+
+        // A task recovers its own lists despite its worker being alive
+        assert_eq!(recover(&mut conn, &queues, &task).await.unwrap(), 2);
+        for queue in queues {
+            assert_eq!(list(&keys, queue), [format!("{}-job", queue)]);
+            assert!(list(&keys, &processing_list(queue, &task)).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_consumer_names() {
+        let worker = "dsp-7c9f-1234-0badf00d";
+        let consumer = consumer(worker, 3);
+        assert_eq!(consumer, "dsp-7c9f-1234-0badf00d/3");
+        // The reaper finds the heartbeat the worker keeps alive
+        assert_eq!(
+            heartbeat_key(consumer_worker(&consumer)),
+            "worker-heartbeat:dsp-7c9f-1234-0badf00d"
+        );
+        assert_eq!(consumer_worker("legacy"), "legacy");
+        assert_eq!(
+            processing_list("jobs:dsp", &consumer),
+            "jobs:dsp:processing:dsp-7c9f-1234-0badf00d/3"
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//! Kafka queue mode (`kafka` feature)
+//!
+//! With `queue.mode = "kafka"` (`QUEUE_MODE=kafka`) jobs come from Kafka
+//! instead of Redis, for deployments whose event bus is Kafka. `queue.names`
+//! are topics, consumed together with no priority between them, and each
+//! message's value is the JSON payload list mode takes. Every job task joins
+//! the `kafka.group_id` consumer group, so the group spreads partitions over
+//! the tasks of all workers.
+//!
+//! A message's offset is committed only once its job has been dealt with:
+//! after it succeeded and its result webhooks were posted, or after it failed
+//! for good and was dead-lettered. A job left unfinished by a worker that died
+//! or was stopped stays uncommitted, so the group redelivers it, with a fresh
+//! set of attempts, to whichever task gets its partition next. Delivery is at
+//! least once; list mode's result cache lives in Redis and isn't used, so a
+//! job can occasionally run twice.
+//!
+//! Failed jobs are retried in place, up to `queue.max_attempts` times with
+//! list mode's backoff, holding up the rest of their partition meanwhile.
+//! Jobs that use up their attempts, and rejected payloads, are produced to
+//! the `queue.dead_letter_queue` topic (default `{first topic}.dead`) in list
+//! mode's dead-letter format. A job must finish within
+//! `kafka.max_poll_interval_seconds`, or the group takes its task for stuck
+//! and hands the partition to another.
+
+use anyhow::{Context, Result};
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::{Headers, Message, OwnedMessage};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Offset, TopicPartitionList};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::config::KafkaConfig;
+use crate::inflight;
+use crate::limits;
+use crate::reconnect::Backoff;
+use crate::webhook::WebhookClient;
+use crate::worker::{dead_letter_entry, job_span, parse_job, run_handler, LoopConfig, QueueJob};
+
+/// How long producing a dead letter may take, including librdkafka's retries
+const DEAD_LETTER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Header a message's trace context may travel in instead of its payload
+const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Connection settings shared by the consumer and the dead-letter producer
+fn client_config(kafka: &KafkaConfig) -> ClientConfig {
+    let mut client = ClientConfig::new();
+    client.set("bootstrap.servers", &kafka.brokers);
+    let optional = [
+        ("security.protocol", &kafka.security_protocol),
+        ("sasl.mechanism", &kafka.sasl_mechanism),
+        ("sasl.username", &kafka.sasl_username),
+        ("sasl.password", &kafka.sasl_password),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            client.set(key, value);
+        }
+    }
+    client
+}
+
+/// Consume jobs as one member of the consumer group until shutdown
+///
+/// librdkafka reconnects to brokers by itself, so only a consumer that can't
+/// be set up (a bad setting, say) is an error.
+pub(crate) async fn process_jobs<J, F, Fut>(
+    task: u64,
+    config: Arc<LoopConfig>,
+    webhook: &'static WebhookClient,
+    handler: Arc<F>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let kafka = &config.kafka;
+    let topics: Vec<&str> = config.queues.iter().map(|q| q.name.as_str()).collect();
+    let group = kafka
+        .group_id
+        .clone()
+        .unwrap_or_else(|| format!("{}-workers", topics[0]));
+    let consumer: Arc<StreamConsumer> = Arc::new(
+        client_config(kafka)
+            .set("group.id", &group)
+            .set("client.id", inflight::consumer(&config.worker, task))
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .set(
+                "max.poll.interval.ms",
+                (kafka.max_poll_interval_seconds * 1000).to_string(),
+            )
+            .create()
+            .context("Failed to create the Kafka consumer")?,
+    );
+    consumer
+        .subscribe(&topics)
+        .context("Failed to subscribe to the Kafka topics")?;
+    let producer: FutureProducer = client_config(kafka)
+        .create()
+        .context("Failed to create the Kafka dead-letter producer")?;
+    if task == 0 {
+        info!("Joined Kafka consumer group {}", group);
+        if topics.len() > 1 {
+            warn!("Kafka topics are consumed together, without priority between them");
+        }
+    }
+
+    let mut backoff = Backoff::new();
+    let mut stop = shutdown.clone();
+    loop {
+        let received = tokio::select! {
+            received = consumer.recv() => received.map(|message| message.detach()),
+            _ = stop.wait_for(|&stop| stop) => break,
+        };
+        let message = match received {
+            Ok(message) => {
+                backoff.reset();
+                message
+            }
+            Err(e) => {
+                let delay = backoff.next_delay();
+                warn!(
+                    "Kafka unavailable for job task {}, retrying in {:?}: {}",
+                    task, delay, e
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => continue,
+                    _ = stop.wait_for(|&stop| stop) => break,
+                }
+            }
+        };
+
+        let dealt_with =
+            run_message(&message, &config, &producer, webhook, &*handler, &shutdown).await;
+        if !dealt_with {
+            info!(
+                "Left offset {} of {}/{} uncommitted for redelivery",
+                message.offset(),
+                message.topic(),
+                message.partition()
+            );
+            break;
+        }
+        if let Err(e) = commit(&consumer, &message).await {
+            error!(
+                "Failed to commit offset {} of {}/{}; its job may run again: {:#}",
+                message.offset(),
+                message.topic(),
+                message.partition(),
+                e
+            );
+        }
+
+        if *shutdown.borrow() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Run the job in `message`, retrying it in place, and dead-letter it if it
+/// fails for good; returns `false` if shutdown interrupted it, leaving it to
+/// be redelivered
+async fn run_message<J, F, Fut>(
+    message: &OwnedMessage,
+    config: &LoopConfig,
+    producer: &FutureProducer,
+    webhook: &'static WebhookClient,
+    handler: &F,
+    shutdown: &watch::Receiver<bool>,
+) -> bool
+where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let topic = message.topic();
+    let payload = String::from_utf8_lossy(message.payload().unwrap_or_default()).into_owned();
+    let traceparent = traceparent(&payload, message);
+    let retry = &config.retry;
+    let mut parsed = parse_job::<J>(&payload);
+    let mut attempts = 0;
+
+    loop {
+        let job = match parsed {
+            Ok(job) => job,
+            Err(rejection) => {
+                error!("Rejected job: {}", rejection.error);
+                warn!("Payload was: {}", payload);
+                let dead = dead_letter(producer, config, message, &payload, &rejection.error, 0);
+                if let Err(e) = dead.await {
+                    error!("Failed to dead-letter rejected job: {:#}", e);
+                }
+                if let (Some(job_id), Some(endpoint)) = (&rejection.job_id, rejection.endpoint) {
+                    if let Err(we) = webhook
+                        .report_rejected(job_id, endpoint, &rejection, J::SCHEMA_VERSION)
+                        .await
+                    {
+                        error!("Failed to report rejected job: {:?}", we);
+                    }
+                }
+                return true;
+            }
+        };
+
+        let job_id = job.job_id().to_string();
+        let job_type = job.job_type();
+        if attempts == 0 {
+            info!(
+                "Processing {} job {} ({}/{} offset {})",
+                job_type,
+                job_id,
+                topic,
+                message.partition(),
+                message.offset()
+            );
+        } else {
+            info!(
+                "Processing {} job {} (attempt {} of {})",
+                job_type,
+                job_id,
+                attempts + 1,
+                retry.max_attempts
+            );
+        }
+
+        let span = job_span(
+            job_type,
+            &job_id,
+            topic,
+            attempts + 1,
+            traceparent.as_deref(),
+        );
+        let Some(result) = run_handler(handler, job, span, shutdown, config.grace).await else {
+            warn!(
+                "Job {} didn't finish within {:?} of shutdown",
+                job_id, config.grace
+            );
+            return false;
+        };
+
+        match result {
+            Ok(_) => return true,
+            Err(e) if attempts + 1 < retry.max_attempts && !limits::is_refused(&e) => {
+                attempts += 1;
+                let delay = retry.delay(attempts);
+                warn!(
+                    "Job {} failed (attempt {} of {}), retrying in {:?}: {:?}",
+                    job_id, attempts, retry.max_attempts, delay, e
+                );
+                let mut stop = shutdown.clone();
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = stop.wait_for(|&stop| stop) => return false,
+                }
+                // A job is consumed by running it, so each attempt parses
+                // the payload again
+                parsed = parse_job::<J>(&payload);
+            }
+            Err(e) => {
+                let attempts = attempts + 1;
+                if limits::is_refused(&e) {
+                    error!("Job {} refused: {:?}", job_id, e);
+                } else {
+                    error!("Job {} failed after {} attempts: {:?}", job_id, attempts, e);
+                }
+                let error = format!("{:#}", e);
+                let dead = dead_letter(producer, config, message, &payload, &error, attempts);
+                if let Err(de) = dead.await {
+                    error!("Failed to dead-letter job {}: {:#}", job_id, de);
+                }
+                if let Err(we) = webhook
+                    .report_failure(&job_id, job_type, &e.to_string())
+                    .await
+                {
+                    error!("Failed to report job failure: {:?}", we);
+                }
+                return true;
+            }
+        }
+    }
+}
+
+/// The trace context of the request that enqueued a message: the payload's
+/// `traceparent`, as in list mode, or else its `traceparent` header
+fn traceparent(payload: &str, message: &OwnedMessage) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(payload)
+        .ok()
+        .and_then(|p| p.get("traceparent")?.as_str().map(String::from))
+        .or_else(|| {
+            let header = message
+                .headers()?
+                .iter()
+                .find(|header| header.key == TRACEPARENT_HEADER)?;
+            std::str::from_utf8(header.value?).ok().map(String::from)
+        })
+}
+
+/// Produce a job that won't be run again to the dead-letter topic, keyed
+/// like the message it came in
+async fn dead_letter(
+    producer: &FutureProducer,
+    config: &LoopConfig,
+    message: &OwnedMessage,
+    payload: &str,
+    error: &str,
+    attempts: u32,
+) -> Result<()> {
+    let topic = &config.dead_letters;
+    let entry = dead_letter_entry(message.topic(), payload, error, attempts)?;
+    let mut record: FutureRecord<[u8], String> = FutureRecord::to(topic).payload(&entry);
+    if let Some(key) = message.key() {
+        record = record.key(key);
+    }
+    producer
+        .send(record, DEAD_LETTER_TIMEOUT)
+        .await
+        .map_err(|(e, _)| e)
+        .with_context(|| format!("Failed to produce to {}", topic))?;
+    warn!("Moved job to dead-letter topic {}", topic);
+    Ok(())
+}
+
+/// Commit the offset after `message`, waiting for the broker to confirm
+async fn commit(consumer: &Arc<StreamConsumer>, message: &OwnedMessage) -> Result<()> {
+    let mut offsets = TopicPartitionList::new();
+    offsets.add_partition_offset(
+        message.topic(),
+        message.partition(),
+        Offset::Offset(message.offset() + 1),
+    )?;
+    // A synchronous commit blocks its thread until the broker answers
+    let consumer = consumer.clone();
+    tokio::task::spawn_blocking(move || consumer.commit(&offsets, CommitMode::Sync)).await??;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::{Header, OwnedHeaders};
+    use rdkafka::Timestamp;
+
+    #[test]
+    fn test_traceparent_from_payload_or_header() {
+        let trace = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let message = |payload: &str, header: Option<&str>| {
+            let headers = header.map(|value| {
+                OwnedHeaders::new().insert(Header {
+                    key: TRACEPARENT_HEADER,
+                    value: Some(value),
+                })
+            });
+            OwnedMessage::new(
+                Some(payload.as_bytes().to_vec()),
+                None,
+                "dsp-jobs".to_string(),
+                Timestamp::NotAvailable,
+                0,
+                0,
+                headers,
+            )
+        };
+
+        let payload = format!(r#"{{"jobId":"j1","traceparent":"{}"}}"#, trace);
+        assert_eq!(
+            traceparent(&payload, &message(&payload, Some("00-other"))).as_deref(),
+            Some(trace)
+        );
+        let payload = r#"{"jobId":"j1"}"#;
+        assert_eq!(
+            traceparent(payload, &message(payload, Some(trace))).as_deref(),
+            Some(trace)
+        );
+        assert_eq!(traceparent(payload, &message(payload, None)), None);
+    }
+}
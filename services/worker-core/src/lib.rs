@@ -0,0 +1,52 @@
+//! Budi Worker Core - Infrastructure shared by the DSP and codec workers
+//!
+//! - Configuration from a TOML/YAML file and the environment
+//! - Storage in S3/MinIO, Azure Blob Storage or on local disk, and downloads
+//!   from plain HTTP(S) URLs, with SHA-256 checksums of everything moved
+//! - Webhook reporting back to the API, with versioned JSON Schemas of
+//!   results generated from their Rust types
+//! - Audio decoding (Symphonia, or a memory map for PCM WAVs) and WAV I/O,
+//!   and sample-accurate alignment of two renders; `AudioBuffer` and
+//!   true-peak metering come from `budi-dsp-core` and are re-exported
+//! - Disk and memory guardrails before downloads and decodes
+//! - A local cache of sources and decoded audio for jobs chained on a track
+//! - Job temp directories, and sweeping the ones killed workers leave
+//! - The job loop, over Redis lists or streams, BullMQ queues, or (with the
+//!   `kafka` and `grpc` features) Kafka topics and a gRPC job API
+//! - Logging and OTLP trace export
+
+pub mod align;
+pub mod audio;
+mod azure;
+mod bullmq;
+mod cache;
+pub mod checksum;
+pub mod config;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod http;
+mod inflight;
+#[cfg(feature = "kafka")]
+mod kafka;
+pub mod limits;
+mod local;
+mod mapped_wav;
+mod reconnect;
+pub mod s3;
+pub mod schema;
+pub mod storage;
+mod streams;
+pub mod telemetry;
+pub mod temp;
+pub mod webhook;
+pub mod worker;
+
+pub use audio::AudioBuffer;
+pub use budi_dsp_core::true_peak;
+pub use config::Config;
+pub use limits::{refused, Refused};
+pub use s3::S3Client;
+pub use storage::{Storage, Uploaded};
+pub use telemetry::{init_tracing, shutdown_tracing};
+pub use webhook::WebhookClient;
+pub use worker::{run_jobs, QueueJob};
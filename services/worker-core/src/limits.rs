@@ -0,0 +1,195 @@
+//! Resource guardrails checked before a job downloads or decodes audio
+//!
+//! A long, high-rate file decodes to far more memory than its size on disk
+//! suggests, and a worker that runs out gets OOM-killed along with every
+//! other job it holds. Downloads are checked against the free space on the
+//! disk they land on, and decodes are estimated from the file's header
+//! (frames × channels × 4 bytes) before any samples are read.
+//!
+//! Files from plain HTTP(S) source URLs are also capped at
+//! `max_http_download_mb`, since nothing else bounds what a third-party
+//! server sends.
+//!
+//! Audio over `max_decoded_mb` can never fit, nor a file over
+//! `max_http_download_mb` be fetched, so both fail with `Refused`, which the
+//! job loop dead-letters without retrying. A shortage that may go away, such
+//! as a full disk or other jobs holding memory, fails with a plain error, so
+//! the job is retried later like any other failure.
+//!
+//! Workers refuse other jobs that would fail the same way every time, such
+//! as settings out of range or audio that can't be decoded, by passing the
+//! error through `refused`.
+
+use anyhow::Result;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::config::LimitsConfig;
+
+const MB: u64 = 1024 * 1024;
+
+static LIMITS: OnceLock<LimitsConfig> = OnceLock::new();
+
+/// A job that can't succeed however often it is retried
+#[derive(Debug)]
+pub struct Refused(pub String);
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// Whether `error` means the job should go straight to the dead letter queue
+pub fn is_refused(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Refused>())
+}
+
+/// Mark `error` as one that retrying can't fix
+pub fn refused(error: anyhow::Error) -> anyhow::Error {
+    if is_refused(&error) {
+        return error;
+    }
+    Refused(format!("{:#}", error)).into()
+}
+
+/// Enforce `limits` for the rest of the process
+pub fn init(limits: &LimitsConfig) {
+    let _ = LIMITS.set(limits.clone());
+}
+
+/// This process's limits, or the defaults if `init` wasn't called
+fn get() -> &'static LimitsConfig {
+    LIMITS.get_or_init(LimitsConfig::default)
+}
+
+/// Check that downloading `bytes` into `path` leaves enough disk free
+pub(crate) fn check_download(path: &Path, bytes: u64) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // Not knowing the free space isn't a reason to fail the job
+    let Ok(free) = fs2::available_space(dir) else {
+        return Ok(());
+    };
+    let min_free = get().min_free_disk_mb * MB;
+    if free.saturating_sub(bytes) < min_free {
+        anyhow::bail!(
+            "Downloading {} MB would leave {} MB free in {}, under the {} MB minimum (MIN_FREE_DISK_MB)",
+            bytes.div_ceil(MB),
+            free.saturating_sub(bytes) / MB,
+            dir.display(),
+            min_free / MB
+        );
+    }
+    Ok(())
+}
+
+/// Check that a `bytes` long file from a plain HTTP(S) source URL is under
+/// `max_http_download_mb`
+pub(crate) fn check_http_download(bytes: u64) -> Result<()> {
+    let max_bytes = get().max_http_download_mb * MB;
+    if max_bytes > 0 && bytes > max_bytes {
+        return Err(Refused(format!(
+            "Source file is over the {} MB limit (MAX_HTTP_DOWNLOAD_MB)",
+            max_bytes / MB
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Check that `frames` × `channels` of decoded audio fits in memory
+pub(crate) fn check_decode(frames: u64, channels: usize) -> Result<()> {
+    let bytes = frames
+        .saturating_mul(channels as u64)
+        .saturating_mul(std::mem::size_of::<f32>() as u64);
+    decode_verdict(bytes, get().max_decoded_mb * MB, available_memory())
+}
+
+fn decode_verdict(bytes: u64, max_bytes: u64, available: Option<u64>) -> Result<()> {
+    if max_bytes > 0 && bytes > max_bytes {
+        return Err(Refused(format!(
+            "Decoded audio would take {} MB, over the {} MB limit (MAX_DECODED_MB)",
+            bytes.div_ceil(MB),
+            max_bytes / MB
+        ))
+        .into());
+    }
+    if let Some(available) = available.filter(|&available| bytes > available) {
+        anyhow::bail!(
+            "Decoded audio would take {} MB but only {} MB of memory is available",
+            bytes.div_ceil(MB),
+            available / MB
+        );
+    }
+    Ok(())
+}
+
+/// Memory this process can still allocate: the host's available memory,
+/// capped by the container's cgroup limit when there is one
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let host = meminfo_kb(&meminfo, "MemAvailable")? * 1024;
+    Some(cgroup_available().map_or(host, |cgroup| cgroup.min(host)))
+}
+
+/// Headroom under a cgroup v2 memory limit, counting reclaimable page cache
+/// as free; `None` when there is no limit
+fn cgroup_available() -> Option<u64> {
+    let read = |name: &str| std::fs::read_to_string(Path::new("/sys/fs/cgroup").join(name)).ok();
+    // An unlimited cgroup reads "max", which doesn't parse
+    let max: u64 = read("memory.max")?.trim().parse().ok()?;
+    let current: u64 = read("memory.current")?.trim().parse().ok()?;
+    let inactive_file = read("memory.stat")
+        .and_then(|stat| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix("inactive_file "))
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        })
+        .unwrap_or(0);
+    Some(max.saturating_sub(current.saturating_sub(inactive_file)))
+}
+
+fn meminfo_kb(meminfo: &str, field: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_limits() {
+        // Over the configured limit is refused outright
+        let error = decode_verdict(5000 * MB, 4096 * MB, None).unwrap_err();
+        assert!(is_refused(&error.context("Failed to decode")));
+
+        // Short on memory right now is a temporary failure
+        let error = decode_verdict(2000 * MB, 4096 * MB, Some(1000 * MB)).unwrap_err();
+        assert!(!is_refused(&error));
+        assert!(error.to_string().contains("only 1000 MB"));
+
+        let error = refused(anyhow::anyhow!("Tempo out of range").context("Invalid settings"));
+        assert!(is_refused(&error));
+        assert_eq!(error.to_string(), "Invalid settings: Tempo out of range");
+
+        assert!(decode_verdict(5000 * MB, 0, None).is_ok());
+        assert!(decode_verdict(100 * MB, 4096 * MB, Some(1000 * MB)).is_ok());
+
+        let meminfo = "MemTotal:       16318412 kB\nMemAvailable:    8159206 kB\n";
+        assert_eq!(meminfo_kb(meminfo, "MemAvailable"), Some(8159206));
+        assert_eq!(meminfo_kb(meminfo, "MemFree"), None);
+    }
+}
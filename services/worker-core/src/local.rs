@@ -0,0 +1,156 @@
+//! Local filesystem storage, for deployments without MinIO
+//!
+//! Objects are files under `storage.root` at their key's path, so the tree
+//! looks like the bucket would (`masters/{trackId}/...`), and their URLs are
+//! `file://` URLs to those files. Writes go to a `.partial` file beside the
+//! object and are renamed into place, so a reader never sees half a file.
+
+use anyhow::{Context, Result};
+use std::path::{Component, Path, PathBuf};
+
+use crate::limits::{self, Refused};
+
+/// Files under a root directory, addressed by S3-style keys
+pub(crate) struct LocalStore {
+    /// Canonical, so sources can be checked against it
+    root: PathBuf,
+}
+
+impl LocalStore {
+    /// Use `root`, creating it if needed
+    pub(crate) fn new(root: &str) -> Result<Self> {
+        std::fs::create_dir_all(root)
+            .with_context(|| format!("Failed to create storage root {}", root))?;
+        let root = std::fs::canonicalize(root)
+            .with_context(|| format!("Failed to resolve storage root {}", root))?;
+        Ok(Self { root })
+    }
+
+    /// Copy a `file://` URL under the root to a local path
+    pub(crate) async fn download_file(&self, url: &str, local_path: &Path) -> Result<()> {
+        let path = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .with_context(|| format!("Could not parse file URL: {}", url))?;
+        let path = tokio::fs::canonicalize(&path)
+            .await
+            .with_context(|| format!("Failed to find {}", path.display()))?;
+        if !path.starts_with(&self.root) {
+            return Err(Refused(format!(
+                "{} is outside the storage root (STORAGE_ROOT)",
+                path.display()
+            ))
+            .into());
+        }
+
+        tracing::info!("Copying {:?} to {:?}", path, local_path);
+        let size = tokio::fs::metadata(&path)
+            .await
+            .context("Failed to read source file")?
+            .len();
+        limits::check_download(local_path, size)?;
+        tokio::fs::copy(&path, local_path)
+            .await
+            .context("Failed to copy source file")?;
+        Ok(())
+    }
+
+    /// Copy a file to `key`, returning its `object_url`
+    pub(crate) async fn upload_file(&self, local_path: &Path, key: &str) -> Result<String> {
+        tracing::info!("Copying {:?} to {}", local_path, key);
+        let (path, partial) = self.prepare(key).await?;
+        tokio::fs::copy(local_path, &partial)
+            .await
+            .context("Failed to write to storage")?;
+        self.finish(&partial, &path, key).await
+    }
+
+    /// Write bytes to `key`, returning their `object_url`
+    pub(crate) async fn upload_bytes(&self, data: &[u8], key: &str) -> Result<String> {
+        tracing::info!("Writing {} bytes to {}", data.len(), key);
+        let (path, partial) = self.prepare(key).await?;
+        tokio::fs::write(&partial, data)
+            .await
+            .context("Failed to write to storage")?;
+        self.finish(&partial, &path, key).await
+    }
+
+    /// The `file://` URL of `key`
+    pub(crate) fn object_url(&self, key: &str) -> Result<String> {
+        let path = self.object_path(key)?;
+        url::Url::from_file_path(&path)
+            .map(String::from)
+            .map_err(|_| anyhow::anyhow!("Can't make a file URL for {}", path.display()))
+    }
+
+    /// Where `key` lives; keys can't climb out of the root
+    fn object_path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        anyhow::ensure!(
+            !key.is_empty()
+                && relative
+                    .components()
+                    .all(|part| matches!(part, Component::Normal(_))),
+            "Invalid storage key: {}",
+            key
+        );
+        Ok(self.root.join(relative))
+    }
+
+    /// The object's path and the `.partial` path it is written to first,
+    /// with the directory they share created
+    async fn prepare(&self, key: &str) -> Result<(PathBuf, PathBuf)> {
+        let path = self.object_path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create storage directory")?;
+        }
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        Ok((path, partial.into()))
+    }
+
+    async fn finish(&self, partial: &Path, path: &Path, key: &str) -> Result<String> {
+        tokio::fs::rename(partial, path)
+            .await
+            .context("Failed to write to storage")?;
+        self.object_url(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip_and_containment() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path().join("storage").to_str().unwrap()).unwrap();
+
+        let url = store
+            .upload_bytes(b"master", "masters/t1/1-master.wav")
+            .await
+            .unwrap();
+        assert!(url.starts_with("file://"));
+        assert!(url.ends_with("/storage/masters/t1/1-master.wav"));
+        assert!(!dir
+            .path()
+            .join("storage/masters/t1/1-master.wav.partial")
+            .exists());
+
+        let copy = dir.path().join("copy.wav");
+        store.download_file(&url, &copy).await.unwrap();
+        assert_eq!(std::fs::read(&copy).unwrap(), b"master");
+
+        // Keys stay under the root, and so do sources
+        assert!(store.upload_bytes(b"x", "../escape.wav").await.is_err());
+        assert!(store.upload_bytes(b"x", "/etc/escape.wav").await.is_err());
+        let outside = url::Url::from_file_path(&copy).unwrap();
+        let error = store
+            .download_file(outside.as_str(), &dir.path().join("again.wav"))
+            .await
+            .unwrap_err();
+        assert!(limits::is_refused(&error));
+    }
+}
@@ -0,0 +1,263 @@
+//! Memory-mapped reading of plain PCM and float WAV files
+//!
+//! WAV is the most common upload, and its samples need no decoding: the data
+//! chunk is mapped read-only and converted to f32 a window at a time, the
+//! same way `append_samples` converts Symphonia's output. The mapping is
+//! backed by the page cache rather than the job's heap, so only the windows
+//! being converted count towards resident memory. Anything else (RF64,
+//! compressed WAV, other containers) goes through Symphonia.
+//!
+//! A mapped file must not be truncated while it is read; job sources live in
+//! the job's own temp directory, where nothing else writes them.
+
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::audio::AudioBuffer;
+
+/// WAVE format tags
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// How each sample of the data chunk is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    U8,
+    S16,
+    S24,
+    S32,
+    F32,
+    F64,
+}
+
+/// A WAV file's data chunk, mapped into memory
+pub(crate) struct MappedWav {
+    map: Mmap,
+    /// Byte range of the samples within the file
+    data: Range<usize>,
+    encoding: Encoding,
+    pub channels: usize,
+    pub sample_rate: u32,
+    pub frames: u64,
+}
+
+impl MappedWav {
+    /// Map `path` if it is a WAV this module reads; `None` otherwise
+    pub fn open(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let map = Mmap::map(&file)?;
+        let header = parse(map.bytes())?;
+        let block_align = header.channels * header.encoding.width();
+        Some(Self {
+            frames: (header.data.len() / block_align) as u64,
+            data: header.data,
+            encoding: header.encoding,
+            channels: header.channels,
+            sample_rate: header.sample_rate,
+            map,
+        })
+    }
+
+    /// Append up to `count` frames starting at `start` to `buffer`; returns
+    /// how many there were
+    pub fn read(&self, start: u64, count: usize, buffer: &mut AudioBuffer) -> usize {
+        let width = self.encoding.width();
+        let block_align = self.channels * width;
+        let start = (start.min(self.frames) as usize) * block_align;
+        let end = (start + count * block_align).min(self.data.len());
+        let bytes = &self.map.bytes()[self.data.start + start..self.data.start + end];
+
+        match self.encoding {
+            Encoding::U8 => push_frames(bytes, width, buffer, |s| (s[0] as f32 - 128.0) / 128.0),
+            Encoding::S16 => push_frames(bytes, width, buffer, |s| {
+                i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0
+            }),
+            Encoding::S24 => push_frames(bytes, width, buffer, |s| {
+                // Sign-extend from the top byte
+                (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f32 / 8388608.0
+            }),
+            Encoding::S32 => push_frames(bytes, width, buffer, |s| {
+                i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / 2147483648.0
+            }),
+            Encoding::F32 => push_frames(bytes, width, buffer, |s| {
+                f32::from_le_bytes([s[0], s[1], s[2], s[3]])
+            }),
+            Encoding::F64 => push_frames(bytes, width, buffer, |s| {
+                f64::from_le_bytes([s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7]]) as f32
+            }),
+        }
+        bytes.len() / block_align
+    }
+}
+
+impl Encoding {
+    /// Bytes per sample
+    fn width(self) -> usize {
+        match self {
+            Encoding::U8 => 1,
+            Encoding::S16 => 2,
+            Encoding::S24 => 3,
+            Encoding::S32 | Encoding::F32 => 4,
+            Encoding::F64 => 8,
+        }
+    }
+}
+
+/// Deinterleave `bytes`, whole frames of `width`-byte samples, into `buffer`
+fn push_frames(
+    bytes: &[u8],
+    width: usize,
+    buffer: &mut AudioBuffer,
+    convert: impl Fn(&[u8]) -> f32,
+) {
+    let block_align = buffer.channels * width;
+    for (ch, plane) in buffer.samples.iter_mut().enumerate() {
+        let offset = ch * width;
+        plane.extend(
+            bytes
+                .chunks_exact(block_align)
+                .map(|frame| convert(&frame[offset..offset + width])),
+        );
+    }
+}
+
+struct Header {
+    encoding: Encoding,
+    channels: usize,
+    sample_rate: u32,
+    data: Range<usize>,
+}
+
+/// Find the format and data chunks of a RIFF/WAVE file
+fn parse(bytes: &[u8]) -> Option<Header> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let u16_at = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+    let u32_at = |at: usize| Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+
+    let mut format = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let size = u32_at(at + 4)? as usize;
+        let body = at + 8;
+        match id {
+            b"fmt " if size >= 16 => {
+                let mut tag = u16_at(body)?;
+                if tag == WAVE_FORMAT_EXTENSIBLE && size >= 40 {
+                    // The sub-format GUID starts with the plain format tag
+                    tag = u16_at(body + 24)?;
+                }
+                let channels = u16_at(body + 2)? as usize;
+                let sample_rate = u32_at(body + 4)?;
+                let block_align = u16_at(body + 12)? as usize;
+                let bits = u16_at(body + 14)?;
+                let encoding = match (tag, bits) {
+                    (WAVE_FORMAT_PCM, 8) => Encoding::U8,
+                    (WAVE_FORMAT_PCM, 16) => Encoding::S16,
+                    (WAVE_FORMAT_PCM, 24) => Encoding::S24,
+                    (WAVE_FORMAT_PCM, 32) => Encoding::S32,
+                    (WAVE_FORMAT_IEEE_FLOAT, 32) => Encoding::F32,
+                    (WAVE_FORMAT_IEEE_FLOAT, 64) => Encoding::F64,
+                    _ => return None,
+                };
+                if channels == 0 || sample_rate == 0 || block_align != channels * encoding.width() {
+                    return None;
+                }
+                format = Some((encoding, channels, sample_rate));
+            }
+            b"data" => {
+                let (encoding, channels, sample_rate) = format?;
+                // Writers that stream leave the size unset; the data runs to
+                // the end of the file
+                let end = body.saturating_add(size).min(bytes.len());
+                return Some(Header {
+                    encoding,
+                    channels,
+                    sample_rate,
+                    data: body..end,
+                });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        at = body.checked_add(size)?.checked_add(size & 1)?;
+    }
+    None
+}
+
+/// A read-only private mapping of a whole file
+#[cfg(unix)]
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only and owned by this value
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    fn map(file: &File) -> Option<Self> {
+        use std::os::fd::AsRawFd;
+
+        let len = usize::try_from(file.metadata().ok()?.len()).ok()?;
+        if len == 0 {
+            return None;
+        }
+        // SAFETY: a fresh read-only mapping of an open file, checked for
+        // failure; it is unmapped exactly once, in `drop`
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return None;
+        }
+        // SAFETY: `ptr..ptr + len` is the mapping just made. The advice only
+        // tunes read-ahead, so a failure is ignored
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Some(Self { ptr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` readable bytes and lives as long as self
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` describe a mapping made by `map`
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Elsewhere every file goes through Symphonia
+#[cfg(not(unix))]
+struct Mmap;
+
+#[cfg(not(unix))]
+impl Mmap {
+    fn map(_file: &File) -> Option<Self> {
+        None
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &[]
+    }
+}
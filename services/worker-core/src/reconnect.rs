@@ -0,0 +1,136 @@
+//! Riding out Redis outages
+//!
+//! A dropped connection, a restarting server or a failover shouldn't take
+//! the worker down with it. Errors are split into transient ones (network
+//! failures and the replies Redis gives while loading or failing over),
+//! which are retried with a capped exponential backoff on a fresh
+//! connection, and everything else (bad credentials, wrong key types),
+//! which no amount of retrying fixes and is returned.
+
+use anyhow::Result;
+use redis::ErrorKind;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::warn;
+
+/// First wait after a failure
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest wait between reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Whether `error` comes from a Redis outage that may pass
+pub(crate) fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<redis::RedisError>())
+        .any(|e| {
+            e.is_io_error()
+                || matches!(
+                    e.kind(),
+                    ErrorKind::BusyLoadingError
+                        | ErrorKind::TryAgain
+                        | ErrorKind::ClusterDown
+                        | ErrorKind::MasterDown
+                        | ErrorKind::ReadOnly
+                )
+        })
+}
+
+/// Exponential backoff between reconnection attempts
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Self {
+            next: INITIAL_BACKOFF,
+        }
+    }
+
+    /// The wait before the next attempt, doubling the one after it
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+        delay
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.next = INITIAL_BACKOFF;
+    }
+}
+
+/// Run `attempt` until it succeeds or fails for good, backing off after
+/// transient failures
+///
+/// An attempt that ran for a while before failing had a working connection,
+/// so the backoff starts over after it. Returns `None` if shutdown is
+/// requested while waiting.
+pub(crate) async fn retry<T, F, Fut>(
+    what: &str,
+    shutdown: &watch::Receiver<bool>,
+    backoff: &mut Backoff,
+    mut attempt: F,
+) -> Result<Option<T>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    loop {
+        if *shutdown.borrow() {
+            return Ok(None);
+        }
+        let started = Instant::now();
+        match attempt().await {
+            Ok(value) => return Ok(Some(value)),
+            Err(e) if is_transient(&e) => {
+                if started.elapsed() > MAX_BACKOFF {
+                    backoff.reset();
+                }
+                let delay = backoff.next_delay();
+                warn!(
+                    "Redis unavailable for {}, retrying in {:?}: {:#}",
+                    what, delay, e
+                );
+                let mut shutdown = shutdown.clone();
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.wait_for(|stop| *stop) => return Ok(None),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transient_errors_and_backoff() {
+        let dropped: anyhow::Error =
+            redis::RedisError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset))
+                .into();
+        assert!(is_transient(&dropped.context("Failed to pop job")));
+        let loading: anyhow::Error =
+            redis::RedisError::from((ErrorKind::BusyLoadingError, "loading")).into();
+        assert!(is_transient(&loading));
+
+        let auth: anyhow::Error =
+            redis::RedisError::from((ErrorKind::AuthenticationFailed, "WRONGPASS")).into();
+        assert!(!is_transient(&auth));
+        assert!(!is_transient(&anyhow::anyhow!("Invalid job payload")));
+
+        let mut backoff = Backoff::new();
+        let delays: Vec<u64> = (0..8)
+            .map(|_| backoff.next_delay().as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 16000, 30000, 30000]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), INITIAL_BACKOFF);
+    }
+}
@@ -0,0 +1,588 @@
+//! S3/MinIO file operations
+//!
+//! Requests S3 throttles (`SlowDown`, 429) or fails with a 5xx, and ones that
+//! time out or lose their connection, are retried by the SDK up to
+//! `s3.max_attempts` times with jittered exponential backoff, so a brief 503
+//! costs a job a pause rather than a failed attempt. Other errors, such as a
+//! missing object or bad credentials, reach the job at once. At most
+//! `s3.max_connections` downloads and uploads run at a time across the
+//! worker's jobs; the rest wait for a slot.
+//!
+//! Uploads carry the configured server-side encryption, storage class and
+//! tags, looked up by artifact: the first part of the key.
+
+use anyhow::{Context, Result};
+use aws_sdk_s3::{
+    config::{retry::RetryConfig, timeout::TimeoutConfig, BehaviorVersion, Credentials, Region},
+    presigning::PresigningConfig,
+    primitives::{ByteStream, Length},
+    types::{CompletedMultipartUpload, CompletedPart, ServerSideEncryption, StorageClass},
+    Client,
+};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::config::{S3Config, S3ObjectSettings, S3RetryMode};
+use crate::limits;
+use crate::reconnect::Backoff;
+
+/// Buffer between a download and its file
+pub(crate) const DOWNLOAD_BUFFER_BYTES: usize = 1024 * 1024;
+
+/// S3 client wrapper
+pub struct S3Client {
+    client: Client,
+    bucket: String,
+    endpoint: String,
+    part_size: u64,
+    part_attempts: u32,
+    /// `None` for plain URLs
+    presign_expiry: Option<Duration>,
+    /// Slots for transfers in flight, `None` when unlimited
+    connections: Option<Semaphore>,
+    /// Encryption, storage class and tags for uploads
+    objects: S3ObjectSettings,
+    /// `objects` for each artifact with overrides
+    artifact_objects: HashMap<String, S3ObjectSettings>,
+}
+
+impl S3Client {
+    /// Create a new S3 client
+    pub async fn new(config: &S3Config) -> Result<Self> {
+        let objects = config.object_settings("");
+        let artifact_objects = config
+            .artifacts
+            .keys()
+            .map(|artifact| (artifact.clone(), config.object_settings(artifact)))
+            .collect();
+        let S3Config {
+            endpoint,
+            access_key,
+            secret_key,
+            bucket,
+            part_size_mb,
+            part_attempts,
+            presign_expiry_seconds,
+            retry_mode,
+            max_attempts,
+            retry_base_ms,
+            connect_timeout_seconds,
+            read_timeout_seconds,
+            max_connections,
+            encryption: _,
+            kms_key_id: _,
+            storage_class: _,
+            tags: _,
+            artifacts: _,
+        } = config.clone();
+
+        let credentials = Credentials::new(access_key, secret_key, None, None, "environment");
+
+        let retry = match retry_mode {
+            S3RetryMode::Standard => RetryConfig::standard(),
+            S3RetryMode::Adaptive => RetryConfig::adaptive(),
+        }
+        .with_max_attempts(max_attempts)
+        .with_initial_backoff(Duration::from_millis(retry_base_ms));
+        let timeout = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+        let mut timeouts = TimeoutConfig::builder();
+        timeouts
+            .set_connect_timeout(timeout(connect_timeout_seconds))
+            .set_read_timeout(timeout(read_timeout_seconds));
+
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(&endpoint)
+            .region(Region::new("us-east-1"))
+            .credentials_provider(credentials)
+            .force_path_style(true)
+            .retry_config(retry)
+            .timeout_config(timeouts.build())
+            .build();
+
+        let client = Client::from_conf(config);
+
+        Ok(Self {
+            client,
+            bucket,
+            endpoint,
+            part_size: part_size_mb * 1024 * 1024,
+            part_attempts,
+            presign_expiry: (presign_expiry_seconds > 0)
+                .then(|| Duration::from_secs(presign_expiry_seconds)),
+            connections: (max_connections > 0).then(|| Semaphore::new(max_connections)),
+            objects,
+            artifact_objects,
+        })
+    }
+
+    /// The settings for uploads to `key`, by the artifact its first part
+    /// names
+    fn object_settings(&self, key: &str) -> &S3ObjectSettings {
+        let artifact = key.split('/').next().unwrap_or_default();
+        self.artifact_objects.get(artifact).unwrap_or(&self.objects)
+    }
+
+    /// Wait for a transfer slot under `s3.max_connections`
+    async fn slot(&self) -> Option<SemaphorePermit<'_>> {
+        self.connections.as_ref()?.acquire().await.ok()
+    }
+
+    /// Download a file from S3 to a local path
+    #[tracing::instrument(name = "s3.download", skip(self, local_path))]
+    pub async fn download_file(&self, url: &str, local_path: &Path) -> Result<()> {
+        // Parse the URL to get bucket and key
+        let (bucket, key) = parse_s3_url(url)?;
+        let _slot = self.slot().await;
+
+        tracing::info!(
+            "Downloading from s3://{}/{} to {:?}",
+            bucket,
+            key,
+            local_path
+        );
+
+        let response = self
+            .client
+            .get_object()
+            .bucket(&bucket)
+            .key(&key)
+            .send()
+            .await
+            .context("Failed to get object from S3")?;
+
+        if let Some(size) = response
+            .content_length()
+            .and_then(|n| u64::try_from(n).ok())
+        {
+            limits::check_download(local_path, size)?;
+        }
+
+        // Streamed to disk a chunk at a time, so a large object isn't held
+        // in memory; a failed download doesn't leave a partial file behind
+        let written = write_body(response.body, local_path).await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(local_path).await;
+        }
+        written
+    }
+
+    /// Upload a file from local path to S3, returning its `object_url`
+    ///
+    /// The file is streamed from disk rather than read into memory. Files
+    /// bigger than `s3.part_size_mb` go up as a multipart upload, one part at
+    /// a time, each tried up to `s3.part_attempts` times.
+    #[tracing::instrument(name = "s3.upload", skip(self, local_path, content_type))]
+    pub async fn upload_file(
+        &self,
+        local_path: &Path,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String> {
+        tracing::info!("Uploading {:?} to s3://{}/{}", local_path, self.bucket, key);
+        let _slot = self.slot().await;
+
+        let size = tokio::fs::metadata(local_path)
+            .await
+            .context("Failed to open file for upload")?
+            .len();
+
+        if size <= self.part_size {
+            let body = ByteStream::from_path(local_path)
+                .await
+                .context("Failed to open file for upload")?;
+            let settings = self.object_settings(key);
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .body(body)
+                .content_type(content_type)
+                .set_server_side_encryption(
+                    settings
+                        .encryption
+                        .as_deref()
+                        .map(ServerSideEncryption::from),
+                )
+                .set_ssekms_key_id(settings.kms_key_id.clone())
+                .set_storage_class(settings.storage_class.as_deref().map(StorageClass::from))
+                .set_tagging(settings.tags.clone())
+                .send()
+                .await
+                .context("Failed to upload to S3")?;
+        } else {
+            self.upload_multipart(local_path, size, key, content_type)
+                .await?;
+        }
+
+        self.object_url(key).await
+    }
+
+    /// Upload a file in `part_size` parts, aborting the upload if a part
+    /// can't be sent so S3 doesn't keep the ones that were
+    async fn upload_multipart(
+        &self,
+        local_path: &Path,
+        size: u64,
+        key: &str,
+        content_type: &str,
+    ) -> Result<()> {
+        let settings = self.object_settings(key);
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .set_server_side_encryption(
+                settings
+                    .encryption
+                    .as_deref()
+                    .map(ServerSideEncryption::from),
+            )
+            .set_ssekms_key_id(settings.kms_key_id.clone())
+            .set_storage_class(settings.storage_class.as_deref().map(StorageClass::from))
+            .set_tagging(settings.tags.clone())
+            .send()
+            .await
+            .context("Failed to start multipart upload to S3")?;
+        let upload_id = upload
+            .upload_id()
+            .context("S3 didn't return a multipart upload ID")?;
+
+        let uploaded = self.upload_parts(local_path, size, key, upload_id).await;
+        let parts = match uploaded {
+            Ok(parts) => parts,
+            Err(e) => {
+                let aborted = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(upload_id)
+                    .send()
+                    .await;
+                if let Err(ae) = aborted {
+                    tracing::warn!("Failed to abort multipart upload of {}: {}", key, ae);
+                }
+                return Err(e);
+            }
+        };
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .context("Failed to complete multipart upload to S3")?;
+        Ok(())
+    }
+
+    async fn upload_parts(
+        &self,
+        local_path: &Path,
+        size: u64,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<Vec<CompletedPart>> {
+        let count = size.div_ceil(self.part_size);
+        let mut parts = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let offset = index * self.part_size;
+            let length = self.part_size.min(size - offset);
+            // Part numbers start at 1
+            let number = index as i32 + 1;
+            let mut backoff = Backoff::new();
+            let mut attempt = 1;
+            let etag = loop {
+                match self
+                    .upload_part(local_path, offset, length, key, upload_id, number)
+                    .await
+                {
+                    Ok(etag) => break etag,
+                    Err(e) if attempt < self.part_attempts => {
+                        let delay = backoff.next_delay();
+                        tracing::warn!(
+                            "Part {} of {} failed (attempt {} of {}), retrying in {:?}: {:#}",
+                            number,
+                            count,
+                            attempt,
+                            self.part_attempts,
+                            delay,
+                            e
+                        );
+                        tokio::time::sleep(delay).await;
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        return Err(e.context(format!(
+                            "Failed to upload part {} of {} to S3",
+                            number, count
+                        )))
+                    }
+                }
+            };
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(number)
+                    .e_tag(etag)
+                    .build(),
+            );
+        }
+        Ok(parts)
+    }
+
+    /// Send `length` bytes of the file from `offset` as part `number`;
+    /// returns its ETag
+    async fn upload_part(
+        &self,
+        local_path: &Path,
+        offset: u64,
+        length: u64,
+        key: &str,
+        upload_id: &str,
+        number: i32,
+    ) -> Result<String> {
+        let body = ByteStream::read_from()
+            .path(local_path)
+            .offset(offset)
+            .length(Length::Exact(length))
+            .build()
+            .await
+            .context("Failed to read file for upload")?;
+        let part = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(number)
+            .body(body)
+            .send()
+            .await?;
+        part.e_tag()
+            .map(String::from)
+            .context("S3 didn't return an ETag for the part")
+    }
+
+    /// Upload bytes directly to S3, returning their `object_url`
+    #[tracing::instrument(name = "s3.upload", skip(self, data, content_type))]
+    pub async fn upload_bytes(&self, data: &[u8], key: &str, content_type: &str) -> Result<String> {
+        tracing::info!(
+            "Uploading {} bytes to s3://{}/{}",
+            data.len(),
+            self.bucket,
+            key
+        );
+        let _slot = self.slot().await;
+
+        let body = ByteStream::from(Bytes::from(data.to_vec()));
+        let settings = self.object_settings(key);
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(body)
+            .content_type(content_type)
+            .set_server_side_encryption(
+                settings
+                    .encryption
+                    .as_deref()
+                    .map(ServerSideEncryption::from),
+            )
+            .set_ssekms_key_id(settings.kms_key_id.clone())
+            .set_storage_class(settings.storage_class.as_deref().map(StorageClass::from))
+            .set_tagging(settings.tags.clone())
+            .send()
+            .await
+            .context("Failed to upload to S3")?;
+
+        self.object_url(key).await
+    }
+
+    /// A URL to fetch an object by: presigned for `s3.presign_expiry_seconds`,
+    /// or the plain `endpoint/bucket/key` URL with presigning off
+    ///
+    /// Either form can be passed back as a job's source URL.
+    pub async fn object_url(&self, key: &str) -> Result<String> {
+        let Some(expiry) = self.presign_expiry else {
+            return Ok(format!("{}/{}/{}", self.endpoint, self.bucket, key));
+        };
+        let presigned = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(PresigningConfig::expires_in(expiry)?)
+            .await
+            .context("Failed to presign S3 URL")?;
+        Ok(presigned.uri().to_string())
+    }
+
+    /// Whether `url` names an object in S3 rather than a plain web file
+    pub(crate) fn is_s3_url(&self, url: &str) -> bool {
+        if url.starts_with("s3://") {
+            return true;
+        }
+        match (url::Url::parse(url), url::Url::parse(&self.endpoint)) {
+            (Ok(url), Ok(endpoint)) => url.origin() == endpoint.origin(),
+            // Left to `parse_s3_url` to report
+            _ => true,
+        }
+    }
+}
+
+/// Write an object body to `path` through a `DOWNLOAD_BUFFER_BYTES` buffer
+async fn write_body(mut body: ByteStream, path: &Path) -> Result<()> {
+    let file = File::create(path).await.context("Failed to create file")?;
+    let mut file = BufWriter::with_capacity(DOWNLOAD_BUFFER_BYTES, file);
+    while let Some(chunk) = body
+        .try_next()
+        .await
+        .context("Failed to read object from S3")?
+    {
+        file.write_all(&chunk)
+            .await
+            .context("Failed to write file")?;
+    }
+    file.flush().await.context("Failed to write file")?;
+    Ok(())
+}
+
+/// Parse an S3 URL to extract bucket and key
+fn parse_s3_url(url: &str) -> Result<(String, String)> {
+    // Handle both http://minio:9000/bucket/key and s3://bucket/key formats
+    if let Some(stripped) = url.strip_prefix("s3://") {
+        let parts: Vec<&str> = stripped.splitn(2, '/').collect();
+        if parts.len() == 2 {
+            return Ok((parts[0].to_string(), parts[1].to_string()));
+        }
+    }
+
+    // HTTP URL format: http://host:port/bucket/key
+    if url.starts_with("http://") || url.starts_with("https://") {
+        let url_parsed = url::Url::parse(url).context("Invalid URL")?;
+        let path = url_parsed.path().trim_start_matches('/');
+        let parts: Vec<&str> = path.splitn(2, '/').collect();
+        if parts.len() == 2 {
+            return Ok((parts[0].to_string(), parts[1].to_string()));
+        }
+    }
+
+    anyhow::bail!("Could not parse S3 URL: {}", url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_s3_url() {
+        let (bucket, key) = parse_s3_url("http://localhost:9000/audio/tracks/test.wav").unwrap();
+        assert_eq!(bucket, "audio");
+        assert_eq!(key, "tracks/test.wav");
+
+        let (bucket, key) = parse_s3_url("s3://audio/tracks/test.wav").unwrap();
+        assert_eq!(bucket, "audio");
+        assert_eq!(key, "tracks/test.wav");
+    }
+
+    #[tokio::test]
+    async fn test_retry_and_timeout_settings() {
+        let s3 = S3Client::new(&S3Config {
+            max_attempts: 7,
+            read_timeout_seconds: 0,
+            max_connections: 2,
+            ..S3Config::default()
+        })
+        .await
+        .unwrap();
+        let config = s3.client.config();
+        let retry = config.retry_config().unwrap();
+        assert_eq!(retry.max_attempts(), 7);
+        assert_eq!(retry.initial_backoff(), Duration::from_secs(1));
+        let timeouts = config.timeout_config().unwrap();
+        assert_eq!(timeouts.connect_timeout(), Some(Duration::from_secs(10)));
+        assert_eq!(timeouts.read_timeout(), None);
+
+        let _first = s3.slot().await;
+        let _second = s3.slot().await;
+        assert_eq!(s3.connections.as_ref().unwrap().available_permits(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_object_settings_by_artifact() {
+        let mut config = S3Config {
+            encryption: Some("aws:kms".to_string()),
+            storage_class: Some("STANDARD".to_string()),
+            ..S3Config::default()
+        };
+        config.artifacts.insert(
+            "masters".to_string(),
+            S3ObjectSettings {
+                storage_class: Some("STANDARD_IA".to_string()),
+                ..S3ObjectSettings::default()
+            },
+        );
+        let s3 = S3Client::new(&config).await.unwrap();
+        let masters = s3.object_settings("masters/t1/1-master_24bit.wav");
+        assert_eq!(masters.storage_class.as_deref(), Some("STANDARD_IA"));
+        assert_eq!(masters.encryption.as_deref(), Some("aws:kms"));
+        let previews = s3.object_settings("previews/t1/1-mp3.mp3");
+        assert_eq!(previews.storage_class.as_deref(), Some("STANDARD"));
+    }
+
+    #[tokio::test]
+    async fn test_s3_and_http_urls() {
+        let s3 = S3Client::new(&S3Config::default()).await.unwrap();
+        assert!(s3.is_s3_url("s3://audio/tracks/test.wav"));
+        assert!(s3.is_s3_url("http://localhost:9000/audio/tracks/test.wav"));
+        assert!(!s3.is_s3_url("https://cdn.example.com/audio/test.wav"));
+        assert!(!s3.is_s3_url("http://localhost:8080/audio/test.wav"));
+    }
+
+    #[tokio::test]
+    async fn test_presigned_url_parses_back_to_key() {
+        let s3 = S3Client::new(&S3Config::default()).await.unwrap();
+        let url = s3.object_url("masters/t1/master.wav").await.unwrap();
+        assert!(url.contains("X-Amz-Signature="));
+        let (bucket, key) = parse_s3_url(&url).unwrap();
+        assert_eq!(bucket, "audio");
+        assert_eq!(key, "masters/t1/master.wav");
+
+        let plain = S3Client::new(&S3Config {
+            presign_expiry_seconds: 0,
+            ..S3Config::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            plain.object_url("masters/t1/master.wav").await.unwrap(),
+            "http://localhost:9000/audio/masters/t1/master.wav"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_body_streams_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("download.bin");
+        let data: Vec<u8> = (0..3 * DOWNLOAD_BUFFER_BYTES).map(|i| i as u8).collect();
+        write_body(ByteStream::from(data.clone()), &path)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+}
@@ -0,0 +1,249 @@
+//! Versioned, published JSON Schemas of webhook results
+//!
+//! The `data` type of a result with a published schema is declared inside
+//! `result_schema!`, which leaves the struct as written and implements
+//! `JsonSchema` from its fields, so the schema is generated from what is
+//! actually serialized rather than kept alongside it. Such structs must use
+//! `#[serde(rename_all = "camelCase")]` (or single-word field names) and no
+//! field-level renames; `Option` fields are optional in the schema and may
+//! be null.
+//!
+//! Every result is posted with the `schemaVersion` of its `ResultSchema`,
+//! bumped whenever its data changes shape, and the schemas are published
+//! as `<endpoint>.v<version>.schema.json` under `packages/contracts/schemas`.
+//! Each worker's tests check the published files against the Rust types;
+//! run them with `UPDATE_SCHEMAS=1` to rewrite them after a change.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub use serde_json::{json, Value};
+
+/// A type whose JSON form has a schema
+pub trait JsonSchema {
+    fn json_schema() -> Value;
+}
+
+macro_rules! primitive_schemas {
+    ($($ty:ty => $kind:literal),* $(,)?) => {
+        $(
+            impl JsonSchema for $ty {
+                fn json_schema() -> Value {
+                    json!({ "type": $kind })
+                }
+            }
+        )*
+    };
+}
+
+primitive_schemas! {
+    bool => "boolean",
+    f32 => "number",
+    f64 => "number",
+    u8 => "integer",
+    u16 => "integer",
+    u32 => "integer",
+    u64 => "integer",
+    usize => "integer",
+    i32 => "integer",
+    i64 => "integer",
+    str => "string",
+    String => "string",
+}
+
+impl<T: JsonSchema + ?Sized> JsonSchema for &T {
+    fn json_schema() -> Value {
+        T::json_schema()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn json_schema() -> Value {
+        json!({ "anyOf": [T::json_schema(), { "type": "null" }] })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn json_schema() -> Value {
+        json!({ "type": "array", "items": T::json_schema() })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for [T] {
+    fn json_schema() -> Value {
+        Vec::<T>::json_schema()
+    }
+}
+
+/// Declare a struct and implement `JsonSchema` for it from its fields
+///
+/// Takes a plain struct, with at most one lifetime parameter; see the
+/// module docs for the serde attributes it allows.
+#[macro_export]
+macro_rules! result_schema {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident $(<$lt:lifetime>)? {
+            $(
+                $(#[$field_attr:meta])*
+                $field_vis:vis $field:ident : $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name $(<$lt>)? {
+            $(
+                $(#[$field_attr])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $(<$lt>)? $crate::schema::JsonSchema for $name $(<$lt>)? {
+            fn json_schema() -> $crate::schema::Value {
+                $crate::schema::object_schema(vec![
+                    $((
+                        stringify!($field),
+                        <$ty as $crate::schema::JsonSchema>::json_schema(),
+                    ),)*
+                ])
+            }
+        }
+    };
+}
+
+/// Schema of an object with `fields` (Rust names, camel-cased here), each
+/// required unless it may be null, and nothing else
+pub fn object_schema(fields: Vec<(&str, Value)>) -> Value {
+    let required: Vec<String> = fields
+        .iter()
+        .filter(|(_, schema)| !nullable(schema))
+        .map(|(name, _)| camel_case(name))
+        .collect();
+    let properties: serde_json::Map<String, Value> = fields
+        .into_iter()
+        .map(|(name, schema)| (camel_case(name), schema))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+fn nullable(schema: &Value) -> bool {
+    schema["anyOf"]
+        .as_array()
+        .is_some_and(|options| options.iter().any(|o| o["type"] == "null"))
+}
+
+/// A field name as serde's `rename_all = "camelCase"` writes it
+fn camel_case(name: &str) -> String {
+    let mut camel = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+/// A result posted to a webhook endpoint, at a version of its data's shape
+#[derive(Debug, Clone, Copy)]
+pub struct ResultSchema {
+    /// The payload's `type`, the job type
+    pub job_type: &'static str,
+    pub endpoint: &'static str,
+    pub version: u32,
+    /// Schema of the payload's `data`
+    pub data: fn() -> Value,
+}
+
+impl ResultSchema {
+    /// File name the schema is published under
+    pub fn file_name(&self) -> String {
+        format!("{}.v{}.schema.json", self.endpoint, self.version)
+    }
+
+    /// JSON Schema of the whole completed payload
+    pub fn document(&self) -> Value {
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$id": format!("https://budi.audio/schemas/results/{}", self.file_name()),
+            "title": format!("{} result, version {}", self.job_type, self.version),
+            "type": "object",
+            "properties": {
+                "jobId": { "type": "string" },
+                "type": { "const": self.job_type },
+                "status": { "const": "completed" },
+                "schemaVersion": { "const": self.version },
+                "data": (self.data)(),
+            },
+            "required": ["jobId", "type", "status", "schemaVersion", "data"],
+            "additionalProperties": false,
+        })
+    }
+}
+
+/// Check the schemas published in `dir` match `schemas`, or with
+/// `UPDATE_SCHEMAS` set, write them
+pub fn check_published(schemas: &[ResultSchema], dir: &Path) -> Result<()> {
+    let update = std::env::var_os("UPDATE_SCHEMAS").is_some();
+    for schema in schemas {
+        let path = dir.join(schema.file_name());
+        let document = serde_json::to_string_pretty(&schema.document())? + "\n";
+        if update {
+            std::fs::create_dir_all(dir)?;
+            std::fs::write(&path, document)?;
+            continue;
+        }
+        let published = std::fs::read_to_string(&path)
+            .with_context(|| format!("{} isn't published", path.display()))?;
+        anyhow::ensure!(
+            published == document,
+            "{} is out of date with the Rust types: bump the version if the \
+             data changed shape, then run the tests with UPDATE_SCHEMAS=1",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    result_schema! {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Example<'a> {
+            /// Documented
+            track_id: &'a str,
+            true_peak_db: Option<f64>,
+            modules: Vec<String>,
+        }
+    }
+
+    #[test]
+    fn test_schema_follows_fields() {
+        let example = Example {
+            track_id: "t",
+            true_peak_db: None,
+            modules: Vec::new(),
+        };
+        let value = serde_json::to_value(&example).unwrap();
+        let schema = Example::json_schema();
+        let properties = schema["properties"].as_object().unwrap();
+        let names: Vec<&String> = value.as_object().unwrap().keys().collect();
+        assert_eq!(names, properties.keys().collect::<Vec<_>>());
+        assert_eq!(schema["required"], json!(["trackId", "modules"]));
+        assert_eq!(properties["modules"]["items"]["type"], "string");
+    }
+}
@@ -0,0 +1,183 @@
+//! Where jobs read their sources and write their outputs
+//!
+//! Outputs go to the backend `storage.backend` picks: the S3/MinIO bucket, an
+//! Azure Blob Storage container, or a directory tree on local disk laid out
+//! like the bucket's keys, for on-prem and development deployments without
+//! MinIO. Sources can be in the backend (`s3://` and endpoint URLs for S3,
+//! the account's blob URLs for Azure, `file://` URLs under the root for local
+//! storage) or at any other HTTP(S) URL.
+//!
+//! Downloads and uploads report the SHA-256 of what they moved; see
+//! `checksum`. Downloads go through the local source cache, when one is
+//! configured.
+
+use anyhow::Result;
+use std::path::Path;
+
+use crate::azure::AzureBlobClient;
+use crate::cache;
+use crate::checksum;
+use crate::config::{Config, StorageBackend};
+use crate::http;
+use crate::local::LocalStore;
+use crate::s3::S3Client;
+
+/// An uploaded artifact
+#[derive(Debug, Clone)]
+pub struct Uploaded {
+    /// Where the artifact can be fetched from
+    pub url: String,
+    /// Hex SHA-256 of its contents
+    pub sha256: String,
+}
+
+/// Storage for job sources and outputs
+pub struct Storage {
+    backend: Backend,
+    /// For sources outside the backend
+    http: reqwest::Client,
+}
+
+enum Backend {
+    S3(S3Client),
+    Local(LocalStore),
+    Azure(AzureBlobClient),
+}
+
+impl Storage {
+    /// Open the configured backend
+    pub async fn new(config: &Config) -> Result<Self> {
+        let backend = match config.storage.backend {
+            StorageBackend::S3 => Backend::S3(S3Client::new(&config.s3).await?),
+            StorageBackend::Local => {
+                let root = config.storage.root.as_deref().unwrap_or_default();
+                Backend::Local(LocalStore::new(root)?)
+            }
+            StorageBackend::Azure => Backend::Azure(AzureBlobClient::new(&config.azure)?),
+        };
+        Ok(Self {
+            backend,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    /// Download a source URL to a local path, returning its hex SHA-256
+    ///
+    /// URLs in the backend are read through it; any other `http(s)://` URL,
+    /// such as a customer-hosted file or a CDN link, is fetched over plain
+    /// HTTP. With `expected_sha256`, a download that doesn't match fails,
+    /// and a cached copy of the source is used instead when there is one.
+    pub async fn download_file(
+        &self,
+        url: &str,
+        local_path: &Path,
+        expected_sha256: Option<&str>,
+    ) -> Result<String> {
+        if let Some(expected) = expected_sha256 {
+            if let Some(sha256) = fetch_cached(url, local_path, expected).await? {
+                return Ok(sha256);
+            }
+        }
+
+        self.fetch(url, local_path).await?;
+        let sha256 = checksum::sha256_file(local_path).await?;
+        checksum::verify(url, &sha256, expected_sha256)?;
+
+        let (cached, path) = (sha256.clone(), local_path.to_path_buf());
+        tokio::task::spawn_blocking(move || cache::store_source(&cached, &path)).await?;
+        Ok(sha256)
+    }
+
+    async fn fetch(&self, url: &str, local_path: &Path) -> Result<()> {
+        match &self.backend {
+            Backend::S3(s3) if s3.is_s3_url(url) => s3.download_file(url, local_path).await,
+            Backend::Local(local) if url.starts_with("file://") => {
+                local.download_file(url, local_path).await
+            }
+            Backend::Azure(azure) if azure.is_blob_url(url) => {
+                azure.download_file(url, local_path).await
+            }
+            _ if url.starts_with("http://") || url.starts_with("https://") => {
+                http::download(&self.http, url, local_path).await
+            }
+            _ => anyhow::bail!(
+                "Can't read {} with the {} storage backend",
+                url,
+                self.backend_name()
+            ),
+        }
+    }
+
+    /// Upload a file from a local path to `key`
+    pub async fn upload_file(
+        &self,
+        local_path: &Path,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Uploaded> {
+        let sha256 = checksum::sha256_file(local_path).await?;
+        let url = match &self.backend {
+            Backend::S3(s3) => s3.upload_file(local_path, key, content_type).await,
+            Backend::Local(local) => local.upload_file(local_path, key).await,
+            Backend::Azure(azure) => azure.upload_file(local_path, key, content_type).await,
+        }?;
+        Ok(Uploaded { url, sha256 })
+    }
+
+    /// Upload bytes to `key`
+    pub async fn upload_bytes(
+        &self,
+        data: &[u8],
+        key: &str,
+        content_type: &str,
+    ) -> Result<Uploaded> {
+        let sha256 = checksum::sha256_bytes(data);
+        let url = match &self.backend {
+            Backend::S3(s3) => s3.upload_bytes(data, key, content_type).await,
+            Backend::Local(local) => local.upload_bytes(data, key).await,
+            Backend::Azure(azure) => azure.upload_bytes(data, key, content_type).await,
+        }?;
+        Ok(Uploaded { url, sha256 })
+    }
+
+    /// A URL to fetch `key` by, which can be passed back as a job's source
+    pub async fn object_url(&self, key: &str) -> Result<String> {
+        match &self.backend {
+            Backend::S3(s3) => s3.object_url(key).await,
+            Backend::Local(local) => local.object_url(key),
+            Backend::Azure(azure) => azure.object_url(key),
+        }
+    }
+
+    /// Generate a unique key for a file
+    pub fn generate_key(prefix: &str, track_id: &str, suffix: &str) -> String {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        format!("{}/{}/{}-{}", prefix, track_id, timestamp, suffix)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        match self.backend {
+            Backend::S3(_) => "s3",
+            Backend::Local(_) => "local",
+            Backend::Azure(_) => "azure",
+        }
+    }
+}
+
+/// Copy the source from the cache, if it is there and still matches
+async fn fetch_cached(url: &str, local_path: &Path, expected: &str) -> Result<Option<String>> {
+    let (key, path) = (expected.to_string(), local_path.to_path_buf());
+    if !tokio::task::spawn_blocking(move || cache::fetch_source(&key, &path)).await? {
+        return Ok(None);
+    }
+    let sha256 = checksum::sha256_file(local_path).await?;
+    if checksum::verify(url, &sha256, Some(expected)).is_err() {
+        cache::remove_source(expected);
+        return Ok(None);
+    }
+    tracing::info!("Using cached copy of {}", url);
+    Ok(Some(sha256))
+}
@@ -0,0 +1,434 @@
+//! Redis Streams job source
+//!
+//! With `queue.mode = "stream"` (`QUEUE_MODE=stream`) each queue is a stream
+//! the API adds jobs to with XADD, one entry per job with the payload in its
+//! `payload` field, and every task of every worker reads them through the
+//! `queue.stream_group` consumer group. An entry read with XREADGROUP stays
+//! in the group's pending entries list until the task that read it
+//! acknowledges it, so delivery is at least once:
+//!
+//! - While a job runs, its task re-claims the entry every third of
+//!   `JOB_VISIBILITY_TIMEOUT_SECONDS`, keeping it fresh
+//! - An entry left idle for longer, because its worker died, is claimed by
+//!   another task with XAUTOCLAIM and run again
+//! - A task that lost its connection mid-job runs its own pending entries
+//!   first when it reconnects
+//!
+//! Entries are deleted once acknowledged, so a stream only holds unfinished
+//! jobs. Needs Redis 6.2 or later.
+
+use anyhow::Result;
+use redis::Script;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::inflight::Keepalive;
+
+type Connection = redis::aio::MultiplexedConnection;
+
+/// Entry field holding the job payload
+const PAYLOAD_FIELD: &str = "payload";
+
+/// Re-claim an entry if `consumer` still holds it, resetting its idle time
+///
+/// KEYS: stream
+/// ARGV: group, consumer, entry ID
+const RENEW: &str = r#"
+local pending = redis.call("XPENDING", KEYS[1], ARGV[1], ARGV[3], ARGV[3], 1)
+if #pending == 1 and pending[1][2] == ARGV[2] then
+  redis.call("XCLAIM", KEYS[1], ARGV[1], ARGV[2], 0, ARGV[3], "JUSTID")
+  return 1
+end
+return 0
+"#;
+
+/// A job read from a stream
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) queue: String,
+    pub(crate) id: String,
+    pub(crate) payload: String,
+    /// Times the group has handed the entry out, including this one
+    pub(crate) deliveries: u32,
+}
+
+/// Create the consumer group on each stream, and the stream if need be
+///
+/// The group starts from the beginning of the stream, so jobs added before
+/// any worker ran aren't skipped.
+pub(crate) async fn create_groups(
+    conn: &mut Connection,
+    queues: &[&str],
+    group: &str,
+) -> Result<()> {
+    for queue in queues {
+        let created: redis::RedisResult<()> = redis::cmd("XGROUP")
+            .arg("CREATE")
+            .arg(queue)
+            .arg(group)
+            .arg("0")
+            .arg("MKSTREAM")
+            .query_async(conn)
+            .await;
+        match created {
+            Err(e) if e.code() == Some("BUSYGROUP") => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// Read the next new entry from the first stream in `keys` that has one
+///
+/// As with `inflight::pop`, the other streams are checked without blocking
+/// first and only the first stream is waited on.
+pub(crate) async fn pop(
+    conn: &mut Connection,
+    keys: &[&str],
+    group: &str,
+    consumer: &str,
+    timeout_secs: f64,
+) -> Result<Option<Entry>> {
+    for queue in keys {
+        if let Some(entry) = read(conn, queue, group, consumer, ">", None).await? {
+            return Ok(Some(entry));
+        }
+    }
+    let Some(first) = keys.first() else {
+        return Ok(None);
+    };
+    let block = Duration::from_secs_f64(timeout_secs);
+    read(conn, first, group, consumer, ">", Some(block)).await
+}
+
+/// The next entry `consumer` read and never acknowledged, from a connection
+/// it lost partway through a job
+pub(crate) async fn pop_own_pending(
+    conn: &mut Connection,
+    keys: &[&str],
+    group: &str,
+    consumer: &str,
+) -> Result<Option<Entry>> {
+    for queue in keys {
+        if let Some(entry) = read(conn, queue, group, consumer, "0", None).await? {
+            return Ok(Some(entry));
+        }
+    }
+    Ok(None)
+}
+
+async fn read(
+    conn: &mut Connection,
+    queue: &str,
+    group: &str,
+    consumer: &str,
+    from: &str,
+    block: Option<Duration>,
+) -> Result<Option<Entry>> {
+    loop {
+        let mut cmd = redis::cmd("XREADGROUP");
+        cmd.arg("GROUP")
+            .arg(group)
+            .arg(consumer)
+            .arg("COUNT")
+            .arg(1);
+        if let Some(block) = block {
+            cmd.arg("BLOCK").arg(block.as_millis() as u64);
+        }
+        cmd.arg("STREAMS").arg(queue).arg(from);
+        let reply: redis::Value = cmd.query_async(conn).await?;
+        let Some((id, fields)) = first_read(&reply)? else {
+            return Ok(None);
+        };
+        match fields.and_then(|mut fields| fields.remove(PAYLOAD_FIELD)) {
+            Some(payload) => {
+                let deliveries = if from == ">" {
+                    1
+                } else {
+                    deliveries(conn, queue, group, &id).await?
+                };
+                return Ok(Some(Entry {
+                    queue: queue.to_string(),
+                    id,
+                    payload,
+                    deliveries,
+                }));
+            }
+            None => {
+                // Deleted (or added without a payload) while pending; nothing to run
+                warn!(
+                    "Dropping stream entry {} on {} without a payload",
+                    id, queue
+                );
+                ack(conn, queue, group, &id).await?;
+            }
+        }
+    }
+}
+
+/// Claim one entry that has been idle for `min_idle` from the first stream in
+/// `keys` that has one, for a task whose worker stopped holding it
+pub(crate) async fn claim_stale(
+    conn: &mut Connection,
+    keys: &[&str],
+    group: &str,
+    consumer: &str,
+    min_idle: Duration,
+) -> Result<Option<Entry>> {
+    for queue in keys {
+        let reply: redis::Value = redis::cmd("XAUTOCLAIM")
+            .arg(queue)
+            .arg(group)
+            .arg(consumer)
+            .arg(min_idle.as_millis() as u64)
+            .arg("0-0")
+            .arg("COUNT")
+            .arg(1)
+            .query_async(conn)
+            .await?;
+        let Some((id, mut fields)) = first_claimed(&reply)? else {
+            continue;
+        };
+        let Some(payload) = fields.remove(PAYLOAD_FIELD) else {
+            warn!(
+                "Dropping stream entry {} on {} without a payload",
+                id, queue
+            );
+            ack(conn, queue, group, &id).await?;
+            continue;
+        };
+        let deliveries = deliveries(conn, queue, group, &id).await?;
+        return Ok(Some(Entry {
+            queue: queue.to_string(),
+            id,
+            payload,
+            deliveries,
+        }));
+    }
+    Ok(None)
+}
+
+/// An entry's ID and fields, without fields if it was deleted while pending
+type RawEntry = (String, Option<HashMap<String, String>>);
+
+/// The first entry in an XREADGROUP reply, or `None` if it timed out or
+/// found nothing
+fn first_read(reply: &redis::Value) -> Result<Option<RawEntry>> {
+    // A list of tuples only decodes from a flat reply, so each level is
+    // decoded in turn
+    let streams: Option<Vec<redis::Value>> = redis::from_redis_value(reply)?;
+    let Some(stream) = streams.into_iter().flatten().next() else {
+        return Ok(None);
+    };
+    let (_, entries): (String, Vec<redis::Value>) = redis::from_redis_value(&stream)?;
+    match entries.first() {
+        Some(entry) => Ok(Some(redis::from_redis_value(entry)?)),
+        None => Ok(None),
+    }
+}
+
+/// The first entry in an XAUTOCLAIM reply that still exists
+///
+/// Redis 6.2 lists entries deleted while pending as nil, which are skipped;
+/// 7.0 drops them from the reply and the pending list itself.
+fn first_claimed(reply: &redis::Value) -> Result<Option<(String, HashMap<String, String>)>> {
+    let reply: Vec<redis::Value> = redis::from_redis_value(reply)?;
+    let Some(entries) = reply.get(1) else {
+        anyhow::bail!("XAUTOCLAIM reply has no entries");
+    };
+    let entries: Vec<Option<(String, HashMap<String, String>)>> = redis::from_redis_value(entries)?;
+    Ok(entries.into_iter().flatten().next())
+}
+
+/// Times the group has handed out entry `id`
+async fn deliveries(conn: &mut Connection, queue: &str, group: &str, id: &str) -> Result<u32> {
+    let pending: redis::Value = redis::cmd("XPENDING")
+        .arg(queue)
+        .arg(group)
+        .arg(id)
+        .arg(id)
+        .arg(1)
+        .query_async(conn)
+        .await?;
+    delivery_count(&pending)
+}
+
+/// The delivery count in an XPENDING reply for a single entry, 1 if it is
+/// no longer pending
+fn delivery_count(reply: &redis::Value) -> Result<u32> {
+    let pending: Vec<redis::Value> = redis::from_redis_value(reply)?;
+    let Some(entry) = pending.first() else {
+        return Ok(1);
+    };
+    let (_, _, _, deliveries): (String, String, u64, u32) = redis::from_redis_value(entry)?;
+    Ok(deliveries)
+}
+
+/// Acknowledge an entry that has been dealt with and delete it
+pub(crate) async fn ack(conn: &mut Connection, queue: &str, group: &str, id: &str) -> Result<()> {
+    redis::pipe()
+        .atomic()
+        .cmd("XACK")
+        .arg(queue)
+        .arg(group)
+        .arg(id)
+        .ignore()
+        .cmd("XDEL")
+        .arg(queue)
+        .arg(id)
+        .ignore()
+        .query_async::<_, ()>(conn)
+        .await?;
+    Ok(())
+}
+
+/// Add a job to a stream
+pub(crate) async fn push(conn: &mut Connection, queue: &str, payload: &str) -> Result<()> {
+    redis::cmd("XADD")
+        .arg(queue)
+        .arg("*")
+        .arg(PAYLOAD_FIELD)
+        .arg(payload)
+        .query_async::<_, ()>(conn)
+        .await?;
+    Ok(())
+}
+
+/// Hand an entry back for another task to run now, rather than once it has
+/// been idle for the visibility timeout
+///
+/// Streams can't be added to at the front, so it goes to the back.
+pub(crate) async fn release(
+    conn: &mut Connection,
+    queue: &str,
+    group: &str,
+    id: &str,
+    payload: &str,
+) -> Result<()> {
+    redis::pipe()
+        .atomic()
+        .cmd("XADD")
+        .arg(queue)
+        .arg("*")
+        .arg(PAYLOAD_FIELD)
+        .arg(payload)
+        .ignore()
+        .cmd("XACK")
+        .arg(queue)
+        .arg(group)
+        .arg(id)
+        .ignore()
+        .cmd("XDEL")
+        .arg(queue)
+        .arg(id)
+        .ignore()
+        .query_async::<_, ()>(conn)
+        .await?;
+    Ok(())
+}
+
+/// Keep an entry from going idle while its job runs
+pub(crate) fn keep_fresh(
+    client: &redis::Client,
+    queue: &str,
+    group: &str,
+    consumer: &str,
+    id: &str,
+    visibility: Duration,
+) -> Keepalive {
+    let script = Script::new(RENEW);
+    let (queue, group) = (queue.to_string(), group.to_string());
+    let (consumer, id) = (consumer.to_string(), id.to_string());
+    let what = format!("stream entry {} on {}", id, queue);
+    Keepalive::start(client, what, visibility / 3, move |conn| {
+        let held: i32 = script
+            .key(&queue)
+            .arg(&group)
+            .arg(&consumer)
+            .arg(&id)
+            .invoke(conn)?;
+        Ok(held == 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Value;
+
+    fn data(s: &str) -> Value {
+        Value::Data(s.as_bytes().to_vec())
+    }
+
+    fn entry(id: &str, payload: &str) -> Value {
+        Value::Bulk(vec![
+            data(id),
+            Value::Bulk(vec![data(PAYLOAD_FIELD), data(payload)]),
+        ])
+    }
+
+    #[test]
+    fn test_read_reply() {
+        let reply = Value::Bulk(vec![Value::Bulk(vec![
+            data("jobs"),
+            Value::Bulk(vec![entry("1-0", "{}"), entry("2-0", "[]")]),
+        ])]);
+        let (id, fields) = first_read(&reply).unwrap().unwrap();
+        assert_eq!(id, "1-0");
+        assert_eq!(fields.unwrap()[PAYLOAD_FIELD], "{}");
+
+        // BLOCK timed out
+        assert!(first_read(&Value::Nil).unwrap().is_none());
+
+        // Own pending entries, all handled
+        let empty = Value::Bulk(vec![Value::Bulk(vec![data("jobs"), Value::Bulk(vec![])])]);
+        assert!(first_read(&empty).unwrap().is_none());
+
+        // Deleted while pending
+        let deleted = Value::Bulk(vec![Value::Bulk(vec![
+            data("jobs"),
+            Value::Bulk(vec![Value::Bulk(vec![data("1-0"), Value::Nil])]),
+        ])]);
+        assert_eq!(
+            first_read(&deleted).unwrap(),
+            Some(("1-0".to_string(), None))
+        );
+
+        assert!(first_read(&Value::Int(1)).is_err());
+    }
+
+    #[test]
+    fn test_autoclaim_reply() {
+        // Redis 7.0 adds a list of deleted IDs, which is ignored
+        let reply = Value::Bulk(vec![
+            data("0-0"),
+            Value::Bulk(vec![Value::Nil, entry("3-0", "{}")]),
+            Value::Bulk(vec![data("2-0")]),
+        ]);
+        let (id, fields) = first_claimed(&reply).unwrap().unwrap();
+        assert_eq!(id, "3-0");
+        assert_eq!(fields[PAYLOAD_FIELD], "{}");
+
+        let none_idle = Value::Bulk(vec![data("0-0"), Value::Bulk(vec![])]);
+        assert!(first_claimed(&none_idle).unwrap().is_none());
+
+        let only_deleted = Value::Bulk(vec![data("0-0"), Value::Bulk(vec![Value::Nil])]);
+        assert!(first_claimed(&only_deleted).unwrap().is_none());
+
+        assert!(first_claimed(&Value::Bulk(vec![data("0-0")])).is_err());
+    }
+
+    #[test]
+    fn test_delivery_count() {
+        let reply = Value::Bulk(vec![Value::Bulk(vec![
+            data("1-0"),
+            data("worker-1/0"),
+            Value::Int(120_000),
+            Value::Int(3),
+        ])]);
+        assert_eq!(delivery_count(&reply).unwrap(), 3);
+        assert_eq!(delivery_count(&Value::Bulk(vec![])).unwrap(), 1);
+        assert!(delivery_count(&Value::Okay).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Logging, and trace export over OTLP
+//!
+//! Spans are exported when `OTEL_EXPORTER_OTLP_ENDPOINT` (or the traces-only
+//! `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) is set, using the standard OTel
+//! environment variables for everything else. Jobs join the trace of the
+//! request that enqueued them through the W3C `traceparent` payload field.
+
+use anyhow::Result;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Initialize logging at info for `crate_name` and the shared worker code
+/// and warn for dependencies, exporting spans if OTLP is configured
+pub fn init_tracing(crate_name: &str) -> Result<()> {
+    let filter = tracing_subscriber::EnvFilter::from_default_env()
+        .add_directive(format!("{}=info", crate_name).parse()?)
+        .add_directive("budi_worker_core=info".parse()?)
+        .add_directive("warn".parse()?);
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer());
+
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|var| std::env::var(var).is_ok_and(|v| !v.is_empty()));
+    if !configured {
+        registry.init();
+        return Ok(());
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+    let service_name =
+        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| crate_name.replace('_', "-"));
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build();
+    let tracer = provider.tracer(crate_name.to_string());
+    let _ = PROVIDER.set(provider);
+
+    registry
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .init();
+    Ok(())
+}
+
+/// Flush and stop span export, so the last jobs' spans aren't lost on exit
+pub fn shutdown_tracing() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to flush traces: {:?}", e);
+        }
+    }
+}
+
+/// Make `span` a child of the W3C `traceparent` a job was enqueued with
+pub(crate) fn set_parent(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+    let context = TraceContextPropagator::new().extract(&carrier);
+    // Fails only when OTel export is off, where there's nothing to link
+    let _ = span.set_parent(context);
+}
@@ -0,0 +1,210 @@
+//! Job scratch directories and the sweep for ones left behind
+//!
+//! Every job works in its own directory under the worker's temp root, which
+//! is removed when the job finishes, fails or is cancelled. A worker that is
+//! killed outright never gets to, so the root is swept at startup and then
+//! periodically: any job directory that hasn't changed for
+//! `max_age_seconds` is removed. Several workers may share a root; the age
+//! threshold is what keeps their live jobs safe.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+use tracing::{info, info_span, warn};
+
+use crate::config::TempConfig;
+
+/// Name of the default temp root inside the system temp directory
+const DEFAULT_ROOT: &str = "budi-worker";
+
+/// Prefix of job directories; nothing else in the root is swept
+const JOB_DIR_PREFIX: &str = "job-";
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use the root from `config` for the rest of the process
+pub fn init(config: &TempConfig) {
+    let _ = ROOT.set(root_path(config));
+}
+
+/// The temp root, or the default if `init` wasn't called
+pub fn root() -> &'static Path {
+    ROOT.get_or_init(|| root_path(&TempConfig::default()))
+}
+
+fn root_path(config: &TempConfig) -> PathBuf {
+    config
+        .root
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join(DEFAULT_ROOT))
+}
+
+/// Create a scratch directory for one job, removed when dropped
+pub fn job_dir() -> Result<TempDir> {
+    let root = root();
+    std::fs::create_dir_all(root)
+        .with_context(|| format!("Failed to create temp root {}", root.display()))?;
+    tempfile::Builder::new()
+        .prefix(JOB_DIR_PREFIX)
+        .tempdir_in(root)
+        .with_context(|| format!("Failed to create a job directory in {}", root.display()))
+}
+
+/// What a sweep removed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SweepStats {
+    pub dirs_removed: usize,
+    pub bytes_reclaimed: u64,
+}
+
+/// Remove job directories under `root` unchanged for longer than `max_age`
+pub fn sweep(root: &Path, max_age: Duration) -> Result<SweepStats> {
+    let mut stats = SweepStats::default();
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stats),
+        Err(e) => return Err(e).context(format!("Failed to read {}", root.display())),
+    };
+
+    let now = SystemTime::now();
+    for entry in entries {
+        let entry = entry?;
+        let is_job_dir = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(JOB_DIR_PREFIX)
+            && entry.file_type()?.is_dir();
+        if !is_job_dir {
+            continue;
+        }
+
+        let path = entry.path();
+        // A directory's own mtime doesn't change while a file in it grows,
+        // so the newest change anywhere inside decides its age
+        let usage = match disk_usage(&path) {
+            Ok(usage) => usage,
+            Err(e) => {
+                warn!("Skipping {} in temp sweep: {:?}", path.display(), e);
+                continue;
+            }
+        };
+        let age = now.duration_since(usage.modified).unwrap_or_default();
+        if age <= max_age {
+            continue;
+        }
+
+        match std::fs::remove_dir_all(&path) {
+            Ok(()) => {
+                stats.dirs_removed += 1;
+                stats.bytes_reclaimed += usage.bytes;
+            }
+            Err(e) => warn!("Failed to remove orphaned {}: {:?}", path.display(), e),
+        }
+    }
+    Ok(stats)
+}
+
+struct Usage {
+    bytes: u64,
+    modified: SystemTime,
+}
+
+/// Total size of the files under `path`, and when anything there last changed
+fn disk_usage(path: &Path) -> Result<Usage> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut usage = Usage {
+        bytes: 0,
+        modified: metadata.modified()?,
+    };
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            let child = disk_usage(&entry?.path())?;
+            usage.bytes += child.bytes;
+            usage.modified = usage.modified.max(child.modified);
+        }
+    } else {
+        usage.bytes = metadata.len();
+    }
+    Ok(usage)
+}
+
+/// Sweep the temp root now, then every `sweep_interval_seconds`
+pub(crate) async fn run_sweeper(config: TempConfig) {
+    let max_age = Duration::from_secs(config.max_age_seconds);
+    let interval = Duration::from_secs(config.sweep_interval_seconds);
+    loop {
+        sweep_root(max_age).await;
+        if interval.is_zero() {
+            return;
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn sweep_root(max_age: Duration) {
+    let root = root();
+    let span = info_span!(
+        "temp.sweep",
+        temp.root = %root.display(),
+        temp.dirs_removed = tracing::field::Empty,
+        temp.bytes_reclaimed = tracing::field::Empty
+    );
+    let swept = tokio::task::spawn_blocking(move || sweep(root, max_age)).await;
+    match swept {
+        Ok(Ok(stats)) => {
+            span.record("temp.dirs_removed", stats.dirs_removed);
+            span.record("temp.bytes_reclaimed", stats.bytes_reclaimed);
+            if stats.dirs_removed > 0 {
+                let _entered = span.enter();
+                info!(
+                    dirs_removed = stats.dirs_removed,
+                    bytes_reclaimed = stats.bytes_reclaimed,
+                    "Removed {} orphaned job directories from {}, reclaiming {} MB",
+                    stats.dirs_removed,
+                    root.display(),
+                    stats.bytes_reclaimed / (1024 * 1024)
+                );
+            }
+        }
+        Ok(Err(e)) => warn!("Temp sweep of {} failed: {:?}", root.display(), e),
+        Err(e) => warn!("Temp sweep of {} panicked: {:?}", root.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_removes_stale_job_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        let job = root.path().join("job-abc");
+        std::fs::create_dir_all(job.join("track_0")).unwrap();
+        std::fs::write(job.join("track_0/input.wav"), vec![0u8; 1000]).unwrap();
+        std::fs::write(job.join("fixed.wav"), vec![0u8; 500]).unwrap();
+        // Anything that isn't a job directory is left alone
+        std::fs::create_dir(root.path().join("other")).unwrap();
+
+        let fresh = sweep(root.path(), Duration::from_secs(3600)).unwrap();
+        assert_eq!(fresh, SweepStats::default());
+        assert!(job.exists());
+
+        std::thread::sleep(Duration::from_millis(20));
+        let stale = sweep(root.path(), Duration::from_millis(1)).unwrap();
+        assert_eq!(
+            stale,
+            SweepStats {
+                dirs_removed: 1,
+                bytes_reclaimed: 1500
+            }
+        );
+        assert!(!job.exists());
+        assert!(root.path().join("other").exists());
+
+        let missing = sweep(&root.path().join("missing"), Duration::ZERO).unwrap();
+        assert_eq!(missing, SweepStats::default());
+    }
+}
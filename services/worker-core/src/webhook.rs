@@ -0,0 +1,414 @@
+//! Webhook client for API callbacks
+//!
+//! Progress updates are also published to the Redis channel
+//! `progress:{jobId}`, so the API can push them to browsers as they happen.
+//! Publishing is best effort: the webhook stays the durable record, and a
+//! listener that misses a message gets the next one.
+//!
+//! Results with a published schema (see `schema`) are posted with
+//! `report_result`, which stamps them with their `schemaVersion`.
+//!
+//! Result webhooks are also written to `results/{jobId}.json` in storage
+//! before they are posted, so a result survives a webhook that never arrives
+//! and finished jobs can be audited later.
+
+use anyhow::Result;
+use redis::aio::ConnectionManager;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use crate::config::Config;
+use crate::limits::Refused;
+use crate::schema::ResultSchema;
+use crate::storage::Storage;
+use crate::worker::Rejection;
+
+tokio::task_local! {
+    /// Result webhooks posted by the job running on this task, if recording
+    static RECORDED: RefCell<Vec<RecordedPost>>;
+}
+
+/// A webhook a job posted, kept so it can be sent again for a duplicate job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RecordedPost {
+    pub(crate) endpoint: String,
+    pub(crate) payload: serde_json::Value,
+}
+
+/// Run `job`, collecting every webhook it posts other than progress updates
+pub(crate) async fn record<F: Future>(job: F) -> (F::Output, Vec<RecordedPost>) {
+    RECORDED
+        .scope(RefCell::new(Vec::new()), async {
+            let output = job.await;
+            (output, RECORDED.with(|posts| posts.take()))
+        })
+        .await
+}
+
+/// Add a posted webhook to the running job's recording, if there is one
+fn record_post<T: Serialize + ?Sized>(endpoint: &str, payload: &T) {
+    // A payload that won't serialize fails when it is posted anyway
+    let _ = RECORDED.try_with(|posts| {
+        if let Ok(payload) = serde_json::to_value(payload) {
+            posts.borrow_mut().push(RecordedPost {
+                endpoint: endpoint.to_string(),
+                payload,
+            });
+        }
+    });
+}
+
+/// Whether the API accepted a result webhook
+///
+/// A result the API turned away is lost, unlike a missed progress update, so
+/// a server error fails the job to be retried. The work is done by then,
+/// though: an endpoint or job the API doesn't know (404) is only logged,
+/// since the result is still in storage when `webhook.store_results` is on,
+/// and any other client error is refused, since posting it again won't help.
+fn check_result_response(endpoint: &str, job_id: &str, status: StatusCode) -> Result<()> {
+    if status == StatusCode::NOT_FOUND {
+        warn!(
+            "API has no {} webhook for job {}; result not delivered",
+            endpoint, job_id
+        );
+    } else if status.is_client_error() {
+        return Err(Refused(format!("API rejected the {} webhook: {}", endpoint, status)).into());
+    } else if !status.is_success() {
+        anyhow::bail!("API failed the {} webhook: {}", endpoint, status);
+    }
+    Ok(())
+}
+
+/// Webhook client for reporting job progress and results
+pub struct WebhookClient {
+    client: Client,
+    api_url: String,
+    secret: String,
+    /// Where progress updates are published, unless turned off
+    progress: Option<ProgressChannel>,
+    /// Where result payloads are stored, unless turned off
+    results: Option<&'static Storage>,
+}
+
+/// Longest a progress update may spend being published
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Redis connection for progress updates, opened on first use
+struct ProgressChannel {
+    client: redis::Client,
+    conn: OnceCell<ConnectionManager>,
+}
+
+impl ProgressChannel {
+    /// Publish a progress payload, giving up after `PUBLISH_TIMEOUT` so an
+    /// unreachable Redis can't hold up the job
+    async fn publish<T: Serialize + ?Sized>(&self, job_id: &str, payload: &T) -> Result<()> {
+        let message = serde_json::to_string(payload)?;
+        let publish = async {
+            let conn = self
+                .conn
+                .get_or_try_init(|| self.client.get_connection_manager())
+                .await?;
+            redis::cmd("PUBLISH")
+                .arg(progress_channel(job_id))
+                .arg(message)
+                .query_async::<_, ()>(&mut conn.clone())
+                .await
+        };
+        tokio::time::timeout(PUBLISH_TIMEOUT, publish).await??;
+        Ok(())
+    }
+}
+
+/// Redis channel a job's progress updates are published to
+fn progress_channel(job_id: &str) -> String {
+    format!("progress:{}", job_id)
+}
+
+/// Bucket key a job's result payload is stored under
+fn result_key(job_id: &str) -> String {
+    format!("results/{}.json", job_id)
+}
+
+/// A result payload as stored in the bucket
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredResult<'a, T: ?Sized> {
+    job_id: &'a str,
+    /// The webhook endpoint the payload was posted to
+    endpoint: &'a str,
+    payload: &'a T,
+}
+
+/// Write a result payload to `result_key(job_id)`
+async fn store_result<T: Serialize + ?Sized>(
+    storage: &Storage,
+    job_id: &str,
+    endpoint: &str,
+    payload: &T,
+) -> Result<()> {
+    let body = serde_json::to_vec(&StoredResult {
+        job_id,
+        endpoint,
+        payload,
+    })?;
+    storage
+        .upload_bytes(&body, &result_key(job_id), "application/json")
+        .await?;
+    Ok(())
+}
+
+impl WebhookClient {
+    /// Create a new webhook client, storing results in `storage`
+    pub fn new(config: &Config, storage: &'static Storage) -> Result<Self> {
+        let progress = if config.webhook.publish_progress && config.queue.mode.uses_redis() {
+            Some(ProgressChannel {
+                client: redis::Client::open(config.redis.url.as_str())?,
+                conn: OnceCell::new(),
+            })
+        } else {
+            None
+        };
+        Ok(Self {
+            client: Client::new(),
+            api_url: config.webhook.api_url.clone(),
+            secret: config.webhook.secret.clone(),
+            progress,
+            results: config.webhook.store_results.then_some(storage),
+        })
+    }
+
+    /// Post a payload to `/webhooks/jobs/{job_id}/{endpoint}`
+    #[tracing::instrument(name = "webhook.post", skip(self, payload))]
+    pub async fn post<T: Serialize + ?Sized>(
+        &self,
+        job_id: &str,
+        endpoint: &str,
+        payload: &T,
+    ) -> Result<()> {
+        if endpoint != "progress" {
+            record_post(endpoint, payload);
+            if let Some(storage) = self.results {
+                // The webhook below is still the primary channel
+                if let Err(e) = store_result(storage, job_id, endpoint, payload).await {
+                    warn!("Failed to store result of job {}: {:#}", job_id, e);
+                }
+            }
+        } else if let Some(channel) = &self.progress {
+            if let Err(e) = channel.publish(job_id, payload).await {
+                warn!("Failed to publish progress for job {}: {:#}", job_id, e);
+            }
+        }
+
+        let url = format!("{}/webhooks/jobs/{}/{}", self.api_url, job_id, endpoint);
+
+        let response = self
+            .client
+            .post(&url)
+            .header("X-Webhook-Secret", &self.secret)
+            .json(payload)
+            .send()
+            .await?;
+        if endpoint != "progress" {
+            check_result_response(endpoint, job_id, response.status())?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a finished job's result webhooks again
+    pub(crate) async fn replay(&self, job_id: &str, posts: &[RecordedPost]) -> Result<()> {
+        for post in posts {
+            self.post(job_id, &post.endpoint, &post.payload).await?;
+        }
+        Ok(())
+    }
+
+    /// Report job progress
+    pub async fn report_progress(&self, job_id: &str, progress: u8, message: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct ProgressPayload<'a> {
+            progress: u8,
+            message: &'a str,
+        }
+
+        self.post(job_id, "progress", &ProgressPayload { progress, message })
+            .await
+    }
+
+    /// Report a completed job's results to its job type's endpoint
+    pub async fn report_completed<T: Serialize + ?Sized>(
+        &self,
+        job_id: &str,
+        job_type: &str,
+        data: &T,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CompletedPayload<'a, T: ?Sized> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: &'a T,
+        }
+
+        self.post(
+            job_id,
+            job_type,
+            &CompletedPayload {
+                job_id,
+                job_type,
+                status: "completed",
+                data,
+            },
+        )
+        .await
+    }
+
+    /// Report a completed job's results to the endpoint of their schema,
+    /// at its version
+    pub async fn report_result<T: Serialize + ?Sized>(
+        &self,
+        job_id: &str,
+        schema: &ResultSchema,
+        data: &T,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ResultPayload<'a, T: ?Sized> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            schema_version: u32,
+            data: &'a T,
+        }
+
+        self.post(
+            job_id,
+            schema.endpoint,
+            &ResultPayload {
+                job_id,
+                job_type: schema.job_type,
+                status: "completed",
+                schema_version: schema.version,
+                data,
+            },
+        )
+        .await
+    }
+
+    /// Report a job refused before it ran, with a machine-readable
+    /// `errorCode` and the schema versions involved
+    pub(crate) async fn report_rejected(
+        &self,
+        job_id: &str,
+        job_type: &str,
+        rejection: &Rejection,
+        supported_schema_version: u32,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RejectedPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            error: &'a str,
+            error_code: &'a str,
+            schema_version: Option<u64>,
+            supported_schema_version: u32,
+        }
+
+        self.post(
+            job_id,
+            job_type,
+            &RejectedPayload {
+                job_id,
+                job_type,
+                status: "failed",
+                error: &rejection.error,
+                error_code: rejection.code,
+                schema_version: rejection.schema_version,
+                supported_schema_version,
+            },
+        )
+        .await
+    }
+
+    /// Report job failure
+    pub async fn report_failure(&self, job_id: &str, job_type: &str, error: &str) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct FailurePayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            error: &'a str,
+        }
+
+        self.post(
+            job_id,
+            job_type,
+            &FailurePayload {
+                job_id,
+                job_type,
+                status: "failed",
+                error,
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits;
+
+    #[test]
+    fn test_result_responses() {
+        assert!(check_result_response("analysis", "j1", StatusCode::OK).is_ok());
+        // Not routed by this API version; the result isn't lost
+        assert!(check_result_response("waveform", "j1", StatusCode::NOT_FOUND).is_ok());
+
+        let error = check_result_response("fix", "j1", StatusCode::BAD_REQUEST).unwrap_err();
+        assert!(limits::is_refused(&error));
+
+        let error =
+            check_result_response("fix", "j1", StatusCode::SERVICE_UNAVAILABLE).unwrap_err();
+        assert!(!limits::is_refused(&error));
+    }
+
+    #[tokio::test]
+    async fn test_record_posts() {
+        // Nothing is collected outside a job
+        record_post("completed", &serde_json::json!({ "status": "completed" }));
+
+        let (output, posts) = record(async {
+            record_post("stems", &serde_json::json!({ "count": 4 }));
+            record_post("completed", &serde_json::json!({ "status": "completed" }));
+            7
+        })
+        .await;
+        assert_eq!(output, 7);
+        let endpoints: Vec<&str> = posts.iter().map(|p| p.endpoint.as_str()).collect();
+        assert_eq!(endpoints, ["stems", "completed"]);
+
+        // Stored as JSON between the job and its duplicate
+        let stored = serde_json::to_string(&posts).unwrap();
+        let replayed: Vec<RecordedPost> = serde_json::from_str(&stored).unwrap();
+        assert_eq!(replayed[0].payload["count"], 4);
+        assert_eq!(replayed[1].endpoint, "completed");
+
+        let (_, posts) = record(async {}).await;
+        assert!(posts.is_empty());
+    }
+}
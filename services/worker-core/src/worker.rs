@@ -0,0 +1,1263 @@
+//! Redis job loop scaffolding
+
+use anyhow::Result;
+use redis::AsyncCommands;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+
+use crate::bullmq;
+use crate::cache;
+use crate::config::{Config, GrpcConfig, KafkaConfig, QueueConfig, QueueMode};
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::inflight::{self, Heartbeat, Keepalive};
+#[cfg(feature = "kafka")]
+use crate::kafka;
+use crate::limits;
+use crate::reconnect::{self, Backoff};
+use crate::streams;
+use crate::telemetry;
+use crate::temp;
+use crate::webhook::{self, RecordedPost, WebhookClient};
+
+/// A job payload popped from a worker queue
+///
+/// Payloads carry a `schemaVersion` (version 1 when missing) so the API and
+/// workers can be deployed independently. Fields a worker doesn't know are
+/// ignored, so adding one doesn't need a new version; changing what an
+/// existing field means does.
+pub trait QueueJob: DeserializeOwned {
+    /// Newest `schemaVersion` this worker understands
+    const SCHEMA_VERSION: u32;
+
+    fn job_id(&self) -> &str;
+
+    /// Job type, which is also the webhook endpoint its results go to
+    fn job_type(&self) -> &'static str;
+
+    /// Webhook endpoint for a payload's `type`, so a payload that can't be
+    /// run is still reported failed; `None` for types this worker doesn't run
+    fn endpoint_for(payload_type: &str) -> Option<&'static str>;
+}
+
+/// How long BRPOP waits before checking for shutdown (seconds)
+const POLL_TIMEOUT_SECS: f64 = 1.0;
+
+/// Longest wait between attempts
+const MAX_RETRY_DELAY_SECS: u64 = 600;
+
+/// Payload field counting a job's failed attempts so far
+const ATTEMPTS_FIELD: &str = "attempts";
+
+/// Payload field holding the job schema version
+const SCHEMA_VERSION_FIELD: &str = "schemaVersion";
+
+/// Worker metadata carried in a job's payload alongside its own fields
+#[derive(Debug, Default, Deserialize)]
+struct PayloadMeta {
+    #[serde(default)]
+    attempts: u32,
+    /// W3C trace context of the request that enqueued the job
+    #[serde(default)]
+    traceparent: Option<String>,
+}
+
+/// How failed jobs are retried
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    base_delay: Duration,
+}
+
+impl RetryPolicy {
+    fn new(queue: &QueueConfig) -> Self {
+        Self {
+            max_attempts: queue.max_attempts,
+            base_delay: Duration::from_secs(queue.retry_base_seconds),
+        }
+    }
+
+    /// Wait before running a job that has failed `attempts` times
+    pub(crate) fn delay(&self, attempts: u32) -> Duration {
+        let factor = 1u32 << attempts.saturating_sub(1).min(16);
+        (self.base_delay * factor).min(Duration::from_secs(MAX_RETRY_DELAY_SECS))
+    }
+}
+
+/// Pop jobs from Redis until SIGTERM/SIGINT, running `handler` on each
+///
+/// The queues come from `queue.names`; see `parse_queues` for the format.
+/// Several queues are polled in strict priority order, or in a weighted
+/// random order when weights are given. `queue.concurrency` jobs (by
+/// default one per CPU) run at once, each in its own task with its own Redis
+/// connection, since a blocking pop stalls every other command on a
+/// connection. A task only pops a job when it is free, so memory stays
+/// bounded by the number of tasks. Failed jobs are reported to their job
+/// type's webhook.
+///
+/// A failed (or panicking) job is retried up to `queue.max_attempts` times
+/// in total, waiting `queue.retry_base_seconds` doubled for each attempt,
+/// and only reported failed once they are used up. A job failing with an
+/// error retrying can't fix, such as an invalid setting or undecodable
+/// audio, is dead-lettered at once; see `limits::refused`. Waiting retries
+/// sit in the `{queue}:retry` sorted set of the queue they came from, scored
+/// by when they are due.
+///
+/// Jobs that exhaust their attempts, and rejected payloads, are pushed to
+/// the `queue.dead_letter_queue` list (default `{first queue}:dead`); see
+/// `dead_letter` for the entry format.
+///
+/// Payloads that don't parse, or carry a `schemaVersion` newer than the
+/// worker's, are rejected without being run and reported failed with an
+/// `errorCode` of `invalid_payload` or `unsupported_schema_version`, when
+/// their `jobId` and `type` can be read.
+///
+/// A job that succeeds has the result webhooks it posted stored under
+/// `job-results:{jobId}:{payload checksum}` for `queue.result_ttl_seconds`
+/// (default a week). A duplicate of it is answered by posting them again
+/// instead of being processed.
+///
+/// Popped jobs are tracked in Redis until they are dealt with, and recovered
+/// by other workers if this one dies without finishing them; see `inflight`.
+///
+/// Losing Redis doesn't stop the worker: each task reconnects with backoff
+/// and carries on, returning any job it held to its queue. Only errors
+/// retrying can't fix are returned; see `reconnect`.
+///
+/// With `queue.mode = "bullmq"` the queues are BullMQ queues instead, and
+/// retries, failures and recovery follow BullMQ's rules; see `bullmq`. With
+/// `queue.mode = "kafka"` they are Kafka topics read by a consumer group, and
+/// Redis isn't used at all; see `kafka`. With `queue.mode = "stream"` they
+/// are Redis streams read through the `queue.stream_group` consumer group,
+/// and taken jobs are held in its pending entries instead of processing
+/// lists; see `streams`. With `queue.mode = "grpc"` jobs are submitted to the
+/// worker's own gRPC API and held in memory, again without Redis; see `grpc`.
+///
+/// On shutdown no new jobs are taken. In-flight jobs get
+/// `queue.shutdown_grace_seconds` to finish; past that they are dropped
+/// (removing their temp files) and pushed back onto the queue for another
+/// worker.
+pub async fn run_jobs<J, F, Fut>(
+    config: &Config,
+    webhook: &'static WebhookClient,
+    handler: F,
+) -> Result<()>
+where
+    J: QueueJob + Send + 'static,
+    F: Fn(J) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    limits::init(&config.limits);
+    temp::init(&config.temp);
+    cache::init(&config.cache);
+    tokio::spawn(temp::run_sweeper(config.temp.clone()));
+
+    // Connect to Redis
+    let client = redis::Client::open(config.redis.url.as_str())?;
+
+    let queue = &config.queue;
+    let queues = parse_queues(&queue.names)?;
+    let visibility = Duration::from_secs(queue.visibility_timeout_seconds);
+    let concurrency = queue
+        .concurrency
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let config = Arc::new(LoopConfig {
+        worker: inflight::worker_id(),
+        mode: queue.mode,
+        bullmq_prefix: queue.bullmq_prefix.clone(),
+        stream_group: queue.stream_group.clone(),
+        kafka: config.kafka.clone(),
+        grpc: config.grpc.clone(),
+        visibility,
+        // Kafka topic names can't contain ':'
+        dead_letters: queue
+            .dead_letter_queue
+            .clone()
+            .unwrap_or_else(|| match queue.mode {
+                QueueMode::Kafka => format!("{}.dead", queues[0].name),
+                _ => format!("{}:dead", queues[0].name),
+            }),
+        retry: RetryPolicy::new(queue),
+        result_ttl: queue.result_ttl_seconds,
+        grace: Duration::from_secs(queue.shutdown_grace_seconds),
+        queues,
+    });
+
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("Shutdown requested; no longer accepting jobs");
+        let _ = shutdown_tx.send(true);
+    });
+
+    if config.mode == QueueMode::Grpc {
+        #[cfg(feature = "grpc")]
+        return grpc::run(config, concurrency, webhook, Arc::new(handler), shutdown).await;
+        #[cfg(not(feature = "grpc"))]
+        anyhow::bail!("gRPC mode needs the grpc feature");
+    }
+
+    let names: Vec<String> = config.queues.iter().map(|q| q.to_string()).collect();
+    info!(
+        "Listening for jobs on {}: {} ({} at a time)",
+        if config.weighted() {
+            "weighted queues"
+        } else {
+            "queues"
+        },
+        names.join(", "),
+        concurrency
+    );
+
+    // BullMQ jobs are held by their own locks, and Kafka and stream jobs by
+    // their consumer group, instead
+    let heartbeat = match config.mode {
+        QueueMode::List => {
+            let mut backoff = Backoff::new();
+            let heartbeat =
+                reconnect::retry("the worker heartbeat", &shutdown, &mut backoff, || async {
+                    Heartbeat::start(&client, &config.worker, visibility)
+                })
+                .await?;
+            let Some(heartbeat) = heartbeat else {
+                info!("Worker stopped");
+                return Ok(());
+            };
+            Some(heartbeat)
+        }
+        QueueMode::Bullmq | QueueMode::Kafka | QueueMode::Stream | QueueMode::Grpc => None,
+    };
+
+    let handler = Arc::new(handler);
+    let mut tasks = JoinSet::new();
+    for task in 0..concurrency {
+        tasks.spawn(job_loop(
+            task as u64,
+            client.clone(),
+            config.clone(),
+            webhook,
+            handler.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    // Tasks ride out Redis outages, so only a fatal error (such as bad
+    // credentials) stops the worker; dropping the set aborts the rest
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+
+    if let Some(heartbeat) = heartbeat {
+        tokio::task::spawn_blocking(move || heartbeat.stop()).await??;
+    }
+    info!("Worker stopped");
+    Ok(())
+}
+
+/// A queue polled by the worker, with its share of polls when weighted
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueueSpec {
+    pub(crate) name: String,
+    pub(crate) weight: Option<u32>,
+}
+
+impl std::fmt::Display for QueueSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.weight {
+            Some(weight) => write!(f, "{} (weight {})", self.name, weight),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Parse a comma-separated queue list
+///
+/// - "dsp-jobs": a single queue
+/// - "dsp-jobs-high,dsp-jobs-low": strict priority; a job is only taken from
+///   a queue when every queue before it is empty
+/// - "dsp-jobs-high:4,dsp-jobs-low:1": weighted; each poll tries the queues in
+///   a random order where each comes first in proportion to its weight, so
+///   low-priority work still drains under load. Missing weights count as 1.
+pub(crate) fn parse_queues(value: &str) -> Result<Vec<QueueSpec>> {
+    let mut queues: Vec<QueueSpec> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.rsplit_once(':') {
+            Some((name, weight)) if weight.chars().all(|c| c.is_ascii_digit()) => {
+                let weight: u32 = weight
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid queue weight in {}", entry))?;
+                if weight == 0 {
+                    anyhow::bail!("Queue weight must be positive: {}", entry);
+                }
+                Ok(QueueSpec {
+                    name: name.to_string(),
+                    weight: Some(weight),
+                })
+            }
+            _ => Ok(QueueSpec {
+                name: entry.to_string(),
+                weight: None,
+            }),
+        })
+        .collect::<Result<_>>()?;
+    if queues.is_empty() {
+        anyhow::bail!("No job queues configured");
+    }
+    if queues.iter().any(|q| q.weight.is_some()) {
+        for queue in &mut queues {
+            queue.weight.get_or_insert(1);
+        }
+    }
+    Ok(queues)
+}
+
+/// Settings shared by every job-processing task
+pub(crate) struct LoopConfig {
+    pub(crate) worker: String,
+    pub(crate) mode: QueueMode,
+    /// Key prefix of BullMQ queues
+    pub(crate) bullmq_prefix: String,
+    /// Consumer group of stream queues
+    stream_group: String,
+    #[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+    pub(crate) kafka: KafkaConfig,
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub(crate) grpc: GrpcConfig,
+    pub(crate) visibility: Duration,
+    pub(crate) queues: Vec<QueueSpec>,
+    pub(crate) dead_letters: String,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) result_ttl: u64,
+    pub(crate) grace: Duration,
+}
+
+impl LoopConfig {
+    fn weighted(&self) -> bool {
+        self.queues.iter().any(|q| q.weight.is_some())
+    }
+
+    /// Queue names in the order the next BRPOP should try them
+    pub(crate) fn poll_order(&self, rng: &mut XorShift) -> Vec<&str> {
+        let mut remaining: Vec<&QueueSpec> = self.queues.iter().collect();
+        if !self.weighted() {
+            return remaining.iter().map(|q| q.name.as_str()).collect();
+        }
+
+        let mut order = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let total: u64 = remaining.iter().map(|q| q.weight.unwrap_or(1) as u64).sum();
+            let mut pick = rng.next() % total;
+            let index = remaining
+                .iter()
+                .position(|q| {
+                    let weight = q.weight.unwrap_or(1) as u64;
+                    if pick < weight {
+                        true
+                    } else {
+                        pick -= weight;
+                        false
+                    }
+                })
+                .unwrap_or(0);
+            order.push(remaining.remove(index).name.as_str());
+        }
+        order
+    }
+}
+
+/// Small PRNG for weighted polling, which doesn't need to be unpredictable
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// One job-processing task: pop, run and retry jobs until shutdown,
+/// reconnecting whenever Redis goes away
+async fn job_loop<J, F, Fut>(
+    task: u64,
+    client: redis::Client,
+    config: Arc<LoopConfig>,
+    webhook: &'static WebhookClient,
+    handler: Arc<F>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    if config.mode == QueueMode::Kafka {
+        #[cfg(feature = "kafka")]
+        return kafka::process_jobs(task, config, webhook, handler, shutdown).await;
+        #[cfg(not(feature = "kafka"))]
+        anyhow::bail!("Kafka mode needs the kafka feature");
+    }
+
+    let what = format!("job task {}", task);
+    let mut backoff = Backoff::new();
+    reconnect::retry(&what, &shutdown, &mut backoff, || {
+        let client = client.clone();
+        let config = config.clone();
+        let handler = handler.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            let conn = client.get_multiplexed_async_connection().await?;
+            match config.mode {
+                QueueMode::Bullmq => {
+                    bullmq::process_jobs(task, client, conn, config, webhook, handler, shutdown)
+                        .await
+                }
+                _ => process_jobs(task, &client, conn, config, webhook, handler, shutdown).await,
+            }
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+/// Process jobs over one Redis connection until shutdown or a Redis error
+async fn process_jobs<J, F, Fut>(
+    task: u64,
+    client: &redis::Client,
+    mut conn: redis::aio::MultiplexedConnection,
+    config: Arc<LoopConfig>,
+    webhook: &'static WebhookClient,
+    handler: Arc<F>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()>
+where
+    J: QueueJob,
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let LoopConfig {
+        dead_letters,
+        retry,
+        result_ttl,
+        grace,
+        ..
+    } = &*config;
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    let mut rng = XorShift::new(seed ^ task.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+    let consumer = inflight::consumer(&config.worker, task);
+    let queue_names: Vec<&str> = config.queues.iter().map(|q| q.name.as_str()).collect();
+    let mut source = JobSource::new(&config);
+    source
+        .start(&mut conn, &queue_names, &consumer, task)
+        .await?;
+    let mut last_reap: Option<Instant> = None;
+
+    loop {
+        // One reaper per worker is plenty; stream entries are reclaimed as
+        // they are taken instead
+        let reaps = matches!(source, JobSource::List);
+        if reaps && task == 0 && last_reap.is_none_or(|t| t.elapsed() >= config.visibility / 2) {
+            let recovered = inflight::reap(&mut conn, &queue_names).await?;
+            if recovered > 0 {
+                warn!("Recovered {} jobs from workers that stopped", recovered);
+            }
+            last_reap = Some(Instant::now());
+        }
+
+        for queue in &config.queues {
+            promote_due_retries(&mut conn, &source, &queue.name, &retry_set(&queue.name)).await?;
+        }
+
+        // Dropping a blocking pop could lose a job popped after the drop,
+        // so poll with a timeout and check for shutdown in between
+        let keys = config.poll_order(&mut rng);
+        let result = source
+            .take(&mut conn, &keys, &consumer, config.visibility)
+            .await?;
+        let shutting_down = *shutdown.borrow();
+
+        let Some(taken) = result else {
+            if shutting_down {
+                break;
+            }
+            continue;
+        };
+        if shutting_down {
+            // Popped as the signal arrived; hand it back untouched
+            source.release(&mut conn, &consumer, &taken).await?;
+            break;
+        }
+        let (queue, payload) = (taken.queue.as_str(), taken.payload.as_str());
+        let job = match parse_job::<J>(payload) {
+            Ok(job) => job,
+            Err(rejection) => {
+                error!("Rejected job: {}", rejection.error);
+                warn!("Payload was: {}", payload);
+                dead_letter(&mut conn, dead_letters, queue, payload, &rejection.error, 0).await?;
+                source.ack(&mut conn, &consumer, &taken).await?;
+                if let (Some(job_id), Some(endpoint)) = (&rejection.job_id, rejection.endpoint) {
+                    if let Err(we) = webhook
+                        .report_rejected(job_id, endpoint, &rejection, J::SCHEMA_VERSION)
+                        .await
+                    {
+                        error!("Failed to report rejected job: {:?}", we);
+                    }
+                }
+                continue;
+            }
+        };
+
+        let meta = serde_json::from_str::<PayloadMeta>(payload).unwrap_or_default();
+        let attempts = meta.attempts;
+
+        let job_id = job.job_id().to_string();
+        let job_type = job.job_type();
+
+        // Stream entries are handed out again when their worker stops
+        // mid-job; one that keeps taking workers down isn't run again
+        if taken.deliveries > retry.max_attempts {
+            error!(
+                "Job {} was taken {} times by workers that stopped without finishing it",
+                job_id, taken.deliveries
+            );
+            let error = format!(
+                "Abandoned by {} workers that stopped mid-job",
+                taken.deliveries - 1
+            );
+            dead_letter(&mut conn, dead_letters, queue, payload, &error, attempts).await?;
+            source.ack(&mut conn, &consumer, &taken).await?;
+            if let Err(we) = webhook.report_failure(&job_id, job_type, &error).await {
+                error!("Failed to report job failure: {:?}", we);
+            }
+            continue;
+        }
+
+        let result_key = result_key(&job_id, payload);
+        let cached: Option<String> = conn.get(&result_key).await?;
+        if let Some(posts) = cached.and_then(|c| serde_json::from_str::<Vec<RecordedPost>>(&c).ok())
+        {
+            info!(
+                "{} job {} already completed; replaying its results",
+                job_type, job_id
+            );
+            if let Err(e) = webhook.replay(&job_id, &posts).await {
+                error!("Failed to replay job results: {:?}", e);
+            }
+            source.ack(&mut conn, &consumer, &taken).await?;
+            continue;
+        }
+
+        if attempts == 0 {
+            info!("Processing {} job {}", job_type, job_id);
+        } else {
+            info!(
+                "Processing {} job {} (attempt {} of {})",
+                job_type,
+                job_id,
+                attempts + 1,
+                retry.max_attempts
+            );
+        }
+
+        let span = job_span(
+            job_type,
+            &job_id,
+            queue,
+            attempts + 1,
+            meta.traceparent.as_deref(),
+        );
+        let keepalive = source.keep_fresh(client, &consumer, &taken, config.visibility);
+        let result = run_handler(&*handler, job, span, &shutdown, *grace).await;
+        drop(keepalive);
+        let Some(result) = result else {
+            warn!(
+                "Job {} didn't finish within {:?} of shutdown",
+                job_id, grace
+            );
+            source.release(&mut conn, &consumer, &taken).await?;
+            info!("Re-queued unfinished job {} on {}", job_id, queue);
+            break;
+        };
+
+        match result {
+            Ok(posts) => {
+                let posts = serde_json::to_string(&posts)?;
+                conn.set_ex::<_, _, ()>(&result_key, posts, *result_ttl)
+                    .await?;
+                source.ack(&mut conn, &consumer, &taken).await?;
+            }
+            Err(e) if attempts + 1 < retry.max_attempts && !limits::is_refused(&e) => {
+                let attempts = attempts + 1;
+                let delay = retry.delay(attempts);
+                warn!(
+                    "Job {} failed (attempt {} of {}), retrying in {:?}: {:?}",
+                    job_id, attempts, retry.max_attempts, delay, e
+                );
+                let due = SystemTime::now().duration_since(UNIX_EPOCH)? + delay;
+                let retry_payload = with_attempts(payload, attempts)?;
+                conn.zadd::<_, _, _, ()>(retry_set(queue), retry_payload, due.as_secs_f64())
+                    .await?;
+                source.ack(&mut conn, &consumer, &taken).await?;
+            }
+            Err(e) => {
+                let attempts = attempts + 1;
+                if limits::is_refused(&e) {
+                    error!("Job {} refused: {:?}", job_id, e);
+                } else {
+                    error!("Job {} failed after {} attempts: {:?}", job_id, attempts, e);
+                }
+                let error = format!("{:#}", e);
+                dead_letter(&mut conn, dead_letters, queue, payload, &error, attempts).await?;
+                source.ack(&mut conn, &consumer, &taken).await?;
+                if let Err(we) = webhook
+                    .report_failure(&job_id, job_type, &e.to_string())
+                    .await
+                {
+                    error!("Failed to report job failure: {:?}", we);
+                }
+            }
+        }
+
+        if *shutdown.borrow() {
+            break;
+        }
+    }
+
+    source.stop(&mut conn, &queue_names, &consumer).await?;
+    Ok(())
+}
+
+/// Where a task takes jobs from, and how it holds them while they run
+enum JobSource {
+    /// Redis lists, holding taken jobs in the task's processing lists; see
+    /// `inflight`
+    List,
+    /// Redis streams read through a consumer group; see `streams`
+    Stream {
+        group: String,
+        /// Whether the task may still hold entries from a lost connection
+        recovering: bool,
+        last_claim: Option<Instant>,
+    },
+}
+
+/// A job taken from a queue
+struct Taken {
+    queue: String,
+    payload: String,
+    /// The stream entry it was read from, in stream mode
+    entry_id: Option<String>,
+    /// Times the job has been handed out by its queue, including this once
+    deliveries: u32,
+}
+
+impl From<streams::Entry> for Taken {
+    fn from(entry: streams::Entry) -> Self {
+        Self {
+            queue: entry.queue,
+            payload: entry.payload,
+            entry_id: Some(entry.id),
+            deliveries: entry.deliveries,
+        }
+    }
+}
+
+impl JobSource {
+    fn new(config: &LoopConfig) -> Self {
+        match config.mode {
+            QueueMode::Stream => Self::Stream {
+                group: config.stream_group.clone(),
+                recovering: true,
+                last_claim: None,
+            },
+            _ => Self::List,
+        }
+    }
+
+    /// Get ready to take jobs over a fresh connection
+    async fn start(
+        &mut self,
+        conn: &mut redis::aio::MultiplexedConnection,
+        queues: &[&str],
+        consumer: &str,
+        task: u64,
+    ) -> Result<()> {
+        match self {
+            Self::List => {
+                inflight::register(conn, queues, consumer).await?;
+                let recovered = inflight::recover(conn, queues, consumer).await?;
+                if recovered > 0 {
+                    warn!(
+                        "Re-queued {} jobs task {} held when it lost Redis",
+                        recovered, task
+                    );
+                }
+            }
+            Self::Stream {
+                group, recovering, ..
+            } => {
+                streams::create_groups(conn, queues, group).await?;
+                *recovering = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the next job from the first of `keys` that has one, waiting a
+    /// little on the first when none do
+    async fn take(
+        &mut self,
+        conn: &mut redis::aio::MultiplexedConnection,
+        keys: &[&str],
+        consumer: &str,
+        visibility: Duration,
+    ) -> Result<Option<Taken>> {
+        match self {
+            Self::List => {
+                let popped = inflight::pop(conn, keys, consumer, POLL_TIMEOUT_SECS).await?;
+                Ok(popped.map(|(queue, payload)| Taken {
+                    queue,
+                    payload,
+                    entry_id: None,
+                    deliveries: 1,
+                }))
+            }
+            Self::Stream {
+                group,
+                recovering,
+                last_claim,
+            } => {
+                if *recovering {
+                    let pending = streams::pop_own_pending(conn, keys, group, consumer).await?;
+                    if let Some(entry) = pending {
+                        warn!(
+                            "Resuming job {} on {} held when Redis was lost",
+                            entry.id, entry.queue
+                        );
+                        return Ok(Some(entry.into()));
+                    }
+                    *recovering = false;
+                }
+                // Entries idle for the whole visibility timeout belong to
+                // workers that stopped; keep claiming while there are some
+                if last_claim.is_none_or(|t| t.elapsed() >= visibility / 2) {
+                    let stale =
+                        streams::claim_stale(conn, keys, group, consumer, visibility).await?;
+                    if let Some(entry) = stale {
+                        warn!(
+                            "Recovered job {} on {} from a worker that stopped",
+                            entry.id, entry.queue
+                        );
+                        return Ok(Some(entry.into()));
+                    }
+                    *last_claim = Some(Instant::now());
+                }
+                let entry = streams::pop(conn, keys, group, consumer, POLL_TIMEOUT_SECS).await?;
+                Ok(entry.map(Taken::from))
+            }
+        }
+    }
+
+    /// Keep a taken job from being handed to another worker while it runs;
+    /// list jobs are covered by the worker's heartbeat
+    fn keep_fresh(
+        &self,
+        client: &redis::Client,
+        consumer: &str,
+        taken: &Taken,
+        visibility: Duration,
+    ) -> Option<Keepalive> {
+        match (self, &taken.entry_id) {
+            (Self::Stream { group, .. }, Some(id)) => Some(streams::keep_fresh(
+                client,
+                &taken.queue,
+                group,
+                consumer,
+                id,
+                visibility,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Let go of a job that has been dealt with
+    async fn ack(
+        &self,
+        conn: &mut redis::aio::MultiplexedConnection,
+        consumer: &str,
+        taken: &Taken,
+    ) -> Result<()> {
+        match (self, &taken.entry_id) {
+            (Self::Stream { group, .. }, Some(id)) => {
+                streams::ack(conn, &taken.queue, group, id).await
+            }
+            _ => inflight::ack(conn, &taken.queue, consumer, &taken.payload).await,
+        }
+    }
+
+    /// Hand a job back for another worker to run
+    async fn release(
+        &self,
+        conn: &mut redis::aio::MultiplexedConnection,
+        consumer: &str,
+        taken: &Taken,
+    ) -> Result<()> {
+        match (self, &taken.entry_id) {
+            (Self::Stream { group, .. }, Some(id)) => {
+                streams::release(conn, &taken.queue, group, id, &taken.payload).await
+            }
+            _ => inflight::release(conn, &taken.queue, consumer, &taken.payload).await,
+        }
+    }
+
+    /// Add a job to the back of `queue`
+    async fn push(
+        &self,
+        conn: &mut redis::aio::MultiplexedConnection,
+        queue: &str,
+        payload: &str,
+    ) -> Result<()> {
+        match self {
+            Self::List => Ok(conn.lpush::<_, _, ()>(queue, payload).await?),
+            Self::Stream { .. } => streams::push(conn, queue, payload).await,
+        }
+    }
+
+    /// Stop taking jobs, with none held
+    async fn stop(
+        &self,
+        conn: &mut redis::aio::MultiplexedConnection,
+        queues: &[&str],
+        consumer: &str,
+    ) -> Result<()> {
+        match self {
+            Self::List => inflight::deregister(conn, queues, consumer).await,
+            // Consumers with nothing pending are harmless in the group
+            Self::Stream { .. } => Ok(()),
+        }
+    }
+}
+
+/// A payload that was refused before running
+#[derive(Debug)]
+pub(crate) struct Rejection {
+    pub(crate) job_id: Option<String>,
+    /// Where the failure is reported, if the payload's type is known
+    pub(crate) endpoint: Option<&'static str>,
+    /// Machine-readable reason: `unsupported_schema_version` or
+    /// `invalid_payload`
+    pub(crate) code: &'static str,
+    /// The payload's schema version, when it had a readable one
+    pub(crate) schema_version: Option<u64>,
+    pub(crate) error: String,
+}
+
+/// Check a payload's schema version, then parse it
+///
+/// Newer versions are refused without being parsed, since their fields may
+/// mean something this worker would get wrong.
+pub(crate) fn parse_job<J: QueueJob>(payload: &str) -> Result<J, Rejection> {
+    let value: serde_json::Value = serde_json::from_str(payload).map_err(|e| Rejection {
+        job_id: None,
+        endpoint: None,
+        code: "invalid_payload",
+        schema_version: None,
+        error: format!("Invalid job payload: {}", e),
+    })?;
+    let job_id = value
+        .get("jobId")
+        .and_then(|id| id.as_str())
+        .map(String::from);
+    let endpoint = value
+        .get("type")
+        .and_then(|t| t.as_str())
+        .and_then(J::endpoint_for);
+    let reject = |code, schema_version, error| Rejection {
+        job_id: job_id.clone(),
+        endpoint,
+        code,
+        schema_version,
+        error,
+    };
+
+    let schema_version = match value.get(SCHEMA_VERSION_FIELD) {
+        None | Some(serde_json::Value::Null) => 1,
+        Some(version) => version.as_u64().ok_or_else(|| {
+            reject(
+                "invalid_payload",
+                None,
+                format!(
+                    "Invalid job payload: schemaVersion {} isn't a number",
+                    version
+                ),
+            )
+        })?,
+    };
+    if !(1..=u64::from(J::SCHEMA_VERSION)).contains(&schema_version) {
+        return Err(reject(
+            "unsupported_schema_version",
+            Some(schema_version),
+            format!(
+                "Unsupported job schema version {} (this worker supports 1 to {})",
+                schema_version,
+                J::SCHEMA_VERSION
+            ),
+        ));
+    }
+
+    serde_json::from_value(value).map_err(|e| {
+        reject(
+            "invalid_payload",
+            Some(schema_version),
+            format!("Invalid job payload: {}", e),
+        )
+    })
+}
+
+/// Sorted set holding a queue's jobs waiting to be retried
+fn retry_set(queue: &str) -> String {
+    format!("{}:retry", queue)
+}
+
+/// Move retries whose wait is over from `retry_set` to the back of the queue
+async fn promote_due_retries(
+    conn: &mut redis::aio::MultiplexedConnection,
+    source: &JobSource,
+    queue: &str,
+    retry_set: &str,
+) -> Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+    let due: Vec<String> = conn.zrangebyscore(retry_set, "-inf", now).await?;
+    for payload in due {
+        // Only the worker whose ZREM succeeds re-queues it
+        let removed: u32 = conn.zrem(retry_set, &payload).await?;
+        if removed > 0 {
+            source.push(conn, queue, &payload).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Record a job that won't be run again on the dead-letter list
+///
+/// Entries are JSON objects: `queue`, the original `payload` string (with
+/// retry metadata removed, so `LPUSH <queue> <payload>` replays it with a
+/// fresh set of attempts), the last `error`, `attempts` made and `failedAt`
+/// (Unix seconds).
+async fn dead_letter(
+    conn: &mut redis::aio::MultiplexedConnection,
+    dead_letters: &str,
+    queue: &str,
+    payload: &str,
+    error: &str,
+    attempts: u32,
+) -> Result<()> {
+    let entry = dead_letter_entry(queue, payload, error, attempts)?;
+    conn.lpush::<_, _, ()>(dead_letters, entry).await?;
+    warn!("Moved job to dead-letter queue {}", dead_letters);
+    Ok(())
+}
+
+/// A dead-letter entry in the format `dead_letter` describes
+pub(crate) fn dead_letter_entry(
+    queue: &str,
+    payload: &str,
+    error: &str,
+    attempts: u32,
+) -> Result<String> {
+    let failed_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let entry = serde_json::json!({
+        "queue": queue,
+        "payload": without_attempts(payload),
+        "error": error,
+        "attempts": attempts,
+        "failedAt": failed_at
+    });
+    Ok(entry.to_string())
+}
+
+/// Poll a job, turning a panic into an error so it is retried like one
+async fn catch_panic<T, Fut>(job: &mut std::pin::Pin<Box<Fut>>) -> Result<T>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(AssertUnwindSafe(|| job.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(panic) => {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                Poll::Ready(Err(anyhow::anyhow!("Job panicked: {}", message)))
+            }
+        }
+    })
+    .await
+}
+
+/// Key a successful job's results are stored under
+///
+/// The checksum (64-bit FNV-1a of the payload without retry metadata, whose
+/// keys serde_json keeps sorted) makes a reused job ID with different
+/// parameters run again.
+fn result_key(job_id: &str, payload: &str) -> String {
+    let checksum = without_attempts(payload)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("job-results:{}:{:016x}", job_id, checksum)
+}
+
+/// A payload without its retry metadata, unchanged if it isn't a JSON object
+fn without_attempts(payload: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(payload) {
+        Ok(serde_json::Value::Object(mut object)) => {
+            object.remove(ATTEMPTS_FIELD);
+            serde_json::Value::Object(object).to_string()
+        }
+        _ => payload.to_string(),
+    }
+}
+
+/// A job payload with its failed attempt count set
+fn with_attempts(payload: &str, attempts: u32) -> Result<String> {
+    let mut value: serde_json::Value = serde_json::from_str(payload)?;
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow::anyhow!("Job payload is not a JSON object"))?;
+    object.insert(ATTEMPTS_FIELD.to_string(), attempts.into());
+    Ok(value.to_string())
+}
+
+/// The span a job runs under, continuing the enqueuing request's trace
+pub(crate) fn job_span(
+    job_type: &str,
+    job_id: &str,
+    queue: &str,
+    attempt: u32,
+    traceparent: Option<&str>,
+) -> Span {
+    let span = info_span!(
+        "job",
+        otel.name = format!("{} job", job_type),
+        job.id = %job_id,
+        job.queue = %queue,
+        job.attempt = attempt
+    );
+    if let Some(traceparent) = traceparent {
+        telemetry::set_parent(&span, traceparent);
+    }
+    span
+}
+
+/// Run `handler` on a job under `span`, collecting the result webhooks it
+/// posts; a panic becomes an error
+///
+/// Returns `None` if shutdown is requested and the job doesn't finish within
+/// `grace`, by which time it has been dropped.
+pub(crate) async fn run_handler<J, F, Fut>(
+    handler: &F,
+    job: J,
+    span: Span,
+    shutdown: &watch::Receiver<bool>,
+    grace: Duration,
+) -> Option<Result<Vec<RecordedPost>>>
+where
+    F: Fn(J) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let job = handler(job);
+    let mut job = Box::pin(
+        async move {
+            let (result, posts) = webhook::record(job).await;
+            result.map(|()| posts)
+        }
+        .instrument(span),
+    );
+    tokio::select! {
+        result = catch_panic(&mut job) => Some(result),
+        _ = grace_expired(shutdown.clone(), grace) => None,
+    }
+}
+
+/// Resolve `grace` after shutdown is requested
+async fn grace_expired(mut shutdown: watch::Receiver<bool>, grace: Duration) {
+    if shutdown.wait_for(|&stop| stop).await.is_err() {
+        // The signal task is gone, so shutdown will never be requested
+        std::future::pending::<()>().await;
+    }
+    tokio::time::sleep(grace).await;
+}
+
+/// Wait for SIGTERM (what Kubernetes and Docker send) or Ctrl-C
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM: {:?}", e),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_backoff_and_attempts() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(5),
+        };
+        let delays: Vec<u64> = (1..=4).map(|a| policy.delay(a).as_secs()).collect();
+        assert_eq!(delays, [5, 10, 20, 40]);
+        assert_eq!(policy.delay(30), Duration::from_secs(MAX_RETRY_DELAY_SECS));
+
+        let payload = r#"{"type":"analyze","jobId":"j1"}"#;
+        let retried = with_attempts(payload, 2).unwrap();
+        let meta: PayloadMeta = serde_json::from_str(&retried).unwrap();
+        assert_eq!(meta.attempts, 2);
+        let value: serde_json::Value = serde_json::from_str(&retried).unwrap();
+        assert_eq!(value["jobId"], "j1");
+
+        // Dead letters are replayable from scratch
+        let replay: serde_json::Value = serde_json::from_str(&without_attempts(&retried)).unwrap();
+        assert_eq!(
+            replay,
+            serde_json::from_str::<serde_json::Value>(payload).unwrap()
+        );
+        assert_eq!(without_attempts("not json"), "not json");
+
+        // Retries and reordered fields are the same job; other parameters aren't
+        let reordered = r#"{"jobId":"j1","type":"analyze"}"#;
+        assert_eq!(result_key("j1", payload), result_key("j1", &retried));
+        assert_eq!(result_key("j1", payload), result_key("j1", reordered));
+        assert_ne!(
+            result_key("j1", payload),
+            result_key("j1", r#"{"type":"fix","jobId":"j1"}"#)
+        );
+    }
+
+    #[test]
+    fn test_schema_versions() {
+        #[derive(Debug, Deserialize)]
+        #[serde(tag = "type")]
+        enum TestJob {
+            #[serde(rename = "analyze")]
+            Analyze {
+                #[serde(rename = "jobId")]
+                job_id: String,
+            },
+        }
+        impl QueueJob for TestJob {
+            const SCHEMA_VERSION: u32 = 2;
+            fn job_id(&self) -> &str {
+                let TestJob::Analyze { job_id } = self;
+                job_id
+            }
+            fn job_type(&self) -> &'static str {
+                "analysis"
+            }
+            fn endpoint_for(payload_type: &str) -> Option<&'static str> {
+                (payload_type == "analyze").then_some("analysis")
+            }
+        }
+
+        // Unversioned payloads are version 1, and unknown fields are ignored
+        let job: TestJob = parse_job(r#"{"type":"analyze","jobId":"j1","extra":true}"#).unwrap();
+        assert_eq!(job.job_id(), "j1");
+        assert!(
+            parse_job::<TestJob>(r#"{"type":"analyze","jobId":"j1","schemaVersion":2}"#).is_ok()
+        );
+
+        let newer =
+            parse_job::<TestJob>(r#"{"type":"analyze","jobId":"j1","schemaVersion":3,"new":1}"#)
+                .unwrap_err();
+        assert_eq!(newer.code, "unsupported_schema_version");
+        assert_eq!(newer.schema_version, Some(3));
+        assert_eq!(
+            (newer.job_id.as_deref(), newer.endpoint),
+            (Some("j1"), Some("analysis"))
+        );
+
+        let invalid = parse_job::<TestJob>(r#"{"type":"analyze"}"#).unwrap_err();
+        assert_eq!(invalid.code, "invalid_payload");
+        assert_eq!(invalid.endpoint, Some("analysis"));
+        let unknown = parse_job::<TestJob>(r#"{"type":"remix","jobId":"j2"}"#).unwrap_err();
+        assert_eq!(unknown.endpoint, None);
+        assert!(parse_job::<TestJob>("not json")
+            .unwrap_err()
+            .job_id
+            .is_none());
+    }
+
+    #[test]
+    fn test_queue_priorities() {
+        let strict = parse_queues("dsp-jobs-high, dsp-jobs-low").unwrap();
+        assert_eq!(
+            strict.iter().map(|q| q.weight).collect::<Vec<_>>(),
+            [None, None]
+        );
+        assert!(parse_queues("dsp-jobs:0").is_err());
+        assert!(parse_queues(" , ").is_err());
+
+        let config = |queues| LoopConfig {
+            worker: "test".into(),
+            mode: QueueMode::List,
+            bullmq_prefix: "bull".into(),
+            stream_group: "budi-workers".into(),
+            kafka: KafkaConfig::default(),
+            grpc: GrpcConfig::default(),
+            visibility: Duration::ZERO,
+            queues,
+            dead_letters: "dead".into(),
+            retry: RetryPolicy {
+                max_attempts: 1,
+                base_delay: Duration::ZERO,
+            },
+            result_ttl: 0,
+            grace: Duration::ZERO,
+        };
+        let mut rng = XorShift::new(42);
+        assert_eq!(
+            config(strict).poll_order(&mut rng),
+            ["dsp-jobs-high", "dsp-jobs-low"]
+        );
+
+        // Unweighted queues count as weight 1 once any weight is given
+        let weighted = config(parse_queues("high:3,low").unwrap());
+        let high_first = (0..4000)
+            .filter(|_| weighted.poll_order(&mut rng)[0] == "high")
+            .count();
+        assert!((2800..3200).contains(&high_first), "{}", high_first);
+    }
+}
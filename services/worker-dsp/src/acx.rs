@@ -0,0 +1,282 @@
+//! ACX (Audible) audiobook submission requirements
+//!
+//! ACX rejects chapters that break any of a handful of level rules, each
+//! reported here on its own so a producer knows what to fix:
+//!
+//! - RMS level between -23 and -18 dB
+//! - Sample peaks no higher than -3 dB
+//! - Noise floor (the quietest half second) no higher than -60 dB RMS
+//! - 0.5 to 1 second of room tone at the head and 1 to 5 seconds at the tail
+//!
+//! Levels are gathered per `BLOCK_SECONDS` block as the file is decoded, so
+//! a chapter of any length is checked holding only one number per block.
+
+use anyhow::Result;
+use budi_worker_core::audio::AudioDecoder;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::types::AudioBuffer;
+
+/// Allowed RMS level of the whole chapter (dB)
+const RMS_RANGE_DB: (f64, f64) = (-23.0, -18.0);
+
+/// Highest sample peak allowed (dB)
+const MAX_PEAK_DB: f64 = -3.0;
+
+/// Highest noise floor allowed (dB RMS)
+const MAX_NOISE_FLOOR_DB: f64 = -60.0;
+
+/// Allowed room tone at the head and tail (seconds)
+const HEAD_ROOM_TONE_SECONDS: (f64, f64) = (0.5, 1.0);
+const TAIL_ROOM_TONE_SECONDS: (f64, f64) = (1.0, 5.0);
+
+/// Length of the blocks levels are measured in (seconds)
+const BLOCK_SECONDS: f64 = 0.05;
+
+/// Blocks in the window the noise floor is measured over (half a second)
+const NOISE_FLOOR_BLOCKS: usize = 10;
+
+/// Level above which a block counts as narration rather than room tone (dB
+/// RMS); well above any passing noise floor and well below speech
+const ROOM_TONE_MAX_DB: f64 = -50.0;
+
+/// Level reported for digital silence (dB)
+const FLOOR_DB: f64 = -144.0;
+
+/// One ACX requirement and how the chapter measured against it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Criterion {
+    /// "rms", "peak", "noise-floor", "head-room-tone" or "tail-room-tone"
+    pub name: &'static str,
+    pub passes: bool,
+    /// Measured level (dB) or length (seconds)
+    pub value: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Criterion {
+    fn new(name: &'static str, value: f64, min: Option<f64>, max: Option<f64>) -> Self {
+        let passes = min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max);
+        Self {
+            name,
+            passes,
+            value,
+            min,
+            max,
+        }
+    }
+}
+
+/// Outcome of an ACX check
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcxReport {
+    /// Whether every criterion passes
+    pub passes: bool,
+    pub criteria: Vec<Criterion>,
+    pub rms_db: f64,
+    pub peak_db: f64,
+    pub noise_floor_db: f64,
+    pub head_room_tone_seconds: f64,
+    pub tail_room_tone_seconds: f64,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_secs: f64,
+}
+
+/// Running levels of a chapter, fed a block of audio at a time
+pub struct AcxMeter {
+    channels: usize,
+    sample_rate: u32,
+    block_frames: usize,
+    /// Mean square of each finished block
+    blocks: Vec<f64>,
+    /// Sum of squares and frames of the block being filled
+    partial: (f64, usize),
+    sum_squares: f64,
+    frames: usize,
+    peak: f32,
+}
+
+impl AcxMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            channels,
+            sample_rate,
+            block_frames: ((BLOCK_SECONDS * sample_rate as f64) as usize).max(1),
+            blocks: Vec::new(),
+            partial: (0.0, 0),
+            sum_squares: 0.0,
+            frames: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Add the next block of the chapter
+    pub fn add(&mut self, buffer: &AudioBuffer) {
+        let channels = self.channels.max(1) as f64;
+        for i in 0..buffer.frame_count() {
+            let mut square = 0.0;
+            for channel in &buffer.samples {
+                let sample = channel[i];
+                self.peak = self.peak.max(sample.abs());
+                square += (sample as f64).powi(2);
+            }
+            self.sum_squares += square;
+            self.partial.0 += square / channels;
+            self.partial.1 += 1;
+            if self.partial.1 == self.block_frames {
+                self.blocks.push(self.partial.0 / self.block_frames as f64);
+                self.partial = (0.0, 0);
+            }
+        }
+        self.frames += buffer.frame_count();
+    }
+
+    /// Evaluate everything added against the ACX requirements
+    pub fn finish(self) -> AcxReport {
+        let samples = (self.frames * self.channels.max(1)) as f64;
+        let rms_db = if self.frames == 0 {
+            FLOOR_DB
+        } else {
+            power_db(self.sum_squares / samples)
+        };
+        let peak_db = if self.peak > 0.0 {
+            (20.0 * (self.peak as f64).log10()).max(FLOOR_DB)
+        } else {
+            FLOOR_DB
+        };
+
+        // Quietest half second; shorter chapters are measured whole
+        let window = NOISE_FLOOR_BLOCKS.min(self.blocks.len()).max(1);
+        let noise_floor_db = self
+            .blocks
+            .windows(window)
+            .map(|w| power_db(w.iter().sum::<f64>() / w.len() as f64))
+            .min_by(f64::total_cmp)
+            .unwrap_or(rms_db);
+
+        let block_seconds = self.block_frames as f64 / self.sample_rate as f64;
+        let is_tone = |&power: &f64| power_db(power) <= ROOM_TONE_MAX_DB;
+        let head_blocks = self.blocks.iter().take_while(|p| is_tone(p)).count();
+        let tail_blocks = self.blocks.iter().rev().take_while(|p| is_tone(p)).count();
+        // The unfinished last block is part of the tail
+        let partial_seconds = self.partial.1 as f64 / self.sample_rate as f64;
+        let head_room_tone_seconds = head_blocks as f64 * block_seconds;
+        let tail_room_tone_seconds = tail_blocks as f64 * block_seconds + partial_seconds;
+
+        let criteria = vec![
+            Criterion::new("rms", rms_db, Some(RMS_RANGE_DB.0), Some(RMS_RANGE_DB.1)),
+            Criterion::new("peak", peak_db, None, Some(MAX_PEAK_DB)),
+            Criterion::new(
+                "noise-floor",
+                noise_floor_db,
+                None,
+                Some(MAX_NOISE_FLOOR_DB),
+            ),
+            Criterion::new(
+                "head-room-tone",
+                head_room_tone_seconds,
+                Some(HEAD_ROOM_TONE_SECONDS.0),
+                Some(HEAD_ROOM_TONE_SECONDS.1),
+            ),
+            Criterion::new(
+                "tail-room-tone",
+                tail_room_tone_seconds,
+                Some(TAIL_ROOM_TONE_SECONDS.0),
+                Some(TAIL_ROOM_TONE_SECONDS.1),
+            ),
+        ];
+
+        AcxReport {
+            passes: criteria.iter().all(|c| c.passes),
+            criteria,
+            rms_db,
+            peak_db,
+            noise_floor_db,
+            head_room_tone_seconds,
+            tail_room_tone_seconds,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            duration_secs: self.frames as f64 / self.sample_rate as f64,
+        }
+    }
+}
+
+/// Check an audio file against the ACX requirements as it is decoded
+#[tracing::instrument(name = "dsp.acx_check", skip_all)]
+pub fn check_file(path: &Path) -> Result<AcxReport> {
+    let mut decoder = AudioDecoder::open(path)?;
+    let mut meter = AcxMeter::new(decoder.channels, decoder.sample_rate);
+    let mut block = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    while decoder.decode_next(&mut block)? {
+        meter.add(&block);
+        for channel in &mut block.samples {
+            channel.clear();
+        }
+    }
+    Ok(meter.finish())
+}
+
+fn power_db(mean_square: f64) -> f64 {
+    if mean_square > 0.0 {
+        (10.0 * mean_square.log10()).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chapter_checked_per_criterion() {
+        // 0.7 s of room tone at -65 dB RMS, 10 s of narration at -20 dB RMS
+        // and 2 s of room tone, fed in uneven blocks
+        let rate = 44100;
+        let mut x: u32 = 5;
+        let mut tone = move || {
+            x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+            ((x >> 8) as f32 / 16777216.0 - 0.5) * 2.0 * 9.7e-4
+        };
+        let narration =
+            |i: usize| 0.1414 * (2.0 * std::f32::consts::PI * 200.0 * i as f32 / rate as f32).sin();
+        let chapter: Vec<f32> = (0..rate * 127 / 10)
+            .map(|i| match i {
+                i if i < rate * 7 / 10 => tone(),
+                i if i < rate * 107 / 10 => narration(i),
+                _ => tone(),
+            })
+            .collect();
+
+        let check = |samples: &[f32]| {
+            let mut meter = AcxMeter::new(1, rate as u32);
+            for chunk in samples.chunks(10_000) {
+                let mut block = AudioBuffer::new(1, rate as u32);
+                block.samples[0] = chunk.to_vec();
+                meter.add(&block);
+            }
+            meter.finish()
+        };
+
+        let report = check(&chapter);
+        assert!(report.passes, "{:?}", report);
+        assert!((report.noise_floor_db + 65.0).abs() < 1.0);
+        assert!((report.head_room_tone_seconds - 0.7).abs() <= BLOCK_SECONDS);
+        assert!((report.tail_room_tone_seconds - 2.0).abs() <= BLOCK_SECONDS);
+
+        // Cut the head: narration from the first sample fails only that
+        let report = check(&chapter[rate * 7 / 10..]);
+        let failed: Vec<&str> = report
+            .criteria
+            .iter()
+            .filter(|c| !c.passes)
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(failed, ["head-room-tone"]);
+    }
+}
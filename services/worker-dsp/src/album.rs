@@ -0,0 +1,354 @@
+//! Album planning: common loudness target and consistent tonality across tracks
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::config;
+use crate::types::AudioBuffer;
+use budi_dsp_core::mastering::{self, MasteringResult, ReferenceMatch};
+
+/// Fraction of the deviation from the album's average tonal balance that is
+/// corrected per track; full correction would flatten intentional differences
+const TONAL_CORRECTION_STRENGTH: f64 = 0.5;
+
+/// Maximum per-band tonal correction applied to a track (dB)
+const MAX_TONAL_CORRECTION_DB: f64 = 3.0;
+
+/// Quietest a track may end up relative to the loudest when relative
+/// loudness is preserved (LU)
+const MAX_RELATIVE_OFFSET_LU: f64 = -6.0;
+
+/// Loudness and tonal balance measured for one album track
+#[derive(Debug, Clone)]
+pub struct TrackProfile {
+    pub integrated_lufs: f64,
+    pub band_levels: Option<Vec<f64>>,
+}
+
+/// Measure the properties album planning needs for a track
+pub fn profile_track(buffer: &AudioBuffer) -> Result<TrackProfile> {
+    Ok(TrackProfile {
+        integrated_lufs: mastering::calculate_loudness(buffer)?,
+        band_levels: mastering::tonal_balance(buffer)?,
+    })
+}
+
+/// Derive per-track mastering settings for the album
+///
+/// With `normalize_loudness` every track is mastered to the album target;
+/// otherwise the loudest track hits the target and the others keep their
+/// relative level, so intentionally quiet tracks stay quiet. Each track's EQ
+/// is nudged towards the album's average tonal balance.
+pub fn plan_album(
+    tracks: &[TrackProfile],
+    target_lufs: f64,
+    normalize_loudness: bool,
+) -> Vec<ReferenceMatch> {
+    let loudest = tracks
+        .iter()
+        .map(|t| t.integrated_lufs)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let average_lufs = if tracks.is_empty() {
+        target_lufs
+    } else {
+        tracks.iter().map(|t| t.integrated_lufs).sum::<f64>() / tracks.len() as f64
+    };
+    let album_bands = average_band_levels(tracks.iter().filter_map(|t| t.band_levels.as_ref()));
+
+    tracks
+        .iter()
+        .map(|track| {
+            let track_target = if normalize_loudness {
+                target_lufs
+            } else {
+                target_lufs + (track.integrated_lufs - loudest).max(MAX_RELATIVE_OFFSET_LU)
+            };
+
+            let correction = |band: usize| match (&album_bands, &track.band_levels) {
+                (Some(album), Some(levels)) => ((album[band] - levels[band])
+                    * TONAL_CORRECTION_STRENGTH)
+                    .clamp(-MAX_TONAL_CORRECTION_DB, MAX_TONAL_CORRECTION_DB),
+                _ => 0.0,
+            };
+
+            ReferenceMatch {
+                reference_lufs: average_lufs,
+                target_lufs: track_target,
+                low_gain_db: correction(0),
+                mid_gain_db: correction(1),
+                high_gain_db: correction(2),
+            }
+        })
+        .collect()
+}
+
+/// Average of the tonal balances that could be measured
+fn average_band_levels<'a>(levels: impl IntoIterator<Item = &'a Vec<f64>>) -> Option<Vec<f64>> {
+    let measured: Vec<&Vec<f64>> = levels.into_iter().collect();
+    let first = measured.first()?;
+
+    let mut average = vec![0.0; first.len()];
+    for levels in &measured {
+        for (avg, level) in average.iter_mut().zip(levels.iter()) {
+            *avg += level / measured.len() as f64;
+        }
+    }
+    Some(average)
+}
+
+/// Continuous album render built up track by track
+///
+/// Used to verify gapless playback: each track is appended after the
+/// previous one's gap, or overlapped with an equal-power crossfade.
+#[derive(Default)]
+pub struct AlbumRender {
+    buffer: Option<AudioBuffer>,
+    track_offsets: Vec<f64>,
+}
+
+impl AlbumRender {
+    /// Append a mastered track
+    ///
+    /// `gap_secs` and `crossfade_secs` describe the transition from the
+    /// previous track; they are ignored for the first one. A crossfade is
+    /// limited to the shorter of the two tracks.
+    pub fn append(
+        &mut self,
+        track: &AudioBuffer,
+        gap_secs: f64,
+        crossfade_secs: f64,
+    ) -> Result<()> {
+        let Some(album) = self.buffer.as_mut() else {
+            self.buffer = Some(track.clone());
+            self.track_offsets.push(0.0);
+            return Ok(());
+        };
+
+        if album.sample_rate != track.sample_rate || album.channels != track.channels {
+            bail!(
+                "Continuous render needs matching formats: album is {} Hz/{} ch, track is {} Hz/{} ch",
+                album.sample_rate,
+                album.channels,
+                track.sample_rate,
+                track.channels
+            );
+        }
+
+        let rate = album.sample_rate as f64;
+        let album_len = album.frame_count();
+        let overlap = ((crossfade_secs.max(0.0) * rate) as usize)
+            .min(album_len)
+            .min(track.frame_count());
+
+        let start = if overlap > 0 {
+            let start = album_len - overlap;
+            for (dst, src) in album.samples.iter_mut().zip(&track.samples) {
+                for i in 0..overlap {
+                    let t = (i as f32 + 0.5) / overlap as f32;
+                    let angle = t * std::f32::consts::FRAC_PI_2;
+                    dst[start + i] = dst[start + i] * angle.cos() + src[i] * angle.sin();
+                }
+                dst.extend_from_slice(&src[overlap..]);
+            }
+            start
+        } else {
+            let gap = (gap_secs.max(0.0) * rate) as usize;
+            for (dst, src) in album.samples.iter_mut().zip(&track.samples) {
+                dst.resize(album_len + gap, 0.0);
+                dst.extend_from_slice(src);
+            }
+            album_len + gap
+        };
+
+        self.track_offsets.push(start as f64 / rate);
+        Ok(())
+    }
+
+    /// Start time of each track in the render (seconds)
+    pub fn track_offsets(&self) -> &[f64] {
+        &self.track_offsets
+    }
+
+    pub fn buffer(&self) -> Option<&AudioBuffer> {
+        self.buffer.as_ref()
+    }
+}
+
+/// Album-level statistics over the mastered tracks
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumStats {
+    pub track_count: usize,
+    pub target_lufs: f64,
+    pub average_lufs: f64,
+    /// Difference between the loudest and quietest master (LU)
+    pub loudness_spread: f64,
+    pub max_true_peak: f64,
+    pub all_pass_qc: bool,
+}
+
+/// Summarize the mastered tracks of an album
+pub fn album_stats(results: &[MasteringResult], target_lufs: f64) -> AlbumStats {
+    let lufs: Vec<f64> = results.iter().map(|r| r.final_lufs).collect();
+    let max_lufs = lufs.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let min_lufs = lufs.iter().cloned().fold(f64::INFINITY, f64::min);
+
+    AlbumStats {
+        track_count: results.len(),
+        target_lufs,
+        average_lufs: if lufs.is_empty() {
+            0.0
+        } else {
+            lufs.iter().sum::<f64>() / lufs.len() as f64
+        },
+        loudness_spread: if lufs.is_empty() {
+            0.0
+        } else {
+            max_lufs - min_lufs
+        },
+        max_true_peak: results
+            .iter()
+            .map(|r| r.final_true_peak)
+            .fold(f64::NEG_INFINITY, f64::max),
+        all_pass_qc: results.iter().all(|r| r.passes_qc),
+    }
+}
+
+/// Consistency of one mastered track against the rest of the album
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackConsistency {
+    pub track_id: String,
+    pub final_lufs: f64,
+    /// Loudness change from the previous track (LU)
+    pub delta_from_previous_lu: Option<f64>,
+    pub delta_from_average_lu: f64,
+    /// Per-band deviation from the album's average tonal balance (dB)
+    pub tonal_deviation_db: Option<Vec<f64>>,
+    pub true_peak: f64,
+    pub true_peak_passes: bool,
+}
+
+/// Album QC report: track-to-track consistency plus album statistics
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsistencyReport {
+    pub album: AlbumStats,
+    pub true_peak_max: f64,
+    /// Largest loudness jump between adjacent tracks (LU)
+    pub max_track_delta_lu: f64,
+    /// Largest single-band deviation from the album tonal balance (dB)
+    pub max_tonal_deviation_db: f64,
+    pub tracks: Vec<TrackConsistency>,
+}
+
+/// Build the album consistency report from the mastered tracks
+///
+/// `tonal_balance` holds each master's band levels as measured by
+/// `mastering::tonal_balance`, in the same order as `results`.
+pub fn consistency_report(
+    track_ids: &[String],
+    results: &[MasteringResult],
+    tonal_balance: &[Option<Vec<f64>>],
+    stats: AlbumStats,
+) -> ConsistencyReport {
+    let album_bands = average_band_levels(tonal_balance.iter().flatten());
+    let true_peak_max = config::get().qc.true_peak_max;
+
+    let tracks: Vec<TrackConsistency> = results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            let tonal_deviation_db =
+                match (&album_bands, tonal_balance.get(i).and_then(Option::as_ref)) {
+                    (Some(album), Some(levels)) => {
+                        Some(levels.iter().zip(album).map(|(l, a)| l - a).collect())
+                    }
+                    _ => None,
+                };
+
+            TrackConsistency {
+                track_id: track_ids.get(i).cloned().unwrap_or_default(),
+                final_lufs: result.final_lufs,
+                delta_from_previous_lu: i
+                    .checked_sub(1)
+                    .map(|prev| result.final_lufs - results[prev].final_lufs),
+                delta_from_average_lu: result.final_lufs - stats.average_lufs,
+                tonal_deviation_db,
+                true_peak: result.final_true_peak,
+                true_peak_passes: result.final_true_peak <= true_peak_max,
+            }
+        })
+        .collect();
+
+    let max_track_delta_lu = tracks
+        .iter()
+        .filter_map(|t| t.delta_from_previous_lu)
+        .map(f64::abs)
+        .fold(0.0, f64::max);
+    let max_tonal_deviation_db = tracks
+        .iter()
+        .filter_map(|t| t.tonal_deviation_db.as_ref())
+        .flatten()
+        .map(|d| d.abs())
+        .fold(0.0, f64::max);
+
+    ConsistencyReport {
+        album: stats,
+        true_peak_max,
+        max_track_delta_lu,
+        max_tonal_deviation_db,
+        tracks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_album_loudness_and_tonality() {
+        let tracks = vec![
+            TrackProfile {
+                integrated_lufs: -12.0,
+                band_levels: Some(vec![-3.0, -6.0, -12.0]),
+            },
+            TrackProfile {
+                integrated_lufs: -20.0,
+                band_levels: Some(vec![-5.0, -6.0, -8.0]),
+            },
+        ];
+
+        let normalized = plan_album(&tracks, -14.0, true);
+        assert!(normalized.iter().all(|m| m.target_lufs == -14.0));
+
+        // Relative level is kept, but capped for very quiet tracks
+        let relative = plan_album(&tracks, -14.0, false);
+        assert_eq!(relative[0].target_lufs, -14.0);
+        assert_eq!(relative[1].target_lufs, -20.0);
+
+        // Corrections pull both tracks towards the album average
+        assert!(relative[0].low_gain_db < 0.0 && relative[1].low_gain_db > 0.0);
+        assert_eq!(relative[0].mid_gain_db, 0.0);
+        assert!(relative[0].high_gain_db > 0.0 && relative[1].high_gain_db < 0.0);
+    }
+
+    #[test]
+    fn test_album_render_gap_and_crossfade() {
+        let track = |value: f32| AudioBuffer {
+            samples: vec![vec![value; 1000]],
+            sample_rate: 1000,
+            channels: 1,
+        };
+
+        let mut render = AlbumRender::default();
+        render.append(&track(0.5), 0.0, 0.0).unwrap();
+        render.append(&track(0.5), 0.5, 0.0).unwrap();
+        render.append(&track(0.5), 0.0, 0.25).unwrap();
+
+        assert_eq!(render.track_offsets(), &[0.0, 1.5, 2.25]);
+        let buffer = render.buffer().unwrap();
+        assert_eq!(buffer.frame_count(), 3250);
+        assert_eq!(buffer.samples[0][1200], 0.0);
+    }
+}
@@ -1,119 +1,90 @@
-//! Audio file reading and writing using Symphonia and Hound
+//! Audio file writing using Hound, LAME and flacenc, and analysis of files
+//! as they are decoded
+//!
+//! Decoding is shared with the codec worker through `budi_worker_core::audio`.
 
 use anyhow::{Context, Result};
+use budi_dsp_core::analysis::Analyzer;
+use budi_worker_core::audio::AudioDecoder;
 use hound::{SampleFormat, WavSpec, WavWriter};
 use std::fs::File;
+use std::future::Future;
 use std::path::Path;
-use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
-
-use crate::types::AudioBuffer;
-
-/// Read an audio file and return the decoded samples
-pub fn read_audio_file(path: &Path) -> Result<AudioBuffer> {
-    let file = File::open(path).context("Failed to open audio file")?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-
-    // Create a hint for the file type
-    let mut hint = Hint::new();
-    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-        hint.with_extension(ext);
-    }
-
-    // Probe the file
-    let format_opts = FormatOptions::default();
-    let metadata_opts = MetadataOptions::default();
-    let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &format_opts, &metadata_opts)
-        .context("Failed to probe audio format")?;
-
-    let mut format = probed.format;
-
-    // Find the first audio track
-    let track = format
-        .tracks()
-        .iter()
-        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-        .context("No audio track found")?;
-
-    let track_id = track.id;
-    let codec_params = track.codec_params.clone();
-
-    let sample_rate = codec_params.sample_rate.unwrap_or(44100);
-    let channels = codec_params.channels.map(|c| c.count()).unwrap_or(2);
-
-    // Create decoder
-    let decoder_opts = DecoderOptions::default();
-    let mut decoder = symphonia::default::get_codecs()
-        .make(&codec_params, &decoder_opts)
-        .context("Failed to create decoder")?;
-
-    let mut audio_buffer = AudioBuffer::new(channels, sample_rate);
-
-    // Decode all packets
-    loop {
-        let packet = match format.next_packet() {
-            Ok(p) => p,
-            Err(symphonia::core::errors::Error::IoError(e))
-                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
-            {
-                break;
-            }
-            Err(e) => return Err(e.into()),
-        };
 
-        if packet.track_id() != track_id {
-            continue;
+pub use budi_worker_core::audio::read_audio_cached;
+
+use crate::types::{AnalysisResult, AudioBuffer, Dither, PartialAnalysis};
+
+/// Analyze an audio file as it is decoded, without holding it in memory
+///
+/// Such files never go through `read_audio_file` and so aren't held to
+/// `max_decoded_mb`.
+#[tracing::instrument(name = "dsp.analyze_file", skip_all)]
+pub fn analyze_file(path: &Path, bit_depth: u32) -> Result<AnalysisResult> {
+    let mut decoder = AudioDecoder::open(path)?;
+    let mut analyzer = Analyzer::new(decoder.channels, decoder.sample_rate)?;
+    let mut block = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    while decoder.decode_next(&mut block)? {
+        analyzer.add(&block)?;
+        for channel in &mut block.samples {
+            channel.clear();
         }
-
-        let decoded = decoder.decode(&packet)?;
-        append_samples(&mut audio_buffer, decoded)?;
     }
-
-    Ok(audio_buffer)
+    analyzer.finalize(bit_depth)
 }
 
-/// Append decoded samples to the audio buffer
-fn append_samples(buffer: &mut AudioBuffer, decoded: AudioBufferRef) -> Result<()> {
-    match decoded {
-        AudioBufferRef::F32(buf) => {
-            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
-                let plane = buf.chan(ch);
-                buffer.samples[ch].extend_from_slice(plane);
-            }
+/// Like `analyze_file`, handing `report` the metrics so far after every
+/// `interval_secs` of audio, with the fraction decoded when the file
+/// declares its length
+pub async fn analyze_file_reporting<F, Fut>(
+    path: &Path,
+    bit_depth: u32,
+    interval_secs: f64,
+    mut report: F,
+) -> Result<AnalysisResult>
+where
+    F: FnMut(PartialAnalysis, Option<f64>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut decoder = AudioDecoder::open(path)?;
+    let mut analyzer = Analyzer::new(decoder.channels, decoder.sample_rate)?;
+    let mut block = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    let interval = ((interval_secs * decoder.sample_rate as f64) as u64).max(1);
+    let (mut frames, mut next_report) = (0, interval);
+    while decoder.decode_next(&mut block)? {
+        frames += block.frame_count() as u64;
+        analyzer.add(&block)?;
+        for channel in &mut block.samples {
+            channel.clear();
         }
-        AudioBufferRef::S16(buf) => {
-            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
-                let plane = buf.chan(ch);
-                buffer.samples[ch].extend(plane.iter().map(|&s| s as f32 / 32768.0));
-            }
-        }
-        AudioBufferRef::S32(buf) => {
-            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
-                let plane = buf.chan(ch);
-                buffer.samples[ch].extend(plane.iter().map(|&s| s as f32 / 2147483648.0));
-            }
-        }
-        AudioBufferRef::U8(buf) => {
-            for ch in 0..buffer.channels.min(buf.spec().channels.count()) {
-                let plane = buf.chan(ch);
-                buffer.samples[ch].extend(plane.iter().map(|&s| (s as f32 - 128.0) / 128.0));
-            }
-        }
-        _ => {
-            // Handle other formats by converting to f32
-            anyhow::bail!("Unsupported audio format");
+        if frames >= next_report {
+            next_report = frames + interval;
+            let decoded = decoder
+                .n_frames
+                .filter(|&n| n > 0)
+                .map(|n| (frames as f64 / n as f64).min(1.0));
+            report(analyzer.snapshot(), decoded).await?;
         }
     }
-    Ok(())
+    analyzer.finalize(bit_depth)
 }
 
 /// Write audio buffer to a WAV file
 pub fn write_wav_file(buffer: &AudioBuffer, path: &Path, bit_depth: u16) -> Result<()> {
+    write_wav_file_dithered(buffer, path, bit_depth, Dither::None)
+}
+
+/// Write audio buffer to a WAV file, dithering when reducing to 16-bit
+///
+/// Dither only applies to 16-bit output; at 24 bits and above the
+/// quantization error is already below the noise floor of any real playback
+/// chain.
+pub fn write_wav_file_dithered(
+    buffer: &AudioBuffer,
+    path: &Path,
+    bit_depth: u16,
+    dither: Dither,
+) -> Result<()> {
     let spec = WavSpec {
         channels: buffer.channels as u16,
         sample_rate: buffer.sample_rate,
@@ -126,11 +97,14 @@ pub fn write_wav_file(buffer: &AudioBuffer, path: &Path, bit_depth: u16) -> Resu
     let frame_count = buffer.frame_count();
     match bit_depth {
         16 => {
-            for i in 0..frame_count {
-                for ch in 0..buffer.channels {
-                    let sample = buffer.samples[ch][i];
-                    let sample_i16 = (sample.clamp(-1.0, 1.0) * 32767.0) as i16;
-                    writer.write_sample(sample_i16)?;
+            let mut quantizers = DitherQuantizer::per_channel(dither, buffer.channels);
+            let mut block = Vec::with_capacity(QUANTIZE_BLOCK_FRAMES * buffer.channels);
+            for start in (0..frame_count).step_by(QUANTIZE_BLOCK_FRAMES) {
+                let end = (start + QUANTIZE_BLOCK_FRAMES).min(frame_count);
+                block.clear();
+                quantize_frames(&mut quantizers, buffer, start..end, &mut block);
+                for &sample in &block {
+                    writer.write_sample(sample)?;
                 }
             }
         }
@@ -159,11 +133,108 @@ pub fn write_wav_file(buffer: &AudioBuffer, path: &Path, bit_depth: u16) -> Resu
     Ok(())
 }
 
-/// Write audio buffer to MP3 file
-pub fn write_mp3_file(buffer: &AudioBuffer, path: &Path, _bitrate: u32) -> Result<()> {
-    use mp3lame_encoder::{Builder, FlushNoGap, InterleavedPcm};
+/// Frames quantized at a time when writing a 16-bit WAV
+const QUANTIZE_BLOCK_FRAMES: usize = 4096;
+
+/// Quantize a buffer to interleaved 16-bit PCM, dithering unless `Dither::None`
+pub fn quantize_16(buffer: &AudioBuffer, dither: Dither) -> Vec<i16> {
+    let frame_count = buffer.frame_count();
+    let mut pcm = Vec::with_capacity(frame_count * buffer.channels);
+    let mut quantizers = DitherQuantizer::per_channel(dither, buffer.channels);
+    quantize_frames(&mut quantizers, buffer, 0..frame_count, &mut pcm);
+    pcm
+}
+
+/// Quantize `frames` of a buffer, appending them to `pcm` interleaved
+///
+/// The quantizers carry their dither and noise-shaping state from one call
+/// to the next, so a buffer can be quantized a block at a time.
+fn quantize_frames(
+    quantizers: &mut [DitherQuantizer],
+    buffer: &AudioBuffer,
+    frames: std::ops::Range<usize>,
+    pcm: &mut Vec<i16>,
+) {
+    for i in frames {
+        for (ch, quantizer) in quantizers.iter_mut().enumerate() {
+            let sample = buffer.samples[ch][i];
+            pcm.push(quantizer.quantize(sample.clamp(-1.0, 1.0) * 32767.0));
+        }
+    }
+}
+
+/// Per-channel 16-bit quantizer with TPDF dither and optional noise shaping
+struct DitherQuantizer {
+    dither: Dither,
+    rng_state: u32,
+    error: f32,
+}
+
+impl DitherQuantizer {
+    fn new(dither: Dither, seed: u32) -> Self {
+        Self {
+            dither,
+            rng_state: seed.max(1),
+            error: 0.0,
+        }
+    }
+
+    /// One quantizer per channel, each with its own noise
+    fn per_channel(dither: Dither, channels: usize) -> Vec<Self> {
+        (0..channels)
+            .map(|ch| Self::new(dither, 0x9E37_79B9 ^ ch as u32))
+            .collect()
+    }
+
+    /// Uniform random value in [-0.5, 0.5) LSB (xorshift32)
+    fn next_uniform(&mut self) -> f32 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        (x as f32 / u32::MAX as f32) - 0.5
+    }
+
+    /// Quantize a sample already scaled to the 16-bit range
+    fn quantize(&mut self, scaled: f32) -> i16 {
+        if self.dither == Dither::None {
+            return scaled as i16;
+        }
+
+        // Triangular PDF dither: sum of two independent uniform variables
+        let tpdf = self.next_uniform() + self.next_uniform();
+
+        // First-order error feedback pushes the requantization noise
+        // towards high frequencies where hearing is least sensitive
+        let target = match self.dither {
+            Dither::NoiseShaped => scaled - self.error,
+            _ => scaled,
+        };
+
+        let quantized = (target + tpdf).round().clamp(-32768.0, 32767.0);
+        self.error = quantized - target;
+        quantized as i16
+    }
+}
+
+/// Write audio buffer to a constant-bitrate MP3 file (`bitrate` in kbps)
+pub fn write_mp3_file(buffer: &AudioBuffer, path: &Path, bitrate: u32) -> Result<()> {
+    use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm};
     use std::io::Write;
 
+    let bitrate = match bitrate {
+        48 => Bitrate::Kbps48,
+        64 => Bitrate::Kbps64,
+        96 => Bitrate::Kbps96,
+        128 => Bitrate::Kbps128,
+        160 => Bitrate::Kbps160,
+        192 => Bitrate::Kbps192,
+        256 => Bitrate::Kbps256,
+        320 => Bitrate::Kbps320,
+        other => anyhow::bail!("Unsupported MP3 bitrate: {} kbps", other),
+    };
+
     let mut mp3_encoder =
         Builder::new().ok_or_else(|| anyhow::anyhow!("Failed to create MP3 encoder"))?;
     mp3_encoder
@@ -173,7 +244,7 @@ pub fn write_mp3_file(buffer: &AudioBuffer, path: &Path, _bitrate: u32) -> Resul
         .set_sample_rate(buffer.sample_rate)
         .map_err(|e| anyhow::anyhow!("Failed to set sample rate: {:?}", e))?;
     mp3_encoder
-        .set_brate(mp3lame_encoder::Bitrate::Kbps320)
+        .set_brate(bitrate)
         .map_err(|e| anyhow::anyhow!("Failed to set bitrate: {:?}", e))?;
     mp3_encoder
         .set_quality(mp3lame_encoder::Quality::Best)
@@ -224,59 +295,242 @@ pub fn write_mp3_file(buffer: &AudioBuffer, path: &Path, _bitrate: u32) -> Resul
     Ok(())
 }
 
-/// Read WAV file using hound (for simpler cases)
-#[allow(dead_code)]
-pub fn read_wav_file(path: &Path) -> Result<AudioBuffer> {
-    let reader = hound::WavReader::open(path).context("Failed to open WAV file")?;
-    let spec = reader.spec();
+/// Highest FLAC compression level accepted, matching the reference encoder
+const FLAC_MAX_COMPRESSION_LEVEL: u8 = 8;
+
+/// Write audio buffer to a 24-bit FLAC file
+///
+/// `compression_level` follows the reference encoder's 0-8 scale: higher
+/// levels use longer blocks and higher-order prediction, trading encode time
+/// for size. Output is lossless at every level.
+pub fn write_flac_file(buffer: &AudioBuffer, path: &Path, compression_level: u8) -> Result<()> {
+    use flacenc::component::BitRepr;
+    use flacenc::error::Verify;
+
+    let level = compression_level.min(FLAC_MAX_COMPRESSION_LEVEL);
+    let mut config = flacenc::config::Encoder::default();
+    config.block_size = if level <= 2 { 1152 } else { 4096 };
+    config.stereo_coding.use_leftside = level >= 1;
+    config.stereo_coding.use_rightside = level >= 1;
+    config.stereo_coding.use_midside = level >= 1;
+    config.subframe_coding.use_lpc = level >= 3;
+    config.subframe_coding.qlpc.lpc_order = if level >= 6 { 12 } else { 8 };
+    config.subframe_coding.prc.max_parameter = if level >= 6 { 14 } else { 6 };
+    let config = config
+        .into_verified()
+        .map_err(|(_, e)| anyhow::anyhow!("Invalid FLAC encoder config: {:?}", e))?;
+
+    let frame_count = buffer.frame_count();
+    let mut interleaved = Vec::with_capacity(frame_count * buffer.channels);
+    for i in 0..frame_count {
+        for ch in 0..buffer.channels {
+            interleaved.push((buffer.samples[ch][i].clamp(-1.0, 1.0) * 8388607.0) as i32);
+        }
+    }
 
-    let channels = spec.channels as usize;
-    let sample_rate = spec.sample_rate;
+    let source = flacenc::source::MemSource::from_samples(
+        &interleaved,
+        buffer.channels,
+        24,
+        buffer.sample_rate as usize,
+    );
+    let mut stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| anyhow::anyhow!("Failed to encode FLAC: {:?}", e))?;
+
+    // The encoder counts the short final block towards the minimum block
+    // size, which makes decoders treat the fixed-size stream as variable.
+    // The spec excludes the last block, so both bounds are the block size.
+    stream
+        .stream_info_mut()
+        .set_block_sizes(config.block_size, config.block_size)
+        .map_err(|e| anyhow::anyhow!("Invalid FLAC block size: {:?}", e))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| anyhow::anyhow!("Failed to write FLAC stream: {:?}", e))?;
+    std::fs::write(path, sink.as_slice()).context("Failed to create FLAC file")?;
 
-    let mut buffer = AudioBuffer::new(channels, sample_rate);
+    Ok(())
+}
 
-    match (spec.sample_format, spec.bits_per_sample) {
-        (SampleFormat::Int, 16) => {
-            let samples: Vec<i16> = reader
-                .into_samples::<i16>()
-                .filter_map(|s| s.ok())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use budi_dsp_core::analysis::analyze_audio;
+    use budi_worker_core::audio::{read_audio_file, write_wav_f32};
+
+    #[test]
+    fn test_flac_round_trip() {
+        let mut buffer = AudioBuffer::new(2, 44100);
+        for ch in 0..2 {
+            buffer.samples[ch] = (0..10000)
+                .map(|i| (i as f32 * 0.01 * (ch + 1) as f32).sin() * 0.5)
                 .collect();
-            for (i, sample) in samples.iter().enumerate() {
-                let ch = i % channels;
-                buffer.samples[ch].push(*sample as f32 / 32768.0);
-            }
         }
-        (SampleFormat::Int, 24) | (SampleFormat::Int, 32) => {
-            let samples: Vec<i32> = reader
-                .into_samples::<i32>()
-                .filter_map(|s| s.ok())
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.flac");
+        write_flac_file(&buffer, &path, 5).unwrap();
+        let decoded = read_audio_file(&path).unwrap();
+
+        assert_eq!(decoded.channels, 2);
+        assert_eq!(decoded.frame_count(), buffer.frame_count());
+        for (out, inp) in decoded.samples[1].iter().zip(&buffer.samples[1]) {
+            assert!((out - inp).abs() < 1e-6);
+        }
+    }
+
+    /// `frames` of a 1 kHz tone at `db` dBFS
+    fn tone(db: f32, frames: usize) -> AudioBuffer {
+        let amplitude = 10.0_f32.powf(db / 20.0);
+        let mut buffer = AudioBuffer::new(1, 44100);
+        buffer.samples[0] = (0..frames)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 44100.0).sin())
+            .collect();
+        buffer
+    }
+
+    #[test]
+    fn test_dither_keeps_signal_below_one_lsb() {
+        // A third of an LSB at 16 bits
+        let buffer = tone(-100.0, 44100);
+        assert!(quantize_16(&buffer, Dither::None).iter().all(|&s| s == 0));
+
+        for dither in [Dither::Tpdf, Dither::NoiseShaped] {
+            let pcm = quantize_16(&buffer, dither);
+            assert!(pcm.iter().any(|&s| s != 0), "{:?}", dither);
+            // The tone survives in the dithered output, rather than noise alone
+            let correlation: f64 = pcm
+                .iter()
+                .zip(&buffer.samples[0])
+                .map(|(&q, &x)| q as f64 * x as f64)
+                .sum();
+            assert!(correlation > 0.0, "{:?}", dither);
+        }
+
+        // Written a block at a time, the file holds the same samples
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dithered.wav");
+        write_wav_file_dithered(&buffer, &path, 16, Dither::NoiseShaped).unwrap();
+        let written: Vec<i16> = hound::WavReader::open(&path)
+            .unwrap()
+            .samples::<i16>()
+            .map(|s| s.unwrap())
+            .collect();
+        assert_eq!(written, quantize_16(&buffer, Dither::NoiseShaped));
+    }
+
+    #[test]
+    fn test_noise_shaping_pushes_error_up() {
+        use realfft::RealFftPlanner;
+
+        const N: usize = 8192;
+        let buffer = tone(-20.0, N);
+        // Average error power in the lowest and highest quarters of the band
+        let error_bands = |dither| {
+            let pcm = quantize_16(&buffer, dither);
+            let mut error: Vec<f64> = pcm
+                .iter()
+                .zip(&buffer.samples[0])
+                .map(|(&q, &x)| q as f64 - x as f64 * 32767.0)
                 .collect();
-            let max_val = if spec.bits_per_sample == 24 {
-                8388608.0
-            } else {
-                2147483648.0
+            let fft = RealFftPlanner::<f64>::new().plan_fft_forward(N);
+            let mut spectrum = fft.make_output_vec();
+            fft.process(&mut error, &mut spectrum).unwrap();
+            let power = |bins: &[realfft::num_complex::Complex<f64>]| {
+                bins.iter().map(|c| c.norm_sqr()).sum::<f64>() / bins.len() as f64
             };
-            for (i, sample) in samples.iter().enumerate() {
-                let ch = i % channels;
-                buffer.samples[ch].push(*sample as f32 / max_val);
-            }
-        }
-        (SampleFormat::Float, _) => {
-            let samples: Vec<f32> = reader
-                .into_samples::<f32>()
-                .filter_map(|s| s.ok())
+            let quarter = spectrum.len() / 4;
+            (
+                power(&spectrum[1..quarter]),
+                power(&spectrum[spectrum.len() - quarter..]),
+            )
+        };
+
+        let (low, high) = error_bands(Dither::NoiseShaped);
+        assert!(high > 4.0 * low, "low {} high {}", low, high);
+        // Plain TPDF leaves it flat
+        let (low, high) = error_bands(Dither::Tpdf);
+        assert!(
+            high < 2.0 * low && low < 2.0 * high,
+            "low {} high {}",
+            low,
+            high
+        );
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_buffer() {
+        let mut buffer = AudioBuffer::new(2, 48000);
+        for ch in 0..2 {
+            buffer.samples[ch] = (0..48000 * 5 + 123)
+                .map(|i| {
+                    let t = i as f32 / 48000.0;
+                    (2.0 * std::f32::consts::PI * 440.0 * t * (ch + 1) as f32).sin() * 0.5 + 0.01
+                })
                 .collect();
-            for (i, sample) in samples.iter().enumerate() {
-                let ch = i % channels;
-                buffer.samples[ch].push(*sample);
-            }
         }
-        _ => anyhow::bail!(
-            "Unsupported WAV format: {:?} {}bit",
-            spec.sample_format,
-            spec.bits_per_sample
-        ),
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("long.wav");
+        write_wav_f32(&buffer, &path).unwrap();
+
+        let whole = analyze_audio(&read_audio_file(&path).unwrap(), 24).unwrap();
+        let streamed = analyze_file(&path, 24).unwrap();
+
+        assert!((whole.integrated_lufs - streamed.integrated_lufs).abs() < 1e-6);
+        assert_eq!(whole.sample_peak, streamed.sample_peak);
+        assert_eq!(whole.true_peak, streamed.true_peak);
+        assert_eq!(whole.clipped_samples, streamed.clipped_samples);
+        assert!((whole.dc_offset_value.unwrap() - 0.01).abs() < 1e-3);
+        assert!((whole.dc_offset_value.unwrap() - streamed.dc_offset_value.unwrap()).abs() < 1e-9);
+        assert_eq!(whole.spectral_centroid, streamed.spectral_centroid);
+        assert_eq!(whole.spectral_rolloff, streamed.spectral_rolloff);
+        assert!(
+            (whole.stereo_correlation.unwrap() - streamed.stereo_correlation.unwrap()).abs() < 1e-9
+        );
+        assert_eq!(whole.duration_secs, streamed.duration_secs);
+
+        // Pushed in as interleaved chunks of any size
+        let mut analyzer = Analyzer::new(2, 48000).unwrap();
+        let interleaved: Vec<f32> = (0..buffer.frame_count())
+            .flat_map(|i| [buffer.samples[0][i], buffer.samples[1][i]])
+            .collect();
+        for chunk in interleaved.chunks(2 * 1001) {
+            analyzer.feed(chunk).unwrap();
+        }
+        assert!(analyzer.feed(&[0.0]).is_err());
+        let partial = analyzer.snapshot();
+        assert_eq!(partial.duration_secs, whole.duration_secs);
+        assert!((partial.integrated_lufs.unwrap() - whole.integrated_lufs).abs() < 1e-6);
+        let fed = analyzer.finalize(24).unwrap();
+        assert!((whole.integrated_lufs - fed.integrated_lufs).abs() < 1e-6);
+        assert_eq!(whole.true_peak, fed.true_peak);
     }
 
-    Ok(buffer)
+    #[tokio::test]
+    async fn test_analysis_reports_partial_metrics() {
+        let mut buffer = AudioBuffer::new(1, 8000);
+        buffer.samples[0] = (0..8000 * 10)
+            .map(|i| (i as f32 * 0.1).sin() * 0.25)
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+        write_wav_f32(&buffer, &path).unwrap();
+
+        let mut partials = Vec::new();
+        let result = analyze_file_reporting(&path, 24, 2.0, |partial, decoded| {
+            partials.push((partial, decoded));
+            async { Ok(()) }
+        })
+        .await
+        .unwrap();
+        assert!(partials.len() >= 4, "{} reports", partials.len());
+        assert!(partials
+            .windows(2)
+            .all(|w| w[0].0.duration_secs < w[1].0.duration_secs && w[0].1 < w[1].1));
+        let (last, decoded) = partials.last().unwrap();
+        assert!(decoded.unwrap() <= 1.0);
+        assert!((last.integrated_lufs.unwrap() - result.integrated_lufs).abs() < 0.5);
+    }
 }
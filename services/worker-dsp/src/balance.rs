@@ -0,0 +1,236 @@
+//! Vocal-to-backing balance over time
+//!
+//! "The vocal is buried in the second chorus" is among the commonest client
+//! notes. The vocal is estimated as the center of the stereo image in the
+//! vocal range (see `karaoke`) and the backing as everything else; the
+//! short-term loudness (3 s, BS.1770) of each is taken every second, and
+//! their difference is the balance. Runs of at least `MIN_SECTION_SECONDS`
+//! outside the allowed range are reported as buried or overpowering
+//! sections.
+//!
+//! Where the vocal estimate is far below the backing there is most likely
+//! no vocal at all (an intro, a solo), so those seconds count as
+//! instrumental rather than buried. As the estimate also holds anything
+//! else mixed dead center, the figures are a guide for an engineer's ears.
+
+use anyhow::Result;
+use ebur128::{EbuR128, Mode};
+use serde::Serialize;
+
+use crate::karaoke;
+use crate::types::AudioBuffer;
+
+/// Balance below which the vocal counts as buried, and above which it
+/// counts as overpowering, when a job doesn't say (dB)
+pub const DEFAULT_BURIED_DB: f64 = -8.0;
+pub const DEFAULT_OVERPOWERING_DB: f64 = 4.0;
+
+/// Balance below which no vocal is taken to be present (dB)
+const ABSENT_DB: f64 = -20.0;
+
+/// Vocal estimate level below which no vocal is present either (LUFS)
+const ABSENT_LUFS: f64 = -50.0;
+
+/// Interval between measurements (seconds); each covers the 3 s ending
+/// with it
+const HOP_SECONDS: f64 = 1.0;
+const WINDOW_SECONDS: f64 = 3.0;
+
+/// Shortest run of flagged measurements reported as a section (seconds)
+const MIN_SECTION_SECONDS: f64 = 4.0;
+
+/// Level reported for silence (LUFS)
+const FLOOR_LUFS: f64 = -70.0;
+
+/// How a section's vocal sits in the mix
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalanceIssue {
+    Buried,
+    Overpowering,
+}
+
+/// Balance at one point in the track
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancePoint {
+    /// Middle of the 3 s measured
+    pub time_seconds: f64,
+    pub vocal_lufs: f64,
+    pub backing_lufs: f64,
+    /// Vocal minus backing (dB); absent where no vocal is present
+    pub balance_db: Option<f64>,
+}
+
+/// A stretch where the vocal is out of balance
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceSection {
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+    pub issue: BalanceIssue,
+    /// Mean balance across it (dB)
+    pub balance_db: f64,
+}
+
+/// Outcome of a balance check
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VocalBalance {
+    /// Median balance where a vocal is present (dB); absent if it never is
+    pub median_balance_db: Option<f64>,
+    /// Share of the track with a vocal present (0-1)
+    pub vocal_share: f64,
+    pub buried_db: f64,
+    pub overpowering_db: f64,
+    pub sections: Vec<BalanceSection>,
+    pub points: Vec<BalancePoint>,
+}
+
+/// Check a job's thresholds
+pub fn validate(buried_db: f64, overpowering_db: f64) -> Result<()> {
+    anyhow::ensure!(
+        buried_db < overpowering_db,
+        "buriedDb must be below overpoweringDb"
+    );
+    anyhow::ensure!(
+        buried_db > ABSENT_DB,
+        "buriedDb must be above {} dB, where a vocal counts as absent",
+        ABSENT_DB
+    );
+    Ok(())
+}
+
+/// Measure the vocal-to-backing balance of stereo `buffer`
+#[tracing::instrument(name = "dsp.vocal_balance", skip_all)]
+pub fn measure(buffer: &AudioBuffer, buried_db: f64, overpowering_db: f64) -> Result<VocalBalance> {
+    validate(buried_db, overpowering_db)?;
+    let (backing, vocal, _) = karaoke::extract(buffer, 1.0)?;
+
+    let rate = buffer.sample_rate;
+    let hop = (HOP_SECONDS * rate as f64) as usize;
+    let warmup = (WINDOW_SECONDS / HOP_SECONDS) as usize;
+    let mut meters = [
+        EbuR128::new(2, rate, Mode::S)?,
+        EbuR128::new(2, rate, Mode::S)?,
+    ];
+    let mut interleaved = Vec::with_capacity(2 * hop);
+    let mut points = Vec::new();
+    for (i, start) in (0..buffer.frame_count()).step_by(hop).enumerate() {
+        let end = (start + hop).min(buffer.frame_count());
+        let mut levels = [FLOOR_LUFS; 2];
+        for ((meter, part), level) in meters.iter_mut().zip([&vocal, &backing]).zip(&mut levels) {
+            interleaved.clear();
+            for n in start..end {
+                interleaved.extend([part.samples[0][n], part.samples[1][n]]);
+            }
+            meter.add_frames_f32(&interleaved)?;
+            let lufs = meter.loudness_shortterm()?;
+            if lufs.is_finite() {
+                *level = lufs.max(FLOOR_LUFS);
+            }
+        }
+        // Only once a full window has gone by
+        if i + 1 < warmup || end - start < hop {
+            continue;
+        }
+        let [vocal_lufs, backing_lufs] = levels;
+        let balance = vocal_lufs - backing_lufs;
+        points.push(BalancePoint {
+            time_seconds: end as f64 / rate as f64 - WINDOW_SECONDS / 2.0,
+            vocal_lufs,
+            backing_lufs,
+            balance_db: (vocal_lufs > ABSENT_LUFS && balance > ABSENT_DB).then_some(balance),
+        });
+    }
+
+    let mut present: Vec<f64> = points.iter().filter_map(|p| p.balance_db).collect();
+    present.sort_by(f64::total_cmp);
+    let median_balance_db = (!present.is_empty()).then(|| present[present.len() / 2]);
+    let vocal_share = if points.is_empty() {
+        0.0
+    } else {
+        present.len() as f64 / points.len() as f64
+    };
+
+    let issue = |point: &BalancePoint| match point.balance_db {
+        Some(b) if b < buried_db => Some(BalanceIssue::Buried),
+        Some(b) if b > overpowering_db => Some(BalanceIssue::Overpowering),
+        _ => None,
+    };
+    let mut sections = Vec::new();
+    let mut i = 0;
+    while i < points.len() {
+        let Some(kind) = issue(&points[i]) else {
+            i += 1;
+            continue;
+        };
+        let run = points[i..]
+            .iter()
+            .take_while(|p| issue(p) == Some(kind))
+            .count();
+        let seconds = run as f64 * HOP_SECONDS;
+        if seconds >= MIN_SECTION_SECONDS {
+            let balances = points[i..i + run].iter().filter_map(|p| p.balance_db);
+            sections.push(BalanceSection {
+                start_seconds: points[i].time_seconds - HOP_SECONDS / 2.0,
+                end_seconds: points[i + run - 1].time_seconds + HOP_SECONDS / 2.0,
+                issue: kind,
+                balance_db: balances.sum::<f64>() / run as f64,
+            });
+        }
+        i += run;
+    }
+
+    Ok(VocalBalance {
+        median_balance_db,
+        vocal_share,
+        buried_db,
+        overpowering_db,
+        sections,
+        points,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buried_and_overpowering_sections_flagged() {
+        // Guitars panned hard left and right under a centered "vocal" that
+        // sits level for 8 s, 12 dB down for 8 s and 12 dB up for 8 s
+        let rate = 44100;
+        let tone =
+            |i: usize, hz: f32| (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin();
+        let vocal = |i: usize| {
+            let gain = match i / (rate * 8) {
+                0 => 0.2,
+                1 => 0.05,
+                _ => 0.8,
+            };
+            gain * tone(i, 440.0)
+        };
+        let mut buffer = AudioBuffer::new(2, rate as u32);
+        buffer.samples[0] = (0..rate * 24)
+            .map(|i| 0.2 * tone(i, 660.0) + vocal(i))
+            .collect();
+        buffer.samples[1] = (0..rate * 24)
+            .map(|i| 0.2 * tone(i, 990.0) + vocal(i))
+            .collect();
+
+        let balance = measure(&buffer, DEFAULT_BURIED_DB, DEFAULT_OVERPOWERING_DB).unwrap();
+
+        assert_eq!(balance.sections.len(), 2, "{:?}", balance.sections);
+        let buried = &balance.sections[0];
+        assert_eq!(buried.issue, BalanceIssue::Buried);
+        assert!(buried.start_seconds >= 8.0 && buried.end_seconds <= 17.0);
+        assert!((buried.balance_db + 12.0).abs() < 2.0, "{:?}", buried);
+        let overpowering = &balance.sections[1];
+        assert_eq!(overpowering.issue, BalanceIssue::Overpowering);
+        // Half a 3 s window early at most
+        assert!(overpowering.start_seconds >= 14.5, "{:?}", overpowering);
+        assert!((balance.points[3].balance_db.unwrap()).abs() < 2.0);
+        assert_eq!(balance.vocal_share, 1.0);
+    }
+}
@@ -0,0 +1,314 @@
+//! Catalog statistics for batch analysis
+//!
+//! A batch-analyze job measures a whole catalog (or a release's worth of
+//! tracks) in one go. Besides each track's numbers it reports how loudness is
+//! spread across the batch and which tracks stand out from the rest: far
+//! louder or quieter than the catalog (outside the Tukey fences of its
+//! integrated loudness), over the QC true peak gate, or clipped.
+
+use serde::Serialize;
+
+use crate::types::AnalysisResult;
+
+/// Interquartile ranges beyond the quartiles a track's loudness must be to
+/// count as an outlier
+const FENCE_IQR: f64 = 1.5;
+
+/// Fewest analyzed tracks loudness outliers are looked for in
+const MIN_TRACKS_FOR_FENCES: usize = 4;
+
+/// Width of the loudness histogram's bins (LU)
+const HISTOGRAM_BIN_LU: f64 = 1.0;
+
+/// The measurements reported for each track of a batch
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackSummary {
+    pub integrated_lufs: f64,
+    pub loudness_range: f64,
+    pub short_term_max: f64,
+    pub true_peak: f64,
+    pub sample_peak: f64,
+    pub has_clipping: bool,
+    pub clipped_samples: usize,
+    pub has_dc_offset: bool,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_secs: f64,
+}
+
+impl From<&AnalysisResult> for TrackSummary {
+    fn from(result: &AnalysisResult) -> Self {
+        Self {
+            integrated_lufs: result.integrated_lufs,
+            loudness_range: result.loudness_range,
+            short_term_max: result.short_term_max,
+            true_peak: result.true_peak,
+            sample_peak: result.sample_peak,
+            has_clipping: result.has_clipping,
+            clipped_samples: result.clipped_samples,
+            has_dc_offset: result.has_dc_offset,
+            sample_rate: result.sample_rate,
+            channels: result.channels,
+            duration_secs: result.duration_secs,
+        }
+    }
+}
+
+/// One track of a batch: its analysis, or why it couldn't be analyzed
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchTrack {
+    pub track_id: String,
+    pub source_sha256: Option<String>,
+    pub analysis: Option<TrackSummary>,
+    pub error: Option<String>,
+}
+
+impl BatchTrack {
+    pub fn new(track_id: &str) -> Self {
+        Self {
+            track_id: track_id.to_string(),
+            source_sha256: None,
+            analysis: None,
+            error: None,
+        }
+    }
+}
+
+/// Spread of one measurement across the analyzed tracks
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Distribution {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p10: f64,
+    pub p90: f64,
+    pub std_dev: f64,
+}
+
+/// Tracks whose integrated loudness falls in `[lufs, lufs + 1)`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramBin {
+    pub lufs: f64,
+    pub count: usize,
+}
+
+/// Why a track stands out from the batch
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutlierReason {
+    TooLoud,
+    TooQuiet,
+    TruePeak,
+    Clipping,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Outlier {
+    pub track_id: String,
+    pub reason: OutlierReason,
+    /// The track's integrated loudness, true peak or clipped sample count
+    pub value: f64,
+    /// The fence, gate or count it crossed
+    pub limit: f64,
+}
+
+/// Statistics over every analyzed track of a batch
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogStats {
+    pub track_count: usize,
+    pub analyzed: usize,
+    pub failed: usize,
+    pub total_duration_secs: f64,
+    pub integrated_lufs: Option<Distribution>,
+    pub loudness_range: Option<Distribution>,
+    pub true_peak: Option<Distribution>,
+    pub loudness_histogram: Vec<HistogramBin>,
+    pub outliers: Vec<Outlier>,
+}
+
+/// Catalog statistics of a batch, flagging true peaks above `true_peak_max`
+pub fn catalog_stats(tracks: &[BatchTrack], true_peak_max: f64) -> CatalogStats {
+    let analyzed: Vec<(&str, &TrackSummary)> = tracks
+        .iter()
+        .filter_map(|t| t.analysis.as_ref().map(|a| (t.track_id.as_str(), a)))
+        .collect();
+    let values = |measure: fn(&TrackSummary) -> f64| -> Vec<f64> {
+        let mut values: Vec<f64> = analyzed
+            .iter()
+            .map(|(_, a)| measure(a))
+            .filter(|v| v.is_finite())
+            .collect();
+        values.sort_by(f64::total_cmp);
+        values
+    };
+    let lufs = values(|a| a.integrated_lufs);
+
+    let mut outliers = Vec::new();
+    if lufs.len() >= MIN_TRACKS_FOR_FENCES {
+        let (q1, q3) = (percentile(&lufs, 0.25), percentile(&lufs, 0.75));
+        let low = q1 - FENCE_IQR * (q3 - q1);
+        let high = q3 + FENCE_IQR * (q3 - q1);
+        for (track_id, a) in &analyzed {
+            let flag = if a.integrated_lufs > high {
+                Some((OutlierReason::TooLoud, high))
+            } else if a.integrated_lufs < low {
+                Some((OutlierReason::TooQuiet, low))
+            } else {
+                None
+            };
+            if let Some((reason, limit)) = flag {
+                outliers.push(Outlier {
+                    track_id: track_id.to_string(),
+                    reason,
+                    value: a.integrated_lufs,
+                    limit,
+                });
+            }
+        }
+    }
+    for (track_id, a) in &analyzed {
+        if a.true_peak > true_peak_max {
+            outliers.push(Outlier {
+                track_id: track_id.to_string(),
+                reason: OutlierReason::TruePeak,
+                value: a.true_peak,
+                limit: true_peak_max,
+            });
+        }
+        if a.has_clipping {
+            outliers.push(Outlier {
+                track_id: track_id.to_string(),
+                reason: OutlierReason::Clipping,
+                value: a.clipped_samples as f64,
+                limit: 0.0,
+            });
+        }
+    }
+
+    CatalogStats {
+        track_count: tracks.len(),
+        analyzed: analyzed.len(),
+        failed: tracks.len() - analyzed.len(),
+        total_duration_secs: analyzed.iter().map(|(_, a)| a.duration_secs).sum(),
+        integrated_lufs: distribution(&lufs),
+        loudness_range: distribution(&values(|a| a.loudness_range)),
+        true_peak: distribution(&values(|a| a.true_peak)),
+        loudness_histogram: histogram(&lufs),
+        outliers,
+    }
+}
+
+/// Spread of sorted, finite `values`
+fn distribution(values: &[f64]) -> Option<Distribution> {
+    let (&min, &max) = (values.first()?, values.last()?);
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    Some(Distribution {
+        min,
+        max,
+        mean,
+        median: percentile(values, 0.5),
+        p10: percentile(values, 0.1),
+        p90: percentile(values, 0.9),
+        std_dev: variance.sqrt(),
+    })
+}
+
+/// Linearly interpolated percentile of sorted, non-empty `values`
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let position = p * (values.len() - 1) as f64;
+    let below = position.floor() as usize;
+    let above = (below + 1).min(values.len() - 1);
+    values[below] + (values[above] - values[below]) * (position - below as f64)
+}
+
+/// Counts of sorted `lufs` per bin, from the quietest track's bin to the
+/// loudest's
+fn histogram(lufs: &[f64]) -> Vec<HistogramBin> {
+    let (Some(&first), Some(&last)) = (lufs.first(), lufs.last()) else {
+        return Vec::new();
+    };
+    let bin = |v: f64| (v / HISTOGRAM_BIN_LU).floor() as i64;
+    let lowest = bin(first);
+    let mut bins: Vec<HistogramBin> = (lowest..=bin(last))
+        .map(|b| HistogramBin {
+            lufs: b as f64 * HISTOGRAM_BIN_LU,
+            count: 0,
+        })
+        .collect();
+    for &v in lufs {
+        bins[(bin(v) - lowest) as usize].count += 1;
+    }
+    bins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_stats_flags_outliers() {
+        let track = |id: &str, lufs: f64, true_peak: f64| BatchTrack {
+            track_id: id.to_string(),
+            source_sha256: None,
+            analysis: Some(TrackSummary {
+                integrated_lufs: lufs,
+                loudness_range: 6.0,
+                short_term_max: lufs + 3.0,
+                true_peak,
+                sample_peak: true_peak,
+                has_clipping: false,
+                clipped_samples: 0,
+                has_dc_offset: false,
+                sample_rate: 44100,
+                channels: 2,
+                duration_secs: 180.0,
+            }),
+            error: None,
+        };
+        let mut failed = BatchTrack::new("missing");
+        failed.error = Some("404".to_string());
+        let tracks = vec![
+            track("a", -14.2, -1.5),
+            track("b", -13.8, -1.2),
+            track("c", -14.0, -1.0),
+            track("d", -13.5, -0.5),
+            track("e", -14.4, -1.1),
+            track("quiet", -23.0, -6.0),
+            failed,
+        ];
+
+        let stats = catalog_stats(&tracks, -1.0);
+        assert_eq!((stats.track_count, stats.analyzed, stats.failed), (7, 6, 1));
+        assert_eq!(stats.total_duration_secs, 1080.0);
+        let lufs = stats.integrated_lufs.unwrap();
+        assert_eq!((lufs.min, lufs.max), (-23.0, -13.5));
+        assert!((lufs.median + 14.1).abs() < 1e-9, "{}", lufs.median);
+
+        let flagged: Vec<(&str, OutlierReason)> = stats
+            .outliers
+            .iter()
+            .map(|o| (o.track_id.as_str(), o.reason))
+            .collect();
+        assert_eq!(
+            flagged,
+            [
+                ("quiet", OutlierReason::TooQuiet),
+                ("d", OutlierReason::TruePeak)
+            ]
+        );
+
+        let histogram = &stats.loudness_histogram;
+        assert_eq!(histogram.len(), 10);
+        assert_eq!((histogram[0].lufs, histogram[0].count), (-23.0, 1));
+        assert_eq!(histogram.iter().map(|b| b.count).sum::<usize>(), 6);
+    }
+}
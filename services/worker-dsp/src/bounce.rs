@@ -0,0 +1,218 @@
+//! Summing stems into a stereo mix
+//!
+//! Each stem is brought to the mix's sample rate, given its gain and pan,
+//! and added in; the mix runs as long as the longest stem. Mono stems are
+//! panned with a constant-power (-3 dB center) law, as a console would,
+//! while stereo stems are balanced, keeping their own image at center.
+//! The sum is kept in floating point, so a mix over full scale can still be
+//! mastered cleanly; only the WAV written of it clips.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::resample;
+use crate::types::{AudioBuffer, BounceStem};
+
+/// Most stems one bounce takes
+pub const MAX_STEMS: usize = 128;
+
+/// Highest gain a stem may be given (dB)
+const MAX_GAIN_DB: f64 = 24.0;
+
+/// Check a job's stems and sample rate
+pub fn validate(stems: &[BounceStem], sample_rate: Option<u32>) -> Result<()> {
+    anyhow::ensure!(!stems.is_empty(), "A bounce needs at least one stem");
+    anyhow::ensure!(
+        stems.len() <= MAX_STEMS,
+        "A bounce takes at most {} stems",
+        MAX_STEMS
+    );
+    anyhow::ensure!(
+        stems.iter().any(|s| !s.mute),
+        "Every stem is muted; there is nothing to bounce"
+    );
+    for (i, stem) in stems.iter().enumerate() {
+        anyhow::ensure!(
+            (-1.0..=1.0).contains(&stem.pan),
+            "Stem {}: pan must be between -1 (left) and 1 (right)",
+            i
+        );
+        anyhow::ensure!(
+            stem.gain_db.is_finite() && stem.gain_db <= MAX_GAIN_DB,
+            "Stem {}: gain must be at most {} dB",
+            i,
+            MAX_GAIN_DB
+        );
+    }
+    if let Some(rate) = sample_rate {
+        anyhow::ensure!(
+            resample::SUPPORTED_SAMPLE_RATES.contains(&rate),
+            "Unsupported bounce sample rate: {} Hz",
+            rate
+        );
+    }
+    Ok(())
+}
+
+/// One stem as it went into the mix
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BouncedStem {
+    /// The stem's name, or its position in the job
+    pub name: String,
+    pub muted: bool,
+    /// Source sample rate and length; absent for muted stems, which aren't
+    /// downloaded
+    pub sample_rate: Option<u32>,
+    pub duration_secs: Option<f64>,
+}
+
+/// The finished mix
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BounceSummary {
+    pub sample_rate: u32,
+    pub duration_secs: f64,
+    /// Highest sample of the sum (dBFS)
+    pub peak_db: f64,
+    /// Whether the sum goes over full scale, and so the mix WAV clips
+    pub clipped: bool,
+    pub stems: Vec<BouncedStem>,
+}
+
+/// Running stereo sum of stems
+pub struct Mixer {
+    /// Sample rate of the mix; the first stem added sets it if unset
+    sample_rate: Option<u32>,
+    mix: [Vec<f32>; 2],
+    stems: Vec<BouncedStem>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: Option<u32>) -> Self {
+        Self {
+            sample_rate,
+            mix: [Vec::new(), Vec::new()],
+            stems: Vec::new(),
+        }
+    }
+
+    /// Record a muted stem
+    pub fn skip(&mut self, name: String) {
+        self.stems.push(BouncedStem {
+            name,
+            muted: true,
+            sample_rate: None,
+            duration_secs: None,
+        });
+    }
+
+    /// Add a mono or stereo stem at `gain_db` and `pan` (-1 left to 1 right)
+    pub fn add(&mut self, name: String, stem: &AudioBuffer, gain_db: f64, pan: f64) -> Result<()> {
+        anyhow::ensure!(
+            stem.channels == 1 || stem.channels == 2,
+            "Stem {} has {} channels; only mono and stereo stems can be bounced",
+            name,
+            stem.channels
+        );
+        self.stems.push(BouncedStem {
+            name,
+            muted: false,
+            sample_rate: Some(stem.sample_rate),
+            duration_secs: Some(stem.duration_secs()),
+        });
+        let rate = *self.sample_rate.get_or_insert(stem.sample_rate);
+        let resampled;
+        let stem = if stem.sample_rate == rate {
+            stem
+        } else {
+            resampled = resample::resample(stem, rate)?;
+            &resampled
+        };
+
+        let gain = 10f64.powf(gain_db / 20.0);
+        let gains = if stem.channels == 1 {
+            let angle = (pan + 1.0) * std::f64::consts::FRAC_PI_4;
+            [gain * angle.cos(), gain * angle.sin()]
+        } else {
+            [gain * (1.0 - pan).min(1.0), gain * (1.0 + pan).min(1.0)]
+        };
+        let frames = stem.frame_count();
+        for (ch, (out, gain)) in self.mix.iter_mut().zip(gains).enumerate() {
+            if out.len() < frames {
+                out.resize(frames, 0.0);
+            }
+            let source = &stem.samples[ch.min(stem.channels - 1)];
+            for (o, &s) in out.iter_mut().zip(source) {
+                *o += s * gain as f32;
+            }
+        }
+        Ok(())
+    }
+
+    /// The mix, once at least one stem has been added
+    pub fn finish(self) -> Result<(AudioBuffer, BounceSummary)> {
+        let sample_rate = self
+            .sample_rate
+            .filter(|_| self.stems.iter().any(|s| !s.muted))
+            .ok_or_else(|| anyhow::anyhow!("Every stem is muted; there is nothing to bounce"))?;
+        let mix = AudioBuffer {
+            samples: self.mix.into(),
+            sample_rate,
+            channels: 2,
+        };
+        let peak = mix
+            .samples
+            .iter()
+            .flatten()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let summary = BounceSummary {
+            sample_rate,
+            duration_secs: mix.duration_secs(),
+            peak_db: if peak > 0.0 {
+                (20.0 * (peak as f64).log10()).max(-144.0)
+            } else {
+                -144.0
+            },
+            clipped: peak > 1.0,
+            stems: self.stems,
+        };
+        Ok((mix, summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stems_panned_and_summed() {
+        let rate = 48000;
+        // A mono stem hard left, a mono stem at center and a shorter stereo
+        // stem 6 dB down, summing over full scale on the left
+        let mut left = AudioBuffer::new(1, rate);
+        left.samples[0] = vec![0.6; 1000];
+        let mut center = AudioBuffer::new(1, rate);
+        center.samples[0] = vec![0.5; 1000];
+        let mut stereo = AudioBuffer::new(2, rate);
+        stereo.samples[0] = vec![0.2; 500];
+        stereo.samples[1] = vec![-0.2; 500];
+
+        let mut mixer = Mixer::new(None);
+        mixer.add("left".into(), &left, 0.0, -1.0).unwrap();
+        mixer.add("center".into(), &center, 0.0, 0.0).unwrap();
+        mixer.skip("muted".into());
+        mixer.add("stereo".into(), &stereo, -6.0206, 0.0).unwrap();
+        let (mix, summary) = mixer.finish().unwrap();
+
+        assert_eq!(mix.frame_count(), 1000);
+        let center_gain = std::f32::consts::FRAC_1_SQRT_2 * 0.5;
+        assert!((mix.samples[0][0] - (0.6 + center_gain + 0.1)).abs() < 1e-4);
+        assert!((mix.samples[1][0] - (center_gain - 0.1)).abs() < 1e-4);
+        assert!((mix.samples[0][999] - (0.6 + center_gain)).abs() < 1e-4);
+        assert!(summary.clipped);
+        assert_eq!(summary.stems.len(), 4);
+        assert!(summary.stems[2].muted);
+        assert_eq!(summary.sample_rate, rate);
+    }
+}
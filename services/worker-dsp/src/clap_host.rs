@@ -0,0 +1,679 @@
+//! Offline hosting of CLAP plugins
+//!
+//! Some studios are required to master through a particular commercial
+//! limiter or EQ. With the `clap` feature, a plugin installed on the worker
+//! and configured under `[clap.<name>]` can run as a `clap` stage of a
+//! pipeline, fix or master job, restored from a saved preset first. A master
+//! job can also take one as its `finalStage`, after its own limiter, and is
+//! measured and QC'd on the plugin's output.
+//!
+//! The plugin is loaded from its `.clap` shared library for the one stage,
+//! given a host that offers no extensions, restored from the preset through
+//! its `clap.state` extension and run over the audio a block at a time,
+//! every call from the job's thread. The delay it reports through
+//! `clap.latency` is compensated, so its output lines up with its input.
+//! Only the main audio port is used, and it must have as many channels as
+//! the audio. VST3 plugins aren't hosted; most vendors ship the same plugin
+//! as CLAP, and anything else can run as an external processor.
+//!
+//! A plugin runs in the worker's process: only plugins the operator
+//! installed and configured are loaded, never anything a job supplies.
+
+use anyhow::{Context, Result};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::Path;
+use std::ptr;
+
+use crate::config::ClapPluginSettings;
+use crate::types::AudioBuffer;
+
+/// Frames processed per call
+const BLOCK_FRAMES: u32 = 1024;
+
+const PLUGIN_FACTORY_ID: &CStr = c"clap.plugin-factory";
+const EXT_STATE: &CStr = c"clap.state";
+const EXT_LATENCY: &CStr = c"clap.latency";
+const EXT_AUDIO_PORTS: &CStr = c"clap.audio-ports";
+
+/// `clap_process_status` of a plugin that failed
+const PROCESS_ERROR: i32 = 0;
+
+/// The CLAP version this host implements
+const HOST_CLAP_VERSION: ClapVersion = ClapVersion {
+    major: 1,
+    minor: 2,
+    revision: 0,
+};
+
+// The C ABI of CLAP 1.x, as far as this host uses it
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ClapVersion {
+    major: u32,
+    minor: u32,
+    revision: u32,
+}
+
+#[repr(C)]
+struct PluginEntry {
+    clap_version: ClapVersion,
+    init: unsafe extern "C" fn(plugin_path: *const c_char) -> bool,
+    deinit: unsafe extern "C" fn(),
+    get_factory: unsafe extern "C" fn(factory_id: *const c_char) -> *const c_void,
+}
+
+#[repr(C)]
+struct PluginFactory {
+    get_plugin_count: unsafe extern "C" fn(factory: *const PluginFactory) -> u32,
+    get_plugin_descriptor:
+        unsafe extern "C" fn(factory: *const PluginFactory, index: u32) -> *const PluginDescriptor,
+    create_plugin: unsafe extern "C" fn(
+        factory: *const PluginFactory,
+        host: *const Host,
+        plugin_id: *const c_char,
+    ) -> *const Plugin,
+}
+
+#[repr(C)]
+struct PluginDescriptor {
+    clap_version: ClapVersion,
+    id: *const c_char,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    manual_url: *const c_char,
+    support_url: *const c_char,
+    version: *const c_char,
+    description: *const c_char,
+    features: *const *const c_char,
+}
+
+#[repr(C)]
+struct Host {
+    clap_version: ClapVersion,
+    host_data: *mut c_void,
+    name: *const c_char,
+    vendor: *const c_char,
+    url: *const c_char,
+    version: *const c_char,
+    get_extension: unsafe extern "C" fn(host: *const Host, id: *const c_char) -> *const c_void,
+    request_restart: unsafe extern "C" fn(host: *const Host),
+    request_process: unsafe extern "C" fn(host: *const Host),
+    request_callback: unsafe extern "C" fn(host: *const Host),
+}
+
+#[repr(C)]
+struct Plugin {
+    desc: *const PluginDescriptor,
+    plugin_data: *mut c_void,
+    init: unsafe extern "C" fn(plugin: *const Plugin) -> bool,
+    destroy: unsafe extern "C" fn(plugin: *const Plugin),
+    activate: unsafe extern "C" fn(
+        plugin: *const Plugin,
+        sample_rate: f64,
+        min_frames: u32,
+        max_frames: u32,
+    ) -> bool,
+    deactivate: unsafe extern "C" fn(plugin: *const Plugin),
+    start_processing: unsafe extern "C" fn(plugin: *const Plugin) -> bool,
+    stop_processing: unsafe extern "C" fn(plugin: *const Plugin),
+    reset: unsafe extern "C" fn(plugin: *const Plugin),
+    process: unsafe extern "C" fn(plugin: *const Plugin, process: *const Process) -> i32,
+    get_extension: unsafe extern "C" fn(plugin: *const Plugin, id: *const c_char) -> *const c_void,
+    on_main_thread: unsafe extern "C" fn(plugin: *const Plugin),
+}
+
+#[repr(C)]
+struct ClapAudioBuffer {
+    data32: *mut *mut f32,
+    data64: *mut *mut f64,
+    channel_count: u32,
+    latency: u32,
+    constant_mask: u64,
+}
+
+#[repr(C)]
+struct Process {
+    steady_time: i64,
+    frames_count: u32,
+    transport: *const c_void,
+    audio_inputs: *const ClapAudioBuffer,
+    audio_outputs: *mut ClapAudioBuffer,
+    audio_inputs_count: u32,
+    audio_outputs_count: u32,
+    in_events: *const InputEvents,
+    out_events: *const OutputEvents,
+}
+
+#[repr(C)]
+struct InputEvents {
+    ctx: *mut c_void,
+    size: unsafe extern "C" fn(list: *const InputEvents) -> u32,
+    get: unsafe extern "C" fn(list: *const InputEvents, index: u32) -> *const c_void,
+}
+
+#[repr(C)]
+struct OutputEvents {
+    ctx: *mut c_void,
+    try_push: unsafe extern "C" fn(list: *const OutputEvents, event: *const c_void) -> bool,
+}
+
+#[repr(C)]
+struct PluginState {
+    save: unsafe extern "C" fn(plugin: *const Plugin, stream: *const c_void) -> bool,
+    load: unsafe extern "C" fn(plugin: *const Plugin, stream: *const IStream) -> bool,
+}
+
+#[repr(C)]
+struct IStream {
+    ctx: *mut c_void,
+    read: unsafe extern "C" fn(stream: *const IStream, buffer: *mut c_void, size: u64) -> i64,
+}
+
+#[repr(C)]
+struct PluginLatency {
+    get: unsafe extern "C" fn(plugin: *const Plugin) -> u32,
+}
+
+#[repr(C)]
+struct PluginAudioPorts {
+    count: unsafe extern "C" fn(plugin: *const Plugin, is_input: bool) -> u32,
+    get: unsafe extern "C" fn(
+        plugin: *const Plugin,
+        index: u32,
+        is_input: bool,
+        info: *mut AudioPortInfo,
+    ) -> bool,
+}
+
+#[repr(C)]
+struct AudioPortInfo {
+    id: u32,
+    name: [c_char; 256],
+    flags: u32,
+    channel_count: u32,
+    port_type: *const c_char,
+    in_place_pair: u32,
+}
+
+// Host callbacks: no extensions, and nothing to do on request since the
+// whole run happens in one call
+
+unsafe extern "C" fn host_get_extension(_: *const Host, _: *const c_char) -> *const c_void {
+    ptr::null()
+}
+
+unsafe extern "C" fn host_request(_: *const Host) {}
+
+unsafe extern "C" fn no_events_size(_: *const InputEvents) -> u32 {
+    0
+}
+
+unsafe extern "C" fn no_events_get(_: *const InputEvents, _: u32) -> *const c_void {
+    ptr::null()
+}
+
+unsafe extern "C" fn discard_event(_: *const OutputEvents, _: *const c_void) -> bool {
+    true
+}
+
+/// A preset being read by the plugin
+struct PresetReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+unsafe extern "C" fn preset_read(stream: *const IStream, buffer: *mut c_void, size: u64) -> i64 {
+    // SAFETY: `ctx` is the `PresetReader` `load_preset` set up, alive for
+    // the `load` call this is made from, and the plugin gives a buffer of
+    // at least `size` bytes
+    let reader = &mut *((*stream).ctx as *mut PresetReader);
+    let remaining = &reader.data[reader.position..];
+    let n = remaining.len().min(size as usize);
+    ptr::copy_nonoverlapping(remaining.as_ptr(), buffer as *mut u8, n);
+    reader.position += n;
+    n as i64
+}
+
+/// A loaded `.clap` library
+struct Library(*mut c_void);
+
+impl Library {
+    fn open(path: &Path) -> Result<Self> {
+        let c_path = CString::new(path.as_os_str().as_encoded_bytes())?;
+        // SAFETY: a NUL-terminated path; loading runs the library's
+        // initializers, which is what configuring it as a plugin trusts
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            // SAFETY: dlerror returns a NUL-terminated message or null
+            let error = unsafe { libc::dlerror() };
+            let message = if error.is_null() {
+                "unknown error".into()
+            } else {
+                unsafe { CStr::from_ptr(error) }.to_string_lossy()
+            };
+            anyhow::bail!("Failed to load {}: {}", path.display(), message);
+        }
+        Ok(Self(handle))
+    }
+
+    fn entry(&self) -> Result<&PluginEntry> {
+        // SAFETY: a live handle and a NUL-terminated symbol name; CLAP
+        // libraries export `clap_entry` as a `clap_plugin_entry_t`
+        let entry = unsafe { libc::dlsym(self.0, c"clap_entry".as_ptr()) };
+        anyhow::ensure!(!entry.is_null(), "Library exports no clap_entry");
+        Ok(unsafe { &*(entry as *const PluginEntry) })
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        // SAFETY: opened by `open`, and nothing from it outlives `process`
+        unsafe { libc::dlclose(self.0) };
+    }
+}
+
+/// Run the plugin configured by `settings` over `buffer`, restored from
+/// `preset` first; returns the latency compensated (frames)
+#[tracing::instrument(name = "dsp.clap", skip_all)]
+pub fn process(
+    settings: &ClapPluginSettings,
+    preset: Option<&Path>,
+    buffer: &mut AudioBuffer,
+) -> Result<u32> {
+    let preset = preset
+        .map(|path| {
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+        })
+        .transpose()?;
+    let library = Library::open(Path::new(&settings.path))?;
+    let path = CString::new(settings.path.as_str())?;
+    run_entry(
+        library.entry()?,
+        &path,
+        settings.id.as_deref(),
+        preset.as_deref(),
+        buffer,
+    )
+}
+
+/// Calls `f` when dropped, to undo a step of the plugin's setup
+struct Guard<F: FnMut()>(F);
+
+impl<F: FnMut()> Drop for Guard<F> {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+fn run_entry(
+    entry: &PluginEntry,
+    path: &CStr,
+    id: Option<&str>,
+    preset: Option<&[u8]>,
+    buffer: &mut AudioBuffer,
+) -> Result<u32> {
+    anyhow::ensure!(
+        entry.clap_version.major >= 1,
+        "Plugin implements CLAP {}.{}, before 1.0",
+        entry.clap_version.major,
+        entry.clap_version.minor
+    );
+
+    // SAFETY (for the calls below): every function pointer comes from the
+    // plugin's entry, factory or plugin struct and is called as CLAP
+    // specifies, with pointers that outlive the call; each setup step is
+    // undone by a guard in reverse order, after the steps following it
+    unsafe {
+        anyhow::ensure!(
+            (entry.init)(path.as_ptr()),
+            "Plugin library failed to initialize"
+        );
+        let _deinit = Guard(|| (entry.deinit)());
+
+        let factory = (entry.get_factory)(PLUGIN_FACTORY_ID.as_ptr()) as *const PluginFactory;
+        anyhow::ensure!(!factory.is_null(), "Plugin library has no plugin factory");
+        let count = ((*factory).get_plugin_count)(factory);
+        let descriptor = (0..count)
+            .map(|i| ((*factory).get_plugin_descriptor)(factory, i))
+            .find(|&d| {
+                !d.is_null()
+                    && id.is_none_or(|id| CStr::from_ptr((*d).id).to_bytes() == id.as_bytes())
+            })
+            .with_context(|| match id {
+                Some(id) => format!("Plugin library has no plugin {}", id),
+                None => "Plugin library has no plugins".into(),
+            })?;
+        let plugin_name = CStr::from_ptr((*descriptor).name)
+            .to_string_lossy()
+            .into_owned();
+
+        let host = Host {
+            clap_version: HOST_CLAP_VERSION,
+            host_data: ptr::null_mut(),
+            name: c"Budi".as_ptr(),
+            vendor: c"Budi".as_ptr(),
+            url: c"".as_ptr(),
+            version: c"1.0.0".as_ptr(),
+            get_extension: host_get_extension,
+            request_restart: host_request,
+            request_process: host_request,
+            request_callback: host_request,
+        };
+        let plugin = ((*factory).create_plugin)(factory, &host, (*descriptor).id);
+        anyhow::ensure!(!plugin.is_null(), "{} could not be created", plugin_name);
+        let _destroy = Guard(|| ((*plugin).destroy)(plugin));
+        anyhow::ensure!(
+            ((*plugin).init)(plugin),
+            "{} failed to initialize",
+            plugin_name
+        );
+
+        let channels = buffer.channels as u32;
+        let ports = (*plugin).get_extension;
+        let ports = ports(plugin, EXT_AUDIO_PORTS.as_ptr()) as *const PluginAudioPorts;
+        if !ports.is_null() {
+            for is_input in [true, false] {
+                let mut info: AudioPortInfo = std::mem::zeroed();
+                let main = ((*ports).count)(plugin, is_input) > 0
+                    && ((*ports).get)(plugin, 0, is_input, &mut info);
+                anyhow::ensure!(
+                    main && info.channel_count == channels,
+                    "{} has no {}-channel main {}",
+                    plugin_name,
+                    channels,
+                    if is_input { "input" } else { "output" }
+                );
+            }
+        }
+
+        if let Some(preset) = preset {
+            let state = ((*plugin).get_extension)(plugin, EXT_STATE.as_ptr()) as *const PluginState;
+            anyhow::ensure!(!state.is_null(), "{} can't load presets", plugin_name);
+            let mut reader = PresetReader {
+                data: preset,
+                position: 0,
+            };
+            let stream = IStream {
+                ctx: &mut reader as *mut PresetReader as *mut c_void,
+                read: preset_read,
+            };
+            anyhow::ensure!(
+                ((*state).load)(plugin, &stream),
+                "{} rejected the preset",
+                plugin_name
+            );
+        }
+
+        anyhow::ensure!(
+            ((*plugin).activate)(plugin, buffer.sample_rate as f64, 1, BLOCK_FRAMES),
+            "{} failed to activate at {} Hz",
+            plugin_name,
+            buffer.sample_rate
+        );
+        let _deactivate = Guard(|| ((*plugin).deactivate)(plugin));
+        let latency =
+            ((*plugin).get_extension)(plugin, EXT_LATENCY.as_ptr()) as *const PluginLatency;
+        let latency = if latency.is_null() {
+            0
+        } else {
+            ((*latency).get)(plugin)
+        };
+
+        anyhow::ensure!(
+            ((*plugin).start_processing)(plugin),
+            "{} failed to start processing",
+            plugin_name
+        );
+        let _stop = Guard(|| ((*plugin).stop_processing)(plugin));
+
+        let in_events = InputEvents {
+            ctx: ptr::null_mut(),
+            size: no_events_size,
+            get: no_events_get,
+        };
+        let out_events = OutputEvents {
+            ctx: ptr::null_mut(),
+            try_push: discard_event,
+        };
+
+        // The input runs on into silence for `latency` frames, and that
+        // many frames are dropped from the start of the output
+        let frames = buffer.frame_count();
+        let total = frames + latency as usize;
+        let block = BLOCK_FRAMES as usize;
+        let mut inputs = vec![vec![0.0f32; block]; buffer.channels];
+        let mut outputs = vec![vec![0.0f32; block]; buffer.channels];
+        let mut processed = vec![Vec::with_capacity(total); buffer.channels];
+        let mut start = 0;
+        while start < total {
+            let n = block.min(total - start);
+            for (input, channel) in inputs.iter_mut().zip(&buffer.samples) {
+                let available = channel.len().saturating_sub(start).min(n);
+                input[..available].copy_from_slice(&channel[start..start + available]);
+                input[available..n].fill(0.0);
+            }
+            let mut input_ptrs: Vec<*mut f32> = inputs.iter_mut().map(|c| c.as_mut_ptr()).collect();
+            let mut output_ptrs: Vec<*mut f32> =
+                outputs.iter_mut().map(|c| c.as_mut_ptr()).collect();
+            let audio_input = ClapAudioBuffer {
+                data32: input_ptrs.as_mut_ptr(),
+                data64: ptr::null_mut(),
+                channel_count: channels,
+                latency: 0,
+                constant_mask: 0,
+            };
+            let mut audio_output = ClapAudioBuffer {
+                data32: output_ptrs.as_mut_ptr(),
+                data64: ptr::null_mut(),
+                channel_count: channels,
+                latency: 0,
+                constant_mask: 0,
+            };
+            let call = Process {
+                steady_time: start as i64,
+                frames_count: n as u32,
+                transport: ptr::null(),
+                audio_inputs: &audio_input,
+                audio_outputs: &mut audio_output,
+                audio_inputs_count: 1,
+                audio_outputs_count: 1,
+                in_events: &in_events,
+                out_events: &out_events,
+            };
+            anyhow::ensure!(
+                ((*plugin).process)(plugin, &call) != PROCESS_ERROR,
+                "{} failed to process frames {}-{}",
+                plugin_name,
+                start,
+                start + n
+            );
+            for (channel, output) in processed.iter_mut().zip(&outputs) {
+                channel.extend_from_slice(&output[..n]);
+            }
+            start += n;
+        }
+
+        for (channel, mut output) in buffer.samples.iter_mut().zip(processed) {
+            output.drain(..latency as usize);
+            anyhow::ensure!(
+                output.iter().all(|s| s.is_finite()),
+                "{} output non-finite samples",
+                plugin_name
+            );
+            *channel = output;
+        }
+        Ok(latency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stand-in plugin: a gain (restored from a 4-byte preset) with two
+    // frames of delay
+
+    const DELAY: usize = 2;
+
+    struct Gain {
+        gain: f32,
+        history: Vec<[f32; DELAY]>,
+    }
+
+    static DESCRIPTOR: PluginDescriptorSync = PluginDescriptorSync(PluginDescriptor {
+        clap_version: HOST_CLAP_VERSION,
+        id: c"test.gain".as_ptr(),
+        name: c"Test Gain".as_ptr(),
+        vendor: c"".as_ptr(),
+        url: c"".as_ptr(),
+        manual_url: c"".as_ptr(),
+        support_url: c"".as_ptr(),
+        version: c"1".as_ptr(),
+        description: c"".as_ptr(),
+        features: ptr::null(),
+    });
+
+    struct PluginDescriptorSync(PluginDescriptor);
+    // SAFETY: only static strings, never written
+    unsafe impl Sync for PluginDescriptorSync {}
+
+    unsafe fn gain(plugin: *const Plugin) -> &'static mut Gain {
+        &mut *((*plugin).plugin_data as *mut Gain)
+    }
+
+    unsafe extern "C" fn init(_: *const Plugin) -> bool {
+        true
+    }
+    unsafe extern "C" fn destroy(plugin: *const Plugin) {
+        drop(Box::from_raw((*plugin).plugin_data as *mut Gain));
+        drop(Box::from_raw(plugin as *mut Plugin));
+    }
+    unsafe extern "C" fn activate(plugin: *const Plugin, _: f64, _: u32, _: u32) -> bool {
+        gain(plugin).history = vec![[0.0; DELAY]; 2];
+        true
+    }
+    unsafe extern "C" fn nothing(_: *const Plugin) {}
+    unsafe extern "C" fn start(_: *const Plugin) -> bool {
+        true
+    }
+    unsafe extern "C" fn run(plugin: *const Plugin, process: *const Process) -> i32 {
+        let state = gain(plugin);
+        let process = &*process;
+        let input = &*process.audio_inputs;
+        let output = &*process.audio_outputs;
+        for ch in 0..input.channel_count as usize {
+            let history = &mut state.history[ch];
+            for i in 0..process.frames_count as usize {
+                let x = *(*input.data32.add(ch)).add(i) * state.gain;
+                *(*output.data32.add(ch)).add(i) = history[0];
+                history.rotate_left(1);
+                history[DELAY - 1] = x;
+            }
+        }
+        1
+    }
+    unsafe extern "C" fn load(plugin: *const Plugin, stream: *const IStream) -> bool {
+        let mut bytes = [0u8; 4];
+        ((*stream).read)(stream, bytes.as_mut_ptr() as *mut c_void, 4) == 4 && {
+            gain(plugin).gain = f32::from_le_bytes(bytes);
+            true
+        }
+    }
+    unsafe extern "C" fn save(_: *const Plugin, _: *const c_void) -> bool {
+        false
+    }
+    unsafe extern "C" fn latency(_: *const Plugin) -> u32 {
+        DELAY as u32
+    }
+    static STATE: PluginState = PluginState { save, load };
+    static LATENCY: PluginLatency = PluginLatency { get: latency };
+    unsafe extern "C" fn extension(_: *const Plugin, id: *const c_char) -> *const c_void {
+        match CStr::from_ptr(id) {
+            id if id == EXT_STATE => &STATE as *const PluginState as *const c_void,
+            id if id == EXT_LATENCY => &LATENCY as *const PluginLatency as *const c_void,
+            _ => ptr::null(),
+        }
+    }
+
+    unsafe extern "C" fn plugin_count(_: *const PluginFactory) -> u32 {
+        1
+    }
+    unsafe extern "C" fn descriptor(_: *const PluginFactory, _: u32) -> *const PluginDescriptor {
+        &DESCRIPTOR.0
+    }
+    unsafe extern "C" fn create(
+        _: *const PluginFactory,
+        _: *const Host,
+        _: *const c_char,
+    ) -> *const Plugin {
+        let data = Box::into_raw(Box::new(Gain {
+            gain: 1.0,
+            history: Vec::new(),
+        }));
+        Box::into_raw(Box::new(Plugin {
+            desc: &DESCRIPTOR.0,
+            plugin_data: data as *mut c_void,
+            init,
+            destroy,
+            activate,
+            deactivate: nothing,
+            start_processing: start,
+            stop_processing: nothing,
+            reset: nothing,
+            process: run,
+            get_extension: extension,
+            on_main_thread: nothing,
+        }))
+    }
+    static FACTORY: PluginFactory = PluginFactory {
+        get_plugin_count: plugin_count,
+        get_plugin_descriptor: descriptor,
+        create_plugin: create,
+    };
+
+    unsafe extern "C" fn entry_init(_: *const c_char) -> bool {
+        true
+    }
+    unsafe extern "C" fn entry_deinit() {}
+    unsafe extern "C" fn get_factory(id: *const c_char) -> *const c_void {
+        if CStr::from_ptr(id) == PLUGIN_FACTORY_ID {
+            &FACTORY as *const PluginFactory as *const c_void
+        } else {
+            ptr::null()
+        }
+    }
+
+    #[test]
+    fn test_hosts_plugin_with_preset_and_latency() {
+        let entry = PluginEntry {
+            clap_version: HOST_CLAP_VERSION,
+            init: entry_init,
+            deinit: entry_deinit,
+            get_factory,
+        };
+        let mut buffer = AudioBuffer::new(2, 48000);
+        let ramp: Vec<f32> = (0..3000).map(|i| i as f32 / 3000.0).collect();
+        buffer.samples = vec![ramp.clone(), ramp.clone()];
+
+        let preset = 0.5f32.to_le_bytes();
+        let latency = run_entry(
+            &entry,
+            c"test.clap",
+            Some("test.gain"),
+            Some(&preset),
+            &mut buffer,
+        )
+        .unwrap();
+        assert_eq!(latency, DELAY as u32);
+        // Halved by the preset, and lined up with the input again
+        assert_eq!(buffer.frame_count(), ramp.len());
+        for (x, y) in ramp.iter().zip(&buffer.samples[1]) {
+            assert!((x * 0.5 - y).abs() < 1e-6);
+        }
+
+        let missing = run_entry(&entry, c"test.clap", Some("test.other"), None, &mut buffer);
+        assert!(missing.is_err());
+    }
+}
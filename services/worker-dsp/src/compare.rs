@@ -0,0 +1,266 @@
+//! Null-test comparison of two renders, for revision review
+//!
+//! The candidate (e.g. master v2) is brought to the reference's sample rate,
+//! lined up with it to the sample, and subtracted. What is left over is
+//! measured over time and per band, next to the loudness of each render, so
+//! a reviewer can see where and how much a revision changed without
+//! listening to both in full.
+
+use anyhow::Result;
+use budi_worker_core::align;
+use serde::Serialize;
+
+use crate::resample;
+use crate::types::{AudioBuffer, Loudness};
+use budi_dsp_core::analysis;
+
+/// Longest lead-in difference searched for when aligning (seconds)
+pub const DEFAULT_MAX_OFFSET_SECONDS: f64 = 1.0;
+
+/// Largest `maxOffsetSeconds` a job may ask for; the search grows with it
+pub const MAX_OFFSET_SECONDS: f64 = 10.0;
+
+/// Length of each point of the difference-over-time curve (seconds)
+const BLOCK_SECONDS: f64 = 0.5;
+
+/// Level reported for digital silence (dBFS)
+const FLOOR_DB: f64 = -144.0;
+
+/// Octave bands the spectral difference is reported in (Hz), also used for
+/// the tonal balance of revision reports
+pub const BANDS: [(f64, f64); 10] = [
+    (20.0, 40.0),
+    (40.0, 80.0),
+    (80.0, 160.0),
+    (160.0, 320.0),
+    (320.0, 640.0),
+    (640.0, 1280.0),
+    (1280.0, 2560.0),
+    (2560.0, 5120.0),
+    (5120.0, 10240.0),
+    (10240.0, 20480.0),
+];
+
+/// Level change of one band from the reference to the candidate
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandDelta {
+    pub low_hz: f64,
+    pub high_hz: f64,
+    pub reference_db: f64,
+    pub candidate_db: f64,
+    pub delta_db: f64,
+}
+
+/// Everything measured by a comparison
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Comparison {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// Frames the candidate lags the reference by (negative: it leads)
+    pub offset_frames: i64,
+    pub offset_seconds: f64,
+    /// Length both renders cover once aligned
+    pub overlap_seconds: f64,
+    /// The candidate was resampled from this rate to the reference's
+    pub candidate_sample_rate: Option<u32>,
+    pub reference_rms_db: f64,
+    pub difference_rms_db: f64,
+    pub difference_peak_db: f64,
+    /// How far the difference is below the reference (dB; higher is closer)
+    pub null_depth_db: f64,
+    pub block_seconds: f64,
+    /// Difference RMS of each block (dBFS)
+    pub difference_over_time: Vec<f64>,
+    pub bands: Vec<BandDelta>,
+    pub reference: Loudness,
+    pub candidate: Loudness,
+    /// Candidate minus reference
+    pub loudness_delta: Loudness,
+}
+
+/// Compare `candidate` against `reference`, returning the measurements and
+/// the aligned difference signal
+#[tracing::instrument(name = "dsp.compare", skip_all)]
+pub fn compare(
+    reference: &AudioBuffer,
+    candidate: &AudioBuffer,
+    max_offset_seconds: f64,
+) -> Result<(Comparison, AudioBuffer)> {
+    anyhow::ensure!(
+        reference.channels == candidate.channels,
+        "Can't compare a {}-channel render with a {}-channel one",
+        reference.channels,
+        candidate.channels
+    );
+    anyhow::ensure!(
+        (0.0..=MAX_OFFSET_SECONDS).contains(&max_offset_seconds),
+        "maxOffsetSeconds must be between 0 and {}",
+        MAX_OFFSET_SECONDS
+    );
+
+    let resampled;
+    let (candidate, candidate_sample_rate) = if candidate.sample_rate != reference.sample_rate {
+        resampled = resample::resample(candidate, reference.sample_rate)?;
+        (&resampled, Some(candidate.sample_rate))
+    } else {
+        (candidate, None)
+    };
+
+    let rate = reference.sample_rate as f64;
+    let max_delay = (max_offset_seconds * rate) as usize;
+    let (ref_offset, cand_offset, frames) = align::align(reference, candidate, max_delay)?;
+    anyhow::ensure!(frames > 0, "The renders don't overlap");
+
+    let slice = |buffer: &AudioBuffer, offset: usize| AudioBuffer {
+        samples: buffer
+            .samples
+            .iter()
+            .map(|ch| ch[offset..offset + frames].to_vec())
+            .collect(),
+        sample_rate: buffer.sample_rate,
+        channels: buffer.channels,
+    };
+    let reference_aligned = slice(reference, ref_offset);
+    let candidate_aligned = slice(candidate, cand_offset);
+    let mut difference = AudioBuffer::new(reference.channels, reference.sample_rate);
+    for ((out, r), c) in difference
+        .samples
+        .iter_mut()
+        .zip(&reference_aligned.samples)
+        .zip(&candidate_aligned.samples)
+    {
+        *out = c.iter().zip(r).map(|(c, r)| c - r).collect();
+    }
+
+    let block = ((BLOCK_SECONDS * rate) as usize).max(1);
+    let difference_over_time = (0..frames.div_ceil(block))
+        .map(|b| rms_db(&difference, b * block..((b + 1) * block).min(frames)))
+        .collect();
+    let reference_rms_db = rms_db(&reference_aligned, 0..frames);
+    let difference_rms_db = rms_db(&difference, 0..frames);
+    let peak = difference
+        .samples
+        .iter()
+        .flatten()
+        .fold(0.0f32, |max, &s| max.max(s.abs()));
+
+    let bands = match (
+        analysis::absolute_band_levels(&reference_aligned, &BANDS)?,
+        analysis::absolute_band_levels(&candidate_aligned, &BANDS)?,
+    ) {
+        (Some(reference_db), Some(candidate_db)) => BANDS
+            .iter()
+            .zip(reference_db.into_iter().zip(candidate_db))
+            .filter(|((low, _), _)| *low < rate / 2.0)
+            .map(
+                |(&(low_hz, high_hz), (reference_db, candidate_db))| BandDelta {
+                    low_hz,
+                    high_hz,
+                    reference_db,
+                    candidate_db,
+                    delta_db: candidate_db - reference_db,
+                },
+            )
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let reference_loudness = Loudness::from(&analysis::analyze_audio(reference, 24)?);
+    let candidate_loudness = Loudness::from(&analysis::analyze_audio(candidate, 24)?);
+    let loudness_delta = Loudness {
+        integrated_lufs: candidate_loudness.integrated_lufs - reference_loudness.integrated_lufs,
+        loudness_range: candidate_loudness.loudness_range - reference_loudness.loudness_range,
+        true_peak: candidate_loudness.true_peak - reference_loudness.true_peak,
+        sample_peak: candidate_loudness.sample_peak - reference_loudness.sample_peak,
+    };
+
+    let offset_frames = cand_offset as i64 - ref_offset as i64;
+    let comparison = Comparison {
+        sample_rate: reference.sample_rate,
+        channels: reference.channels,
+        offset_frames,
+        offset_seconds: offset_frames as f64 / rate,
+        overlap_seconds: frames as f64 / rate,
+        candidate_sample_rate,
+        reference_rms_db,
+        difference_rms_db,
+        difference_peak_db: to_db(peak as f64),
+        null_depth_db: reference_rms_db - difference_rms_db,
+        block_seconds: BLOCK_SECONDS,
+        difference_over_time,
+        bands,
+        reference: reference_loudness,
+        candidate: candidate_loudness,
+        loudness_delta,
+    };
+    Ok((comparison, difference))
+}
+
+/// RMS of all channels over `frames` (dBFS)
+fn rms_db(buffer: &AudioBuffer, frames: std::ops::Range<usize>) -> f64 {
+    let count = frames.len() * buffer.channels;
+    if count == 0 {
+        return FLOOR_DB;
+    }
+    let sum: f64 = buffer
+        .samples
+        .iter()
+        .flat_map(|ch| &ch[frames.clone()])
+        .map(|&s| (s as f64).powi(2))
+        .sum();
+    to_db((sum / count as f64).sqrt())
+}
+
+fn to_db(level: f64) -> f64 {
+    if level > 0.0 {
+        (20.0 * level.log10()).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_render_nulls_except_where_changed() {
+        // Noise, and a revision of it starting 480 frames later that is 6 dB
+        // hotter in its second half
+        let rate = 48000;
+        let mut x: u32 = 7;
+        let noise: Vec<f32> = (0..rate * 4)
+            .map(|_| {
+                x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+                ((x >> 8) as f32 / 16777216.0 - 0.5) * 0.5
+            })
+            .collect();
+        let mut reference = AudioBuffer::new(1, rate as u32);
+        reference.samples[0] = noise.clone();
+        let mut candidate = AudioBuffer::new(1, rate as u32);
+        candidate.samples[0] = std::iter::repeat_n(0.0, 480)
+            .chain(
+                noise
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &s)| if i < rate * 2 { s } else { s * 2.0 }),
+            )
+            .collect();
+
+        let (comparison, difference) = compare(&reference, &candidate, 1.0).unwrap();
+        assert_eq!(comparison.offset_frames, 480);
+        assert_eq!(difference.frame_count(), rate * 4);
+        // Silent where nothing changed, the level of the change after
+        assert_eq!(comparison.difference_over_time[1], FLOOR_DB);
+        let changed = comparison.difference_over_time[6];
+        assert!(
+            (changed - comparison.reference_rms_db).abs() < 0.5,
+            "{}",
+            changed
+        );
+        assert!(comparison.loudness_delta.integrated_lufs > 2.0);
+        assert!(comparison.bands.iter().all(|b| b.delta_db > 2.0));
+    }
+}
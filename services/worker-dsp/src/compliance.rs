@@ -0,0 +1,218 @@
+//! Loudness compliance against platform delivery specs
+//!
+//! For tracks mastered elsewhere and only QC'd here. Measuring is a single
+//! streaming pass of loudness and true peak, skipping the spectrum, stereo
+//! and DC work of a full analysis.
+//!
+//! Streaming services normalize playback, turning loud masters down (but not
+//! quiet ones up past their peak headroom), so for them only the true peak
+//! after that gain can fail. Delivery specs with a loudness tolerance, such
+//! as EBU R128 or ATSC A/85 for broadcast, also fail a master outside it, and
+//! report the gain that would bring it back to target.
+
+use anyhow::Result;
+use budi_dsp_core::true_peak::TruePeakMeter;
+use budi_worker_core::audio::AudioDecoder;
+use ebur128::{EbuR128, Mode};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::types::AudioBuffer;
+
+/// Streaming services' normalization targets: (name, LUFS, max true peak
+/// dBTP), checked when a job lists no platforms
+pub const DEFAULT_PLATFORMS: [(&str, f64, f64); 6] = [
+    ("Spotify", -14.0, -1.0),
+    ("Apple Music", -16.0, -1.0),
+    ("YouTube", -14.0, -1.0),
+    ("Tidal", -14.0, -1.0),
+    ("Amazon Music", -14.0, -2.0),
+    ("Deezer", -15.0, -1.0),
+];
+
+/// A platform's loudness spec
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformSpec {
+    pub name: String,
+    pub target_lufs: f64,
+    /// Highest true peak accepted (dBTP)
+    pub max_true_peak: f64,
+    /// How far from the target loudness a delivery may be (LU); without one
+    /// the platform normalizes instead
+    #[serde(default)]
+    pub tolerance_lu: Option<f64>,
+}
+
+impl PlatformSpec {
+    pub fn defaults() -> Vec<Self> {
+        DEFAULT_PLATFORMS
+            .iter()
+            .map(|&(name, target_lufs, max_true_peak)| Self {
+                name: name.to_string(),
+                target_lufs,
+                max_true_peak,
+                tolerance_lu: None,
+            })
+            .collect()
+    }
+}
+
+/// Check a job's platform specs
+pub fn validate(platforms: &[PlatformSpec]) -> Result<()> {
+    for spec in platforms {
+        anyhow::ensure!(
+            !spec.name.trim().is_empty(),
+            "Platform specs must have a name"
+        );
+        anyhow::ensure!(
+            spec.target_lufs.is_finite() && spec.target_lufs < 0.0,
+            "{}: targetLufs must be below 0 LUFS",
+            spec.name
+        );
+        anyhow::ensure!(
+            spec.max_true_peak.is_finite() && spec.max_true_peak <= 0.0,
+            "{}: maxTruePeak must be at most 0 dBTP",
+            spec.name
+        );
+        if let Some(tolerance) = spec.tolerance_lu {
+            anyhow::ensure!(
+                tolerance.is_finite() && tolerance >= 0.0,
+                "{}: toleranceLu must not be negative",
+                spec.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Loudness and peaks of a track
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Measurement {
+    pub integrated_lufs: f64,
+    pub loudness_range: f64,
+    pub true_peak: f64,
+    pub sample_peak: f64,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_secs: f64,
+}
+
+/// Measure loudness and peaks of an audio file as it is decoded
+#[tracing::instrument(name = "dsp.measure_loudness", skip_all)]
+pub fn measure_file(path: &Path) -> Result<Measurement> {
+    let mut decoder = AudioDecoder::open(path)?;
+    let (channels, sample_rate) = (decoder.channels, decoder.sample_rate);
+    let mut ebu = EbuR128::new(channels as u32, sample_rate, Mode::I | Mode::LRA)?;
+    let mut true_peak = TruePeakMeter::new(channels, sample_rate)?;
+    let mut sample_peak = 0.0f32;
+    let mut frames = 0;
+
+    let mut block = AudioBuffer::new(channels, sample_rate);
+    while decoder.decode_next(&mut block)? {
+        let planes: Vec<&[f32]> = block.samples.iter().map(Vec::as_slice).collect();
+        ebu.add_frames_planar_f32(&planes)?;
+        true_peak.add(&planes)?;
+        sample_peak = planes
+            .iter()
+            .flat_map(|plane| plane.iter())
+            .fold(sample_peak, |peak, s| peak.max(s.abs()));
+        frames += block.frame_count();
+        for channel in &mut block.samples {
+            channel.clear();
+        }
+    }
+
+    Ok(Measurement {
+        integrated_lufs: ebu.loudness_global().unwrap_or(-70.0),
+        loudness_range: ebu.loudness_range().unwrap_or(0.0),
+        true_peak: true_peak.finish()?.db(),
+        sample_peak: if sample_peak > 0.0 {
+            20.0 * (sample_peak as f64).log10()
+        } else {
+            -96.0
+        },
+        sample_rate,
+        channels,
+        duration_secs: frames as f64 / sample_rate as f64,
+    })
+}
+
+/// How a track fares against one platform's spec
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformResult {
+    #[serde(flatten)]
+    pub spec: PlatformSpec,
+    pub passes: bool,
+    pub loudness_passes: bool,
+    pub true_peak_passes: bool,
+    /// Gain the platform applies on playback (dB, 0 for specs with a
+    /// tolerance)
+    pub playback_gain_db: f64,
+    /// Gain needed to land within the tolerance (dB, 0 when it already does)
+    pub required_gain_db: f64,
+    /// True peak once both gains are applied (dBTP)
+    pub true_peak_after_gain: f64,
+    /// How much further peaks must come down to pass (dB)
+    pub true_peak_reduction_db: f64,
+}
+
+/// Evaluate a track's loudness and true peak against `spec`
+pub fn evaluate(spec: &PlatformSpec, integrated_lufs: f64, true_peak: f64) -> PlatformResult {
+    let offset = spec.target_lufs - integrated_lufs;
+    let (playback_gain_db, required_gain_db, loudness_passes) = match spec.tolerance_lu {
+        Some(tolerance) if offset.abs() > tolerance => (0.0, offset, false),
+        Some(_) => (0.0, 0.0, true),
+        None => (offset.min(0.0), 0.0, true),
+    };
+    let true_peak_after_gain = true_peak + playback_gain_db + required_gain_db;
+    let true_peak_passes = true_peak_after_gain <= spec.max_true_peak;
+    PlatformResult {
+        spec: spec.clone(),
+        passes: loudness_passes && true_peak_passes,
+        loudness_passes,
+        true_peak_passes,
+        playback_gain_db,
+        required_gain_db,
+        true_peak_after_gain,
+        true_peak_reduction_db: (true_peak_after_gain - spec.max_true_peak).max(0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalizing_and_gated_specs() {
+        let spotify = &PlatformSpec::defaults()[0];
+        // A loud master is turned down on playback, taking its peak with it
+        let loud = evaluate(spotify, -8.0, 0.5);
+        assert!(loud.passes);
+        assert_eq!(loud.playback_gain_db, -6.0);
+        assert_eq!(loud.true_peak_after_gain, -5.5);
+        // A quiet one isn't turned up, so only its own peak counts
+        let quiet = evaluate(spotify, -18.0, -0.2);
+        assert!(!quiet.passes && quiet.loudness_passes);
+        assert_eq!(quiet.playback_gain_db, 0.0);
+        assert!((quiet.true_peak_reduction_db - 0.8).abs() < 1e-9);
+
+        let r128 = PlatformSpec {
+            name: "EBU R128".to_string(),
+            target_lufs: -23.0,
+            max_true_peak: -1.0,
+            tolerance_lu: Some(0.5),
+        };
+        let result = evaluate(&r128, -14.0, -1.0);
+        assert!(!result.loudness_passes && result.true_peak_passes);
+        assert_eq!(result.required_gain_db, -9.0);
+        assert!(evaluate(&r128, -23.3, -3.0).passes);
+
+        assert!(validate(std::slice::from_ref(&r128)).is_ok());
+        let mut bad = r128;
+        bad.max_true_peak = 1.0;
+        assert!(validate(&[bad]).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+//! DSP worker settings
+//!
+//! On top of the shared worker config (`budi_worker_core::config`), the DSP
+//! worker reads detection and repair thresholds and the dry-run switch from
+//! `[dsp]`, the gates masters are checked against from `[qc]`, how stem
+//! separation runs Demucs from `[separation]`, the speech-to-text backend
+//! transcription uses from `[transcription]`, where duplicate detection
+//! keeps its fingerprint indexes from `[fingerprint]`, how WebAssembly
+//! plugins are sandboxed from `[plugins]`, the external processors
+//! pipelines may run from `[processors.<name>]`, and the CLAP plugins they
+//! may host (with the `clap` feature) from `[clap.<name>]`:
+//!
+//! ```toml
+//! [dsp]
+//! clip_threshold = 0.99
+//!
+//! [qc]
+//! true_peak_max = -1.0
+//! loudness_tolerance = 0.5
+//!
+//! [separation]
+//! model = "htdemucs_ft"
+//! device = "cuda"
+//!
+//! [transcription]
+//! backend = "openai"
+//!
+//! [fingerprint]
+//! key_prefix = "budi:fingerprints:"
+//!
+//! [plugins]
+//! max_memory_mb = 256
+//!
+//! [processors.denoise]
+//! command = "/opt/acme/bin/denoise"
+//! args = ["--strength", "0.5", "-", "-"]
+//! sandbox = ["bwrap", "--ro-bind", "/", "/", "--unshare-all", "--"]
+//!
+//! [clap.limiter]
+//! path = "/usr/lib/clap/AcmeLimiter.clap"
+//! presets = { loud = "/etc/budi/presets/limiter-loud.bin" }
+//! ```
+
+use anyhow::Result;
+use budi_worker_core::config::env_override;
+use budi_worker_core::Config;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Config file sections the DSP worker adds
+pub const SECTIONS: [&str; 8] = [
+    "dsp",
+    "qc",
+    "separation",
+    "transcription",
+    "fingerprint",
+    "plugins",
+    "processors",
+    "clap",
+];
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub dsp: DspSettings,
+    pub qc: QcGates,
+    pub separation: SeparationSettings,
+    pub transcription: TranscriptionSettings,
+    pub fingerprint: FingerprintSettings,
+    pub plugins: PluginSettings,
+    /// External processors by the name pipeline jobs use; file only
+    pub processors: HashMap<String, ProcessorSettings>,
+    /// CLAP plugins by the name pipeline jobs use; file only
+    pub clap: HashMap<String, ClapPluginSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DspSettings {
+    /// `DSP_DRY_RUN`: plan every fix and master job instead of rendering it,
+    /// as if each had `dryRun` set
+    pub dry_run: bool,
+    /// `DSP_CLIP_THRESHOLD`: sample level counted as clipped, and repaired
+    /// by the clip repair fix (linear, slightly below full scale to catch
+    /// near-clipping)
+    pub clip_threshold: f32,
+    /// `DSP_DC_OFFSET_THRESHOLD`: mean sample value reported as DC offset
+    pub dc_offset_threshold: f64,
+    /// `DSP_SILENCE_THRESHOLD`: level the silence trim treats as silent
+    /// (linear, 0.001 is -60 dBFS)
+    pub silence_threshold: f32,
+    /// `DSP_BATCH_CONCURRENCY`: tracks a batch-analyze job analyzes at once
+    pub batch_concurrency: usize,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            clip_threshold: 0.99,
+            dc_offset_threshold: 0.001,
+            silence_threshold: 0.001,
+            batch_concurrency: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QcGates {
+    /// `QC_TRUE_PEAK_MAX`: highest true peak a master passes with (dBTP),
+    /// also the limiter ceiling
+    pub true_peak_max: f64,
+    /// `QC_LOUDNESS_TOLERANCE`: how close to its target the limiter brings
+    /// a master's loudness (LU)
+    pub loudness_tolerance: f64,
+}
+
+impl Default for QcGates {
+    fn default() -> Self {
+        Self {
+            true_peak_max: -2.0,
+            loudness_tolerance: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeparationSettings {
+    /// `DEMUCS_PATH`: the Demucs command line tool
+    pub command: String,
+    /// `DEMUCS_MODEL`: pretrained model jobs use unless they name one
+    pub model: String,
+    /// `DEMUCS_DEVICE`: "cpu", "cuda" or "mps"
+    pub device: String,
+    /// `DEMUCS_SHIFTS`: random shifts averaged per prediction, each one
+    /// slower and slightly cleaner
+    pub shifts: u32,
+    /// `DEMUCS_TIMEOUT_SECONDS`: longest a separation may run (0: no limit)
+    pub timeout_seconds: u64,
+}
+
+impl Default for SeparationSettings {
+    fn default() -> Self {
+        Self {
+            command: "demucs".into(),
+            model: "htdemucs".into(),
+            device: "cpu".into(),
+            shifts: 1,
+            timeout_seconds: 3600,
+        }
+    }
+}
+
+/// Kind of speech-to-text server transcription posts audio to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SttBackend {
+    /// whisper.cpp's `whisper-server`
+    WhisperServer,
+    /// OpenAI's transcription API, or a server compatible with it
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+impl SttBackend {
+    /// Where the backend listens when no URL is configured
+    pub fn default_url(self) -> &'static str {
+        match self {
+            SttBackend::WhisperServer => "http://127.0.0.1:8080/inference",
+            SttBackend::OpenAi => "https://api.openai.com/v1/audio/transcriptions",
+        }
+    }
+}
+
+impl FromStr for SttBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "whisper-server" => Ok(SttBackend::WhisperServer),
+            "openai" => Ok(SttBackend::OpenAi),
+            other => Err(format!(
+                "expected \"whisper-server\" or \"openai\", not {:?}",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranscriptionSettings {
+    /// `STT_BACKEND`: "whisper-server" or "openai"
+    pub backend: SttBackend,
+    /// `STT_URL`: transcription endpoint (empty: the backend's default)
+    pub url: String,
+    /// `STT_API_KEY`: bearer token sent with each request, if any
+    pub api_key: String,
+    /// `STT_MODEL`: model the OpenAI API transcribes with
+    pub model: String,
+    /// `STT_TIMEOUT_SECONDS`: longest a transcription request may take
+    pub timeout_seconds: u64,
+    /// `STT_MAX_UPLOAD_MB`: largest audio file the backend accepts (0: no
+    /// limit)
+    pub max_upload_mb: u64,
+}
+
+impl Default for TranscriptionSettings {
+    fn default() -> Self {
+        Self {
+            backend: SttBackend::WhisperServer,
+            url: String::new(),
+            api_key: String::new(),
+            model: "whisper-1".into(),
+            timeout_seconds: 1800,
+            max_upload_mb: 25,
+        }
+    }
+}
+
+impl TranscriptionSettings {
+    pub fn url(&self) -> &str {
+        if self.url.is_empty() {
+            self.backend.default_url()
+        } else {
+            &self.url
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FingerprintSettings {
+    /// `FINGERPRINT_REDIS_URL`: Redis holding the fingerprint indexes
+    /// (empty: the queue's `REDIS_URL`)
+    pub redis_url: String,
+    /// `FINGERPRINT_KEY_PREFIX`: prefix of each index's hash key, before
+    /// the index name
+    pub key_prefix: String,
+    /// `FINGERPRINT_SCAN_COUNT`: fingerprints fetched from an index per
+    /// round trip
+    pub scan_count: usize,
+}
+
+impl Default for FingerprintSettings {
+    fn default() -> Self {
+        Self {
+            redis_url: String::new(),
+            key_prefix: "budi:fingerprints:".into(),
+            scan_count: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginSettings {
+    /// `PLUGIN_RUNTIME`: the Wasmtime command line tool plugins run in
+    pub command: String,
+    /// `PLUGIN_TIMEOUT_SECONDS`: longest one plugin stage may run
+    pub timeout_seconds: u64,
+    /// `PLUGIN_MAX_MEMORY_MB`: linear memory a plugin may grow to
+    pub max_memory_mb: u64,
+    /// `PLUGIN_MAX_MODULE_MB`: largest plugin module a job may supply
+    pub max_module_mb: u64,
+}
+
+impl Default for PluginSettings {
+    fn default() -> Self {
+        Self {
+            command: "wasmtime".into(),
+            timeout_seconds: 300,
+            max_memory_mb: 1024,
+            max_module_mb: 50,
+        }
+    }
+}
+
+/// How audio is passed to and from an external processor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProcessorFormat {
+    /// A 32-bit float WAV file
+    #[default]
+    Wav,
+    /// The `BPA1` header and interleaved f32 frames plugins use
+    F32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessorSettings {
+    /// The tool, reading audio on stdin and writing it on stdout
+    pub command: String,
+    pub args: Vec<String>,
+    pub format: ProcessorFormat,
+    /// Longest one run may take
+    pub timeout_seconds: u64,
+    /// Run with only `env` (and `PATH`) in the environment, so the
+    /// worker's credentials don't reach the tool
+    pub clear_env: bool,
+    pub env: HashMap<String, String>,
+    /// Command line the tool runs inside, e.g. a `bwrap`, `firejail` or
+    /// `prlimit` invocation ending where the tool's command line starts
+    pub sandbox: Vec<String>,
+}
+
+impl Default for ProcessorSettings {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            args: Vec::new(),
+            format: ProcessorFormat::Wav,
+            timeout_seconds: 600,
+            clear_env: true,
+            env: HashMap::new(),
+            sandbox: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClapPluginSettings {
+    /// The plugin's `.clap` library
+    pub path: String,
+    /// Plugin ID within the library, when it holds more than one (the
+    /// first otherwise)
+    pub id: Option<String>,
+    /// Saved plugin states by the name pipeline jobs use
+    pub presets: HashMap<String, String>,
+}
+
+impl Settings {
+    /// Read the `[dsp]`, `[qc]`, `[separation]`, `[transcription]`,
+    /// `[fingerprint]`, `[plugins]`, `[processors]` and `[clap]` sections
+    /// and their environment overrides
+    pub fn load(config: &Config) -> Result<Self> {
+        let mut settings = Self {
+            dsp: config.section("dsp")?,
+            qc: config.section("qc")?,
+            separation: config.section("separation")?,
+            transcription: config.section("transcription")?,
+            fingerprint: config.section("fingerprint")?,
+            plugins: config.section("plugins")?,
+            processors: config.section("processors")?,
+            clap: config.section("clap")?,
+        };
+        let dsp = &mut settings.dsp;
+        env_override(&mut dsp.dry_run, "DSP_DRY_RUN")?;
+        env_override(&mut dsp.clip_threshold, "DSP_CLIP_THRESHOLD")?;
+        env_override(&mut dsp.dc_offset_threshold, "DSP_DC_OFFSET_THRESHOLD")?;
+        env_override(&mut dsp.silence_threshold, "DSP_SILENCE_THRESHOLD")?;
+        env_override(&mut dsp.batch_concurrency, "DSP_BATCH_CONCURRENCY")?;
+        env_override(&mut settings.qc.true_peak_max, "QC_TRUE_PEAK_MAX")?;
+        env_override(&mut settings.qc.loudness_tolerance, "QC_LOUDNESS_TOLERANCE")?;
+        let separation = &mut settings.separation;
+        env_override(&mut separation.command, "DEMUCS_PATH")?;
+        env_override(&mut separation.model, "DEMUCS_MODEL")?;
+        env_override(&mut separation.device, "DEMUCS_DEVICE")?;
+        env_override(&mut separation.shifts, "DEMUCS_SHIFTS")?;
+        env_override(&mut separation.timeout_seconds, "DEMUCS_TIMEOUT_SECONDS")?;
+        let transcription = &mut settings.transcription;
+        env_override(&mut transcription.backend, "STT_BACKEND")?;
+        env_override(&mut transcription.url, "STT_URL")?;
+        env_override(&mut transcription.api_key, "STT_API_KEY")?;
+        env_override(&mut transcription.model, "STT_MODEL")?;
+        env_override(&mut transcription.timeout_seconds, "STT_TIMEOUT_SECONDS")?;
+        env_override(&mut transcription.max_upload_mb, "STT_MAX_UPLOAD_MB")?;
+        let fingerprint = &mut settings.fingerprint;
+        env_override(&mut fingerprint.redis_url, "FINGERPRINT_REDIS_URL")?;
+        env_override(&mut fingerprint.key_prefix, "FINGERPRINT_KEY_PREFIX")?;
+        env_override(&mut fingerprint.scan_count, "FINGERPRINT_SCAN_COUNT")?;
+        if fingerprint.redis_url.is_empty() {
+            fingerprint.redis_url = config.redis.url.clone();
+        }
+        let plugins = &mut settings.plugins;
+        env_override(&mut plugins.command, "PLUGIN_RUNTIME")?;
+        env_override(&mut plugins.timeout_seconds, "PLUGIN_TIMEOUT_SECONDS")?;
+        env_override(&mut plugins.max_memory_mb, "PLUGIN_MAX_MEMORY_MB")?;
+        env_override(&mut plugins.max_module_mb, "PLUGIN_MAX_MODULE_MB")?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let level = |x: f64| x > 0.0 && x < 1.0;
+        anyhow::ensure!(
+            self.dsp.clip_threshold > 0.0 && self.dsp.clip_threshold <= 1.0,
+            "dsp.clip_threshold (DSP_CLIP_THRESHOLD) must be above 0 and at most 1"
+        );
+        anyhow::ensure!(
+            level(self.dsp.dc_offset_threshold),
+            "dsp.dc_offset_threshold (DSP_DC_OFFSET_THRESHOLD) must be between 0 and 1"
+        );
+        anyhow::ensure!(
+            level(self.dsp.silence_threshold as f64),
+            "dsp.silence_threshold (DSP_SILENCE_THRESHOLD) must be between 0 and 1"
+        );
+        anyhow::ensure!(
+            self.dsp.batch_concurrency >= 1,
+            "dsp.batch_concurrency (DSP_BATCH_CONCURRENCY) must be at least 1"
+        );
+        anyhow::ensure!(
+            self.qc.true_peak_max.is_finite() && self.qc.true_peak_max <= 0.0,
+            "qc.true_peak_max (QC_TRUE_PEAK_MAX) must be at most 0 dBTP"
+        );
+        anyhow::ensure!(
+            self.qc.loudness_tolerance.is_finite() && self.qc.loudness_tolerance > 0.0,
+            "qc.loudness_tolerance (QC_LOUDNESS_TOLERANCE) must be above 0 LU"
+        );
+        anyhow::ensure!(
+            !self.separation.command.trim().is_empty(),
+            "separation.command (DEMUCS_PATH) must not be empty"
+        );
+        anyhow::ensure!(
+            self.separation.shifts >= 1,
+            "separation.shifts (DEMUCS_SHIFTS) must be at least 1"
+        );
+        anyhow::ensure!(
+            self.transcription.backend != SttBackend::OpenAi
+                || !self.transcription.model.trim().is_empty(),
+            "transcription.model (STT_MODEL) must not be empty with the openai backend"
+        );
+        anyhow::ensure!(
+            self.transcription.timeout_seconds >= 1,
+            "transcription.timeout_seconds (STT_TIMEOUT_SECONDS) must be at least 1"
+        );
+        anyhow::ensure!(
+            self.fingerprint.scan_count >= 1,
+            "fingerprint.scan_count (FINGERPRINT_SCAN_COUNT) must be at least 1"
+        );
+        anyhow::ensure!(
+            !self.plugins.command.trim().is_empty(),
+            "plugins.command (PLUGIN_RUNTIME) must not be empty"
+        );
+        anyhow::ensure!(
+            self.plugins.timeout_seconds >= 1,
+            "plugins.timeout_seconds (PLUGIN_TIMEOUT_SECONDS) must be at least 1"
+        );
+        anyhow::ensure!(
+            self.plugins.max_memory_mb >= 1 && self.plugins.max_module_mb >= 1,
+            "plugins.max_memory_mb and plugins.max_module_mb (PLUGIN_MAX_MEMORY_MB, \
+             PLUGIN_MAX_MODULE_MB) must be at least 1"
+        );
+        for (name, processor) in &self.processors {
+            anyhow::ensure!(
+                !processor.command.trim().is_empty(),
+                "processors.{}.command must not be empty",
+                name
+            );
+            anyhow::ensure!(
+                processor.timeout_seconds >= 1,
+                "processors.{}.timeout_seconds must be at least 1",
+                name
+            );
+        }
+        for (name, plugin) in &self.clap {
+            anyhow::ensure!(
+                !plugin.path.trim().is_empty(),
+                "clap.{}.path must not be empty",
+                name
+            );
+            for (preset, path) in &plugin.presets {
+                anyhow::ensure!(
+                    !path.trim().is_empty(),
+                    "clap.{}.presets.{} must not be empty",
+                    name,
+                    preset
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Use `settings` for the rest of the process, passing the thresholds and
+/// QC gates on to `budi_dsp_core`
+pub fn init(settings: Settings) {
+    budi_dsp_core::settings::init(budi_dsp_core::Settings {
+        clip_threshold: settings.dsp.clip_threshold,
+        dc_offset_threshold: settings.dsp.dc_offset_threshold,
+        silence_threshold: settings.dsp.silence_threshold,
+        true_peak_max: settings.qc.true_peak_max,
+        loudness_tolerance: settings.qc.loudness_tolerance,
+    });
+    let _ = SETTINGS.set(settings);
+}
+
+/// This process's settings, or the defaults if `init` wasn't called
+pub fn get() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
@@ -0,0 +1,553 @@
+//! DDP 2.0 fileset generation for CD replication
+//!
+//! The fileset consists of the audio image (`IMAGE.DAT`, 16-bit 44.1 kHz
+//! stereo little-endian PCM, sector-aligned), the DDP identifier (`DDPID`),
+//! the map stream (`DDPMS`), the PQ descriptor (`PQDESCR`) and, when any text
+//! is given, a CD-Text stream (`CDTEXT.BIN`). All files are packaged into one
+//! ZIP archive.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+use crate::audio;
+use crate::tags::normalize_isrc;
+use crate::types::{AudioBuffer, Dither};
+
+/// CD audio sample rate (Hz)
+pub const CD_SAMPLE_RATE: u32 = 44100;
+
+/// Stereo frames per CD-DA sector
+const FRAMES_PER_SECTOR: usize = 588;
+/// Bytes per CD-DA sector (16-bit stereo)
+const SECTOR_BYTES: usize = FRAMES_PER_SECTOR * 4;
+const SECTORS_PER_SECOND: u64 = 75;
+
+/// Red Book requires at least two seconds of pause before the first track
+const MIN_FIRST_PAUSE_SECTORS: u64 = 2 * SECTORS_PER_SECOND;
+
+/// Longest program a CD-R master can hold (79:59:74)
+const MAX_PROGRAM_SECTORS: u64 = 80 * 60 * SECTORS_PER_SECOND - 1;
+
+const MAX_TRACKS: usize = 99;
+
+/// CD-Text pack types
+const CDTEXT_TITLE: u8 = 0x80;
+const CDTEXT_PERFORMER: u8 = 0x81;
+const CDTEXT_SIZE_INFO: u8 = 0x8F;
+
+/// CD-Text packs a single language block may hold
+const CDTEXT_MAX_PACKS: usize = 253;
+
+/// Per-track settings for the DDP image
+#[derive(Debug, Clone, Default)]
+pub struct DdpTrack {
+    pub isrc: Option<String>,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Pause (index 0) before the track (seconds)
+    pub pause_secs: f64,
+}
+
+/// Disc-level settings for the DDP image
+#[derive(Debug, Clone, Default)]
+pub struct DdpAlbum {
+    pub upc: Option<String>,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+}
+
+/// Position of a track on the disc, reported in the export manifest
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DdpTrackOffset {
+    pub track_number: usize,
+    /// Index 0 (pause start) as MM:SS:FF
+    pub pause_start: String,
+    /// Index 1 (audio start) as MM:SS:FF
+    pub start: String,
+    pub pause_secs: f64,
+    pub isrc: Option<String>,
+}
+
+/// Summary of a finished DDP fileset
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DdpSummary {
+    pub track_count: usize,
+    /// Total program length as MM:SS:FF
+    pub program_length: String,
+    pub has_cd_text: bool,
+    pub tracks: Vec<DdpTrackOffset>,
+}
+
+struct TrackEntry {
+    pause_sector: u64,
+    start_sector: u64,
+    isrc: Option<String>,
+    title: Option<String>,
+    performer: Option<String>,
+}
+
+/// Builds the audio image track by track, then writes the descriptors
+pub struct DdpBuilder {
+    dir: PathBuf,
+    image: BufWriter<File>,
+    sectors: u64,
+    tracks: Vec<TrackEntry>,
+}
+
+impl DdpBuilder {
+    /// Start a fileset in `dir`, which must be empty
+    pub fn create(dir: &Path) -> Result<Self> {
+        let image = File::create(dir.join("IMAGE.DAT")).context("Failed to create DDP image")?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            image: BufWriter::new(image),
+            sectors: 0,
+            tracks: Vec::new(),
+        })
+    }
+
+    /// Append a track (44.1 kHz) after its pause, dithered to 16 bits
+    pub fn append_track(&mut self, buffer: &AudioBuffer, track: &DdpTrack) -> Result<()> {
+        if buffer.sample_rate != CD_SAMPLE_RATE {
+            bail!(
+                "DDP tracks must be {} Hz, got {} Hz",
+                CD_SAMPLE_RATE,
+                buffer.sample_rate
+            );
+        }
+        if self.tracks.len() == MAX_TRACKS {
+            bail!("A CD holds at most {} tracks", MAX_TRACKS);
+        }
+
+        let stereo = match buffer.channels {
+            2 => None,
+            1 => Some(AudioBuffer {
+                samples: vec![buffer.samples[0].clone(), buffer.samples[0].clone()],
+                sample_rate: buffer.sample_rate,
+                channels: 2,
+            }),
+            n => bail!("DDP tracks must be mono or stereo, got {} channels", n),
+        };
+        let pcm = audio::quantize_16(stereo.as_ref().unwrap_or(buffer), Dither::Tpdf);
+
+        let mut pause_sectors =
+            (track.pause_secs.max(0.0) * SECTORS_PER_SECOND as f64).round() as u64;
+        if self.tracks.is_empty() {
+            pause_sectors = pause_sectors.max(MIN_FIRST_PAUSE_SECTORS);
+        }
+        let pause_sector = self.sectors;
+        self.write_silence(pause_sectors)?;
+        let start_sector = self.sectors;
+
+        let mut bytes = Vec::with_capacity(pcm.len() * 2);
+        for sample in &pcm {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        // Pad the last sector with silence
+        let sectors = buffer.frame_count().div_ceil(FRAMES_PER_SECTOR);
+        bytes.resize(sectors * SECTOR_BYTES, 0);
+        self.image.write_all(&bytes)?;
+        self.sectors += sectors as u64;
+
+        if self.sectors > MAX_PROGRAM_SECTORS {
+            bail!("Program exceeds the maximum CD length of 79:59:74");
+        }
+
+        self.tracks.push(TrackEntry {
+            pause_sector,
+            start_sector,
+            isrc: track.isrc.as_deref().map(normalize_isrc).transpose()?,
+            title: track.title.clone(),
+            performer: track.performer.clone(),
+        });
+        Ok(())
+    }
+
+    fn write_silence(&mut self, sectors: u64) -> Result<()> {
+        let silence = [0u8; SECTOR_BYTES];
+        for _ in 0..sectors {
+            self.image.write_all(&silence)?;
+        }
+        self.sectors += sectors;
+        Ok(())
+    }
+
+    /// Write the descriptors and package the fileset into `archive_path`
+    pub fn finish(mut self, album: &DdpAlbum, archive_path: &Path) -> Result<DdpSummary> {
+        if self.tracks.is_empty() {
+            bail!("DDP image has no tracks");
+        }
+        self.image.flush()?;
+        drop(self.image);
+
+        let upc = match &album.upc {
+            Some(upc) if upc.len() == 12 || upc.len() == 13 => {
+                if !upc.chars().all(|c| c.is_ascii_digit()) {
+                    bail!("Invalid UPC/EAN: {}", upc);
+                }
+                format!("{:0>13}", upc)
+            }
+            Some(upc) => bail!("Invalid UPC/EAN: {}", upc),
+            None => String::new(),
+        };
+
+        let pq = pq_descriptor(&self.tracks, self.sectors, &upc);
+        let cd_text = cd_text(album, &self.tracks)?;
+
+        let mut streams = vec![MapStream {
+            stream_type: "D0",
+            subcode: "",
+            name: "IMAGE.DAT",
+            length: self.sectors * SECTOR_BYTES as u64,
+        }];
+        streams.push(MapStream {
+            stream_type: "S0",
+            subcode: "PQ DESCR",
+            name: "PQDESCR",
+            length: pq.len() as u64,
+        });
+        if let Some(cd_text) = &cd_text {
+            streams.push(MapStream {
+                stream_type: "S0",
+                subcode: "CDTEXT",
+                name: "CDTEXT.BIN",
+                length: cd_text.len() as u64,
+            });
+        }
+        let map: Vec<u8> = streams.iter().flat_map(MapStream::encode).collect();
+
+        std::fs::write(self.dir.join("DDPID"), ddp_id(&upc, map.len()))?;
+        std::fs::write(self.dir.join("DDPMS"), &map)?;
+        std::fs::write(self.dir.join("PQDESCR"), &pq)?;
+        if let Some(cd_text) = &cd_text {
+            std::fs::write(self.dir.join("CDTEXT.BIN"), cd_text)?;
+        }
+
+        // DDP files are stored uncompressed so the archive can be verified as-is
+        let mut zip = zip::ZipWriter::new(File::create(archive_path)?);
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for name in ["DDPID", "DDPMS", "PQDESCR", "CDTEXT.BIN", "IMAGE.DAT"] {
+            let path = self.dir.join(name);
+            if !path.exists() {
+                continue;
+            }
+            zip.start_file(name, options)?;
+            std::io::copy(&mut File::open(&path)?, &mut zip)?;
+        }
+        zip.finish()?;
+
+        Ok(DdpSummary {
+            track_count: self.tracks.len(),
+            program_length: msf(self.sectors),
+            has_cd_text: cd_text.is_some(),
+            tracks: self
+                .tracks
+                .iter()
+                .enumerate()
+                .map(|(i, t)| DdpTrackOffset {
+                    track_number: i + 1,
+                    pause_start: msf(t.pause_sector),
+                    start: msf(t.start_sector),
+                    pause_secs: (t.start_sector - t.pause_sector) as f64
+                        / SECTORS_PER_SECOND as f64,
+                    isrc: t.isrc.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// One 128-byte DDPMS map packet
+struct MapStream {
+    stream_type: &'static str,
+    subcode: &'static str,
+    name: &'static str,
+    length: u64,
+}
+
+impl MapStream {
+    fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(128);
+        text(&mut packet, "VVVM", 4); // MPV: map packet valid
+        text(&mut packet, self.stream_type, 2); // DST: data stream type
+        number(&mut packet, 0, 8); // DSP: data stream pointer
+        number(&mut packet, self.length, 8); // DSL: data stream length (bytes)
+        number(&mut packet, 0, 8); // DSS: data stream start
+        text(&mut packet, self.subcode, 8); // SUB: subcode descriptor
+        text(&mut packet, "DA", 2); // CDM: CD-DA mode
+        text(&mut packet, "0", 1); // SSM: source storage mode
+        text(&mut packet, "0", 1); // SCR: not scrambled
+        text(&mut packet, "", 4); // PRE1
+        text(&mut packet, "", 4); // PRE2
+        text(&mut packet, "", 4); // PST
+        text(&mut packet, "", 1); // MED
+        text(&mut packet, "", 2); // TRK
+        text(&mut packet, "", 2); // IDX
+        text(&mut packet, "", 12); // ISRC
+        text(&mut packet, "", 3); // SIZ
+        text(&mut packet, self.name, 17); // DSN: data stream name
+        text(&mut packet, "", 1); // NEW
+        text(&mut packet, "", 4); // PRE1NXT
+        text(&mut packet, "", 8); // PAUSEADD
+        text(&mut packet, "", 9); // OFS
+        text(&mut packet, "", 15); // PAD
+        packet
+    }
+}
+
+/// The 128-byte DDPID identifier
+fn ddp_id(upc: &str, map_len: usize) -> Vec<u8> {
+    let mut id = Vec::with_capacity(128);
+    text(&mut id, "DDP 2.00", 8); // DDPID: level
+    text(&mut id, upc, 13); // UPC/EAN
+    text(&mut id, "", 8); // MSS: map stream start
+    number(&mut id, map_len as u64, 8); // MSL: map stream length
+    text(&mut id, "0", 1); // MED: media number
+    text(&mut id, "", 48); // MID: master ID
+    text(&mut id, "", 1); // BK
+    text(&mut id, "CD", 2); // TYPE
+    text(&mut id, "1", 1); // NSIDE
+    text(&mut id, "0", 1); // SIDE
+    text(&mut id, "1", 1); // NLAYER
+    text(&mut id, "0", 1); // LAYER
+    text(&mut id, "", 1); // DIR
+    text(&mut id, "Budi", 34); // TXT: user text
+    id
+}
+
+/// PQ descriptor: one 64-byte entry per index point plus the lead-out
+fn pq_descriptor(tracks: &[TrackEntry], total_sectors: u64, upc: &str) -> Vec<u8> {
+    let mut pq = Vec::new();
+    for (i, track) in tracks.iter().enumerate() {
+        let number = format!("{:02}", i + 1);
+        if track.start_sector > track.pause_sector {
+            pq_entry(
+                &mut pq,
+                &number,
+                "00",
+                track.pause_sector,
+                None,
+                if i == 0 { upc } else { "" },
+            );
+        }
+        pq_entry(
+            &mut pq,
+            &number,
+            "01",
+            track.start_sector,
+            track.isrc.as_deref(),
+            "",
+        );
+    }
+    pq_entry(&mut pq, "AA", "01", total_sectors, None, "");
+    pq
+}
+
+fn pq_entry(
+    out: &mut Vec<u8>,
+    track: &str,
+    index: &str,
+    sector: u64,
+    isrc: Option<&str>,
+    upc: &str,
+) {
+    let frames = sector % SECTORS_PER_SECOND;
+    let seconds = sector / SECTORS_PER_SECOND;
+    text(out, "VVVS", 4); // SPV: subcode packet valid
+    text(out, track, 2); // TRK
+    text(out, index, 2); // IDX
+    number(out, seconds / 3600, 2); // HRS
+    number(out, seconds / 60 % 60, 2); // MIN
+    number(out, seconds % 60, 2); // SEC
+    number(out, frames, 2); // FRM
+    text(out, "00", 2); // CB1: audio, no pre-emphasis, copy prohibited
+    text(out, "00", 2); // CB2
+    text(out, isrc.unwrap_or(""), 12); // ISRC
+    text(out, upc, 13); // UPC/EAN
+    text(out, "", 19); // TXT
+}
+
+/// Encode album and track titles/performers as CD-Text packs (block 0, English)
+fn cd_text(album: &DdpAlbum, tracks: &[TrackEntry]) -> Result<Option<Vec<u8>>> {
+    let titles: Vec<&str> = std::iter::once(album.title.as_deref())
+        .chain(tracks.iter().map(|t| t.title.as_deref()))
+        .map(|t| t.unwrap_or(""))
+        .collect();
+    let performers: Vec<&str> = std::iter::once(album.performer.as_deref())
+        .chain(tracks.iter().map(|t| t.performer.as_deref()))
+        .map(|t| t.unwrap_or(""))
+        .collect();
+
+    let mut packs: Vec<[u8; 18]> = Vec::new();
+    let mut counts = [0u8; 16];
+    for (pack_type, texts) in [(CDTEXT_TITLE, &titles), (CDTEXT_PERFORMER, &performers)] {
+        if texts.iter().all(|t| t.is_empty()) {
+            continue;
+        }
+        let before = packs.len();
+        text_packs(&mut packs, pack_type, texts)?;
+        counts[(pack_type & 0x0F) as usize] = (packs.len() - before) as u8;
+    }
+    if packs.is_empty() {
+        return Ok(None);
+    }
+
+    // Size information: three packs describing the block
+    counts[(CDTEXT_SIZE_INFO & 0x0F) as usize] = 3;
+    let total = packs.len() + 3;
+    if total > CDTEXT_MAX_PACKS {
+        bail!("CD-Text exceeds {} packs", CDTEXT_MAX_PACKS);
+    }
+    let mut info = [0u8; 36];
+    info[0] = 0x00; // ISO 8859-1
+    info[1] = 1;
+    info[2] = tracks.len() as u8;
+    info[4..20].copy_from_slice(&counts);
+    info[20] = (total - 1) as u8; // last sequence number of block 0
+    info[28] = 0x09; // English
+    for (n, chunk) in info.chunks(12).enumerate() {
+        let mut pack = [0u8; 18];
+        pack[0] = CDTEXT_SIZE_INFO;
+        pack[1] = n as u8;
+        pack[2] = packs.len() as u8;
+        pack[4..16].copy_from_slice(chunk);
+        packs.push(pack);
+    }
+
+    let mut out = Vec::with_capacity(packs.len() * 18);
+    for mut pack in packs {
+        let crc = !crc16_ccitt(&pack[..16]);
+        pack[16..].copy_from_slice(&crc.to_be_bytes());
+        out.extend_from_slice(&pack);
+    }
+    Ok(Some(out))
+}
+
+/// Split NUL-terminated strings (disc first, then tracks) into 12-byte packs
+fn text_packs(packs: &mut Vec<[u8; 18]>, pack_type: u8, texts: &[&str]) -> Result<()> {
+    // (track, byte, position) for every character, so each pack knows which
+    // track its first character belongs to and how far into it it starts
+    let mut chars: Vec<(u8, u8, usize)> = Vec::new();
+    for (track, text) in texts.iter().enumerate() {
+        if !text.is_ascii() {
+            bail!("CD-Text supports ASCII only: {:?}", text);
+        }
+        for (pos, byte) in text.bytes().chain(std::iter::once(0)).enumerate() {
+            chars.push((track as u8, byte, pos));
+        }
+    }
+
+    for chunk in chars.chunks(12) {
+        let (track, _, position) = chunk[0];
+        let mut pack = [0u8; 18];
+        pack[0] = pack_type;
+        pack[1] = track;
+        pack[2] = packs.len() as u8;
+        pack[3] = position.min(15) as u8;
+        for (dst, (_, byte, _)) in pack[4..16].iter_mut().zip(chunk) {
+            *dst = *byte;
+        }
+        packs.push(pack);
+    }
+    Ok(())
+}
+
+/// CRC-16/CCITT (polynomial 0x1021, zero initial value) used by CD-Text
+fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Format a sector count as MM:SS:FF
+fn msf(sector: u64) -> String {
+    let seconds = sector / SECTORS_PER_SECOND;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 60,
+        seconds % 60,
+        sector % SECTORS_PER_SECOND
+    )
+}
+
+/// Append a left-aligned, space-padded ASCII field
+fn text(out: &mut Vec<u8>, value: &str, len: usize) {
+    let bytes = value.as_bytes();
+    let n = bytes.len().min(len);
+    out.extend_from_slice(&bytes[..n]);
+    out.resize(out.len() + len - n, b' ');
+}
+
+/// Append a right-aligned, zero-padded decimal field
+fn number(out: &mut Vec<u8>, value: u64, len: usize) {
+    text(out, &format!("{:0>width$}", value, width = len), len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ddp_track_offsets_and_descriptors() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = DdpBuilder::create(dir.path()).unwrap();
+
+        let mut buffer = AudioBuffer::new(2, CD_SAMPLE_RATE);
+        for ch in 0..2 {
+            buffer.samples[ch] = vec![0.1; FRAMES_PER_SECTOR * 75 * 3 + 10];
+        }
+        let track = DdpTrack {
+            isrc: Some("US-S1Z-99-00001".to_string()),
+            title: Some("Intro".to_string()),
+            ..Default::default()
+        };
+        builder.append_track(&buffer, &track).unwrap();
+        builder
+            .append_track(
+                &buffer,
+                &DdpTrack {
+                    pause_secs: 1.0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let archive = dir.path().join("ddp.zip");
+        let summary = builder.finish(&DdpAlbum::default(), &archive).unwrap();
+
+        // 2 s pregap, 3 s + 1 padded sector of audio, 1 s pause
+        assert_eq!(summary.tracks[0].start, "00:02:00");
+        assert_eq!(summary.tracks[1].pause_start, "00:05:01");
+        assert_eq!(summary.tracks[1].start, "00:06:01");
+        assert!(summary.has_cd_text);
+
+        let pq = std::fs::read(dir.path().join("PQDESCR")).unwrap();
+        assert_eq!(pq.len(), 64 * 5);
+        assert_eq!(&pq[..8], b"VVVS0100");
+        assert_eq!(&pq[64 + 20..64 + 32], b"USS1Z9900001");
+        assert_eq!(
+            std::fs::read(dir.path().join("DDPMS")).unwrap().len(),
+            128 * 3
+        );
+        assert_eq!(
+            std::fs::read(dir.path().join("CDTEXT.BIN")).unwrap().len() % 18,
+            0
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! External processors: existing command line tools as pipeline stages
+//!
+//! Teams with a tool of their own (a proprietary de-noiser, say) configure
+//! it under `[processors.<name>]` and run it as an `external` stage of a
+//! pipeline job. Jobs only ever name a configured processor; the command,
+//! its arguments and its sandbox come from the worker's config.
+//!
+//! The tool reads the audio on stdin and writes the processed audio on
+//! stdout, either as a 32-bit float WAV or in the `BPA1` framing plugins use
+//! (see `plugin`), with the same channel count and sample rate. It runs in
+//! an empty working directory of its own, with the job's `params` as JSON in
+//! `BUDI_PARAMS`, and with only `PATH` and the configured `env` unless
+//! `clear_env` is off. A non-zero exit fails the job; the last lines of
+//! stderr say why.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::config::{ProcessorFormat, ProcessorSettings};
+use crate::plugin;
+use crate::types::AudioBuffer;
+
+/// Stderr lines kept when a processor fails
+const ERROR_LINES: usize = 3;
+
+/// Run processor `name` on `buffer`, replacing it with the output; files go
+/// under `dir`
+#[tracing::instrument(name = "dsp.external", skip_all, fields(processor = name))]
+pub async fn process(
+    name: &str,
+    settings: &ProcessorSettings,
+    params: &serde_json::Value,
+    buffer: &mut AudioBuffer,
+    dir: &Path,
+) -> Result<()> {
+    let work_dir = dir.join(format!("{}_work", name));
+    std::fs::create_dir_all(&work_dir)?;
+    let extension = match settings.format {
+        ProcessorFormat::Wav => "wav",
+        ProcessorFormat::F32 => "f32",
+    };
+    let input_path = dir.join(format!("{}_in.{}", name, extension));
+    let output_path = dir.join(format!("{}_out.{}", name, extension));
+    match settings.format {
+        ProcessorFormat::Wav => budi_worker_core::audio::write_wav_f32(buffer, &input_path)?,
+        ProcessorFormat::F32 => std::fs::write(&input_path, plugin::encode(buffer))?,
+    }
+
+    let (program, args) = match settings.sandbox.split_first() {
+        Some((sandbox, sandbox_args)) => {
+            let mut args = sandbox_args.to_vec();
+            args.push(settings.command.clone());
+            args.extend(settings.args.iter().cloned());
+            (sandbox.clone(), args)
+        }
+        None => (settings.command.clone(), settings.args.clone()),
+    };
+    let mut command = tokio::process::Command::new(&program);
+    command.args(&args).current_dir(&work_dir);
+    if settings.clear_env {
+        command.env_clear();
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+    }
+    command
+        .envs(&settings.env)
+        .env("BUDI_PARAMS", params.to_string())
+        .stdin(std::fs::File::open(&input_path)?)
+        .stdout(std::fs::File::create(&output_path)?)
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    // Not `output()`, which would pipe stdout back to the worker
+    let child = command
+        .spawn()
+        .with_context(|| format!("Failed to run processor {} ({})", name, program))?;
+    let output = tokio::time::timeout(
+        Duration::from_secs(settings.timeout_seconds),
+        child.wait_with_output(),
+    )
+    .await
+    .map_err(|_| {
+        anyhow::anyhow!(
+            "Processor {} didn't finish within {} seconds",
+            name,
+            settings.timeout_seconds
+        )
+    })?
+    .with_context(|| format!("Processor {} failed to finish", name))?;
+    std::fs::remove_file(&input_path).ok();
+    std::fs::remove_dir_all(&work_dir).ok();
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Processor {} failed ({}): {}",
+            name,
+            output.status,
+            error_summary(&output.stderr)
+        );
+    }
+
+    let samples = match settings.format {
+        ProcessorFormat::Wav => {
+            let processed = budi_worker_core::audio::read_audio_file(&output_path)
+                .with_context(|| format!("Processor {} wrote no readable WAV", name))?;
+            anyhow::ensure!(
+                processed.channels == buffer.channels
+                    && processed.sample_rate == buffer.sample_rate,
+                "Processor {} wrote {} channels at {} Hz, not {} at {} Hz",
+                name,
+                processed.channels,
+                processed.sample_rate,
+                buffer.channels,
+                buffer.sample_rate
+            );
+            processed.samples
+        }
+        ProcessorFormat::F32 => plugin::decode(&std::fs::read(&output_path)?, buffer)
+            .with_context(|| format!("Processor {} wrote unusable audio", name))?,
+    };
+    std::fs::remove_file(&output_path).ok();
+    buffer.samples = samples;
+    Ok(())
+}
+
+/// The last few lines of a processor's stderr
+fn error_summary(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(ERROR_LINES)..].join(" / ")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process_pipes_audio_through_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut buffer = AudioBuffer::new(2, 48000);
+        buffer.samples = vec![vec![0.5, -1.5, 0.25], vec![0.0, 0.1, -0.1]];
+        let original = buffer.samples.clone();
+
+        // `cat` stands in for a tool that leaves the audio as it is, in
+        // both formats, with the worker's environment withheld
+        for format in [ProcessorFormat::Wav, ProcessorFormat::F32] {
+            let settings = ProcessorSettings {
+                command: "cat".into(),
+                format,
+                ..Default::default()
+            };
+            process(
+                "copy",
+                &settings,
+                &serde_json::Value::Null,
+                &mut buffer,
+                dir.path(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(buffer.samples, original);
+        }
+
+        // The params arrive in the environment; a failure reports stderr
+        let settings = ProcessorSettings {
+            command: "sh".into(),
+            args: vec![
+                "-c".into(),
+                "cat > /dev/null; echo \"rejected $BUDI_PARAMS\" >&2; exit 3".into(),
+            ],
+            ..Default::default()
+        };
+        let err = process(
+            "reject",
+            &settings,
+            &serde_json::json!({"strength": 2}),
+            &mut buffer,
+            dir.path(),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string().contains(r#"rejected {"strength":2}"#),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Acoustic fingerprints for duplicate detection
+//!
+//! Each `HOP_SECONDS` step of a track gets a 32-bit sub-fingerprint: one bit
+//! per pair of neighbouring bands between 300 Hz and 2 kHz, set when the
+//! energy difference between the pair grew since the previous step (the
+//! Haitsma-Kalker scheme). The bits survive re-encoding, level changes and
+//! resampling, so a re-release of the same master or a lossy copy of it
+//! lands close to the original while unrelated tracks differ in about half
+//! their bits.
+//!
+//! Two fingerprints are aligned by voting on the offsets at which their
+//! sub-fingerprints match exactly, then scored on the bit error rate over
+//! the overlap, so trimmed or padded copies still match.
+//!
+//! Fingerprints to compare against come with the job, from a Redis index
+//! (a hash of track ID to fingerprint per index name), or both.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use budi_worker_core::audio::AudioDecoder;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::FingerprintSettings;
+use crate::types::AudioBuffer;
+
+/// Length of the frames sub-fingerprints are taken from (seconds)
+const FRAME_SECONDS: f64 = 0.37;
+
+/// Step between sub-fingerprints (seconds)
+pub const HOP_SECONDS: f64 = FRAME_SECONDS / 4.0;
+
+/// Bands the energy differences are taken between, log-spaced
+const BANDS: usize = 33;
+const LOW_HZ: f64 = 300.0;
+const HIGH_HZ: f64 = 2000.0;
+
+/// Shortest overlap two fingerprints are scored over (seconds)
+const MIN_OVERLAP_SECONDS: f64 = 5.0;
+
+/// Offsets with the most exact matches whose bit error rate is measured
+const CANDIDATE_OFFSETS: usize = 3;
+
+/// Positions of one sub-fingerprint value kept when voting; values that
+/// repeat more often (sustained notes, room tone) say little about alignment
+const MAX_POSITIONS: usize = 16;
+
+/// Similarity from which a track is reported as a probable duplicate
+pub const DEFAULT_MIN_SIMILARITY: f64 = 0.6;
+
+/// Running sub-fingerprints of a track, fed a block of audio at a time
+pub struct Fingerprinter {
+    channels: usize,
+    frame_len: usize,
+    hop_len: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    band_bins: Vec<(usize, usize)>,
+    mono: Vec<f32>,
+    previous: Option<[f64; BANDS]>,
+    frames: Vec<u32>,
+}
+
+impl Fingerprinter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        // Frame and hop are fixed in seconds, not samples, so tracks at
+        // different sample rates fingerprint alike
+        let frame_len = (FRAME_SECONDS * sample_rate as f64).round() as usize;
+        let hop_len = (HOP_SECONDS * sample_rate as f64).round() as usize;
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(frame_len);
+        let window = (0..frame_len)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / frame_len as f32).cos())
+            .collect();
+        let bin_hz = sample_rate as f64 / frame_len as f64;
+        let edge = |band: usize| LOW_HZ * (HIGH_HZ / LOW_HZ).powf(band as f64 / BANDS as f64);
+        let band_bins = (0..BANDS)
+            .map(|b| {
+                let low = (edge(b) / bin_hz).round() as usize;
+                let high = ((edge(b + 1) / bin_hz).round() as usize).max(low + 1);
+                (low, high)
+            })
+            .collect();
+        Self {
+            channels,
+            frame_len,
+            hop_len,
+            input: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            fft,
+            window,
+            band_bins,
+            mono: Vec::with_capacity(frame_len + hop_len),
+            previous: None,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Add the next block of the track
+    pub fn add(&mut self, buffer: &AudioBuffer) -> Result<()> {
+        let scale = 1.0 / self.channels.max(1) as f32;
+        for i in 0..buffer.frame_count() {
+            self.mono
+                .push(buffer.samples.iter().map(|ch| ch[i]).sum::<f32>() * scale);
+            if self.mono.len() == self.frame_len {
+                self.add_frame()?;
+                self.mono.drain(..self.hop_len);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_frame(&mut self) -> Result<()> {
+        for ((input, &sample), &window) in self.input.iter_mut().zip(&self.mono).zip(&self.window) {
+            *input = sample * window;
+        }
+        self.fft
+            .process(&mut self.input, &mut self.spectrum)
+            .map_err(|e| anyhow::anyhow!("FFT failed: {}", e))?;
+        let mut energies = [0.0; BANDS];
+        for (energy, &(low, high)) in energies.iter_mut().zip(&self.band_bins) {
+            *energy = self.spectrum[low..high]
+                .iter()
+                .map(|c| c.norm_sqr() as f64)
+                .sum();
+        }
+        if let Some(previous) = &self.previous {
+            let mut bits = 0u32;
+            for m in 0..BANDS - 1 {
+                let now = energies[m] - energies[m + 1];
+                let before = previous[m] - previous[m + 1];
+                if now - before > 0.0 {
+                    bits |= 1 << m;
+                }
+            }
+            self.frames.push(bits);
+        }
+        self.previous = Some(energies);
+        Ok(())
+    }
+
+    /// Sub-fingerprints of everything added
+    pub fn finish(self) -> Vec<u32> {
+        self.frames
+    }
+}
+
+/// Fingerprint an audio file as it is decoded
+#[tracing::instrument(name = "dsp.fingerprint", skip_all)]
+pub fn fingerprint_file(path: &Path) -> Result<Vec<u32>> {
+    let mut decoder = AudioDecoder::open(path)?;
+    let mut fingerprinter = Fingerprinter::new(decoder.channels, decoder.sample_rate);
+    let mut block = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    while decoder.decode_next(&mut block)? {
+        fingerprinter.add(&block)?;
+        for channel in &mut block.samples {
+            channel.clear();
+        }
+    }
+    Ok(fingerprinter.finish())
+}
+
+/// A fingerprint as jobs and the index carry it: base64 of the
+/// sub-fingerprints as little-endian `u32`s
+pub fn encode(frames: &[u32]) -> String {
+    let bytes: Vec<u8> = frames.iter().flat_map(|f| f.to_le_bytes()).collect();
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+pub fn decode(fingerprint: &str) -> Result<Vec<u32>> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(fingerprint.trim())
+        .context("Fingerprint isn't valid base64")?;
+    anyhow::ensure!(
+        bytes.len().is_multiple_of(4),
+        "Fingerprint length isn't a whole number of sub-fingerprints"
+    );
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// How closely two fingerprints match at their best alignment
+#[derive(Debug, Clone, Copy)]
+pub struct Similarity {
+    /// 1 for identical audio, around 0 for unrelated audio
+    pub similarity: f64,
+    /// Share of the shorter track the overlap covers (0-1)
+    pub coverage: f64,
+    /// Where the other track starts in this one (sub-fingerprints, negative
+    /// when it starts before)
+    pub offset: isize,
+}
+
+/// Compare two fingerprints, or `None` if they can't overlap by
+/// `MIN_OVERLAP_SECONDS`
+pub fn compare(a: &[u32], b: &[u32]) -> Option<Similarity> {
+    let min_overlap = (MIN_OVERLAP_SECONDS / HOP_SECONDS).ceil() as usize;
+    if a.len().min(b.len()) < min_overlap {
+        return None;
+    }
+
+    let mut positions: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, &value) in a.iter().enumerate() {
+        // All-zero sub-fingerprints come from silence
+        if value != 0 {
+            positions.entry(value).or_default().push(i);
+        }
+    }
+    let mut votes: HashMap<isize, usize> = HashMap::new();
+    for (j, value) in b.iter().enumerate() {
+        match positions.get(value) {
+            Some(found) if found.len() <= MAX_POSITIONS => {
+                for &i in found {
+                    *votes.entry(i as isize - j as isize).or_default() += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut offsets: Vec<(isize, usize)> = votes.into_iter().collect();
+    offsets.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+    offsets.truncate(CANDIDATE_OFFSETS);
+    if !offsets.iter().any(|&(offset, _)| offset == 0) {
+        offsets.push((0, 0));
+    }
+
+    offsets
+        .into_iter()
+        .filter_map(|(offset, _)| {
+            let start = offset.max(0) as usize;
+            let end = (a.len() as isize).min(b.len() as isize + offset).max(0) as usize;
+            let overlap = end.saturating_sub(start);
+            if overlap < min_overlap {
+                return None;
+            }
+            let errors: u32 = (start..end)
+                .map(|i| (a[i] ^ b[(i as isize - offset) as usize]).count_ones())
+                .sum();
+            let bit_error_rate = errors as f64 / (32 * overlap) as f64;
+            Some(Similarity {
+                similarity: (1.0 - 2.0 * bit_error_rate).clamp(0.0, 1.0),
+                coverage: overlap as f64 / a.len().min(b.len()) as f64,
+                offset,
+            })
+        })
+        .max_by(|x, y| x.similarity.total_cmp(&y.similarity))
+}
+
+/// A fingerprint supplied with a job
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FingerprintEntry {
+    pub track_id: String,
+    pub fingerprint: String,
+}
+
+/// Where a matching fingerprint came from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchSource {
+    Supplied,
+    Index,
+}
+
+/// A probable duplicate
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateMatch {
+    pub track_id: String,
+    pub similarity: f64,
+    pub coverage: f64,
+    /// Where the other track starts in this one (seconds)
+    pub offset_seconds: f64,
+    pub source: MatchSource,
+}
+
+/// Compare `query` against `candidates`, keeping those at least
+/// `min_similarity` alike; the track's own ID is skipped
+pub fn find_duplicates(
+    track_id: &str,
+    query: &[u32],
+    candidates: &[(String, Vec<u32>)],
+    source: MatchSource,
+    min_similarity: f64,
+) -> Vec<DuplicateMatch> {
+    candidates
+        .iter()
+        .filter(|(id, _)| id != track_id)
+        .filter_map(|(id, frames)| {
+            let found = compare(query, frames)?;
+            (found.similarity >= min_similarity).then(|| DuplicateMatch {
+                track_id: id.clone(),
+                similarity: found.similarity,
+                coverage: found.coverage,
+                offset_seconds: found.offset as f64 * HOP_SECONDS,
+                source,
+            })
+        })
+        .collect()
+}
+
+/// Check a job's index name and threshold
+pub fn validate(index: Option<&str>, min_similarity: f64) -> Result<()> {
+    if let Some(index) = index {
+        // Part of a Redis key
+        anyhow::ensure!(
+            !index.is_empty()
+                && index.len() <= 64
+                && index
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            "Invalid fingerprint index name: {:?}",
+            index
+        );
+    }
+    anyhow::ensure!(
+        (0.0..=1.0).contains(&min_similarity),
+        "minSimilarity must be between 0 and 1"
+    );
+    Ok(())
+}
+
+/// A fingerprint index in Redis
+pub struct FingerprintIndex {
+    conn: redis::aio::MultiplexedConnection,
+    key: String,
+    scan_count: usize,
+}
+
+impl FingerprintIndex {
+    pub async fn open(settings: &FingerprintSettings, index: &str) -> Result<Self> {
+        let client = redis::Client::open(settings.redis_url.as_str())?;
+        let conn = client
+            .get_multiplexed_async_connection()
+            .await
+            .context("Failed to connect to the fingerprint index")?;
+        Ok(Self {
+            conn,
+            key: format!("{}{}", settings.key_prefix, index),
+            scan_count: settings.scan_count,
+        })
+    }
+
+    /// Compare `query` against every fingerprint in the index, a page at a
+    /// time, returning the matches and how many were compared
+    pub async fn find_duplicates(
+        &mut self,
+        track_id: &str,
+        query: &[u32],
+        min_similarity: f64,
+    ) -> Result<(Vec<DuplicateMatch>, usize)> {
+        let mut matches = Vec::new();
+        let mut compared = 0;
+        let mut cursor = 0u64;
+        loop {
+            let (next, page): (u64, Vec<(String, String)>) = redis::cmd("HSCAN")
+                .arg(&self.key)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(self.scan_count)
+                .query_async(&mut self.conn)
+                .await?;
+            let mut candidates = Vec::with_capacity(page.len());
+            for (id, fingerprint) in page {
+                match decode(&fingerprint) {
+                    Ok(frames) => candidates.push((id, frames)),
+                    Err(e) => {
+                        tracing::warn!("Skipping fingerprint of {} in {}: {}", id, self.key, e)
+                    }
+                }
+            }
+            compared += candidates.iter().filter(|(id, _)| id != track_id).count();
+            matches.extend(find_duplicates(
+                track_id,
+                query,
+                &candidates,
+                MatchSource::Index,
+                min_similarity,
+            ));
+            if next == 0 {
+                return Ok((matches, compared));
+            }
+            cursor = next;
+        }
+    }
+
+    /// Store a track's fingerprint, replacing any it had
+    pub async fn add(&mut self, track_id: &str, fingerprint: &str) -> Result<()> {
+        redis::cmd("HSET")
+            .arg(&self.key)
+            .arg(track_id)
+            .arg(fingerprint)
+            .query_async::<_, ()>(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copies_match_and_unrelated_tracks_dont() {
+        // Chords changing every half second over a little noise
+        let render = |seed: u32, rate: u32, seconds: usize| {
+            let mut x = seed;
+            let mut next = move || {
+                x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+                (x >> 8) as f32 / 16777216.0
+            };
+            let step = rate as usize / 2;
+            let frames = rate as usize * seconds;
+            let mut samples = vec![0.0f32; frames];
+            for chunk in samples.chunks_mut(step) {
+                let tones: Vec<f32> = (0..3).map(|_| 200.0 + 1600.0 * next()).collect();
+                for (i, sample) in chunk.iter_mut().enumerate() {
+                    let t = i as f32 / rate as f32;
+                    *sample = tones
+                        .iter()
+                        .map(|hz| 0.2 * (2.0 * std::f32::consts::PI * hz * t).sin())
+                        .sum::<f32>()
+                        + 0.01 * (next() - 0.5);
+                }
+            }
+            samples
+        };
+        let fingerprint = |samples: &[f32], rate: u32| {
+            let mut fingerprinter = Fingerprinter::new(1, rate);
+            for chunk in samples.chunks(20_000) {
+                let mut block = AudioBuffer::new(1, rate);
+                block.samples[0] = chunk.to_vec();
+                fingerprinter.add(&block).unwrap();
+            }
+            fingerprinter.finish()
+        };
+
+        let original = render(1, 44100, 30);
+        let query = fingerprint(&original, 44100);
+        // Turned down 6 dB with two seconds trimmed off the top
+        let copy: Vec<f32> = original[44100 * 2..].iter().map(|s| s * 0.5).collect();
+        let unrelated = render(2, 44100, 30);
+
+        let candidates = vec![
+            ("copy".to_string(), fingerprint(&copy, 44100)),
+            ("unrelated".to_string(), fingerprint(&unrelated, 44100)),
+            ("self".to_string(), query.clone()),
+        ];
+        let matches = find_duplicates(
+            "self",
+            &query,
+            &candidates,
+            MatchSource::Supplied,
+            DEFAULT_MIN_SIMILARITY,
+        );
+        assert_eq!(matches.len(), 1, "{:?}", matches);
+        assert_eq!(matches[0].track_id, "copy");
+        assert_eq!(matches[0].coverage, 1.0);
+        assert!((matches[0].offset_seconds - 2.0).abs() < HOP_SECONDS);
+        let unrelated = compare(&query, &candidates[1].1).unwrap();
+        assert!(unrelated.similarity < 0.3, "{:?}", unrelated);
+
+        assert_eq!(decode(&encode(&query)).unwrap(), query);
+    }
+}
@@ -0,0 +1,253 @@
+//! Instrumental and acapella versions of a stereo master
+//!
+//! Lead vocals are nearly always mixed dead center, identical in both
+//! channels, while most instruments are panned or spread by stereo reverb.
+//! Each STFT bin is scored by how alike the two channels are in it (1 when
+//! they carry the same level and phase); bins close to 1 are taken as the
+//! center. The center estimate, limited to the vocal range, is the acapella,
+//! and subtracting it from both channels leaves the instrumental. Bass and
+//! kick, centered as well, sit below `VOCAL_LOW_HZ` and are left alone, as is
+//! the air above `VOCAL_HIGH_HZ`.
+//!
+//! Anything else mixed dead center in the vocal range (snare, a mono lead
+//! instrument) goes with the vocal and centered reverb returns stay behind,
+//! so the results are estimates; a stem-separation job with `twoStems:
+//! "vocals"` separates by source instead, where Demucs is available.
+
+use anyhow::Result;
+use realfft::RealFftPlanner;
+use serde::Serialize;
+
+use crate::types::AudioBuffer;
+
+/// Share of the center removed when a job doesn't say
+pub const DEFAULT_SUPPRESSION: f64 = 1.0;
+
+/// Vocal range the center is extracted over (Hz); the edges fade over an
+/// octave outside it
+const VOCAL_LOW_HZ: f32 = 120.0;
+const VOCAL_HIGH_HZ: f32 = 12000.0;
+
+/// Channel similarity from which a bin counts toward the center, rising to
+/// wholly center at 1
+const CENTER_SIMILARITY_MIN: f32 = 0.6;
+
+/// Length of the STFT frames (seconds, rounded up to a power of two) and
+/// their overlap
+const FRAME_SECONDS: f64 = 0.08;
+const OVERLAP: usize = 4;
+
+/// Smoothing of each bin's center mask across frames
+const MASK_SMOOTHING: f32 = 0.5;
+
+/// What extraction found
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Extraction {
+    pub suppression: f64,
+    /// Share of the vocal range's mid (L+R) energy found in the center;
+    /// low values mean little was centered, and the vocal likely remains
+    pub center_share: f64,
+}
+
+/// Split stereo `buffer` into an instrumental (stereo, center suppressed
+/// by `suppression`) and an acapella (the center, in both channels)
+#[tracing::instrument(name = "dsp.karaoke", skip_all)]
+pub fn extract(
+    buffer: &AudioBuffer,
+    suppression: f64,
+) -> Result<(AudioBuffer, AudioBuffer, Extraction)> {
+    anyhow::ensure!(
+        (0.0..=1.0).contains(&suppression),
+        "suppression must be between 0 and 1"
+    );
+    anyhow::ensure!(
+        buffer.channels == 2,
+        "Instrumental extraction needs a stereo master, not {} channels",
+        buffer.channels
+    );
+    let frames = buffer.frame_count();
+    let size = ((FRAME_SECONDS * buffer.sample_rate as f64) as usize).next_power_of_two();
+    let hop = size / OVERLAP;
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(size);
+    let inverse = planner.plan_fft_inverse(size);
+    let window: Vec<f32> = (0..size)
+        .map(|i| (0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos()).sqrt())
+        .collect();
+    // sqrt-Hann in and out sums to 2 at 75% overlap; the inverse FFT is
+    // unnormalized
+    let scale = 1.0 / (size as f32 * OVERLAP as f32 / 2.0);
+
+    let bin_hz = buffer.sample_rate as f32 / size as f32;
+    let band: Vec<f32> = (0..size / 2 + 1)
+        .map(|k| band_weight(k as f32 * bin_hz))
+        .collect();
+
+    // Padded so every sample is covered by a full set of frames
+    let padded: Vec<Vec<f32>> = buffer
+        .samples
+        .iter()
+        .map(|ch| {
+            let mut padded = vec![0.0f32; frames + 2 * size];
+            padded[size..size + frames].copy_from_slice(ch);
+            padded
+        })
+        .collect();
+    let mut center = vec![0.0f32; frames + 2 * size];
+
+    let mut input = forward.make_input_vec();
+    let mut left = forward.make_output_vec();
+    let mut right = forward.make_output_vec();
+    let mut mask = vec![0.0f32; left.len()];
+    let (mut center_energy, mut mid_energy) = (0.0f64, 0.0f64);
+    for start in (0..=padded[0].len() - size).step_by(hop) {
+        for (spectrum, channel) in [(&mut left, &padded[0]), (&mut right, &padded[1])] {
+            for ((value, &s), &w) in input
+                .iter_mut()
+                .zip(&channel[start..start + size])
+                .zip(&window)
+            {
+                *value = s * w;
+            }
+            forward
+                .process(&mut input, spectrum)
+                .map_err(|e| anyhow::anyhow!("FFT failed: {}", e))?;
+        }
+
+        for k in 0..left.len() {
+            let (l, r) = (left[k], right[k]);
+            let power = l.norm_sqr() + r.norm_sqr();
+            let similarity = if power > 0.0 {
+                (2.0 * (l * r.conj()).re / power).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let centered = ((similarity - CENTER_SIMILARITY_MIN) / (1.0 - CENTER_SIMILARITY_MIN))
+                .clamp(0.0, 1.0)
+                .powi(2);
+            mask[k] = MASK_SMOOTHING * mask[k] + (1.0 - MASK_SMOOTHING) * centered * band[k];
+
+            let mid = (l + r) * 0.5;
+            let c = mid * mask[k];
+            mid_energy += (mid.norm_sqr() * band[k]) as f64;
+            center_energy += c.norm_sqr() as f64;
+            left[k] = c;
+        }
+        // The DC and Nyquist bins of a real signal's spectrum are real
+        left[0].im = 0.0;
+        if let Some(last) = left.last_mut() {
+            last.im = 0.0;
+        }
+        inverse
+            .process(&mut left, &mut input)
+            .map_err(|e| anyhow::anyhow!("Inverse FFT failed: {}", e))?;
+        for ((out, &s), &w) in center[start..start + size]
+            .iter_mut()
+            .zip(&input)
+            .zip(&window)
+        {
+            *out += s * w * scale;
+        }
+    }
+    let center = &center[size..size + frames];
+
+    let mut instrumental = AudioBuffer::new(2, buffer.sample_rate);
+    for (out, channel) in instrumental.samples.iter_mut().zip(&buffer.samples) {
+        *out = channel
+            .iter()
+            .zip(center)
+            .map(|(&s, &c)| s - suppression as f32 * c)
+            .collect();
+    }
+    let mut acapella = AudioBuffer::new(2, buffer.sample_rate);
+    for out in &mut acapella.samples {
+        *out = center.to_vec();
+    }
+
+    let center_share = if mid_energy > 0.0 {
+        center_energy / mid_energy
+    } else {
+        0.0
+    };
+    Ok((
+        instrumental,
+        acapella,
+        Extraction {
+            suppression,
+            center_share,
+        },
+    ))
+}
+
+/// 1 inside the vocal range, fading to 0 over an octave either side
+fn band_weight(hz: f32) -> f32 {
+    let fade = |octaves: f32| 0.5 + 0.5 * (std::f32::consts::PI * octaves.clamp(0.0, 1.0)).cos();
+    if hz <= 0.0 {
+        0.0
+    } else if hz < VOCAL_LOW_HZ {
+        fade((VOCAL_LOW_HZ / hz).log2())
+    } else if hz > VOCAL_HIGH_HZ {
+        fade((hz / VOCAL_HIGH_HZ).log2())
+    } else {
+        1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_center_vocal_split_from_panned_instruments() {
+        // A centered "vocal" at 440 Hz, guitars at 660 Hz hard left and
+        // 990 Hz hard right, and centered bass at 55 Hz
+        let rate = 44100;
+        let tone =
+            |i: usize, hz: f32| (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin();
+        let mut buffer = AudioBuffer::new(2, rate as u32);
+        buffer.samples[0] = (0..rate * 2)
+            .map(|i| 0.3 * tone(i, 440.0) + 0.2 * tone(i, 660.0) + 0.2 * tone(i, 55.0))
+            .collect();
+        buffer.samples[1] = (0..rate * 2)
+            .map(|i| 0.3 * tone(i, 440.0) + 0.2 * tone(i, 990.0) + 0.2 * tone(i, 55.0))
+            .collect();
+
+        let (instrumental, acapella, extraction) = extract(&buffer, DEFAULT_SUPPRESSION).unwrap();
+
+        // Level of `hz` in the middle second, relative to full scale (dB)
+        let level = |samples: &[f32], hz: f64| {
+            let middle = &samples[rate / 2..rate * 3 / 2];
+            let w = 2.0 * std::f64::consts::PI * hz / rate as f64;
+            let (mut s1, mut s2) = (0.0f64, 0.0f64);
+            for &s in middle {
+                let s0 = s as f64 + 2.0 * w.cos() * s1 - s2;
+                s2 = s1;
+                s1 = s0;
+            }
+            let magnitude = (s1 * s1 + s2 * s2 - 2.0 * w.cos() * s1 * s2).sqrt();
+            20.0 * (2.0 * magnitude / middle.len() as f64).log10()
+        };
+        let source_vocal = level(&buffer.samples[0], 440.0);
+        assert!(level(&instrumental.samples[0], 440.0) < source_vocal - 30.0);
+        assert!(level(&acapella.samples[0], 440.0) > source_vocal - 1.0);
+        assert!(level(&acapella.samples[0], 660.0) < level(&buffer.samples[0], 660.0) - 30.0);
+        for (hz, channel) in [(660.0, 0), (990.0, 1), (55.0, 0)] {
+            let before = level(&buffer.samples[channel], hz);
+            let after = level(&instrumental.samples[channel], hz);
+            assert!(
+                (before - after).abs() < 1.0,
+                "{} Hz: {} -> {}",
+                hz,
+                before,
+                after
+            );
+        }
+        assert!(extraction.center_share > 0.3, "{:?}", extraction);
+
+        let mut mono = AudioBuffer::new(1, rate as u32);
+        mono.samples[0] = buffer.samples[0].clone();
+        assert!(extract(&mono, 1.0).is_err());
+    }
+}
@@ -2,232 +2,2297 @@
 //!
 //! This worker processes audio jobs from a Redis queue:
 //! - Analyze: Compute loudness, peaks, spectral metrics
+//! - Batch Analyze: Analyze many tracks in one job, with catalog statistics
+//!   and outliers
+//! - Compliance Check: Measure loudness and true peak only, and check them
+//!   against platform specs
+//! - ACX Check: Check an audiobook chapter against ACX's level and room
+//!   tone requirements
+//! - Segment Detect: Mark long silences and section changes as chapter or
+//!   track split points
+//! - Vocal Balance: Track the vocal's level against the backing over time
+//!   and flag buried or overpowering sections
 //! - Fix: Apply repair operations (normalize, clip repair, etc.)
+//! - Restore: Declick, decrackle, dehum, denoise and azimuth-correct vinyl
+//!   and tape transfers at a single strength
+//! - Karaoke: Instrumental (center suppressed) and acapella estimates of a
+//!   stereo master
+//! - Time Pitch: Change tempo keeping pitch and pitch keeping length, with a
+//!   phase vocoder
 //! - Master: Apply mastering chain (EQ, compression, limiting)
 //! - Album Master: Master multiple tracks with consistent loudness
+//! - Revision Report: Compare loudness, peaks and tonal balance across
+//!   revisions of a master
+//! - Replay Gain: Compute ReplayGain 2.0 track and album gain and peak for
+//!   the tracks of an album
+//! - Pipeline: Run a chain of stages (fixes, EQ, compressor, limiter,
+//!   encodes...) described by the job, with per-stage settings, including
+//!   sandboxed WebAssembly plugins, configured external tools and (with the
+//!   `clap` feature) hosted CLAP plugins
+//! - Bounce: Sum stems with per-stem gain, pan and mute into a stereo mix,
+//!   optionally mastering it in the same job
+//! - Podcast Process: Dialogue chain (filter, gate, de-esser, leveler,
+//!   compressor, limiter) for spoken word, delivered as MP3
+//! - Export: Encode final masters into delivery formats (WAV, MP3, FLAC, DDP)
+//!   and package them into a single ZIP with a manifest
+//! - Waveform Peaks: Multi-resolution min/max/RMS peaks for the waveform view
+//! - Spectrogram: Mel or linear spectrogram PNGs for the visual QC view
+//! - Stem Separation: Vocals/drums/bass/other stems through Demucs, with
+//!   per-stem loudness
+//! - Compare: Null-test two renders of a track for revision review
+//! - Transcribe: Speech-to-text through a whisper.cpp server or OpenAI's
+//!   API, with timed segments
+//! - Duplicate Check: Fingerprint a track and report probable duplicates
+//!   among supplied fingerprints or a Redis fingerprint index
+//!
+//! Fix, master and album master jobs with `dryRun` set (or every one, with
+//! `DSP_DRY_RUN`) stop after analysis and report the settings and predicted
+//! loudness they would use to the `plan` webhook, rendering nothing.
 
-mod analysis;
+mod acx;
+mod album;
 mod audio;
-mod fix;
-mod mastering;
-mod s3;
+mod balance;
+mod batch;
+mod bounce;
+#[cfg(feature = "clap")]
+mod clap_host;
+mod compare;
+mod compliance;
+mod config;
+mod ddp;
+mod external;
+mod fingerprint;
+mod karaoke;
+mod package;
+mod pipeline;
+mod plugin;
+mod podcast;
+mod qc_pdf;
+mod replaygain;
+mod resample;
+mod restore;
+mod revisions;
+mod segments;
+mod spectrogram;
+mod stems;
+mod stretch;
+mod tags;
+mod transcribe;
 mod types;
+mod waveform;
 mod webhook;
 
-use anyhow::Result;
-use redis::AsyncCommands;
-use std::env;
-use tempfile::TempDir;
-use tracing::{error, info, warn};
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::path::Path;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use crate::batch::BatchTrack;
+use crate::bounce::Mixer;
+use crate::compliance::PlatformSpec;
+use crate::fingerprint::{FingerprintEntry, FingerprintIndex, MatchSource};
+use crate::package::{EntryKind, ManifestEntry};
+use crate::pipeline::{Stage, StageReport};
+use crate::revisions::RevisionMeasurement;
+use crate::types::{
+    AlbumRenderResult, AlbumTrackResult, AnalysisResult, AudioBuffer, BounceMaster, BounceStem,
+    ColorMap, DiscMetadata, Dither, ExportFile, ExportFormat, ExportTrack, FixChange, FixModule,
+    Job, Loudness, LoudnessTarget, MasterProfile, MasterRevision, ReplayGainTrack,
+    SpectrogramImage, SpectrogramResolution, SpectrogramScale, StemFile, WaveformFile,
+};
+use crate::webhook::WebhookClient;
+use budi_dsp_core::mastering::{self, MasteringOptions, MasteringResult};
+use budi_dsp_core::{analysis, fix};
+use budi_worker_core::{refused, temp, Config, Storage};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    budi_worker_core::init_tracing("worker_dsp")?;
+
+    info!("Budi DSP Worker starting...");
+
+    let config = Config::load("DSP_QUEUE", "dsp-jobs", &config::SECTIONS)?;
+    config::init(config::Settings::load(&config)?);
+
+    // Clients live for the whole process and are shared by every job task
+    let storage: &'static Storage = Box::leak(Box::new(Storage::new(&config).await?));
+    let webhook: &'static WebhookClient =
+        Box::leak(Box::new(WebhookClient::new(&config, storage)?));
+
+    let result = budi_worker_core::run_jobs(&config, webhook.core(), move |job: Job| async move {
+        process_job(&job, storage, webhook).await
+    })
+    .await;
+    budi_worker_core::shutdown_tracing();
+    result
+}
+
+/// Process a single job
+async fn process_job(job: &Job, storage: &Storage, webhook: &WebhookClient) -> Result<()> {
+    match job {
+        Job::Analyze {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+        } => {
+            process_analyze_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::BatchAnalyze {
+            job_id,
+            batch_id,
+            track_ids,
+            source_urls,
+            source_sha256s,
+        } => {
+            process_batch_analyze_job(
+                job_id,
+                batch_id,
+                track_ids,
+                source_urls,
+                source_sha256s,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::ComplianceCheck {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            platforms,
+        } => {
+            process_compliance_check_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                platforms,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::PodcastProcess {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            target_lufs,
+            high_pass_hz,
+            gate,
+            de_ess,
+            mono,
+            mp3_bitrate,
+        } => {
+            let options = PodcastSettings {
+                target_lufs: *target_lufs,
+                high_pass_hz: high_pass_hz.unwrap_or(podcast::DEFAULT_HIGH_PASS_HZ),
+                gate: gate.unwrap_or(true),
+                de_ess: de_ess.unwrap_or(true),
+                mono: *mono,
+                mp3_bitrate: mp3_bitrate.unwrap_or(podcast::DEFAULT_MP3_BITRATE),
+            };
+            process_podcast_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                &options,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::AcxCheck {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+        } => {
+            process_acx_check_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::SegmentDetect {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            silence_threshold_db,
+            min_silence_seconds,
+            min_segment_seconds,
+            detect_changes,
+            label_prefix,
+        } => {
+            let options = segments::SegmentOptions {
+                silence_threshold_db: silence_threshold_db
+                    .unwrap_or(segments::DEFAULT_SILENCE_THRESHOLD_DB),
+                min_silence_seconds: min_silence_seconds
+                    .unwrap_or(segments::DEFAULT_MIN_SILENCE_SECONDS),
+                min_segment_seconds: min_segment_seconds
+                    .unwrap_or(segments::DEFAULT_MIN_SEGMENT_SECONDS),
+                detect_changes: detect_changes.unwrap_or(true),
+                label_prefix: label_prefix
+                    .clone()
+                    .unwrap_or_else(|| segments::DEFAULT_LABEL_PREFIX.to_string()),
+            };
+            process_segment_detect_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                &options,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Transcribe {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            language,
+            prompt,
+            downmix,
+            normalize,
+        } => {
+            process_transcribe_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                language.as_deref(),
+                prompt.as_deref(),
+                downmix.unwrap_or(true),
+                normalize.unwrap_or(true),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::DuplicateCheck {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            candidates,
+            index,
+            add_to_index,
+            min_similarity,
+        } => {
+            process_duplicate_check_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                candidates,
+                index.as_deref(),
+                *add_to_index,
+                min_similarity.unwrap_or(fingerprint::DEFAULT_MIN_SIMILARITY),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Restore {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            strength,
+        } => {
+            process_restore_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                strength.unwrap_or(restore::DEFAULT_STRENGTH),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Karaoke {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            suppression,
+        } => {
+            process_karaoke_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                suppression.unwrap_or(karaoke::DEFAULT_SUPPRESSION),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::TimePitch {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            tempo,
+            semitones,
+        } => {
+            process_time_pitch_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                tempo.unwrap_or(1.0),
+                semitones.unwrap_or(0.0),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::VocalBalance {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            buried_db,
+            overpowering_db,
+        } => {
+            process_vocal_balance_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                buried_db.unwrap_or(balance::DEFAULT_BURIED_DB),
+                overpowering_db.unwrap_or(balance::DEFAULT_OVERPOWERING_DB),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Bounce {
+            job_id,
+            track_id,
+            stems,
+            sample_rate,
+            master,
+        } => {
+            process_bounce_job(
+                job_id,
+                track_id,
+                stems,
+                *sample_rate,
+                master.as_ref(),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::RevisionReport {
+            job_id,
+            track_id,
+            revisions,
+        } => process_revision_report_job(job_id, track_id, revisions, storage, webhook).await,
+        Job::ReplayGain { job_id, tracks } => {
+            process_replay_gain_job(job_id, tracks, storage, webhook).await
+        }
+        Job::Pipeline {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            stages,
+        } => {
+            process_pipeline_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                stages,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Fix {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            modules,
+            stages,
+            dry_run,
+        } => {
+            let dry_run = *dry_run || config::get().dsp.dry_run;
+            process_fix_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                modules,
+                stages,
+                dry_run,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Master {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            profile,
+            loudness_target,
+            reference_url,
+            reference_sha256,
+            eq_mode,
+            precision,
+            limiter_mode,
+            min_loudness_range,
+            dither,
+            mix,
+            output_trim_db,
+            stages,
+            final_stage,
+            dry_run,
+        } => {
+            let options = MasteringOptions {
+                eq_mode: *eq_mode,
+                precision: *precision,
+                limiter_mode: *limiter_mode,
+                min_loudness_range: *min_loudness_range,
+                dither: *dither,
+                mix: mix.unwrap_or(1.0),
+                output_trim_db: output_trim_db.unwrap_or(0.0),
+                ..Default::default()
+            };
+            process_master_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                *profile,
+                *loudness_target,
+                reference_url.as_deref(),
+                reference_sha256.as_deref(),
+                options,
+                stages,
+                final_stage.as_ref(),
+                *dry_run || config::get().dsp.dry_run,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::AlbumMaster {
+            job_id,
+            project_id,
+            track_ids,
+            profile,
+            loudness_target,
+            normalize_loudness,
+            source_urls,
+            source_sha256s,
+            gap_seconds,
+            crossfade_seconds,
+            render_continuous,
+            dry_run,
+        } => {
+            let transitions = AlbumTransitions {
+                gap_seconds,
+                crossfade_seconds,
+                render_continuous: *render_continuous,
+            };
+            process_album_master_job(
+                job_id,
+                project_id,
+                track_ids,
+                source_urls,
+                source_sha256s,
+                *profile,
+                *loudness_target,
+                *normalize_loudness,
+                &transitions,
+                *dry_run || config::get().dsp.dry_run,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Export {
+            job_id,
+            project_id,
+            formats,
+            include_qc,
+            tracks,
+            flac_compression_level,
+            sample_rates,
+            disc,
+        } => {
+            let settings = ExportSettings {
+                formats,
+                include_qc: *include_qc,
+                sample_rates,
+                flac_compression_level: flac_compression_level.unwrap_or(5),
+                disc,
+            };
+            process_export_job(job_id, project_id, tracks, &settings, storage, webhook).await
+        }
+        Job::WaveformPeaks {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            samples_per_pixel,
+            bits,
+        } => {
+            process_waveform_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                samples_per_pixel,
+                *bits,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Spectrogram {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            resolutions,
+            scale,
+            color_map,
+        } => {
+            process_spectrogram_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                resolutions,
+                *scale,
+                *color_map,
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::StemSeparation {
+            job_id,
+            track_id,
+            source_url,
+            source_sha256,
+            model,
+            two_stems,
+        } => {
+            process_stem_separation_job(
+                job_id,
+                track_id,
+                source_url,
+                source_sha256.as_deref(),
+                model.as_deref(),
+                two_stems.as_deref(),
+                storage,
+                webhook,
+            )
+            .await
+        }
+        Job::Compare {
+            job_id,
+            track_id,
+            reference_url,
+            reference_sha256,
+            candidate_url,
+            candidate_sha256,
+            max_offset_seconds,
+            render_difference,
+        } => {
+            process_compare_job(
+                job_id,
+                track_id,
+                (reference_url, reference_sha256.as_deref()),
+                (candidate_url, candidate_sha256.as_deref()),
+                max_offset_seconds.unwrap_or(compare::DEFAULT_MAX_OFFSET_SECONDS),
+                *render_difference,
+                storage,
+                webhook,
+            )
+            .await
+        }
+    }
+}
+
+/// Audio analyzed between the partial metrics an analyze job reports (s)
+const PARTIAL_ANALYSIS_INTERVAL_SECS: f64 = 60.0;
+
+/// Process an analyze job
+async fn process_analyze_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    info!("Analyzing track {}", track_id);
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    // Create temp directory for processing
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+
+    // Download the source file
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 30, "Analyzing loudness and peaks...")
+        .await?;
+
+    // Analyze the audio as it is decoded, so long files fit in memory,
+    // reporting the metrics so far along the way
+    let bit_depth = 24; // Assume 24-bit for analysis
+    let result = audio::analyze_file_reporting(
+        &input_path,
+        bit_depth,
+        PARTIAL_ANALYSIS_INTERVAL_SECS,
+        |partial, decoded| async move {
+            let progress = 30 + (decoded.unwrap_or(0.0) * 50.0) as u8;
+            webhook
+                .report_partial_analysis(job_id, progress, &partial)
+                .await
+        },
+    )
+    .await?;
+    webhook
+        .report_progress(job_id, 80, "Generating report...")
+        .await?;
+
+    // Generate JSON report
+    let report_json = serde_json::to_string_pretty(&result)?;
+    let report_key = Storage::generate_key("reports", track_id, "analysis.json");
+    let report = storage
+        .upload_bytes(report_json.as_bytes(), &report_key, "application/json")
+        .await?;
+
+    webhook
+        .report_progress(job_id, 100, "Analysis complete")
+        .await?;
+
+    // Report results to API
+    webhook
+        .report_analysis(
+            job_id,
+            &result,
+            &source_sha256,
+            Some(&report.url),
+            Some(&report_key),
+            Some(&report.sha256),
+        )
+        .await?;
+
+    info!(
+        "Analysis complete for {}: {:.1} LUFS, {:.1} dBTP",
+        track_id, result.integrated_lufs, result.true_peak
+    );
+
+    Ok(())
+}
+
+/// Process a batch-analyze job
+///
+/// Sources are downloaded one after another while up to
+/// `dsp.batch_concurrency` of them are analyzed on blocking threads. A track
+/// that fails to download or decode is reported with its error rather than
+/// failing the batch.
+async fn process_batch_analyze_job(
+    job_id: &str,
+    batch_id: &str,
+    track_ids: &[String],
+    source_urls: &[String],
+    source_sha256s: &[String],
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    if track_ids.is_empty() {
+        anyhow::bail!("Batch analyze job has no tracks");
+    }
+    if source_urls.len() != track_ids.len() {
+        anyhow::bail!(
+            "Batch analyze job has {} tracks but {} source URLs",
+            track_ids.len(),
+            source_urls.len()
+        );
+    }
+    if !source_sha256s.is_empty() && source_sha256s.len() != track_ids.len() {
+        anyhow::bail!(
+            "Batch analyze job has {} tracks but {} source checksums",
+            track_ids.len(),
+            source_sha256s.len()
+        );
+    }
+
+    let settings = config::get();
+    let concurrency = settings.dsp.batch_concurrency;
+    let track_count = track_ids.len();
+    info!(
+        "Analyzing batch {} ({} tracks, {} at a time)",
+        batch_id, track_count, concurrency
+    );
+    webhook
+        .report_progress(job_id, 5, "Downloading and analyzing tracks...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let mut tracks: Vec<BatchTrack> = track_ids.iter().map(|id| BatchTrack::new(id)).collect();
+    let mut running = JoinSet::new();
+    let mut finished = 0;
+    let mut reported = 5;
+    for (i, source_url) in source_urls.iter().enumerate() {
+        while running.len() >= concurrency {
+            if let Some(joined) = running.join_next().await {
+                let (i, result) = joined?;
+                record_batch_analysis(&mut tracks[i], result);
+                finished += 1;
+            }
+        }
+        if finished * 85 / track_count + 5 > reported {
+            reported = finished * 85 / track_count + 5;
+            webhook
+                .report_progress(
+                    job_id,
+                    reported as u8,
+                    &format!("Analyzed {}/{} tracks...", finished, track_count),
+                )
+                .await?;
+        }
+
+        let input_path = temp_dir.path().join(format!("input_{}.wav", i));
+        let expected_sha256 = source_sha256s.get(i).map(String::as_str);
+        match storage
+            .download_file(source_url, &input_path, expected_sha256)
+            .await
+        {
+            Ok(source_sha256) => {
+                tracks[i].source_sha256 = Some(source_sha256);
+                running.spawn_blocking(move || {
+                    let result = audio::analyze_file(&input_path, 24);
+                    // Only one source per running analysis stays on disk
+                    let _ = std::fs::remove_file(&input_path);
+                    (i, result)
+                });
+            }
+            Err(e) => {
+                tracks[i].error = Some(format!("{:#}", e));
+                finished += 1;
+            }
+        }
+    }
+    while let Some(joined) = running.join_next().await {
+        let (i, result) = joined?;
+        record_batch_analysis(&mut tracks[i], result);
+    }
+
+    let stats = batch::catalog_stats(&tracks, settings.qc.true_peak_max);
+    if stats.analyzed == 0 {
+        let first_error = tracks.iter().find_map(|t| t.error.as_deref());
+        anyhow::bail!(
+            "No track of batch {} could be analyzed: {}",
+            batch_id,
+            first_error.unwrap_or("unknown error")
+        );
+    }
+    webhook
+        .report_progress(job_id, 90, "Generating report...")
+        .await?;
+
+    let report_json = serde_json::to_string_pretty(&serde_json::json!({
+        "batchId": batch_id,
+        "catalog": stats,
+        "tracks": tracks,
+    }))?;
+    let report_key = Storage::generate_key("reports", batch_id, "batch-analysis.json");
+    let report = storage
+        .upload_bytes(report_json.as_bytes(), &report_key, "application/json")
+        .await?;
+
+    webhook
+        .report_progress(job_id, 100, "Batch analysis complete")
+        .await?;
+    webhook
+        .report_batch_analysis(job_id, batch_id, &tracks, &stats, &report, &report_key)
+        .await?;
+
+    info!(
+        "Batch {} analyzed: {}/{} tracks, {} outliers",
+        batch_id,
+        stats.analyzed,
+        stats.track_count,
+        stats.outliers.len()
+    );
+    Ok(())
+}
+
+/// Store the outcome of analyzing one track of a batch
+fn record_batch_analysis(track: &mut BatchTrack, result: Result<AnalysisResult>) {
+    match result {
+        Ok(analysis) => track.analysis = Some((&analysis).into()),
+        Err(e) => {
+            warn!("Batch track {} failed: {:#}", track.track_id, e);
+            track.error = Some(format!("{:#}", e));
+        }
+    }
+}
+
+/// Process a compliance-check job
+async fn process_compliance_check_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    platforms: &[PlatformSpec],
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    compliance::validate(platforms).map_err(refused)?;
+    let platforms = if platforms.is_empty() {
+        Cow::Owned(PlatformSpec::defaults())
+    } else {
+        Cow::Borrowed(platforms)
+    };
+    info!(
+        "Checking compliance of track {} against {} platforms",
+        track_id,
+        platforms.len()
+    );
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 40, "Measuring loudness and true peak...")
+        .await?;
+
+    let measurement = compliance::measure_file(&input_path)?;
+    let results: Vec<_> = platforms
+        .iter()
+        .map(|spec| compliance::evaluate(spec, measurement.integrated_lufs, measurement.true_peak))
+        .collect();
+
+    webhook
+        .report_progress(job_id, 100, "Compliance check complete")
+        .await?;
+    webhook
+        .report_compliance(job_id, &measurement, &results, &source_sha256)
+        .await?;
+
+    let failed = results.iter().filter(|r| !r.passes).count();
+    info!(
+        "Compliance check for {}: {:.1} LUFS, {:.1} dBTP, {}/{} platforms pass",
+        track_id,
+        measurement.integrated_lufs,
+        measurement.true_peak,
+        results.len() - failed,
+        results.len()
+    );
+    Ok(())
+}
+
+/// Process an acx-check job
+async fn process_acx_check_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    info!("Checking track {} against ACX requirements", track_id);
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 40, "Measuring levels and room tone...")
+        .await?;
+
+    let report = acx::check_file(&input_path)?;
+
+    webhook
+        .report_progress(job_id, 100, "ACX check complete")
+        .await?;
+    webhook.report_acx(job_id, &report, &source_sha256).await?;
+
+    let failed: Vec<&str> = report
+        .criteria
+        .iter()
+        .filter(|c| !c.passes)
+        .map(|c| c.name)
+        .collect();
+    info!(
+        "ACX check for {}: RMS {:.1} dB, peak {:.1} dB, noise floor {:.1} dB, {}",
+        track_id,
+        report.rms_db,
+        report.peak_db,
+        report.noise_floor_db,
+        if failed.is_empty() {
+            "PASS".to_string()
+        } else {
+            format!("FAIL ({})", failed.join(", "))
+        }
+    );
+    Ok(())
+}
+
+/// Process a segment-detect job
+async fn process_segment_detect_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    options: &segments::SegmentOptions,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    options.validate().map_err(refused)?;
+    info!("Detecting segments of track {}", track_id);
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 40, "Finding silences and section changes...")
+        .await?;
+
+    let segmentation = segments::detect_file(&input_path, options)?;
+
+    webhook
+        .report_progress(job_id, 100, "Segment detection complete")
+        .await?;
+    webhook
+        .report_segments(job_id, &segmentation, &source_sha256)
+        .await?;
+
+    info!(
+        "Segment detection for {}: {} segments, {} silences over {:.1}s",
+        track_id,
+        segmentation.markers.len(),
+        segmentation.silences.len(),
+        segmentation.duration_secs
+    );
+    Ok(())
+}
+
+/// Process a transcribe job
+#[allow(clippy::too_many_arguments)]
+async fn process_transcribe_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    language: Option<&str>,
+    prompt: Option<&str>,
+    downmix: bool,
+    normalize: bool,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    transcribe::validate(language).map_err(refused)?;
+    let settings = &config::get().transcription;
+    info!("Transcribing track {} with {}", track_id, settings.url());
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 20, "Preparing audio...")
+        .await?;
+
+    let mut buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+    if downmix {
+        podcast::downmix_mono(&mut buffer);
+    }
+    let gain_db = if normalize {
+        transcribe::normalize(&mut buffer)?
+    } else {
+        0.0
+    };
+    let bitrate = if buffer.channels == 1 {
+        transcribe::UPLOAD_BITRATE_MONO
+    } else {
+        transcribe::UPLOAD_BITRATE_STEREO
+    };
+    let mp3_path = temp_dir.path().join("speech.mp3");
+    audio::write_mp3_file(&buffer, &mp3_path, bitrate)?;
+    let duration_secs = buffer.duration_secs();
+    drop(buffer);
+    webhook
+        .report_progress(job_id, 30, "Transcribing...")
+        .await?;
+
+    let transcript = transcribe::transcribe(settings, &mp3_path, language, prompt).await?;
+
+    webhook
+        .report_progress(job_id, 100, "Transcription complete")
+        .await?;
+    webhook
+        .report_transcript(job_id, &transcript, duration_secs, gain_db, &source_sha256)
+        .await?;
+
+    info!(
+        "Transcribed {}: {} segments, {} words{}",
+        track_id,
+        transcript.segments.len(),
+        transcript.text.split_whitespace().count(),
+        transcript
+            .language
+            .as_deref()
+            .map(|l| format!(" ({})", l))
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Process a duplicate-check job
+#[allow(clippy::too_many_arguments)]
+async fn process_duplicate_check_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    candidates: &[FingerprintEntry],
+    index: Option<&str>,
+    add_to_index: bool,
+    min_similarity: f64,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    fingerprint::validate(index, min_similarity).map_err(refused)?;
+    anyhow::ensure!(
+        !add_to_index || index.is_some(),
+        "addToIndex needs an index"
+    );
+    let supplied = candidates
+        .iter()
+        .map(|c| {
+            fingerprint::decode(&c.fingerprint)
+                .map(|frames| (c.track_id.clone(), frames))
+                .with_context(|| format!("Candidate {}", c.track_id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    info!(
+        "Checking track {} for duplicates among {} fingerprints{}",
+        track_id,
+        supplied.len(),
+        index
+            .map(|i| format!(" and index {}", i))
+            .unwrap_or_default()
+    );
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 40, "Fingerprinting...")
+        .await?;
+
+    let query = fingerprint::fingerprint_file(&input_path)?;
+    let encoded = fingerprint::encode(&query);
+    webhook
+        .report_progress(job_id, 70, "Comparing fingerprints...")
+        .await?;
+
+    let mut matches = fingerprint::find_duplicates(
+        track_id,
+        &query,
+        &supplied,
+        MatchSource::Supplied,
+        min_similarity,
+    );
+    let mut compared = supplied.iter().filter(|(id, _)| id != track_id).count();
+    if let Some(index) = index {
+        let mut index = FingerprintIndex::open(&config::get().fingerprint, index).await?;
+        let (found, scanned) = index
+            .find_duplicates(track_id, &query, min_similarity)
+            .await?;
+        matches.extend(found);
+        compared += scanned;
+        if add_to_index {
+            index.add(track_id, &encoded).await?;
+        }
+    }
+    matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+
+    webhook
+        .report_progress(job_id, 100, "Duplicate check complete")
+        .await?;
+    webhook
+        .report_duplicates(
+            job_id,
+            &encoded,
+            query.len() as f64 * fingerprint::HOP_SECONDS,
+            compared,
+            &matches,
+            &source_sha256,
+        )
+        .await?;
+
+    info!(
+        "Duplicate check for {}: {} of {} fingerprints match{}",
+        track_id,
+        matches.len(),
+        compared,
+        matches
+            .first()
+            .map(|m| format!(", best {} ({:.2})", m.track_id, m.similarity))
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Process a restore job
+async fn process_restore_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    strength: f64,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    anyhow::ensure!(
+        (0.0..=1.0).contains(&strength),
+        "strength must be between 0 and 1"
+    );
+    info!("Restoring track {} at strength {:.2}", track_id, strength);
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let output_path = temp_dir.path().join("restored.wav");
+
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook.report_progress(job_id, 30, "Restoring...").await?;
+
+    let mut buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+    let result = restore::restore(&mut buffer, strength)?;
+    webhook
+        .report_progress(job_id, 70, "Encoding output...")
+        .await?;
+
+    audio::write_wav_file(&buffer, &output_path, 24)?;
+    let output_key = Storage::generate_key("restored", track_id, "restored.wav");
+    let restored = storage
+        .upload_file(&output_path, &output_key, "audio/wav")
+        .await?;
+
+    webhook
+        .report_progress(job_id, 100, "Restoration complete")
+        .await?;
+    webhook
+        .report_restore(job_id, &restored, &output_key, &result, &source_sha256)
+        .await?;
+
+    info!(
+        "Restoration complete for {}: {} clicks, {} crackles, hum {}, noise floor {:.1} -> {:.1} dB",
+        track_id,
+        result.clicks_repaired,
+        result.crackles_repaired,
+        result
+            .hum_hz
+            .map(|hz| format!("{:.1} Hz", hz))
+            .unwrap_or_else(|| "none".to_string()),
+        result.noise_floor_db,
+        result.noise_floor_after_db
+    );
+    Ok(())
+}
+
+/// Process a karaoke job
+async fn process_karaoke_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    suppression: f64,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    info!("Extracting instrumental and acapella of track {}", track_id);
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 30, "Extracting center...")
+        .await?;
+
+    let buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+    let (instrumental, acapella, extraction) = karaoke::extract(&buffer, suppression)?;
+    drop(buffer);
+    webhook
+        .report_progress(job_id, 70, "Measuring and uploading...")
+        .await?;
+
+    let mut files = Vec::with_capacity(2);
+    for (name, output) in [("instrumental", &instrumental), ("acapella", &acapella)] {
+        let path = temp_dir.path().join(format!("{}.wav", name));
+        audio::write_wav_file(output, &path, 24)?;
+        let analysis = audio::analyze_file(&path, 24)?;
+        let key = Storage::generate_key("karaoke", track_id, &format!("{}.wav", name));
+        let uploaded = storage.upload_file(&path, &key, "audio/wav").await?;
+        files.push(StemFile {
+            name: name.to_string(),
+            url: uploaded.url,
+            key,
+            sha256: uploaded.sha256,
+            integrated_lufs: analysis.integrated_lufs,
+            loudness_range: analysis.loudness_range,
+            true_peak: analysis.true_peak,
+            sample_peak: analysis.sample_peak,
+        });
+    }
+
+    webhook
+        .report_progress(job_id, 100, "Extraction complete")
+        .await?;
+    webhook
+        .report_karaoke(job_id, &extraction, &files, &source_sha256)
+        .await?;
+
+    info!(
+        "Instrumental and acapella of {}: {:.0}% of the vocal range centered",
+        track_id,
+        extraction.center_share * 100.0
+    );
+    Ok(())
+}
+
+/// Process a time-pitch job
+#[allow(clippy::too_many_arguments)]
+async fn process_time_pitch_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    tempo: f64,
+    semitones: f64,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    stretch::validate(tempo, semitones).map_err(refused)?;
+    info!(
+        "Rendering track {} at {:.1}% tempo, {:+} semitones",
+        track_id,
+        tempo * 100.0,
+        semitones
+    );
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let output_path = temp_dir.path().join("time-pitch.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook.report_progress(job_id, 30, "Stretching...").await?;
+
+    let buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+    let source_duration_secs = buffer.duration_secs();
+    let rendered = stretch::time_pitch(&buffer, tempo, semitones)?;
+    drop(buffer);
+    webhook
+        .report_progress(job_id, 70, "Encoding output...")
+        .await?;
+
+    audio::write_wav_file(&rendered, &output_path, 24)?;
+    let output_key = Storage::generate_key(
+        "time-pitch",
+        track_id,
+        &format!("tempo-{}_semitones-{:+}.wav", tempo, semitones),
+    );
+    let uploaded = storage
+        .upload_file(&output_path, &output_key, "audio/wav")
+        .await?;
+
+    webhook
+        .report_progress(job_id, 100, "Time-pitch render complete")
+        .await?;
+    webhook
+        .report_time_pitch(
+            job_id,
+            (tempo, semitones),
+            (source_duration_secs, rendered.duration_secs()),
+            (&uploaded, &output_key),
+            &source_sha256,
+        )
+        .await?;
+
+    info!(
+        "Time-pitch render for {}: {:.1}s -> {:.1}s",
+        track_id,
+        source_duration_secs,
+        rendered.duration_secs()
+    );
+    Ok(())
+}
+
+/// Process a vocal-balance job
+#[allow(clippy::too_many_arguments)]
+async fn process_vocal_balance_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    buried_db: f64,
+    overpowering_db: f64,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    balance::validate(buried_db, overpowering_db).map_err(refused)?;
+    info!("Checking vocal balance of track {}", track_id);
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 40, "Measuring vocal balance...")
+        .await?;
+
+    let buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+    let result = balance::measure(&buffer, buried_db, overpowering_db)?;
+
+    webhook
+        .report_progress(job_id, 100, "Vocal balance check complete")
+        .await?;
+    webhook
+        .report_vocal_balance(job_id, &result, buffer.duration_secs(), &source_sha256)
+        .await?;
+
+    info!(
+        "Vocal balance of {}: median {}, {} sections flagged",
+        track_id,
+        result
+            .median_balance_db
+            .map(|db| format!("{:+.1} dB", db))
+            .unwrap_or_else(|| "no vocal".to_string()),
+        result.sections.len()
+    );
+    Ok(())
+}
+
+/// Process a bounce job
+///
+/// Stems are downloaded and added one at a time, so only the mix and one
+/// stem are held at once.
+async fn process_bounce_job(
+    job_id: &str,
+    track_id: &str,
+    stems: &[BounceStem],
+    sample_rate: Option<u32>,
+    master: Option<&BounceMaster>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    bounce::validate(stems, sample_rate).map_err(refused)?;
+    info!("Bouncing {} stems of track {}", stems.len(), track_id);
+    webhook
+        .report_progress(job_id, 5, "Downloading stems...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let mut mixer = Mixer::new(sample_rate);
+    for (i, stem) in stems.iter().enumerate() {
+        let name = stem
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("stem {}", i + 1));
+        if stem.mute {
+            mixer.skip(name);
+            continue;
+        }
+        let path = temp_dir.path().join(format!("stem_{}.wav", i));
+        let sha256 = storage
+            .download_file(&stem.source_url, &path, stem.source_sha256.as_deref())
+            .await?;
+        let buffer = audio::read_audio_cached(&path, &sha256)?;
+        mixer.add(name, &buffer, stem.gain_db, stem.pan)?;
+        drop(buffer);
+        std::fs::remove_file(&path).ok();
+        webhook
+            .report_progress(
+                job_id,
+                5 + (45 * (i + 1) / stems.len()) as u8,
+                &format!("Mixed {} of {} stems", i + 1, stems.len()),
+            )
+            .await?;
+    }
+    let (mut mix, summary) = mixer.finish()?;
+
+    let mix_path = temp_dir.path().join("mix.wav");
+    audio::write_wav_file(&mix, &mix_path, 24)?;
+    let mix_key = Storage::generate_key("bounces", track_id, "mix.wav");
+    let mix_upload = storage
+        .upload_file(&mix_path, &mix_key, "audio/wav")
+        .await?;
+
+    let mastered = match master {
+        Some(master) => {
+            webhook
+                .report_progress(job_id, 60, "Mastering mix...")
+                .await?;
+            let options = MasteringOptions {
+                dither: master.dither,
+                ..Default::default()
+            };
+            let result = mastering::apply_mastering(
+                &mut mix,
+                master.profile,
+                master.loudness_target,
+                &options,
+            )?;
+            webhook
+                .report_progress(job_id, 80, "Encoding master outputs...")
+                .await?;
+            Some(
+                upload_master_outputs(
+                    track_id,
+                    master.profile,
+                    master.loudness_target,
+                    &mix,
+                    &options,
+                    &result,
+                    &[],
+                    &mix_upload.sha256,
+                    temp_dir.path(),
+                    storage,
+                )
+                .await?,
+            )
+        }
+        None => None,
+    };
+
+    webhook
+        .report_progress(job_id, 100, "Bounce complete")
+        .await?;
+    webhook
+        .report_bounce(job_id, &summary, (&mix_upload, &mix_key), mastered.as_ref())
+        .await?;
+
+    info!(
+        "Bounce complete for {}: {:.1}s at {} Hz, peak {:.1} dBFS{}",
+        track_id,
+        summary.duration_secs,
+        summary.sample_rate,
+        summary.peak_db,
+        mastered
+            .as_ref()
+            .map(|m| format!(", mastered to {:.1} LUFS", m.final_lufs))
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Process a revision-report job
+///
+/// Revisions are measured one at a time, so only one is held at once.
+async fn process_revision_report_job(
+    job_id: &str,
+    track_id: &str,
+    revisions: &[MasterRevision],
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    anyhow::ensure!(
+        (2..=revisions::MAX_REVISIONS).contains(&revisions.len()),
+        "A revision report compares 2 to {} revisions, not {}",
+        revisions::MAX_REVISIONS,
+        revisions.len()
+    );
+    info!(
+        "Comparing {} revisions of track {}",
+        revisions.len(),
+        track_id
+    );
+    webhook
+        .report_progress(job_id, 5, "Downloading revisions...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let mut measurements = Vec::with_capacity(revisions.len());
+    for (i, revision) in revisions.iter().enumerate() {
+        let path = temp_dir.path().join(format!("revision_{}.wav", i));
+        let sha256 = storage
+            .download_file(
+                &revision.source_url,
+                &path,
+                revision.source_sha256.as_deref(),
+            )
+            .await?;
+        let buffer = audio::read_audio_cached(&path, &sha256)?;
+        measurements.push(RevisionMeasurement::measure(
+            &revision.label,
+            &buffer,
+            &sha256,
+        )?);
+        drop(buffer);
+        std::fs::remove_file(&path).ok();
+        webhook
+            .report_progress(
+                job_id,
+                5 + (80 * (i + 1) / revisions.len()) as u8,
+                &format!("Measured {}", revision.label),
+            )
+            .await?;
+    }
+    let changes = revisions::changes(&measurements);
+
+    let report_json = serde_json::to_string_pretty(&serde_json::json!({
+        "trackId": track_id,
+        "revisions": measurements,
+        "changes": changes,
+    }))?;
+    let report_key = Storage::generate_key("reports", track_id, "revisions.json");
+    let report = storage
+        .upload_bytes(report_json.as_bytes(), &report_key, "application/json")
+        .await?;
+
+    webhook
+        .report_progress(job_id, 100, "Revision report complete")
+        .await?;
+    webhook
+        .report_revisions(job_id, &measurements, &changes, (&report, &report_key))
+        .await?;
+
+    info!(
+        "Revision report for {}: {}",
+        track_id,
+        changes
+            .iter()
+            .map(|c| format!("{} -> {}: {} notes", c.from, c.to, c.notes.len()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+/// Process a replay-gain job
+///
+/// Tracks are measured one at a time; only their loudness histograms are
+/// kept for the album gain.
+async fn process_replay_gain_job(
+    job_id: &str,
+    tracks: &[ReplayGainTrack],
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    anyhow::ensure!(!tracks.is_empty(), "Replay gain job has no tracks");
+    anyhow::ensure!(
+        tracks.len() <= replaygain::MAX_TRACKS,
+        "A replay gain job takes at most {} tracks",
+        replaygain::MAX_TRACKS
+    );
+    info!("Computing ReplayGain for {} tracks", tracks.len());
+    webhook
+        .report_progress(job_id, 5, "Downloading tracks...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let mut meter = replaygain::AlbumMeter::default();
+    for (i, track) in tracks.iter().enumerate() {
+        let path = temp_dir.path().join(format!("track_{}.wav", i));
+        let sha256 = storage
+            .download_file(&track.source_url, &path, track.source_sha256.as_deref())
+            .await?;
+        let buffer = audio::read_audio_cached(&path, &sha256)?;
+        meter.add(&buffer)?;
+        drop(buffer);
+        std::fs::remove_file(&path).ok();
+        webhook
+            .report_progress(
+                job_id,
+                5 + (90 * (i + 1) / tracks.len()) as u8,
+                &format!("Measured track {}/{}", i + 1, tracks.len()),
+            )
+            .await?;
+    }
+    let gains = meter.finish()?;
+
+    webhook
+        .report_progress(job_id, 100, "Replay gain complete")
+        .await?;
+    webhook.report_replay_gain(job_id, tracks, &gains).await?;
+
+    info!(
+        "ReplayGain for {} tracks: album {:.2} dB",
+        tracks.len(),
+        gains[0].album_gain_db.unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Process a pipeline job
+///
+/// Stages run in the job's order; each encode stage's deliverable is
+/// uploaded as soon as it is written.
+async fn process_pipeline_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    stages: &[Stage],
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    pipeline::validate(stages).map_err(refused)?;
+    info!(
+        "Running {}-stage pipeline on track {}",
+        stages.len(),
+        track_id
+    );
+    webhook
+        .report_progress(job_id, 5, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    download_plugin_modules(stages, temp_dir.path(), storage).await?;
+    let input_path = temp_dir.path().join("input.wav");
+    let sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    let mut buffer = audio::read_audio_cached(&input_path, &sha256)?;
+
+    let mut reports = Vec::with_capacity(stages.len());
+    let mut files = Vec::new();
+    for (i, stage) in stages.iter().enumerate() {
+        webhook
+            .report_progress(
+                job_id,
+                10 + (85 * i / stages.len()) as u8,
+                &format!("Stage {}/{}: {}", i + 1, stages.len(), stage.name()),
+            )
+            .await?;
+        let (report, rendered) =
+            pipeline::run_stage(&mut buffer, i, stage, temp_dir.path()).await?;
+        reports.push(report);
+
+        if let Some(rendered) = rendered {
+            let (_, content_type) = rendered.format.file_info();
+            let key = Storage::generate_key("pipelines", track_id, &rendered.filename);
+            let uploaded = storage
+                .upload_file(&rendered.path, &key, content_type)
+                .await?;
+            std::fs::remove_file(&rendered.path).ok();
+            files.push(ExportFile {
+                track_id: Some(track_id.to_string()),
+                format: rendered.format,
+                filename: rendered.filename,
+                sample_rate: buffer.sample_rate,
+                url: uploaded.url,
+                key,
+                sha256: uploaded.sha256,
+            });
+        }
+    }
+    let final_lufs = mastering::calculate_loudness(&buffer)?;
+    let final_true_peak = mastering::calculate_true_peak(&buffer)?;
+
+    webhook
+        .report_progress(job_id, 100, "Pipeline complete")
+        .await?;
+    webhook
+        .report_pipeline(job_id, &reports, &files, final_lufs, final_true_peak)
+        .await?;
+
+    info!(
+        "Pipeline on track {}: {} stages, {} files, {:.1} LUFS",
+        track_id,
+        reports.len(),
+        files.len(),
+        final_lufs
+    );
+    Ok(())
+}
+
+/// Download and check the module of each plugin stage into `dir`
+///
+/// Done before the audio is fetched, so a bad module fails the job early.
+async fn download_plugin_modules(stages: &[Stage], dir: &Path, storage: &Storage) -> Result<()> {
+    for (i, stage) in stages.iter().enumerate() {
+        if let Stage::Plugin {
+            module_url,
+            module_sha256,
+            ..
+        } = stage
+        {
+            let path = pipeline::module_path(dir, i);
+            storage
+                .download_file(module_url, &path, Some(module_sha256))
+                .await?;
+            plugin::check_module(&config::get().plugins, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the plugin, external and clap stages of a fix or master job
+async fn run_custom_stages(
+    buffer: &mut AudioBuffer,
+    stages: &[Stage],
+    dir: &Path,
+) -> Result<Vec<StageReport>> {
+    let mut reports = Vec::with_capacity(stages.len());
+    for (i, stage) in stages.iter().enumerate() {
+        // Custom stages have no deliverable of their own
+        let (report, _) = pipeline::run_stage(buffer, i, stage, dir).await?;
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Process a waveform-peaks job
+#[allow(clippy::too_many_arguments)]
+async fn process_waveform_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    samples_per_pixel: &[u32],
+    bits: Option<u8>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    let (samples_per_pixel, bits) = waveform::validate(samples_per_pixel, bits).map_err(refused)?;
+    info!(
+        "Generating waveform peaks for track {} at {:?} samples per pixel",
+        track_id, samples_per_pixel
+    );
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 30, "Generating waveform peaks...")
+        .await?;
+
+    // Peaks are gathered as the audio is decoded, like analysis
+    let waveform = waveform::waveform_file(&input_path, &samples_per_pixel)?;
+    webhook
+        .report_progress(job_id, 80, "Uploading waveform data...")
+        .await?;
+
+    let mut files = Vec::with_capacity(waveform.levels.len());
+    for level in &waveform.levels {
+        let dat = level.to_dat(waveform.sample_rate, bits);
+        let suffix = format!("peaks_{}.dat", level.samples_per_pixel);
+        let key = Storage::generate_key("waveforms", track_id, &suffix);
+        let uploaded = storage
+            .upload_bytes(&dat, &key, "application/octet-stream")
+            .await?;
+        files.push(WaveformFile {
+            samples_per_pixel: level.samples_per_pixel,
+            length: level.length(),
+            url: uploaded.url,
+            key,
+            sha256: uploaded.sha256,
+        });
+    }
+
+    let json = serde_json::to_vec(&waveform.to_json(bits))?;
+    let json_key = Storage::generate_key("waveforms", track_id, "peaks.json");
+    let json_upload = storage
+        .upload_bytes(&json, &json_key, "application/json")
+        .await?;
+
+    webhook
+        .report_progress(job_id, 100, "Waveform peaks complete")
+        .await?;
+    webhook
+        .report_waveform(
+            job_id,
+            &waveform,
+            bits,
+            &source_sha256,
+            &files,
+            &json_upload.url,
+            &json_key,
+            &json_upload.sha256,
+        )
+        .await?;
+
+    info!(
+        "Waveform peaks for {}: {} levels over {:.1}s",
+        track_id,
+        files.len(),
+        waveform.duration_secs
+    );
+    Ok(())
+}
+
+/// Process a spectrogram job
+#[allow(clippy::too_many_arguments)]
+async fn process_spectrogram_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    resolutions: &[SpectrogramResolution],
+    scale: SpectrogramScale,
+    color_map: ColorMap,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    let resolutions = spectrogram::validate(resolutions).map_err(refused)?;
+    info!(
+        "Rendering {} spectrogram images for track {}",
+        resolutions.len(),
+        track_id
+    );
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 30, "Rendering spectrogram...")
+        .await?;
+
+    // The STFT runs as the audio is decoded, like analysis
+    let spectrogram = spectrogram::spectrogram_file(&input_path, &resolutions, scale, color_map)?;
+    webhook
+        .report_progress(job_id, 80, "Uploading spectrogram images...")
+        .await?;
+
+    let mut images = Vec::with_capacity(spectrogram.images.len());
+    for (resolution, rgb) in &spectrogram.images {
+        let png = spectrogram::encode_png(resolution.width, resolution.height, rgb)?;
+        let suffix = format!("spectrogram_{}x{}.png", resolution.width, resolution.height);
+        let key = Storage::generate_key("spectrograms", track_id, &suffix);
+        let uploaded = storage.upload_bytes(&png, &key, "image/png").await?;
+        images.push(SpectrogramImage {
+            width: resolution.width,
+            height: resolution.height,
+            url: uploaded.url,
+            key,
+            sha256: uploaded.sha256,
+        });
+    }
+
+    webhook
+        .report_progress(job_id, 100, "Spectrogram complete")
+        .await?;
+    webhook
+        .report_spectrogram(
+            job_id,
+            &spectrogram,
+            scale,
+            color_map,
+            &source_sha256,
+            &images,
+        )
+        .await?;
+
+    info!(
+        "Spectrogram for {}: {} images over {:.1}s",
+        track_id,
+        images.len(),
+        spectrogram.duration_secs
+    );
+    Ok(())
+}
+
+/// Process a stem separation job
+#[allow(clippy::too_many_arguments)]
+async fn process_stem_separation_job(
+    job_id: &str,
+    track_id: &str,
+    source_url: &str,
+    source_sha256: Option<&str>,
+    model: Option<&str>,
+    two_stems: Option<&str>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    stems::validate(model, two_stems).map_err(refused)?;
+    let settings = &config::get().separation;
+    let model = model.unwrap_or(&settings.model);
+    info!("Separating track {} into stems with {}", track_id, model);
+    webhook
+        .report_progress(job_id, 10, "Downloading audio file...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let input_path = temp_dir.path().join("input.wav");
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 20, "Separating stems...")
+        .await?;
+
+    let out_dir = temp_dir.path().join("stems");
+    let outputs = stems::separate(settings, &input_path, &out_dir, model, two_stems).await?;
+    webhook
+        .report_progress(job_id, 80, "Measuring and uploading stems...")
+        .await?;
+
+    let mut files = Vec::with_capacity(outputs.len());
+    for stem in &outputs {
+        let analysis = audio::analyze_file(&stem.path, 24)?;
+        let key = Storage::generate_key("stems", track_id, &format!("{}.wav", stem.name));
+        let uploaded = storage.upload_file(&stem.path, &key, "audio/wav").await?;
+        files.push(StemFile {
+            name: stem.name.clone(),
+            url: uploaded.url,
+            key,
+            sha256: uploaded.sha256,
+            integrated_lufs: analysis.integrated_lufs,
+            loudness_range: analysis.loudness_range,
+            true_peak: analysis.true_peak,
+            sample_peak: analysis.sample_peak,
+        });
+    }
+
+    webhook
+        .report_progress(job_id, 100, "Stem separation complete")
+        .await?;
+    webhook
+        .report_stems(job_id, model, &source_sha256, &files)
+        .await?;
+
+    info!(
+        "Stems for {}: {}",
+        track_id,
+        files
+            .iter()
+            .map(|f| format!("{} {:.1} LUFS", f.name, f.integrated_lufs))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+/// Process a compare job
+///
+/// Sources are given as (URL, expected SHA-256).
+#[allow(clippy::too_many_arguments)]
+async fn process_compare_job(
+    job_id: &str,
+    track_id: &str,
+    (reference_url, reference_sha256): (&str, Option<&str>),
+    (candidate_url, candidate_sha256): (&str, Option<&str>),
+    max_offset_seconds: f64,
+    render_difference: bool,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    info!("Comparing two renders of track {}", track_id);
+    webhook
+        .report_progress(job_id, 10, "Downloading renders...")
+        .await?;
+
+    let temp_dir = temp::job_dir()?;
+    let reference_path = temp_dir.path().join("reference.wav");
+    let candidate_path = temp_dir.path().join("candidate.wav");
+    let reference_sha256 = storage
+        .download_file(reference_url, &reference_path, reference_sha256)
+        .await?;
+    let candidate_sha256 = storage
+        .download_file(candidate_url, &candidate_path, candidate_sha256)
+        .await?;
+    webhook
+        .report_progress(job_id, 30, "Aligning and comparing...")
+        .await?;
 
-use crate::s3::S3Client;
-use crate::types::{Job, LoudnessTarget, MasterProfile};
-use crate::webhook::WebhookClient;
+    let reference = audio::read_audio_cached(&reference_path, &reference_sha256)?;
+    let candidate = audio::read_audio_cached(&candidate_path, &candidate_sha256)?;
+    let (comparison, difference) = compare::compare(&reference, &candidate, max_offset_seconds)?;
+    drop((reference, candidate));
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("worker_dsp=info".parse()?)
-                .add_directive("warn".parse()?),
-        )
-        .init();
+    let difference_upload = if render_difference {
+        webhook
+            .report_progress(job_id, 80, "Uploading difference audio...")
+            .await?;
+        let path = temp_dir.path().join("difference.wav");
+        audio::write_wav_file(&difference, &path, 32)?;
+        let key = Storage::generate_key("comparisons", track_id, "difference.wav");
+        let uploaded = storage.upload_file(&path, &key, "audio/wav").await?;
+        Some((uploaded, key))
+    } else {
+        None
+    };
 
-    info!("Budi DSP Worker starting...");
+    webhook
+        .report_progress(job_id, 100, "Comparison complete")
+        .await?;
+    webhook
+        .report_compare(
+            job_id,
+            &comparison,
+            &reference_sha256,
+            &candidate_sha256,
+            difference_upload.as_ref(),
+        )
+        .await?;
 
-    // Connect to Redis
-    let redis_url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string());
-    let client = redis::Client::open(redis_url)?;
-    let mut conn = client.get_multiplexed_async_connection().await?;
-
-    // Initialize S3 client
-    let s3 = S3Client::from_env().await?;
-
-    // Initialize webhook client
-    let webhook = WebhookClient::from_env()?;
-
-    // Queue name for DSP jobs
-    let queue = env::var("DSP_QUEUE").unwrap_or_else(|_| "dsp-jobs".to_string());
-
-    info!("Listening for jobs on queue: {}", queue);
-
-    // Main worker loop
-    loop {
-        // Block until a job is available (0 = block forever)
-        let result: Option<(String, String)> = conn.brpop(&queue, 0.0).await?;
-
-        if let Some((_key, payload)) = result {
-            match serde_json::from_str::<Job>(&payload) {
-                Ok(job) => {
-                    let job_id = job.job_id().to_string();
-                    info!(
-                        "Processing job: {} (type: {:?})",
-                        job_id,
-                        std::mem::discriminant(&job)
-                    );
-
-                    if let Err(e) = process_job(&job, &s3, &webhook).await {
-                        error!("Job {} failed: {:?}", job_id, e);
-                        let job_type = match &job {
-                            Job::Analyze { .. } => "analysis",
-                            Job::Fix { .. } => "fix",
-                            Job::Master { .. } => "master",
-                            Job::AlbumMaster { .. } => "album-master",
-                            Job::Export { .. } => "export",
-                        };
-                        if let Err(we) = webhook
-                            .report_failure(&job_id, job_type, &e.to_string())
-                            .await
-                        {
-                            error!("Failed to report job failure: {:?}", we);
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to parse job: {:?}", e);
-                    warn!("Payload was: {}", payload);
-                }
-            }
-        }
-    }
+    info!(
+        "Compared renders of {}: offset {} frames, null depth {:.1} dB, loudness {:+.1} LU",
+        track_id,
+        comparison.offset_frames,
+        comparison.null_depth_db,
+        comparison.loudness_delta.integrated_lufs
+    );
+    Ok(())
 }
 
-/// Process a single job
-async fn process_job(job: &Job, s3: &S3Client, webhook: &WebhookClient) -> Result<()> {
-    match job {
-        Job::Analyze {
-            job_id,
-            track_id,
-            source_url,
-        } => process_analyze_job(job_id, track_id, source_url, s3, webhook).await,
-        Job::Fix {
-            job_id,
-            track_id,
-            source_url,
-            modules,
-        } => process_fix_job(job_id, track_id, source_url, modules, s3, webhook).await,
-        Job::Master {
-            job_id,
-            track_id,
-            source_url,
-            profile,
-            loudness_target,
-        } => {
-            process_master_job(
-                job_id,
-                track_id,
-                source_url,
-                profile,
-                loudness_target,
-                s3,
-                webhook,
-            )
-            .await
-        }
-        Job::AlbumMaster { job_id, .. } => {
-            // Album master is handled by orchestrating individual master jobs
-            info!("Album master job {} - delegating to API", job_id);
-            Ok(())
-        }
-        Job::Export { job_id, .. } => {
-            // Export is handled separately
-            info!("Export job {} - delegating to API", job_id);
-            Ok(())
-        }
-    }
+/// Settings of a podcast-process job, defaults applied
+struct PodcastSettings {
+    /// `None` picks the target for the channel count
+    target_lufs: Option<f64>,
+    high_pass_hz: f32,
+    gate: bool,
+    de_ess: bool,
+    mono: bool,
+    mp3_bitrate: u32,
 }
 
-/// Process an analyze job
-async fn process_analyze_job(
+/// Process a podcast-process job
+async fn process_podcast_job(
     job_id: &str,
     track_id: &str,
     source_url: &str,
-    s3: &S3Client,
+    source_sha256: Option<&str>,
+    settings: &PodcastSettings,
+    storage: &Storage,
     webhook: &WebhookClient,
 ) -> Result<()> {
-    info!("Analyzing track {}", track_id);
+    info!("Processing spoken-word track {}", track_id);
     webhook
         .report_progress(job_id, 10, "Downloading audio file...")
         .await?;
 
-    // Create temp directory for processing
-    let temp_dir = TempDir::new()?;
+    let temp_dir = temp::job_dir()?;
     let input_path = temp_dir.path().join("input.wav");
-
-    // Download the source file
-    s3.download_file(source_url, &input_path).await?;
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
     webhook
-        .report_progress(job_id, 30, "Decoding audio...")
+        .report_progress(job_id, 25, "Processing dialogue...")
         .await?;
 
-    // Read and decode the audio file
-    let buffer = audio::read_audio_file(&input_path)?;
+    let mut buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+    if settings.mono {
+        podcast::downmix_mono(&mut buffer);
+    }
+    let source = Loudness::from(&analysis::analyze_audio(&buffer, 24)?);
+    let options = podcast::PodcastOptions {
+        target_lufs: settings
+            .target_lufs
+            .unwrap_or_else(|| podcast::default_target_lufs(buffer.channels)),
+        high_pass_hz: settings.high_pass_hz,
+        gate: settings.gate,
+        de_ess: settings.de_ess,
+    };
+    let result = podcast::process(&mut buffer, &options)?;
+    let output = Loudness::from(&analysis::analyze_audio(&buffer, 24)?);
     webhook
-        .report_progress(job_id, 50, "Analyzing loudness and peaks...")
+        .report_progress(job_id, 75, "Encoding MP3...")
         .await?;
 
-    // Analyze the audio
-    let bit_depth = 24; // Assume 24-bit for analysis
-    let result = analysis::analyze_audio(&buffer, bit_depth)?;
-    webhook
-        .report_progress(job_id, 80, "Generating report...")
+    let mp3_path = temp_dir.path().join("episode.mp3");
+    audio::write_mp3_file(&buffer, &mp3_path, settings.mp3_bitrate)?;
+    let mp3_key = Storage::generate_key("podcasts", track_id, "episode.mp3");
+    let mp3 = storage
+        .upload_file(&mp3_path, &mp3_key, "audio/mpeg")
         .await?;
 
-    // Generate JSON report
-    let report_json = serde_json::to_string_pretty(&result)?;
-    let report_key = S3Client::generate_key("reports", track_id, "analysis.json");
-    let report_url = s3
+    let report_json = serde_json::to_string_pretty(&serde_json::json!({
+        "trackId": track_id,
+        "sourceSha256": source_sha256,
+        "source": source,
+        "output": output,
+        "chain": result,
+        "highPassHz": options.high_pass_hz,
+        "gate": options.gate,
+        "deEss": options.de_ess,
+        "mono": settings.mono,
+        "mp3Bitrate": settings.mp3_bitrate,
+    }))?;
+    let report_key = Storage::generate_key("reports", track_id, "podcast-loudness.json");
+    let report = storage
         .upload_bytes(report_json.as_bytes(), &report_key, "application/json")
         .await?;
 
     webhook
-        .report_progress(job_id, 100, "Analysis complete")
+        .report_progress(job_id, 100, "Podcast processing complete")
         .await?;
-
-    // Report results to API
     webhook
-        .report_analysis(job_id, &result, Some(&report_url))
+        .report_podcast(
+            job_id,
+            &result,
+            &source,
+            &output,
+            (&mp3, &mp3_key),
+            (&report, &report_key),
+            &source_sha256,
+        )
         .await?;
 
     info!(
-        "Analysis complete for {}: {:.1} LUFS, {:.1} dBTP",
-        track_id, result.integrated_lufs, result.true_peak
+        "Podcast processing complete for {}: {:.1} -> {:.1} LUFS, {:.1} dBTP",
+        track_id, source.integrated_lufs, result.final_lufs, result.final_true_peak
     );
-
     Ok(())
 }
 
 /// Process a fix job
+///
+/// Custom stages run after the fix modules, each reported as a change. A
+/// dry run applies both in memory and reports the changes and the resulting
+/// levels, without encoding or uploading the result.
+#[allow(clippy::too_many_arguments)]
 async fn process_fix_job(
     job_id: &str,
     track_id: &str,
     source_url: &str,
-    modules: &[String],
-    s3: &S3Client,
+    source_sha256: Option<&str>,
+    modules: &[FixModule],
+    stages: &[Stage],
+    dry_run: bool,
+    storage: &Storage,
     webhook: &WebhookClient,
 ) -> Result<()> {
+    pipeline::validate_custom(stages).map_err(refused)?;
     info!("Fixing track {} with modules: {:?}", track_id, modules);
     webhook
         .report_progress(job_id, 10, "Downloading audio file...")
         .await?;
 
-    let temp_dir = TempDir::new()?;
+    let temp_dir = temp::job_dir()?;
+    download_plugin_modules(stages, temp_dir.path(), storage).await?;
     let input_path = temp_dir.path().join("input.wav");
     let output_path = temp_dir.path().join("fixed.wav");
 
     // Download the source file
-    s3.download_file(source_url, &input_path).await?;
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
     webhook
         .report_progress(job_id, 30, "Applying fixes...")
         .await?;
 
     // Read audio
-    let mut buffer = audio::read_audio_file(&input_path)?;
+    let mut buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+
+    if dry_run {
+        let source_lufs = mastering::calculate_loudness(&buffer)?;
+        let source_true_peak = mastering::calculate_true_peak(&buffer)?;
+        let mut changes = fix::apply_fixes(&mut buffer, modules)?;
+        changes.extend(stage_changes(
+            run_custom_stages(&mut buffer, stages, temp_dir.path()).await?,
+        ));
+        let plan = serde_json::json!({
+            "modules": modules,
+            "stages": stages,
+            "sourceLufs": source_lufs,
+            "sourceTruePeak": source_true_peak,
+            "changes": changes,
+            "predictedLufs": mastering::calculate_loudness(&buffer)?,
+            "predictedTruePeak": mastering::calculate_true_peak(&buffer)?,
+        });
+        webhook
+            .report_progress(job_id, 100, "Dry run complete")
+            .await?;
+        webhook.report_plan(job_id, "fix", &plan).await?;
+        info!(
+            "Dry run for {}: {} changes planned",
+            track_id,
+            changes.len()
+        );
+        return Ok(());
+    }
 
     // Apply fixes
-    let changes = fix::apply_fixes(&mut buffer, modules)?;
+    let mut changes = fix::apply_fixes(&mut buffer, modules)?;
+    changes.extend(stage_changes(
+        run_custom_stages(&mut buffer, stages, temp_dir.path()).await?,
+    ));
     webhook
         .report_progress(job_id, 70, "Encoding output...")
         .await?;
@@ -236,15 +2301,24 @@ async fn process_fix_job(
     audio::write_wav_file(&buffer, &output_path, 24)?;
 
     // Upload fixed file
-    let output_key = S3Client::generate_key("fixed", track_id, "fixed.wav");
-    let fixed_url = s3
+    let output_key = Storage::generate_key("fixed", track_id, "fixed.wav");
+    let fixed = storage
         .upload_file(&output_path, &output_key, "audio/wav")
         .await?;
 
     webhook.report_progress(job_id, 100, "Fix complete").await?;
 
     // Report results
-    webhook.report_fix(job_id, &fixed_url, &changes).await?;
+    webhook
+        .report_fix(
+            job_id,
+            &source_sha256,
+            &fixed.url,
+            &output_key,
+            &fixed.sha256,
+            &changes,
+        )
+        .await?;
 
     info!(
         "Fix complete for {}: {} changes applied",
@@ -255,46 +2329,113 @@ async fn process_fix_job(
     Ok(())
 }
 
+/// A fix job's custom stages, reported alongside its modules' changes
+fn stage_changes(reports: Vec<StageReport>) -> impl Iterator<Item = FixChange> {
+    reports.into_iter().map(|report| FixChange {
+        module: report.stage.to_string(),
+        description: report.description,
+    })
+}
+
 /// Process a master job
+///
+/// Custom stages run on the source before the chain, and a final clap stage
+/// after it, before the master is measured; both are listed in the QC
+/// report. A dry run stops after analysis and reports the chain's settings
+/// and the predicted loudness instead of rendering.
+#[allow(clippy::too_many_arguments)]
 async fn process_master_job(
     job_id: &str,
     track_id: &str,
     source_url: &str,
-    profile: &str,
-    loudness_target: &str,
-    s3: &S3Client,
+    source_sha256: Option<&str>,
+    profile: MasterProfile,
+    target: LoudnessTarget,
+    reference_url: Option<&str>,
+    reference_sha256: Option<&str>,
+    mut options: MasteringOptions,
+    stages: &[Stage],
+    final_stage: Option<&Stage>,
+    dry_run: bool,
+    storage: &Storage,
     webhook: &WebhookClient,
 ) -> Result<()> {
+    pipeline::validate_custom(stages).map_err(refused)?;
+    if let Some(stage) = final_stage {
+        pipeline::validate_final(stage).map_err(refused)?;
+    }
+    // Also checked when the payload is parsed; a bad blend or trim must not
+    // cost a download and decode first
+    options.validate().map_err(refused)?;
     info!(
         "Mastering track {} with profile {} and target {}",
-        track_id, profile, loudness_target
+        track_id, profile, target
     );
     webhook
         .report_progress(job_id, 5, "Downloading audio file...")
         .await?;
 
-    let temp_dir = TempDir::new()?;
+    let temp_dir = temp::job_dir()?;
+    download_plugin_modules(stages, temp_dir.path(), storage).await?;
     let input_path = temp_dir.path().join("input.wav");
-    let output_hd_path = temp_dir.path().join("master_24bit.wav");
-    let output_16_path = temp_dir.path().join("master_16bit.wav");
-    let output_mp3_path = temp_dir.path().join("master.mp3");
 
     // Download the source file
-    s3.download_file(source_url, &input_path).await?;
+    let source_sha256 = storage
+        .download_file(source_url, &input_path, source_sha256)
+        .await?;
     webhook
         .report_progress(job_id, 15, "Decoding audio...")
         .await?;
 
     // Read audio
-    let mut buffer = audio::read_audio_file(&input_path)?;
+    let mut buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+    if !stages.is_empty() {
+        webhook
+            .report_progress(job_id, 18, "Running custom stages...")
+            .await?;
+    }
+    let mut stage_reports = run_custom_stages(&mut buffer, stages, temp_dir.path()).await?;
+
+    if let Some(reference_url) = reference_url {
+        webhook
+            .report_progress(job_id, 20, "Analyzing reference track...")
+            .await?;
+
+        let reference_path = temp_dir.path().join("reference.wav");
+        let reference_sha256 = storage
+            .download_file(reference_url, &reference_path, reference_sha256)
+            .await?;
+        let reference = audio::read_audio_cached(&reference_path, &reference_sha256)?;
+        let matched = mastering::match_reference(&buffer, &reference)?;
+        info!(
+            "Matching reference for {}: {:.1} LUFS, EQ {:+.1}/{:+.1}/{:+.1} dB",
+            track_id,
+            matched.reference_lufs,
+            matched.low_gain_db,
+            matched.mid_gain_db,
+            matched.high_gain_db
+        );
+        options.reference = Some(matched);
+    }
+
+    if dry_run {
+        let plan = mastering::plan_mastering(&buffer, profile, target, &options)?;
+        webhook
+            .report_progress(job_id, 100, "Dry run complete")
+            .await?;
+        webhook.report_plan(job_id, "master", &plan).await?;
+        info!(
+            "Dry run for {}: {:+.1} dB to {:.1} LUFS",
+            track_id, plan.gain_db, plan.predicted_lufs
+        );
+        return Ok(());
+    }
+
+    // Apply mastering chain
     webhook
         .report_progress(job_id, 25, "Applying EQ...")
         .await?;
 
-    // Apply mastering chain
-    let master_profile = MasterProfile::from(profile);
-    let target = LoudnessTarget::from(loudness_target);
-
     webhook
         .report_progress(job_id, 40, "Applying compression...")
         .await?;
@@ -302,61 +2443,120 @@ async fn process_master_job(
         .report_progress(job_id, 55, "Applying limiter...")
         .await?;
 
-    let result = mastering::apply_mastering(&mut buffer, master_profile, target)?;
+    let mut result = mastering::apply_mastering(&mut buffer, profile, target, &options)?;
+    if let Some(stage) = final_stage {
+        webhook
+            .report_progress(job_id, 65, "Running final stage...")
+            .await?;
+        let (report, _) =
+            pipeline::run_stage(&mut buffer, stages.len(), stage, temp_dir.path()).await?;
+        stage_reports.push(report);
+        result.remeasure(&buffer, options.output_trim_db)?;
+    }
     webhook
         .report_progress(job_id, 70, "Encoding outputs...")
         .await?;
 
-    // Write 24-bit WAV
-    audio::write_wav_file(&buffer, &output_hd_path, 24)?;
-    webhook
-        .report_progress(job_id, 80, "Encoding 16-bit WAV...")
-        .await?;
+    let outputs = upload_master_outputs(
+        track_id,
+        profile,
+        target,
+        &buffer,
+        &options,
+        &result,
+        &stage_reports,
+        &source_sha256,
+        temp_dir.path(),
+        storage,
+    )
+    .await?;
 
-    // Write 16-bit WAV
-    audio::write_wav_file(&buffer, &output_16_path, 16)?;
     webhook
-        .report_progress(job_id, 85, "Encoding MP3...")
+        .report_progress(job_id, 100, "Mastering complete")
         .await?;
 
-    // Write MP3
-    audio::write_mp3_file(&buffer, &output_mp3_path, 320)?;
-    webhook
-        .report_progress(job_id, 90, "Uploading files...")
-        .await?;
+    // Report results
+    webhook.report_master(job_id, &outputs).await?;
+
+    info!(
+        "Mastering complete for {}: {:.1} LUFS, {:.1} dBTP, QC: {}",
+        track_id,
+        result.final_lufs,
+        result.final_true_peak,
+        if result.passes_qc { "PASS" } else { "FAIL" }
+    );
+
+    Ok(())
+}
+
+/// Encode the mastered buffer, upload all deliverables and the QC report
+#[allow(clippy::too_many_arguments)]
+async fn upload_master_outputs(
+    track_id: &str,
+    profile: MasterProfile,
+    loudness_target: LoudnessTarget,
+    buffer: &AudioBuffer,
+    options: &MasteringOptions,
+    result: &MasteringResult,
+    stages: &[StageReport],
+    source_sha256: &str,
+    dir: &Path,
+    storage: &Storage,
+) -> Result<AlbumTrackResult> {
+    let output_hd_path = dir.join("master_24bit.wav");
+    let output_16_path = dir.join("master_16bit.wav");
+    let output_mp3_path = dir.join("master.mp3");
+
+    audio::write_wav_file(buffer, &output_hd_path, 24)?;
+    audio::write_wav_file_dithered(buffer, &output_16_path, 16, options.dither)?;
+    audio::write_mp3_file(buffer, &output_mp3_path, 320)?;
 
-    // Upload all files
-    let hd_key = S3Client::generate_key("masters", track_id, "master_24bit.wav");
-    let wav_hd_url = s3
+    let hd_key = Storage::generate_key("masters", track_id, "master_24bit.wav");
+    let wav_hd = storage
         .upload_file(&output_hd_path, &hd_key, "audio/wav")
         .await?;
 
-    let key_16 = S3Client::generate_key("masters", track_id, "master_16bit.wav");
-    let wav_16_url = s3
+    let key_16 = Storage::generate_key("masters", track_id, "master_16bit.wav");
+    let wav16 = storage
         .upload_file(&output_16_path, &key_16, "audio/wav")
         .await?;
 
-    let mp3_key = S3Client::generate_key("masters", track_id, "master.mp3");
-    let mp3_url = s3
+    let mp3_key = Storage::generate_key("masters", track_id, "master.mp3");
+    let mp3_preview = storage
         .upload_file(&output_mp3_path, &mp3_key, "audio/mpeg")
         .await?;
 
     // Generate QC report
+    let qc_gate = &config::get().qc;
     let qc_report = serde_json::json!({
         "trackId": track_id,
         "profile": profile,
         "loudnessTarget": loudness_target,
+        "targetLufs": result.target_lufs,
         "finalLufs": result.final_lufs,
         "finalTruePeak": result.final_true_peak,
+        "loudnessIterations": result.loudness_iterations,
         "passesQc": result.passes_qc,
+        "referenceMatch": result.reference,
+        "eqMode": options.eq_mode,
+        "precision": options.precision,
+        "limiterMode": result.limiter_mode,
+        "bandReduction": result.band_reduction,
+        "dynamics": result.dynamics,
+        "dither": options.dither,
+        "mix": options.mix,
+        "outputTrimDb": options.output_trim_db,
+        "stages": stages,
         "qcGate": {
-            "truePeakMax": -2.0,
+            "truePeakMax": qc_gate.true_peak_max,
             "truePeakActual": result.final_true_peak,
-            "truePeakPasses": result.final_true_peak <= -2.0
+            "truePeakPasses": result.final_true_peak <= qc_gate.true_peak_max,
+            "loudnessTolerance": qc_gate.loudness_tolerance,
+            "loudnessPasses": result.passes_loudness
         }
     });
-    let qc_key = S3Client::generate_key("reports", track_id, "qc.json");
-    let qc_url = s3
+    let qc_key = Storage::generate_key("reports", track_id, "qc.json");
+    let qc = storage
         .upload_bytes(
             serde_json::to_string_pretty(&qc_report)?.as_bytes(),
             &qc_key,
@@ -364,31 +2564,616 @@ async fn process_master_job(
         )
         .await?;
 
+    // Human-readable copy for client deliveries
+    let analysis = analysis::analyze_audio(buffer, 24)?;
+    let qc_pdf = qc_pdf::render_qc_pdf(
+        &qc_pdf::QcPdf {
+            track_id,
+            profile,
+            loudness_target,
+            options,
+            result,
+            analysis: &analysis,
+        },
+        buffer,
+    )?;
+    let qc_pdf_key = Storage::generate_key("reports", track_id, "qc.pdf");
+    let qc_pdf = storage
+        .upload_bytes(&qc_pdf, &qc_pdf_key, "application/pdf")
+        .await?;
+
+    Ok(AlbumTrackResult {
+        track_id: track_id.to_string(),
+        wav_hd_url: wav_hd.url,
+        wav_hd_key: hd_key,
+        wav_hd_sha256: wav_hd.sha256,
+        wav16_url: wav16.url,
+        wav16_key: key_16,
+        wav16_sha256: wav16.sha256,
+        mp3_preview_url: mp3_preview.url,
+        mp3_preview_key: mp3_key,
+        mp3_preview_sha256: mp3_preview.sha256,
+        final_lufs: result.final_lufs,
+        final_true_peak: result.final_true_peak,
+        passes_qc: result.passes_qc,
+        passes_loudness: result.passes_loudness,
+        true_peak_max: qc_gate.true_peak_max,
+        qc_report_url: Some(qc.url),
+        qc_report_key: Some(qc_key),
+        qc_report_sha256: Some(qc.sha256),
+        qc_pdf_url: Some(qc_pdf.url),
+        qc_pdf_key: Some(qc_pdf_key),
+        qc_pdf_sha256: Some(qc_pdf.sha256),
+        source_sha256: source_sha256.to_string(),
+    })
+}
+
+/// Track transitions for the continuous album render
+struct AlbumTransitions<'a> {
+    /// Silence after each track (seconds)
+    gap_seconds: &'a [f64],
+    /// Crossfade into the next track (seconds)
+    crossfade_seconds: &'a [f64],
+    render_continuous: bool,
+}
+
+/// Process an album master job
+///
+/// All tracks are analyzed first so the album can share one loudness plan
+/// and tonal balance; each track is then decoded again and mastered on its
+/// own to keep memory bounded to a single track.
+#[allow(clippy::too_many_arguments)]
+async fn process_album_master_job(
+    job_id: &str,
+    project_id: &str,
+    track_ids: &[String],
+    source_urls: &[String],
+    source_sha256s: &[String],
+    profile: MasterProfile,
+    target: LoudnessTarget,
+    normalize_loudness: bool,
+    transitions: &AlbumTransitions<'_>,
+    dry_run: bool,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    if track_ids.is_empty() {
+        anyhow::bail!("Album master job has no tracks");
+    }
+    if source_urls.len() != track_ids.len() {
+        anyhow::bail!(
+            "Album master job has {} tracks but {} source URLs",
+            track_ids.len(),
+            source_urls.len()
+        );
+    }
+    if !source_sha256s.is_empty() && source_sha256s.len() != track_ids.len() {
+        anyhow::bail!(
+            "Album master job has {} tracks but {} source checksums",
+            track_ids.len(),
+            source_sha256s.len()
+        );
+    }
+
+    info!(
+        "Album mastering project {} ({} tracks) with profile {} and target {}",
+        project_id,
+        track_ids.len(),
+        profile,
+        target
+    );
+
+    let temp_dir = temp::job_dir()?;
+    let track_count = track_ids.len();
+
+    // Pass 1: download and profile every track
+    let mut input_paths = Vec::with_capacity(track_count);
+    let mut source_digests = Vec::with_capacity(track_count);
+    let mut profiles = Vec::with_capacity(track_count);
+    for (i, (track_id, source_url)) in track_ids.iter().zip(source_urls).enumerate() {
+        webhook
+            .report_progress(
+                job_id,
+                (5 + 25 * i / track_count) as u8,
+                &format!("Analyzing track {}/{}...", i + 1, track_count),
+            )
+            .await?;
+
+        let input_path = temp_dir.path().join(format!("input_{}.wav", i));
+        let expected_sha256 = source_sha256s.get(i).map(String::as_str);
+        let source_sha256 = storage
+            .download_file(source_url, &input_path, expected_sha256)
+            .await?;
+        let buffer = audio::read_audio_cached(&input_path, &source_sha256)?;
+        let track_profile = album::profile_track(&buffer)?;
+        info!(
+            "Album track {}: {:.1} LUFS",
+            track_id, track_profile.integrated_lufs
+        );
+        profiles.push(track_profile);
+        input_paths.push(input_path);
+        source_digests.push(source_sha256);
+    }
+
+    let plan = album::plan_album(&profiles, target.lufs_value(), normalize_loudness);
+
+    if dry_run {
+        let mut tracks = Vec::with_capacity(track_count);
+        for (i, (track_id, settings)) in track_ids.iter().zip(plan).enumerate() {
+            let buffer = audio::read_audio_cached(&input_paths[i], &source_digests[i])?;
+            let options = MasteringOptions {
+                reference: Some(settings),
+                ..Default::default()
+            };
+            let plan = mastering::plan_mastering(&buffer, profile, target, &options)?;
+            tracks.push(serde_json::json!({ "trackId": track_id, "plan": plan }));
+        }
+        let plan = serde_json::json!({
+            "projectId": project_id,
+            "targetLufs": target.lufs_value(),
+            "normalizeLoudness": normalize_loudness,
+            "tracks": tracks,
+        });
+        webhook
+            .report_progress(job_id, 100, "Dry run complete")
+            .await?;
+        webhook.report_plan(job_id, "album-master", &plan).await?;
+        info!(
+            "Dry run for album {}: {} tracks planned",
+            project_id, track_count
+        );
+        return Ok(());
+    }
+
+    // Pass 2: master each track with its planned settings
+    let mut tracks = Vec::with_capacity(track_count);
+    let mut results = Vec::with_capacity(track_count);
+    let mut tonal_balance = Vec::with_capacity(track_count);
+    let mut render = album::AlbumRender::default();
+    for (i, (track_id, settings)) in track_ids.iter().zip(plan).enumerate() {
+        webhook
+            .report_progress(
+                job_id,
+                (30 + 65 * i / track_count) as u8,
+                &format!("Mastering track {}/{}...", i + 1, track_count),
+            )
+            .await?;
+
+        let mut buffer = audio::read_audio_cached(&input_paths[i], &source_digests[i])?;
+        let options = MasteringOptions {
+            reference: Some(settings),
+            ..Default::default()
+        };
+        let result = mastering::apply_mastering(&mut buffer, profile, target, &options)?;
+
+        let track_dir = temp_dir.path().join(format!("track_{}", i));
+        std::fs::create_dir_all(&track_dir)?;
+        let outputs = upload_master_outputs(
+            track_id,
+            profile,
+            target,
+            &buffer,
+            &options,
+            &result,
+            &[],
+            &source_digests[i],
+            &track_dir,
+            storage,
+        )
+        .await?;
+
+        info!(
+            "Album track {} mastered: {:.1} LUFS, {:.1} dBTP, QC: {}",
+            track_id,
+            result.final_lufs,
+            result.final_true_peak,
+            if result.passes_qc { "PASS" } else { "FAIL" }
+        );
+        tonal_balance.push(mastering::tonal_balance(&buffer)?);
+
+        if transitions.render_continuous {
+            // Transition settings are indexed by the track they follow
+            let previous = i.saturating_sub(1);
+            render.append(
+                &buffer,
+                transitions
+                    .gap_seconds
+                    .get(previous)
+                    .copied()
+                    .unwrap_or(0.0),
+                transitions
+                    .crossfade_seconds
+                    .get(previous)
+                    .copied()
+                    .unwrap_or(0.0),
+            )?;
+        }
+
+        tracks.push(outputs);
+        results.push(result);
+    }
+
+    let album_render = match render.buffer() {
+        Some(buffer) => {
+            webhook
+                .report_progress(job_id, 95, "Uploading continuous album render...")
+                .await?;
+
+            let render_path = temp_dir.path().join("album_continuous.wav");
+            audio::write_wav_file(buffer, &render_path, 24)?;
+            let render_key = Storage::generate_key("masters", project_id, "album_continuous.wav");
+            let uploaded = storage
+                .upload_file(&render_path, &render_key, "audio/wav")
+                .await?;
+
+            Some(AlbumRenderResult {
+                url: uploaded.url,
+                key: render_key,
+                sha256: uploaded.sha256,
+                track_offsets: render.track_offsets().to_vec(),
+                duration_secs: buffer.duration_secs(),
+            })
+        }
+        None => None,
+    };
+
+    let stats = album::album_stats(&results, target.lufs_value());
+    let report = album::consistency_report(track_ids, &results, &tonal_balance, stats.clone());
+    info!(
+        "Album consistency for {}: max {:.1} LU between tracks, max {:.1} dB tonal deviation",
+        project_id, report.max_track_delta_lu, report.max_tonal_deviation_db
+    );
+    let qc_key = Storage::generate_key("reports", project_id, "album_qc.json");
+    let album_qc = storage
+        .upload_bytes(
+            serde_json::to_string_pretty(&report)?.as_bytes(),
+            &qc_key,
+            "application/json",
+        )
+        .await?;
+
     webhook
-        .report_progress(job_id, 100, "Mastering complete")
+        .report_progress(job_id, 100, "Album mastering complete")
         .await?;
 
-    // Report results
     webhook
-        .report_master(
+        .report_album_master(
             job_id,
-            &wav_hd_url,
-            &wav_16_url,
-            &mp3_url,
-            result.final_lufs,
-            result.final_true_peak,
-            result.passes_qc,
-            Some(&qc_url),
+            project_id,
+            &tracks,
+            &stats,
+            album_render.as_ref(),
+            Some(&album_qc.url),
+            Some(&qc_key),
+            Some(&album_qc.sha256),
         )
         .await?;
 
     info!(
-        "Mastering complete for {}: {:.1} LUFS, {:.1} dBTP, QC: {}",
-        track_id,
-        result.final_lufs,
-        result.final_true_peak,
-        if result.passes_qc { "PASS" } else { "FAIL" }
+        "Album mastering complete for {}: {:.1} LUFS average, {:.1} LU spread, QC: {}",
+        project_id,
+        stats.average_lufs,
+        stats.loudness_spread,
+        if stats.all_pass_qc { "PASS" } else { "FAIL" }
+    );
+
+    Ok(())
+}
+
+/// Deliverable settings of an export job, shared by every track
+struct ExportSettings<'a> {
+    formats: &'a [ExportFormat],
+    include_qc: bool,
+    /// Output sample rates; empty keeps each master's own rate
+    sample_rates: &'a [u32],
+    flac_compression_level: u8,
+    disc: &'a DiscMetadata,
+}
+
+/// Default pause between tracks on CD (seconds)
+const DEFAULT_CD_PAUSE_SECS: f64 = 2.0;
+
+/// MP3 does not support sample rates above 48 kHz
+const MP3_MAX_SAMPLE_RATE: u32 = 48000;
+
+/// Process an export job
+async fn process_export_job(
+    job_id: &str,
+    project_id: &str,
+    tracks: &[ExportTrack],
+    settings: &ExportSettings<'_>,
+    storage: &Storage,
+    webhook: &WebhookClient,
+) -> Result<()> {
+    if tracks.is_empty() {
+        anyhow::bail!("Export job has no tracks");
+    }
+    if let Some(rate) = settings
+        .sample_rates
+        .iter()
+        .find(|r| !resample::SUPPORTED_SAMPLE_RATES.contains(r))
+    {
+        anyhow::bail!("Unsupported export sample rate: {} Hz", rate);
+    }
+
+    info!(
+        "Exporting project {} ({} tracks) as {:?} at {:?} Hz",
+        project_id,
+        tracks.len(),
+        settings.formats,
+        settings.sample_rates
     );
 
+    let temp_dir = temp::job_dir()?;
+    let mut files = Vec::new();
+    let mut resampled = false;
+    let package_path = temp_dir.path().join("export.zip");
+    let mut package = package::ExportPackage::create(&package_path)?;
+
+    let ddp_dir = temp_dir.path().join("ddp");
+    let mut ddp = if settings.formats.contains(&ExportFormat::Ddp) {
+        std::fs::create_dir_all(&ddp_dir)?;
+        Some(ddp::DdpBuilder::create(&ddp_dir)?)
+    } else {
+        None
+    };
+
+    for (i, track) in tracks.iter().enumerate() {
+        webhook
+            .report_progress(
+                job_id,
+                (5 + 90 * i / tracks.len()) as u8,
+                &format!("Exporting track {}/{}...", i + 1, tracks.len()),
+            )
+            .await?;
+
+        let input_path = temp_dir.path().join(format!("master_{}.wav", i));
+        let master_sha256 = storage
+            .download_file(
+                &track.master_url,
+                &input_path,
+                track.master_sha256.as_deref(),
+            )
+            .await?;
+        let buffer = audio::read_audio_cached(&input_path, &master_sha256)?;
+
+        let artwork = match &track.metadata.artwork_url {
+            Some(artwork_url) => {
+                let artwork_path = temp_dir.path().join(format!("artwork_{}", i));
+                storage
+                    .download_file(artwork_url, &artwork_path, None)
+                    .await?;
+                let artwork = tags::Artwork::from_bytes(std::fs::read(&artwork_path)?)?;
+                let ext = if artwork.mime_type == "image/png" {
+                    "png"
+                } else {
+                    "jpg"
+                };
+                package.add_file(
+                    &artwork_path,
+                    ManifestEntry {
+                        track_id: Some(track.track_id.clone()),
+                        ..ManifestEntry::new(
+                            format!("artwork/{}.{}", track.track_id, ext),
+                            EntryKind::Artwork,
+                        )
+                    },
+                )?;
+                Some(artwork)
+            }
+            None => None,
+        };
+
+        if let Some(ddp) = ddp.as_mut() {
+            let cd_audio = if buffer.sample_rate == ddp::CD_SAMPLE_RATE {
+                Cow::Borrowed(&buffer)
+            } else {
+                resampled = true;
+                Cow::Owned(resample::resample(&buffer, ddp::CD_SAMPLE_RATE)?)
+            };
+            ddp.append_track(
+                &cd_audio,
+                &ddp::DdpTrack {
+                    isrc: track.metadata.isrc.clone(),
+                    title: track.metadata.title.clone(),
+                    performer: track.metadata.artist.clone(),
+                    pause_secs: track.pause_seconds.unwrap_or(DEFAULT_CD_PAUSE_SECS),
+                },
+            )?;
+        }
+
+        let rates = if settings.sample_rates.is_empty() {
+            vec![buffer.sample_rate]
+        } else {
+            settings.sample_rates.to_vec()
+        };
+
+        for rate in rates {
+            let converted = if rate == buffer.sample_rate {
+                Cow::Borrowed(&buffer)
+            } else {
+                resampled = true;
+                Cow::Owned(resample::resample(&buffer, rate)?)
+            };
+
+            for format in settings.formats {
+                if *format == ExportFormat::Ddp {
+                    continue;
+                }
+                if *format == ExportFormat::Mp3_320 && rate > MP3_MAX_SAMPLE_RATE {
+                    warn!("Skipping MP3 export of {} at {} Hz", track.track_id, rate);
+                    continue;
+                }
+
+                let (suffix, content_type) = format.file_info();
+                let filename = if settings.sample_rates.is_empty() {
+                    suffix.to_string()
+                } else {
+                    with_rate_label(suffix, rate)
+                };
+                let output_path = temp_dir.path().join(format!("{}_{}", i, filename));
+                match format {
+                    ExportFormat::Wav24 => audio::write_wav_file(&converted, &output_path, 24)?,
+                    ExportFormat::Wav16 => {
+                        audio::write_wav_file_dithered(&converted, &output_path, 16, Dither::Tpdf)?
+                    }
+                    ExportFormat::Mp3_320 => audio::write_mp3_file(&converted, &output_path, 320)?,
+                    ExportFormat::Flac => audio::write_flac_file(
+                        &converted,
+                        &output_path,
+                        settings.flac_compression_level,
+                    )?,
+                    ExportFormat::Ddp => unreachable!("DDP is written per project"),
+                }
+                tags::tag_file(&output_path, *format, &track.metadata, artwork.as_ref())?;
+                package.add_file(
+                    &output_path,
+                    ManifestEntry {
+                        track_id: Some(track.track_id.clone()),
+                        format: Some(*format),
+                        sample_rate: Some(rate),
+                        duration_secs: Some(converted.frame_count() as f64 / rate as f64),
+                        ..ManifestEntry::new(
+                            format!("{}/{}", track.track_id, filename),
+                            EntryKind::Audio,
+                        )
+                    },
+                )?;
+
+                let key = Storage::generate_key("exports", &track.track_id, &filename);
+                let uploaded = storage
+                    .upload_file(&output_path, &key, content_type)
+                    .await?;
+                files.push(ExportFile {
+                    track_id: Some(track.track_id.clone()),
+                    format: *format,
+                    filename,
+                    sample_rate: rate,
+                    url: uploaded.url,
+                    key,
+                    sha256: uploaded.sha256,
+                });
+            }
+        }
+    }
+
+    let ddp_summary = match ddp {
+        Some(builder) => {
+            webhook
+                .report_progress(job_id, 95, "Packaging DDP image...")
+                .await?;
+
+            let album = ddp::DdpAlbum {
+                upc: settings.disc.upc.clone(),
+                title: settings.disc.title.clone(),
+                performer: settings.disc.artist.clone(),
+            };
+            let archive_path = temp_dir.path().join("ddp.zip");
+            let summary = builder.finish(&album, &archive_path)?;
+
+            let (filename, content_type) = ExportFormat::Ddp.file_info();
+            package.add_file(
+                &archive_path,
+                ManifestEntry {
+                    format: Some(ExportFormat::Ddp),
+                    sample_rate: Some(ddp::CD_SAMPLE_RATE),
+                    ..ManifestEntry::new(filename.to_string(), EntryKind::Ddp)
+                },
+            )?;
+            let key = Storage::generate_key("exports", project_id, filename);
+            let uploaded = storage
+                .upload_file(&archive_path, &key, content_type)
+                .await?;
+            files.push(ExportFile {
+                track_id: None,
+                format: ExportFormat::Ddp,
+                filename: filename.to_string(),
+                sample_rate: ddp::CD_SAMPLE_RATE,
+                url: uploaded.url,
+                key,
+                sha256: uploaded.sha256,
+            });
+            Some(summary)
+        }
+        None => None,
+    };
+
+    let mut qc_report_urls = Vec::new();
+    if settings.include_qc {
+        for (i, track) in tracks.iter().enumerate() {
+            let reports = [(&track.qc_report_url, "json"), (&track.qc_pdf_url, "pdf")];
+            for (url, ext) in reports {
+                let Some(url) = url else { continue };
+                let qc_path = temp_dir.path().join(format!("qc_{}.{}", i, ext));
+                storage.download_file(url, &qc_path, None).await?;
+                package.add_file(
+                    &qc_path,
+                    ManifestEntry {
+                        track_id: Some(track.track_id.clone()),
+                        ..ManifestEntry::new(
+                            format!("qc/{}.{}", track.track_id, ext),
+                            EntryKind::QcReport,
+                        )
+                    },
+                )?;
+                qc_report_urls.push(url.clone());
+            }
+        }
+    }
+
+    // Export manifest, uploaded on its own and as the last entry of the package
+    let manifest = serde_json::json!({
+        "projectId": project_id,
+        "files": files,
+        "contents": package.entries(),
+        "qcReportUrls": qc_report_urls,
+        "sampleRateConversion": resampled.then_some(resample::SRC_SETTINGS),
+        "dither16Bit": Dither::Tpdf,
+        "ddp": ddp_summary,
+    });
+    let manifest_key = Storage::generate_key("exports", project_id, "manifest.json");
+    let manifest_upload = storage
+        .upload_bytes(
+            serde_json::to_string_pretty(&manifest)?.as_bytes(),
+            &manifest_key,
+            "application/json",
+        )
+        .await?;
+
+    package.finish(&manifest)?;
+    let pack_key = Storage::generate_key("exports", project_id, "export.zip");
+    let pack = storage
+        .upload_file(&package_path, &pack_key, "application/zip")
+        .await?;
+
+    webhook
+        .report_progress(job_id, 100, "Export complete")
+        .await?;
+
+    webhook
+        .report_export(
+            job_id,
+            project_id,
+            &files,
+            &qc_report_urls,
+            Some(&manifest_upload.url),
+            Some(&manifest_key),
+            Some(&manifest_upload.sha256),
+            Some(&pack.url),
+            Some(&pack_key),
+            Some(&pack.sha256),
+        )
+        .await?;
+
+    info!("Export complete for {}: {} files", project_id, files.len());
+
     Ok(())
 }
+
+/// Insert the sample rate before the extension: "master.flac" -> "master_96000hz.flac"
+fn with_rate_label(filename: &str, rate: u32) -> String {
+    match filename.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}_{}hz.{}", stem, rate, ext),
+        None => format!("{}_{}hz", filename, rate),
+    }
+}
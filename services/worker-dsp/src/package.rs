@@ -0,0 +1,159 @@
+//! ZIP packaging of export deliverables with a machine-readable manifest
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+
+use crate::types::ExportFormat;
+
+/// Name of the manifest inside the package
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// What a packaged file is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    Audio,
+    Ddp,
+    QcReport,
+    Artwork,
+}
+
+/// One file in the package, as listed in the manifest
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// Path inside the archive
+    pub path: String,
+    pub kind: EntryKind,
+    pub track_id: Option<String>,
+    pub format: Option<ExportFormat>,
+    pub sample_rate: Option<u32>,
+    pub duration_secs: Option<f64>,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Archive being assembled from files on disk
+pub struct ExportPackage {
+    zip: zip::ZipWriter<File>,
+    entries: Vec<ManifestEntry>,
+}
+
+impl ExportPackage {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).context("Failed to create export package")?;
+        Ok(Self {
+            zip: zip::ZipWriter::new(file),
+            entries: Vec::new(),
+        })
+    }
+
+    /// Copy a file into the archive, filling in its size and checksum
+    ///
+    /// Audio and images are already dense, so everything except text is
+    /// stored rather than deflated.
+    pub fn add_file(&mut self, source: &Path, mut entry: ManifestEntry) -> Result<()> {
+        let method = match entry.kind {
+            EntryKind::QcReport => CompressionMethod::Deflated,
+            _ => CompressionMethod::Stored,
+        };
+        self.zip.start_file(
+            entry.path.as_str(),
+            SimpleFileOptions::default()
+                .compression_method(method)
+                .large_file(true),
+        )?;
+
+        let mut file = File::open(source)
+            .with_context(|| format!("Failed to open {} for packaging", source.display()))?;
+        let mut hasher = Sha256::new();
+        let mut chunk = vec![0u8; 1 << 16];
+        let mut bytes = 0u64;
+        loop {
+            let n = file.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&chunk[..n]);
+            self.zip.write_all(&chunk[..n])?;
+            bytes += n as u64;
+        }
+
+        entry.bytes = bytes;
+        entry.sha256 = hex(&hasher.finalize());
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// Write the manifest as the last entry and close the archive
+    pub fn finish(mut self, manifest: &serde_json::Value) -> Result<()> {
+        self.zip.start_file(
+            MANIFEST_NAME,
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+        )?;
+        self.zip
+            .write_all(serde_json::to_string_pretty(manifest)?.as_bytes())?;
+        self.zip.finish()?;
+        Ok(())
+    }
+}
+
+impl ManifestEntry {
+    /// Entry for `path` whose size and checksum are filled in when added
+    pub fn new(path: String, kind: EntryKind) -> Self {
+        Self {
+            path,
+            kind,
+            track_id: None,
+            format: None,
+            sample_rate: None,
+            duration_secs: None,
+            bytes: 0,
+            sha256: String::new(),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_checksums_and_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("qc.json");
+        std::fs::write(&source, b"abc").unwrap();
+
+        let archive_path = dir.path().join("package.zip");
+        let mut package = ExportPackage::create(&archive_path).unwrap();
+        package
+            .add_file(
+                &source,
+                ManifestEntry::new("qc/track.json".to_string(), EntryKind::QcReport),
+            )
+            .unwrap();
+        assert_eq!(
+            package.entries()[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        package.finish(&serde_json::json!({ "ok": true })).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&archive_path).unwrap()).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert!(archive.by_name(MANIFEST_NAME).is_ok());
+    }
+}
@@ -0,0 +1,691 @@
+//! Processing chains described by the job
+//!
+//! A pipeline job lists its stages in order, each with its own settings,
+//! and they run one after another on the decoded source: fixes, EQ,
+//! compression, saturation, gain, limiting and resampling in any order, with
+//! an `encode` stage wherever a deliverable should be rendered from the
+//! audio as it stands. A new chain needs a new payload, not a new worker,
+//! and processing of the customer's own can run as a `plugin` stage (see
+//! `plugin`), through a tool the worker is configured with as an
+//! `external` stage (see `external`), or, in builds with the `clap` feature,
+//! through a configured CLAP plugin and preset as a `clap` stage (see
+//! `clap_host`). Fix and master jobs take those three kinds of stage too.
+//!
+//! Every stage's settings are checked before the source is downloaded, so a
+//! bad chain fails at once rather than halfway through.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::audio;
+#[cfg(feature = "clap")]
+use crate::clap_host;
+use crate::config;
+use crate::external;
+use crate::plugin;
+use crate::resample;
+use crate::types::{AudioBuffer, Dither, EqMode, ExportFormat, FixModule, LimiterMode, Precision};
+use crate::MP3_MAX_SAMPLE_RATE;
+use budi_dsp_core::fir::BiquadCoefs;
+use budi_dsp_core::mastering::{self, BandCompression, Compressor};
+use budi_dsp_core::{fix, settings};
+
+/// Most stages one pipeline takes
+pub const MAX_STAGES: usize = 32;
+
+/// Most bands one EQ stage takes
+const MAX_EQ_BANDS: usize = 16;
+
+/// Defaults of the stage settings a job leaves out
+const DEFAULT_ATTACK_MS: f32 = 10.0;
+const DEFAULT_RELEASE_MS: f32 = 100.0;
+const DEFAULT_PEAK_Q: f32 = 1.0;
+const DEFAULT_FLAC_COMPRESSION: u8 = 5;
+
+/// One stage of a pipeline
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "stage", rename_all = "kebab-case")]
+pub enum Stage {
+    /// A fix module with its settings (`{"stage": "fix", "module":
+    /// "de_ess", "frequencyHz": 6000}`)
+    Fix(FixModule),
+    /// Filters and shelves, applied in order
+    #[serde(rename_all = "camelCase")]
+    Eq {
+        bands: Vec<EqBand>,
+        #[serde(default)]
+        mode: EqMode,
+        #[serde(default)]
+        precision: Precision,
+    },
+    /// Wideband feed-forward compressor, each channel on its own
+    #[serde(rename_all = "camelCase")]
+    Compressor {
+        threshold_db: f32,
+        ratio: f32,
+        #[serde(default)]
+        attack_ms: Option<f32>,
+        #[serde(default)]
+        release_ms: Option<f32>,
+        #[serde(default)]
+        makeup_db: Option<f32>,
+    },
+    /// Tanh soft clipping; `drive` 0 is gentle, 1 is heavy
+    Saturation { drive: f32 },
+    #[serde(rename_all = "camelCase")]
+    Gain { gain_db: f32 },
+    /// True-peak limiting with makeup gain to a loudness target
+    #[serde(rename_all = "camelCase")]
+    Limiter {
+        target_lufs: f64,
+        /// dBTP, default `qc.true_peak_max`
+        #[serde(default)]
+        ceiling_db: Option<f64>,
+        #[serde(default)]
+        mode: Option<LimiterMode>,
+        #[serde(default)]
+        release_ms: Option<f32>,
+        #[serde(default)]
+        precision: Precision,
+    },
+    #[serde(rename_all = "camelCase")]
+    Resample { sample_rate: u32 },
+    /// Render the audio as it stands to a deliverable; `name` labels the
+    /// file (default: the format)
+    Encode {
+        format: ExportFormat,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// A customer's WebAssembly module, run sandboxed with `params`
+    #[serde(rename_all = "camelCase")]
+    Plugin {
+        module_url: String,
+        /// Required, so a job runs exactly the module it was built with
+        module_sha256: String,
+        /// Reported in place of the module's hash
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        params: serde_json::Value,
+    },
+    /// A processor from the worker's `[processors]` config, given `params`
+    External {
+        processor: String,
+        #[serde(default)]
+        params: serde_json::Value,
+    },
+    /// A plugin from the worker's `[clap]` config, restored from one of its
+    /// configured presets
+    Clap {
+        plugin: String,
+        #[serde(default)]
+        preset: Option<String>,
+    },
+}
+
+/// Shape of one EQ band
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EqBandType {
+    LowShelf,
+    HighShelf,
+    Peak,
+    /// 12 dB/octave Butterworth
+    HighPass,
+    /// 12 dB/octave Butterworth
+    LowPass,
+}
+
+/// One band of an EQ stage
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EqBand {
+    #[serde(rename = "type")]
+    pub band_type: EqBandType,
+    pub frequency_hz: f32,
+    /// Ignored by the pass filters
+    #[serde(default)]
+    pub gain_db: f32,
+    /// Peak bands only (default 1)
+    #[serde(default)]
+    pub q: Option<f32>,
+}
+
+impl Stage {
+    /// Name of the stage, as reported
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fix(_) => "fix",
+            Self::Eq { .. } => "eq",
+            Self::Compressor { .. } => "compressor",
+            Self::Saturation { .. } => "saturation",
+            Self::Gain { .. } => "gain",
+            Self::Limiter { .. } => "limiter",
+            Self::Resample { .. } => "resample",
+            Self::Encode { .. } => "encode",
+            Self::Plugin { .. } => "plugin",
+            Self::External { .. } => "external",
+            Self::Clap { .. } => "clap",
+        }
+    }
+}
+
+/// Check a job's stages
+pub fn validate(stages: &[Stage]) -> Result<()> {
+    anyhow::ensure!(
+        stages.len() <= MAX_STAGES,
+        "A pipeline takes at most {} stages",
+        MAX_STAGES
+    );
+    anyhow::ensure!(
+        stages.iter().any(|s| matches!(s, Stage::Encode { .. })),
+        "A pipeline needs at least one encode stage"
+    );
+    check_stages(stages)
+}
+
+/// Check the stages a fix or master job runs on its audio: only `plugin`,
+/// `external` and `clap` stages, since the job renders its own deliverables
+pub fn validate_custom(stages: &[Stage]) -> Result<()> {
+    anyhow::ensure!(
+        stages.len() <= MAX_STAGES,
+        "A job takes at most {} stages",
+        MAX_STAGES
+    );
+    for (i, stage) in stages.iter().enumerate() {
+        anyhow::ensure!(
+            matches!(
+                stage,
+                Stage::Plugin { .. } | Stage::External { .. } | Stage::Clap { .. }
+            ),
+            "Stage {} ({}): only plugin, external and clap stages run outside pipelines",
+            i,
+            stage.name()
+        );
+    }
+    check_stages(stages)
+}
+
+/// Check a master job's final stage, which must host a CLAP plugin
+pub fn validate_final(stage: &Stage) -> Result<()> {
+    anyhow::ensure!(
+        matches!(stage, Stage::Clap { .. }),
+        "Final stage ({}): only a clap stage can follow the mastering chain",
+        stage.name()
+    );
+    check_stages(std::slice::from_ref(stage))
+}
+
+/// Check each stage's settings
+fn check_stages(stages: &[Stage]) -> Result<()> {
+    let mut filenames = Vec::new();
+    for (i, stage) in stages.iter().enumerate() {
+        let within = |x: f32, range: std::ops::RangeInclusive<f32>, what: &str| {
+            anyhow::ensure!(
+                range.contains(&x),
+                "Stage {} ({}): {} must be between {} and {}",
+                i,
+                stage.name(),
+                what,
+                range.start(),
+                range.end()
+            );
+            Ok(())
+        };
+        match stage {
+            // Checked as the payload was parsed
+            Stage::Fix(_) => {}
+            Stage::Eq { bands, .. } => {
+                anyhow::ensure!(
+                    !bands.is_empty() && bands.len() <= MAX_EQ_BANDS,
+                    "Stage {} (eq): needs between 1 and {} bands",
+                    i,
+                    MAX_EQ_BANDS
+                );
+                for band in bands {
+                    within(band.frequency_hz, 10.0..=24000.0, "frequencyHz")?;
+                    within(band.gain_db, -24.0..=24.0, "gainDb")?;
+                    if let Some(q) = band.q {
+                        within(q, 0.1..=10.0, "q")?;
+                    }
+                }
+            }
+            Stage::Compressor {
+                threshold_db,
+                ratio,
+                attack_ms,
+                release_ms,
+                makeup_db,
+            } => {
+                within(*threshold_db, -60.0..=0.0, "thresholdDb")?;
+                within(*ratio, 1.0..=20.0, "ratio")?;
+                within(
+                    attack_ms.unwrap_or(DEFAULT_ATTACK_MS),
+                    0.1..=500.0,
+                    "attackMs",
+                )?;
+                within(
+                    release_ms.unwrap_or(DEFAULT_RELEASE_MS),
+                    1.0..=5000.0,
+                    "releaseMs",
+                )?;
+                within(makeup_db.unwrap_or(0.0), -12.0..=24.0, "makeupDb")?;
+            }
+            Stage::Saturation { drive } => within(*drive, 0.0..=2.0, "drive")?,
+            Stage::Gain { gain_db } => within(*gain_db, -48.0..=24.0, "gainDb")?,
+            Stage::Limiter {
+                target_lufs,
+                ceiling_db,
+                release_ms,
+                ..
+            } => {
+                within(*target_lufs as f32, -40.0..=-5.0, "targetLufs")?;
+                if let Some(ceiling_db) = ceiling_db {
+                    within(*ceiling_db as f32, -6.0..=0.0, "ceilingDb")?;
+                }
+                within(
+                    release_ms.unwrap_or(DEFAULT_RELEASE_MS),
+                    1.0..=1000.0,
+                    "releaseMs",
+                )?;
+            }
+            Stage::Resample { sample_rate } => anyhow::ensure!(
+                resample::SUPPORTED_SAMPLE_RATES.contains(sample_rate),
+                "Stage {} (resample): unsupported sample rate {} Hz",
+                i,
+                sample_rate
+            ),
+            Stage::Encode { format, name } => {
+                anyhow::ensure!(
+                    *format != ExportFormat::Ddp,
+                    "Stage {} (encode): DDP is only written by export jobs",
+                    i
+                );
+                anyhow::ensure!(
+                    name.as_deref().is_none_or(valid_name),
+                    "Stage {} (encode): name must be 1-64 letters, digits, '-' or '_'",
+                    i
+                );
+                let filename = output_filename(*format, name.as_deref());
+                anyhow::ensure!(
+                    !filenames.contains(&filename),
+                    "Stage {} (encode): another stage already writes {}",
+                    i,
+                    filename
+                );
+                filenames.push(filename);
+            }
+            Stage::Plugin {
+                module_sha256,
+                name,
+                ..
+            } => {
+                anyhow::ensure!(
+                    module_sha256.len() == 64
+                        && module_sha256.chars().all(|c| c.is_ascii_hexdigit()),
+                    "Stage {} (plugin): moduleSha256 must be a hex SHA-256",
+                    i
+                );
+                anyhow::ensure!(
+                    name.as_deref().is_none_or(valid_name),
+                    "Stage {} (plugin): name must be 1-64 letters, digits, '-' or '_'",
+                    i
+                );
+            }
+            Stage::External { processor, .. } => anyhow::ensure!(
+                config::get().processors.contains_key(processor),
+                "Stage {} (external): no processor {:?} is configured",
+                i,
+                processor
+            ),
+            Stage::Clap { plugin, preset } => {
+                anyhow::ensure!(
+                    cfg!(feature = "clap"),
+                    "Stage {} (clap): this worker was built without CLAP hosting",
+                    i
+                );
+                let settings = config::get().clap.get(plugin).with_context(|| {
+                    format!("Stage {} (clap): no plugin {:?} is configured", i, plugin)
+                })?;
+                anyhow::ensure!(
+                    preset
+                        .as_ref()
+                        .is_none_or(|preset| settings.presets.contains_key(preset)),
+                    "Stage {} (clap): plugin {} has no preset {:?}",
+                    i,
+                    plugin,
+                    preset.as_deref().unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is fit for a file name and a report
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Where the handler downloads the module of plugin stage `index`
+pub fn module_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("plugin_{}.wasm", index))
+}
+
+/// File an encode stage writes
+fn output_filename(format: ExportFormat, name: Option<&str>) -> String {
+    let (suffix, _) = format.file_info();
+    match (name, suffix.rsplit_once('.')) {
+        (Some(name), Some((_, extension))) => format!("{}.{}", name, extension),
+        _ => suffix.to_string(),
+    }
+}
+
+/// A deliverable rendered by an encode stage
+#[derive(Debug, Clone)]
+pub struct Rendered {
+    pub format: ExportFormat,
+    pub filename: String,
+    pub path: PathBuf,
+}
+
+/// What one stage did
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StageReport {
+    pub index: usize,
+    pub stage: &'static str,
+    pub description: String,
+}
+
+/// Run stage `index` of a pipeline on `buffer`, writing any deliverable to
+/// `dir`, where plugin modules are expected at `module_path`
+pub async fn run_stage(
+    buffer: &mut AudioBuffer,
+    index: usize,
+    stage: &Stage,
+    dir: &Path,
+) -> Result<(StageReport, Option<Rendered>)> {
+    let mut rendered = None;
+    let description = match stage {
+        Stage::Fix(module) => fix::apply_fixes(buffer, std::slice::from_ref(module))?
+            .pop()
+            .map(|change| change.description)
+            .unwrap_or_default(),
+        Stage::Eq {
+            bands,
+            mode,
+            precision,
+        } => {
+            let sections = eq_sections(buffer.sample_rate, bands)?;
+            mastering::apply_eq_sections(buffer, &sections, *mode, *precision)?;
+            format!("Applied {} EQ bands", bands.len())
+        }
+        Stage::Compressor {
+            threshold_db,
+            ratio,
+            attack_ms,
+            release_ms,
+            makeup_db,
+        } => {
+            let settings = BandCompression {
+                band: "wideband",
+                threshold_db: *threshold_db,
+                ratio: *ratio,
+                attack_ms: attack_ms.unwrap_or(DEFAULT_ATTACK_MS),
+                release_ms: release_ms.unwrap_or(DEFAULT_RELEASE_MS),
+            };
+            let makeup = 10.0_f32.powf(makeup_db.unwrap_or(0.0) / 20.0);
+            for channel in &mut buffer.samples {
+                Compressor::new(&settings, buffer.sample_rate as f32).process(channel);
+                channel.iter_mut().for_each(|s| *s *= makeup);
+            }
+            format!(
+                "Compressed {}:1 above {:.1} dBFS",
+                settings.ratio, settings.threshold_db
+            )
+        }
+        Stage::Saturation { drive } => {
+            mastering::apply_saturation(buffer, *drive)?;
+            format!("Saturated with drive {:.2}", drive)
+        }
+        Stage::Gain { gain_db } => {
+            let gain = 10.0_f32.powf(gain_db / 20.0);
+            for channel in &mut buffer.samples {
+                channel.iter_mut().for_each(|s| *s *= gain);
+            }
+            format!("Applied {:+.1} dB gain", gain_db)
+        }
+        Stage::Limiter {
+            target_lufs,
+            ceiling_db,
+            mode,
+            release_ms,
+            precision,
+        } => {
+            let outcome = mastering::apply_limiter(
+                buffer,
+                *target_lufs,
+                mode.unwrap_or(LimiterMode::BrickWall),
+                release_ms.unwrap_or(DEFAULT_RELEASE_MS),
+                *precision,
+                ceiling_db.unwrap_or(settings::get().true_peak_max),
+            )?;
+            format!(
+                "Limited to {:.1} LUFS, {:.1} dBTP",
+                outcome.final_lufs, outcome.final_true_peak
+            )
+        }
+        Stage::Resample { sample_rate } => {
+            let from = buffer.sample_rate;
+            if from != *sample_rate {
+                *buffer = resample::resample(buffer, *sample_rate)?;
+            }
+            format!("Resampled from {} Hz to {} Hz", from, sample_rate)
+        }
+        Stage::Plugin {
+            module_sha256,
+            name,
+            params,
+            ..
+        } => {
+            plugin::process(
+                &config::get().plugins,
+                &module_path(dir, index),
+                params,
+                buffer,
+            )
+            .await?;
+            format!(
+                "Ran plugin {}",
+                name.as_deref().unwrap_or(&module_sha256[..12])
+            )
+        }
+        Stage::External { processor, params } => {
+            let settings = config::get()
+                .processors
+                .get(processor)
+                .with_context(|| format!("No processor {:?} is configured", processor))?;
+            external::process(processor, settings, params, buffer, dir).await?;
+            format!("Ran external processor {}", processor)
+        }
+        #[cfg(feature = "clap")]
+        Stage::Clap { plugin, preset } => {
+            let settings = config::get()
+                .clap
+                .get(plugin)
+                .with_context(|| format!("No CLAP plugin {:?} is configured", plugin))?;
+            let preset_path = match preset {
+                Some(preset) => Some(Path::new(settings.presets.get(preset).with_context(
+                    || format!("CLAP plugin {} has no preset {:?}", plugin, preset),
+                )?)),
+                None => None,
+            };
+            let latency = clap_host::process(settings, preset_path, buffer)?;
+            match preset {
+                Some(preset) => format!(
+                    "Ran CLAP plugin {} with preset {} ({} frames latency compensated)",
+                    plugin, preset, latency
+                ),
+                None => format!(
+                    "Ran CLAP plugin {} ({} frames latency compensated)",
+                    plugin, latency
+                ),
+            }
+        }
+        #[cfg(not(feature = "clap"))]
+        Stage::Clap { .. } => anyhow::bail!("This worker was built without CLAP hosting"),
+        Stage::Encode { format, name } => {
+            let filename = output_filename(*format, name.as_deref());
+            let path = dir.join(format!("{}_{}", index, filename));
+            match format {
+                ExportFormat::Wav24 => audio::write_wav_file(buffer, &path, 24)?,
+                ExportFormat::Wav16 => {
+                    audio::write_wav_file_dithered(buffer, &path, 16, Dither::Tpdf)?
+                }
+                ExportFormat::Mp3_320 => {
+                    anyhow::ensure!(
+                        buffer.sample_rate <= MP3_MAX_SAMPLE_RATE,
+                        "Stage {} (encode): MP3 needs {} Hz or below; resample first",
+                        index,
+                        MP3_MAX_SAMPLE_RATE
+                    );
+                    audio::write_mp3_file(buffer, &path, 320)?
+                }
+                ExportFormat::Flac => {
+                    audio::write_flac_file(buffer, &path, DEFAULT_FLAC_COMPRESSION)?
+                }
+                ExportFormat::Ddp => anyhow::bail!("DDP is only written by export jobs"),
+            }
+            let description = format!("Encoded {}", filename);
+            rendered = Some(Rendered {
+                format: *format,
+                filename,
+                path,
+            });
+            description
+        }
+    };
+    Ok((
+        StageReport {
+            index,
+            stage: stage.name(),
+            description,
+        },
+        rendered,
+    ))
+}
+
+/// Biquad sections of an EQ stage at `sample_rate`
+fn eq_sections(sample_rate: u32, bands: &[EqBand]) -> Result<Vec<BiquadCoefs>> {
+    let rate = sample_rate as f32;
+    bands
+        .iter()
+        .map(|band| {
+            anyhow::ensure!(
+                band.frequency_hz < rate / 2.0,
+                "EQ band at {} Hz is above the Nyquist frequency of {} Hz audio",
+                band.frequency_hz,
+                sample_rate
+            );
+            let hz = band.frequency_hz;
+            Ok(match band.band_type {
+                EqBandType::LowShelf => mastering::low_shelf_coefs(rate, hz, band.gain_db),
+                EqBandType::HighShelf => mastering::high_shelf_coefs(rate, hz, band.gain_db),
+                EqBandType::Peak => mastering::peaking_eq_coefs(
+                    rate,
+                    hz,
+                    band.gain_db,
+                    band.q.unwrap_or(DEFAULT_PEAK_Q),
+                ),
+                EqBandType::HighPass => mastering::highpass_butterworth_coefs(rate, hz),
+                EqBandType::LowPass => mastering::lowpass_butterworth_coefs(rate, hz),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_chain_from_payload() {
+        let stages: Vec<Stage> = serde_json::from_str(
+            r#"[
+                {"stage": "fix", "module": "dc_offset"},
+                {"stage": "eq", "bands": [
+                    {"type": "high-pass", "frequencyHz": 30},
+                    {"type": "peak", "frequencyHz": 1000, "gainDb": -6, "q": 2}
+                ]},
+                {"stage": "compressor", "thresholdDb": -20, "ratio": 3},
+                {"stage": "gain", "gainDb": -3},
+                {"stage": "encode", "format": "wav-24", "name": "premaster"},
+                {"stage": "limiter", "targetLufs": -14, "ceilingDb": -1},
+                {"stage": "encode", "format": "flac"}
+            ]"#,
+        )
+        .unwrap();
+        validate(&stages).unwrap();
+
+        let rate = 48000;
+        let mut buffer = AudioBuffer::new(2, rate);
+        for channel in &mut buffer.samples {
+            *channel = (0..rate as usize * 3)
+                .map(|i| {
+                    0.3 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / rate as f32).sin()
+                        + 0.05
+                })
+                .collect();
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let mut reports = Vec::new();
+        let mut rendered = Vec::new();
+        for (i, stage) in stages.iter().enumerate() {
+            let (report, file) = run_stage(&mut buffer, i, stage, dir.path()).await.unwrap();
+            reports.push(report);
+            rendered.extend(file);
+        }
+
+        assert_eq!(reports[0].stage, "fix");
+        assert_eq!(reports[5].stage, "limiter");
+        let names: Vec<&str> = rendered.iter().map(|r| r.filename.as_str()).collect();
+        assert_eq!(names, ["premaster.wav", "master.flac"]);
+        assert!(rendered.iter().all(|r| r.path.exists()));
+        let lufs = mastering::calculate_loudness(&buffer).unwrap();
+        assert!((lufs + 14.0).abs() < 1.0, "{}", lufs);
+
+        // No deliverable, or a DDP one, is refused before anything runs
+        assert!(validate(&stages[..4]).is_err());
+        let ddp: Vec<Stage> =
+            serde_json::from_str(r#"[{"stage": "encode", "format": "ddp"}]"#).unwrap();
+        assert!(validate(&ddp).is_err());
+    }
+
+    #[test]
+    fn test_custom_stages() {
+        let stages: Vec<Stage> = serde_json::from_str(&format!(
+            r#"[{{"stage": "plugin", "moduleUrl": "https://x/p.wasm", "moduleSha256": "{}"}}]"#,
+            "ab".repeat(32)
+        ))
+        .unwrap();
+        // No encode stage needed
+        validate_custom(&stages).unwrap();
+        validate_custom(&[]).unwrap();
+
+        let gain: Vec<Stage> = serde_json::from_str(r#"[{"stage": "gain", "gainDb": 1}]"#).unwrap();
+        let err = validate_custom(&gain).unwrap_err();
+        assert!(
+            err.to_string().contains("only plugin, external and clap"),
+            "{}",
+            err
+        );
+        assert!(validate_final(&stages[0]).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! WebAssembly plugins for custom processing stages
+//!
+//! Customers can ship their own processing as a WASI command module and
+//! run it as a `plugin` stage of a pipeline job, or among the `stages` of a
+//! fix or master job. Plugins run in a subprocess (the `wasmtime` CLI from
+//! `[plugins]`), which keeps the runtime out of the worker and a
+//! misbehaving plugin out of its memory.
+//! Wasmtime gives a module no files, network or environment unless asked
+//! to, so all a plugin sees is its audio, its parameters and its limits on
+//! memory and time.
+//!
+//! The interface, version 1:
+//!
+//! - The module's `_start` is called with the stage's `params` as a JSON
+//!   string in `argv[1]`.
+//! - Stdin carries the audio: the magic `BPA1`, then the channel count
+//!   (u32), sample rate (u32) and frame count (u64), then the interleaved
+//!   f32 samples, all little-endian.
+//! - The plugin writes its output to stdout the same way, with the same
+//!   channel count and sample rate. It may shorten the audio, or lengthen
+//!   it by up to `MAX_TAIL_SECS` (a reverb tail, say).
+//! - A non-zero exit fails the job; the last lines of stderr say why.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::PluginSettings;
+use crate::types::AudioBuffer;
+
+/// Magic at the start of the audio on stdin and stdout
+const MAGIC: &[u8; 4] = b"BPA1";
+
+/// Magic and header fields
+const HEADER_LEN: usize = 20;
+
+/// Most a plugin may lengthen the audio by (seconds)
+const MAX_TAIL_SECS: u64 = 60;
+
+/// Stderr lines kept when a plugin fails
+const ERROR_LINES: usize = 3;
+
+/// Check a downloaded module before it is run
+pub fn check_module(settings: &PluginSettings, path: &Path) -> Result<()> {
+    let size = std::fs::metadata(path)?.len();
+    anyhow::ensure!(
+        size <= settings.max_module_mb * 1024 * 1024,
+        "Plugin module is {} MB, over the {} MB limit",
+        size / (1024 * 1024),
+        settings.max_module_mb
+    );
+    let mut magic = [0u8; 4];
+    std::io::Read::read_exact(&mut std::fs::File::open(path)?, &mut magic)
+        .context("Plugin module is empty")?;
+    anyhow::ensure!(
+        &magic == b"\0asm",
+        "Plugin module is not a WebAssembly binary"
+    );
+    Ok(())
+}
+
+/// Run the plugin in `module` on `buffer`, replacing it with the output
+#[tracing::instrument(name = "dsp.plugin", skip_all)]
+pub async fn process(
+    settings: &PluginSettings,
+    module: &Path,
+    params: &serde_json::Value,
+    buffer: &mut AudioBuffer,
+) -> Result<()> {
+    let mut command = tokio::process::Command::new(&settings.command);
+    command
+        .arg("run")
+        .arg(format!(
+            "-Wmax-memory-size={}",
+            settings.max_memory_mb * 1024 * 1024
+        ))
+        .arg(format!("-Wtimeout={}s", settings.timeout_seconds))
+        .arg(module)
+        .arg(params.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to run plugin runtime ({})", settings.command))?;
+
+    // Written while the output is read, so neither side blocks on a full
+    // pipe
+    let input = encode(buffer);
+    let mut stdin = child.stdin.take().context("Plugin stdin unavailable")?;
+    let feed = async move {
+        // A plugin may stop reading early; its exit status tells
+        let _ = stdin.write_all(&input).await;
+    };
+
+    // Wasmtime enforces the timeout itself; this catches a stuck runtime
+    let run = async {
+        let (_, output) = tokio::join!(feed, child.wait_with_output());
+        output
+    };
+    let output = tokio::time::timeout(Duration::from_secs(settings.timeout_seconds + 10), run)
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Plugin didn't finish within {} seconds",
+                settings.timeout_seconds
+            )
+        })?
+        .context("Plugin runtime failed")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Plugin failed ({}): {}",
+            output.status,
+            error_summary(&output.stderr)
+        );
+    }
+    let samples = decode(&output.stdout, buffer)?;
+    buffer.samples = samples;
+    Ok(())
+}
+
+/// A buffer as a plugin reads it
+pub fn encode(buffer: &AudioBuffer) -> Vec<u8> {
+    let frames = buffer.frame_count();
+    let mut bytes = Vec::with_capacity(HEADER_LEN + frames * buffer.channels * 4);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&(buffer.channels as u32).to_le_bytes());
+    bytes.extend_from_slice(&buffer.sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(frames as u64).to_le_bytes());
+    for i in 0..frames {
+        for channel in &buffer.samples {
+            bytes.extend_from_slice(&channel[i].to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// A plugin's output, as channels matching `input`
+pub fn decode(bytes: &[u8], input: &AudioBuffer) -> Result<Vec<Vec<f32>>> {
+    anyhow::ensure!(
+        bytes.len() >= HEADER_LEN && &bytes[..4] == MAGIC,
+        "Plugin output doesn't start with a BPA1 header"
+    );
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    let (channels, sample_rate) = (u32_at(4) as usize, u32_at(8));
+    let frames = u64::from_le_bytes(bytes[12..20].try_into().unwrap());
+    anyhow::ensure!(
+        channels == input.channels && sample_rate == input.sample_rate,
+        "Plugin output is {} channels at {} Hz, not {} at {} Hz",
+        channels,
+        sample_rate,
+        input.channels,
+        input.sample_rate
+    );
+    anyhow::ensure!(
+        frames <= input.frame_count() as u64 + MAX_TAIL_SECS * sample_rate as u64,
+        "Plugin output is more than {} seconds longer than its input",
+        MAX_TAIL_SECS
+    );
+    let data = &bytes[HEADER_LEN..];
+    anyhow::ensure!(
+        data.len() as u64 == frames * channels as u64 * 4,
+        "Plugin output has {} bytes of samples, not the {} frames its header gives",
+        data.len(),
+        frames
+    );
+
+    let mut samples = vec![Vec::with_capacity(frames as usize); channels];
+    for frame in data.chunks_exact(channels * 4) {
+        for (channel, sample) in samples.iter_mut().zip(frame.chunks_exact(4)) {
+            let sample = f32::from_le_bytes(sample.try_into().unwrap());
+            anyhow::ensure!(sample.is_finite(), "Plugin output has non-finite samples");
+            channel.push(sample);
+        }
+    }
+    Ok(samples)
+}
+
+/// The last few lines of a plugin's stderr
+fn error_summary(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(ERROR_LINES)..].join(" / ")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_process_round_trips_through_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        // Stands in for Wasmtime: an identity plugin, or a failing one
+        // when its parameters ask
+        let script = dir.path().join("wasmtime");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             for last; do :; done\n\
+             case $last in *fail*) cat > /dev/null; echo 'bad params' >&2; exit 1;; esac\n\
+             cat\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let settings = PluginSettings {
+            command: script.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let mut buffer = AudioBuffer::new(2, 44100);
+        buffer.samples = vec![vec![0.1, 0.2, 0.3], vec![-0.1, -0.2, -0.3]];
+        let original = buffer.samples.clone();
+        let module = Path::new("plugin.wasm");
+        process(
+            &settings,
+            module,
+            &serde_json::json!({"gain": 1}),
+            &mut buffer,
+        )
+        .await
+        .unwrap();
+        assert_eq!(buffer.samples, original);
+
+        let err = process(&settings, module, &serde_json::json!("fail"), &mut buffer)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("bad params"), "{}", err);
+
+        // Output that disagrees with its header is refused
+        let mut bytes = encode(&buffer);
+        bytes.pop();
+        assert!(decode(&bytes, &buffer).is_err());
+    }
+}
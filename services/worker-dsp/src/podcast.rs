@@ -0,0 +1,379 @@
+//! Spoken-word processing for podcasts and voice-over
+//!
+//! The music master profiles suit neither the level nor the dynamics of
+//! speech, so dialogue gets its own chain:
+//!
+//! 1. High-pass filter, removing rumble, handling noise and plosive thumps
+//! 2. Gate, pulling room tone between phrases down by `GATE_RANGE_DB`
+//! 3. De-esser, ducking the sibilance band when it gets loud relative to the
+//!    whole signal
+//! 4. Leveler, riding the gain so quiet and loud speakers meet at one level
+//! 5. Compressor, evening out syllables within a phrase
+//! 6. Loudness normalization to -16 LUFS (stereo) or -19 LUFS (mono), as
+//!    AES TD1004 recommends, under a -1 dBTP limiter
+//!
+//! The gate, de-esser and leveler detect on all channels together, so a
+//! stereo recording keeps its image.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::types::{AudioBuffer, LimiterMode, Precision};
+use budi_dsp_core::mastering::{self, BandCompression, Biquad, Compressor};
+
+/// Corner of the rumble filter when a job doesn't give one (Hz)
+pub const DEFAULT_HIGH_PASS_HZ: f32 = 80.0;
+
+/// MP3 bitrate when a job doesn't give one (kbps)
+pub const DEFAULT_MP3_BITRATE: u32 = 128;
+
+/// Loudness targets of the finished episode (LUFS)
+const STEREO_TARGET_LUFS: f64 = -16.0;
+const MONO_TARGET_LUFS: f64 = -19.0;
+
+/// True peak ceiling of the finished episode (dBTP)
+pub const CEILING_DBTP: f64 = -1.0;
+
+/// Level below which the signal is treated as room tone (dBFS)
+const GATE_THRESHOLD_DB: f32 = -50.0;
+/// How far the gate turns room tone down; not a full mute, which pumps (dB)
+const GATE_RANGE_DB: f32 = -12.0;
+const GATE_HOLD_MS: f32 = 150.0;
+const GATE_ATTACK_MS: f32 = 2.0;
+const GATE_RELEASE_MS: f32 = 150.0;
+/// Release of the gate's level detector, quick so it closes soon after a
+/// phrase ends
+const GATE_DETECTOR_RELEASE_MS: f32 = 20.0;
+
+/// Crossover below which the de-esser leaves the signal alone (Hz)
+const DE_ESS_CROSSOVER_HZ: f32 = 5000.0;
+/// Level of the sibilance band relative to the whole signal above which it
+/// is ducked (dB)
+const DE_ESS_THRESHOLD_DB: f32 = -9.0;
+const DE_ESS_RATIO: f32 = 4.0;
+const DE_ESS_MAX_REDUCTION_DB: f32 = 10.0;
+
+/// RMS level the leveler brings speech to (dBFS)
+const LEVELER_TARGET_DB: f32 = -20.0;
+/// Window of the leveler's RMS detector
+const LEVELER_WINDOW_MS: f32 = 400.0;
+/// Time constant of the leveler's gain changes
+const LEVELER_SPEED_MS: f32 = 2000.0;
+/// Most the leveler boosts or cuts (dB)
+const LEVELER_MAX_GAIN_DB: f32 = 12.0;
+
+/// Syllable-level compressor after the leveler
+const COMPRESSOR: BandCompression = BandCompression {
+    band: "speech",
+    threshold_db: -24.0,
+    ratio: 3.0,
+    attack_ms: 5.0,
+    release_ms: 80.0,
+};
+
+/// Release of the final limiter; short, since speech has no sustained tails
+const LIMITER_RELEASE_MS: f32 = 50.0;
+
+/// Loudness target for a recording with `channels` channels
+pub fn default_target_lufs(channels: usize) -> f64 {
+    if channels == 1 {
+        MONO_TARGET_LUFS
+    } else {
+        STEREO_TARGET_LUFS
+    }
+}
+
+/// Per-job settings of the chain
+#[derive(Debug, Clone)]
+pub struct PodcastOptions {
+    pub target_lufs: f64,
+    pub high_pass_hz: f32,
+    pub gate: bool,
+    pub de_ess: bool,
+}
+
+/// What the chain did
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PodcastResult {
+    pub target_lufs: f64,
+    pub final_lufs: f64,
+    pub final_true_peak: f64,
+    pub loudness_iterations: usize,
+    /// Share of the recording the gate turned down (0-1)
+    pub gated_fraction: Option<f64>,
+    /// Largest cut the de-esser made (dB)
+    pub de_ess_max_reduction_db: Option<f64>,
+    /// Smallest and largest gain the leveler applied (dB)
+    pub leveler_min_gain_db: f64,
+    pub leveler_max_gain_db: f64,
+}
+
+/// Run the dialogue chain over `buffer`
+#[tracing::instrument(name = "dsp.podcast", skip_all)]
+pub fn process(buffer: &mut AudioBuffer, options: &PodcastOptions) -> Result<PodcastResult> {
+    anyhow::ensure!(
+        options.target_lufs.is_finite() && options.target_lufs < 0.0,
+        "targetLufs must be below 0 LUFS"
+    );
+    let nyquist = buffer.sample_rate as f32 / 2.0;
+    anyhow::ensure!(
+        options.high_pass_hz > 0.0 && options.high_pass_hz < nyquist.min(500.0),
+        "highPassHz must be between 0 and 500"
+    );
+
+    high_pass(buffer, options.high_pass_hz);
+    let gated_fraction = options.gate.then(|| gate(buffer));
+    let de_ess_max_reduction_db = options.de_ess.then(|| de_ess(buffer) as f64);
+    let (leveler_min_gain_db, leveler_max_gain_db) = level(buffer);
+
+    let sample_rate = buffer.sample_rate as f32;
+    for channel in &mut buffer.samples {
+        Compressor::new(&COMPRESSOR, sample_rate).process(channel);
+    }
+
+    let limiter = mastering::apply_limiter(
+        buffer,
+        options.target_lufs,
+        LimiterMode::BrickWall,
+        LIMITER_RELEASE_MS,
+        Precision::default(),
+        CEILING_DBTP,
+    )?;
+
+    Ok(PodcastResult {
+        target_lufs: options.target_lufs,
+        final_lufs: limiter.final_lufs,
+        final_true_peak: limiter.final_true_peak,
+        loudness_iterations: limiter.iterations,
+        gated_fraction,
+        de_ess_max_reduction_db,
+        leveler_min_gain_db: leveler_min_gain_db as f64,
+        leveler_max_gain_db: leveler_max_gain_db as f64,
+    })
+}
+
+/// Average `buffer` down to one channel
+pub fn downmix_mono(buffer: &mut AudioBuffer) {
+    if buffer.channels <= 1 {
+        return;
+    }
+    let scale = 1.0 / buffer.channels as f32;
+    let mono = (0..buffer.frame_count())
+        .map(|i| buffer.samples.iter().map(|ch| ch[i]).sum::<f32>() * scale)
+        .collect();
+    buffer.samples = vec![mono];
+    buffer.channels = 1;
+}
+
+/// Two cascaded Butterworth sections, 24 dB/octave
+fn high_pass(buffer: &mut AudioBuffer, corner_hz: f32) {
+    let coefs = mastering::highpass_butterworth_coefs(buffer.sample_rate as f32, corner_hz);
+    for channel in &mut buffer.samples {
+        for _ in 0..2 {
+            Biquad::new(coefs, Precision::default()).process(channel);
+        }
+    }
+}
+
+/// One-pole smoothing coefficient for a time constant
+fn coef(ms: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (ms * sample_rate / 1000.0)).exp()
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+/// Largest absolute sample of frame `i` across channels
+fn frame_peak(buffer: &AudioBuffer, i: usize) -> f32 {
+    buffer
+        .samples
+        .iter()
+        .fold(0.0f32, |peak, ch| peak.max(ch[i].abs()))
+}
+
+/// Downward expander with hold; returns the share of frames turned down
+fn gate(buffer: &mut AudioBuffer) -> f64 {
+    let sample_rate = buffer.sample_rate as f32;
+    let threshold = db_to_linear(GATE_THRESHOLD_DB);
+    let floor = db_to_linear(GATE_RANGE_DB);
+    let hold = (GATE_HOLD_MS * sample_rate / 1000.0) as usize;
+    let (attack, release, detector_release) = (
+        coef(GATE_ATTACK_MS, sample_rate),
+        coef(GATE_RELEASE_MS, sample_rate),
+        coef(GATE_DETECTOR_RELEASE_MS, sample_rate),
+    );
+
+    let mut envelope = 0.0f32;
+    let mut held = 0;
+    let mut gain = 1.0f32;
+    let mut closed = 0usize;
+    let frames = buffer.frame_count();
+    for i in 0..frames {
+        let peak = frame_peak(buffer, i);
+        let smoothing = if peak > envelope {
+            attack
+        } else {
+            detector_release
+        };
+        envelope = smoothing * envelope + (1.0 - smoothing) * peak;
+
+        if envelope >= threshold {
+            held = hold;
+        } else {
+            held = held.saturating_sub(1);
+        }
+        let (target, smoothing) = if held > 0 {
+            (1.0, attack)
+        } else {
+            closed += 1;
+            (floor, release)
+        };
+        gain = smoothing * gain + (1.0 - smoothing) * target;
+        for channel in &mut buffer.samples {
+            channel[i] *= gain;
+        }
+    }
+    if frames == 0 {
+        0.0
+    } else {
+        closed as f64 / frames as f64
+    }
+}
+
+/// Split-band de-esser; returns the largest cut it made (dB)
+fn de_ess(buffer: &mut AudioBuffer) -> f32 {
+    let sample_rate = buffer.sample_rate as f32;
+    if DE_ESS_CROSSOVER_HZ >= sample_rate / 2.0 {
+        return 0.0;
+    }
+    let coefs = mastering::highpass_butterworth_coefs(sample_rate, DE_ESS_CROSSOVER_HZ);
+    let highs: Vec<Vec<f32>> = buffer
+        .samples
+        .iter()
+        .map(|channel| {
+            let mut high = channel.clone();
+            Biquad::new(coefs, Precision::default()).process(&mut high);
+            high
+        })
+        .collect();
+
+    let (attack, release) = (coef(1.0, sample_rate), coef(60.0, sample_rate));
+    let follow = |envelope: f32, level: f32| {
+        let smoothing = if level > envelope { attack } else { release };
+        smoothing * envelope + (1.0 - smoothing) * level
+    };
+    let (mut high_env, mut full_env) = (0.0f32, 0.0f32);
+    let mut max_reduction = 0.0f32;
+    for i in 0..buffer.frame_count() {
+        high_env = follow(
+            high_env,
+            highs.iter().fold(0.0f32, |peak, ch| peak.max(ch[i].abs())),
+        );
+        full_env = follow(full_env, frame_peak(buffer, i));
+        if high_env <= 0.0 || full_env <= 0.0 {
+            continue;
+        }
+
+        let over = 20.0 * (high_env / full_env).log10() - DE_ESS_THRESHOLD_DB;
+        if over <= 0.0 {
+            continue;
+        }
+        let reduction = (over * (1.0 - 1.0 / DE_ESS_RATIO)).min(DE_ESS_MAX_REDUCTION_DB);
+        max_reduction = max_reduction.max(reduction);
+        // Only the band above the crossover is turned down
+        let cut = db_to_linear(-reduction) - 1.0;
+        for (channel, high) in buffer.samples.iter_mut().zip(&highs) {
+            channel[i] += high[i] * cut;
+        }
+    }
+    max_reduction
+}
+
+/// Slow automatic gain towards `LEVELER_TARGET_DB`; returns the smallest and
+/// largest gain applied (dB)
+///
+/// The gain only moves while someone is speaking, so pauses neither pull it
+/// up into the room tone nor reset it between phrases.
+fn level(buffer: &mut AudioBuffer) -> (f32, f32) {
+    let sample_rate = buffer.sample_rate as f32;
+    let window = coef(LEVELER_WINDOW_MS, sample_rate);
+    let speed = coef(LEVELER_SPEED_MS, sample_rate);
+    let speech = GATE_THRESHOLD_DB + 10.0;
+    let channels = buffer.channels.max(1) as f32;
+
+    let mut power = 0.0f32;
+    let mut gain_db = 0.0f32;
+    let (mut min_gain, mut max_gain) = (f32::INFINITY, f32::NEG_INFINITY);
+    for i in 0..buffer.frame_count() {
+        let frame_power = buffer.samples.iter().map(|ch| ch[i] * ch[i]).sum::<f32>() / channels;
+        power = window * power + (1.0 - window) * frame_power;
+        let level_db = 10.0 * power.max(1e-12).log10();
+        if level_db > speech {
+            let wanted =
+                (LEVELER_TARGET_DB - level_db).clamp(-LEVELER_MAX_GAIN_DB, LEVELER_MAX_GAIN_DB);
+            gain_db = speed * gain_db + (1.0 - speed) * wanted;
+        }
+        min_gain = min_gain.min(gain_db);
+        max_gain = max_gain.max(gain_db);
+        let gain = db_to_linear(gain_db);
+        for channel in &mut buffer.samples {
+            channel[i] *= gain;
+        }
+    }
+    if min_gain > max_gain {
+        (0.0, 0.0)
+    } else {
+        (min_gain, max_gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_levels_speakers_and_hits_target() {
+        // A loud and a quiet "speaker" (modulated tones), with a pause
+        // of faint hiss between them
+        let rate = 48000;
+        let mut x: u32 = 3;
+        let tone = |i: usize, amplitude: f32| {
+            let t = i as f32 / rate as f32;
+            let syllables = 0.6 + 0.4 * (2.0 * std::f32::consts::PI * 4.0 * t).sin();
+            amplitude * syllables * (2.0 * std::f32::consts::PI * 220.0 * t).sin()
+        };
+        let samples: Vec<f32> = (0..rate * 12)
+            .map(|i| match i / rate {
+                0..=4 => tone(i, 0.5),
+                5..=6 => {
+                    x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+                    ((x >> 8) as f32 / 16777216.0 - 0.5) * 0.002
+                }
+                _ => tone(i, 0.05),
+            })
+            .collect();
+        let mut buffer = AudioBuffer::new(1, rate as u32);
+        buffer.samples[0] = samples;
+
+        let options = PodcastOptions {
+            target_lufs: default_target_lufs(1),
+            high_pass_hz: DEFAULT_HIGH_PASS_HZ,
+            gate: true,
+            de_ess: true,
+        };
+        let result = process(&mut buffer, &options).unwrap();
+        assert!((result.final_lufs - MONO_TARGET_LUFS).abs() <= 1.0);
+        assert!(result.final_true_peak <= CEILING_DBTP);
+        assert!(result.gated_fraction.unwrap() > 0.1);
+
+        // The two speakers started 20 dB apart and end up within a few dB
+        let rms = |range: std::ops::Range<usize>| {
+            let s = &buffer.samples[0][range];
+            10.0 * (s.iter().map(|v| v * v).sum::<f32>() / s.len() as f32).log10()
+        };
+        let difference = rms(rate * 3..rate * 5) - rms(rate * 10..rate * 12);
+        assert!(difference.abs() < 4.0, "{}", difference);
+    }
+}
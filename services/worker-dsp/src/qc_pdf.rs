@@ -0,0 +1,583 @@
+//! Human-readable QC report rendered as a single-page PDF
+//!
+//! The PDF is written directly (base-14 Helvetica fonts, one uncompressed RGB
+//! image) so the worker needs no layout engine or font files.
+
+use anyhow::Result;
+use realfft::RealFftPlanner;
+use serde::Serialize;
+use std::fmt::Write as _;
+
+use crate::compliance::{self, PlatformSpec};
+use crate::config;
+use crate::spectrogram::color;
+use crate::types::{
+    AnalysisResult, AudioBuffer, ColorMap, LoudnessTarget, MasterProfile, Precision,
+};
+use budi_dsp_core::mastering::{MasteringOptions, MasteringResult};
+
+/// A4 in points
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 40.0;
+
+/// Brand colours (RGB 0-1)
+const BRAND: (f64, f64, f64) = (0.118, 0.106, 0.294);
+const ACCENT: (f64, f64, f64) = (0.486, 0.361, 0.984);
+const TEXT: (f64, f64, f64) = (0.12, 0.12, 0.14);
+const MUTED: (f64, f64, f64) = (0.45, 0.45, 0.5);
+const PASS: (f64, f64, f64) = (0.086, 0.639, 0.290);
+const FAIL: (f64, f64, f64) = (0.863, 0.149, 0.149);
+
+/// Spectrogram thumbnail resolution (pixels)
+const SPECTROGRAM_COLUMNS: usize = 256;
+const SPECTROGRAM_ROWS: usize = 96;
+const SPECTROGRAM_FFT_SIZE: usize = 4096;
+/// Level mapped to black in the thumbnail (dBFS)
+const SPECTROGRAM_FLOOR_DB: f32 = -110.0;
+
+/// Everything shown on the QC report
+pub struct QcPdf<'a> {
+    pub track_id: &'a str,
+    pub profile: MasterProfile,
+    pub loudness_target: LoudnessTarget,
+    pub options: &'a MasteringOptions,
+    pub result: &'a MasteringResult,
+    /// Measurements of the final master
+    pub analysis: &'a AnalysisResult,
+}
+
+/// Render the QC report for a mastered buffer
+#[tracing::instrument(name = "dsp.qc_pdf", skip_all)]
+pub fn render_qc_pdf(report: &QcPdf, buffer: &AudioBuffer) -> Result<Vec<u8>> {
+    let spectrogram = spectrogram(buffer);
+    let mut page = Canvas::default();
+    let result = report.result;
+    let analysis = report.analysis;
+
+    // Header band
+    page.fill_rect(0.0, PAGE_HEIGHT - 80.0, PAGE_WIDTH, 80.0, BRAND);
+    page.fill_rect(0.0, PAGE_HEIGHT - 84.0, PAGE_WIDTH, 4.0, ACCENT);
+    page.text(
+        MARGIN,
+        PAGE_HEIGHT - 48.0,
+        26.0,
+        true,
+        (1.0, 1.0, 1.0),
+        "budi",
+    );
+    page.text(
+        MARGIN + 80.0,
+        PAGE_HEIGHT - 46.0,
+        13.0,
+        false,
+        (0.85, 0.85, 0.95),
+        "Mastering QC Report",
+    );
+
+    let mut y = PAGE_HEIGHT - 120.0;
+    page.text(MARGIN, y, 15.0, true, TEXT, report.track_id);
+    let (verdict, colour) = if result.passes_qc {
+        ("QC PASS", PASS)
+    } else {
+        ("QC FAIL", FAIL)
+    };
+    page.fill_rect(PAGE_WIDTH - MARGIN - 70.0, y - 6.0, 70.0, 22.0, colour);
+    page.text(
+        PAGE_WIDTH - MARGIN - 58.0,
+        y + 1.0,
+        11.0,
+        true,
+        (1.0, 1.0, 1.0),
+        verdict,
+    );
+    y -= 18.0;
+    page.text(
+        MARGIN,
+        y,
+        9.0,
+        false,
+        MUTED,
+        &format!(
+            "Profile {}  |  Target {}  |  {} Hz, {} ch, {}",
+            report.profile,
+            report.loudness_target,
+            analysis.sample_rate,
+            analysis.channels,
+            format_duration(analysis.duration_secs)
+        ),
+    );
+
+    // Loudness and peaks
+    y -= 36.0;
+    y = page.section(y, "Loudness & Peaks");
+    let measurements = [
+        (
+            "Integrated loudness",
+            format!("{:.1} LUFS", result.final_lufs),
+        ),
+        ("Target loudness", format!("{:.1} LUFS", result.target_lufs)),
+        (
+            "Loudness range",
+            format!("{:.1} LU", analysis.loudness_range),
+        ),
+        (
+            "Short-term max",
+            format!("{:.1} LUFS", analysis.short_term_max),
+        ),
+        (
+            "Momentary max",
+            format!("{:.1} LUFS", analysis.momentary_max),
+        ),
+        ("True peak", format!("{:.2} dBTP", result.final_true_peak)),
+        ("Sample peak", format!("{:.2} dBFS", analysis.sample_peak)),
+    ];
+    for (i, (label, value)) in measurements.iter().enumerate() {
+        // Two columns
+        let x = MARGIN + if i % 2 == 0 { 0.0 } else { 260.0 };
+        if i % 2 == 0 && i > 0 {
+            y -= 16.0;
+        }
+        page.text(x, y, 10.0, false, MUTED, label);
+        page.text(x + 130.0, y, 10.0, true, TEXT, value);
+    }
+
+    // Compliance matrix
+    y -= 36.0;
+    y = page.section(y, "Delivery Compliance");
+    let columns = [0.0, 130.0, 230.0, 330.0, 430.0];
+    for (x, heading) in columns.iter().zip([
+        "Platform",
+        "Target",
+        "Playback gain",
+        "Peak after gain",
+        "Status",
+    ]) {
+        page.text(MARGIN + x, y, 9.0, true, MUTED, heading);
+    }
+    y -= 4.0;
+    page.line(MARGIN, y, PAGE_WIDTH - MARGIN, y, 0.8);
+
+    let mut rows: Vec<(String, String, f64, f64, bool)> = PlatformSpec::defaults()
+        .iter()
+        .map(|spec| {
+            let check = compliance::evaluate(spec, result.final_lufs, result.final_true_peak);
+            // Below display precision; avoids printing "-0.0 dB"
+            let gain = check.playback_gain_db;
+            let gain = if gain > -0.05 { 0.0 } else { gain };
+            (
+                spec.name.clone(),
+                format!("{:.0} LUFS", spec.target_lufs),
+                gain,
+                check.true_peak_after_gain,
+                check.true_peak_passes,
+            )
+        })
+        .collect();
+    let true_peak_max = config::get().qc.true_peak_max;
+    rows.push((
+        "Budi QC gate".to_string(),
+        format!("<= {:.1} dBTP", true_peak_max),
+        0.0,
+        result.final_true_peak,
+        result.final_true_peak <= true_peak_max,
+    ));
+    for (name, target, gain, peak, ok) in rows {
+        y -= 15.0;
+        page.text(MARGIN + columns[0], y, 10.0, false, TEXT, &name);
+        page.text(MARGIN + columns[1], y, 10.0, false, TEXT, &target);
+        page.text(
+            MARGIN + columns[2],
+            y,
+            10.0,
+            false,
+            TEXT,
+            &format!("{:+.1} dB", gain),
+        );
+        page.text(
+            MARGIN + columns[3],
+            y,
+            10.0,
+            false,
+            TEXT,
+            &format!("{:.2} dBTP", peak),
+        );
+        let (status, colour) = if ok { ("OK", PASS) } else { ("Over", FAIL) };
+        page.text(MARGIN + columns[4], y, 10.0, true, colour, status);
+    }
+
+    // Applied chain
+    y -= 36.0;
+    y = page.section(y, "Processing Chain");
+    for (stage, detail) in chain_rows(report) {
+        page.text(MARGIN, y, 10.0, false, MUTED, stage);
+        page.text(MARGIN + 130.0, y, 10.0, false, TEXT, &detail);
+        y -= 15.0;
+    }
+
+    // Spectrogram
+    y -= 21.0;
+    y = page.section(y, "Spectrogram");
+    let image_height = 150.0;
+    let image_width = PAGE_WIDTH - 2.0 * MARGIN;
+    page.image(MARGIN, y - image_height + 8.0, image_width, image_height);
+    page.text(
+        MARGIN,
+        y - image_height - 4.0,
+        8.0,
+        false,
+        MUTED,
+        &format!(
+            "20 Hz - {:.0} kHz (log), 0 - {}, {:.0} to 0 dBFS",
+            buffer.sample_rate as f64 / 2000.0,
+            format_duration(analysis.duration_secs),
+            SPECTROGRAM_FLOOR_DB
+        ),
+    );
+
+    page.text(
+        MARGIN,
+        24.0,
+        8.0,
+        false,
+        MUTED,
+        "Generated by Budi. Loudness per ITU-R BS.1770-4 / EBU R128; true peak with 4x oversampling.",
+    );
+
+    Ok(write_pdf(&page.ops, &spectrogram))
+}
+
+/// Stage/detail rows describing what the mastering chain did
+fn chain_rows(report: &QcPdf) -> Vec<(&'static str, String)> {
+    let options = report.options;
+    let result = report.result;
+
+    let mut eq = format!("{} profile, {}", report.profile, label(&options.eq_mode));
+    if let Some(reference) = &result.reference {
+        let _ = write!(
+            eq,
+            ", reference match {:+.1}/{:+.1}/{:+.1} dB",
+            reference.low_gain_db, reference.mid_gain_db, reference.high_gain_db
+        );
+    }
+    let mut rows = vec![("EQ", eq)];
+    if options.precision == Precision::F64 {
+        rows.push(("Precision", "64-bit float filters".to_string()));
+    }
+
+    rows.push((
+        "Compression",
+        match &result.dynamics {
+            Some(d) => format!(
+                "{:.0}% intensity to keep LRA >= {:.1} LU ({:.1} LU, {:.1} LU given up)",
+                d.compression_intensity * 100.0,
+                d.min_loudness_range,
+                d.final_loudness_range,
+                d.loudness_sacrificed
+            ),
+            None => "Profile default".to_string(),
+        },
+    ));
+
+    let mut limiter = label(&result.limiter_mode);
+    if let Some(bands) = &result.band_reduction {
+        for band in bands {
+            let _ = write!(
+                limiter,
+                ", {} {:.1} dB max",
+                band.band, band.max_reduction_db
+            );
+        }
+    }
+    rows.push(("Limiter", limiter));
+    rows.push((
+        "Loudness",
+        format!(
+            "{:.1} LUFS target, {} pass(es)",
+            result.target_lufs, result.loudness_iterations
+        ),
+    ));
+    if options.mix < 1.0 {
+        rows.push(("Parallel mix", format!("{:.0}% wet", options.mix * 100.0)));
+    }
+    if options.output_trim_db != 0.0 {
+        rows.push(("Output trim", format!("{:+.1} dB", options.output_trim_db)));
+    }
+    rows.push(("16-bit dither", label(&options.dither)));
+    rows
+}
+
+/// The serialized (kebab-case) name of a setting
+fn label<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+fn format_duration(secs: f64) -> String {
+    let total = secs.round() as u64;
+    format!("{}:{:02}", total / 60, total % 60)
+}
+
+/// PDF content stream operators for one page
+#[derive(Default)]
+struct Canvas {
+    ops: String,
+}
+
+impl Canvas {
+    fn fill_rect(&mut self, x: f64, y: f64, w: f64, h: f64, (r, g, b): (f64, f64, f64)) {
+        let _ = writeln!(
+            self.ops,
+            "{:.3} {:.3} {:.3} rg {:.2} {:.2} {:.2} {:.2} re f",
+            r, g, b, x, y, w, h
+        );
+    }
+
+    fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, gray: f64) {
+        let _ = writeln!(
+            self.ops,
+            "{:.3} G 0.5 w {:.2} {:.2} m {:.2} {:.2} l S",
+            gray, x1, y1, x2, y2
+        );
+    }
+
+    fn text(
+        &mut self,
+        x: f64,
+        y: f64,
+        size: f64,
+        bold: bool,
+        (r, g, b): (f64, f64, f64),
+        text: &str,
+    ) {
+        let font = if bold { "F2" } else { "F1" };
+        let _ = writeln!(
+            self.ops,
+            "BT {:.3} {:.3} {:.3} rg /{} {:.1} Tf {:.2} {:.2} Td ({}) Tj ET",
+            r,
+            g,
+            b,
+            font,
+            size,
+            x,
+            y,
+            escape_text(text)
+        );
+    }
+
+    fn image(&mut self, x: f64, y: f64, w: f64, h: f64) {
+        let _ = writeln!(
+            self.ops,
+            "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im1 Do Q",
+            w, h, x, y
+        );
+    }
+
+    /// Section heading with a rule underneath; returns the y of the first row
+    fn section(&mut self, y: f64, title: &str) -> f64 {
+        self.text(MARGIN, y, 12.0, true, BRAND, title);
+        self.line(MARGIN, y - 5.0, PAGE_WIDTH - MARGIN, y - 5.0, 0.8);
+        y - 22.0
+    }
+}
+
+/// Escape a string for a PDF literal in WinAnsiEncoding
+///
+/// Latin-1 characters are written as octal escapes; anything the base-14
+/// fonts can't show becomes '?'.
+fn escape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+/// Assemble the single-page document, returning the file bytes
+fn write_pdf(content: &str, image: &[u8]) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::new();
+
+    let mut object = |pdf: &mut Vec<u8>, body: &[u8]| {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", offsets.len()).as_bytes());
+        pdf.extend_from_slice(body);
+        pdf.extend_from_slice(b"\nendobj\n");
+    };
+    let stream = |dict: &str, data: &[u8]| {
+        let mut body = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\nendstream");
+        body
+    };
+
+    object(&mut pdf, b"<< /Type /Catalog /Pages 2 0 R >>");
+    object(&mut pdf, b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>");
+    object(
+        &mut pdf,
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 4 0 R /F2 5 0 R >> /XObject << /Im1 6 0 R >> >> \
+             /Contents 7 0 R >>",
+            PAGE_WIDTH, PAGE_HEIGHT
+        )
+        .as_bytes(),
+    );
+    object(
+        &mut pdf,
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+    );
+    object(
+        &mut pdf,
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>",
+    );
+    object(
+        &mut pdf,
+        &stream(
+            &format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} \
+                 /ColorSpace /DeviceRGB /BitsPerComponent 8",
+                SPECTROGRAM_COLUMNS, SPECTROGRAM_ROWS
+            ),
+            image,
+        ),
+    );
+    object(&mut pdf, &stream("", content.as_bytes()));
+
+    let xref = pdf.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+    for offset in &offsets {
+        let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        trailer,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        offsets.len() + 1,
+        xref
+    );
+    pdf.extend_from_slice(trailer.as_bytes());
+    pdf
+}
+
+/// Log-frequency spectrogram thumbnail as RGB rows, highest frequency first
+fn spectrogram(buffer: &AudioBuffer) -> Vec<u8> {
+    let mono: Vec<f32> = (0..buffer.frame_count())
+        .map(|i| buffer.samples.iter().map(|ch| ch[i]).sum::<f32>() / buffer.channels as f32)
+        .collect();
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(SPECTROGRAM_FFT_SIZE);
+    let window: Vec<f32> = (0..SPECTROGRAM_FFT_SIZE)
+        .map(|i| {
+            0.5 * (1.0
+                - (2.0 * std::f32::consts::PI * i as f32 / SPECTROGRAM_FFT_SIZE as f32).cos())
+        })
+        .collect();
+    // Full-scale sine -> 0 dBFS
+    let scale = 2.0 / window.iter().sum::<f32>();
+
+    let nyquist = buffer.sample_rate as f32 / 2.0;
+    let bin_hz = buffer.sample_rate as f32 / SPECTROGRAM_FFT_SIZE as f32;
+    let bin_at = |row: usize| {
+        let freq = 20.0 * (nyquist / 20.0).powf(row as f32 / SPECTROGRAM_ROWS as f32);
+        ((freq / bin_hz) as usize).min(SPECTROGRAM_FFT_SIZE / 2)
+    };
+
+    let hop = mono.len().saturating_sub(SPECTROGRAM_FFT_SIZE) as f64 / SPECTROGRAM_COLUMNS as f64;
+    let mut input = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+    let mut levels = vec![SPECTROGRAM_FLOOR_DB; SPECTROGRAM_COLUMNS * SPECTROGRAM_ROWS];
+
+    for col in 0..SPECTROGRAM_COLUMNS {
+        let start = (col as f64 * hop) as usize;
+        for (i, sample) in input.iter_mut().enumerate() {
+            *sample = mono.get(start + i).copied().unwrap_or(0.0) * window[i];
+        }
+        if fft.process(&mut input, &mut spectrum).is_err() {
+            continue;
+        }
+        for row in 0..SPECTROGRAM_ROWS {
+            let lo = bin_at(row);
+            let hi = bin_at(row + 1).max(lo + 1).min(spectrum.len());
+            let peak = spectrum[lo.min(hi - 1)..hi]
+                .iter()
+                .map(|c| c.norm() * scale)
+                .fold(0.0f32, f32::max);
+            let db = 20.0 * peak.max(1e-9).log10();
+            levels[(SPECTROGRAM_ROWS - 1 - row) * SPECTROGRAM_COLUMNS + col] = db;
+        }
+    }
+
+    levels
+        .into_iter()
+        .flat_map(|db| {
+            color(
+                ColorMap::Heat,
+                (db - SPECTROGRAM_FLOOR_DB) / -SPECTROGRAM_FLOOR_DB,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use budi_dsp_core::analysis;
+
+    #[test]
+    fn test_qc_pdf_structure() {
+        let mut buffer = AudioBuffer::new(2, 44100);
+        for ch in 0..2 {
+            buffer.samples[ch] = (0..44100 * 3)
+                .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin() * 0.3)
+                .collect();
+        }
+        let options = MasteringOptions::default();
+        let result = budi_dsp_core::mastering::apply_mastering(
+            &mut buffer,
+            MasterProfile::Balanced,
+            LoudnessTarget::Low,
+            &options,
+        )
+        .unwrap();
+        let analysis = analysis::analyze_audio(&buffer, 24).unwrap();
+
+        let pdf = render_qc_pdf(
+            &QcPdf {
+                track_id: "track (1)",
+                profile: MasterProfile::Balanced,
+                loudness_target: LoudnessTarget::Low,
+                options: &options,
+                result: &result,
+                analysis: &analysis,
+            },
+            &buffer,
+        )
+        .unwrap();
+
+        assert!(pdf.starts_with(b"%PDF-1.4"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        // startxref must point at the cross-reference table
+        let text = String::from_utf8_lossy(&pdf);
+        let startxref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|s| s.lines().next())
+            .and_then(|s| s.parse().ok())
+            .unwrap();
+        assert!(pdf[startxref..].starts_with(b"xref"));
+        assert!(text.contains("(track \\(1\\)) Tj"));
+    }
+}
@@ -0,0 +1,117 @@
+//! ReplayGain 2.0 track and album gain
+//!
+//! ReplayGain 2.0 measures loudness with BS.1770 and gives each file the
+//! gain that brings it to -18 LUFS, with its peak so players can keep the
+//! gain from clipping. Album gain is measured over every track's gating
+//! blocks together, as if the album were one file, so playing it with album
+//! gain keeps the level differences between its tracks.
+
+use anyhow::Result;
+use ebur128::{EbuR128, Mode};
+
+use crate::types::{AudioBuffer, ReplayGain};
+
+/// Loudness ReplayGain 2.0 brings every track to (LUFS)
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+/// Most tracks one job takes
+pub const MAX_TRACKS: usize = 100;
+
+/// Loudness assumed for silence, capping its gain (LUFS)
+const FLOOR_LUFS: f64 = -70.0;
+
+/// Gains and peaks of a set of tracks, measured one at a time
+#[derive(Default)]
+pub struct AlbumMeter {
+    tracks: Vec<(EbuR128, f64)>,
+}
+
+impl AlbumMeter {
+    /// Measure the next track
+    pub fn add(&mut self, buffer: &AudioBuffer) -> Result<()> {
+        // The histogram keeps the memory of each meter fixed however long
+        // the track
+        let mut meter = EbuR128::new(
+            buffer.channels as u32,
+            buffer.sample_rate,
+            Mode::I | Mode::HISTOGRAM | Mode::SAMPLE_PEAK,
+        )?;
+        let channels: Vec<&[f32]> = buffer.samples.iter().map(Vec::as_slice).collect();
+        meter.add_frames_planar_f32(&channels)?;
+        let mut peak = 0.0f64;
+        for ch in 0..buffer.channels as u32 {
+            peak = peak.max(meter.sample_peak(ch)?);
+        }
+        self.tracks.push((meter, peak));
+        Ok(())
+    }
+
+    /// Each track's gain and peak, with the album's filled in
+    pub fn finish(self) -> Result<Vec<ReplayGain>> {
+        let album_gain = gain(EbuR128::loudness_global_multiple(
+            self.tracks.iter().map(|(meter, _)| meter),
+        )?);
+        let album_peak = self.tracks.iter().fold(0.0f64, |p, (_, peak)| p.max(*peak));
+        self.tracks
+            .iter()
+            .map(|(meter, peak)| {
+                Ok(ReplayGain {
+                    track_gain_db: gain(meter.loudness_global()?),
+                    track_peak: *peak,
+                    album_gain_db: Some(album_gain),
+                    album_peak: Some(album_peak),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Gain bringing `lufs` to the reference, rounded to 0.01 dB as tagged
+fn gain(lufs: f64) -> f64 {
+    let lufs = if lufs.is_finite() {
+        lufs.max(FLOOR_LUFS)
+    } else {
+        FLOOR_LUFS
+    };
+    ((REFERENCE_LUFS - lufs) * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_and_album_gain() {
+        // The same tone at two levels 6 dB apart
+        let rate = 48000;
+        let render = |amplitude: f32| {
+            let mut buffer = AudioBuffer::new(2, rate);
+            for channel in &mut buffer.samples {
+                *channel = (0..rate as usize * 5)
+                    .map(|i| {
+                        amplitude
+                            * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / rate as f32).sin()
+                    })
+                    .collect();
+            }
+            buffer
+        };
+        let mut meter = AlbumMeter::default();
+        meter.add(&render(0.5)).unwrap();
+        meter.add(&render(0.25)).unwrap();
+        let gains = meter.finish().unwrap();
+
+        // A 1 kHz sine at -6 dBFS in both channels measures -6 LUFS
+        assert!(
+            (gains[0].track_gain_db + 12.0).abs() < 0.2,
+            "{:?}",
+            gains[0]
+        );
+        assert!((gains[1].track_gain_db - gains[0].track_gain_db - 6.02).abs() < 0.1);
+        assert!((gains[0].track_peak - 0.5).abs() < 1e-3);
+        // The album lies between its tracks, peaking with the louder
+        let album = gains[0].album_gain_db.unwrap();
+        assert!(album > gains[0].track_gain_db && album < gains[1].track_gain_db);
+        assert_eq!(gains[1].album_peak, Some(gains[0].track_peak));
+    }
+}
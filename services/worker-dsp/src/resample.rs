@@ -0,0 +1,147 @@
+//! Mastering-grade sample rate conversion for deliverables
+
+use anyhow::{bail, Result};
+use rubato::{
+    Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction,
+};
+use serde::Serialize;
+
+use crate::types::AudioBuffer;
+
+/// Sample rates deliverables can be requested at (Hz)
+pub const SUPPORTED_SAMPLE_RATES: [u32; 4] = [44100, 48000, 88200, 96000];
+
+/// Frames fed to the resampler per call
+const CHUNK_SIZE: usize = 4096;
+
+/// Resampler settings, reported in the export manifest
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SrcSettings {
+    pub algorithm: &'static str,
+    pub sinc_length: usize,
+    /// Anti-aliasing cutoff relative to the lower Nyquist frequency
+    pub cutoff: f32,
+    pub oversampling_factor: usize,
+    pub interpolation: &'static str,
+    pub window: &'static str,
+    pub precision: &'static str,
+}
+
+/// Long windowed-sinc filter evaluated in double precision; passband is flat
+/// to ~20 kHz at 44.1 kHz with stopband rejection well below 24-bit noise
+pub const SRC_SETTINGS: SrcSettings = SrcSettings {
+    algorithm: "windowed-sinc",
+    sinc_length: 512,
+    cutoff: 0.95,
+    oversampling_factor: 256,
+    interpolation: "cubic",
+    window: "blackman-harris-2",
+    precision: "f64",
+};
+
+/// Convert a buffer to `target_rate`, keeping it time-aligned with the source
+///
+/// The output has exactly `frames * target_rate / source_rate` frames and
+/// starts within a fraction of a sample of the source.
+#[tracing::instrument(name = "dsp.resample", skip_all)]
+pub fn resample(buffer: &AudioBuffer, target_rate: u32) -> Result<AudioBuffer> {
+    if !SUPPORTED_SAMPLE_RATES.contains(&target_rate) {
+        bail!("Unsupported export sample rate: {} Hz", target_rate);
+    }
+    if buffer.sample_rate == target_rate {
+        return Ok(buffer.clone());
+    }
+
+    let ratio = target_rate as f64 / buffer.sample_rate as f64;
+    Ok(AudioBuffer {
+        samples: convert(buffer, ratio)?,
+        sample_rate: target_rate,
+        channels: buffer.channels,
+    })
+}
+
+/// Play `buffer` `1 / ratio` times as fast at its own sample rate, as a tape
+/// machine varying its speed would: both its length and its pitch change
+///
+/// The output has exactly `frames * ratio` frames.
+pub fn change_speed(buffer: &AudioBuffer, ratio: f64) -> Result<AudioBuffer> {
+    Ok(AudioBuffer {
+        samples: convert(buffer, ratio)?,
+        sample_rate: buffer.sample_rate,
+        channels: buffer.channels,
+    })
+}
+
+/// `buffer`'s channels resampled to `ratio` times as many frames
+fn convert(buffer: &AudioBuffer, ratio: f64) -> Result<Vec<Vec<f32>>> {
+    let params = SincInterpolationParameters {
+        sinc_len: SRC_SETTINGS.sinc_length,
+        f_cutoff: SRC_SETTINGS.cutoff,
+        oversampling_factor: SRC_SETTINGS.oversampling_factor,
+        interpolation: SincInterpolationType::Cubic,
+        window: WindowFunction::BlackmanHarris2,
+    };
+    let mut resampler = SincFixedIn::<f64>::new(ratio, 1.0, params, CHUNK_SIZE, buffer.channels)?;
+
+    let frame_count = buffer.frame_count();
+    let expected_len = (frame_count as f64 * ratio).round() as usize;
+    // The sinc window is centered on the current input position, so the
+    // output starts aligned with the input; the filter tail only needs flushing
+    let mut output: Vec<Vec<f64>> = vec![Vec::with_capacity(expected_len); buffer.channels];
+
+    let mut start = 0;
+    while start < frame_count {
+        let end = (start + CHUNK_SIZE).min(frame_count);
+        let chunk: Vec<Vec<f64>> = buffer
+            .samples
+            .iter()
+            .map(|ch| ch[start..end].iter().map(|&s| s as f64).collect())
+            .collect();
+        let processed = if end - start == CHUNK_SIZE {
+            resampler.process(&chunk, None)?
+        } else {
+            resampler.process_partial(Some(&chunk), None)?
+        };
+        for (out, ch) in output.iter_mut().zip(processed) {
+            out.extend(ch);
+        }
+        start = end;
+    }
+
+    while output.first().map_or(0, Vec::len) < expected_len {
+        let processed = resampler.process_partial::<Vec<f64>>(None, None)?;
+        for (out, ch) in output.iter_mut().zip(processed) {
+            out.extend(ch);
+        }
+    }
+
+    Ok(output
+        .into_iter()
+        .map(|ch| ch[..expected_len].iter().map(|&s| s as f32).collect())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resample_preserves_length_and_alignment() {
+        let rate = 44100;
+        let mut buffer = AudioBuffer::new(1, rate);
+        buffer.samples[0] = (0..rate as usize)
+            .map(|i| (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / rate as f32).sin() * 0.5)
+            .collect();
+
+        let resampled = resample(&buffer, 48000).unwrap();
+        assert_eq!(resampled.frame_count(), 48000);
+
+        // A 1 kHz sine should still be in phase (within a fraction of a sample)
+        for i in (1000..47000).step_by(997) {
+            let t = i as f32 / 48000.0;
+            let expected = (2.0 * std::f32::consts::PI * 1000.0 * t).sin() * 0.5;
+            assert!((resampled.samples[0][i] - expected).abs() < 1e-2);
+        }
+    }
+}
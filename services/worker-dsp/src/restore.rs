@@ -0,0 +1,761 @@
+//! Restoration of vinyl and tape transfers
+//!
+//! Archival transfers carry defects of the carrier rather than of the mix,
+//! and are repaired in this order:
+//!
+//! - Azimuth and polarity: a tape head out of line delays one channel by a
+//!   fraction of a millisecond, combing the highs when the two are summed,
+//!   and a miswired channel arrives inverted. The inter-channel lag is found
+//!   by cross-correlation and the leading channel delayed to match.
+//! - Declick: samples a short autoregressive (AR) model of the surrounding
+//!   audio can't explain, up to `CLICK_MAX_MS` long, rebuilt by least-squares
+//!   AR interpolation
+//! - Decrackle: the same detector at a lower threshold, for the dense bursts
+//!   of a few samples that worn records crackle with
+//! - Hum: a 50 or 60 Hz mains fundamental or its harmonics, when they stand
+//!   out of the spectrum, notched at their measured frequency
+//! - Denoise: hiss and surface noise, subtracted in the spectrum against the
+//!   quietest stretches of the transfer (lead-ins, run-outs, pauses)
+//!
+//! One `strength` (0-1) sets them all. The thresholds are relative to the
+//! local level of what the AR model can't predict, which percussive attacks
+//! raise, and the noise reduction stops at a floor well short of silence, so
+//! even full strength leaves a recording's character intact; modern mixes,
+//! full of transients and with no carrier noise, want the fix modules instead.
+
+use anyhow::Result;
+use realfft::RealFftPlanner;
+use serde::Serialize;
+
+use crate::types::{AudioBuffer, Precision};
+use budi_dsp_core::mastering::Biquad;
+
+/// Strength when a job doesn't give one
+pub const DEFAULT_STRENGTH: f64 = 0.5;
+
+/// AR model order for click detection and interpolation
+const AR_ORDER: usize = 32;
+
+/// Samples each AR model is fitted to and scanned over
+const AR_BLOCK: usize = 4096;
+
+/// Longest click and crackle repaired (ms); anything longer is music
+const CLICK_MAX_MS: f64 = 3.0;
+const CRACKLE_MAX_MS: f64 = 0.5;
+
+/// Samples either side of a detected click that are rebuilt with it
+const CLICK_PAD: usize = 2;
+
+/// Detections this close together (samples) are one click
+const CLICK_MERGE: usize = 4;
+
+/// Largest inter-channel lag an azimuth error is taken for (ms)
+const AZIMUTH_MAX_MS: f64 = 1.0;
+
+/// Audio the inter-channel lag is measured over, from the middle (seconds)
+const AZIMUTH_EXCERPT_SECONDS: f64 = 10.0;
+
+/// Correlation between channels below which they are too different (a true
+/// stereo recording) for their lag to mean anything
+const AZIMUTH_MIN_CORRELATION: f64 = 0.5;
+
+/// Smallest lag worth correcting (samples)
+const AZIMUTH_MIN_LAG: f64 = 0.1;
+
+/// Taps of the fractional delay filter
+const FRACTIONAL_DELAY_TAPS: usize = 32;
+
+/// FFT of the hum search (about 0.7 Hz bins at 44.1 kHz) and the most
+/// segments averaged
+const HUM_FFT: usize = 1 << 16;
+const HUM_SEGMENTS: usize = 8;
+
+/// Mains frequencies hum is looked for at (Hz), harmonics searched for it,
+/// and how far a mains frequency or tape speed may drift (fraction)
+const MAINS_HZ: [f64; 2] = [50.0, 60.0];
+const HUM_SEARCH_HARMONICS: usize = 4;
+const HUM_DRIFT: f64 = 0.02;
+
+/// How far a hum line must stand above its neighbourhood (dB)
+const HUM_PROMINENCE_DB: f64 = 12.0;
+
+/// Width of the neighbourhood hum lines are compared with (Hz)
+const HUM_NEIGHBOURHOOD_HZ: f64 = 10.0;
+
+/// Q of the hum notches
+const HUM_NOTCH_Q: f32 = 20.0;
+
+/// Length of the denoiser's STFT frames (seconds, rounded up to a power of
+/// two) and its overlap
+const DENOISE_FRAME_SECONDS: f64 = 0.04;
+const DENOISE_OVERLAP: usize = 4;
+
+/// Share of frames, the quietest, the noise profile is taken from
+const NOISE_PROFILE_SHARE: f64 = 0.1;
+
+/// Smoothing of the signal spectrum and of the gains across frames, against
+/// the warbling ("musical noise") of isolated bins
+const SPECTRUM_SMOOTHING: f32 = 0.6;
+const GAIN_RELEASE: f32 = 0.8;
+
+/// Settings derived from a strength
+#[derive(Debug, Clone, Copy)]
+struct Tuning {
+    /// Detector thresholds (robust standard deviations)
+    click_threshold: f64,
+    crackle_threshold: f64,
+    hum_harmonics: usize,
+    /// How many times the noise profile is subtracted
+    over_subtraction: f32,
+    /// Most the denoiser attenuates a bin (dB)
+    noise_reduction_db: f64,
+}
+
+impl Tuning {
+    fn new(strength: f64) -> Self {
+        Self {
+            click_threshold: 10.0 - 5.0 * strength,
+            crackle_threshold: 7.0 - 3.0 * strength,
+            hum_harmonics: 1 + (7.0 * strength).round() as usize,
+            over_subtraction: 1.0 + 1.5 * strength as f32,
+            noise_reduction_db: 4.0 + 14.0 * strength,
+        }
+    }
+}
+
+/// What restoration found and did
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreResult {
+    pub strength: f64,
+    /// Lag of the right channel behind the left that was removed (samples;
+    /// negative when the right led)
+    pub azimuth_lag_samples: f64,
+    /// Correlation between the channels at that lag (0 for mono)
+    pub channel_correlation: f64,
+    pub polarity_inverted: bool,
+    pub clicks_repaired: usize,
+    pub crackles_repaired: usize,
+    /// Measured hum fundamental, if hum was found
+    pub hum_hz: Option<f64>,
+    pub hum_harmonics_notched: usize,
+    /// RMS of the quietest stretches before and after (dBFS)
+    pub noise_floor_db: f64,
+    pub noise_floor_after_db: f64,
+    pub max_noise_reduction_db: f64,
+}
+
+/// Restore `buffer` in place at `strength` (0-1)
+#[tracing::instrument(name = "dsp.restore", skip_all)]
+pub fn restore(buffer: &mut AudioBuffer, strength: f64) -> Result<RestoreResult> {
+    anyhow::ensure!(
+        (0.0..=1.0).contains(&strength),
+        "strength must be between 0 and 1"
+    );
+    let tuning = Tuning::new(strength);
+    let sample_rate = buffer.sample_rate as f64;
+
+    let (azimuth_lag_samples, channel_correlation, polarity_inverted) = correct_azimuth(buffer);
+
+    let ms = |ms: f64| ((ms / 1000.0 * sample_rate) as usize).max(1);
+    let mut clicks_repaired = 0;
+    let mut crackles_repaired = 0;
+    for channel in &mut buffer.samples {
+        clicks_repaired += declick(channel, tuning.click_threshold, ms(CLICK_MAX_MS));
+        crackles_repaired += declick(channel, tuning.crackle_threshold, ms(CRACKLE_MAX_MS));
+    }
+
+    let hum_hz = find_hum(buffer)?;
+    let mut hum_harmonics_notched = 0;
+    if let Some(f0) = hum_hz {
+        let nyquist = sample_rate / 2.0;
+        for harmonic in (1..=tuning.hum_harmonics).filter(|&h| h as f64 * f0 < 0.9 * nyquist) {
+            let coefs = notch_coefs(
+                buffer.sample_rate as f32,
+                (harmonic as f64 * f0) as f32,
+                HUM_NOTCH_Q,
+            );
+            for channel in &mut buffer.samples {
+                Biquad::new(coefs, Precision::F64).process(channel);
+            }
+            hum_harmonics_notched += 1;
+        }
+    }
+
+    let (noise_floor_db, noise_floor_after_db) = denoise(buffer, &tuning)?;
+
+    Ok(RestoreResult {
+        strength,
+        azimuth_lag_samples,
+        channel_correlation,
+        polarity_inverted,
+        clicks_repaired,
+        crackles_repaired,
+        hum_hz,
+        hum_harmonics_notched,
+        noise_floor_db,
+        noise_floor_after_db,
+        max_noise_reduction_db: tuning.noise_reduction_db,
+    })
+}
+
+/// Line a stereo pair's channels up and fix an inverted one, returning the
+/// lag removed, the correlation at it and whether the right was inverted
+fn correct_azimuth(buffer: &mut AudioBuffer) -> (f64, f64, bool) {
+    if buffer.channels != 2 {
+        return (0.0, 0.0, false);
+    }
+    let max_lag = ((AZIMUTH_MAX_MS / 1000.0) * buffer.sample_rate as f64).ceil() as usize;
+    let frames = buffer.frame_count();
+    let excerpt = ((AZIMUTH_EXCERPT_SECONDS * buffer.sample_rate as f64) as usize).min(frames);
+    if excerpt <= 4 * max_lag {
+        return (0.0, 0.0, false);
+    }
+    let start = (frames - excerpt) / 2;
+    let (left, right) = (&buffer.samples[0], &buffer.samples[1]);
+
+    // rho[lag] = sum left[n] * right[n + lag], normalized
+    let energy = |x: &[f32]| x.iter().map(|&s| (s as f64).powi(2)).sum::<f64>();
+    let norm =
+        (energy(&left[start..start + excerpt]) * energy(&right[start..start + excerpt])).sqrt();
+    if norm <= 0.0 {
+        return (0.0, 0.0, false);
+    }
+    let lo = start + max_lag;
+    let hi = start + excerpt - max_lag;
+    let rho: Vec<f64> = (0..=2 * max_lag)
+        .map(|i| {
+            let lag = i as isize - max_lag as isize;
+            (lo..hi)
+                .map(|n| left[n] as f64 * right[(n as isize + lag) as usize] as f64)
+                .sum::<f64>()
+                / norm
+        })
+        .collect();
+    let peak = (0..rho.len())
+        .max_by(|&a, &b| rho[a].abs().total_cmp(&rho[b].abs()))
+        .unwrap_or(max_lag);
+    let correlation = rho[peak];
+    if correlation.abs() < AZIMUTH_MIN_CORRELATION {
+        return (0.0, correlation, false);
+    }
+
+    let inverted = correlation < 0.0;
+    if inverted {
+        for sample in &mut buffer.samples[1] {
+            *sample = -*sample;
+        }
+    }
+
+    // Parabolic interpolation around the peak for the fractional part
+    let mut lag = peak as f64 - max_lag as f64;
+    if peak > 0 && peak + 1 < rho.len() {
+        let (a, b, c) = (rho[peak - 1].abs(), rho[peak].abs(), rho[peak + 1].abs());
+        let denominator = a - 2.0 * b + c;
+        if denominator < 0.0 {
+            lag += 0.5 * (a - c) / denominator;
+        }
+    }
+    if lag.abs() >= AZIMUTH_MIN_LAG {
+        // The right lags by `lag`: delay the left to match, or the reverse
+        let leading = if lag > 0.0 { 0 } else { 1 };
+        fractional_delay(&mut buffer.samples[leading], lag.abs());
+    } else {
+        lag = 0.0;
+    }
+    (lag, correlation.abs(), inverted)
+}
+
+/// Delay `samples` by `delay` samples with a windowed-sinc filter
+fn fractional_delay(samples: &mut [f32], delay: f64) {
+    let half = FRACTIONAL_DELAY_TAPS as isize / 2;
+    let taps: Vec<f64> = (-half + 1..=half)
+        .map(|k| {
+            let x = k as f64 - delay.fract();
+            let sinc = if x.abs() < 1e-9 {
+                1.0
+            } else {
+                (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+            };
+            let t = (k + half - 1) as f64 / (FRACTIONAL_DELAY_TAPS - 1) as f64;
+            let blackman = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * t).cos()
+                + 0.08 * (4.0 * std::f64::consts::PI * t).cos();
+            sinc * blackman
+        })
+        .collect();
+    let whole = delay.trunc() as isize;
+    let source = samples.to_vec();
+    for (n, sample) in samples.iter_mut().enumerate() {
+        // y[n] = sum h[k] x[n - whole - k]
+        *sample = (-half + 1..=half)
+            .zip(&taps)
+            .filter_map(|(k, &h)| {
+                let i = n as isize - whole - k;
+                (i >= 0 && (i as usize) < source.len()).then(|| h * source[i as usize] as f64)
+            })
+            .sum::<f64>() as f32;
+    }
+}
+
+/// Find and rebuild clicks in `channel` no longer than `max_len` samples,
+/// returning how many
+fn declick(channel: &mut [f32], threshold: f64, max_len: usize) -> usize {
+    let len = channel.len();
+    let p = AR_ORDER;
+    if len < AR_BLOCK.max(4 * p) {
+        return 0;
+    }
+    let mut repaired = 0;
+    let mut start = p;
+    while start + p < len {
+        let end = (start + AR_BLOCK).min(len - p);
+        let context = start.saturating_sub(AR_BLOCK / 2)..(end + AR_BLOCK / 2).min(len);
+        let a = ar_coefficients(&channel[context], p);
+        // e = A(z) x, d = A(1/z) e: a click's residual folded back onto it
+        let a_prime: Vec<f64> = std::iter::once(1.0).chain(a.iter().map(|c| -c)).collect();
+        let residual: Vec<f64> = (start..end + p)
+            .map(|n| {
+                (0..=p)
+                    .map(|k| a_prime[k] * channel[n - k] as f64)
+                    .sum::<f64>()
+            })
+            .collect();
+        let detector: Vec<f64> = (0..end - start)
+            .map(|i| (0..=p).map(|k| a_prime[k] * residual[i + k]).sum())
+            .collect();
+
+        let mut magnitudes: Vec<f64> = detector.iter().map(|d| d.abs()).collect();
+        let middle = magnitudes.len() / 2;
+        let (_, median, _) = magnitudes.select_nth_unstable_by(middle, f64::total_cmp);
+        let sigma = 1.4826 * *median;
+        if sigma > 0.0 {
+            let mut regions: Vec<(usize, usize)> = Vec::new();
+            for (i, d) in detector.iter().enumerate() {
+                if d.abs() <= threshold * sigma {
+                    continue;
+                }
+                let n = start + i;
+                match regions.last_mut() {
+                    Some(last) if n <= last.1 + CLICK_MERGE => last.1 = n,
+                    _ => regions.push((n, n)),
+                }
+            }
+            for (first, last) in regions {
+                let from = first.saturating_sub(CLICK_PAD);
+                let to = (last + CLICK_PAD + 1).min(len);
+                if to - from <= max_len + 2 * CLICK_PAD
+                    && interpolate(channel, from, to - from, &a_prime)
+                {
+                    repaired += 1;
+                }
+            }
+        }
+        start = end;
+    }
+    repaired
+}
+
+/// AR coefficients `a` of order `order`, predicting x[n] as
+/// `sum a[k] x[n - 1 - k]`, by Levinson-Durbin on the Hann-windowed block
+fn ar_coefficients(x: &[f32], order: usize) -> Vec<f64> {
+    let n = x.len();
+    let windowed: Vec<f64> = x
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| {
+            let w = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / n as f64).cos();
+            s as f64 * w
+        })
+        .collect();
+    let mut r: Vec<f64> = (0..=order)
+        .map(|lag| (lag..n).map(|i| windowed[i] * windowed[i - lag]).sum())
+        .collect();
+    let mut a = vec![0.0; order];
+    if r[0] <= 0.0 {
+        return a;
+    }
+    // A little white noise keeps the model stable on pure tones
+    r[0] *= 1.0 + 1e-6;
+    let mut error = r[0];
+    for i in 0..order {
+        let k = (r[i + 1] - (0..i).map(|j| a[j] * r[i - j]).sum::<f64>()) / error;
+        let previous = a.clone();
+        a[i] = k;
+        for j in 0..i {
+            a[j] = previous[j] - k * previous[i - 1 - j];
+        }
+        error *= 1.0 - k * k;
+        if error <= 0.0 {
+            break;
+        }
+    }
+    a
+}
+
+/// Rebuild `len` samples from `start` as those minimizing the AR model's
+/// residual energy (least-squares AR interpolation); `false` if there isn't
+/// `AR_ORDER` samples of context either side
+fn interpolate(x: &mut [f32], start: usize, len: usize, a_prime: &[f64]) -> bool {
+    let p = a_prime.len() - 1;
+    if start < p || start + len + p > x.len() {
+        return false;
+    }
+    let m = len;
+    // The normal equations' matrix is Toeplitz in the autocorrelation of a'
+    let ra: Vec<f64> = (0..=p)
+        .map(|lag| (0..=p - lag).map(|k| a_prime[k] * a_prime[k + lag]).sum())
+        .collect();
+    let mut matrix = vec![0.0; m * m];
+    for i in 0..m {
+        for j in 0..m {
+            let d = i.abs_diff(j);
+            if d <= p {
+                matrix[i * m + j] = ra[d];
+            }
+        }
+        matrix[i * m + i] += 1e-9 * ra[0];
+    }
+    let gap = start..start + m;
+    let mut rhs = vec![0.0; m];
+    for n in start..start + m + p {
+        let known: f64 = (0..=p)
+            .filter(|&k| !gap.contains(&(n - k)))
+            .map(|k| a_prime[k] * x[n - k] as f64)
+            .sum();
+        for (j, value) in rhs.iter_mut().enumerate() {
+            let position = start + j;
+            if n >= position && n - position <= p {
+                *value -= a_prime[n - position] * known;
+            }
+        }
+    }
+    let Some(solution) = cholesky_solve(&mut matrix, &rhs, m) else {
+        return false;
+    };
+    for (sample, value) in x[gap].iter_mut().zip(solution) {
+        *sample = value as f32;
+    }
+    true
+}
+
+/// Solve `matrix * u = rhs` for a symmetric positive definite `m`x`m`
+/// matrix, factorizing it in place
+fn cholesky_solve(matrix: &mut [f64], rhs: &[f64], m: usize) -> Option<Vec<f64>> {
+    for j in 0..m {
+        let diagonal = matrix[j * m + j] - (0..j).map(|k| matrix[j * m + k].powi(2)).sum::<f64>();
+        if diagonal <= 0.0 {
+            return None;
+        }
+        let diagonal = diagonal.sqrt();
+        matrix[j * m + j] = diagonal;
+        for i in j + 1..m {
+            let sum: f64 = (0..j).map(|k| matrix[i * m + k] * matrix[j * m + k]).sum();
+            matrix[i * m + j] = (matrix[i * m + j] - sum) / diagonal;
+        }
+    }
+    let mut y = vec![0.0; m];
+    for i in 0..m {
+        let sum: f64 = (0..i).map(|k| matrix[i * m + k] * y[k]).sum();
+        y[i] = (rhs[i] - sum) / matrix[i * m + i];
+    }
+    let mut u = vec![0.0; m];
+    for i in (0..m).rev() {
+        let sum: f64 = (i + 1..m).map(|k| matrix[k * m + i] * u[k]).sum();
+        u[i] = (y[i] - sum) / matrix[i * m + i];
+    }
+    Some(u)
+}
+
+/// Fundamental of mains hum, if a 50 or 60 Hz series stands out
+fn find_hum(buffer: &AudioBuffer) -> Result<Option<f64>> {
+    let frames = buffer.frame_count();
+    if frames < HUM_FFT {
+        return Ok(None);
+    }
+    let fft = RealFftPlanner::<f32>::new().plan_fft_forward(HUM_FFT);
+    let mut input = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+    let mut power = vec![0.0f64; spectrum.len()];
+    let segments = (frames / HUM_FFT).min(HUM_SEGMENTS);
+    let step = (frames - HUM_FFT) / segments.max(1);
+    for segment in 0..segments {
+        let offset = segment * step;
+        for (i, value) in input.iter_mut().enumerate() {
+            let w = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / HUM_FFT as f32).cos();
+            *value = buffer.samples.iter().map(|ch| ch[offset + i]).sum::<f32>() * w;
+        }
+        fft.process(&mut input, &mut spectrum)
+            .map_err(|e| anyhow::anyhow!("FFT failed: {}", e))?;
+        for (p, c) in power.iter_mut().zip(&spectrum) {
+            *p += c.norm_sqr() as f64;
+        }
+    }
+
+    let bin_hz = buffer.sample_rate as f64 / HUM_FFT as f64;
+    let bin = |hz: f64| ((hz / bin_hz).round() as usize).min(power.len() - 1);
+    // Strongest line of a harmonic series: (prominence dB, fundamental Hz)
+    let series = |mains: f64| -> Option<(f64, f64)> {
+        (1..=HUM_SEARCH_HARMONICS)
+            .filter_map(|h| {
+                let target = h as f64 * mains;
+                let (lo, hi) = (
+                    bin(target * (1.0 - HUM_DRIFT)),
+                    bin(target * (1.0 + HUM_DRIFT)),
+                );
+                let peak = (lo..=hi).max_by(|&a, &b| power[a].total_cmp(&power[b]))?;
+                let mut neighbourhood: Vec<f64> = (bin(target - HUM_NEIGHBOURHOOD_HZ)
+                    ..=bin(target + HUM_NEIGHBOURHOOD_HZ))
+                    .filter(|&k| k.abs_diff(peak) > 2)
+                    .map(|k| power[k])
+                    .collect();
+                if neighbourhood.is_empty() || power[peak] <= 0.0 {
+                    return None;
+                }
+                let middle = neighbourhood.len() / 2;
+                let (_, median, _) = neighbourhood.select_nth_unstable_by(middle, f64::total_cmp);
+                let prominence = 10.0 * (power[peak] / median.max(1e-30)).log10();
+                // Parabolic interpolation on the log spectrum
+                let mut frequency = peak as f64;
+                if peak > 0 && peak + 1 < power.len() {
+                    let db = |k: usize| power[k].max(1e-30).ln();
+                    let (a, b, c) = (db(peak - 1), db(peak), db(peak + 1));
+                    let denominator = a - 2.0 * b + c;
+                    if denominator < 0.0 {
+                        frequency += 0.5 * (a - c) / denominator;
+                    }
+                }
+                Some((prominence, frequency * bin_hz / h as f64))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+    };
+    Ok(MAINS_HZ
+        .iter()
+        .filter_map(|&mains| series(mains))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .filter(|&(prominence, _)| prominence >= HUM_PROMINENCE_DB)
+        .map(|(_, fundamental)| fundamental))
+}
+
+/// RBJ notch
+fn notch_coefs(sample_rate: f32, freq: f32, q: f32) -> [f32; 5] {
+    let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+    let cos_w0 = w0.cos();
+    let alpha = w0.sin() / (2.0 * q);
+
+    let a0 = 1.0 + alpha;
+    [
+        1.0 / a0,
+        -2.0 * cos_w0 / a0,
+        1.0 / a0,
+        -2.0 * cos_w0 / a0,
+        (1.0 - alpha) / a0,
+    ]
+}
+
+/// Spectral subtraction against the quietest frames' spectrum, returning
+/// the RMS of those frames before and after (dBFS)
+fn denoise(buffer: &mut AudioBuffer, tuning: &Tuning) -> Result<(f64, f64)> {
+    let size = ((DENOISE_FRAME_SECONDS * buffer.sample_rate as f64) as usize).next_power_of_two();
+    let hop = size / DENOISE_OVERLAP;
+    let frames = buffer.frame_count();
+    if frames < 2 * size {
+        return Ok((-144.0, -144.0));
+    }
+
+    // Frames lying wholly inside the audio, quietest first, skipping
+    // digital silence
+    let starts: Vec<usize> = (0..=frames - size).step_by(hop).collect();
+    let energy = |buffer: &AudioBuffer, start: usize| -> f64 {
+        buffer
+            .samples
+            .iter()
+            .flat_map(|ch| &ch[start..start + size])
+            .map(|&s| (s as f64).powi(2))
+            .sum()
+    };
+    let mut quiet: Vec<(usize, f64)> = starts
+        .iter()
+        .map(|&s| (s, energy(buffer, s)))
+        .filter(|&(_, e)| e > 1e-12)
+        .collect();
+    if quiet.is_empty() {
+        return Ok((-144.0, -144.0));
+    }
+    quiet.sort_by(|a, b| a.1.total_cmp(&b.1));
+    quiet.truncate(((quiet.len() as f64 * NOISE_PROFILE_SHARE).ceil() as usize).max(1));
+    let floor_db = |buffer: &AudioBuffer| {
+        let total: f64 = quiet.iter().map(|&(s, _)| energy(buffer, s)).sum();
+        let mean = total / (quiet.len() * size * buffer.channels) as f64;
+        (10.0 * mean.max(1e-30).log10()).max(-144.0)
+    };
+    let before = floor_db(buffer);
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(size);
+    let inverse = planner.plan_fft_inverse(size);
+    let window: Vec<f32> = (0..size)
+        .map(|i| (0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos()).sqrt())
+        .collect();
+    let floor_gain = 10f32.powf(-tuning.noise_reduction_db as f32 / 20.0);
+    // sqrt-Hann in and out sums to 2 at 75% overlap; the inverse FFT is
+    // unnormalized
+    let scale = 1.0 / (size as f32 * DENOISE_OVERLAP as f32 / 2.0);
+
+    let mut input = forward.make_input_vec();
+    let mut spectrum = forward.make_output_vec();
+    for channel in &mut buffer.samples {
+        // Padded so every sample is covered by a full set of frames
+        let mut padded = vec![0.0f32; frames + 2 * size];
+        padded[size..size + frames].copy_from_slice(channel);
+
+        let mut noise = vec![0.0f32; spectrum.len()];
+        for &(start, _) in &quiet {
+            for ((value, &s), &w) in input
+                .iter_mut()
+                .zip(&padded[size + start..2 * size + start])
+                .zip(&window)
+            {
+                *value = s * w;
+            }
+            forward
+                .process(&mut input, &mut spectrum)
+                .map_err(|e| anyhow::anyhow!("FFT failed: {}", e))?;
+            for (n, c) in noise.iter_mut().zip(&spectrum) {
+                *n += c.norm_sqr() / quiet.len() as f32;
+            }
+        }
+
+        let mut output = vec![0.0f32; padded.len()];
+        let mut smoothed = vec![0.0f32; spectrum.len()];
+        let mut gains = vec![1.0f32; spectrum.len()];
+        for start in (0..=padded.len() - size).step_by(hop) {
+            for ((value, &s), &w) in input
+                .iter_mut()
+                .zip(&padded[start..start + size])
+                .zip(&window)
+            {
+                *value = s * w;
+            }
+            forward
+                .process(&mut input, &mut spectrum)
+                .map_err(|e| anyhow::anyhow!("FFT failed: {}", e))?;
+            for (k, c) in spectrum.iter_mut().enumerate() {
+                smoothed[k] =
+                    SPECTRUM_SMOOTHING * smoothed[k] + (1.0 - SPECTRUM_SMOOTHING) * c.norm_sqr();
+                let subtracted = if smoothed[k] > 0.0 {
+                    (1.0 - tuning.over_subtraction * noise[k] / smoothed[k]).max(0.0)
+                } else {
+                    0.0
+                };
+                let gain = subtracted
+                    .sqrt()
+                    .max(floor_gain)
+                    .max(GAIN_RELEASE * gains[k]);
+                gains[k] = gain.min(1.0);
+                *c *= gains[k];
+            }
+            // The DC and Nyquist bins of a real signal's spectrum are real
+            spectrum[0].im = 0.0;
+            if let Some(last) = spectrum.last_mut() {
+                last.im = 0.0;
+            }
+            inverse
+                .process(&mut spectrum, &mut input)
+                .map_err(|e| anyhow::anyhow!("Inverse FFT failed: {}", e))?;
+            for ((out, &s), &w) in output[start..start + size]
+                .iter_mut()
+                .zip(&input)
+                .zip(&window)
+            {
+                *out += s * w * scale;
+            }
+        }
+        channel.copy_from_slice(&output[size..size + frames]);
+    }
+
+    Ok((before, floor_db(buffer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_defects_repaired() {
+        // Half a second of hiss, then a chord over hiss, with 60 Hz hum,
+        // clicks, and the right channel 3 samples late
+        let rate = 44100;
+        let frames = rate * 3;
+        let mut x: u32 = 11;
+        let mut hiss = move || {
+            x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+            ((x >> 8) as f32 / 16777216.0 - 0.5) * 0.004
+        };
+        let tone =
+            |i: usize, hz: f32| (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin();
+        let source: Vec<f32> = (0..frames + 3)
+            .map(|i| {
+                let music = if i < rate / 2 {
+                    0.0
+                } else {
+                    0.2 * tone(i, 440.0) + 0.15 * tone(i, 554.4) + 0.1 * tone(i, 659.3)
+                };
+                music + 0.01 * tone(i, 60.0) + hiss()
+            })
+            .collect();
+        let clicks = [30_000, 52_000, 77_777, 101_000, 120_500];
+        let mut buffer = AudioBuffer::new(2, rate as u32);
+        buffer.samples[0] = source[3..].to_vec();
+        buffer.samples[1] = source[..frames].to_vec();
+        for channel in &mut buffer.samples {
+            for &c in &clicks {
+                channel[c] += 0.7;
+                channel[c + 1] -= 0.4;
+            }
+        }
+        let goertzel = |samples: &[f32], hz: f64| {
+            let w = 2.0 * std::f64::consts::PI * hz / rate as f64;
+            let (mut s1, mut s2) = (0.0f64, 0.0f64);
+            for &s in samples {
+                let s0 = s as f64 + 2.0 * w.cos() * s1 - s2;
+                s2 = s1;
+                s1 = s0;
+            }
+            (s1 * s1 + s2 * s2 - 2.0 * w.cos() * s1 * s2).sqrt()
+        };
+        let hum_before = goertzel(&buffer.samples[0], 60.0);
+
+        let result = restore(&mut buffer, DEFAULT_STRENGTH).unwrap();
+
+        assert!(
+            (result.azimuth_lag_samples - 3.0).abs() < 0.3,
+            "{:?}",
+            result
+        );
+        assert!(!result.polarity_inverted);
+        assert!(result.clicks_repaired >= 2 * clicks.len(), "{:?}", result);
+        for channel in &buffer.samples {
+            for &c in &clicks {
+                assert!(
+                    channel[c].abs() < 0.6,
+                    "click at {} left: {}",
+                    c,
+                    channel[c]
+                );
+            }
+        }
+        assert!((result.hum_hz.unwrap() - 60.0).abs() < 0.5, "{:?}", result);
+        let hum_after = goertzel(&buffer.samples[0], 60.0);
+        assert!(
+            hum_after < hum_before / 10.0,
+            "{} -> {}",
+            hum_before,
+            hum_after
+        );
+        assert!(
+            result.noise_floor_after_db < result.noise_floor_db - 3.0,
+            "{:?}",
+            result
+        );
+    }
+}
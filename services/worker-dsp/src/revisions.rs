@@ -0,0 +1,276 @@
+//! What changed between revisions of a master
+//!
+//! Clients comparing "v3" and "v4" of a master mostly want to know whether
+//! it got louder, more squashed, hotter on peaks or brighter. Each revision
+//! is measured for loudness, loudness range, peaks and tonal balance (octave
+//! band levels relative to the whole, so a change in level alone doesn't
+//! show as a change in tone), and each revision is compared with the one
+//! before it, with plain-language notes for the changes big enough to hear.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::compare::BANDS;
+use crate::types::AudioBuffer;
+use budi_dsp_core::analysis;
+
+/// Most revisions one report takes
+pub const MAX_REVISIONS: usize = 20;
+
+/// Smallest changes noted: loudness and loudness range (LU), true peak and
+/// band level (dB), stereo width
+const NOTABLE_LU: f64 = 0.5;
+const NOTABLE_PEAK_DB: f64 = 0.3;
+const NOTABLE_BAND_DB: f64 = 1.0;
+const NOTABLE_WIDTH: f64 = 0.05;
+
+/// Level of one octave band relative to the whole revision
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandLevel {
+    pub low_hz: f64,
+    pub high_hz: f64,
+    pub level_db: f64,
+}
+
+/// The measurements of one revision
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionMeasurement {
+    pub label: String,
+    pub integrated_lufs: f64,
+    pub loudness_range: f64,
+    pub short_term_max: f64,
+    pub true_peak: f64,
+    pub sample_peak: f64,
+    pub stereo_width: Option<f64>,
+    pub clipped_samples: usize,
+    /// Empty for a revision too short to measure
+    pub tonal_balance: Vec<BandLevel>,
+    pub sample_rate: u32,
+    pub duration_secs: f64,
+    pub source_sha256: String,
+}
+
+impl RevisionMeasurement {
+    /// Measure revision `label` of a master
+    pub fn measure(label: &str, buffer: &AudioBuffer, source_sha256: &str) -> Result<Self> {
+        let analysis = analysis::analyze_audio(buffer, 24)?;
+        let tonal_balance = analysis::band_levels(buffer, &BANDS)?
+            .map(|levels| {
+                BANDS
+                    .iter()
+                    .zip(levels)
+                    .map(|(&(low_hz, high_hz), level_db)| BandLevel {
+                        low_hz,
+                        high_hz,
+                        level_db,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self {
+            label: label.to_string(),
+            integrated_lufs: analysis.integrated_lufs,
+            loudness_range: analysis.loudness_range,
+            short_term_max: analysis.short_term_max,
+            true_peak: analysis.true_peak,
+            sample_peak: analysis.sample_peak,
+            stereo_width: analysis.stereo_width,
+            clipped_samples: analysis.clipped_samples,
+            tonal_balance,
+            sample_rate: analysis.sample_rate,
+            duration_secs: analysis.duration_secs,
+            source_sha256: source_sha256.to_string(),
+        })
+    }
+}
+
+/// Change in one band's relative level
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandChange {
+    pub low_hz: f64,
+    pub high_hz: f64,
+    pub delta_db: f64,
+}
+
+/// How one revision differs from the one before it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionChange {
+    pub from: String,
+    pub to: String,
+    pub integrated_lufs_delta: f64,
+    pub loudness_range_delta: f64,
+    pub true_peak_delta: f64,
+    pub stereo_width_delta: Option<f64>,
+    pub duration_delta_secs: f64,
+    /// Empty unless both revisions were long enough to measure
+    pub tonal_balance_delta: Vec<BandChange>,
+    /// The changes big enough to hear, in words
+    pub notes: Vec<String>,
+}
+
+/// Compare each revision with the one before it
+pub fn changes(revisions: &[RevisionMeasurement]) -> Vec<RevisionChange> {
+    revisions
+        .windows(2)
+        .map(|pair| {
+            let (a, b) = (&pair[0], &pair[1]);
+            let tonal_balance_delta: Vec<BandChange> =
+                if a.tonal_balance.len() == b.tonal_balance.len() {
+                    a.tonal_balance
+                        .iter()
+                        .zip(&b.tonal_balance)
+                        .map(|(x, y)| BandChange {
+                            low_hz: x.low_hz,
+                            high_hz: x.high_hz,
+                            delta_db: y.level_db - x.level_db,
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+            let stereo_width_delta = a.stereo_width.zip(b.stereo_width).map(|(x, y)| y - x);
+
+            let mut change = RevisionChange {
+                from: a.label.clone(),
+                to: b.label.clone(),
+                integrated_lufs_delta: b.integrated_lufs - a.integrated_lufs,
+                loudness_range_delta: b.loudness_range - a.loudness_range,
+                true_peak_delta: b.true_peak - a.true_peak,
+                stereo_width_delta,
+                duration_delta_secs: b.duration_secs - a.duration_secs,
+                tonal_balance_delta,
+                notes: Vec::new(),
+            };
+            change.notes = notes(&change, b);
+            change
+        })
+        .collect()
+}
+
+/// Plain-language notes on a change
+fn notes(change: &RevisionChange, to: &RevisionMeasurement) -> Vec<String> {
+    let mut notes = Vec::new();
+    let lufs = change.integrated_lufs_delta;
+    if lufs.abs() >= NOTABLE_LU {
+        notes.push(format!(
+            "{:.1} LU {} ({:.1} LUFS)",
+            lufs.abs(),
+            if lufs > 0.0 { "louder" } else { "quieter" },
+            to.integrated_lufs
+        ));
+    }
+    let lra = change.loudness_range_delta;
+    if lra.abs() >= NOTABLE_LU {
+        notes.push(format!(
+            "Loudness range {} by {:.1} LU ({})",
+            if lra > 0.0 { "wider" } else { "narrower" },
+            lra.abs(),
+            if lra > 0.0 {
+                "more dynamic"
+            } else {
+                "more compressed"
+            }
+        ));
+    }
+    let peak = change.true_peak_delta;
+    if peak.abs() >= NOTABLE_PEAK_DB {
+        notes.push(format!(
+            "True peak {} by {:.1} dB ({:.1} dBTP)",
+            if peak > 0.0 { "up" } else { "down" },
+            peak.abs(),
+            to.true_peak
+        ));
+    }
+    if let Some(width) = change
+        .stereo_width_delta
+        .filter(|w| w.abs() >= NOTABLE_WIDTH)
+    {
+        notes.push(format!(
+            "Stereo image {}",
+            if width > 0.0 { "wider" } else { "narrower" }
+        ));
+    }
+    for band in &change.tonal_balance_delta {
+        if band.delta_db.abs() >= NOTABLE_BAND_DB {
+            notes.push(format!(
+                "{:+.1} dB at {}-{}",
+                band.delta_db,
+                hz(band.low_hz),
+                hz(band.high_hz)
+            ));
+        }
+    }
+    if change.duration_delta_secs.abs() >= 0.5 {
+        notes.push(format!(
+            "{:.1}s {}",
+            change.duration_delta_secs.abs(),
+            if change.duration_delta_secs > 0.0 {
+                "longer"
+            } else {
+                "shorter"
+            }
+        ));
+    }
+    notes
+}
+
+fn hz(hz: f64) -> String {
+    if hz >= 1000.0 {
+        format!("{:.1} kHz", hz / 1000.0)
+    } else {
+        format!("{:.0} Hz", hz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_louder_brighter_revision_noted() {
+        // v2 is v1 turned up 3 dB with an 8 kHz tone added on top
+        let rate = 44100;
+        let tone =
+            |i: usize, hz: f32| (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin();
+        let render = |gain: f32, air: f32| {
+            let mut buffer = AudioBuffer::new(2, rate as u32);
+            for channel in &mut buffer.samples {
+                *channel = (0..rate * 5)
+                    .map(|i| {
+                        gain * (0.2 * tone(i, 220.0)
+                            + 0.1 * tone(i, 1000.0)
+                            + air * tone(i, 8000.0))
+                    })
+                    .collect();
+            }
+            buffer
+        };
+        let v1 = RevisionMeasurement::measure("v1", &render(0.5, 0.0), "a").unwrap();
+        let v2 = RevisionMeasurement::measure("v2", &render(0.706, 0.05), "b").unwrap();
+        let v3 = RevisionMeasurement::measure("v3", &render(0.706, 0.05), "c").unwrap();
+
+        let changes = changes(&[v1, v2, v3]);
+        assert_eq!(changes.len(), 2);
+        let louder = &changes[0];
+        assert_eq!((louder.from.as_str(), louder.to.as_str()), ("v1", "v2"));
+        assert!(louder.integrated_lufs_delta > 2.5, "{:?}", louder);
+        assert!(louder.notes[0].contains("louder"), "{:?}", louder.notes);
+        let air = louder
+            .tonal_balance_delta
+            .iter()
+            .find(|b| b.low_hz <= 8000.0 && 8000.0 < b.high_hz)
+            .unwrap();
+        assert!(air.delta_db > 10.0, "{:?}", air);
+        assert!(louder
+            .notes
+            .iter()
+            .any(|n| n.contains("at 5.1 kHz-10.2 kHz")));
+
+        // Identical revisions: nothing to note
+        assert!(changes[1].notes.is_empty(), "{:?}", changes[1].notes);
+    }
+}
@@ -0,0 +1,440 @@
+//! Silence and section boundary detection, for chapters and track splits
+//!
+//! Two kinds of boundary are found as the file is decoded:
+//!
+//! - Silences: runs of `BLOCK_SECONDS` blocks quieter than the threshold for
+//!   at least the minimum length, split at their midpoint
+//! - Changes: points where the spectral shape over the `CONTEXT_SECONDS`
+//!   before differs most from the shape over those after, such as a segue
+//!   between songs in a live set or music giving way to speech
+//!
+//! Silences win over changes, and no boundary is kept closer than the
+//! minimum segment length to another, the start or the end.
+
+use anyhow::Result;
+use budi_worker_core::audio::AudioDecoder;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::types::AudioBuffer;
+
+/// Defaults when a job doesn't give them
+pub const DEFAULT_SILENCE_THRESHOLD_DB: f64 = -50.0;
+pub const DEFAULT_MIN_SILENCE_SECONDS: f64 = 2.0;
+pub const DEFAULT_MIN_SEGMENT_SECONDS: f64 = 30.0;
+pub const DEFAULT_LABEL_PREFIX: &str = "Chapter";
+
+/// Length of the blocks silence is measured in (seconds)
+const BLOCK_SECONDS: f64 = 0.1;
+
+/// Blocks between spectral shape frames (half a second)
+const FRAME_BLOCKS: usize = 5;
+
+/// FFT of each spectral shape frame
+const FFT_SIZE: usize = 4096;
+
+/// Log-spaced bands the spectral shape is measured in
+const SHAPE_BANDS: usize = 16;
+const SHAPE_LOW_HZ: f64 = 50.0;
+const SHAPE_HIGH_HZ: f64 = 16000.0;
+
+/// Audio compared either side of a candidate change (seconds)
+const CONTEXT_SECONDS: f64 = 8.0;
+
+/// Smallest difference in spectral shape (total variation, 0-1) counted as
+/// a change
+const CHANGE_THRESHOLD: f64 = 0.3;
+
+/// Settings of a detection
+#[derive(Debug, Clone)]
+pub struct SegmentOptions {
+    pub silence_threshold_db: f64,
+    pub min_silence_seconds: f64,
+    pub min_segment_seconds: f64,
+    /// Find spectral changes as well as silences
+    pub detect_changes: bool,
+    /// Markers are labelled "<prefix> 1", "<prefix> 2", ...
+    pub label_prefix: String,
+}
+
+impl SegmentOptions {
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            (-120.0..0.0).contains(&self.silence_threshold_db),
+            "silenceThresholdDb must be between -120 and 0"
+        );
+        anyhow::ensure!(
+            self.min_silence_seconds >= BLOCK_SECONDS,
+            "minSilenceSeconds must be at least {}",
+            BLOCK_SECONDS
+        );
+        anyhow::ensure!(
+            self.min_segment_seconds > 0.0,
+            "minSegmentSeconds must be above 0"
+        );
+        Ok(())
+    }
+}
+
+/// Why a segment starts where it does
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BoundaryKind {
+    /// The first segment
+    Start,
+    Silence,
+    Change,
+}
+
+/// Start of a segment
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Marker {
+    pub time_seconds: f64,
+    pub label: String,
+    pub kind: BoundaryKind,
+    /// Length of the silence, or the difference in spectral shape (0-1)
+    pub strength: f64,
+}
+
+/// A run of silence
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Silence {
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+}
+
+/// Everything a detection found
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Segmentation {
+    pub duration_secs: f64,
+    pub markers: Vec<Marker>,
+    pub silences: Vec<Silence>,
+}
+
+/// Running block levels and spectral shapes of a recording
+pub struct SegmentDetector {
+    channels: usize,
+    sample_rate: u32,
+    block_frames: usize,
+    /// Mean square of each finished block
+    blocks: Vec<f64>,
+    partial: (f64, usize),
+    /// Spectral shape every `FRAME_BLOCKS` blocks, `None` where silent
+    shapes: Vec<Option<[f64; SHAPE_BANDS]>>,
+    /// Most recent mono samples, for the next shape frame
+    mono: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    band_bins: Vec<(usize, usize)>,
+    silence_threshold: f64,
+}
+
+impl SegmentDetector {
+    pub fn new(channels: usize, sample_rate: u32, silence_threshold_db: f64) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        let bin_hz = sample_rate as f64 / FFT_SIZE as f64;
+        let high = SHAPE_HIGH_HZ.min(sample_rate as f64 / 2.0);
+        let edge = |band: usize| {
+            SHAPE_LOW_HZ * (high / SHAPE_LOW_HZ).powf(band as f64 / SHAPE_BANDS as f64)
+        };
+        let band_bins = (0..SHAPE_BANDS)
+            .map(|b| {
+                let low = (edge(b) / bin_hz).ceil() as usize;
+                let high = ((edge(b + 1) / bin_hz).ceil() as usize).max(low + 1);
+                (low, high.min(FFT_SIZE / 2 + 1))
+            })
+            .collect();
+        Self {
+            channels,
+            sample_rate,
+            block_frames: ((BLOCK_SECONDS * sample_rate as f64) as usize).max(1),
+            blocks: Vec::new(),
+            partial: (0.0, 0),
+            shapes: Vec::new(),
+            mono: Vec::with_capacity(FFT_SIZE * 2),
+            input: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            fft,
+            window,
+            band_bins,
+            silence_threshold: 10f64.powf(silence_threshold_db / 10.0),
+        }
+    }
+
+    /// Add the next block of the recording
+    pub fn add(&mut self, buffer: &AudioBuffer) -> Result<()> {
+        let channels = self.channels.max(1) as f32;
+        for i in 0..buffer.frame_count() {
+            let mut square = 0.0f64;
+            let mut sum = 0.0f32;
+            for channel in &buffer.samples {
+                square += (channel[i] as f64).powi(2);
+                sum += channel[i];
+            }
+            self.mono.push(sum / channels);
+            self.partial.0 += square / channels as f64;
+            self.partial.1 += 1;
+            if self.partial.1 == self.block_frames {
+                self.blocks.push(self.partial.0 / self.block_frames as f64);
+                self.partial = (0.0, 0);
+                if self.blocks.len().is_multiple_of(FRAME_BLOCKS) {
+                    self.add_shape()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Spectral shape of the frame just finished
+    fn add_shape(&mut self) -> Result<()> {
+        let recent = &self.blocks[self.blocks.len() - FRAME_BLOCKS..];
+        let silent = recent.iter().sum::<f64>() / FRAME_BLOCKS as f64 <= self.silence_threshold;
+        let start = self.mono.len().saturating_sub(FFT_SIZE);
+        let shape = if silent || self.mono.len() < FFT_SIZE {
+            None
+        } else {
+            for ((input, &sample), &window) in self
+                .input
+                .iter_mut()
+                .zip(&self.mono[start..])
+                .zip(&self.window)
+            {
+                *input = sample * window;
+            }
+            self.fft
+                .process(&mut self.input, &mut self.spectrum)
+                .map_err(|e| anyhow::anyhow!("FFT failed: {}", e))?;
+            let mut shape = [0.0; SHAPE_BANDS];
+            for (energy, &(low, high)) in shape.iter_mut().zip(&self.band_bins) {
+                *energy = self.spectrum[low.min(high)..high]
+                    .iter()
+                    .map(|c| c.norm_sqr() as f64)
+                    .sum();
+            }
+            let total: f64 = shape.iter().sum();
+            (total > 0.0).then(|| shape.map(|e| e / total))
+        };
+        self.shapes.push(shape);
+        self.mono.drain(..start);
+        Ok(())
+    }
+
+    /// Boundaries of everything added
+    pub fn finish(self, options: &SegmentOptions) -> Segmentation {
+        let block_seconds = self.block_frames as f64 / self.sample_rate as f64;
+        let frames = self.blocks.len() * self.block_frames + self.partial.1;
+        let duration = frames as f64 / self.sample_rate as f64;
+
+        // Runs of quiet blocks
+        let min_blocks = (options.min_silence_seconds / block_seconds).ceil() as usize;
+        let mut silences = Vec::new();
+        let mut run_start = None;
+        for (i, &power) in self.blocks.iter().chain([&f64::INFINITY]).enumerate() {
+            match (power <= self.silence_threshold, run_start) {
+                (true, None) => run_start = Some(i),
+                (false, Some(start)) => {
+                    if i - start >= min_blocks {
+                        silences.push(Silence {
+                            start_seconds: start as f64 * block_seconds,
+                            end_seconds: (i as f64 * block_seconds).min(duration),
+                        });
+                    }
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+
+        // Candidates, strongest first: silences by length, then changes
+        let mut candidates: Vec<(f64, BoundaryKind, f64)> = silences
+            .iter()
+            .map(|s| {
+                (
+                    (s.start_seconds + s.end_seconds) / 2.0,
+                    BoundaryKind::Silence,
+                    s.end_seconds - s.start_seconds,
+                )
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+        if options.detect_changes {
+            let frame_seconds = FRAME_BLOCKS as f64 * block_seconds;
+            let mut changes = novelty_peaks(&self.shapes, CONTEXT_SECONDS / frame_seconds);
+            changes.sort_by(|a, b| b.1.total_cmp(&a.1));
+            candidates.extend(changes.into_iter().map(|(frame, strength)| {
+                (frame as f64 * frame_seconds, BoundaryKind::Change, strength)
+            }));
+        }
+
+        let mut accepted: Vec<(f64, BoundaryKind, f64)> = Vec::new();
+        for candidate in candidates {
+            let time = candidate.0;
+            let spaced = time >= options.min_segment_seconds
+                && duration - time >= options.min_segment_seconds
+                && accepted
+                    .iter()
+                    .all(|a| (a.0 - time).abs() >= options.min_segment_seconds);
+            if spaced {
+                accepted.push(candidate);
+            }
+        }
+        accepted.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let markers = std::iter::once((0.0, BoundaryKind::Start, 0.0))
+            .chain(accepted)
+            .enumerate()
+            .map(|(i, (time_seconds, kind, strength))| Marker {
+                time_seconds,
+                label: format!("{} {}", options.label_prefix, i + 1),
+                kind,
+                strength,
+            })
+            .collect();
+
+        Segmentation {
+            duration_secs: duration,
+            markers,
+            silences,
+        }
+    }
+}
+
+/// Frames where the spectral shape before differs most from the shape after,
+/// with that difference, over `context` frames either side
+fn novelty_peaks(shapes: &[Option<[f64; SHAPE_BANDS]>], context: f64) -> Vec<(usize, f64)> {
+    let context = (context as usize).max(1);
+    let mean = |frames: &[Option<[f64; SHAPE_BANDS]>]| {
+        let present: Vec<&[f64; SHAPE_BANDS]> = frames.iter().flatten().collect();
+        // Mostly silent context is left to silence detection
+        if present.len() * 2 < context {
+            return None;
+        }
+        let mut mean = [0.0; SHAPE_BANDS];
+        for shape in &present {
+            for (m, s) in mean.iter_mut().zip(shape.iter()) {
+                *m += s / present.len() as f64;
+            }
+        }
+        Some(mean)
+    };
+
+    let novelty: Vec<f64> = (0..shapes.len())
+        .map(|t| {
+            if t < context || t + context > shapes.len() {
+                return 0.0;
+            }
+            match (mean(&shapes[t - context..t]), mean(&shapes[t..t + context])) {
+                (Some(before), Some(after)) => {
+                    0.5 * before
+                        .iter()
+                        .zip(&after)
+                        .map(|(a, b)| (a - b).abs())
+                        .sum::<f64>()
+                }
+                _ => 0.0,
+            }
+        })
+        .collect();
+
+    (0..novelty.len())
+        .filter(|&t| {
+            let neighbours = t.saturating_sub(context)..(t + context + 1).min(novelty.len());
+            novelty[t] >= CHANGE_THRESHOLD
+                && neighbours.clone().all(|n| novelty[n] <= novelty[t])
+                // The first of equal neighbours
+                && neighbours.take_while(|&n| n < t).all(|n| novelty[n] < novelty[t])
+        })
+        .map(|t| (t, novelty[t]))
+        .collect()
+}
+
+/// Detect the segments of an audio file as it is decoded
+#[tracing::instrument(name = "dsp.segments", skip_all)]
+pub fn detect_file(path: &Path, options: &SegmentOptions) -> Result<Segmentation> {
+    let mut decoder = AudioDecoder::open(path)?;
+    let mut detector = SegmentDetector::new(
+        decoder.channels,
+        decoder.sample_rate,
+        options.silence_threshold_db,
+    );
+    let mut block = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    while decoder.decode_next(&mut block)? {
+        detector.add(&block)?;
+        for channel in &mut block.samples {
+            channel.clear();
+        }
+    }
+    Ok(detector.finish(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_silence_and_change_boundaries() {
+        // A low tone, 3 s of silence, noise, then straight into a high tone
+        let rate = 44100;
+        let mut x: u32 = 9;
+        let sine = |i: usize, hz: f32| {
+            0.3 * (2.0 * std::f32::consts::PI * hz * i as f32 / rate as f32).sin()
+        };
+        let samples: Vec<f32> = (0..rate * 63)
+            .map(|i| match i / rate {
+                0..=19 => sine(i, 200.0),
+                20..=22 => 0.0,
+                23..=42 => {
+                    x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+                    ((x >> 8) as f32 / 16777216.0 - 0.5) * 0.5
+                }
+                _ => sine(i, 3000.0),
+            })
+            .collect();
+
+        let options = SegmentOptions {
+            silence_threshold_db: DEFAULT_SILENCE_THRESHOLD_DB,
+            min_silence_seconds: DEFAULT_MIN_SILENCE_SECONDS,
+            min_segment_seconds: 10.0,
+            detect_changes: true,
+            label_prefix: "Track".to_string(),
+        };
+        let mut detector = SegmentDetector::new(1, rate as u32, options.silence_threshold_db);
+        for chunk in samples.chunks(30_000) {
+            let mut block = AudioBuffer::new(1, rate as u32);
+            block.samples[0] = chunk.to_vec();
+            detector.add(&block).unwrap();
+        }
+        let result = detector.finish(&options);
+
+        assert_eq!(result.silences.len(), 1);
+        assert!((result.silences[0].start_seconds - 20.0).abs() < 0.2);
+        assert!((result.silences[0].end_seconds - 23.0).abs() < 0.2);
+
+        let markers: Vec<(BoundaryKind, &str)> = result
+            .markers
+            .iter()
+            .map(|m| (m.kind, m.label.as_str()))
+            .collect();
+        assert_eq!(
+            markers,
+            [
+                (BoundaryKind::Start, "Track 1"),
+                (BoundaryKind::Silence, "Track 2"),
+                (BoundaryKind::Change, "Track 3")
+            ]
+        );
+        assert!((result.markers[1].time_seconds - 21.5).abs() < 0.2);
+        assert!((result.markers[2].time_seconds - 43.0).abs() < 1.0);
+    }
+}
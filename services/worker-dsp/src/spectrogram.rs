@@ -0,0 +1,451 @@
+//! Spectrogram images for the UI's visual QC view
+//!
+//! The source is mixed to mono and run through a Hann-windowed STFT as it is
+//! decoded, and each frame's magnitudes are reduced to the rows of every
+//! requested image (mel or linear bands, the peak bin of each). Frames are
+//! folded into columns by their peak too, and whenever an image holds twice
+//! its width in columns neighbouring pairs are merged, so memory stays
+//! bounded by the image sizes however long the source is. The images are
+//! encoded as 8-bit RGB PNGs, highest frequency at the top.
+
+use anyhow::Result;
+use budi_worker_core::audio::AudioDecoder;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::io::Write as _;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::types::{AudioBuffer, ColorMap, SpectrogramResolution, SpectrogramScale};
+
+/// Images rendered when a job doesn't give any sizes
+pub const DEFAULT_RESOLUTIONS: [SpectrogramResolution; 2] = [
+    SpectrogramResolution {
+        width: 1024,
+        height: 256,
+    },
+    SpectrogramResolution {
+        width: 4096,
+        height: 512,
+    },
+];
+
+/// Largest image a job may ask for (pixels)
+pub const MAX_WIDTH: u32 = 8192;
+pub const MAX_HEIGHT: u32 = 1024;
+
+/// Smallest image a job may ask for (pixels)
+pub const MIN_SIZE: u32 = 16;
+
+/// Most images a job may ask for
+pub const MAX_IMAGES: usize = 8;
+
+const FFT_SIZE: usize = 4096;
+const HOP_SIZE: usize = FFT_SIZE / 4;
+
+/// Level drawn in the first colour of the map (dBFS)
+pub const FLOOR_DB: f32 = -110.0;
+
+/// Lowest frequency of the mel scale (Hz)
+const MEL_MIN_HZ: f32 = 20.0;
+
+/// Peak magnitudes of one image, a column at a time
+struct Image {
+    width: usize,
+    height: usize,
+    /// FFT bins of each row, lowest frequency first
+    bands: Vec<Range<usize>>,
+    /// STFT frames folded into each column, doubled by every merge
+    frames_per_column: usize,
+    /// Frames folded into the last column so far
+    last_frames: usize,
+    /// Column-major, `height` values per column
+    columns: Vec<f32>,
+}
+
+impl Image {
+    fn new(resolution: SpectrogramResolution, scale: SpectrogramScale, sample_rate: u32) -> Self {
+        let height = resolution.height as usize;
+        let bins = FFT_SIZE / 2 + 1;
+        let bin_hz = sample_rate as f32 / FFT_SIZE as f32;
+        let nyquist = sample_rate as f32 / 2.0;
+        let edge = |row: usize| {
+            let t = row as f32 / height as f32;
+            let freq = match scale {
+                SpectrogramScale::Linear => t * nyquist,
+                SpectrogramScale::Mel => {
+                    let (lo, hi) = (hz_to_mel(MEL_MIN_HZ), hz_to_mel(nyquist));
+                    mel_to_hz(lo + (hi - lo) * t)
+                }
+            };
+            ((freq / bin_hz).round() as usize).min(bins - 1)
+        };
+        // Rows narrower than a bin show the nearest one
+        let bands = (0..height)
+            .map(|row| {
+                let lo = edge(row);
+                lo..edge(row + 1).max(lo + 1)
+            })
+            .collect();
+
+        Self {
+            width: resolution.width as usize,
+            height,
+            bands,
+            frames_per_column: 1,
+            last_frames: 0,
+            columns: Vec::new(),
+        }
+    }
+
+    fn column_count(&self) -> usize {
+        self.columns.len() / self.height
+    }
+
+    /// Fold one frame's magnitudes into the last column
+    fn add(&mut self, magnitudes: &[f32]) {
+        if self.columns.is_empty() || self.last_frames == self.frames_per_column {
+            if self.column_count() == 2 * self.width {
+                self.merge();
+            }
+            self.columns.resize(self.columns.len() + self.height, 0.0);
+            self.last_frames = 0;
+        }
+        let start = self.columns.len() - self.height;
+        for (value, band) in self.columns[start..].iter_mut().zip(&self.bands) {
+            let peak = magnitudes[band.clone()].iter().copied().fold(0.0, f32::max);
+            *value = value.max(peak);
+        }
+        self.last_frames += 1;
+    }
+
+    /// Halve the columns by merging neighbouring pairs
+    fn merge(&mut self) {
+        let height = self.height;
+        for pair in 0..self.column_count() / 2 {
+            for row in 0..height {
+                let value = self.columns[2 * pair * height + row]
+                    .max(self.columns[(2 * pair + 1) * height + row]);
+                self.columns[pair * height + row] = value;
+            }
+        }
+        self.columns.truncate(self.column_count() / 2 * height);
+        self.frames_per_column *= 2;
+    }
+
+    /// The image as RGB rows, highest frequency first, stretched or shrunk
+    /// from the columns gathered to the requested width
+    fn render(&self, color_map: ColorMap) -> Vec<u8> {
+        let count = self.column_count();
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for row in (0..self.height).rev() {
+            for x in 0..self.width {
+                let lo = x * count / self.width;
+                let hi = ((x + 1) * count / self.width).max(lo + 1).min(count);
+                let peak = (lo..hi)
+                    .map(|col| self.columns[col * self.height + row])
+                    .fold(0.0f32, f32::max);
+                let db = 20.0 * peak.max(1e-9).log10();
+                rgb.extend_from_slice(&color(color_map, (db - FLOOR_DB) / -FLOOR_DB));
+            }
+        }
+        rgb
+    }
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+/// Mono STFT of a source as it is decoded
+struct Stft {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    /// Scales magnitudes so a full-scale sine reads 0 dBFS
+    scale: f32,
+    input: Vec<f32>,
+    spectrum: Vec<realfft::num_complex::Complex<f32>>,
+    magnitudes: Vec<f32>,
+    /// Mono samples not yet past the last frame
+    mono: Vec<f32>,
+    frames: usize,
+}
+
+impl Stft {
+    fn new() -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let window: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| 0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos()))
+            .collect();
+        Self {
+            scale: 2.0 / window.iter().sum::<f32>(),
+            window,
+            input: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            magnitudes: vec![0.0; FFT_SIZE / 2 + 1],
+            fft,
+            mono: Vec::with_capacity(2 * FFT_SIZE),
+            frames: 0,
+        }
+    }
+
+    fn add(&mut self, planes: &[Vec<f32>], images: &mut [Image]) -> Result<()> {
+        let frames = planes.first().map_or(0, Vec::len);
+        let gain = 1.0 / planes.len().max(1) as f32;
+        self.mono
+            .extend((0..frames).map(|i| planes.iter().map(|p| p[i]).sum::<f32>() * gain));
+        self.drain(images)
+    }
+
+    /// Zero-pad the tail so its last samples reach a frame
+    fn finish(&mut self, images: &mut [Image]) -> Result<()> {
+        if !self.mono.is_empty() {
+            let len = (self.mono.len() + FFT_SIZE - HOP_SIZE).max(FFT_SIZE);
+            self.mono.resize(len, 0.0);
+            self.drain(images)?;
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self, images: &mut [Image]) -> Result<()> {
+        let mut start = 0;
+        while start + FFT_SIZE <= self.mono.len() {
+            for ((input, &sample), &w) in self
+                .input
+                .iter_mut()
+                .zip(&self.mono[start..start + FFT_SIZE])
+                .zip(&self.window)
+            {
+                *input = sample * w;
+            }
+            self.fft.process(&mut self.input, &mut self.spectrum)?;
+            for (magnitude, bin) in self.magnitudes.iter_mut().zip(&self.spectrum) {
+                *magnitude = bin.norm() * self.scale;
+            }
+            for image in images.iter_mut() {
+                image.add(&self.magnitudes);
+            }
+            self.frames += 1;
+            start += HOP_SIZE;
+        }
+        self.mono.drain(..start);
+        Ok(())
+    }
+}
+
+/// Spectrogram images of a source
+pub struct Spectrogram {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_secs: f64,
+    /// RGB rows of each image, in the order of the job's resolutions
+    pub images: Vec<(SpectrogramResolution, Vec<u8>)>,
+}
+
+/// Check a job's image sizes, filling in the defaults
+pub fn validate(resolutions: &[SpectrogramResolution]) -> Result<Vec<SpectrogramResolution>> {
+    let resolutions = if resolutions.is_empty() {
+        DEFAULT_RESOLUTIONS.to_vec()
+    } else {
+        resolutions.to_vec()
+    };
+    anyhow::ensure!(
+        resolutions.len() <= MAX_IMAGES,
+        "At most {} spectrogram images can be rendered",
+        MAX_IMAGES
+    );
+    for size in &resolutions {
+        anyhow::ensure!(
+            (MIN_SIZE..=MAX_WIDTH).contains(&size.width)
+                && (MIN_SIZE..=MAX_HEIGHT).contains(&size.height),
+            "Spectrogram images must be {}-{} pixels wide and {}-{} high, not {}x{}",
+            MIN_SIZE,
+            MAX_WIDTH,
+            MIN_SIZE,
+            MAX_HEIGHT,
+            size.width,
+            size.height
+        );
+    }
+    Ok(resolutions)
+}
+
+/// Render the spectrogram of `path` at each of `resolutions`
+#[tracing::instrument(name = "dsp.spectrogram", skip_all)]
+pub fn spectrogram_file(
+    path: &Path,
+    resolutions: &[SpectrogramResolution],
+    scale: SpectrogramScale,
+    color_map: ColorMap,
+) -> Result<Spectrogram> {
+    let mut decoder = AudioDecoder::open(path)?;
+    let mut images: Vec<Image> = resolutions
+        .iter()
+        .map(|&resolution| Image::new(resolution, scale, decoder.sample_rate))
+        .collect();
+
+    let mut stft = Stft::new();
+    let mut block = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    let mut frames = 0;
+    while decoder.decode_next(&mut block)? {
+        frames += block.frame_count();
+        stft.add(&block.samples, &mut images)?;
+        for plane in &mut block.samples {
+            plane.clear();
+        }
+    }
+    stft.finish(&mut images)?;
+    anyhow::ensure!(stft.frames > 0, "Source has no audio to draw");
+
+    Ok(Spectrogram {
+        sample_rate: decoder.sample_rate,
+        channels: decoder.channels,
+        duration_secs: frames as f64 / decoder.sample_rate as f64,
+        images: resolutions
+            .iter()
+            .zip(&images)
+            .map(|(&resolution, image)| (resolution, image.render(color_map)))
+            .collect(),
+    })
+}
+
+/// The colour of level `t` (0 at the floor, 1 at full scale) in `map`
+pub fn color(map: ColorMap, t: f32) -> [u8; 3] {
+    const HEAT: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.19, 0.11, 0.45],
+        [0.78, 0.16, 0.55],
+        [0.98, 0.62, 0.18],
+        [1.0, 0.98, 0.85],
+    ];
+    const VIRIDIS: [[f32; 3]; 5] = [
+        [0.267, 0.005, 0.329],
+        [0.231, 0.322, 0.545],
+        [0.129, 0.569, 0.549],
+        [0.369, 0.788, 0.384],
+        [0.992, 0.906, 0.145],
+    ];
+    const MAGMA: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.016],
+        [0.317, 0.071, 0.486],
+        [0.718, 0.216, 0.475],
+        [0.988, 0.537, 0.380],
+        [0.988, 0.992, 0.749],
+    ];
+    const GRAYSCALE: [[f32; 3]; 2] = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]];
+
+    let stops: &[[f32; 3]] = match map {
+        ColorMap::Heat => &HEAT,
+        ColorMap::Viridis => &VIRIDIS,
+        ColorMap::Magma => &MAGMA,
+        ColorMap::Grayscale => &GRAYSCALE,
+    };
+    let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (t as usize).min(stops.len() - 2);
+    let f = t - i as f32;
+    let mut rgb = [0u8; 3];
+    for (c, out) in rgb.iter_mut().enumerate() {
+        let v = stops[i][c] + (stops[i + 1][c] - stops[i][c]) * f;
+        *out = (v * 255.0).round() as u8;
+    }
+    rgb
+}
+
+/// Encode RGB rows as an 8-bit truecolour PNG
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Result<Vec<u8>> {
+    let stride = width as usize * 3;
+    anyhow::ensure!(rgb.len() == stride * height as usize, "Image size mismatch");
+
+    // Each row is prefixed with its filter type (0, none)
+    let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    for row in rgb.chunks_exact(stride) {
+        zlib.write_all(&[0])?;
+        zlib.write_all(row)?;
+    }
+    let idat = zlib.finish()?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // Bit depth 8, colour type 2 (RGB), deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = Vec::with_capacity(idat.len() + 64);
+    png.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+    for (kind, data) in [
+        (b"IHDR", &ihdr[..]),
+        (b"IDAT", &idat[..]),
+        (b"IEND", &[][..]),
+    ] {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let mut crc = flate2::Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        png.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_lands_in_its_row_and_encodes() {
+        // Five seconds of 1 kHz, far more frames than the image is wide, so
+        // columns are merged along the way
+        let sample_rate = 48000;
+        let tone: Vec<f32> = (0..sample_rate * 5)
+            .map(|i| (2.0 * std::f32::consts::PI * (i % 48) as f32 / 48.0).sin())
+            .collect();
+        let resolution = SpectrogramResolution {
+            width: 64,
+            height: 32,
+        };
+        let mut images = [Image::new(
+            resolution,
+            SpectrogramScale::Linear,
+            sample_rate as u32,
+        )];
+        let mut stft = Stft::new();
+        for block in tone.chunks(1000) {
+            stft.add(&[block.to_vec(), block.to_vec()], &mut images)
+                .unwrap();
+        }
+        stft.finish(&mut images).unwrap();
+
+        let image = &images[0];
+        assert!(image.frames_per_column > 1);
+        assert!(image.column_count() <= 2 * image.width);
+        // 1 kHz is in the second of 32 rows up to 24 kHz, and a full-scale
+        // sine reads close to 0 dBFS
+        let middle = image.column_count() / 2 * image.height;
+        let db = 20.0 * image.columns[middle + 1].log10();
+        assert!(db > -1.0 && db < 0.5, "{}", db);
+        assert!(image.columns[middle + 8] < 1e-3);
+
+        let rgb = image.render(ColorMap::Grayscale);
+        assert_eq!(rgb.len(), 64 * 32 * 3);
+        // The tone's row is near white, the top row near black
+        assert!(rgb[(32 - 2) * 64 * 3 + 3 * 32] > 240);
+        assert!(rgb[3 * 32] < 40);
+
+        let png = encode_png(64, 32, &rgb).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(&png[12..16], b"IHDR");
+        assert!(png.ends_with(&[0xAE, 0x42, 0x60, 0x82]));
+
+        assert!(validate(&[]).is_ok());
+        assert!(validate(&[SpectrogramResolution {
+            width: 8,
+            height: 64
+        }])
+        .is_err());
+    }
+}
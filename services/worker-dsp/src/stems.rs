@@ -0,0 +1,176 @@
+//! Stem separation through Demucs
+//!
+//! The model runs as a subprocess (the `demucs` CLI from `[separation]`),
+//! which keeps PyTorch and the model weights out of the worker and lets the
+//! separation use a GPU where the host has one. Demucs writes one 24-bit WAV
+//! per stem: vocals, drums, bass and other for the 4-stem models, guitar and
+//! piano too for `htdemucs_6s`, or a stem and everything else with
+//! `twoStems` (e.g. vocals and no_vocals, for karaoke).
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use crate::config::SeparationSettings;
+
+/// Stems `twoStems` can split out
+pub const STEMS: [&str; 6] = ["vocals", "drums", "bass", "other", "guitar", "piano"];
+
+/// Stderr lines kept when Demucs fails
+const ERROR_LINES: usize = 3;
+
+/// One stem Demucs wrote
+#[derive(Debug, Clone)]
+pub struct StemOutput {
+    /// The stem's name, e.g. "vocals" or "no_vocals"
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Check a job's model and two-stem choice
+pub fn validate(model: Option<&str>, two_stems: Option<&str>) -> Result<()> {
+    if let Some(model) = model {
+        // Passed to Demucs as a single argument and used as a directory name
+        anyhow::ensure!(
+            !model.is_empty()
+                && model
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            "Invalid separation model name: {:?}",
+            model
+        );
+    }
+    if let Some(stem) = two_stems {
+        anyhow::ensure!(
+            STEMS.contains(&stem),
+            "twoStems must be one of {}, not {:?}",
+            STEMS.join(", "),
+            stem
+        );
+    }
+    Ok(())
+}
+
+/// Separate `input` into stems under `out_dir`, in Demucs's stem order
+#[tracing::instrument(name = "dsp.separate", skip_all)]
+pub async fn separate(
+    settings: &SeparationSettings,
+    input: &Path,
+    out_dir: &Path,
+    model: &str,
+    two_stems: Option<&str>,
+) -> Result<Vec<StemOutput>> {
+    let mut command = tokio::process::Command::new(&settings.command);
+    command
+        .args(["-n", model, "-d", &settings.device])
+        .args(["--shifts", &settings.shifts.to_string()])
+        .args(["--int24", "--filename", "{stem}.{ext}"])
+        .arg("-o")
+        .arg(out_dir);
+    if let Some(stem) = two_stems {
+        command.args(["--two-stems", stem]);
+    }
+    command.arg(input).stdin(Stdio::null()).kill_on_drop(true);
+
+    let run = command.output();
+    let output = if settings.timeout_seconds > 0 {
+        tokio::time::timeout(Duration::from_secs(settings.timeout_seconds), run)
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Demucs didn't finish within {} seconds",
+                    settings.timeout_seconds
+                )
+            })?
+    } else {
+        run.await
+    }
+    .with_context(|| format!("Failed to run Demucs ({})", settings.command))?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "Demucs failed ({}): {}",
+            output.status,
+            error_summary(&output.stderr)
+        );
+    }
+
+    // Stems land in <out>/<model>/<stem>.wav
+    let stem_dir = out_dir.join(model);
+    let mut stems = Vec::new();
+    for entry in std::fs::read_dir(&stem_dir)
+        .with_context(|| format!("Demucs wrote nothing to {}", stem_dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "wav") {
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                stems.push(StemOutput {
+                    name: name.to_string(),
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+    anyhow::ensure!(!stems.is_empty(), "Demucs produced no stems");
+    stems.sort_by_key(|stem| stem_order(&stem.name));
+    Ok(stems)
+}
+
+/// Position of a stem in reports: the named stems in Demucs's order, then
+/// the rest ("no_vocals" and the like) by name
+fn stem_order(name: &str) -> (usize, String) {
+    let position = STEMS.iter().position(|s| *s == name);
+    (position.unwrap_or(STEMS.len()), name.to_string())
+}
+
+/// The last few lines of Demucs's stderr, where Python puts the error
+fn error_summary(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(ERROR_LINES)..].join(" / ")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_separate_runs_demucs_and_orders_stems() {
+        let dir = tempfile::tempdir().unwrap();
+        // Stands in for Demucs: writes the stems where `-o` and `-n` say
+        let script = dir.path().join("demucs");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             while [ $# -gt 1 ]; do\n\
+               case $1 in -n) model=$2;; -o) out=$2;; esac\n\
+               shift\n\
+             done\n\
+             mkdir -p \"$out/$model\"\n\
+             for stem in other vocals bass drums; do touch \"$out/$model/$stem.wav\"; done\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let settings = SeparationSettings {
+            command: script.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let out = dir.path().join("stems");
+        let stems = separate(&settings, Path::new("input.wav"), &out, "htdemucs", None)
+            .await
+            .unwrap();
+        let names: Vec<&str> = stems.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["vocals", "drums", "bass", "other"]);
+
+        assert!(validate(Some("htdemucs_6s"), Some("vocals")).is_ok());
+        assert!(validate(Some("../models"), None).is_err());
+        assert!(validate(None, Some("kazoo")).is_err());
+    }
+}
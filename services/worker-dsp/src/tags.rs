@@ -0,0 +1,315 @@
+//! Metadata embedding for exported deliverables
+//!
+//! - MP3: ID3v2.4 frames, artwork as an APIC frame
+//! - FLAC: Vorbis comments plus a PICTURE block
+//! - WAV: LIST/INFO and BWF `bext` chunks, with an `id3 ` chunk carrying the
+//!   ISRC and artwork, which INFO and `bext` have no field for
+//!
+//! ReplayGain goes in `REPLAYGAIN_*` TXXX frames and Vorbis comments, as
+//! foobar2000 and most players read it.
+
+use anyhow::{bail, Context, Result};
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::types::{ExportFormat, ReplayGain, TrackMetadata};
+
+/// Cover art attached to a deliverable
+#[derive(Debug, Clone)]
+pub struct Artwork {
+    pub mime_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl Artwork {
+    /// Wrap image bytes, detecting the format from its signature
+    ///
+    /// Distributors only accept JPEG and PNG cover art.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let mime_type = if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            "image/jpeg"
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            "image/png"
+        } else {
+            bail!("Unsupported artwork format (expected JPEG or PNG)");
+        };
+        Ok(Self { mime_type, data })
+    }
+}
+
+/// Normalize an ISRC to its 12-character form (e.g. "US-S1Z-99-00001")
+pub fn normalize_isrc(isrc: &str) -> Result<String> {
+    let code: String = isrc
+        .chars()
+        .filter(|c| *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let valid = code.len() == 12
+        && code.is_ascii()
+        && code[..2].chars().all(|c| c.is_ascii_uppercase())
+        && code[2..5].chars().all(|c| c.is_ascii_alphanumeric())
+        && code[5..].chars().all(|c| c.is_ascii_digit());
+    if !valid {
+        bail!("Invalid ISRC: {}", isrc);
+    }
+    Ok(code)
+}
+
+/// `REPLAYGAIN_*` tag names and values
+fn replay_gain_fields(gain: &ReplayGain) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        (
+            "REPLAYGAIN_TRACK_GAIN",
+            format!("{:.2} dB", gain.track_gain_db),
+        ),
+        ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", gain.track_peak)),
+    ];
+    if let Some(album_gain) = gain.album_gain_db {
+        fields.push(("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", album_gain)));
+    }
+    if let Some(album_peak) = gain.album_peak {
+        fields.push(("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album_peak)));
+    }
+    fields
+}
+
+/// Embed metadata into an exported file of the given format
+pub fn tag_file(
+    path: &Path,
+    format: ExportFormat,
+    metadata: &TrackMetadata,
+    artwork: Option<&Artwork>,
+) -> Result<()> {
+    match format {
+        ExportFormat::Mp3_320 => tag_mp3(path, metadata, artwork),
+        ExportFormat::Flac => tag_flac(path, metadata, artwork),
+        ExportFormat::Wav24 | ExportFormat::Wav16 => tag_wav(path, metadata, artwork),
+        // DDP carries its metadata in the PQ descriptor and CD-Text
+        ExportFormat::Ddp => Ok(()),
+    }
+}
+
+/// Build the ID3v2 tag shared by MP3 files and the WAV `id3 ` chunk
+fn id3_tag(metadata: &TrackMetadata, artwork: Option<&Artwork>) -> Result<id3::Tag> {
+    use id3::TagLike;
+
+    let mut tag = id3::Tag::new();
+    if let Some(title) = &metadata.title {
+        tag.set_title(title.as_str());
+    }
+    if let Some(artist) = &metadata.artist {
+        tag.set_artist(artist.as_str());
+    }
+    if let Some(album) = &metadata.album {
+        tag.set_album(album.as_str());
+    }
+    if let Some(track) = metadata.track_number {
+        tag.set_track(track);
+    }
+    if let Some(total) = metadata.track_total {
+        tag.set_total_tracks(total);
+    }
+    if let Some(isrc) = &metadata.isrc {
+        tag.set_text("TSRC", normalize_isrc(isrc)?);
+    }
+    if let Some(gain) = &metadata.replay_gain {
+        for (description, value) in replay_gain_fields(gain) {
+            tag.add_frame(id3::frame::ExtendedText {
+                description: description.to_string(),
+                value,
+            });
+        }
+    }
+    if let Some(artwork) = artwork {
+        tag.add_frame(id3::frame::Picture {
+            mime_type: artwork.mime_type.to_string(),
+            picture_type: id3::frame::PictureType::CoverFront,
+            description: String::new(),
+            data: artwork.data.clone(),
+        });
+    }
+    Ok(tag)
+}
+
+fn tag_mp3(path: &Path, metadata: &TrackMetadata, artwork: Option<&Artwork>) -> Result<()> {
+    id3_tag(metadata, artwork)?
+        .write_to_path(path, id3::Version::Id3v24)
+        .context("Failed to write ID3 tag")
+}
+
+fn tag_flac(path: &Path, metadata: &TrackMetadata, artwork: Option<&Artwork>) -> Result<()> {
+    let mut tag = metaflac::Tag::read_from_path(path).context("Failed to read FLAC metadata")?;
+
+    let comments = [
+        ("TITLE", metadata.title.clone()),
+        ("ARTIST", metadata.artist.clone()),
+        ("ALBUM", metadata.album.clone()),
+        ("TRACKNUMBER", metadata.track_number.map(|n| n.to_string())),
+        ("TRACKTOTAL", metadata.track_total.map(|n| n.to_string())),
+        (
+            "ISRC",
+            metadata.isrc.as_deref().map(normalize_isrc).transpose()?,
+        ),
+    ];
+    for (key, value) in comments {
+        if let Some(value) = value {
+            tag.set_vorbis(key, vec![value]);
+        }
+    }
+    if let Some(gain) = &metadata.replay_gain {
+        for (key, value) in replay_gain_fields(gain) {
+            tag.set_vorbis(key, vec![value]);
+        }
+    }
+    if let Some(artwork) = artwork {
+        tag.add_picture(
+            artwork.mime_type,
+            metaflac::block::PictureType::CoverFront,
+            artwork.data.clone(),
+        );
+    }
+
+    tag.save().context("Failed to write FLAC metadata")
+}
+
+fn tag_wav(path: &Path, metadata: &TrackMetadata, artwork: Option<&Artwork>) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .context("Failed to open WAV file for tagging")?;
+
+    let mut chunks = Vec::new();
+    write_chunk(&mut chunks, b"bext", &bext_chunk(metadata)?);
+    let info = info_chunk(metadata);
+    if !info.is_empty() {
+        let mut list = b"INFO".to_vec();
+        list.extend_from_slice(&info);
+        write_chunk(&mut chunks, b"LIST", &list);
+    }
+
+    // Chunks after `data` are valid RIFF; readers skip the ones they don't know
+    let end = file.seek(SeekFrom::End(0))?;
+    file.write_all(&chunks)?;
+    let riff_size = u32::try_from(end + chunks.len() as u64 - 8).context("WAV file too large")?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_size.to_le_bytes())?;
+    drop(file);
+
+    id3_tag(metadata, artwork)?
+        .write_to_path(path, id3::Version::Id3v24)
+        .context("Failed to write WAV ID3 chunk")
+}
+
+/// LIST/INFO sub-chunks: title, artist, album (product) and track number
+fn info_chunk(metadata: &TrackMetadata) -> Vec<u8> {
+    let fields = [
+        (b"INAM", metadata.title.clone()),
+        (b"IART", metadata.artist.clone()),
+        (b"IPRD", metadata.album.clone()),
+        (b"IPRT", metadata.track_number.map(|n| n.to_string())),
+    ];
+
+    let mut info = Vec::new();
+    for (id, value) in fields {
+        if let Some(value) = value {
+            let mut text = value.into_bytes();
+            text.push(0);
+            write_chunk(&mut info, id, &text);
+        }
+    }
+    info
+}
+
+/// BWF version 1 `bext` chunk
+///
+/// The title goes in the description, the artist in the originator and the
+/// ISRC in the originator reference, as most mastering tools do.
+fn bext_chunk(metadata: &TrackMetadata) -> Result<Vec<u8>> {
+    let isrc = metadata
+        .isrc
+        .as_deref()
+        .map(normalize_isrc)
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut bext = Vec::with_capacity(602);
+    push_fixed(&mut bext, metadata.title.as_deref().unwrap_or(""), 256);
+    push_fixed(&mut bext, metadata.artist.as_deref().unwrap_or(""), 32);
+    push_fixed(&mut bext, &isrc, 32);
+    push_fixed(&mut bext, "", 10); // OriginationDate
+    push_fixed(&mut bext, "", 8); // OriginationTime
+    bext.extend_from_slice(&0u64.to_le_bytes()); // TimeReference
+    bext.extend_from_slice(&1u16.to_le_bytes()); // Version
+    bext.resize(bext.len() + 64 + 190, 0); // UMID + reserved
+    Ok(bext)
+}
+
+/// Append `text` truncated or zero-padded to exactly `len` bytes
+fn push_fixed(out: &mut Vec<u8>, text: &str, len: usize) {
+    let bytes = text.as_bytes();
+    let n = bytes.len().min(len);
+    out.extend_from_slice(&bytes[..n]);
+    out.resize(out.len() + len - n, 0);
+}
+
+/// Append a RIFF chunk, padded to an even length
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio;
+    use crate::types::AudioBuffer;
+    use budi_worker_core::audio::read_audio_file;
+    use id3::TagLike;
+
+    #[test]
+    fn test_tagged_wav_still_decodes() {
+        let mut buffer = AudioBuffer::new(2, 48000);
+        for ch in 0..2 {
+            buffer.samples[ch] = vec![0.25; 4801];
+        }
+        let metadata = TrackMetadata {
+            title: Some("Night Drive".to_string()),
+            artist: Some("Budi".to_string()),
+            isrc: Some("us-s1z-99-00001".to_string()),
+            track_number: Some(3),
+            replay_gain: Some(ReplayGain {
+                track_gain_db: -7.5,
+                track_peak: 0.25,
+                album_gain_db: None,
+                album_peak: None,
+            }),
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tagged.wav");
+        audio::write_wav_file(&buffer, &path, 24).unwrap();
+        tag_file(&path, ExportFormat::Wav24, &metadata, None).unwrap();
+
+        let decoded = read_audio_file(&path).unwrap();
+        assert_eq!(decoded.frame_count(), 4801);
+        let tag = id3::Tag::read_from_path(&path).unwrap();
+        assert_eq!(
+            tag.get("TSRC").and_then(|f| f.content().text()),
+            Some("USS1Z9900001")
+        );
+        let gain = tag
+            .extended_texts()
+            .find(|t| t.description == "REPLAYGAIN_TRACK_GAIN")
+            .unwrap();
+        assert_eq!(gain.value, "-7.50 dB");
+        assert!(normalize_isrc("US-S1Z-99").is_err());
+    }
+}
@@ -0,0 +1,310 @@
+//! Speech-to-text through an external backend
+//!
+//! Transcription posts the audio to the server from `[transcription]`: a
+//! whisper.cpp `whisper-server`, or OpenAI's API (or a server speaking it,
+//! such as faster-whisper-server). Both take a multipart form with the audio
+//! in `file` and answer `verbose_json` with the text, its language and timed
+//! segments. The audio is sent as a compact MP3, by default folded to mono
+//! and brought to a steady speech level first, which keeps over an hour of
+//! speech under OpenAI's 25 MB upload limit.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{SttBackend, TranscriptionSettings};
+use crate::types::AudioBuffer;
+use budi_dsp_core::mastering;
+
+/// Loudness audio is brought to before it is sent (LUFS)
+pub const NORMALIZE_TARGET_LUFS: f64 = -20.0;
+
+/// Highest sample peak normalizing may raise audio to (dBFS)
+const NORMALIZE_PEAK_DB: f64 = -1.0;
+
+/// Bitrate of the MP3 sent, mono and stereo (kbps)
+pub const UPLOAD_BITRATE_MONO: u32 = 48;
+pub const UPLOAD_BITRATE_STEREO: u32 = 96;
+
+/// Response body characters kept when the backend fails
+const ERROR_CHARS: usize = 300;
+
+/// Check a job's language
+pub fn validate(language: Option<&str>) -> Result<()> {
+    if let Some(language) = language {
+        anyhow::ensure!(
+            language == "auto"
+                || ((2..=3).contains(&language.len())
+                    && language.chars().all(|c| c.is_ascii_lowercase())),
+            "language must be an ISO 639-1 code such as \"en\", or \"auto\", not {:?}",
+            language
+        );
+    }
+    Ok(())
+}
+
+/// Bring `buffer` to `NORMALIZE_TARGET_LUFS` with plain gain, as far as its
+/// peak allows, returning the gain applied (dB)
+pub fn normalize(buffer: &mut AudioBuffer) -> Result<f64> {
+    let lufs = mastering::calculate_loudness(buffer)?;
+    let peak = buffer
+        .samples
+        .iter()
+        .flatten()
+        .fold(0.0f32, |peak, s| peak.max(s.abs()));
+    // Nothing above the gate to measure
+    if lufs <= -70.0 || peak <= 0.0 {
+        return Ok(0.0);
+    }
+    let headroom = NORMALIZE_PEAK_DB - 20.0 * (peak as f64).log10();
+    let gain_db = (NORMALIZE_TARGET_LUFS - lufs).min(headroom);
+    let gain = 10f32.powf(gain_db as f32 / 20.0);
+    for channel in &mut buffer.samples {
+        for sample in channel.iter_mut() {
+            *sample *= gain;
+        }
+    }
+    Ok(gain_db)
+}
+
+/// A stretch of the transcript
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptSegment {
+    pub start_seconds: f64,
+    pub end_seconds: f64,
+    pub text: String,
+}
+
+/// What the backend heard
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transcript {
+    pub text: String,
+    /// Language the backend detected or was told, as it names it
+    pub language: Option<String>,
+    /// Timed segments; empty if the backend gave only the text
+    pub segments: Vec<TranscriptSegment>,
+}
+
+/// The `verbose_json` fields both backends answer with
+#[derive(Deserialize)]
+struct VerboseJson {
+    text: String,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    segments: Vec<VerboseSegment>,
+}
+
+#[derive(Deserialize)]
+struct VerboseSegment {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+/// Transcribe the audio file at `path` (an MP3)
+#[tracing::instrument(name = "dsp.transcribe", skip_all)]
+pub async fn transcribe(
+    settings: &TranscriptionSettings,
+    path: &Path,
+    language: Option<&str>,
+    prompt: Option<&str>,
+) -> Result<Transcript> {
+    let audio = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if settings.max_upload_mb > 0 {
+        let limit = settings.max_upload_mb * 1024 * 1024;
+        anyhow::ensure!(
+            audio.len() as u64 <= limit,
+            "Audio for transcription is {:.1} MB, over the backend's {} MB limit \
+             (transcription.max_upload_mb)",
+            audio.len() as f64 / (1024.0 * 1024.0),
+            settings.max_upload_mb
+        );
+    }
+
+    let mut fields = vec![("response_format", "verbose_json")];
+    if settings.backend == SttBackend::OpenAi {
+        fields.push(("model", settings.model.as_str()));
+        fields.push(("timestamp_granularities[]", "segment"));
+    }
+    if let Some(language) = language {
+        fields.push(("language", language));
+    }
+    if let Some(prompt) = prompt {
+        fields.push(("prompt", prompt));
+    }
+    let boundary = format!("budi-{}", uuid::Uuid::new_v4().simple());
+    let body = multipart_body(&boundary, &fields, "audio.mp3", "audio/mpeg", &audio);
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(settings.timeout_seconds))
+        .build()?;
+    let mut request = client
+        .post(settings.url())
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body);
+    if !settings.api_key.is_empty() {
+        request = request.bearer_auth(&settings.api_key);
+    }
+    let response = request.send().await.with_context(|| {
+        format!(
+            "Failed to reach the transcription backend ({})",
+            settings.url()
+        )
+    })?;
+
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "Transcription backend returned {}: {}",
+            status,
+            text.chars().take(ERROR_CHARS).collect::<String>()
+        );
+    }
+    let verbose: VerboseJson =
+        serde_json::from_str(&text).context("Unexpected transcription response")?;
+
+    Ok(Transcript {
+        text: verbose.text.trim().to_string(),
+        language: verbose.language.filter(|l| !l.is_empty()),
+        segments: verbose
+            .segments
+            .into_iter()
+            .map(|s| TranscriptSegment {
+                start_seconds: s.start,
+                end_seconds: s.end,
+                text: s.text.trim().to_string(),
+            })
+            .collect(),
+    })
+}
+
+/// A `multipart/form-data` body of text `fields` and one file
+fn multipart_body(
+    boundary: &str,
+    fields: &[(&str, &str)],
+    file_name: &str,
+    content_type: &str,
+    file: &[u8],
+) -> Vec<u8> {
+    let mut body = Vec::with_capacity(file.len() + 256 * (fields.len() + 1));
+    for (name, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                boundary, name, value
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+             Content-Type: {}\r\n\r\n",
+            boundary, file_name, content_type
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(file);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_transcribe_posts_form_and_reads_segments() {
+        // Stands in for the OpenAI API: keeps the request, answers one reply
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/v1/audio/transcriptions",
+            listener.local_addr().unwrap()
+        );
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length: usize = text
+                        .lines()
+                        .find_map(|l| {
+                            l.to_ascii_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().parse().unwrap())
+                        })
+                        .unwrap();
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+            }
+            let reply = r#"{"text":" Hello there. Welcome back.","language":"english",
+                "duration":4.2,"segments":[{"id":0,"start":0.0,"end":1.5,"text":" Hello there."},
+                            {"id":1,"start":1.5,"end":4.2,"text":" Welcome back."}]}"#;
+            socket
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        reply.len(),
+                        reply
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let audio = dir.path().join("audio.mp3");
+        std::fs::write(&audio, b"ID3 not really an mp3").unwrap();
+        let settings = TranscriptionSettings {
+            backend: SttBackend::OpenAi,
+            url,
+            api_key: "sk-test".to_string(),
+            ..Default::default()
+        };
+        let transcript = transcribe(&settings, &audio, Some("en"), None)
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap();
+        assert!(
+            request.contains("authorization: Bearer sk-test"),
+            "{}",
+            request
+        );
+        assert!(request.contains("name=\"model\"\r\n\r\nwhisper-1\r\n"));
+        assert!(request.contains("name=\"language\"\r\n\r\nen\r\n"));
+        assert!(
+            request.contains("filename=\"audio.mp3\"\r\nContent-Type: audio/mpeg\r\n\r\nID3 not")
+        );
+
+        assert_eq!(transcript.text, "Hello there. Welcome back.");
+        assert_eq!(transcript.language.as_deref(), Some("english"));
+        assert_eq!(transcript.segments.len(), 2);
+        assert_eq!(transcript.segments[1].text, "Welcome back.");
+        assert_eq!(transcript.segments[1].end_seconds, 4.2);
+
+        assert!(validate(Some("auto")).is_ok());
+        assert!(validate(Some("English")).is_err());
+    }
+}
@@ -1,6 +1,12 @@
 //! Shared type definitions for the DSP worker
 
-use serde::{Deserialize, Serialize};
+use budi_dsp_core::mastering::MasteringOptions;
+use budi_worker_core::QueueJob;
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::compliance::PlatformSpec;
+use crate::fingerprint::FingerprintEntry;
+use crate::pipeline::Stage;
 
 /// Job types matching @budi/contracts
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -14,6 +20,9 @@ pub enum Job {
         track_id: String,
         #[serde(rename = "sourceUrl")]
         source_url: String,
+        /// Expected SHA-256 of the source, checked after download
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
     },
     #[serde(rename = "fix")]
     Fix {
@@ -23,7 +32,15 @@ pub enum Job {
         track_id: String,
         #[serde(rename = "sourceUrl")]
         source_url: String,
-        modules: Vec<String>,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        modules: Vec<FixModule>,
+        /// Plugin, external or clap stages run after the fix modules
+        #[serde(default)]
+        stages: Vec<Stage>,
+        /// Report what would be done instead of doing it
+        #[serde(rename = "dryRun", default)]
+        dry_run: bool,
     },
     #[serde(rename = "master")]
     Master {
@@ -33,9 +50,304 @@ pub enum Job {
         track_id: String,
         #[serde(rename = "sourceUrl")]
         source_url: String,
-        profile: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        profile: MasterProfile,
         #[serde(rename = "loudnessTarget")]
-        loudness_target: String,
+        loudness_target: LoudnessTarget,
+        /// Optional reference track whose tonal balance and loudness to match
+        #[serde(rename = "referenceUrl", default)]
+        reference_url: Option<String>,
+        #[serde(rename = "referenceSha256", default)]
+        reference_sha256: Option<String>,
+        #[serde(rename = "eqMode", default)]
+        eq_mode: EqMode,
+        #[serde(default)]
+        precision: Precision,
+        /// Overrides the profile's final limiter
+        #[serde(rename = "limiterMode", default)]
+        limiter_mode: Option<LimiterMode>,
+        /// Minimum loudness range (LU) to preserve
+        #[serde(rename = "minLoudnessRange", default)]
+        min_loudness_range: Option<f64>,
+        /// Dither used for the 16-bit deliverable
+        #[serde(default)]
+        dither: Dither,
+        /// Dry/wet blend of the whole chain (0.0-1.0, default 1.0), at the
+        /// target loudness
+        #[serde(default, deserialize_with = "mix")]
+        mix: Option<f32>,
+        /// Gain after the chain (dB, at most 0, default 0)
+        #[serde(rename = "outputTrimDb", default, deserialize_with = "output_trim")]
+        output_trim_db: Option<f64>,
+        /// Plugin, external or clap stages run on the source before the
+        /// mastering chain
+        #[serde(default)]
+        stages: Vec<Stage>,
+        /// A clap stage run on the master after the chain, e.g. a hosted
+        /// limiter; the master is measured and QC'd after it
+        #[serde(rename = "finalStage", default)]
+        final_stage: Option<Stage>,
+        /// Report what would be done instead of doing it
+        #[serde(rename = "dryRun", default)]
+        dry_run: bool,
+    },
+    #[serde(rename = "batch-analyze")]
+    BatchAnalyze {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        /// Names the batch's report in storage, e.g. a catalog or label ID
+        #[serde(rename = "batchId")]
+        batch_id: String,
+        #[serde(rename = "trackIds")]
+        track_ids: Vec<String>,
+        /// Source URLs in the same order as `track_ids`
+        #[serde(rename = "sourceUrls")]
+        source_urls: Vec<String>,
+        /// Expected SHA-256 of each source, in the same order; may be empty
+        #[serde(rename = "sourceSha256s", default)]
+        source_sha256s: Vec<String>,
+    },
+    #[serde(rename = "compliance-check")]
+    ComplianceCheck {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Specs to check against; the major streaming services when empty
+        #[serde(default)]
+        platforms: Vec<PlatformSpec>,
+    },
+    #[serde(rename = "podcast-process")]
+    PodcastProcess {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Loudness target (default -16 LUFS, or -19 LUFS for mono)
+        #[serde(rename = "targetLufs", default)]
+        target_lufs: Option<f64>,
+        /// Corner of the rumble filter (Hz, default 80)
+        #[serde(rename = "highPassHz", default)]
+        high_pass_hz: Option<f32>,
+        /// Turn room tone down between phrases (default true)
+        #[serde(default)]
+        gate: Option<bool>,
+        /// Duck harsh sibilance (default true)
+        #[serde(rename = "deEss", default)]
+        de_ess: Option<bool>,
+        /// Fold the recording down to mono before processing
+        #[serde(default)]
+        mono: bool,
+        /// Bitrate of the MP3 (kbps, default 128)
+        #[serde(rename = "mp3Bitrate", default)]
+        mp3_bitrate: Option<u32>,
+    },
+    #[serde(rename = "acx-check")]
+    AcxCheck {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+    },
+    #[serde(rename = "segment-detect")]
+    SegmentDetect {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Level below which audio counts as silence (dB RMS, default -50)
+        #[serde(rename = "silenceThresholdDb", default)]
+        silence_threshold_db: Option<f64>,
+        /// Shortest silence that marks a boundary (seconds, default 2)
+        #[serde(rename = "minSilenceSeconds", default)]
+        min_silence_seconds: Option<f64>,
+        /// Shortest segment between boundaries (seconds, default 30)
+        #[serde(rename = "minSegmentSeconds", default)]
+        min_segment_seconds: Option<f64>,
+        /// Also mark changes in spectral shape without a silence between
+        /// (default true)
+        #[serde(rename = "detectChanges", default)]
+        detect_changes: Option<bool>,
+        /// Markers are labelled "<prefix> 1", "<prefix> 2", ... (default
+        /// "Chapter")
+        #[serde(rename = "labelPrefix", default)]
+        label_prefix: Option<String>,
+    },
+    #[serde(rename = "transcribe")]
+    Transcribe {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// ISO 639-1 code of the spoken language, or "auto" (default: the
+        /// backend detects it)
+        #[serde(default)]
+        language: Option<String>,
+        /// Text to steer spelling and style, such as names and jargon
+        #[serde(default)]
+        prompt: Option<String>,
+        /// Fold the audio down to mono before sending it (default true)
+        #[serde(default)]
+        downmix: Option<bool>,
+        /// Bring the audio to a steady speech level first (default true)
+        #[serde(default)]
+        normalize: Option<bool>,
+    },
+    #[serde(rename = "duplicate-check")]
+    DuplicateCheck {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Fingerprints to compare against
+        #[serde(default)]
+        candidates: Vec<FingerprintEntry>,
+        /// Fingerprint index in Redis to compare against as well
+        #[serde(default)]
+        index: Option<String>,
+        /// Store this track's fingerprint in `index` once compared
+        #[serde(rename = "addToIndex", default)]
+        add_to_index: bool,
+        /// Similarity from which a track is reported (0-1, default 0.6)
+        #[serde(rename = "minSimilarity", default)]
+        min_similarity: Option<f64>,
+    },
+    #[serde(rename = "restore")]
+    Restore {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// How hard to restore, from a light touch to heavy repair (0-1,
+        /// default 0.5)
+        #[serde(default)]
+        strength: Option<f64>,
+    },
+    #[serde(rename = "karaoke")]
+    Karaoke {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Share of the center taken out of the instrumental (0-1, default
+        /// 1); less leaves some vocal as a guide
+        #[serde(default)]
+        suppression: Option<f64>,
+    },
+    #[serde(rename = "time-pitch")]
+    TimePitch {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Speed relative to the source, keeping pitch (0.5-2, e.g. 0.95
+        /// for 95%; default 1)
+        #[serde(default)]
+        tempo: Option<f64>,
+        /// Pitch shift, keeping tempo (-12 to 12, fractions for cents;
+        /// default 0)
+        #[serde(default)]
+        semitones: Option<f64>,
+    },
+    #[serde(rename = "vocal-balance")]
+    VocalBalance {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        /// A stereo mix
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Vocal-to-backing balance below which the vocal is flagged as
+        /// buried (dB, default -8)
+        #[serde(rename = "buriedDb", default)]
+        buried_db: Option<f64>,
+        /// Balance above which it is flagged as overpowering (dB, default 4)
+        #[serde(rename = "overpoweringDb", default)]
+        overpowering_db: Option<f64>,
+    },
+    #[serde(rename = "bounce")]
+    Bounce {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        /// Track the mix (and its master) is stored under
+        #[serde(rename = "trackId")]
+        track_id: String,
+        stems: Vec<BounceStem>,
+        /// Sample rate of the mix (default: the first unmuted stem's)
+        #[serde(rename = "sampleRate", default)]
+        sample_rate: Option<u32>,
+        /// Master the mix as a master job would
+        #[serde(default)]
+        master: Option<BounceMaster>,
+    },
+    #[serde(rename = "revision-report")]
+    RevisionReport {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        /// Revisions of the track's master, oldest first
+        revisions: Vec<MasterRevision>,
+    },
+    #[serde(rename = "replay-gain")]
+    ReplayGain {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        /// Tracks of one album, in order; album gain covers them all
+        tracks: Vec<ReplayGainTrack>,
+    },
+    #[serde(rename = "pipeline")]
+    Pipeline {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Stages run in order on the decoded source
+        stages: Vec<Stage>,
     },
     #[serde(rename = "album-master")]
     AlbumMaster {
@@ -45,11 +357,29 @@ pub enum Job {
         project_id: String,
         #[serde(rename = "trackIds")]
         track_ids: Vec<String>,
-        profile: String,
+        profile: MasterProfile,
         #[serde(rename = "loudnessTarget")]
-        loudness_target: String,
+        loudness_target: LoudnessTarget,
         #[serde(rename = "normalizeLoudness")]
         normalize_loudness: bool,
+        /// Source URLs in the same order as `track_ids`
+        #[serde(rename = "sourceUrls", default)]
+        source_urls: Vec<String>,
+        /// Expected SHA-256 of each source, in the same order; may be empty
+        #[serde(rename = "sourceSha256s", default)]
+        source_sha256s: Vec<String>,
+        /// Silence after each track in the continuous render (seconds)
+        #[serde(rename = "gapSeconds", default)]
+        gap_seconds: Vec<f64>,
+        /// Crossfade into the next track (seconds); replaces the gap when set
+        #[serde(rename = "crossfadeSeconds", default)]
+        crossfade_seconds: Vec<f64>,
+        /// Also render the whole album as one continuous file
+        #[serde(rename = "renderContinuous", default)]
+        render_continuous: bool,
+        /// Report what would be done instead of doing it
+        #[serde(rename = "dryRun", default)]
+        dry_run: bool,
     },
     #[serde(rename = "export")]
     Export {
@@ -57,136 +387,521 @@ pub enum Job {
         job_id: String,
         #[serde(rename = "projectId")]
         project_id: String,
-        formats: Vec<String>,
+        formats: Vec<ExportFormat>,
         #[serde(rename = "includeQc")]
         include_qc: bool,
+        /// Final masters to export
+        #[serde(default)]
+        tracks: Vec<ExportTrack>,
+        /// FLAC compression level (0-8, default 5)
+        #[serde(rename = "flacCompressionLevel", default)]
+        flac_compression_level: Option<u8>,
+        /// Sample rates to deliver at (44100, 48000, 88200 or 96000 Hz)
+        #[serde(rename = "sampleRates", default)]
+        sample_rates: Vec<u32>,
+        /// Disc-level metadata for the DDP image
+        #[serde(default)]
+        disc: DiscMetadata,
+    },
+    #[serde(rename = "waveform-peaks")]
+    WaveformPeaks {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Source frames per point of each zoom level (default 256, 1024,
+        /// 4096 and 16384)
+        #[serde(rename = "samplesPerPixel", default)]
+        samples_per_pixel: Vec<u32>,
+        /// Resolution of the points, 8 or 16 bits (default 8)
+        #[serde(default)]
+        bits: Option<u8>,
+    },
+    #[serde(rename = "spectrogram")]
+    Spectrogram {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Image sizes to render (default 1024x256 and 4096x512)
+        #[serde(default)]
+        resolutions: Vec<SpectrogramResolution>,
+        #[serde(default)]
+        scale: SpectrogramScale,
+        #[serde(rename = "colorMap", default)]
+        color_map: ColorMap,
+    },
+    #[serde(rename = "stem-separation")]
+    StemSeparation {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        #[serde(rename = "sourceUrl")]
+        source_url: String,
+        #[serde(rename = "sourceSha256", default)]
+        source_sha256: Option<String>,
+        /// Demucs model, e.g. "htdemucs_ft" (default from `separation.model`)
+        #[serde(default)]
+        model: Option<String>,
+        /// Split out just this stem and everything else, e.g. "vocals"
+        #[serde(rename = "twoStems", default)]
+        two_stems: Option<String>,
+    },
+    #[serde(rename = "compare")]
+    Compare {
+        #[serde(rename = "jobId")]
+        job_id: String,
+        #[serde(rename = "trackId")]
+        track_id: String,
+        /// The earlier render, e.g. master v1
+        #[serde(rename = "referenceUrl")]
+        reference_url: String,
+        #[serde(rename = "referenceSha256", default)]
+        reference_sha256: Option<String>,
+        /// The render under review, e.g. master v2
+        #[serde(rename = "candidateUrl")]
+        candidate_url: String,
+        #[serde(rename = "candidateSha256", default)]
+        candidate_sha256: Option<String>,
+        /// Longest lead-in difference to align across (seconds, default 1)
+        #[serde(rename = "maxOffsetSeconds", default)]
+        max_offset_seconds: Option<f64>,
+        /// Also upload the aligned difference (candidate minus reference)
+        #[serde(rename = "renderDifference", default)]
+        render_difference: bool,
     },
 }
 
-impl Job {
-    pub fn job_id(&self) -> &str {
+impl QueueJob for Job {
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn job_id(&self) -> &str {
         match self {
             Job::Analyze { job_id, .. } => job_id,
             Job::Fix { job_id, .. } => job_id,
             Job::Master { job_id, .. } => job_id,
+            Job::BatchAnalyze { job_id, .. } => job_id,
+            Job::ComplianceCheck { job_id, .. } => job_id,
+            Job::PodcastProcess { job_id, .. } => job_id,
+            Job::AcxCheck { job_id, .. } => job_id,
+            Job::SegmentDetect { job_id, .. } => job_id,
+            Job::Transcribe { job_id, .. } => job_id,
+            Job::DuplicateCheck { job_id, .. } => job_id,
+            Job::Restore { job_id, .. } => job_id,
+            Job::Karaoke { job_id, .. } => job_id,
+            Job::TimePitch { job_id, .. } => job_id,
+            Job::VocalBalance { job_id, .. } => job_id,
+            Job::Bounce { job_id, .. } => job_id,
+            Job::RevisionReport { job_id, .. } => job_id,
+            Job::ReplayGain { job_id, .. } => job_id,
+            Job::Pipeline { job_id, .. } => job_id,
             Job::AlbumMaster { job_id, .. } => job_id,
             Job::Export { job_id, .. } => job_id,
+            Job::WaveformPeaks { job_id, .. } => job_id,
+            Job::Spectrogram { job_id, .. } => job_id,
+            Job::StemSeparation { job_id, .. } => job_id,
+            Job::Compare { job_id, .. } => job_id,
         }
     }
-}
-
-/// Audio buffer for processing
-#[derive(Debug, Clone)]
-pub struct AudioBuffer {
-    pub samples: Vec<Vec<f32>>, // Channel-interleaved samples
-    pub sample_rate: u32,
-    pub channels: usize,
-}
 
-impl AudioBuffer {
-    pub fn new(channels: usize, sample_rate: u32) -> Self {
-        Self {
-            samples: vec![Vec::new(); channels],
-            sample_rate,
-            channels,
+    fn job_type(&self) -> &'static str {
+        match self {
+            Job::Analyze { .. } => "analysis",
+            Job::Fix { .. } => "fix",
+            Job::Master { .. } => "master",
+            Job::BatchAnalyze { .. } => "batch-analyze",
+            Job::ComplianceCheck { .. } => "compliance-check",
+            Job::PodcastProcess { .. } => "podcast-process",
+            Job::AcxCheck { .. } => "acx-check",
+            Job::SegmentDetect { .. } => "segment-detect",
+            Job::Transcribe { .. } => "transcribe",
+            Job::DuplicateCheck { .. } => "duplicate-check",
+            Job::Restore { .. } => "restore",
+            Job::Karaoke { .. } => "karaoke",
+            Job::TimePitch { .. } => "time-pitch",
+            Job::VocalBalance { .. } => "vocal-balance",
+            Job::Bounce { .. } => "bounce",
+            Job::RevisionReport { .. } => "revision-report",
+            Job::ReplayGain { .. } => "replay-gain",
+            Job::Pipeline { .. } => "pipeline",
+            Job::AlbumMaster { .. } => "album-master",
+            Job::Export { .. } => "export",
+            Job::WaveformPeaks { .. } => "waveform-peaks",
+            Job::Spectrogram { .. } => "spectrogram",
+            Job::StemSeparation { .. } => "stem-separation",
+            Job::Compare { .. } => "compare",
         }
     }
 
-    pub fn duration_secs(&self) -> f64 {
-        if self.samples.is_empty() || self.samples[0].is_empty() {
-            return 0.0;
+    fn endpoint_for(payload_type: &str) -> Option<&'static str> {
+        match payload_type {
+            "analyze" => Some("analysis"),
+            "fix" => Some("fix"),
+            "master" => Some("master"),
+            "batch-analyze" => Some("batch-analyze"),
+            "compliance-check" => Some("compliance-check"),
+            "podcast-process" => Some("podcast-process"),
+            "acx-check" => Some("acx-check"),
+            "segment-detect" => Some("segment-detect"),
+            "transcribe" => Some("transcribe"),
+            "duplicate-check" => Some("duplicate-check"),
+            "restore" => Some("restore"),
+            "karaoke" => Some("karaoke"),
+            "time-pitch" => Some("time-pitch"),
+            "vocal-balance" => Some("vocal-balance"),
+            "bounce" => Some("bounce"),
+            "revision-report" => Some("revision-report"),
+            "replay-gain" => Some("replay-gain"),
+            "pipeline" => Some("pipeline"),
+            "album-master" => Some("album-master"),
+            "export" => Some("export"),
+            "waveform-peaks" => Some("waveform-peaks"),
+            "spectrogram" => Some("spectrogram"),
+            "stem-separation" => Some("stem-separation"),
+            "compare" => Some("compare"),
+            _ => None,
         }
-        self.samples[0].len() as f64 / self.sample_rate as f64
     }
+}
+
+/// A master job's `mix`, rejected with the payload unless between 0 and 1
+fn mix<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let mix = Option::<f32>::deserialize(deserializer)?;
+    if let Some(mix) = mix {
+        let options = MasteringOptions {
+            mix,
+            ..Default::default()
+        };
+        options.validate().map_err(de::Error::custom)?;
+    }
+    Ok(mix)
+}
+
+/// A master job's `outputTrimDb`, rejected with the payload if positive
+fn output_trim<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let trim = Option::<f64>::deserialize(deserializer)?;
+    if let Some(output_trim_db) = trim {
+        let options = MasteringOptions {
+            output_trim_db,
+            ..Default::default()
+        };
+        options.validate().map_err(de::Error::custom)?;
+    }
+    Ok(trim)
+}
+
+/// Deliverable format of an export job
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum ExportFormat {
+    #[serde(rename = "wav-24")]
+    Wav24,
+    #[serde(rename = "wav-16")]
+    Wav16,
+    #[serde(rename = "mp3-320")]
+    Mp3_320,
+    #[serde(rename = "flac")]
+    Flac,
+    /// DDP 2.0 fileset of the whole project for CD replication
+    #[serde(rename = "ddp")]
+    Ddp,
+}
 
-    pub fn frame_count(&self) -> usize {
-        if self.samples.is_empty() {
-            0
-        } else {
-            self.samples[0].len()
+impl ExportFormat {
+    /// File name suffix and MIME type of the deliverable
+    pub fn file_info(&self) -> (&'static str, &'static str) {
+        match self {
+            ExportFormat::Wav24 => ("master_24bit.wav", "audio/wav"),
+            ExportFormat::Wav16 => ("master_16bit.wav", "audio/wav"),
+            ExportFormat::Mp3_320 => ("master.mp3", "audio/mpeg"),
+            ExportFormat::Flac => ("master.flac", "audio/flac"),
+            ExportFormat::Ddp => ("ddp.zip", "application/zip"),
         }
     }
 }
 
-/// Analysis results
+/// A mastered track included in an export
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportTrack {
+    pub track_id: String,
+    /// URL of the final 24-bit master
+    pub master_url: String,
+    /// Expected SHA-256 of the master, checked after download
+    #[serde(default)]
+    pub master_sha256: Option<String>,
+    #[serde(default)]
+    pub qc_report_url: Option<String>,
+    /// Rendered PDF QC report, packaged alongside the JSON one
+    #[serde(default)]
+    pub qc_pdf_url: Option<String>,
+    /// Tags embedded into every deliverable of the track
+    #[serde(default)]
+    pub metadata: TrackMetadata,
+    /// Pause before the track on CD (seconds, default 2)
+    #[serde(default)]
+    pub pause_seconds: Option<f64>,
+}
+
+/// One stem of a bounce job
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BounceStem {
+    /// Shown in the report; defaults to the stem's position
+    #[serde(default)]
+    pub name: Option<String>,
+    pub source_url: String,
+    #[serde(default)]
+    pub source_sha256: Option<String>,
+    #[serde(default)]
+    pub gain_db: f64,
+    /// -1 (left) to 1 (right)
+    #[serde(default)]
+    pub pan: f64,
+    /// Left out of the mix, and not downloaded
+    #[serde(default)]
+    pub mute: bool,
+}
+
+/// One revision of a master in a revision report
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MasterRevision {
+    /// e.g. "v3"
+    pub label: String,
+    pub source_url: String,
+    #[serde(default)]
+    pub source_sha256: Option<String>,
+}
+
+/// Mastering applied to a bounced mix
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BounceMaster {
+    pub profile: MasterProfile,
+    pub loudness_target: LoudnessTarget,
+    /// Dither used for the 16-bit deliverable
+    #[serde(default)]
+    pub dither: Dither,
+}
+
+/// Disc-level metadata used for CD replication masters
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscMetadata {
+    /// UPC/EAN barcode (12 or 13 digits)
+    pub upc: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+/// Distribution metadata for a track
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub isrc: Option<String>,
+    /// Cover art (JPEG or PNG)
+    pub artwork_url: Option<String>,
+    /// ReplayGain tags, as a replay-gain job reports them
+    pub replay_gain: Option<ReplayGain>,
+}
+
+/// ReplayGain 2.0 values of a track
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayGain {
+    pub track_gain_db: f64,
+    /// Highest sample, linear (1.0 is full scale)
+    pub track_peak: f64,
+    #[serde(default)]
+    pub album_gain_db: Option<f64>,
+    #[serde(default)]
+    pub album_peak: Option<f64>,
+}
+
+/// One file of a replay-gain job
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayGainTrack {
+    pub track_id: String,
+    pub source_url: String,
+    #[serde(default)]
+    pub source_sha256: Option<String>,
+}
+
+/// One exported file
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportFile {
+    /// `None` for disc-level deliverables such as the DDP image
+    pub track_id: Option<String>,
+    pub format: ExportFormat,
+    pub filename: String,
+    pub sample_rate: u32,
+    pub url: String,
+    pub key: String,
+    pub sha256: String,
+}
+
+/// One zoom level of a waveform-peaks job, as a `.dat` file
 #[derive(Debug, Clone, Serialize)]
-pub struct AnalysisResult {
+#[serde(rename_all = "camelCase")]
+pub struct WaveformFile {
+    pub samples_per_pixel: u32,
+    /// Points per channel
+    pub length: usize,
+    pub url: String,
+    pub key: String,
+    pub sha256: String,
+}
+
+/// Size of one spectrogram image, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SpectrogramResolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Frequency axis of a spectrogram
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpectrogramScale {
+    /// Rows evenly spaced in mels, giving the low end most of the height
+    #[default]
+    Mel,
+    /// Rows evenly spaced in Hz
+    Linear,
+}
+
+/// Colours of a spectrogram, from the floor to full scale
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMap {
+    /// Black through brand indigo, magenta and amber to white, as in the QC
+    /// report
+    #[default]
+    Heat,
+    Viridis,
+    Magma,
+    Grayscale,
+}
+
+/// One image of a spectrogram job
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpectrogramImage {
+    pub width: u32,
+    pub height: u32,
+    pub url: String,
+    pub key: String,
+    pub sha256: String,
+}
+
+/// One stem of a stem-separation job, with its loudness
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StemFile {
+    /// e.g. "vocals", "drums" or "no_vocals"
+    pub name: String,
+    pub url: String,
+    pub key: String,
+    pub sha256: String,
     pub integrated_lufs: f64,
     pub loudness_range: f64,
-    pub short_term_max: f64,
-    pub momentary_max: f64,
-    pub sample_peak: f64,
     pub true_peak: f64,
-    pub spectral_centroid: Option<f64>,
-    pub spectral_rolloff: Option<f64>,
-    pub stereo_correlation: Option<f64>,
-    pub stereo_width: Option<f64>,
-    pub has_clipping: bool,
-    pub has_dc_offset: bool,
-    pub dc_offset_value: Option<f64>,
-    pub clipped_samples: usize,
-    pub sample_rate: u32,
-    pub bit_depth: u32,
-    pub channels: usize,
-    pub duration_secs: f64,
+    pub sample_peak: f64,
 }
 
-/// Fix operation result
+pub use budi_dsp_core::types::{
+    AnalysisResult, Dither, EqMode, FixChange, FixModule, LimiterMode, Loudness, LoudnessTarget,
+    MasterProfile, PartialAnalysis, Precision,
+};
+pub use budi_dsp_core::AudioBuffer;
+
+/// Deliverables and measurements for one track of an album master
 #[derive(Debug, Clone, Serialize)]
-pub struct FixChange {
-    pub module: String,
-    pub description: String,
-}
-
-/// Mastering profile
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum MasterProfile {
-    Balanced,
-    Warm,
-    Punchy,
-    Custom,
-}
-
-impl From<&str> for MasterProfile {
-    fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "warm" => Self::Warm,
-            "punchy" => Self::Punchy,
-            "custom" => Self::Custom,
-            _ => Self::Balanced,
-        }
-    }
+#[serde(rename_all = "camelCase")]
+pub struct AlbumTrackResult {
+    pub track_id: String,
+    pub wav_hd_url: String,
+    pub wav_hd_key: String,
+    pub wav_hd_sha256: String,
+    pub wav16_url: String,
+    pub wav16_key: String,
+    pub wav16_sha256: String,
+    pub mp3_preview_url: String,
+    pub mp3_preview_key: String,
+    pub mp3_preview_sha256: String,
+    pub final_lufs: f64,
+    pub final_true_peak: f64,
+    pub passes_qc: bool,
+    /// Whether the final loudness is within `qc.loudness_tolerance` of the
+    /// target
+    pub passes_loudness: bool,
+    /// True-peak ceiling the master was checked against (dBTP)
+    pub true_peak_max: f64,
+    pub qc_report_url: Option<String>,
+    pub qc_report_key: Option<String>,
+    pub qc_report_sha256: Option<String>,
+    pub qc_pdf_url: Option<String>,
+    pub qc_pdf_key: Option<String>,
+    pub qc_pdf_sha256: Option<String>,
+    /// SHA-256 of the source as downloaded
+    pub source_sha256: String,
 }
 
-/// Loudness target
-#[derive(Debug, Clone, Copy)]
-pub enum LoudnessTarget {
-    Low,    // -14 LUFS
-    Medium, // -11 LUFS
-    High,   // -8 LUFS
+/// Continuous album render uploaded alongside the individual masters
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumRenderResult {
+    pub url: String,
+    pub key: String,
+    pub sha256: String,
+    /// Start time of each track in the render (seconds)
+    pub track_offsets: Vec<f64>,
+    pub duration_secs: f64,
 }
 
-impl LoudnessTarget {
-    pub fn lufs_value(&self) -> f64 {
-        match self {
-            Self::Low => -14.0,
-            Self::Medium => -11.0,
-            Self::High => -8.0,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master(options: &str) -> serde_json::Result<Job> {
+        serde_json::from_str(&format!(
+            r#"{{"type": "master", "jobId": "j", "trackId": "t", "sourceUrl": "s",
+                "profile": "balanced", "loudnessTarget": "medium"{}}}"#,
+            options
+        ))
     }
-}
 
-impl From<&str> for LoudnessTarget {
-    fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "low" => Self::Low,
-            "high" => Self::High,
-            _ => Self::Medium,
-        }
+    #[test]
+    fn test_master_rejects_bad_mix_and_trim() {
+        master(r#", "mix": 0.5, "outputTrimDb": -2"#).unwrap();
+        let err = master(r#", "mix": 1.2"#).unwrap_err();
+        assert!(
+            err.to_string().contains("mix must be between 0 and 1"),
+            "{}",
+            err
+        );
+        let err = master(r#", "outputTrimDb": 3"#).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("outputTrimDb must be at most 0 dB"),
+            "{}",
+            err
+        );
     }
 }
-
-/// QC thresholds
-pub const QC_TRUE_PEAK_MAX: f64 = -2.0; // dBTP
-#[allow(dead_code)]
-pub const QC_LOUDNESS_TOLERANCE: f64 = 1.0; // LU
@@ -0,0 +1,288 @@
+//! Multi-resolution waveform peaks for drawing zoomable waveforms
+//!
+//! Each zoom level reduces every `samples_per_pixel` frames of each channel
+//! to one point holding their minimum, maximum and RMS. Levels are written
+//! as audiowaveform's binary `.dat` format (version 2: min/max per channel),
+//! which peaks.js and similar players read directly, and all together as
+//! one JSON document that adds the RMS. Like analysis, peaks are gathered as
+//! the source is decoded, so only the points are held in memory.
+
+use anyhow::Result;
+use budi_worker_core::audio::AudioDecoder;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::types::AudioBuffer;
+
+/// Zoom levels used when a job doesn't give any (source frames per point)
+pub const DEFAULT_SAMPLES_PER_PIXEL: [u32; 4] = [256, 1024, 4096, 16384];
+
+/// Finest zoom level allowed, which bounds the points held per source
+pub const MIN_SAMPLES_PER_PIXEL: u32 = 32;
+
+/// Most zoom levels a job may ask for
+pub const MAX_LEVELS: usize = 8;
+
+/// One point of one channel
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    min: f32,
+    max: f32,
+    sum_squares: f64,
+}
+
+impl Point {
+    const EMPTY: Self = Self {
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+        sum_squares: 0.0,
+    };
+}
+
+/// Peaks of a whole source at one zoom level
+#[derive(Debug, Clone)]
+pub struct WaveformLevel {
+    pub samples_per_pixel: u32,
+    /// Frames in the last point, which may cover fewer than the rest
+    last_frames: usize,
+    channels: usize,
+    /// Point-major, channels interleaved
+    points: Vec<Point>,
+}
+
+impl WaveformLevel {
+    fn new(samples_per_pixel: u32, channels: usize) -> Self {
+        Self {
+            samples_per_pixel,
+            last_frames: 0,
+            channels,
+            points: Vec::new(),
+        }
+    }
+
+    /// Number of points per channel
+    pub fn length(&self) -> usize {
+        self.points.len() / self.channels.max(1)
+    }
+
+    fn add(&mut self, planes: &[Vec<f32>]) {
+        let frames = planes.first().map_or(0, Vec::len);
+        let spp = self.samples_per_pixel as usize;
+        let mut start = 0;
+        while start < frames {
+            if self.last_frames == 0 || self.last_frames == spp {
+                self.points
+                    .extend(std::iter::repeat_n(Point::EMPTY, self.channels));
+                self.last_frames = 0;
+            }
+            let end = (start + spp - self.last_frames).min(frames);
+            let points = self.points.len() - self.channels;
+            for (point, plane) in self.points[points..].iter_mut().zip(planes) {
+                for &sample in &plane[start..end] {
+                    point.min = point.min.min(sample);
+                    point.max = point.max.max(sample);
+                    point.sum_squares += (sample as f64) * (sample as f64);
+                }
+            }
+            self.last_frames += end - start;
+            start = end;
+        }
+    }
+
+    /// Frames covered by point `index`
+    fn point_frames(&self, index: usize) -> usize {
+        if index + 1 == self.length() {
+            self.last_frames
+        } else {
+            self.samples_per_pixel as usize
+        }
+    }
+
+    /// The level in audiowaveform's `.dat` format, version 2, at 8 or 16 bits
+    pub fn to_dat(&self, sample_rate: u32, bits: u8) -> Vec<u8> {
+        let width = if bits == 8 { 1 } else { 2 };
+        let mut dat = Vec::with_capacity(24 + self.points.len() * 2 * width);
+        dat.extend_from_slice(&2i32.to_le_bytes());
+        // Flags: bit 0 set for 8-bit points
+        dat.extend_from_slice(&u32::from(bits == 8).to_le_bytes());
+        dat.extend_from_slice(&(sample_rate as i32).to_le_bytes());
+        dat.extend_from_slice(&(self.samples_per_pixel as i32).to_le_bytes());
+        dat.extend_from_slice(&(self.length() as u32).to_le_bytes());
+        dat.extend_from_slice(&(self.channels as i32).to_le_bytes());
+        for point in &self.points {
+            for value in [point.min, point.max] {
+                if bits == 8 {
+                    dat.push(quantize(value, 8) as i8 as u8);
+                } else {
+                    dat.extend_from_slice(&(quantize(value, 16) as i16).to_le_bytes());
+                }
+            }
+        }
+        dat
+    }
+
+    fn to_json(&self, bits: u8) -> LevelJson {
+        let mut data = Vec::with_capacity(self.points.len() * 2);
+        let mut rms = Vec::with_capacity(self.points.len());
+        for (i, point) in self.points.iter().enumerate() {
+            data.push(quantize(point.min, bits));
+            data.push(quantize(point.max, bits));
+            let frames = self.point_frames(i / self.channels.max(1)).max(1);
+            let value = (point.sum_squares / frames as f64).sqrt() as f32;
+            rms.push(quantize(value, bits));
+        }
+        LevelJson {
+            samples_per_pixel: self.samples_per_pixel,
+            length: self.length(),
+            data,
+            rms,
+        }
+    }
+}
+
+/// A sample scaled to a signed integer of `bits`, clamped to its range
+fn quantize(value: f32, bits: u8) -> i32 {
+    let full_scale = ((1i32 << (bits - 1)) - 1) as f32;
+    let min = -(1i32 << (bits - 1));
+    ((value * full_scale).round() as i32).clamp(min, full_scale as i32)
+}
+
+/// All levels of a source, in the layout of audiowaveform's JSON output
+/// (`data` is min/max pairs per point, channels interleaved), plus `rms`
+/// with one value per point and channel
+#[derive(Debug, Serialize)]
+pub struct WaveformJson {
+    pub version: u32,
+    pub channels: usize,
+    pub sample_rate: u32,
+    pub bits: u8,
+    pub levels: Vec<LevelJson>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LevelJson {
+    pub samples_per_pixel: u32,
+    pub length: usize,
+    pub data: Vec<i32>,
+    pub rms: Vec<i32>,
+}
+
+/// Peaks of every level of a source
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_secs: f64,
+    pub levels: Vec<WaveformLevel>,
+}
+
+impl Waveform {
+    pub fn to_json(&self, bits: u8) -> WaveformJson {
+        WaveformJson {
+            version: 2,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits,
+            levels: self.levels.iter().map(|l| l.to_json(bits)).collect(),
+        }
+    }
+}
+
+/// Check a job's zoom levels and bit depth, filling in the defaults
+pub fn validate(samples_per_pixel: &[u32], bits: Option<u8>) -> Result<(Vec<u32>, u8)> {
+    let mut levels = if samples_per_pixel.is_empty() {
+        DEFAULT_SAMPLES_PER_PIXEL.to_vec()
+    } else {
+        samples_per_pixel.to_vec()
+    };
+    levels.sort_unstable();
+    levels.dedup();
+    anyhow::ensure!(
+        levels.len() <= MAX_LEVELS,
+        "At most {} waveform zoom levels can be generated",
+        MAX_LEVELS
+    );
+    if let Some(&finest) = levels.first() {
+        anyhow::ensure!(
+            finest >= MIN_SAMPLES_PER_PIXEL,
+            "Waveform zoom levels must be at least {} samples per pixel",
+            MIN_SAMPLES_PER_PIXEL
+        );
+    }
+    let bits = bits.unwrap_or(8);
+    anyhow::ensure!(
+        bits == 8 || bits == 16,
+        "Waveform bits must be 8 or 16, not {}",
+        bits
+    );
+    Ok((levels, bits))
+}
+
+/// Gather the peaks of `path` at each of `samples_per_pixel`
+#[tracing::instrument(name = "dsp.waveform", skip_all)]
+pub fn waveform_file(path: &Path, samples_per_pixel: &[u32]) -> Result<Waveform> {
+    let mut decoder = AudioDecoder::open(path)?;
+    let mut levels: Vec<WaveformLevel> = samples_per_pixel
+        .iter()
+        .map(|&spp| WaveformLevel::new(spp, decoder.channels))
+        .collect();
+
+    let mut block = AudioBuffer::new(decoder.channels, decoder.sample_rate);
+    let mut frames = 0;
+    while decoder.decode_next(&mut block)? {
+        frames += block.frame_count();
+        for level in &mut levels {
+            level.add(&block.samples);
+        }
+        for plane in &mut block.samples {
+            plane.clear();
+        }
+    }
+
+    Ok(Waveform {
+        sample_rate: decoder.sample_rate,
+        channels: decoder.channels,
+        duration_secs: frames as f64 / decoder.sample_rate as f64,
+        levels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_points_span_blocks_and_encode() {
+        // A ramp from -1 to 1 in the left channel, silence in the right,
+        // fed in blocks that don't line up with the points
+        let frames = 1000;
+        let left: Vec<f32> = (0..frames)
+            .map(|i| -1.0 + 2.0 * i as f32 / (frames - 1) as f32)
+            .collect();
+        let right = vec![0.0; frames];
+        let mut level = WaveformLevel::new(300, 2);
+        for start in (0..frames).step_by(77) {
+            let end = (start + 77).min(frames);
+            level.add(&[left[start..end].to_vec(), right[start..end].to_vec()]);
+        }
+
+        assert_eq!(level.length(), 4);
+        assert_eq!(level.last_frames, 100);
+        assert_eq!(level.points[0].min, -1.0);
+        assert_eq!(level.points[6].max, 1.0);
+        assert_eq!(level.points[7].max, 0.0);
+
+        let json = level.to_json(8);
+        assert_eq!(&json.data[..4], &[-127, quantize(left[299], 8), 0, 0]);
+        assert_eq!(json.rms.len(), 8);
+
+        let dat = level.to_dat(48000, 8);
+        assert_eq!(&dat[..4], &2i32.to_le_bytes());
+        assert_eq!(&dat[16..20], &4u32.to_le_bytes());
+        assert_eq!(dat.len(), 24 + 4 * 2 * 2);
+        assert_eq!(dat[24] as i8, -127);
+
+        assert!(validate(&[], None).is_ok());
+        assert!(validate(&[16], None).is_err());
+        assert!(validate(&[256], Some(12)).is_err());
+    }
+}
@@ -1,286 +1,1345 @@
-//! Webhook client for API callbacks
+//! Webhook reporting for DSP job results
+//!
+//! Transport lives in `budi_worker_core::webhook`; this adds the DSP job
+//! payloads on top. Analysis, fix and master results carry a
+//! `schemaVersion`, and their JSON Schemas, generated from the `*Data`
+//! types here, are published in `packages/contracts/schemas`.
 
 use anyhow::Result;
-use reqwest::Client;
+use budi_worker_core::config::Config;
+use budi_worker_core::result_schema;
+use budi_worker_core::schema::{JsonSchema, ResultSchema};
+use budi_worker_core::{Storage, Uploaded};
 use serde::Serialize;
 
-use crate::types::{AnalysisResult, FixChange};
+use crate::acx::AcxReport;
+use crate::album::AlbumStats;
+use crate::balance::VocalBalance;
+use crate::batch::{BatchTrack, CatalogStats};
+use crate::bounce::BounceSummary;
+use crate::compare::Comparison;
+use crate::compliance::{Measurement, PlatformResult};
+use crate::fingerprint::DuplicateMatch;
+use crate::karaoke::Extraction;
+use crate::pipeline::StageReport;
+use crate::podcast::PodcastResult;
+use crate::replaygain;
+use crate::restore::RestoreResult;
+use crate::revisions::{RevisionChange, RevisionMeasurement};
+use crate::segments::Segmentation;
+use crate::spectrogram::Spectrogram;
+use crate::transcribe::Transcript;
+use crate::types::{
+    AlbumRenderResult, AlbumTrackResult, AnalysisResult, ColorMap, ExportFile, FixChange, Loudness,
+    PartialAnalysis, ReplayGain, ReplayGainTrack, SpectrogramImage, SpectrogramScale, StemFile,
+    WaveformFile,
+};
+use crate::waveform::Waveform;
+
+result_schema! {
+    /// `data` of an analysis result
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct AnalysisData {
+        integrated_lufs: f64,
+        loudness_range: f64,
+        short_term_max: f64,
+        momentary_max: f64,
+        sample_peak: f64,
+        true_peak: f64,
+        spectral_centroid: Option<f64>,
+        spectral_rolloff: Option<f64>,
+        stereo_correlation: Option<f64>,
+        stereo_width: Option<f64>,
+        has_clipping: bool,
+        has_dc_offset: bool,
+        dc_offset_value: Option<f64>,
+        clipped_samples: usize,
+        sample_rate: u32,
+        bit_depth: u32,
+        channels: usize,
+        duration_secs: f64,
+        source_sha256: String,
+        report_url: Option<String>,
+        report_key: Option<String>,
+        report_sha256: Option<String>,
+    }
+}
+
+result_schema! {
+    /// `data` of a fix result
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct FixData {
+        source_sha256: String,
+        fixed_url: String,
+        fixed_key: String,
+        fixed_sha256: String,
+        applied_modules: Vec<String>,
+        changes: Vec<ChangeEntry>,
+    }
+}
+
+result_schema! {
+    #[derive(Serialize)]
+    struct ChangeEntry {
+        module: String,
+        description: String,
+    }
+}
+
+result_schema! {
+    /// `data` of a master result
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct MasterData<'a> {
+        wav_hd_url: &'a str,
+        wav_hd_key: &'a str,
+        wav_hd_sha256: &'a str,
+        wav16_url: &'a str,
+        wav16_key: &'a str,
+        wav16_sha256: &'a str,
+        mp3_preview_url: &'a str,
+        mp3_preview_key: &'a str,
+        mp3_preview_sha256: &'a str,
+        final_lufs: f64,
+        final_true_peak: f64,
+        passes_qc: bool,
+        passes_loudness: bool,
+        true_peak_max: f64,
+        qc_report_url: Option<&'a str>,
+        qc_report_key: Option<&'a str>,
+        qc_report_sha256: Option<&'a str>,
+        qc_pdf_url: Option<&'a str>,
+        qc_pdf_key: Option<&'a str>,
+        qc_pdf_sha256: Option<&'a str>,
+        source_sha256: &'a str,
+    }
+}
+
+/// Analysis results, posted to `analysis`
+pub const ANALYSIS_RESULT: ResultSchema = ResultSchema {
+    job_type: "analyze",
+    endpoint: "analysis",
+    version: 1,
+    data: AnalysisData::json_schema,
+};
+
+/// Fix results, posted to `fix`
+pub const FIX_RESULT: ResultSchema = ResultSchema {
+    job_type: "fix",
+    endpoint: "fix",
+    version: 1,
+    data: FixData::json_schema,
+};
+
+/// Master results, posted to `master`
+pub const MASTER_RESULT: ResultSchema = ResultSchema {
+    job_type: "master",
+    endpoint: "master",
+    version: 2,
+    data: <MasterData<'static>>::json_schema,
+};
 
 /// Webhook client for reporting job progress and results
 pub struct WebhookClient {
-    client: Client,
-    api_url: String,
-    secret: String,
+    inner: budi_worker_core::WebhookClient,
 }
 
 impl WebhookClient {
-    /// Create a new webhook client from environment variables
-    pub fn from_env() -> Result<Self> {
-        let api_url =
-            std::env::var("API_URL").unwrap_or_else(|_| "http://localhost:4000".to_string());
-        let secret =
-            std::env::var("WEBHOOK_SECRET").unwrap_or_else(|_| "budi-webhook-secret".to_string());
-
+    /// Create a new webhook client
+    pub fn new(config: &Config, storage: &'static Storage) -> Result<Self> {
         Ok(Self {
-            client: Client::new(),
-            api_url,
-            secret,
+            inner: budi_worker_core::WebhookClient::new(config, storage)?,
         })
     }
 
+    /// The shared client underneath, for the job loop's failure reports
+    pub fn core(&self) -> &budi_worker_core::WebhookClient {
+        &self.inner
+    }
+
     /// Report job progress
     pub async fn report_progress(&self, job_id: &str, progress: u8, message: &str) -> Result<()> {
-        let url = format!("{}/webhooks/jobs/{}/progress", self.api_url, job_id);
+        self.inner.report_progress(job_id, progress, message).await
+    }
 
+    /// Report job progress with the metrics of the audio analyzed so far
+    pub async fn report_partial_analysis(
+        &self,
+        job_id: &str,
+        progress: u8,
+        analysis: &PartialAnalysis,
+    ) -> Result<()> {
         #[derive(Serialize)]
-        struct ProgressPayload {
+        struct ProgressPayload<'a> {
             progress: u8,
-            message: String,
+            message: &'a str,
+            analysis: &'a PartialAnalysis,
         }
 
-        self.client
-            .post(&url)
-            .header("X-Webhook-Secret", &self.secret)
-            .json(&ProgressPayload {
-                progress,
-                message: message.to_string(),
-            })
-            .send()
-            .await?;
+        let message = format!("Analyzed {:.0} s...", analysis.duration_secs);
+        self.inner
+            .post(
+                job_id,
+                "progress",
+                &ProgressPayload {
+                    progress,
+                    message: &message,
+                    analysis,
+                },
+            )
+            .await
+    }
+
+    /// Report what a dry-run job would have done; nothing was rendered or
+    /// uploaded
+    pub async fn report_plan<T: Serialize>(
+        &self,
+        job_id: &str,
+        job_type: &str,
+        plan: &T,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct PlanPayload<'a, T> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            dry_run: bool,
+            data: &'a T,
+        }
 
-        Ok(())
+        let payload = PlanPayload {
+            job_id,
+            job_type,
+            status: "planned",
+            dry_run: true,
+            data: plan,
+        };
+
+        self.inner.post(job_id, "plan", &payload).await
     }
 
     /// Report analysis job completion
+    #[allow(clippy::too_many_arguments)]
     pub async fn report_analysis(
         &self,
         job_id: &str,
         result: &AnalysisResult,
+        source_sha256: &str,
         report_url: Option<&str>,
+        report_key: Option<&str>,
+        report_sha256: Option<&str>,
     ) -> Result<()> {
-        let url = format!("{}/webhooks/jobs/{}/analysis", self.api_url, job_id);
+        let data = AnalysisData {
+            integrated_lufs: result.integrated_lufs,
+            loudness_range: result.loudness_range,
+            short_term_max: result.short_term_max,
+            momentary_max: result.momentary_max,
+            sample_peak: result.sample_peak,
+            true_peak: result.true_peak,
+            spectral_centroid: result.spectral_centroid,
+            spectral_rolloff: result.spectral_rolloff,
+            stereo_correlation: result.stereo_correlation,
+            stereo_width: result.stereo_width,
+            has_clipping: result.has_clipping,
+            has_dc_offset: result.has_dc_offset,
+            dc_offset_value: result.dc_offset_value,
+            clipped_samples: result.clipped_samples,
+            sample_rate: result.sample_rate,
+            bit_depth: result.bit_depth,
+            channels: result.channels,
+            duration_secs: result.duration_secs,
+            source_sha256: source_sha256.to_string(),
+            report_url: report_url.map(|s| s.to_string()),
+            report_key: report_key.map(|s| s.to_string()),
+            report_sha256: report_sha256.map(|s| s.to_string()),
+        };
+
+        self.inner
+            .report_result(job_id, &ANALYSIS_RESULT, &data)
+            .await
+    }
 
+    /// Report fix job completion
+    #[allow(clippy::too_many_arguments)]
+    pub async fn report_fix(
+        &self,
+        job_id: &str,
+        source_sha256: &str,
+        fixed_url: &str,
+        fixed_key: &str,
+        fixed_sha256: &str,
+        changes: &[FixChange],
+    ) -> Result<()> {
+        let data = FixData {
+            source_sha256: source_sha256.to_string(),
+            fixed_url: fixed_url.to_string(),
+            fixed_key: fixed_key.to_string(),
+            fixed_sha256: fixed_sha256.to_string(),
+            applied_modules: changes.iter().map(|c| c.module.clone()).collect(),
+            changes: changes
+                .iter()
+                .map(|c| ChangeEntry {
+                    module: c.module.clone(),
+                    description: c.description.clone(),
+                })
+                .collect(),
+        };
+
+        self.inner.report_result(job_id, &FIX_RESULT, &data).await
+    }
+
+    /// Report master job completion
+    pub async fn report_master(&self, job_id: &str, outputs: &AlbumTrackResult) -> Result<()> {
+        let data = MasterData {
+            wav_hd_url: &outputs.wav_hd_url,
+            wav_hd_key: &outputs.wav_hd_key,
+            wav_hd_sha256: &outputs.wav_hd_sha256,
+            wav16_url: &outputs.wav16_url,
+            wav16_key: &outputs.wav16_key,
+            wav16_sha256: &outputs.wav16_sha256,
+            mp3_preview_url: &outputs.mp3_preview_url,
+            mp3_preview_key: &outputs.mp3_preview_key,
+            mp3_preview_sha256: &outputs.mp3_preview_sha256,
+            final_lufs: outputs.final_lufs,
+            final_true_peak: outputs.final_true_peak,
+            passes_qc: outputs.passes_qc,
+            passes_loudness: outputs.passes_loudness,
+            true_peak_max: outputs.true_peak_max,
+            qc_report_url: outputs.qc_report_url.as_deref(),
+            qc_report_key: outputs.qc_report_key.as_deref(),
+            qc_report_sha256: outputs.qc_report_sha256.as_deref(),
+            qc_pdf_url: outputs.qc_pdf_url.as_deref(),
+            qc_pdf_key: outputs.qc_pdf_key.as_deref(),
+            qc_pdf_sha256: outputs.qc_pdf_sha256.as_deref(),
+            source_sha256: &outputs.source_sha256,
+        };
+
+        self.inner
+            .report_result(job_id, &MASTER_RESULT, &data)
+            .await
+    }
+
+    /// Report batch-analyze job completion
+    pub async fn report_batch_analysis(
+        &self,
+        job_id: &str,
+        batch_id: &str,
+        tracks: &[BatchTrack],
+        catalog: &CatalogStats,
+        report: &Uploaded,
+        report_key: &str,
+    ) -> Result<()> {
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct AnalysisPayload {
-            job_id: String,
+        struct BatchAnalysisPayload<'a> {
+            job_id: &'a str,
             #[serde(rename = "type")]
-            job_type: String,
-            status: String,
-            data: AnalysisData,
-        }
-
-        #[derive(Serialize)]
-        #[serde(rename_all = "camelCase")]
-        struct AnalysisData {
-            integrated_lufs: f64,
-            loudness_range: f64,
-            short_term_max: f64,
-            momentary_max: f64,
-            sample_peak: f64,
-            true_peak: f64,
-            spectral_centroid: Option<f64>,
-            spectral_rolloff: Option<f64>,
-            stereo_correlation: Option<f64>,
-            stereo_width: Option<f64>,
-            has_clipping: bool,
-            has_dc_offset: bool,
-            dc_offset_value: Option<f64>,
-            clipped_samples: usize,
-            sample_rate: u32,
-            bit_depth: u32,
-            channels: usize,
+            job_type: &'a str,
+            status: &'a str,
+            data: BatchAnalysisData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct BatchAnalysisData<'a> {
+            batch_id: &'a str,
+            catalog: &'a CatalogStats,
+            tracks: &'a [BatchTrack],
+            report_url: &'a str,
+            report_key: &'a str,
+            report_sha256: &'a str,
+        }
+
+        let payload = BatchAnalysisPayload {
+            job_id,
+            job_type: "batch-analyze",
+            status: "completed",
+            data: BatchAnalysisData {
+                batch_id,
+                catalog,
+                tracks,
+                report_url: &report.url,
+                report_key,
+                report_sha256: &report.sha256,
+            },
+        };
+
+        self.inner.post(job_id, "batch-analyze", &payload).await
+    }
+
+    /// Report compliance-check job completion
+    pub async fn report_compliance(
+        &self,
+        job_id: &str,
+        measurement: &Measurement,
+        platforms: &[PlatformResult],
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CompliancePayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: ComplianceData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ComplianceData<'a> {
+            #[serde(flatten)]
+            measurement: &'a Measurement,
+            /// Whether every platform passes
+            passes: bool,
+            platforms: &'a [PlatformResult],
+            source_sha256: &'a str,
+        }
+
+        let payload = CompliancePayload {
+            job_id,
+            job_type: "compliance-check",
+            status: "completed",
+            data: ComplianceData {
+                measurement,
+                passes: platforms.iter().all(|p| p.passes),
+                platforms,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "compliance-check", &payload).await
+    }
+
+    /// Report podcast-process job completion, with the uploaded MP3 and
+    /// loudness report and their keys
+    #[allow(clippy::too_many_arguments)]
+    pub async fn report_podcast(
+        &self,
+        job_id: &str,
+        result: &PodcastResult,
+        source: &Loudness,
+        output: &Loudness,
+        (mp3, mp3_key): (&Uploaded, &str),
+        (report, report_key): (&Uploaded, &str),
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct PodcastPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: PodcastData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct PodcastData<'a> {
+            #[serde(flatten)]
+            result: &'a PodcastResult,
+            source: &'a Loudness,
+            output: &'a Loudness,
+            mp3_url: &'a str,
+            mp3_key: &'a str,
+            mp3_sha256: &'a str,
+            report_url: &'a str,
+            report_key: &'a str,
+            report_sha256: &'a str,
+            source_sha256: &'a str,
+        }
+
+        let payload = PodcastPayload {
+            job_id,
+            job_type: "podcast-process",
+            status: "completed",
+            data: PodcastData {
+                result,
+                source,
+                output,
+                mp3_url: &mp3.url,
+                mp3_key,
+                mp3_sha256: &mp3.sha256,
+                report_url: &report.url,
+                report_key,
+                report_sha256: &report.sha256,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "podcast-process", &payload).await
+    }
+
+    /// Report acx-check job completion
+    pub async fn report_acx(
+        &self,
+        job_id: &str,
+        report: &AcxReport,
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AcxPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: AcxData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AcxData<'a> {
+            #[serde(flatten)]
+            report: &'a AcxReport,
+            source_sha256: &'a str,
+        }
+
+        let payload = AcxPayload {
+            job_id,
+            job_type: "acx-check",
+            status: "completed",
+            data: AcxData {
+                report,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "acx-check", &payload).await
+    }
+
+    /// Report segment-detect job completion
+    pub async fn report_segments(
+        &self,
+        job_id: &str,
+        segmentation: &Segmentation,
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct SegmentsPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: SegmentsData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct SegmentsData<'a> {
+            #[serde(flatten)]
+            segmentation: &'a Segmentation,
+            source_sha256: &'a str,
+        }
+
+        let payload = SegmentsPayload {
+            job_id,
+            job_type: "segment-detect",
+            status: "completed",
+            data: SegmentsData {
+                segmentation,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "segment-detect", &payload).await
+    }
+
+    /// Report transcribe job completion
+    pub async fn report_transcript(
+        &self,
+        job_id: &str,
+        transcript: &Transcript,
+        duration_secs: f64,
+        gain_db: f64,
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TranscriptPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: TranscriptData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TranscriptData<'a> {
+            #[serde(flatten)]
+            transcript: &'a Transcript,
             duration_secs: f64,
-            report_url: Option<String>,
-        }
-
-        let payload = AnalysisPayload {
-            job_id: job_id.to_string(),
-            job_type: "analyze".to_string(),
-            status: "completed".to_string(),
-            data: AnalysisData {
-                integrated_lufs: result.integrated_lufs,
-                loudness_range: result.loudness_range,
-                short_term_max: result.short_term_max,
-                momentary_max: result.momentary_max,
-                sample_peak: result.sample_peak,
-                true_peak: result.true_peak,
-                spectral_centroid: result.spectral_centroid,
-                spectral_rolloff: result.spectral_rolloff,
-                stereo_correlation: result.stereo_correlation,
-                stereo_width: result.stereo_width,
-                has_clipping: result.has_clipping,
-                has_dc_offset: result.has_dc_offset,
-                dc_offset_value: result.dc_offset_value,
-                clipped_samples: result.clipped_samples,
-                sample_rate: result.sample_rate,
-                bit_depth: result.bit_depth,
-                channels: result.channels,
-                duration_secs: result.duration_secs,
-                report_url: report_url.map(|s| s.to_string()),
+            gain_db: f64,
+            source_sha256: &'a str,
+        }
+
+        let payload = TranscriptPayload {
+            job_id,
+            job_type: "transcribe",
+            status: "completed",
+            data: TranscriptData {
+                transcript,
+                duration_secs,
+                gain_db,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "transcribe", &payload).await
+    }
+
+    /// Report duplicate-check job completion
+    pub async fn report_duplicates(
+        &self,
+        job_id: &str,
+        fingerprint: &str,
+        fingerprint_secs: f64,
+        compared: usize,
+        matches: &[DuplicateMatch],
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DuplicatesPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: DuplicatesData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DuplicatesData<'a> {
+            fingerprint: &'a str,
+            fingerprint_secs: f64,
+            compared: usize,
+            matches: &'a [DuplicateMatch],
+            source_sha256: &'a str,
+        }
+
+        let payload = DuplicatesPayload {
+            job_id,
+            job_type: "duplicate-check",
+            status: "completed",
+            data: DuplicatesData {
+                fingerprint,
+                fingerprint_secs,
+                compared,
+                matches,
+                source_sha256,
             },
         };
 
-        self.client
-            .post(&url)
-            .header("X-Webhook-Secret", &self.secret)
-            .json(&payload)
-            .send()
-            .await?;
+        self.inner.post(job_id, "duplicate-check", &payload).await
+    }
+
+    /// Report restore job completion, with the uploaded restored WAV and
+    /// its key
+    pub async fn report_restore(
+        &self,
+        job_id: &str,
+        restored: &Uploaded,
+        restored_key: &str,
+        result: &RestoreResult,
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RestorePayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: RestoreData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RestoreData<'a> {
+            restored_url: &'a str,
+            restored_key: &'a str,
+            restored_sha256: &'a str,
+            #[serde(flatten)]
+            result: &'a RestoreResult,
+            source_sha256: &'a str,
+        }
+
+        let payload = RestorePayload {
+            job_id,
+            job_type: "restore",
+            status: "completed",
+            data: RestoreData {
+                restored_url: &restored.url,
+                restored_key,
+                restored_sha256: &restored.sha256,
+                result,
+                source_sha256,
+            },
+        };
 
-        Ok(())
+        self.inner.post(job_id, "restore", &payload).await
     }
 
-    /// Report fix job completion
-    pub async fn report_fix(
+    /// Report karaoke job completion, with the uploaded instrumental and
+    /// acapella
+    pub async fn report_karaoke(
         &self,
         job_id: &str,
-        fixed_url: &str,
-        changes: &[FixChange],
+        extraction: &Extraction,
+        files: &[StemFile],
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct KaraokePayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: KaraokeData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct KaraokeData<'a> {
+            #[serde(flatten)]
+            extraction: &'a Extraction,
+            files: &'a [StemFile],
+            source_sha256: &'a str,
+        }
+
+        let payload = KaraokePayload {
+            job_id,
+            job_type: "karaoke",
+            status: "completed",
+            data: KaraokeData {
+                extraction,
+                files,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "karaoke", &payload).await
+    }
+
+    /// Report time-pitch job completion, with the uploaded render and its
+    /// key
+    pub async fn report_time_pitch(
+        &self,
+        job_id: &str,
+        (tempo, semitones): (f64, f64),
+        (source_duration_secs, duration_secs): (f64, f64),
+        (rendered, rendered_key): (&Uploaded, &str),
+        source_sha256: &str,
     ) -> Result<()> {
-        let url = format!("{}/webhooks/jobs/{}/fix", self.api_url, job_id);
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TimePitchPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: TimePitchData<'a>,
+        }
 
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct FixPayload {
-            job_id: String,
+        struct TimePitchData<'a> {
+            tempo: f64,
+            semitones: f64,
+            source_duration_secs: f64,
+            duration_secs: f64,
+            rendered_url: &'a str,
+            rendered_key: &'a str,
+            rendered_sha256: &'a str,
+            source_sha256: &'a str,
+        }
+
+        let payload = TimePitchPayload {
+            job_id,
+            job_type: "time-pitch",
+            status: "completed",
+            data: TimePitchData {
+                tempo,
+                semitones,
+                source_duration_secs,
+                duration_secs,
+                rendered_url: &rendered.url,
+                rendered_key,
+                rendered_sha256: &rendered.sha256,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "time-pitch", &payload).await
+    }
+
+    /// Report vocal-balance job completion
+    pub async fn report_vocal_balance(
+        &self,
+        job_id: &str,
+        balance: &VocalBalance,
+        duration_secs: f64,
+        source_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct VocalBalancePayload<'a> {
+            job_id: &'a str,
             #[serde(rename = "type")]
-            job_type: String,
-            status: String,
-            data: FixData,
+            job_type: &'a str,
+            status: &'a str,
+            data: VocalBalanceData<'a>,
         }
 
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct FixData {
-            fixed_url: String,
-            applied_modules: Vec<String>,
-            changes: Vec<ChangeEntry>,
+        struct VocalBalanceData<'a> {
+            #[serde(flatten)]
+            balance: &'a VocalBalance,
+            duration_secs: f64,
+            source_sha256: &'a str,
+        }
+
+        let payload = VocalBalancePayload {
+            job_id,
+            job_type: "vocal-balance",
+            status: "completed",
+            data: VocalBalanceData {
+                balance,
+                duration_secs,
+                source_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "vocal-balance", &payload).await
+    }
+
+    /// Report bounce job completion, with the uploaded mix and its key, and
+    /// the master's outputs when the mix was mastered
+    pub async fn report_bounce(
+        &self,
+        job_id: &str,
+        summary: &BounceSummary,
+        (mix, mix_key): (&Uploaded, &str),
+        master: Option<&AlbumTrackResult>,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct BouncePayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: BounceData<'a>,
         }
 
         #[derive(Serialize)]
-        struct ChangeEntry {
-            module: String,
-            description: String,
+        #[serde(rename_all = "camelCase")]
+        struct BounceData<'a> {
+            #[serde(flatten)]
+            summary: &'a BounceSummary,
+            mix_url: &'a str,
+            mix_key: &'a str,
+            mix_sha256: &'a str,
+            master: Option<&'a AlbumTrackResult>,
         }
 
-        let payload = FixPayload {
-            job_id: job_id.to_string(),
-            job_type: "fix".to_string(),
-            status: "completed".to_string(),
-            data: FixData {
-                fixed_url: fixed_url.to_string(),
-                applied_modules: changes.iter().map(|c| c.module.clone()).collect(),
-                changes: changes
+        let payload = BouncePayload {
+            job_id,
+            job_type: "bounce",
+            status: "completed",
+            data: BounceData {
+                summary,
+                mix_url: &mix.url,
+                mix_key,
+                mix_sha256: &mix.sha256,
+                master,
+            },
+        };
+
+        self.inner.post(job_id, "bounce", &payload).await
+    }
+
+    /// Report revision-report job completion, with the uploaded JSON report
+    /// and its key
+    pub async fn report_revisions(
+        &self,
+        job_id: &str,
+        revisions: &[RevisionMeasurement],
+        changes: &[RevisionChange],
+        (report, report_key): (&Uploaded, &str),
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RevisionsPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: RevisionsData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RevisionsData<'a> {
+            revisions: &'a [RevisionMeasurement],
+            changes: &'a [RevisionChange],
+            report_url: &'a str,
+            report_key: &'a str,
+            report_sha256: &'a str,
+        }
+
+        let payload = RevisionsPayload {
+            job_id,
+            job_type: "revision-report",
+            status: "completed",
+            data: RevisionsData {
+                revisions,
+                changes,
+                report_url: &report.url,
+                report_key,
+                report_sha256: &report.sha256,
+            },
+        };
+
+        self.inner.post(job_id, "revision-report", &payload).await
+    }
+
+    /// Report replay-gain job completion, with each track's gain in job order
+    pub async fn report_replay_gain(
+        &self,
+        job_id: &str,
+        tracks: &[ReplayGainTrack],
+        gains: &[ReplayGain],
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ReplayGainPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: ReplayGainData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ReplayGainData<'a> {
+            reference_lufs: f64,
+            album_gain_db: Option<f64>,
+            album_peak: Option<f64>,
+            tracks: Vec<TrackGain<'a>>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TrackGain<'a> {
+            track_id: &'a str,
+            track_gain_db: f64,
+            track_peak: f64,
+        }
+
+        let payload = ReplayGainPayload {
+            job_id,
+            job_type: "replay-gain",
+            status: "completed",
+            data: ReplayGainData {
+                reference_lufs: replaygain::REFERENCE_LUFS,
+                album_gain_db: gains.first().and_then(|g| g.album_gain_db),
+                album_peak: gains.first().and_then(|g| g.album_peak),
+                tracks: tracks
                     .iter()
-                    .map(|c| ChangeEntry {
-                        module: c.module.clone(),
-                        description: c.description.clone(),
+                    .zip(gains)
+                    .map(|(track, gain)| TrackGain {
+                        track_id: &track.track_id,
+                        track_gain_db: gain.track_gain_db,
+                        track_peak: gain.track_peak,
                     })
                     .collect(),
             },
         };
 
-        self.client
-            .post(&url)
-            .header("X-Webhook-Secret", &self.secret)
-            .json(&payload)
-            .send()
-            .await?;
-
-        Ok(())
+        self.inner.post(job_id, "replay-gain", &payload).await
     }
 
-    /// Report master job completion
-    #[allow(clippy::too_many_arguments)]
-    pub async fn report_master(
+    /// Report pipeline job completion, with what each stage did and the
+    /// files its encode stages wrote
+    pub async fn report_pipeline(
         &self,
         job_id: &str,
-        wav_hd_url: &str,
-        wav_16_url: &str,
-        mp3_url: &str,
+        stages: &[StageReport],
+        files: &[ExportFile],
         final_lufs: f64,
         final_true_peak: f64,
-        passes_qc: bool,
-        qc_report_url: Option<&str>,
     ) -> Result<()> {
-        let url = format!("{}/webhooks/jobs/{}/master", self.api_url, job_id);
-
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct MasterPayload {
-            job_id: String,
+        struct PipelinePayload<'a> {
+            job_id: &'a str,
             #[serde(rename = "type")]
-            job_type: String,
-            status: String,
-            data: MasterData,
+            job_type: &'a str,
+            status: &'a str,
+            data: PipelineData<'a>,
         }
 
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct MasterData {
-            wav_hd_url: String,
-            wav16_url: String,
-            mp3_preview_url: String,
+        struct PipelineData<'a> {
+            stages: &'a [StageReport],
+            files: &'a [ExportFile],
             final_lufs: f64,
             final_true_peak: f64,
-            passes_qc: bool,
-            qc_report_url: Option<String>,
-        }
-
-        let payload = MasterPayload {
-            job_id: job_id.to_string(),
-            job_type: "master".to_string(),
-            status: "completed".to_string(),
-            data: MasterData {
-                wav_hd_url: wav_hd_url.to_string(),
-                wav16_url: wav_16_url.to_string(),
-                mp3_preview_url: mp3_url.to_string(),
+        }
+
+        let payload = PipelinePayload {
+            job_id,
+            job_type: "pipeline",
+            status: "completed",
+            data: PipelineData {
+                stages,
+                files,
                 final_lufs,
                 final_true_peak,
-                passes_qc,
-                qc_report_url: qc_report_url.map(|s| s.to_string()),
             },
         };
 
-        self.client
-            .post(&url)
-            .header("X-Webhook-Secret", &self.secret)
-            .json(&payload)
-            .send()
-            .await?;
+        self.inner.post(job_id, "pipeline", &payload).await
+    }
+
+    /// Report album master job completion
+    #[allow(clippy::too_many_arguments)]
+    pub async fn report_album_master(
+        &self,
+        job_id: &str,
+        project_id: &str,
+        tracks: &[AlbumTrackResult],
+        album: &AlbumStats,
+        album_render: Option<&AlbumRenderResult>,
+        album_qc_url: Option<&str>,
+        album_qc_key: Option<&str>,
+        album_qc_sha256: Option<&str>,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AlbumMasterPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: AlbumMasterData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AlbumMasterData<'a> {
+            project_id: &'a str,
+            tracks: &'a [AlbumTrackResult],
+            album: &'a AlbumStats,
+            album_render: Option<&'a AlbumRenderResult>,
+            album_qc_url: Option<&'a str>,
+            album_qc_key: Option<&'a str>,
+            album_qc_sha256: Option<&'a str>,
+        }
+
+        let payload = AlbumMasterPayload {
+            job_id,
+            job_type: "album-master",
+            status: "completed",
+            data: AlbumMasterData {
+                project_id,
+                tracks,
+                album,
+                album_render,
+                album_qc_url,
+                album_qc_key,
+                album_qc_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "album-master", &payload).await
+    }
 
-        Ok(())
+    /// Report export job completion
+    #[allow(clippy::too_many_arguments)]
+    pub async fn report_export(
+        &self,
+        job_id: &str,
+        project_id: &str,
+        files: &[ExportFile],
+        qc_report_urls: &[String],
+        manifest_url: Option<&str>,
+        manifest_key: Option<&str>,
+        manifest_sha256: Option<&str>,
+        pack_url: Option<&str>,
+        pack_key: Option<&str>,
+        pack_sha256: Option<&str>,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ExportPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: ExportData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ExportData<'a> {
+            project_id: &'a str,
+            files: &'a [ExportFile],
+            qc_report_included: bool,
+            qc_report_urls: &'a [String],
+            manifest_url: Option<&'a str>,
+            manifest_key: Option<&'a str>,
+            manifest_sha256: Option<&'a str>,
+            pack_url: Option<&'a str>,
+            pack_key: Option<&'a str>,
+            pack_sha256: Option<&'a str>,
+        }
+
+        let payload = ExportPayload {
+            job_id,
+            job_type: "export",
+            status: "completed",
+            data: ExportData {
+                project_id,
+                files,
+                qc_report_included: !qc_report_urls.is_empty(),
+                qc_report_urls,
+                manifest_url,
+                manifest_key,
+                manifest_sha256,
+                pack_url,
+                pack_key,
+                pack_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "export", &payload).await
     }
 
-    /// Report job failure
-    pub async fn report_failure(&self, job_id: &str, job_type: &str, error: &str) -> Result<()> {
-        let url = format!("{}/webhooks/jobs/{}/{}", self.api_url, job_id, job_type);
+    /// Report waveform-peaks job completion
+    #[allow(clippy::too_many_arguments)]
+    pub async fn report_waveform(
+        &self,
+        job_id: &str,
+        waveform: &Waveform,
+        bits: u8,
+        source_sha256: &str,
+        files: &[WaveformFile],
+        json_url: &str,
+        json_key: &str,
+        json_sha256: &str,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct WaveformPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: WaveformData<'a>,
+        }
 
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct FailurePayload {
-            job_id: String,
+        struct WaveformData<'a> {
+            sample_rate: u32,
+            channels: usize,
+            duration_secs: f64,
+            bits: u8,
+            source_sha256: &'a str,
+            levels: &'a [WaveformFile],
+            json_url: &'a str,
+            json_key: &'a str,
+            json_sha256: &'a str,
+        }
+
+        let payload = WaveformPayload {
+            job_id,
+            job_type: "waveform-peaks",
+            status: "completed",
+            data: WaveformData {
+                sample_rate: waveform.sample_rate,
+                channels: waveform.channels,
+                duration_secs: waveform.duration_secs,
+                bits,
+                source_sha256,
+                levels: files,
+                json_url,
+                json_key,
+                json_sha256,
+            },
+        };
+
+        self.inner.post(job_id, "waveform-peaks", &payload).await
+    }
+
+    /// Report spectrogram job completion
+    pub async fn report_spectrogram(
+        &self,
+        job_id: &str,
+        spectrogram: &Spectrogram,
+        scale: SpectrogramScale,
+        color_map: ColorMap,
+        source_sha256: &str,
+        images: &[SpectrogramImage],
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct SpectrogramPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: SpectrogramData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct SpectrogramData<'a> {
+            sample_rate: u32,
+            channels: usize,
+            duration_secs: f64,
+            scale: SpectrogramScale,
+            color_map: ColorMap,
+            floor_db: f32,
+            source_sha256: &'a str,
+            images: &'a [SpectrogramImage],
+        }
+
+        let payload = SpectrogramPayload {
+            job_id,
+            job_type: "spectrogram",
+            status: "completed",
+            data: SpectrogramData {
+                sample_rate: spectrogram.sample_rate,
+                channels: spectrogram.channels,
+                duration_secs: spectrogram.duration_secs,
+                scale,
+                color_map,
+                floor_db: crate::spectrogram::FLOOR_DB,
+                source_sha256,
+                images,
+            },
+        };
+
+        self.inner.post(job_id, "spectrogram", &payload).await
+    }
+
+    /// Report stem-separation job completion
+    pub async fn report_stems(
+        &self,
+        job_id: &str,
+        model: &str,
+        source_sha256: &str,
+        stems: &[StemFile],
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct StemsPayload<'a> {
+            job_id: &'a str,
+            #[serde(rename = "type")]
+            job_type: &'a str,
+            status: &'a str,
+            data: StemsData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct StemsData<'a> {
+            model: &'a str,
+            source_sha256: &'a str,
+            stems: &'a [StemFile],
+        }
+
+        let payload = StemsPayload {
+            job_id,
+            job_type: "stem-separation",
+            status: "completed",
+            data: StemsData {
+                model,
+                source_sha256,
+                stems,
+            },
+        };
+
+        self.inner.post(job_id, "stem-separation", &payload).await
+    }
+
+    /// Report compare job completion, with the uploaded difference audio
+    /// and its key when one was rendered
+    pub async fn report_compare(
+        &self,
+        job_id: &str,
+        comparison: &Comparison,
+        reference_sha256: &str,
+        candidate_sha256: &str,
+        difference: Option<&(Uploaded, String)>,
+    ) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ComparePayload<'a> {
+            job_id: &'a str,
             #[serde(rename = "type")]
-            job_type: String,
-            status: String,
-            error: String,
+            job_type: &'a str,
+            status: &'a str,
+            data: CompareData<'a>,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CompareData<'a> {
+            #[serde(flatten)]
+            comparison: &'a Comparison,
+            reference_sha256: &'a str,
+            candidate_sha256: &'a str,
+            difference_url: Option<&'a str>,
+            difference_key: Option<&'a str>,
+            difference_sha256: Option<&'a str>,
         }
 
-        let payload = FailurePayload {
-            job_id: job_id.to_string(),
-            job_type: job_type.to_string(),
-            status: "failed".to_string(),
-            error: error.to_string(),
+        let payload = ComparePayload {
+            job_id,
+            job_type: "compare",
+            status: "completed",
+            data: CompareData {
+                comparison,
+                reference_sha256,
+                candidate_sha256,
+                difference_url: difference.map(|(u, _)| u.url.as_str()),
+                difference_key: difference.map(|(_, key)| key.as_str()),
+                difference_sha256: difference.map(|(u, _)| u.sha256.as_str()),
+            },
         };
 
-        self.client
-            .post(&url)
-            .header("X-Webhook-Secret", &self.secret)
-            .json(&payload)
-            .send()
-            .await?;
+        self.inner.post(job_id, "compare", &payload).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
 
-        Ok(())
+    #[test]
+    fn test_published_result_schemas_are_current() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../packages/contracts/schemas");
+        budi_worker_core::schema::check_published(
+            &[ANALYSIS_RESULT, FIX_RESULT, MASTER_RESULT],
+            &dir,
+        )
+        .unwrap();
     }
 }